@@ -0,0 +1,443 @@
+#![allow(clippy::needless_return)]
+
+use numpy::ndarray::{Array1, Array2, Array3, Array4};
+use numpy::{IntoPyArray, PyArray1, PyArray2, PyArray3, PyArray4};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use rustynes::input::PLAYERS;
+use rustynes::{Button, EmuError, Nes, Rom};
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::thread;
+
+const WIDTH: usize = 256;
+const HEIGHT: usize = 240;
+const RAM_SIZE: usize = 0x800;
+
+/* Python bindings
+
+   The console for reinforcement learning, as a Python module in the
+   shape of a gym environment. Built with maturin:
+
+    cd rustynes-py && maturin develop --release
+
+   then
+
+    import rustynes_py as nes
+    env = nes.Env("game.nes", frame_skip=4, max_frames=18000)
+    frame = env.reset()
+    frame, ram, done = env.step(nes.RIGHT | nes.A)
+
+   `frame` is the picture as a (240, 256, 3) uint8 array of RGB, `ram`
+   the 2KB of work RAM as a uint8 array, where a game keeps the score,
+   lives and position an agent's reward comes from, and `done` is true
+   once `max_frames` have run since the reset or the CPU has jammed. An
+   action is the buttons held, the module's A, B, SELECT, START, UP,
+   DOWN, LEFT and RIGHT or'd together, for player 1, or a list of them a
+   player each. It's held for `frame_skip` frames and the last picture
+   comes back. `reset` powers on again, or starts from a state
+   `save_state` gave, so an agent can skip the title screen. Sound is
+   made and thrown away.
+
+   `VecEnv(path, count, ...)` runs `count` consoles of one game, the way
+   training wants them: `step` takes an action each and gives back the
+   frames, RAM and done flags stacked into arrays with a first axis of
+   `count`. The consoles step on as many threads as there are cores,
+   without the GIL, and one that was done at its last step starts over
+   at its next.
+
+   `Instance` is the console and its episode without any Python, what
+   both classes wrap, behind a mutex since Python objects can be shared
+   between threads.
+*/
+
+pub struct Instance {
+    nes: Nes,
+    rom: Rom,
+    start: Option<Vec<u8>>, // what `reset` loads, power on without one
+    pub frame_skip: usize,
+    pub max_frames: Option<u64>,
+    frames: u64, // since the last reset
+    done: bool,
+}
+
+impl Instance {
+    pub fn new(rom: Rom, frame_skip: usize, max_frames: Option<u64>) -> Result<Self, EmuError> {
+        return Ok(Self {
+            nes: Nes::new(rom.clone())?,
+            rom,
+            start: None,
+            frame_skip: frame_skip.max(1),
+            max_frames,
+            frames: 0,
+            done: false,
+        });
+    }
+
+    // a new console, at `state` if given and from then on at every reset
+    pub fn reset(&mut self, state: Option<&[u8]>) -> Result<(), EmuError> {
+        let mut nes = Nes::new(self.rom.clone())?;
+        // one that doesn't load leaves the old start in place
+        if let Some(start) = state.or(self.start.as_deref()) {
+            nes.load_state(start)?;
+        }
+        if let Some(state) = state {
+            self.start = Some(state.to_vec());
+        }
+        self.nes = nes;
+        self.frames = 0;
+        self.done = false;
+        return Ok(());
+    }
+
+    // `buttons` held for `frame_skip` frames, true once the episode is over
+    pub fn step(&mut self, buttons: [u8; PLAYERS]) -> bool {
+        let ports = self.nes.ports_mut();
+        for (player, &bits) in buttons.iter().enumerate() {
+            ports.set_buttons(player, bits);
+        }
+        for _ in 0..self.frame_skip {
+            self.nes.run_frame();
+            self.nes.audio_samples();
+            self.frames += 1;
+            self.done =
+                self.nes.cpu.jammed || self.max_frames.is_some_and(|max| self.frames >= max);
+            if self.done {
+                break;
+            }
+        }
+        return self.done;
+    }
+
+    pub fn is_done(&self) -> bool {
+        return self.done;
+    }
+
+    pub fn frames(&self) -> u64 {
+        return self.frames;
+    }
+
+    pub fn nes(&self) -> &Nes {
+        return &self.nes;
+    }
+
+    // the picture as RGB, row by row
+    pub fn rgb(&self, out: &mut Vec<u8>) {
+        for pixel in self.nes.frame().pixels.chunks_exact(4) {
+            out.extend_from_slice(&pixel[..3]);
+        }
+    }
+
+    pub fn ram(&self) -> &[u8] {
+        return &self.nes.cpu.bus.ram()[..RAM_SIZE];
+    }
+}
+
+// one player's buttons, or a list of them a player each
+#[derive(FromPyObject)]
+enum Action {
+    Player1(u8),
+    Players(Vec<u8>),
+}
+
+impl Action {
+    fn buttons(&self) -> PyResult<[u8; PLAYERS]> {
+        let mut buttons = [0; PLAYERS];
+        match self {
+            Action::Player1(bits) => buttons[0] = *bits,
+            Action::Players(players) if players.len() <= PLAYERS => {
+                buttons[..players.len()].copy_from_slice(players);
+            }
+            Action::Players(players) => {
+                return Err(PyValueError::new_err(format!(
+                    "{} players' buttons, the console takes {}",
+                    players.len(),
+                    PLAYERS
+                )));
+            }
+        }
+        return Ok(buttons);
+    }
+}
+
+fn load(path: PathBuf, frame_skip: usize, max_frames: Option<u64>) -> PyResult<Instance> {
+    let rom = Rom::from_file(&path, None).map_err(to_py)?;
+    return Instance::new(rom, frame_skip, max_frames).map_err(to_py);
+}
+
+fn to_py(e: impl std::fmt::Display) -> PyErr {
+    return PyValueError::new_err(e.to_string());
+}
+
+fn rgb_array(instance: &Instance) -> Array3<u8> {
+    let mut rgb = Vec::with_capacity(WIDTH * HEIGHT * 3);
+    instance.rgb(&mut rgb);
+    return Array3::from_shape_vec((HEIGHT, WIDTH, 3), rgb).expect("a whole picture");
+}
+
+// the consoles under a lock only a panic would leave poisoned, and that
+// aborts the step it happened in
+fn unlock<T>(lock: &Mutex<T>) -> MutexGuard<'_, T> {
+    return lock.lock().unwrap_or_else(PoisonError::into_inner);
+}
+
+// without locking, for a method that has the object to itself
+fn unlock_mut<T>(lock: &mut Mutex<T>) -> &mut T {
+    return lock.get_mut().unwrap_or_else(PoisonError::into_inner);
+}
+
+#[pyclass(name = "Env")]
+struct Env {
+    instance: Mutex<Instance>,
+}
+
+#[pymethods]
+impl Env {
+    #[new]
+    #[pyo3(signature = (rom, frame_skip=1, max_frames=None))]
+    fn new(rom: PathBuf, frame_skip: usize, max_frames: Option<u64>) -> PyResult<Self> {
+        return Ok(Self {
+            instance: Mutex::new(load(rom, frame_skip, max_frames)?),
+        });
+    }
+
+    #[pyo3(signature = (state=None))]
+    fn reset<'py>(
+        &mut self,
+        py: Python<'py>,
+        state: Option<&[u8]>,
+    ) -> PyResult<Bound<'py, PyArray3<u8>>> {
+        let instance = unlock_mut(&mut self.instance);
+        instance.reset(state).map_err(to_py)?;
+        return Ok(rgb_array(instance).into_pyarray(py));
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn step<'py>(
+        &mut self,
+        py: Python<'py>,
+        action: Action,
+    ) -> PyResult<(Bound<'py, PyArray3<u8>>, Bound<'py, PyArray1<u8>>, bool)> {
+        let buttons = action.buttons()?;
+        let instance = unlock_mut(&mut self.instance);
+        let done = py.allow_threads(|| instance.step(buttons));
+        let ram = Array1::from(instance.ram().to_vec());
+        return Ok((
+            rgb_array(instance).into_pyarray(py),
+            ram.into_pyarray(py),
+            done,
+        ));
+    }
+
+    fn save_state<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        return PyBytes::new(py, &unlock(&self.instance).nes().save_state());
+    }
+
+    #[getter]
+    fn frames(&self) -> u64 {
+        return unlock(&self.instance).frames();
+    }
+}
+
+#[pyclass(name = "VecEnv")]
+struct VecEnv {
+    instances: Mutex<Vec<Instance>>,
+}
+
+#[pymethods]
+impl VecEnv {
+    #[new]
+    #[pyo3(signature = (rom, count, frame_skip=1, max_frames=None))]
+    fn new(
+        rom: PathBuf,
+        count: usize,
+        frame_skip: usize,
+        max_frames: Option<u64>,
+    ) -> PyResult<Self> {
+        if count == 0 {
+            return Err(PyValueError::new_err("count must be at least 1"));
+        }
+        let first = load(rom, frame_skip, max_frames)?;
+        let mut instances = Vec::with_capacity(count);
+        for _ in 1..count {
+            instances
+                .push(Instance::new(first.rom.clone(), frame_skip, max_frames).map_err(to_py)?);
+        }
+        instances.insert(0, first);
+        return Ok(Self {
+            instances: Mutex::new(instances),
+        });
+    }
+
+    #[pyo3(signature = (state=None))]
+    fn reset<'py>(
+        &mut self,
+        py: Python<'py>,
+        state: Option<&[u8]>,
+    ) -> PyResult<Bound<'py, PyArray4<u8>>> {
+        let instances = unlock_mut(&mut self.instances);
+        for instance in instances.iter_mut() {
+            instance.reset(state).map_err(to_py)?;
+        }
+        return Ok(frames_array(instances).into_pyarray(py));
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn step<'py>(
+        &mut self,
+        py: Python<'py>,
+        actions: Vec<Action>,
+    ) -> PyResult<(
+        Bound<'py, PyArray4<u8>>,
+        Bound<'py, PyArray2<u8>>,
+        Bound<'py, PyArray1<bool>>,
+    )> {
+        let instances = unlock_mut(&mut self.instances);
+        if actions.len() != instances.len() {
+            return Err(PyValueError::new_err(format!(
+                "{} actions for {} consoles",
+                actions.len(),
+                instances.len()
+            )));
+        }
+        let buttons = actions
+            .iter()
+            .map(Action::buttons)
+            .collect::<PyResult<Vec<_>>>()?;
+        py.allow_threads(|| step_all(instances, &buttons))
+            .map_err(to_py)?;
+
+        let mut ram = Vec::with_capacity(instances.len() * RAM_SIZE);
+        for instance in instances.iter() {
+            ram.extend_from_slice(instance.ram());
+        }
+        let ram =
+            Array2::from_shape_vec((instances.len(), RAM_SIZE), ram).expect("the RAM of each");
+        let done: Vec<bool> = instances.iter().map(Instance::is_done).collect();
+        return Ok((
+            frames_array(instances).into_pyarray(py),
+            ram.into_pyarray(py),
+            Array1::from(done).into_pyarray(py),
+        ));
+    }
+
+    fn __len__(&self) -> usize {
+        return unlock(&self.instances).len();
+    }
+}
+
+fn frames_array(instances: &[Instance]) -> Array4<u8> {
+    let mut rgb = Vec::with_capacity(instances.len() * WIDTH * HEIGHT * 3);
+    for instance in instances {
+        instance.rgb(&mut rgb);
+    }
+    return Array4::from_shape_vec((instances.len(), HEIGHT, WIDTH, 3), rgb)
+        .expect("a whole picture each");
+}
+
+// a step of each console with its buttons, split across the cores; one
+// that finished last time starts over first
+pub fn step_all(instances: &mut [Instance], buttons: &[[u8; PLAYERS]]) -> Result<(), EmuError> {
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk = instances.len().div_ceil(threads).max(1);
+    return thread::scope(|scope| {
+        let workers: Vec<_> = instances
+            .chunks_mut(chunk)
+            .zip(buttons.chunks(chunk))
+            .map(|(instances, buttons)| {
+                scope.spawn(move || {
+                    for (instance, &buttons) in instances.iter_mut().zip(buttons) {
+                        if instance.is_done() {
+                            instance.reset(None)?;
+                        }
+                        instance.step(buttons);
+                    }
+                    return Ok(());
+                })
+            })
+            .collect();
+        return workers
+            .into_iter()
+            .try_for_each(|worker| worker.join().expect("a console panicked"));
+    });
+}
+
+#[pymodule]
+fn rustynes_py(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Env>()?;
+    m.add_class::<VecEnv>()?;
+    for button in Button::ALL {
+        m.add(button.name().to_uppercase(), button.bit())?;
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // an NROM game that adds player 1's A button to $10 every time it
+    // reads the controller, forever
+    fn test_rom() -> Rom {
+        let program = [
+            0x78, 0xA9, 0x01, 0x8D, 0x16, 0x40, 0xA9, 0x00, 0x8D, 0x16, 0x40, 0xAD, 0x16, 0x40,
+            0x29, 0x01, 0x18, 0x65, 0x10, 0x85, 0x10, 0x4C, 0x01, 0x80,
+        ];
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let mut prg = vec![0; 0x4000];
+        prg[..program.len()].copy_from_slice(&program);
+        prg[0x3FFD] = 0x80;
+        raw.extend(prg);
+        raw.extend(vec![0; 0x2000]);
+        return Rom::new(&raw).unwrap();
+    }
+
+    #[test]
+    fn test_episodes() {
+        let mut instance = Instance::new(test_rom(), 4, Some(10)).unwrap();
+        assert!(!instance.step([Button::A.bit(), 0, 0, 0]));
+        assert_eq!(instance.frames(), 4);
+        assert_ne!(instance.ram()[0x10], 0);
+        assert_eq!(instance.ram().len(), RAM_SIZE);
+        let mut rgb = Vec::new();
+        instance.rgb(&mut rgb);
+        assert_eq!(rgb.len(), WIDTH * HEIGHT * 3);
+
+        // the last step stops short at the limit
+        assert!(!instance.step([0; PLAYERS]));
+        assert!(instance.step([0; PLAYERS]));
+        assert_eq!(instance.frames(), 10);
+
+        instance.reset(None).unwrap();
+        assert_eq!((instance.frames(), instance.ram()[0x10]), (0, 0));
+    }
+
+    #[test]
+    fn test_bad_start_state_keeps_the_old_one() {
+        let mut instance = Instance::new(test_rom(), 4, None).unwrap();
+        instance.step([Button::A.bit(), 0, 0, 0]);
+        let start = instance.nes().save_state();
+        let counted = instance.ram()[0x10];
+        instance.reset(Some(&start)).unwrap();
+
+        assert!(instance.reset(Some(&start[..8])).is_err());
+        instance.reset(None).unwrap();
+        assert_eq!(instance.ram()[0x10], counted);
+    }
+
+    #[test]
+    fn test_batches_start_over_when_done() {
+        let mut instances: Vec<Instance> = (0..3)
+            .map(|_| Instance::new(test_rom(), 1, Some(2)).unwrap())
+            .collect();
+        let buttons = [[Button::A.bit(), 0, 0, 0], [0; PLAYERS], [0; PLAYERS]];
+        step_all(&mut instances, &buttons).unwrap();
+        assert_ne!(instances[0].ram()[0x10], 0);
+        assert_eq!(instances[1].ram()[0x10], 0);
+        step_all(&mut instances, &buttons).unwrap();
+        assert!(instances.iter().all(Instance::is_done));
+        step_all(&mut instances, &buttons).unwrap();
+        assert!(instances.iter().all(|instance| instance.frames() == 1));
+    }
+}
@@ -0,0 +1,104 @@
+use crate::apu::filter::{FilterChain, FilterKind};
+use crate::apu::resample::{ResampleQuality, Resampler};
+use crate::apu::{CHANNEL_COUNT, CPU_CLOCK_NTSC};
+use crate::audio::{AudioSink, WavAudioSink};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+/* Audio capture
+
+   Records what the APU outputs to a WAV file, either the mixed stream the
+   speakers get or one channel per APU channel: pulse 1, pulse 2,
+   triangle, noise and DMC, in that order. Each channel track is that
+   channel's share of the mix on its own, resampled and filtered like the
+   mixed stream, so the tracks sum to roughly the mixed output (the mixer
+   isn't linear, so not exactly).
+
+   Writing happens as the samples are collected with `Apu::take_samples`.
+   An error there can't interrupt the emulator, so the first one is kept
+   and reported when the capture is stopped.
+*/
+
+type Channel = (Resampler, FilterChain);
+
+pub struct AudioCapture {
+    sink: WavAudioSink<BufWriter<File>>,
+    channels: Vec<Channel>, // empty when recording the mix
+    error: Option<String>,
+}
+
+impl AudioCapture {
+    pub fn mixed(path: &Path) -> Result<Self, String> {
+        return Ok(Self {
+            sink: WavAudioSink::create(path)?,
+            channels: Vec::new(),
+            error: None,
+        });
+    }
+
+    pub fn per_channel(
+        path: &Path,
+        sample_rate: u32,
+        quality: ResampleQuality,
+        filters: &[FilterKind],
+    ) -> Result<Self, String> {
+        let channels = (0..CHANNEL_COUNT)
+            .map(|_| {
+                let resampler = Resampler::new(CPU_CLOCK_NTSC, sample_rate, quality);
+                return (resampler, FilterChain::new(filters, sample_rate));
+            })
+            .collect();
+        return Ok(Self {
+            sink: WavAudioSink::create_with_channels(path, CHANNEL_COUNT as u16)?,
+            channels,
+            error: None,
+        });
+    }
+
+    pub fn is_per_channel(&self) -> bool {
+        return !self.channels.is_empty();
+    }
+
+    // each channel's level for one CPU cycle
+    pub fn push_levels(&mut self, levels: &[f32; CHANNEL_COUNT]) {
+        for ((resampler, _), level) in self.channels.iter_mut().zip(levels) {
+            resampler.push(*level);
+        }
+    }
+
+    // `mixed` is what take_samples is about to hand out
+    pub fn write(&mut self, mixed: &[f32], sample_rate: u32) {
+        let result = if self.channels.is_empty() {
+            self.sink.push_samples(mixed, sample_rate)
+        } else {
+            let tracks: Vec<Vec<f32>> = self
+                .channels
+                .iter_mut()
+                .map(|(resampler, filters)| {
+                    let mut samples = resampler.take();
+                    filters.process(&mut samples);
+                    return samples;
+                })
+                .collect();
+            // the resamplers run in step, so the tracks are the same length
+            let frames = tracks.iter().map(|track| track.len()).min().unwrap_or(0);
+            let mut interleaved = Vec::with_capacity(frames * CHANNEL_COUNT);
+            for frame in 0..frames {
+                interleaved.extend(tracks.iter().map(|track| track[frame]));
+            }
+            self.sink.push_samples(&interleaved, sample_rate)
+        };
+        if let Err(e) = result {
+            self.error.get_or_insert(e);
+        }
+    }
+
+    pub fn finish(mut self) -> Result<(), String> {
+        let finished = self.sink.finish();
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        return finished;
+    }
+}
@@ -0,0 +1,292 @@
+use crate::error::StateError;
+use crate::savestate::{StateReader, StateWriter};
+
+// timer periods in CPU cycles
+const RATE_TABLE: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+
+/* DMC channel registers
+    $4010  IL-- RRRR  IRQ enable, loop, rate index
+    $4011  -DDD DDDD  direct load of the output level
+    $4012  AAAA AAAA  sample address, $C000 + A * 64
+    $4013  LLLL LLLL  sample length, L * 16 + 1 bytes
+
+   The sample is 1 bit deltas, read a byte at a time from CPU memory. The
+   channel can't reach memory itself: when its one byte buffer is empty and
+   bytes remain it raises a fetch request, and the bus reads the byte and
+   stalls the CPU while it does. Each timer clock moves the 7 bit output
+   level up or down by 2 for the next bit, clamped to 0-127. A finished
+   sample either restarts or, with IRQs enabled, raises the IRQ flag.
+*/
+
+#[derive(Debug, Clone)]
+pub struct Dmc {
+    pub enabled: bool,
+    pub irq_enabled: bool,
+    pub irq: bool,
+    pub looping: bool,
+    pub timer_period: u16,
+    pub timer: u16,
+    pub output_level: u8,
+    pub sample_address: u16,
+    pub sample_length: u16,
+    pub current_address: u16,
+    pub bytes_remaining: u16,
+    pub sample_buffer: Option<u8>,
+    pub shift_register: u8,
+    pub bits_remaining: u8,
+    pub silence: bool,
+}
+
+impl Default for Dmc {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl Dmc {
+    pub fn new() -> Self {
+        return Self {
+            enabled: false,
+            irq_enabled: false,
+            irq: false,
+            looping: false,
+            timer_period: RATE_TABLE[0],
+            timer: 0,
+            output_level: 0,
+            sample_address: 0xC000,
+            sample_length: 1,
+            current_address: 0xC000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            shift_register: 0,
+            bits_remaining: 8,
+            silence: true,
+        };
+    }
+
+    pub fn write_register(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.irq_enabled = data & 0b1000_0000 != 0;
+                self.looping = data & 0b0100_0000 != 0;
+                self.timer_period = RATE_TABLE[(data & 0b0000_1111) as usize];
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+            }
+            1 => self.output_level = data & 0b0111_1111,
+            2 => self.sample_address = 0xC000 | ((data as u16) << 6),
+            _ => self.sample_length = ((data as u16) << 4) | 1,
+        }
+    }
+
+    // bit 4 of $4015, which also acknowledges the IRQ
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.irq = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    // the address the bus should read for us, if the buffer wants refilling
+    pub fn fetch_address(&self) -> Option<u16> {
+        if self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+            return Some(self.current_address);
+        }
+        return None;
+    }
+
+    // the byte read from `fetch_address`
+    pub fn fill(&mut self, data: u8) {
+        self.sample_buffer = Some(data);
+        // the address wraps to $8000 rather than $0000
+        self.current_address = self.current_address.checked_add(1).unwrap_or(0x8000);
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.timer_period - 1;
+
+        if !self.silence {
+            if self.shift_register & 1 != 0 {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+        self.shift_register >>= 1;
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(data) => {
+                    self.silence = false;
+                    self.shift_register = data;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        return self.output_level;
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.enabled);
+        writer.write_bool(self.irq_enabled);
+        writer.write_bool(self.irq);
+        writer.write_bool(self.looping);
+        writer.write_u16(self.timer_period);
+        writer.write_u16(self.timer);
+        writer.write_u8(self.output_level);
+        writer.write_u16(self.sample_address);
+        writer.write_u16(self.sample_length);
+        writer.write_u16(self.current_address);
+        writer.write_u16(self.bytes_remaining);
+        writer.write_bool(self.sample_buffer.is_some());
+        writer.write_u8(self.sample_buffer.unwrap_or(0));
+        writer.write_u8(self.shift_register);
+        writer.write_u8(self.bits_remaining);
+        writer.write_bool(self.silence);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = reader.read_bool()?;
+        self.irq_enabled = reader.read_bool()?;
+        self.irq = reader.read_bool()?;
+        self.looping = reader.read_bool()?;
+        self.timer_period = reader.read_u16()?.max(1);
+        self.timer = reader.read_u16()?;
+        self.output_level = reader.read_u8()? & 0b0111_1111;
+        self.sample_address = reader.read_u16()?;
+        self.sample_length = reader.read_u16()?;
+        self.current_address = reader.read_u16()?;
+        self.bytes_remaining = reader.read_u16()?;
+        let buffered = reader.read_bool()?;
+        let data = reader.read_u8()?;
+        self.sample_buffer = if buffered { Some(data) } else { None };
+        self.shift_register = reader.read_u8()?;
+        self.bits_remaining = reader.read_u8()?.clamp(1, 8);
+        self.silence = reader.read_bool()?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // serves fetches from `memory` until the sample stops asking
+    fn play(dmc: &mut Dmc, memory: &[u8], cycles: usize) -> Vec<u16> {
+        let mut fetched = Vec::new();
+        for _ in 0..cycles {
+            dmc.clock_timer();
+            if let Some(addr) = dmc.fetch_address() {
+                fetched.push(addr);
+                dmc.fill(memory[(addr - 0xC000) as usize]);
+            }
+        }
+        return fetched;
+    }
+
+    #[test]
+    fn test_registers() {
+        let mut dmc = Dmc::new();
+        dmc.write_register(0, 0b0100_1111);
+        dmc.write_register(1, 0xFF);
+        dmc.write_register(2, 0x01);
+        dmc.write_register(3, 0x02);
+        assert!(dmc.looping && !dmc.irq_enabled);
+        assert_eq!(dmc.timer_period, 54);
+        assert_eq!(dmc.output(), 0x7F);
+        assert_eq!(dmc.sample_address, 0xC040);
+        assert_eq!(dmc.sample_length, 33);
+
+        dmc.set_enabled(true);
+        assert_eq!(dmc.fetch_address(), Some(0xC040));
+        dmc.set_enabled(false);
+        assert_eq!(dmc.fetch_address(), None);
+    }
+
+    #[test]
+    fn test_deltas_move_the_output() {
+        let mut dmc = Dmc::new();
+        dmc.write_register(0, 0x0F);
+        dmc.write_register(1, 64);
+        // one byte at $C000; the first 8 bits are silent while it loads, then 0xFF climbs
+        dmc.set_enabled(true);
+        play(&mut dmc, &[0xFF], 54 * 8);
+        assert_eq!(dmc.output(), 64);
+        play(&mut dmc, &[0xFF], 54 * 8);
+        assert_eq!(dmc.output(), 80);
+
+        // and the output is clamped at the top
+        dmc.output_level = 126;
+        dmc.sample_buffer = Some(0xFF);
+        play(&mut dmc, &[], 54 * 16);
+        assert_eq!(dmc.output(), 126);
+    }
+
+    #[test]
+    fn test_irq_on_completion() {
+        let mut dmc = Dmc::new();
+        dmc.write_register(0, 0b1000_1111);
+        dmc.write_register(3, 1); // 17 bytes
+        dmc.set_enabled(true);
+        let fetched = play(&mut dmc, &[0; 17], 54 * 8 * 20);
+        assert_eq!(fetched.len(), 17);
+        assert_eq!(fetched[16], 0xC010);
+        assert!(dmc.irq);
+        assert_eq!(dmc.bytes_remaining, 0);
+
+        // disabling IRQs acknowledges it
+        dmc.write_register(0, 0x0F);
+        assert!(!dmc.irq);
+    }
+
+    #[test]
+    fn test_looping_restarts_the_sample() {
+        let mut dmc = Dmc::new();
+        dmc.write_register(0, 0b1100_1111);
+        dmc.write_register(2, 0x00);
+        dmc.write_register(3, 0x00); // 1 byte
+        dmc.set_enabled(true);
+        let fetched = play(&mut dmc, &[0x55], 54 * 8 * 4);
+        assert!(fetched.len() >= 4);
+        assert!(fetched.iter().all(|&addr| addr == 0xC000));
+        assert!(!dmc.irq);
+    }
+
+    #[test]
+    fn test_address_wraps_to_8000() {
+        let mut dmc = Dmc::new();
+        dmc.current_address = 0xFFFF;
+        dmc.bytes_remaining = 2;
+        dmc.fill(0);
+        assert_eq!(dmc.current_address, 0x8000);
+    }
+}
@@ -0,0 +1,105 @@
+use crate::error::StateError;
+use crate::savestate::{StateReader, StateWriter};
+
+/* Envelope generator
+
+   Shared by the pulse and noise channels. Writing the channel's length
+   register sets the start flag; the next quarter frame clock restarts the
+   decay level at 15. After that the divider, reloaded from the volume bits,
+   counts down quarter frames and takes one off the level each time it runs
+   out, wrapping back to 15 when the length counter halt bit doubles as the
+   loop flag. With constant volume set the level is ignored and the volume
+   bits are the output.
+*/
+
+#[derive(Debug, Default, Clone)]
+pub struct Envelope {
+    pub start: bool,
+    pub divider: u8,
+    pub decay: u8,
+}
+
+impl Envelope {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    pub fn restart(&mut self) {
+        self.start = true;
+    }
+
+    // quarter frame clock, `period` is the channel's volume bits
+    pub fn clock(&mut self, period: u8, looping: bool) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = period;
+            return;
+        }
+        if self.divider > 0 {
+            self.divider -= 1;
+            return;
+        }
+        self.divider = period;
+        if self.decay > 0 {
+            self.decay -= 1;
+        } else if looping {
+            self.decay = 15;
+        }
+    }
+
+    pub fn volume(&self, constant_volume: bool, volume: u8) -> u8 {
+        if constant_volume {
+            return volume;
+        }
+        return self.decay;
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.start);
+        writer.write_u8(self.divider);
+        writer.write_u8(self.decay);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.start = reader.read_bool()?;
+        self.divider = reader.read_u8()? & 0b1111;
+        self.decay = reader.read_u8()? & 0b1111;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decay() {
+        let mut envelope = Envelope::new();
+        envelope.restart();
+        envelope.clock(1, false);
+        assert_eq!(envelope.volume(false, 1), 15);
+        // one step every period + 1 clocks
+        envelope.clock(1, false);
+        envelope.clock(1, false);
+        assert_eq!(envelope.volume(false, 1), 14);
+        for _ in 0..40 {
+            envelope.clock(1, false);
+        }
+        assert_eq!(envelope.volume(false, 1), 0);
+        assert_eq!(envelope.volume(true, 9), 9);
+    }
+
+    #[test]
+    fn test_loop() {
+        let mut envelope = Envelope::new();
+        envelope.restart();
+        envelope.clock(0, true);
+        for _ in 0..15 {
+            envelope.clock(0, true);
+        }
+        assert_eq!(envelope.decay, 0);
+        envelope.clock(0, true);
+        assert_eq!(envelope.decay, 15);
+    }
+}
@@ -0,0 +1,147 @@
+use std::f32::consts::PI;
+
+/* Output filters
+
+   Between the APU's DAC and the audio jack the console has a few RC
+   stages that shape its sound: two high-pass filters, around 90Hz and
+   440Hz, which take out the DC offset and thin the bass, and a low-pass
+   around 14kHz which softens the edges of the pulse waves. They are
+   first-order filters, run on the resampled output:
+
+    high-pass  y[n] = a * (y[n-1] + x[n] - x[n-1])   a = rc / (rc + dt)
+    low-pass   y[n] = y[n-1] + b * (x[n] - y[n-1])   b = dt / (rc + dt)
+
+   The mixer's 0.0..=1.0 comes out centred on 0 once a high-pass stage has
+   settled. Any chain can be configured, an empty one gives the raw mix.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterKind {
+    HighPass(f32), // cutoff in Hz
+    LowPass(f32),
+}
+
+pub const NES_FILTERS: [FilterKind; 3] = [
+    FilterKind::HighPass(90.0),
+    FilterKind::HighPass(440.0),
+    FilterKind::LowPass(14_000.0),
+];
+
+#[derive(Debug, Clone)]
+struct Filter {
+    kind: FilterKind,
+    coefficient: f32,
+    last_input: f32,
+    last_output: f32,
+}
+
+impl Filter {
+    fn new(kind: FilterKind, sample_rate: u32) -> Self {
+        let dt = 1.0 / sample_rate as f32;
+        let coefficient = match kind {
+            FilterKind::HighPass(cutoff) => {
+                let rc = 1.0 / (2.0 * PI * cutoff);
+                rc / (rc + dt)
+            }
+            FilterKind::LowPass(cutoff) => {
+                let rc = 1.0 / (2.0 * PI * cutoff);
+                dt / (rc + dt)
+            }
+        };
+        return Self {
+            kind,
+            coefficient,
+            last_input: 0.0,
+            last_output: 0.0,
+        };
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let output = match self.kind {
+            FilterKind::HighPass(_) => {
+                self.coefficient * (self.last_output + input - self.last_input)
+            }
+            FilterKind::LowPass(_) => {
+                self.last_output + self.coefficient * (input - self.last_output)
+            }
+        };
+        self.last_input = input;
+        self.last_output = output;
+        return output;
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FilterChain {
+    filters: Vec<Filter>,
+}
+
+impl FilterChain {
+    pub fn new(kinds: &[FilterKind], sample_rate: u32) -> Self {
+        let filters = kinds
+            .iter()
+            .map(|kind| Filter::new(*kind, sample_rate))
+            .collect();
+        return Self { filters };
+    }
+
+    pub fn kinds(&self) -> Vec<FilterKind> {
+        return self.filters.iter().map(|filter| filter.kind).collect();
+    }
+
+    pub fn process(&mut self, samples: &mut [f32]) {
+        for sample in samples.iter_mut() {
+            for filter in self.filters.iter_mut() {
+                *sample = filter.process(*sample);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // peak amplitude of a sine at `frequency` after the chain has settled
+    fn response(kinds: &[FilterKind], frequency: f32) -> f32 {
+        let mut chain = FilterChain::new(kinds, 44_100);
+        let mut samples: Vec<f32> = (0..44_100)
+            .map(|n| (2.0 * PI * frequency * n as f32 / 44_100.0).sin())
+            .collect();
+        chain.process(&mut samples);
+        return samples[22_050..]
+            .iter()
+            .cloned()
+            .fold(0.0, |a, b| a.max(b.abs()));
+    }
+
+    #[test]
+    fn test_high_pass_removes_dc() {
+        let mut chain = FilterChain::new(&NES_FILTERS, 44_100);
+        let mut samples = vec![0.5; 4410];
+        chain.process(&mut samples);
+        assert!(samples[0] > 0.3);
+        assert!(samples[4409].abs() < 0.001);
+    }
+
+    #[test]
+    fn test_frequency_response() {
+        // first order, so about 0.7 at the cutoff
+        let high_pass = [FilterKind::HighPass(440.0)];
+        assert!((response(&high_pass, 440.0) - 0.707).abs() < 0.02);
+        assert!(response(&high_pass, 50.0) < 0.15);
+        assert!(response(&high_pass, 5000.0) > 0.95);
+
+        let low_pass = [FilterKind::LowPass(14_000.0)];
+        assert!(response(&low_pass, 200.0) > 0.99);
+        assert!(response(&low_pass, 14_000.0) < 0.8);
+    }
+
+    #[test]
+    fn test_empty_chain_is_raw() {
+        let mut chain = FilterChain::new(&[], 44_100);
+        let mut samples = vec![0.25, 0.5];
+        chain.process(&mut samples);
+        assert_eq!(samples, vec![0.25, 0.5]);
+    }
+}
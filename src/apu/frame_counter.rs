@@ -0,0 +1,190 @@
+use crate::error::StateError;
+use crate::savestate::{StateReader, StateWriter};
+
+// CPU cycles after a reset at which the sequencer steps, NTSC
+const STEP_1: u16 = 7457;
+const STEP_2: u16 = 14913;
+const STEP_3: u16 = 22371;
+const STEP_4: u16 = 29829;
+const STEP_5: u16 = 37281;
+
+/* Frame counter
+    $4017  MI-- ----  mode (0 = 4 step, 1 = 5 step), IRQ inhibit
+
+   The sequencer gives the channels their slow clocks. Quarter frames clock
+   the envelopes and the triangle's linear counter, half frames also the
+   length counters and the sweep units:
+
+    4 step   Q   QH  Q   QH      ~240Hz, IRQ on the last step
+    5 step   Q   QH  Q   -   QH  ~192Hz, no IRQ
+
+   In 4 step mode the IRQ flag is raised on the last three cycles of the
+   sequence unless inhibited; setting the inhibit bit also clears it. A
+   write doesn't take effect straight away, the sequencer restarts 3 or 4
+   CPU cycles later depending on which half of an APU cycle it landed on,
+   and a switch to 5 step mode clocks a half frame as it does.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameClock {
+    Quarter,
+    Half, // a half frame is also a quarter frame
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct FrameCounter {
+    pub five_step: bool,
+    pub irq_inhibit: bool,
+    pub irq: bool,
+    pub cycle: u16,
+    pub pending_write: Option<(u8, u8)>, // value and cycles until it applies
+}
+
+impl FrameCounter {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    // `odd_cycle` is whether the write landed between APU cycles
+    pub fn write(&mut self, data: u8, odd_cycle: bool) {
+        self.irq_inhibit = data & 0b0100_0000 != 0;
+        if self.irq_inhibit {
+            self.irq = false;
+        }
+        self.pending_write = Some((data, if odd_cycle { 4 } else { 3 }));
+    }
+
+    // called once per CPU cycle
+    pub fn clock(&mut self) -> Option<FrameClock> {
+        if let Some((data, delay)) = self.pending_write {
+            if delay > 1 {
+                self.pending_write = Some((data, delay - 1));
+            } else {
+                self.pending_write = None;
+                self.five_step = data & 0b1000_0000 != 0;
+                self.cycle = 0;
+                if self.five_step {
+                    return Some(FrameClock::Half);
+                }
+                return None;
+            }
+        }
+
+        self.cycle += 1;
+        match (self.cycle, self.five_step) {
+            (STEP_1, _) | (STEP_3, _) => return Some(FrameClock::Quarter),
+            (STEP_2, _) => return Some(FrameClock::Half),
+            (c, false) if c == STEP_4 - 1 => {
+                self.raise_irq();
+                return None;
+            }
+            (STEP_4, false) => {
+                self.raise_irq();
+                return Some(FrameClock::Half);
+            }
+            (c, false) if c == STEP_4 + 1 => {
+                self.raise_irq();
+                self.cycle = 0;
+                return None;
+            }
+            (STEP_5, true) => return Some(FrameClock::Half),
+            (c, true) if c == STEP_5 + 1 => {
+                self.cycle = 0;
+                return None;
+            }
+            _ => return None,
+        }
+    }
+
+    fn raise_irq(&mut self) {
+        if !self.irq_inhibit {
+            self.irq = true;
+        }
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.five_step);
+        writer.write_bool(self.irq_inhibit);
+        writer.write_bool(self.irq);
+        writer.write_u16(self.cycle);
+        let (data, delay) = self.pending_write.unwrap_or((0, 0));
+        writer.write_u8(data);
+        writer.write_u8(delay);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.five_step = reader.read_bool()?;
+        self.irq_inhibit = reader.read_bool()?;
+        self.irq = reader.read_bool()?;
+        self.cycle = reader.read_u16()?;
+        let data = reader.read_u8()?;
+        let delay = reader.read_u8()?;
+        self.pending_write = if delay > 0 { Some((data, delay)) } else { None };
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn run(counter: &mut FrameCounter, cycles: u32) -> Vec<(u32, FrameClock)> {
+        let mut clocks = Vec::new();
+        for cycle in 1..=cycles {
+            if let Some(clock) = counter.clock() {
+                clocks.push((cycle, clock));
+            }
+        }
+        return clocks;
+    }
+
+    #[test]
+    fn test_four_step_sequence() {
+        let mut counter = FrameCounter::new();
+        let clocks = run(&mut counter, 29830 + 7457);
+        assert_eq!(
+            clocks,
+            vec![
+                (7457, FrameClock::Quarter),
+                (14913, FrameClock::Half),
+                (22371, FrameClock::Quarter),
+                (29829, FrameClock::Half),
+                (29830 + 7457, FrameClock::Quarter),
+            ]
+        );
+        assert!(counter.irq);
+    }
+
+    #[test]
+    fn test_five_step_sequence() {
+        let mut counter = FrameCounter::new();
+        counter.write(0b1000_0000, false);
+        let clocks = run(&mut counter, 3 + 37282 + 7457);
+        assert_eq!(
+            clocks,
+            vec![
+                (3, FrameClock::Half), // the write itself
+                (3 + 7457, FrameClock::Quarter),
+                (3 + 14913, FrameClock::Half),
+                (3 + 22371, FrameClock::Quarter),
+                (3 + 37281, FrameClock::Half),
+                (3 + 37282 + 7457, FrameClock::Quarter),
+            ]
+        );
+        assert!(!counter.irq);
+    }
+
+    #[test]
+    fn test_write_delay_and_irq_inhibit() {
+        let mut counter = FrameCounter::new();
+        run(&mut counter, 29829);
+        assert!(counter.irq);
+        counter.write(0b0100_0000, true);
+        assert!(!counter.irq);
+        // the sequencer keeps going for 4 more cycles, then restarts
+        run(&mut counter, 4);
+        assert_eq!(counter.cycle, 0);
+        assert!(!counter.irq);
+        assert_eq!(run(&mut counter, 7457), vec![(7457, FrameClock::Quarter)]);
+    }
+}
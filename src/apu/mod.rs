@@ -0,0 +1,522 @@
+use crate::error::StateError;
+use crate::savestate::{StateReader, StateWriter};
+use std::path::Path;
+
+pub mod capture;
+pub mod dmc;
+pub mod envelope;
+pub mod filter;
+pub mod frame_counter;
+pub mod noise;
+pub mod pulse;
+pub mod resample;
+pub mod scope;
+pub mod triangle;
+
+use capture::AudioCapture;
+use dmc::Dmc;
+use filter::{FilterChain, FilterKind, NES_FILTERS};
+use frame_counter::{FrameClock, FrameCounter};
+use noise::Noise;
+use pulse::Pulse;
+use resample::{ResampleQuality, Resampler};
+use scope::Scope;
+use triangle::Triangle;
+
+pub const CPU_CLOCK_NTSC: f64 = 1_789_773.0;
+pub const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+pub const CHANNEL_COUNT: usize = 5;
+
+// $4015 bits, channel enables when written and status when read
+pub const STATUS_PULSE_1: u8 = 0b0000_0001;
+pub const STATUS_PULSE_2: u8 = 0b0000_0010;
+pub const STATUS_TRIANGLE: u8 = 0b0000_0100;
+pub const STATUS_NOISE: u8 = 0b0000_1000;
+pub const STATUS_DMC: u8 = 0b0001_0000;
+pub const STATUS_FRAME_IRQ: u8 = 0b0100_0000; // read only
+pub const STATUS_DMC_IRQ: u8 = 0b1000_0000; // read only
+
+/* APU register map
+    $4000-$4003  pulse 1
+    $4004-$4007  pulse 2
+    $4008-$400B  triangle
+    $400C-$400F  noise
+    $4010-$4013  DMC
+    $4015        channel enable (write) / length counter, DMC and IRQ status (read)
+    $4017        frame counter, see frame_counter.rs
+
+   $4015 writes enable the channels. Disabling one zeroes its length
+   counter, silencing it at once, and while disabled length loads are
+   ignored. The DMC bit instead stops the sample once its buffer plays out,
+   or restarts it if it had finished; any write acknowledges the DMC IRQ.
+
+   $4015 reads give whether each length counter is non-zero, whether DMC
+   bytes remain, and the two IRQ flags. Reading acknowledges the frame IRQ
+   but not the DMC's. Bit 5 isn't driven, it reads as 0 here.
+
+   The channels are mixed the way the console's resistor network does it,
+   which is not linear: two pulses at full volume come out quieter than
+   twice one, and the triangle and noise levels depend on what the DMC is
+   outputting. The pulses share one curve and the other three another:
+
+    pulse = 95.88 / (8128 / (pulse_1 + pulse_2) + 100)
+    tnd   = 159.79 / (1 / (triangle / 8227 + noise / 12241 + dmc / 22638) + 100)
+
+   The APU is clocked once per CPU cycle. The mixed output is resampled to
+   the host rate, see resample.rs, and buffered until the frontend collects
+   it, passing through the console's output filters on the way out.
+*/
+
+// length counter load values, indexed by bits 3-7 of the channel's last register
+pub const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+// the hardware mixer, each channel at its DAC level
+pub fn mix(pulse_1: u8, pulse_2: u8, triangle: u8, noise: u8, dmc: u8) -> f32 {
+    let pulse_sum = (pulse_1 + pulse_2) as f32;
+    let pulse = if pulse_sum == 0.0 {
+        0.0
+    } else {
+        95.88 / (8128.0 / pulse_sum + 100.0)
+    };
+    let tnd_sum = triangle as f32 / 8227.0 + noise as f32 / 12241.0 + dmc as f32 / 22638.0;
+    let tnd = if tnd_sum == 0.0 {
+        0.0
+    } else {
+        159.79 / (1.0 / tnd_sum + 100.0)
+    };
+    return pulse + tnd;
+}
+
+pub struct Apu {
+    pub pulse_1: Pulse,
+    pub pulse_2: Pulse,
+    pub triangle: Triangle,
+    pub noise: Noise,
+    pub dmc: Dmc,
+    pub frame_counter: FrameCounter,
+    pub scope: Option<Box<Scope>>, // see `scope`
+    cycle: u64,
+    sample_rate: u32,
+    resampler: Resampler,
+    filters: FilterChain,
+    capture: Option<AudioCapture>,
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        return Self::new(DEFAULT_SAMPLE_RATE);
+    }
+}
+
+impl Apu {
+    pub fn new(sample_rate: u32) -> Self {
+        return Self {
+            pulse_1: Pulse {
+                ones_complement: true,
+                ..Pulse::new()
+            },
+            pulse_2: Pulse::new(),
+            triangle: Triangle::new(),
+            noise: Noise::new(),
+            dmc: Dmc::new(),
+            frame_counter: FrameCounter::new(),
+            scope: None,
+            cycle: 0,
+            sample_rate,
+            resampler: Resampler::new(CPU_CLOCK_NTSC, sample_rate, ResampleQuality::default()),
+            filters: FilterChain::new(&NES_FILTERS, sample_rate),
+            capture: None,
+        };
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        return self.sample_rate;
+    }
+
+    pub fn resample_quality(&self) -> ResampleQuality {
+        return self.resampler.quality();
+    }
+
+    // drops any audio not yet collected
+    pub fn set_resample_quality(&mut self, quality: ResampleQuality) {
+        let ratio = self.resampler.ratio();
+        self.resampler = Resampler::new(CPU_CLOCK_NTSC, self.sample_rate, quality);
+        self.resampler.set_ratio(ratio);
+    }
+
+    // for dynamic rate control, 1.0 is the real rate
+    pub fn set_rate_ratio(&mut self, ratio: f64) {
+        self.resampler.set_ratio(ratio);
+    }
+
+    pub fn rate_ratio(&self) -> f64 {
+        return self.resampler.ratio();
+    }
+
+    pub fn filters(&self) -> Vec<FilterKind> {
+        return self.filters.kinds();
+    }
+
+    // NES_FILTERS by default, an empty list turns filtering off
+    pub fn set_filters(&mut self, kinds: &[FilterKind]) {
+        self.filters = FilterChain::new(kinds, self.sample_rate);
+    }
+
+    pub fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4000..=0x4003 => self.pulse_1.write_register(addr & 0b11, data),
+            0x4004..=0x4007 => self.pulse_2.write_register(addr & 0b11, data),
+            0x4008..=0x400B => self.triangle.write_register(addr & 0b11, data),
+            0x400C..=0x400F => self.noise.write_register(addr & 0b11, data),
+            0x4010..=0x4013 => self.dmc.write_register(addr & 0b11, data),
+            0x4015 => {
+                self.pulse_1.set_enabled(data & STATUS_PULSE_1 != 0);
+                self.pulse_2.set_enabled(data & STATUS_PULSE_2 != 0);
+                self.triangle.set_enabled(data & STATUS_TRIANGLE != 0);
+                self.noise.set_enabled(data & STATUS_NOISE != 0);
+                self.dmc.set_enabled(data & STATUS_DMC != 0);
+            }
+            0x4017 => self.frame_counter.write(data, self.cycle % 2 == 1),
+            _ => {}
+        }
+    }
+
+    pub fn read_status(&mut self) -> u8 {
+        let flags = [
+            (self.pulse_1.length_counter > 0, STATUS_PULSE_1),
+            (self.pulse_2.length_counter > 0, STATUS_PULSE_2),
+            (self.triangle.length_counter > 0, STATUS_TRIANGLE),
+            (self.noise.length_counter > 0, STATUS_NOISE),
+            (self.dmc.bytes_remaining > 0, STATUS_DMC),
+            (self.frame_counter.irq, STATUS_FRAME_IRQ),
+            (self.dmc.irq, STATUS_DMC_IRQ),
+        ];
+        let mut status = 0;
+        for (set, bit) in flags {
+            if set {
+                status |= bit;
+            }
+        }
+        // reading acknowledges the frame IRQ, the DMC one needs a write
+        self.frame_counter.irq = false;
+        return status;
+    }
+
+    // the APU's share of the CPU's IRQ line, held until acknowledged
+    pub fn irq_pending(&self) -> bool {
+        return self.frame_counter.irq || self.dmc.irq;
+    }
+
+    pub fn tick(&mut self, cpu_cycles: u64) {
+        for _ in 0..cpu_cycles {
+            self.cycle += 1;
+            match self.frame_counter.clock() {
+                Some(FrameClock::Quarter) => self.clock_quarter_frame(),
+                Some(FrameClock::Half) => {
+                    self.clock_quarter_frame();
+                    self.clock_half_frame();
+                }
+                None => {}
+            }
+            self.triangle.clock_timer();
+            self.noise.clock_timer();
+            self.dmc.clock_timer();
+            // pulse timers run at half the CPU clock
+            if self.cycle.is_multiple_of(2) {
+                self.pulse_1.clock_timer();
+                self.pulse_2.clock_timer();
+            }
+
+            if self.scope.is_some() {
+                self.record_scope();
+            }
+            let output = self.output();
+            self.resampler.push(output);
+            if let Some(capture) = self.capture.as_mut().filter(|c| c.is_per_channel()) {
+                let levels = [
+                    mix(self.pulse_1.output(), 0, 0, 0, 0),
+                    mix(0, self.pulse_2.output(), 0, 0, 0),
+                    mix(0, 0, self.triangle.output(), 0, 0),
+                    mix(0, 0, 0, self.noise.output(), 0),
+                    mix(0, 0, 0, 0, self.dmc.output()),
+                ];
+                capture.push_levels(&levels);
+            }
+        }
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        self.pulse_1.clock_quarter_frame();
+        self.pulse_2.clock_quarter_frame();
+        self.triangle.clock_quarter_frame();
+        self.noise.clock_quarter_frame();
+    }
+
+    fn clock_half_frame(&mut self) {
+        self.pulse_1.clock_half_frame();
+        self.pulse_2.clock_half_frame();
+        self.triangle.clock_half_frame();
+        self.noise.clock_half_frame();
+    }
+
+    // the mixed output of all five channels, 0.0..=1.0
+    pub fn output(&self) -> f32 {
+        return mix(
+            self.pulse_1.output(),
+            self.pulse_2.output(),
+            self.triangle.output(),
+            self.noise.output(),
+            self.dmc.output(),
+        );
+    }
+
+    // the resampler, with the samples it holds, and the filters' history;
+    // they come after the console, so save states leave them out and
+    // loading one drops what was buffered
+    pub fn output_stage(&self) -> (Resampler, FilterChain) {
+        return (self.resampler.clone(), self.filters.clone());
+    }
+
+    pub fn set_output_stage(&mut self, (resampler, filters): (Resampler, FilterChain)) {
+        self.resampler = resampler;
+        self.filters = filters;
+    }
+
+    // samples produced since the last call, at `sample_rate`
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        let mut samples = self.resampler.take();
+        self.filters.process(&mut samples);
+        if let Some(capture) = &mut self.capture {
+            capture.write(&samples, self.sample_rate);
+        }
+        return samples;
+    }
+
+    // records the mixed output to a WAV file from now until stop_audio_capture
+    pub fn start_audio_capture(&mut self, path: &Path) -> Result<(), String> {
+        self.stop_audio_capture()?;
+        self.capture = Some(AudioCapture::mixed(path)?);
+        return Ok(());
+    }
+
+    // the same, but one WAV channel per APU channel
+    pub fn start_channel_capture(&mut self, path: &Path) -> Result<(), String> {
+        self.stop_audio_capture()?;
+        let capture = AudioCapture::per_channel(
+            path,
+            self.sample_rate,
+            self.resample_quality(),
+            &self.filters(),
+        )?;
+        self.capture = Some(capture);
+        return Ok(());
+    }
+
+    // closes the file; samples not yet collected with take_samples aren't in it
+    pub fn stop_audio_capture(&mut self) -> Result<(), String> {
+        match self.capture.take() {
+            Some(capture) => return capture.finish(),
+            None => return Ok(()),
+        }
+    }
+
+    pub fn is_capturing_audio(&self) -> bool {
+        return self.capture.is_some();
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        self.pulse_1.save_state(writer);
+        self.pulse_2.save_state(writer);
+        self.triangle.save_state(writer);
+        self.noise.save_state(writer);
+        self.dmc.save_state(writer);
+        self.frame_counter.save_state(writer);
+        writer.write_u64(self.cycle);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.pulse_1.load_state(reader)?;
+        self.pulse_2.load_state(reader)?;
+        self.triangle.load_state(reader)?;
+        self.noise.load_state(reader)?;
+        self.dmc.load_state(reader)?;
+        self.frame_counter.load_state(reader)?;
+        self.cycle = reader.read_u64()?;
+        // buffered audio belongs to the timeline we just left
+        self.resampler.clear();
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_status_follows_length_counters() {
+        let mut apu = Apu::default();
+        apu.write_register(0x4015, 0b0000_0101);
+        apu.write_register(0x4003, 0b0000_1000); // length index 1 = 254
+        apu.write_register(0x4007, 0b0000_1000); // pulse 2 is disabled, ignored
+        apu.write_register(0x400B, 0b0000_1000);
+        assert_eq!(apu.read_status(), 0b0000_0101);
+
+        apu.write_register(0x4015, 0b0000_0100);
+        assert_eq!(apu.read_status(), 0b0000_0100);
+    }
+
+    #[test]
+    fn test_frame_counter_clocks_length_counters() {
+        let mut apu = Apu::default();
+        apu.write_register(0x4015, 0b0000_0001);
+        apu.write_register(0x4000, 0b0001_1111);
+        apu.write_register(0x4003, 0b0001_1000); // length 2
+        apu.tick(14913);
+        assert_eq!(apu.pulse_1.length_counter, 1);
+        apu.tick(29829 - 14913);
+        assert!(apu.irq_pending());
+        assert_eq!(apu.read_status(), 0b0100_0000);
+        assert!(!apu.irq_pending());
+
+        // 5 step mode clocks a half frame as soon as the write lands, and no IRQ
+        apu.write_register(0x4003, 0b0001_1000);
+        apu.write_register(0x4017, 0b1100_0000);
+        assert_eq!(apu.read_status() & 0b0100_0000, 0);
+        apu.tick(4);
+        assert_eq!(apu.pulse_1.length_counter, 1);
+        apu.tick(40_000);
+        assert_eq!(apu.read_status(), 0);
+    }
+
+    #[test]
+    fn test_mixer_is_non_linear() {
+        assert_eq!(mix(0, 0, 0, 0, 0), 0.0);
+        let one = mix(15, 0, 0, 0, 0);
+        let both = mix(15, 15, 0, 0, 0);
+        assert!((one - 0.1494).abs() < 0.001);
+        assert!(both < one * 2.0);
+        // everything at full is just under 1.0
+        let full = mix(15, 15, 15, 15, 127);
+        assert!(full > 0.99 && full < 1.0);
+        // the DMC level pulls the triangle down
+        assert!(mix(0, 0, 15, 0, 127) - mix(0, 0, 0, 0, 127) < mix(0, 0, 15, 0, 0));
+    }
+
+    #[test]
+    fn test_status_register() {
+        let mut apu = Apu::default();
+        apu.write_register(0x4015, 0b0001_1111);
+        apu.write_register(0x4003, 0b0000_1000);
+        apu.write_register(0x400F, 0b0000_1000);
+        apu.write_register(0x4010, 0b1000_0000);
+        apu.write_register(0x4013, 0x00);
+        assert_eq!(
+            apu.read_status(),
+            STATUS_PULSE_1 | STATUS_NOISE | STATUS_DMC
+        );
+
+        // disabling drops the length counter straight away
+        apu.write_register(0x4015, 0b0001_0001);
+        assert_eq!(apu.noise.length_counter, 0);
+        apu.write_register(0x400F, 0b0000_1000);
+        assert_eq!(apu.read_status(), STATUS_PULSE_1 | STATUS_DMC);
+
+        // the one byte sample is fetched and finishes, raising the DMC IRQ
+        apu.dmc.fill(0);
+        apu.tick(29_830);
+        let status = apu.read_status();
+        assert_eq!(
+            status & (STATUS_DMC | STATUS_FRAME_IRQ | STATUS_DMC_IRQ),
+            STATUS_FRAME_IRQ | STATUS_DMC_IRQ
+        );
+        // the read cleared the frame IRQ only, a write clears the DMC one
+        assert_eq!(
+            apu.read_status() & (STATUS_FRAME_IRQ | STATUS_DMC_IRQ),
+            STATUS_DMC_IRQ
+        );
+        apu.write_register(0x4015, 0);
+        assert_eq!(apu.read_status(), 0);
+    }
+
+    #[test]
+    fn test_sample_rate_conversion() {
+        let mut apu = Apu::new(48_000);
+        apu.tick(CPU_CLOCK_NTSC as u64);
+        let samples = apu.take_samples();
+        assert!((47_999..=48_000).contains(&samples.len()));
+        assert!(apu.take_samples().is_empty());
+    }
+
+    #[test]
+    fn test_pulse_produces_sound() {
+        let mut apu = Apu::default();
+        apu.write_register(0x4015, 0b0000_0001);
+        apu.write_register(0x4000, 0b1011_1111); // 50% duty, constant volume 15
+        apu.write_register(0x4002, 0xFD); // ~440Hz
+        apu.write_register(0x4003, 0b0000_1000);
+        apu.tick(30_000);
+        let samples = apu.take_samples();
+        let max = samples.iter().cloned().fold(0.0, f32::max);
+        let min = samples.iter().cloned().fold(1.0, f32::min);
+        // the idle triangle holds a constant level, the pulse swings on top of it
+        assert!(max - min > 0.1);
+    }
+
+    #[test]
+    fn test_audio_capture() {
+        let dir = std::env::temp_dir().join(format!("rustynes-capture-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mixed = dir.join("mixed.wav");
+        let channels = dir.join("channels.wav");
+
+        let mut apu = Apu::default();
+        apu.write_register(0x4015, 0b0000_0001);
+        apu.write_register(0x4000, 0b1011_1111);
+        apu.write_register(0x4002, 0xFD);
+        apu.write_register(0x4003, 0b0000_1000);
+        apu.start_audio_capture(&mixed).unwrap();
+        apu.tick(10_000);
+        let samples = apu.take_samples();
+        apu.start_channel_capture(&channels).unwrap(); // ends the mixed capture
+        apu.tick(10_000);
+        let more = apu.take_samples();
+        apu.stop_audio_capture().unwrap();
+        assert!(!apu.is_capturing_audio());
+
+        let wav = std::fs::read(&mixed).unwrap();
+        assert_eq!(wav.len(), 44 + samples.len() * 2);
+        let wav = std::fs::read(&channels).unwrap();
+        assert_eq!(u16::from_le_bytes([wav[22], wav[23]]), CHANNEL_COUNT as u16);
+        assert_eq!(wav.len(), 44 + more.len() * 2 * CHANNEL_COUNT);
+        // only pulse 1 is playing
+        let track = |channel: usize| -> Vec<i16> {
+            return wav[44..]
+                .chunks(2 * CHANNEL_COUNT)
+                .map(|frame| i16::from_le_bytes([frame[channel * 2], frame[channel * 2 + 1]]))
+                .collect();
+        };
+        assert!(track(0).iter().any(|&sample| sample.abs() > 1000));
+        assert!(track(1).iter().all(|&sample| sample == 0));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_save_load_state() {
+        let mut apu = Apu::default();
+        apu.write_register(0x4015, 0b0000_1111);
+        apu.write_register(0x400E, 0x85);
+        apu.write_register(0x400F, 0b0000_1000);
+        apu.tick(1234);
+
+        let mut writer = StateWriter::new();
+        apu.save_state(&mut writer);
+        let data = writer.into_bytes();
+        let mut restored = Apu::default();
+        restored.load_state(&mut StateReader::new(&data)).unwrap();
+        assert_eq!(restored.read_status(), apu.read_status());
+        assert_eq!(restored.output(), apu.output());
+    }
+}
@@ -0,0 +1,180 @@
+use crate::apu::envelope::Envelope;
+use crate::apu::LENGTH_TABLE;
+use crate::error::StateError;
+use crate::savestate::{StateReader, StateWriter};
+
+// timer periods in CPU cycles
+const PERIOD_TABLE: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+
+/* Noise channel registers
+    $400C  --LC VVVV  length counter halt, constant volume, volume
+    $400E  M--- PPPP  mode (short sequence), period index
+    $400F  LLLL L---  length counter load
+*/
+
+#[derive(Debug, Clone)]
+pub struct Noise {
+    pub enabled: bool,
+    pub length_halt: bool,
+    pub constant_volume: bool,
+    pub volume: u8,
+    pub envelope: Envelope,
+    pub short_mode: bool,
+    pub timer_period: u16,
+    pub timer: u16,
+    pub shift_register: u16,
+    pub length_counter: u8,
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl Noise {
+    pub fn new() -> Self {
+        return Self {
+            enabled: false,
+            length_halt: false,
+            constant_volume: false,
+            volume: 0,
+            envelope: Envelope::new(),
+            short_mode: false,
+            timer_period: PERIOD_TABLE[0],
+            timer: 0,
+            shift_register: 1, // loaded with 1 at power on
+            length_counter: 0,
+        };
+    }
+
+    pub fn write_register(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.length_halt = data & 0b0010_0000 != 0;
+                self.constant_volume = data & 0b0001_0000 != 0;
+                self.volume = data & 0b0000_1111;
+            }
+            1 => {}
+            2 => {
+                self.short_mode = data & 0b1000_0000 != 0;
+                self.timer_period = PERIOD_TABLE[(data & 0b0000_1111) as usize];
+            }
+            _ => {
+                if self.enabled {
+                    self.length_counter = LENGTH_TABLE[(data >> 3) as usize];
+                }
+                self.envelope.restart();
+            }
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.length_counter = 0;
+        }
+    }
+
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period - 1;
+            let tap = if self.short_mode { 6 } else { 1 };
+            let feedback = (self.shift_register ^ (self.shift_register >> tap)) & 1;
+            self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock(self.volume, self.length_halt);
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        if !self.length_halt && self.length_counter > 0 {
+            self.length_counter -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.length_counter == 0 || self.shift_register & 1 != 0 {
+            return 0;
+        }
+        return self.envelope.volume(self.constant_volume, self.volume);
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.enabled);
+        writer.write_bool(self.length_halt);
+        writer.write_bool(self.constant_volume);
+        writer.write_u8(self.volume);
+        self.envelope.save_state(writer);
+        writer.write_bool(self.short_mode);
+        writer.write_u16(self.timer_period);
+        writer.write_u16(self.timer);
+        writer.write_u16(self.shift_register);
+        writer.write_u8(self.length_counter);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = reader.read_bool()?;
+        self.length_halt = reader.read_bool()?;
+        self.constant_volume = reader.read_bool()?;
+        self.volume = reader.read_u8()?;
+        self.envelope.load_state(reader)?;
+        self.short_mode = reader.read_bool()?;
+        self.timer_period = reader.read_u16()?.max(1);
+        self.timer = reader.read_u16()?;
+        self.shift_register = reader.read_u16()?;
+        self.length_counter = reader.read_u8()?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_long_sequence_period() {
+        let mut noise = Noise::new();
+        noise.write_register(2, 0);
+        let start = noise.shift_register;
+        let mut steps = 0;
+        loop {
+            for _ in 0..4 {
+                noise.clock_timer();
+            }
+            steps += 1;
+            if noise.shift_register == start {
+                break;
+            }
+        }
+        assert_eq!(steps, 32767);
+    }
+
+    #[test]
+    fn test_short_sequence_period() {
+        let mut noise = Noise::new();
+        noise.write_register(2, 0b1000_0000);
+        // the short mode register settles into a 93 or 31 step loop
+        for _ in 0..4 * 200 {
+            noise.clock_timer();
+        }
+        let start = noise.shift_register;
+        let mut steps = 0;
+        loop {
+            for _ in 0..4 {
+                noise.clock_timer();
+            }
+            steps += 1;
+            if noise.shift_register == start {
+                break;
+            }
+        }
+        assert!(steps == 93 || steps == 31);
+    }
+}
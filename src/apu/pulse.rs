@@ -0,0 +1,256 @@
+use crate::apu::envelope::Envelope;
+use crate::apu::LENGTH_TABLE;
+use crate::error::StateError;
+use crate::savestate::{StateReader, StateWriter};
+
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0], // 12.5%
+    [0, 1, 1, 0, 0, 0, 0, 0], // 25%
+    [0, 1, 1, 1, 1, 0, 0, 0], // 50%
+    [1, 0, 0, 1, 1, 1, 1, 1], // 25% negated
+];
+
+/* Pulse channel registers
+    $4000  DDLC VVVV  duty, length counter halt, constant volume, volume
+    $4001  EPPP NSSS  sweep unit
+    $4002  TTTT TTTT  timer low
+    $4003  LLLL LTTT  length counter load, timer high
+
+   The sweep unit moves the period by a fraction of itself, period >> S,
+   every P + 1 half frames. Pulse 1 negates with one's complement, so it
+   goes one lower than pulse 2 for the same setting. A target period past
+   $7FF, or a current one below 8, mutes the channel whether or not the
+   sweep is enabled.
+*/
+
+#[derive(Debug, Default, Clone)]
+pub struct Pulse {
+    pub enabled: bool,
+    pub duty: u8,
+    pub length_halt: bool,
+    pub constant_volume: bool,
+    pub volume: u8,
+    pub envelope: Envelope,
+    pub sweep_enabled: bool,
+    pub sweep_period: u8,
+    pub sweep_negate: bool,
+    pub sweep_shift: u8,
+    pub sweep_reload: bool,
+    pub sweep_divider: u8,
+    pub ones_complement: bool, // set on pulse 1
+    pub timer_period: u16,
+    pub timer: u16,
+    pub sequence_step: u8,
+    pub length_counter: u8,
+}
+
+impl Pulse {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    pub fn write_register(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.duty = data >> 6;
+                self.length_halt = data & 0b0010_0000 != 0;
+                self.constant_volume = data & 0b0001_0000 != 0;
+                self.volume = data & 0b0000_1111;
+            }
+            1 => {
+                self.sweep_enabled = data & 0b1000_0000 != 0;
+                self.sweep_period = (data >> 4) & 0b111;
+                self.sweep_negate = data & 0b0000_1000 != 0;
+                self.sweep_shift = data & 0b0000_0111;
+                self.sweep_reload = true;
+            }
+            2 => self.timer_period = (self.timer_period & 0xFF00) | data as u16,
+            _ => {
+                self.timer_period = (self.timer_period & 0x00FF) | ((data as u16 & 0b111) << 8);
+                if self.enabled {
+                    self.length_counter = LENGTH_TABLE[(data >> 3) as usize];
+                }
+                self.sequence_step = 0;
+                self.envelope.restart();
+            }
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.length_counter = 0;
+        }
+    }
+
+    // clocked every other CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.sequence_step = (self.sequence_step + 1) & 0b111;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock(self.volume, self.length_halt);
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        if !self.length_halt && self.length_counter > 0 {
+            self.length_counter -= 1;
+        }
+
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.muted() {
+            self.timer_period = self.sweep_target();
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    fn sweep_target(&self) -> u16 {
+        let change = self.timer_period >> self.sweep_shift;
+        if !self.sweep_negate {
+            return self.timer_period + change;
+        }
+        let change = change + self.ones_complement as u16;
+        return self.timer_period.saturating_sub(change);
+    }
+
+    // periods below 8 would be ultrasonic and are muted by the hardware
+    fn muted(&self) -> bool {
+        return self.timer_period < 8 || self.sweep_target() > 0x7FF;
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.length_counter == 0 || self.muted() {
+            return 0;
+        }
+        let volume = self.envelope.volume(self.constant_volume, self.volume);
+        return DUTY_TABLE[self.duty as usize][self.sequence_step as usize] * volume;
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.enabled);
+        writer.write_u8(self.duty);
+        writer.write_bool(self.length_halt);
+        writer.write_bool(self.constant_volume);
+        writer.write_u8(self.volume);
+        self.envelope.save_state(writer);
+        writer.write_bool(self.sweep_enabled);
+        writer.write_u8(self.sweep_period);
+        writer.write_bool(self.sweep_negate);
+        writer.write_u8(self.sweep_shift);
+        writer.write_bool(self.sweep_reload);
+        writer.write_u8(self.sweep_divider);
+        writer.write_u16(self.timer_period);
+        writer.write_u16(self.timer);
+        writer.write_u8(self.sequence_step);
+        writer.write_u8(self.length_counter);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = reader.read_bool()?;
+        self.duty = reader.read_u8()? & 0b11;
+        self.length_halt = reader.read_bool()?;
+        self.constant_volume = reader.read_bool()?;
+        self.volume = reader.read_u8()?;
+        self.envelope.load_state(reader)?;
+        self.sweep_enabled = reader.read_bool()?;
+        self.sweep_period = reader.read_u8()? & 0b111;
+        self.sweep_negate = reader.read_bool()?;
+        self.sweep_shift = reader.read_u8()? & 0b111;
+        self.sweep_reload = reader.read_bool()?;
+        self.sweep_divider = reader.read_u8()? & 0b111;
+        self.timer_period = reader.read_u16()?;
+        self.timer = reader.read_u16()?;
+        self.sequence_step = reader.read_u8()? & 0b111;
+        self.length_counter = reader.read_u8()?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_duty_sequence() {
+        let mut pulse = Pulse::new();
+        pulse.set_enabled(true);
+        pulse.write_register(0, 0b0101_1010); // 25%, volume 10
+        pulse.write_register(2, 8);
+        pulse.write_register(3, 0);
+
+        let mut wave = Vec::new();
+        for _ in 0..8 {
+            wave.push(pulse.output());
+            for _ in 0..9 {
+                pulse.clock_timer();
+            }
+        }
+        assert_eq!(wave, vec![0, 10, 10, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_disabled_channel_ignores_length_load() {
+        let mut pulse = Pulse::new();
+        pulse.write_register(3, 0b1111_1000);
+        assert_eq!(pulse.length_counter, 0);
+        pulse.set_enabled(true);
+        pulse.write_register(3, 0b1111_1000);
+        assert_eq!(pulse.length_counter, 30);
+        pulse.set_enabled(false);
+        assert_eq!(pulse.length_counter, 0);
+    }
+
+    #[test]
+    fn test_length_counter_and_envelope() {
+        let mut pulse = Pulse::new();
+        pulse.set_enabled(true);
+        pulse.write_register(0, 0b1000_0000); // envelope period 0, counting
+        pulse.write_register(3, 0b0001_1000); // length 2
+        pulse.clock_quarter_frame();
+        assert_eq!(pulse.envelope.decay, 15);
+        pulse.clock_quarter_frame();
+        assert_eq!(pulse.envelope.decay, 14);
+
+        pulse.clock_half_frame();
+        assert_eq!(pulse.length_counter, 1);
+        pulse.write_register(0, 0b1010_0000); // halted
+        pulse.clock_half_frame();
+        assert_eq!(pulse.length_counter, 1);
+    }
+
+    #[test]
+    fn test_sweep() {
+        let mut pulse = Pulse::new();
+        pulse.write_register(2, 0x00);
+        pulse.write_register(3, 0x01); // period $100
+        pulse.write_register(1, 0b1000_0001); // every half frame, up by half
+        pulse.clock_half_frame();
+        assert_eq!(pulse.timer_period, 0x180);
+
+        pulse.write_register(1, 0b1000_1001); // down by half
+        pulse.clock_half_frame();
+        assert_eq!(pulse.timer_period, 0xC0);
+        pulse.ones_complement = true;
+        pulse.clock_half_frame();
+        assert_eq!(pulse.timer_period, 0x5F);
+
+        // a target out of range mutes the channel and stops the sweep
+        pulse.set_enabled(true);
+        pulse.write_register(0, 0b0011_1111);
+        pulse.write_register(2, 0xFF);
+        pulse.write_register(3, 0b0000_1111);
+        pulse.write_register(1, 0b1000_0001);
+        pulse.clock_half_frame();
+        assert_eq!(pulse.timer_period, 0x7FF);
+        assert_eq!(pulse.output(), 0);
+    }
+}
@@ -0,0 +1,245 @@
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+const PHASES: usize = 256; // sub-sample positions a step can land on
+const CUTOFF: f64 = 0.9; // of the output Nyquist frequency
+
+/* Resampling
+
+   The mixer produces one value per CPU cycle, ~1.79MHz, and the host wants
+   44.1 or 48kHz. Simply averaging each output period lets everything above
+   the output Nyquist frequency alias back down as audible hiss and whine.
+
+   The output only ever changes in steps, when a channel moves to a new
+   level, so instead of filtering every input sample the resampler works
+   on the steps, the way blip-buffer style synthesis does: each change is
+   added to the output as a band-limited step, a windowed-sinc impulse at
+   the step's exact sub-sample position which the output then integrates.
+   The cost is per change rather than per CPU cycle, and the kernel width
+   sets the quality; the output lags by half a kernel.
+
+   The rate can be nudged by a fraction of a percent while running, for
+   frontends that pace the emulator by the sound card's clock and need a
+   little more or less audio per frame to keep its buffer level.
+
+    Fast  average over each output period, no band-limiting
+    Good  16 sample kernel
+    Best  32 sample kernel
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResampleQuality {
+    Fast,
+    #[default]
+    Good,
+    Best,
+}
+
+impl ResampleQuality {
+    pub fn parse(name: &str) -> Result<ResampleQuality, String> {
+        match name {
+            "fast" => return Ok(ResampleQuality::Fast),
+            "good" => return Ok(ResampleQuality::Good),
+            "best" => return Ok(ResampleQuality::Best),
+            _ => return Err(format!("unknown resampling quality {}", name)),
+        }
+    }
+
+    fn kernel_width(&self) -> usize {
+        match self {
+            ResampleQuality::Fast => return 0,
+            ResampleQuality::Good => return 16,
+            ResampleQuality::Best => return 32,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Resampler {
+    quality: ResampleQuality,
+    step: f64,              // output samples per input sample
+    base_step: f64,         // the same before `set_ratio`
+    time: f64,              // position of the next input, in output samples after `pending[0]`
+    kernel: Vec<Vec<f64>>,  // per phase, one weight per output sample
+    pending: VecDeque<f64>, // step contributions to the output samples still to come
+    level: f64,             // the integrated output
+    last_input: f32,
+    sum: f64, // for Fast
+    count: u32,
+    output: Vec<f32>,
+}
+
+impl Resampler {
+    pub fn new(input_rate: f64, output_rate: u32, quality: ResampleQuality) -> Self {
+        let width = quality.kernel_width();
+        let kernel = (0..PHASES)
+            .map(|phase| step_kernel(width, phase as f64 / PHASES as f64))
+            .collect();
+        return Self {
+            quality,
+            step: output_rate as f64 / input_rate,
+            base_step: output_rate as f64 / input_rate,
+            time: 0.0,
+            kernel,
+            pending: VecDeque::from(vec![0.0; width + 1]),
+            level: 0.0,
+            last_input: 0.0,
+            sum: 0.0,
+            count: 0,
+            output: Vec::new(),
+        };
+    }
+
+    pub fn quality(&self) -> ResampleQuality {
+        return self.quality;
+    }
+
+    // makes `ratio` times as many output samples as the rates given to `new`
+    pub fn set_ratio(&mut self, ratio: f64) {
+        self.step = self.base_step * ratio;
+    }
+
+    pub fn ratio(&self) -> f64 {
+        return self.step / self.base_step;
+    }
+
+    // one input sample
+    pub fn push(&mut self, input: f32) {
+        if self.quality == ResampleQuality::Fast {
+            self.sum += input as f64;
+            self.count += 1;
+        } else if input != self.last_input {
+            let delta = (input - self.last_input) as f64;
+            self.last_input = input;
+            let phase = ((self.time * PHASES as f64) as usize).min(PHASES - 1);
+            for (slot, weight) in self.pending.iter_mut().zip(&self.kernel[phase]) {
+                *slot += delta * weight;
+            }
+        }
+
+        self.time += self.step;
+        if self.time >= 1.0 {
+            self.time -= 1.0;
+            self.emit();
+        }
+    }
+
+    fn emit(&mut self) {
+        if self.quality == ResampleQuality::Fast {
+            self.output.push((self.sum / self.count as f64) as f32);
+            self.sum = 0.0;
+            self.count = 0;
+            return;
+        }
+        self.level += self.pending.pop_front().unwrap_or(0.0);
+        self.pending.push_back(0.0);
+        self.output.push(self.level as f32);
+    }
+
+    pub fn take(&mut self) -> Vec<f32> {
+        return std::mem::take(&mut self.output);
+    }
+
+    // forgets buffered output and in-flight steps, holding the current level
+    pub fn clear(&mut self) {
+        self.output.clear();
+        self.level = self.last_input as f64;
+        self.pending.iter_mut().for_each(|slot| *slot = 0.0);
+        self.sum = 0.0;
+        self.count = 0;
+    }
+}
+
+// a windowed-sinc impulse `offset` of a sample late, normalized to sum to 1
+fn step_kernel(width: usize, offset: f64) -> Vec<f64> {
+    let mut kernel: Vec<f64> = (0..width)
+        .map(|k| {
+            let x = k as f64 - offset - width as f64 / 2.0;
+            let sinc = if x == 0.0 {
+                1.0
+            } else {
+                (PI * CUTOFF * x).sin() / (PI * CUTOFF * x)
+            };
+            // Blackman window over the kernel
+            let n = (x + width as f64 / 2.0) / width as f64;
+            let window = 0.42 - 0.5 * (2.0 * PI * n).cos() + 0.08 * (4.0 * PI * n).cos();
+            return sinc * window.max(0.0);
+        })
+        .collect();
+    let total: f64 = kernel.iter().sum();
+    for weight in kernel.iter_mut() {
+        *weight /= total;
+    }
+    return kernel;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const INPUT_RATE: f64 = 1_789_773.0;
+
+    // a square wave flipping every `half_period` cycles, for a tenth of a second
+    fn square(quality: ResampleQuality, half_period: usize) -> Vec<f32> {
+        let mut resampler = Resampler::new(INPUT_RATE, 44_100, quality);
+        for cycle in 0..INPUT_RATE as usize / 10 {
+            let high = (cycle / half_period) % 2 == 1;
+            resampler.push(if high { 0.5 } else { 0.0 });
+        }
+        return resampler.take();
+    }
+
+    #[test]
+    fn test_steps_settle_at_the_input_level() {
+        for quality in [
+            ResampleQuality::Fast,
+            ResampleQuality::Good,
+            ResampleQuality::Best,
+        ] {
+            let mut resampler = Resampler::new(INPUT_RATE, 48_000, quality);
+            for _ in 0..10_000 {
+                resampler.push(0.25);
+            }
+            let output = resampler.take();
+            assert!((output.last().unwrap() - 0.25).abs() < 1e-6);
+            assert!(output.len() == 268 || output.len() == 269);
+        }
+    }
+
+    #[test]
+    fn test_band_limiting_removes_ultrasonic_tones() {
+        // a ~30kHz square wave is above 22.05kHz, so nothing of it should remain
+        let swing = |samples: &[f32]| -> f32 {
+            let settled = &samples[100..];
+            let max = settled.iter().cloned().fold(f32::MIN, f32::max);
+            let min = settled.iter().cloned().fold(f32::MAX, f32::min);
+            return max - min;
+        };
+        let fast = swing(&square(ResampleQuality::Fast, 30));
+        let good = swing(&square(ResampleQuality::Good, 30));
+        let best = swing(&square(ResampleQuality::Best, 30));
+        assert!(fast > 0.1);
+        assert!(good < fast / 10.0);
+        assert!(best <= good);
+
+        // while an audible tone keeps its full swing
+        assert!(swing(&square(ResampleQuality::Best, 2034)) > 0.45);
+    }
+
+    #[test]
+    fn test_ratio() {
+        let mut resampler = Resampler::new(INPUT_RATE, 48_000, ResampleQuality::Good);
+        resampler.set_ratio(1.01);
+        for _ in 0..100_000 {
+            resampler.push(0.25);
+        }
+        // 2681 and a bit at the plain rate
+        assert_eq!(resampler.take().len(), 2708);
+    }
+
+    #[test]
+    fn test_parse_quality() {
+        assert_eq!(ResampleQuality::parse("best"), Ok(ResampleQuality::Best));
+        assert!(ResampleQuality::parse("ultra").is_err());
+    }
+}
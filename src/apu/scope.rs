@@ -0,0 +1,222 @@
+use crate::apu::{Apu, CHANNEL_COUNT, CPU_CLOCK_NTSC};
+use std::collections::VecDeque;
+use std::fmt;
+
+// CPU cycles between the levels kept, about 28 kHz
+pub const SAMPLE_CYCLES: u64 = 64;
+// levels kept for each channel, a little over a frame
+const HISTORY: usize = 512;
+// a cell of a waveform, from empty to full in eighths
+const BLOCKS: [char; 9] = [' ', '▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+const DUTY_NAMES: [&str; 4] = ["12.5%", "25%", "50%", "75%"];
+
+/* APU scope
+
+   What the sound hardware is doing, for composers checking a driver and
+   for work on the APU itself. `Apu::channels` is each channel as it is
+   now: its timer period and the pitch that gives, its volume as the
+   envelope has it, the pulses' duty and what's left of the length counter
+   (bytes of the sample for the DMC). A triangle has no volume to speak of,
+   and the noise timer sets how often the shift register moves rather
+   than a pitch, so they have none.
+
+   With the scope on, the APU also keeps each channel's output level every
+   `SAMPLE_CYCLES` cycles, the last `HISTORY` of them, which `waveform`
+   draws in block characters like an oscilloscope: at 64 cycles a cell, a
+   row of 64 shows a little over two milliseconds, a cycle of an A440.
+*/
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelInfo {
+    pub name: &'static str,
+    pub enabled: bool,
+    pub period: u16,
+    pub frequency: Option<f64>, // in Hz
+    pub volume: Option<u8>,
+    pub duty: Option<u8>,
+    pub length: u16,
+    pub output: u8,
+    pub max: u8, // the highest level it can output
+}
+
+impl fmt::Display for ChannelInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let frequency = self
+            .frequency
+            .map_or(String::new(), |hz| format!("{:.1} Hz", hz));
+        let volume = self.volume.map_or("-".to_string(), |v| v.to_string());
+        let duty = self.duty.map_or(String::new(), |d| {
+            format!("duty {}", DUTY_NAMES[d as usize])
+        });
+        write!(
+            f,
+            "{:<9} ${:03X} {:>10}  volume {:>3}  {:<10}  length {:>4}",
+            self.name, self.period, frequency, volume, duty, self.length
+        )?;
+        if !self.enabled {
+            write!(f, "  off")?;
+        }
+        return Ok(());
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Scope {
+    levels: [VecDeque<u8>; CHANNEL_COUNT],
+}
+
+impl Default for Scope {
+    fn default() -> Self {
+        return Self {
+            levels: std::array::from_fn(|_| VecDeque::with_capacity(HISTORY)),
+        };
+    }
+}
+
+impl Scope {
+    pub fn record(&mut self, outputs: [u8; CHANNEL_COUNT]) {
+        for (levels, output) in self.levels.iter_mut().zip(outputs) {
+            if levels.len() == HISTORY {
+                levels.pop_front();
+            }
+            levels.push_back(output);
+        }
+    }
+
+    // the last `count` levels of a channel, by its place in
+    // `Apu::channels`, oldest first
+    pub fn history(&self, channel: usize, count: usize) -> Vec<u8> {
+        let levels = &self.levels[channel];
+        return levels
+            .iter()
+            .skip(levels.len().saturating_sub(count))
+            .copied()
+            .collect();
+    }
+}
+
+impl Apu {
+    pub fn channels(&self) -> [ChannelInfo; CHANNEL_COUNT] {
+        let pulse = |pulse: &super::pulse::Pulse, name| ChannelInfo {
+            name,
+            enabled: pulse.enabled,
+            period: pulse.timer_period,
+            frequency: Some(CPU_CLOCK_NTSC / (16.0 * (pulse.timer_period as f64 + 1.0))),
+            volume: Some(pulse.envelope.volume(pulse.constant_volume, pulse.volume)),
+            duty: Some(pulse.duty),
+            length: pulse.length_counter as u16,
+            output: pulse.output(),
+            max: 15,
+        };
+        let (triangle, noise, dmc) = (&self.triangle, &self.noise, &self.dmc);
+        return [
+            pulse(&self.pulse_1, "pulse 1"),
+            pulse(&self.pulse_2, "pulse 2"),
+            ChannelInfo {
+                name: "triangle",
+                enabled: triangle.enabled,
+                period: triangle.timer_period,
+                frequency: Some(CPU_CLOCK_NTSC / (32.0 * (triangle.timer_period as f64 + 1.0))),
+                volume: None,
+                duty: None,
+                length: triangle.length_counter as u16,
+                output: triangle.output(),
+                max: 15,
+            },
+            ChannelInfo {
+                name: "noise",
+                enabled: noise.enabled,
+                period: noise.timer_period,
+                frequency: None,
+                volume: Some(noise.envelope.volume(noise.constant_volume, noise.volume)),
+                duty: None,
+                length: noise.length_counter as u16,
+                output: noise.output(),
+                max: 15,
+            },
+            // a bit of the sample each period
+            ChannelInfo {
+                name: "dmc",
+                enabled: dmc.enabled,
+                period: dmc.timer_period,
+                frequency: Some(CPU_CLOCK_NTSC / dmc.timer_period as f64),
+                volume: Some(dmc.output_level),
+                duty: None,
+                length: dmc.bytes_remaining,
+                output: dmc.output(),
+                max: 127,
+            },
+        ];
+    }
+
+    pub(super) fn record_scope(&mut self) {
+        if !self.cycle.is_multiple_of(SAMPLE_CYCLES) {
+            return;
+        }
+        let outputs = [
+            self.pulse_1.output(),
+            self.pulse_2.output(),
+            self.triangle.output(),
+            self.noise.output(),
+            self.dmc.output(),
+        ];
+        if let Some(scope) = &mut self.scope {
+            scope.record(outputs);
+        }
+    }
+}
+
+// `levels` from 0 to `max` as `rows` rows of bars, a cell a level, the
+// top row first
+pub fn waveform(levels: &[u8], max: u8, rows: usize) -> Vec<String> {
+    let eighths: Vec<usize> = levels
+        .iter()
+        .map(|&level| level.min(max) as usize * rows * 8 / max.max(1) as usize)
+        .collect();
+    return (0..rows)
+        .map(|row| {
+            let below = (rows - 1 - row) * 8;
+            eighths
+                .iter()
+                .map(|&height| BLOCKS[height.saturating_sub(below).min(8)])
+                .collect()
+        })
+        .collect();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_channels_and_waveforms() {
+        let mut apu = Apu::default();
+        apu.write_register(0x4015, 0b0000_0001);
+        apu.write_register(0x4000, 0b1011_1111); // 50%, constant volume 15
+        apu.write_register(0x4002, 0xFD);
+        apu.write_register(0x4003, 0b0000_1000); // length 254
+        let channels = apu.channels();
+        assert_eq!(
+            channels[0].to_string(),
+            "pulse 1   $0FD   440.4 Hz  volume  15  duty 50%    length  254"
+        );
+        assert_eq!(
+            channels[2].to_string(),
+            "triangle  $000 55930.4 Hz  volume   -              length    0  off"
+        );
+        assert_eq!(channels[4].max, 127);
+
+        apu.scope = Some(Box::default());
+        apu.tick(254 * 16 * 2);
+        let scope = apu.scope.as_ref().unwrap();
+        // a whole period of it, half high and half low
+        let pulse = scope.history(0, 64);
+        assert_eq!(pulse.len(), 64);
+        let high = pulse.iter().filter(|&&level| level == 15).count();
+        assert!((31..=33).contains(&high));
+        assert!(scope.history(1, 64).iter().all(|&level| level == 0));
+
+        assert_eq!(waveform(&[0, 15, 8, 2], 15, 1), vec![" █▄▁"]);
+        assert_eq!(waveform(&[0, 15, 8, 2], 15, 2), vec![" █  ", " ██▂"]);
+    }
+}
@@ -0,0 +1,163 @@
+use crate::apu::LENGTH_TABLE;
+use crate::error::StateError;
+use crate::savestate::{StateReader, StateWriter};
+
+const SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12,
+    13, 14, 15,
+];
+
+/* Triangle channel registers
+    $4008  CRRR RRRR  length counter halt / linear counter control, linear counter reload
+    $400A  TTTT TTTT  timer low
+    $400B  LLLL LTTT  length counter load, timer high
+
+   Besides the length counter the triangle has a linear counter, clocked
+   every quarter frame. Writing $400B flags it for reload, and the flag
+   stays set while the control bit is, so a set control bit holds the
+   counter at the reload value.
+*/
+
+#[derive(Debug, Default, Clone)]
+pub struct Triangle {
+    pub enabled: bool,
+    pub control: bool,
+    pub linear_reload: u8,
+    pub linear_counter: u8,
+    pub linear_reload_flag: bool,
+    pub timer_period: u16,
+    pub timer: u16,
+    pub sequence_step: u8,
+    pub length_counter: u8,
+}
+
+impl Triangle {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    pub fn write_register(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.control = data & 0b1000_0000 != 0;
+                self.linear_reload = data & 0b0111_1111;
+            }
+            1 => {}
+            2 => self.timer_period = (self.timer_period & 0xFF00) | data as u16,
+            _ => {
+                self.timer_period = (self.timer_period & 0x00FF) | ((data as u16 & 0b111) << 8);
+                if self.enabled {
+                    self.length_counter = LENGTH_TABLE[(data >> 3) as usize];
+                }
+                self.linear_reload_flag = true;
+            }
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.length_counter = 0;
+        }
+    }
+
+    // clocked every CPU cycle; a silenced triangle holds its level instead of dropping to 0
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            if self.length_counter > 0 && self.linear_counter > 0 {
+                self.sequence_step = (self.sequence_step + 1) & 0b1_1111;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        if self.linear_reload_flag {
+            self.linear_counter = self.linear_reload;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.control {
+            self.linear_reload_flag = false;
+        }
+    }
+
+    // the control bit doubles as the length counter halt
+    pub fn clock_half_frame(&mut self) {
+        if !self.control && self.length_counter > 0 {
+            self.length_counter -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        return SEQUENCE[self.sequence_step as usize];
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.enabled);
+        writer.write_bool(self.control);
+        writer.write_u8(self.linear_reload);
+        writer.write_u8(self.linear_counter);
+        writer.write_bool(self.linear_reload_flag);
+        writer.write_u16(self.timer_period);
+        writer.write_u16(self.timer);
+        writer.write_u8(self.sequence_step);
+        writer.write_u8(self.length_counter);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = reader.read_bool()?;
+        self.control = reader.read_bool()?;
+        self.linear_reload = reader.read_u8()?;
+        self.linear_counter = reader.read_u8()?;
+        self.linear_reload_flag = reader.read_bool()?;
+        self.timer_period = reader.read_u16()?;
+        self.timer = reader.read_u16()?;
+        self.sequence_step = reader.read_u8()? & 0b1_1111;
+        self.length_counter = reader.read_u8()?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sequence_only_runs_while_audible() {
+        let mut triangle = Triangle::new();
+        triangle.write_register(0, 0x7F);
+        triangle.write_register(2, 0);
+        triangle.clock_timer();
+        assert_eq!(triangle.output(), 15); // no length loaded, holds
+
+        triangle.set_enabled(true);
+        triangle.write_register(3, 0b0000_1000);
+        triangle.clock_quarter_frame();
+        triangle.clock_timer();
+        triangle.clock_timer();
+        assert_eq!(triangle.output(), 13);
+    }
+
+    #[test]
+    fn test_linear_counter() {
+        let mut triangle = Triangle::new();
+        triangle.write_register(0, 2);
+        triangle.write_register(3, 0);
+        triangle.clock_quarter_frame();
+        assert_eq!(triangle.linear_counter, 2);
+        triangle.clock_quarter_frame();
+        triangle.clock_quarter_frame();
+        triangle.clock_quarter_frame();
+        assert_eq!(triangle.linear_counter, 0);
+
+        // with control set the reload flag sticks
+        triangle.write_register(0, 0b1000_0010);
+        triangle.write_register(3, 0);
+        triangle.clock_quarter_frame();
+        triangle.clock_quarter_frame();
+        assert_eq!(triangle.linear_counter, 2);
+    }
+}
@@ -0,0 +1,163 @@
+use super::inflate::{DISTANCE_BASE, DISTANCE_EXTRA, LENGTH_BASE, LENGTH_EXTRA};
+
+const WINDOW_SIZE: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: u32 = 15;
+const MAX_CHAIN: usize = 64; // earlier positions tried per match
+const END_OF_BLOCK: u16 = 256;
+
+/* DEFLATE encoder (RFC 1951)
+
+   The other direction, for screenshots and recordings. Everything goes out
+   as a single block with the fixed Huffman codes, so there is no code table
+   to build or send: repeats are found with a hash of the next three bytes
+   chained back through the window, the longest of the first few candidates
+   wins, and anything unmatched is a literal. NES pictures are mostly long
+   runs of a few colours, which this handles nearly as well as zlib does.
+*/
+
+struct BitWriter {
+    out: Vec<u8>,
+    buffer: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        return Self {
+            out: Vec::new(),
+            buffer: 0,
+            count: 0,
+        };
+    }
+
+    // least significant first, like the reader
+    fn bits(&mut self, value: u32, n: u32) {
+        self.buffer |= value << self.count;
+        self.count += n;
+        while self.count >= 8 {
+            self.out.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    // Huffman codes are packed starting from their most significant bit
+    fn code(&mut self, code: u32, n: u32) {
+        self.bits(code.reverse_bits() >> (32 - n), n);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.buffer as u8);
+        }
+        return self.out;
+    }
+}
+
+fn literal(writer: &mut BitWriter, symbol: u16) {
+    let symbol = symbol as u32;
+    match symbol {
+        0..=143 => writer.code(0x30 + symbol, 8),
+        144..=255 => writer.code(0x190 + symbol - 144, 9),
+        256..=279 => writer.code(symbol - 256, 7),
+        _ => writer.code(0xC0 + symbol - 280, 8),
+    }
+}
+
+fn repeat(writer: &mut BitWriter, length: usize, distance: usize) {
+    let index = LENGTH_BASE
+        .iter()
+        .rposition(|&base| base as usize <= length)
+        .unwrap();
+    literal(writer, 257 + index as u16);
+    writer.bits(
+        (length - LENGTH_BASE[index] as usize) as u32,
+        LENGTH_EXTRA[index] as u32,
+    );
+    let index = DISTANCE_BASE
+        .iter()
+        .rposition(|&base| base as usize <= distance)
+        .unwrap();
+    writer.code(index as u32, 5);
+    writer.bits(
+        (distance - DISTANCE_BASE[index] as usize) as u32,
+        DISTANCE_EXTRA[index] as u32,
+    );
+}
+
+fn hash(data: &[u8]) -> usize {
+    let key = (data[0] as u32) << 16 | (data[1] as u32) << 8 | data[2] as u32;
+    return (key.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize;
+}
+
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter::new();
+    writer.bits(1, 1); // the last block
+    writer.bits(1, 2); // fixed codes
+
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; data.len()];
+
+    let mut pos = 0;
+    while pos < data.len() {
+        let mut best = (0, 0);
+        if pos + MIN_MATCH <= data.len() {
+            let mut candidate = head[hash(&data[pos..])];
+            let limit = (data.len() - pos).min(MAX_MATCH);
+            for _ in 0..MAX_CHAIN {
+                if candidate == usize::MAX || pos - candidate > WINDOW_SIZE {
+                    break;
+                }
+                let length = (0..limit)
+                    .take_while(|&i| data[candidate + i] == data[pos + i])
+                    .count();
+                if length > best.0 {
+                    best = (length, pos - candidate);
+                }
+                if length == limit {
+                    break;
+                }
+                candidate = prev[candidate];
+            }
+        }
+        let (mut length, distance) = best;
+        if length >= MIN_MATCH {
+            repeat(&mut writer, length, distance);
+        } else {
+            literal(&mut writer, data[pos] as u16);
+            length = 1;
+        }
+        for skipped in pos..pos + length {
+            if skipped + MIN_MATCH <= data.len() {
+                let key = hash(&data[skipped..]);
+                prev[skipped] = head[key];
+                head[key] = skipped;
+            }
+        }
+        pos += length;
+    }
+    literal(&mut writer, END_OF_BLOCK);
+    return writer.finish();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::archive::inflate::inflate;
+
+    #[test]
+    fn test_round_trips_through_inflate() {
+        let mut data = b"hello hello hello hello".to_vec();
+        data.extend(vec![0x0F; 1000]);
+        data.extend((0..=255).collect::<Vec<u8>>());
+        data.extend(b"hello hello hello hello abab abc");
+        let compressed = deflate(&data);
+        assert!(compressed.len() < data.len() / 2);
+        assert_eq!(inflate(&compressed).unwrap(), data);
+
+        assert_eq!(inflate(&deflate(b"")).unwrap(), b"");
+        assert_eq!(inflate(&deflate(b"ab")).unwrap(), b"ab");
+    }
+}
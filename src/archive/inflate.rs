@@ -0,0 +1,315 @@
+/* DEFLATE decoder (RFC 1951)
+
+   A block starts with a final flag and a 2 bit type: 0 stored, 1 fixed
+   Huffman codes, 2 dynamic codes sent ahead of the data. Huffman codes are
+   canonical, so a table is just the number of codes of each length plus the
+   symbols in code order, and decoding walks the lengths one bit at a time
+   (the same approach as zlib's puff). Slow next to a table driven decoder but
+   a ROM is inflated once.
+*/
+
+const MAX_BITS: usize = 15;
+const END_OF_BLOCK: u16 = 256;
+
+pub(super) const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+pub(super) const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+pub(super) const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+pub(super) const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+// order the code length code lengths are sent in
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        return Self {
+            data,
+            pos: 0,
+            buffer: 0,
+            count: 0,
+        };
+    }
+
+    // bits come out least significant first
+    fn bits(&mut self, n: u32) -> Result<u32, String> {
+        while self.count < n {
+            let byte = *self.data.get(self.pos).ok_or("deflate stream ends early")?;
+            self.pos += 1;
+            self.buffer |= (byte as u32) << self.count;
+            self.count += 8;
+        }
+        let value = self.buffer & ((1u32 << n) - 1);
+        self.buffer >>= n;
+        self.count -= n;
+        return Ok(value);
+    }
+
+    // stored blocks start on a byte boundary
+    fn align(&mut self) {
+        self.buffer = 0;
+        self.count = 0;
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.pos + n > self.data.len() {
+            return Err("deflate stream ends early".to_string());
+        }
+        let bytes = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        return Ok(bytes);
+    }
+}
+
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Huffman, String> {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for length in lengths {
+            counts[*length as usize] += 1;
+        }
+
+        // reject oversubscribed codes, incomplete ones are allowed (single distance code)
+        let mut left: i32 = 1;
+        for count in &counts[1..] {
+            left = (left << 1) - *count as i32;
+            if left < 0 {
+                return Err("invalid Huffman code lengths".to_string());
+            }
+        }
+
+        let mut offsets = [0u16; MAX_BITS + 2];
+        for len in 1..=MAX_BITS {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, length) in lengths.iter().enumerate() {
+            if *length != 0 {
+                symbols[offsets[*length as usize] as usize] = symbol as u16;
+                offsets[*length as usize] += 1;
+            }
+        }
+        counts[0] = 0;
+        return Ok(Huffman { counts, symbols });
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, String> {
+        let mut code: i32 = 0; // bits read so far
+        let mut first: i32 = 0; // first code of the current length
+        let mut index: i32 = 0; // index of that code in `symbols`
+        for len in 1..=MAX_BITS {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        return Err("invalid Huffman code".to_string());
+    }
+}
+
+pub fn inflate(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut reader = BitReader::new(data);
+    let mut out = Vec::new();
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => stored(&mut reader, &mut out)?,
+            1 => {
+                let (literals, distances) = fixed_tables();
+                codes(&mut reader, &mut out, &literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_tables(&mut reader)?;
+                codes(&mut reader, &mut out, &literals, &distances)?;
+            }
+            _ => return Err("invalid deflate block type".to_string()),
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+fn stored(reader: &mut BitReader, out: &mut Vec<u8>) -> Result<(), String> {
+    reader.align();
+    let header = reader.bytes(4)?;
+    let len = u16::from_le_bytes([header[0], header[1]]);
+    let complement = u16::from_le_bytes([header[2], header[3]]);
+    if len != !complement {
+        return Err("stored block length check failed".to_string());
+    }
+    out.extend_from_slice(reader.bytes(len as usize)?);
+    return Ok(());
+}
+
+fn fixed_tables() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    lengths[0..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..288].fill(8);
+    let literals = Huffman::new(&lengths).unwrap();
+    let distances = Huffman::new(&[5; 30]).unwrap();
+    return (literals, distances);
+}
+
+fn dynamic_tables(reader: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_length_count = reader.bits(4)? as usize + 4;
+    if literal_count > 286 || distance_count > 30 {
+        return Err("too many Huffman codes".to_string());
+    }
+
+    let mut code_lengths = [0u8; 19];
+    for index in CODE_LENGTH_ORDER.iter().take(code_length_count) {
+        code_lengths[*index] = reader.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_lengths)?;
+
+    // literal and distance lengths are one run, repeats may cross between them
+    let mut lengths = vec![0u8; literal_count + distance_count];
+    let mut index = 0;
+    while index < lengths.len() {
+        let symbol = code_length_code.decode(reader)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                if index == 0 {
+                    return Err("length repeat with no previous length".to_string());
+                }
+                (lengths[index - 1], 3 + reader.bits(2)? as usize)
+            }
+            17 => (0, 3 + reader.bits(3)? as usize),
+            _ => (0, 11 + reader.bits(7)? as usize),
+        };
+        if index + repeat > lengths.len() {
+            return Err("too many code lengths".to_string());
+        }
+        lengths[index..index + repeat].fill(value);
+        index += repeat;
+    }
+    if lengths[END_OF_BLOCK as usize] == 0 {
+        return Err("dynamic block has no end of block code".to_string());
+    }
+
+    let literals = Huffman::new(&lengths[..literal_count])?;
+    let distances = Huffman::new(&lengths[literal_count..])?;
+    return Ok((literals, distances));
+}
+
+fn codes(
+    reader: &mut BitReader,
+    out: &mut Vec<u8>,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Result<(), String> {
+    loop {
+        let symbol = literals.decode(reader)?;
+        if symbol < END_OF_BLOCK {
+            out.push(symbol as u8);
+            continue;
+        }
+        if symbol == END_OF_BLOCK {
+            return Ok(());
+        }
+
+        let symbol = (symbol - 257) as usize;
+        if symbol >= LENGTH_BASE.len() {
+            return Err("invalid length code".to_string());
+        }
+        let length =
+            LENGTH_BASE[symbol] as usize + reader.bits(LENGTH_EXTRA[symbol] as u32)? as usize;
+
+        let symbol = distances.decode(reader)? as usize;
+        if symbol >= DISTANCE_BASE.len() {
+            return Err("invalid distance code".to_string());
+        }
+        let distance =
+            DISTANCE_BASE[symbol] as usize + reader.bits(DISTANCE_EXTRA[symbol] as u32)? as usize;
+        if distance > out.len() {
+            return Err("distance reaches before the start of the output".to_string());
+        }
+
+        // byte by byte, a match may overlap the bytes it is producing
+        let start = out.len() - distance;
+        for i in 0..length {
+            out.push(out[start + i]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // zlib.compressobj(9, zlib.DEFLATED, -15) of b"hello hello hello hello"
+    const FIXED: [u8; 10] = [0xCB, 0x48, 0xCD, 0xC9, 0xC9, 0x57, 0xC8, 0x40, 0x27, 0x01];
+
+    #[test]
+    fn test_stored_block() {
+        let data = [0x01, 0x03, 0x00, 0xFC, 0xFF, b'a', b'b', b'c'];
+        assert_eq!(inflate(&data).unwrap(), b"abc");
+
+        let corrupt = [0x01, 0x03, 0x00, 0xFC, 0xFE, b'a', b'b', b'c'];
+        assert!(inflate(&corrupt).is_err());
+    }
+
+    #[test]
+    fn test_fixed_block() {
+        assert_eq!(inflate(&FIXED).unwrap(), b"hello hello hello hello");
+    }
+
+    #[test]
+    fn test_dynamic_block() {
+        let expected: Vec<u8> = (0..2000u32).map(|i| (i * i % 251 % 17) as u8).collect();
+        assert_eq!(inflate(DYNAMIC).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_truncated_stream() {
+        assert!(inflate(&FIXED[..4]).is_err());
+        assert!(inflate(&[]).is_err());
+        assert!(inflate(&[0x07]).is_err()); // reserved block type
+    }
+
+    // zlib.compressobj(9, zlib.DEFLATED, -15) of bytes(i * i % 251 % 17 for i in range(2000))
+    const DYNAMIC: &[u8] = &[
+        0xED, 0xCF, 0x49, 0x6E, 0x04, 0x31, 0x08, 0x40, 0x51, 0x30, 0x60, 0x6C, 0x33, 0xDE, 0xFF,
+        0xB4, 0xA9, 0x52, 0x7A, 0xD1, 0xB9, 0x42, 0xE4, 0x2D, 0x42, 0x1F, 0x1E, 0x20, 0xEF, 0x5E,
+        0xA3, 0x22, 0x6A, 0xAC, 0xDE, 0x2C, 0x2C, 0x2B, 0xC8, 0xCF, 0x64, 0x9E, 0x49, 0xC6, 0x04,
+        0x0D, 0xEA, 0xA3, 0xB6, 0xA8, 0x6E, 0x98, 0x69, 0x93, 0xA6, 0xE6, 0x08, 0xE9, 0x4E, 0x24,
+        0x03, 0x17, 0x46, 0x16, 0x67, 0x53, 0x44, 0xA2, 0x1D, 0x13, 0xED, 0xD8, 0x29, 0x39, 0x0C,
+        0x66, 0x01, 0xE3, 0x50, 0xB9, 0x2E, 0xEB, 0x69, 0x82, 0xDD, 0x28, 0x5A, 0xEB, 0x4D, 0xD2,
+        0x2A, 0xC5, 0x74, 0x4F, 0xFC, 0x1A, 0xFC, 0x6E, 0xD8, 0x6C, 0x5B, 0xEA, 0x45, 0x67, 0x40,
+        0x98, 0x01, 0x1F, 0xA9, 0x27, 0x69, 0x38, 0x63, 0x13, 0x21, 0xAA, 0xF1, 0xE7, 0x28, 0x18,
+        0x61, 0x76, 0x4B, 0x8C, 0xD4, 0xE7, 0x2D, 0xCB, 0x09, 0x5B, 0x55, 0x76, 0x0D, 0xD7, 0x37,
+        0xC9, 0x46, 0xF9, 0x3A, 0x8E, 0x53, 0xAC, 0x07, 0xF6, 0x57, 0x8A, 0x70, 0xE9, 0x97, 0x7E,
+        0xE9, 0x97, 0x7E, 0xE9, 0x97, 0xFE, 0x5F, 0xE8, 0x3F,
+    ];
+}
@@ -0,0 +1,315 @@
+use crate::hash;
+use std::fs;
+use std::path::Path;
+
+pub mod deflate;
+pub mod inflate;
+
+/* ROM archives
+
+   Zip files are read through the central directory at the end of the file,
+   which has reliable sizes even when the local headers were written by a
+   streaming compressor. Only stored and deflated entries are supported, which
+   is everything ROM sets use in practice. Each extracted entry is checked
+   against the CRC32 the archive recorded.
+
+    end of central directory  "PK\x05\x06", entry count at +10, directory offset at +16
+    central directory entry   "PK\x01\x02", method +10, crc +16, sizes +20/+24,
+                              name/extra/comment lengths +28/+30/+32, local header +42, name +46
+    local header              "PK\x03\x04", name/extra lengths +26/+28, data at +30
+
+   7z is recognised so the user gets a useful message, decoding LZMA is not
+   implemented.
+*/
+
+const ZIP_LOCAL_HEADER: u32 = 0x0403_4B50;
+const ZIP_CENTRAL_HEADER: u32 = 0x0201_4B50;
+const ZIP_END_OF_DIRECTORY: u32 = 0x0605_4B50;
+const ZIP_END_SIZE: usize = 22;
+const ZIP_MAX_COMMENT: usize = 0xFFFF;
+const SEVEN_ZIP_TAG: [u8; 6] = [0x37, 0x7A, 0xBC, 0xAF, 0x27, 0x1C];
+
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATE: u16 = 8;
+const FLAG_ENCRYPTED: u16 = 0x0001;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZipEntry {
+    pub name: String,
+    method: u16,
+    flags: u16,
+    crc32: u32,
+    compressed_size: usize,
+    size: usize,
+    header_offset: usize,
+}
+
+#[derive(Debug)]
+pub struct ZipArchive<'a> {
+    data: &'a [u8],
+    entries: Vec<ZipEntry>,
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Result<u16, String> {
+    return data
+        .get(offset..offset + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| "zip archive is truncated".to_string());
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Result<u32, String> {
+    return data
+        .get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+        .ok_or_else(|| "zip archive is truncated".to_string());
+}
+
+impl<'a> ZipArchive<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<ZipArchive<'a>, String> {
+        let end = find_end_of_directory(data)?;
+        let count = u16_at(data, end + 10)? as usize;
+        let mut offset = u32_at(data, end + 16)? as usize;
+
+        let mut entries = Vec::with_capacity(count);
+        for _ in 0..count {
+            if u32_at(data, offset)? != ZIP_CENTRAL_HEADER {
+                return Err("zip central directory is corrupt".to_string());
+            }
+            let name_len = u16_at(data, offset + 28)? as usize;
+            let extra_len = u16_at(data, offset + 30)? as usize;
+            let comment_len = u16_at(data, offset + 32)? as usize;
+            let name = data
+                .get(offset + 46..offset + 46 + name_len)
+                .ok_or("zip archive is truncated")?;
+            let entry = ZipEntry {
+                name: String::from_utf8_lossy(name).to_string(),
+                method: u16_at(data, offset + 10)?,
+                flags: u16_at(data, offset + 8)?,
+                crc32: u32_at(data, offset + 16)?,
+                compressed_size: u32_at(data, offset + 20)? as usize,
+                size: u32_at(data, offset + 24)? as usize,
+                header_offset: u32_at(data, offset + 42)? as usize,
+            };
+            if entry.compressed_size == 0xFFFF_FFFF || entry.header_offset == 0xFFFF_FFFF {
+                return Err("zip64 archives are not supported".to_string());
+            }
+            entries.push(entry);
+            offset += 46 + name_len + extra_len + comment_len;
+        }
+        return Ok(ZipArchive { data, entries });
+    }
+
+    pub fn entries(&self) -> &[ZipEntry] {
+        return &self.entries;
+    }
+
+    // an exact path match, or failing that the file name alone, case insensitive
+    pub fn find(&self, name: &str) -> Option<&ZipEntry> {
+        if let Some(entry) = self.entries.iter().find(|e| e.name == name) {
+            return Some(entry);
+        }
+        return self
+            .entries
+            .iter()
+            .find(|e| base_name(&e.name).eq_ignore_ascii_case(name));
+    }
+
+    pub fn first_rom(&self) -> Option<&ZipEntry> {
+        return self
+            .entries
+            .iter()
+            .find(|e| e.name.to_ascii_lowercase().ends_with(".nes"));
+    }
+
+    pub fn extract(&self, entry: &ZipEntry) -> Result<Vec<u8>, String> {
+        if entry.flags & FLAG_ENCRYPTED != 0 {
+            return Err(format!("{} is encrypted", entry.name));
+        }
+        let header = entry.header_offset;
+        if u32_at(self.data, header)? != ZIP_LOCAL_HEADER {
+            return Err(format!("local header for {} is corrupt", entry.name));
+        }
+        let start = header
+            + 30
+            + u16_at(self.data, header + 26)? as usize
+            + u16_at(self.data, header + 28)? as usize;
+        let compressed = self
+            .data
+            .get(start..start + entry.compressed_size)
+            .ok_or("zip archive is truncated")?;
+
+        let data = match entry.method {
+            METHOD_STORED => compressed.to_vec(),
+            METHOD_DEFLATE => inflate::inflate(compressed)?,
+            method => {
+                return Err(format!(
+                    "{} uses compression method {}, only stored and deflate are supported",
+                    entry.name, method
+                ))
+            }
+        };
+        if data.len() != entry.size || hash::crc32(&data) != entry.crc32 {
+            return Err(format!("{} failed its CRC check", entry.name));
+        }
+        return Ok(data);
+    }
+}
+
+fn find_end_of_directory(data: &[u8]) -> Result<usize, String> {
+    if data.len() < ZIP_END_SIZE {
+        return Err("file is too small to be a zip archive".to_string());
+    }
+    // the record sits before a comment of up to 64KB, scan back for its signature
+    let last = data.len() - ZIP_END_SIZE;
+    let first = last.saturating_sub(ZIP_MAX_COMMENT);
+    for offset in (first..=last).rev() {
+        if u32_at(data, offset)? == ZIP_END_OF_DIRECTORY {
+            return Ok(offset);
+        }
+    }
+    return Err("zip end of central directory not found".to_string());
+}
+
+fn base_name(path: &str) -> &str {
+    return path.rsplit(['/', '\\']).next().unwrap_or(path);
+}
+
+pub fn is_zip(data: &[u8]) -> bool {
+    return data.len() >= 4 && u32_at(data, 0) == Ok(ZIP_LOCAL_HEADER);
+}
+
+pub fn is_7z(data: &[u8]) -> bool {
+    return data.starts_with(&SEVEN_ZIP_TAG);
+}
+
+// the ROM image inside `data` if it is an archive, otherwise `data` itself
+pub fn unpack_rom(data: Vec<u8>, entry: Option<&str>) -> Result<Vec<u8>, String> {
+    if is_7z(&data) {
+        return Err(
+            "7z archives are not supported, extract the ROM or repack it as zip".to_string(),
+        );
+    }
+    if !is_zip(&data) {
+        if let Some(entry) = entry {
+            return Err(format!(
+                "{} was requested but the file is not an archive",
+                entry
+            ));
+        }
+        return Ok(data);
+    }
+
+    let archive = ZipArchive::parse(&data)?;
+    let found = match entry {
+        Some(name) => archive
+            .find(name)
+            .ok_or_else(|| format!("archive has no entry named {}", name))?,
+        None => archive.first_rom().ok_or("archive contains no .nes file")?,
+    };
+    return archive.extract(found);
+}
+
+pub fn read_rom(path: &Path, entry: Option<&str>) -> Result<Vec<u8>, String> {
+    let data = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    return unpack_rom(data, entry).map_err(|e| format!("{}: {}", path.display(), e));
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    // a stored-only zip, enough to exercise the directory handling
+    pub fn create_zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut directory = Vec::new();
+        for (name, data) in files {
+            let offset = out.len() as u32;
+            let crc = hash::crc32(data);
+            let mut header = Vec::new();
+            header.extend_from_slice(&crc.to_le_bytes());
+            header.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            header.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            header.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            header.extend_from_slice(&0u16.to_le_bytes());
+
+            out.extend_from_slice(&ZIP_LOCAL_HEADER.to_le_bytes());
+            out.extend_from_slice(&[20, 0, 0, 0, 0, 0, 0, 0, 0, 0]); // version, flags, method, time, date
+            out.extend_from_slice(&header);
+            out.extend_from_slice(name.as_bytes());
+            out.extend_from_slice(data);
+
+            directory.extend_from_slice(&ZIP_CENTRAL_HEADER.to_le_bytes());
+            directory.extend_from_slice(&[20, 0, 20, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+            directory.extend_from_slice(&header);
+            directory.extend_from_slice(&[0; 10]); // comment, disk, attributes
+            directory.extend_from_slice(&offset.to_le_bytes());
+            directory.extend_from_slice(name.as_bytes());
+        }
+        let directory_offset = out.len() as u32;
+        out.extend_from_slice(&directory);
+        out.extend_from_slice(&ZIP_END_OF_DIRECTORY.to_le_bytes());
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+        out.extend_from_slice(&directory_offset.to_le_bytes());
+        out.extend_from_slice(&[0; 2]);
+        return out;
+    }
+
+    // Python's zipfile with ZIP_DEFLATED, one entry "game.nes" holding b"NES\x1a" + 60 zero bytes
+    const DEFLATED_ZIP: &[u8] = &[
+        0x50, 0x4B, 0x03, 0x04, 0x14, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x21, 0x50, 0xB3,
+        0xBA, 0xD2, 0x9E, 0x09, 0x00, 0x00, 0x00, 0x40, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00,
+        0x67, 0x61, 0x6D, 0x65, 0x2E, 0x6E, 0x65, 0x73, 0xF3, 0x73, 0x0D, 0x96, 0x62, 0xA0, 0x00,
+        0x00, 0x00, 0x50, 0x4B, 0x01, 0x02, 0x14, 0x03, 0x14, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00,
+        0x00, 0x21, 0x50, 0xB3, 0xBA, 0xD2, 0x9E, 0x09, 0x00, 0x00, 0x00, 0x40, 0x00, 0x00, 0x00,
+        0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x01, 0x00,
+        0x00, 0x00, 0x00, 0x67, 0x61, 0x6D, 0x65, 0x2E, 0x6E, 0x65, 0x73, 0x50, 0x4B, 0x05, 0x06,
+        0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x36, 0x00, 0x00, 0x00, 0x2F, 0x00, 0x00,
+        0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn test_picks_first_nes_entry() {
+        let zip = create_zip(&[
+            ("readme.txt", b"hi"),
+            ("roms/Game.NES", b"rom"),
+            ("b.nes", b"b"),
+        ]);
+        let archive = ZipArchive::parse(&zip).unwrap();
+        assert_eq!(archive.entries().len(), 3);
+        assert_eq!(archive.first_rom().unwrap().name, "roms/Game.NES");
+        assert_eq!(unpack_rom(zip.clone(), None).unwrap(), b"rom");
+        assert_eq!(unpack_rom(zip.clone(), Some("b.nes")).unwrap(), b"b");
+        assert_eq!(unpack_rom(zip.clone(), Some("game.nes")).unwrap(), b"rom");
+        assert!(unpack_rom(zip, Some("missing.nes")).is_err());
+    }
+
+    #[test]
+    fn test_deflated_entry() {
+        let mut expected = b"NES\x1a".to_vec();
+        expected.extend_from_slice(&[0; 60]);
+        assert_eq!(unpack_rom(DEFLATED_ZIP.to_vec(), None).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_crc_mismatch_is_rejected() {
+        let mut zip = create_zip(&[("a.nes", b"abcd")]);
+        // corrupt the payload, which sits right after the 30 byte header and name
+        zip[30 + 5] ^= 0xFF;
+        let err = unpack_rom(zip, None).unwrap_err();
+        assert!(err.contains("CRC"));
+    }
+
+    #[test]
+    fn test_plain_files_pass_through() {
+        assert_eq!(unpack_rom(vec![1, 2, 3], None).unwrap(), vec![1, 2, 3]);
+        assert!(unpack_rom(vec![1, 2, 3], Some("a.nes")).is_err());
+        assert!(unpack_rom(create_zip(&[("a.txt", b"x")]), None).is_err());
+
+        let mut seven = SEVEN_ZIP_TAG.to_vec();
+        seven.extend_from_slice(&[0; 26]);
+        assert!(unpack_rom(seven, None).unwrap_err().contains("7z"));
+    }
+}
@@ -0,0 +1,519 @@
+use crate::cpu::AddressingMode;
+use crate::op_codes::{OpCode, NMOS_6502_OPCODES};
+use std::collections::HashMap;
+
+const BRANCHES: [&str; 8] = ["BCC", "BCS", "BEQ", "BMI", "BNE", "BPL", "BVC", "BVS"];
+
+/* Mini assembler
+
+   One instruction per line, `;` starts a comment. Operands use the usual
+   syntax: `#$10` immediate, `$10` / `$10,X` / `$10,Y` zero page, `$1234` (plus
+   `,X` / `,Y`) absolute, `($10,X)`, `($10),Y` and `($1234)` indirect, `A` or
+   nothing for accumulator/implied. Numbers are `$hex`, `%binary` or decimal.
+
+   A line can start with a label, `loop:`, and anywhere a number goes a
+   label can go instead, with `<label` and `>label` for its low and high
+   bytes. Branches to a label get the offset worked out; a branch to a
+   number still takes it as the raw signed offset byte. A label already
+   defined above picks zero page when it fits, one further down is always
+   absolute, since its address isn't known yet when the size is decided.
+
+    .org $8000      where the next byte goes, by default the address
+                    `assemble_at` was given. Moving forward fills the gap
+                    with zeros, moving back before code already placed is
+                    an error
+    .byte 1, $FF    bytes as they are
+    .word reset     16 bit values, low byte first, for vectors and tables
+
+   The output is the bytes from the first one placed to the last.
+*/
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Number(u16, bool), // value, written as a 16 bit number
+    Label(String),
+    Low(String),
+    High(String),
+}
+
+#[derive(Debug, PartialEq)]
+enum Operand {
+    None,
+    Immediate(Value),
+    Direct(Value),
+    IndexedX(Value),
+    IndexedY(Value),
+    Indirect(Value),
+    IndirectX(Value),
+    IndirectY(Value),
+}
+
+impl Operand {
+    fn value(&self) -> Option<&Value> {
+        match self {
+            Operand::None => return None,
+            Operand::Immediate(v)
+            | Operand::Direct(v)
+            | Operand::IndexedX(v)
+            | Operand::IndexedY(v)
+            | Operand::Indirect(v)
+            | Operand::IndirectX(v)
+            | Operand::IndirectY(v) => return Some(v),
+        }
+    }
+}
+
+// a line after the first pass, with its size settled
+enum Item {
+    Instruction(&'static OpCode, Operand),
+    Bytes(Vec<Value>),
+    Words(Vec<Value>),
+}
+
+impl Item {
+    fn len(&self) -> usize {
+        match self {
+            Item::Instruction(op, _) => return op.len as usize,
+            Item::Bytes(values) => return values.len(),
+            Item::Words(values) => return values.len() * 2,
+        }
+    }
+}
+
+pub fn assemble(source: &str) -> Result<Vec<u8>, String> {
+    return assemble_at(source, 0);
+}
+
+// for code that runs at `origin`, which labels need to know
+pub fn assemble_at(source: &str, origin: u16) -> Result<Vec<u8>, String> {
+    let mut labels = HashMap::new();
+    let mut items = Vec::new(); // with the line number and address
+    let mut address = origin as usize;
+    let mut start = None; // of the first byte placed
+    for (number, raw) in source.lines().enumerate() {
+        let error = |e: String| format!("line {}: {}", number + 1, e);
+        let mut line = raw.split(';').next().unwrap().trim();
+        if let Some((label, rest)) = split_label(line) {
+            if labels.insert(label.to_string(), address as u16).is_some() {
+                return Err(error(format!("label {} is defined twice", label)));
+            }
+            line = rest;
+        }
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(rest) = strip_directive(line, ".org") {
+            let (to, _) = parse_number(rest).map_err(error)?;
+            if start.is_some() && (to as usize) < address {
+                return Err(error(format!(
+                    ".org ${:04X} is before code already placed",
+                    to
+                )));
+            }
+            address = to as usize;
+            continue;
+        }
+        let item = parse_item(line, &labels).map_err(error)?;
+        if address + item.len() > 0x10000 {
+            return Err(error("the program runs past $FFFF".to_string()));
+        }
+        start.get_or_insert(address);
+        items.push((number, address, item));
+        address += items.last().unwrap().2.len();
+    }
+
+    let mut out = Vec::new();
+    for (number, address, item) in &items {
+        let bytes =
+            emit(item, *address, &labels).map_err(|e| format!("line {}: {}", number + 1, e))?;
+        out.resize(address - start.unwrap(), 0);
+        out.extend(bytes);
+    }
+    return Ok(out);
+}
+
+// `label: rest`, when the line starts with one
+fn split_label(line: &str) -> Option<(&str, &str)> {
+    let (label, rest) = line.split_once(':')?;
+    if !is_label(label) {
+        return None;
+    }
+    return Some((label, rest.trim()));
+}
+
+fn is_label(text: &str) -> bool {
+    let mut chars = text.chars();
+    let first = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_');
+    return first && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+}
+
+fn strip_directive<'a>(line: &'a str, name: &str) -> Option<&'a str> {
+    let (first, rest) = line.split_at(line.find(char::is_whitespace).unwrap_or(line.len()));
+    if !first.eq_ignore_ascii_case(name) {
+        return None;
+    }
+    return Some(rest.trim());
+}
+
+fn parse_item(line: &str, labels: &HashMap<String, u16>) -> Result<Item, String> {
+    if let Some(rest) = strip_directive(line, ".byte") {
+        return Ok(Item::Bytes(parse_list(rest)?));
+    }
+    if let Some(rest) = strip_directive(line, ".word") {
+        return Ok(Item::Words(parse_list(rest)?));
+    }
+    if line.starts_with('.') {
+        return Err(format!(
+            "unknown directive {}",
+            line.split_whitespace().next().unwrap()
+        ));
+    }
+    let (mnemonic, rest) = match line.find(char::is_whitespace) {
+        Some(idx) => (&line[..idx], line[idx..].trim()),
+        None => (line, ""),
+    };
+    let mnemonic = mnemonic.to_ascii_uppercase();
+    let operand = parse_operand(rest)?;
+    let op_code = find_op_code(&mnemonic, &operand, labels)
+        .ok_or_else(|| format!("no {} instruction for operand '{}'", mnemonic, rest))?;
+    return Ok(Item::Instruction(op_code, operand));
+}
+
+fn parse_list(text: &str) -> Result<Vec<Value>, String> {
+    return text.split(',').map(|v| parse_value(v.trim())).collect();
+}
+
+fn emit(item: &Item, address: usize, labels: &HashMap<String, u16>) -> Result<Vec<u8>, String> {
+    match item {
+        Item::Bytes(values) => {
+            let mut bytes = Vec::new();
+            for value in values {
+                bytes.push(byte(resolve(value, labels)?)?);
+            }
+            return Ok(bytes);
+        }
+        Item::Words(values) => {
+            let mut bytes = Vec::new();
+            for value in values {
+                bytes.extend(resolve(value, labels)?.to_le_bytes());
+            }
+            return Ok(bytes);
+        }
+        Item::Instruction(op_code, operand) => {
+            let mut bytes = vec![op_code.code];
+            let mut value = match operand.value() {
+                Some(value) => resolve(value, labels)?,
+                None => 0,
+            };
+            // a branch to a number is already the offset
+            let is_branch = BRANCHES.contains(&op_code.mnemonic);
+            if let (true, Operand::Direct(Value::Label(label))) = (is_branch, operand) {
+                let offset = value as i32 - (address as i32 + 2);
+                if !(-128..=127).contains(&offset) {
+                    return Err(format!("branch to {} is out of range", label));
+                }
+                value = offset as u8 as u16;
+            }
+            match op_code.len {
+                1 => {}
+                2 => bytes.push(byte(value)?),
+                _ => bytes.extend(value.to_le_bytes()),
+            }
+            return Ok(bytes);
+        }
+    }
+}
+
+fn byte(value: u16) -> Result<u8, String> {
+    if value > 0xFF {
+        return Err(format!("operand ${:04X} does not fit in a byte", value));
+    }
+    return Ok(value as u8);
+}
+
+fn resolve(value: &Value, labels: &HashMap<String, u16>) -> Result<u16, String> {
+    let label = |name: &String| {
+        return labels
+            .get(name)
+            .copied()
+            .ok_or_else(|| format!("unknown label {}", name));
+    };
+    match value {
+        Value::Number(v, _) => return Ok(*v),
+        Value::Label(name) => return label(name),
+        Value::Low(name) => return Ok(label(name)? & 0xFF),
+        Value::High(name) => return Ok(label(name)? >> 8),
+    }
+}
+
+// the value for choosing between zero page and absolute, and whether it
+// needs the absolute form
+fn size_hint(value: &Value, labels: &HashMap<String, u16>) -> (u16, bool) {
+    match value {
+        Value::Number(v, wide) => return (*v, *wide),
+        Value::Label(name) => match labels.get(name) {
+            Some(&v) => return (v, false),
+            None => return (0xFFFF, true),
+        },
+        Value::Low(_) | Value::High(_) => return (0, false),
+    }
+}
+
+fn find_op_code(
+    mnemonic: &str,
+    operand: &Operand,
+    labels: &HashMap<String, u16>,
+) -> Option<&'static OpCode> {
+    let lookup = |mode: AddressingMode| {
+        return NMOS_6502_OPCODES
+            .iter()
+            .find(|op| op.mnemonic == mnemonic && op.mode == mode);
+    };
+    let hint = |value: &Value| size_hint(value, labels);
+
+    let candidates: Vec<AddressingMode> = match operand {
+        Operand::None => vec![],
+        Operand::Immediate(_) => vec![AddressingMode::Immediate],
+        Operand::Direct(v) => {
+            zero_page_first(hint(v), AddressingMode::ZeroPage, AddressingMode::Absolute)
+        }
+        Operand::IndexedX(v) => zero_page_first(
+            hint(v),
+            AddressingMode::ZeroPage_X,
+            AddressingMode::Absolute_X,
+        ),
+        Operand::IndexedY(v) => zero_page_first(
+            hint(v),
+            AddressingMode::ZeroPage_Y,
+            AddressingMode::Absolute_Y,
+        ),
+        Operand::Indirect(_) => vec![AddressingMode::Indirect],
+        Operand::IndirectX(_) => vec![AddressingMode::Indirect_X],
+        Operand::IndirectY(_) => vec![AddressingMode::Indirect_Y],
+    };
+
+    for mode in candidates {
+        if let Some(op) = lookup(mode) {
+            return Some(op);
+        }
+    }
+
+    // implied, accumulator, relative and the few opcodes the table files under
+    // NoneAddressing (JMP indirect, BIT absolute)
+    let op = lookup(AddressingMode::NoneAddressing)?;
+    let fits = match operand {
+        Operand::None => op.len == 1,
+        Operand::Direct(_) | Operand::Indirect(_) => op.len > 1,
+        _ => false,
+    };
+    if fits {
+        return Some(op);
+    }
+    return None;
+}
+
+fn zero_page_first(
+    (value, wide): (u16, bool),
+    zero_page: AddressingMode,
+    absolute: AddressingMode,
+) -> Vec<AddressingMode> {
+    if value <= 0xFF && !wide {
+        return vec![zero_page, absolute];
+    }
+    return vec![absolute];
+}
+
+fn parse_operand(text: &str) -> Result<Operand, String> {
+    let text: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+    // the same byte offsets as `text`, for matching the registers in any case
+    let upper = text.to_ascii_uppercase();
+
+    if upper.is_empty() || upper == "A" {
+        return Ok(Operand::None);
+    }
+    if let Some(rest) = text.strip_prefix('#') {
+        let value = parse_value(rest)?;
+        if let Value::Number(v, _) = value {
+            if v > 0xFF {
+                return Err(format!("immediate value {} does not fit in a byte", rest));
+            }
+        }
+        return Ok(Operand::Immediate(value));
+    }
+    if upper.starts_with('(') {
+        if upper.ends_with(",X)") {
+            return Ok(Operand::IndirectX(parse_byte(&text[1..text.len() - 3])?));
+        }
+        if upper.ends_with("),Y") {
+            return Ok(Operand::IndirectY(parse_byte(&text[1..text.len() - 3])?));
+        }
+        if upper.ends_with(')') {
+            return Ok(Operand::Indirect(parse_value(&text[1..text.len() - 1])?));
+        }
+        return Err(format!("malformed indirect operand '{}'", text));
+    }
+    if upper.ends_with(",X") {
+        return Ok(Operand::IndexedX(parse_value(&text[..text.len() - 2])?));
+    }
+    if upper.ends_with(",Y") {
+        return Ok(Operand::IndexedY(parse_value(&text[..text.len() - 2])?));
+    }
+    return Ok(Operand::Direct(parse_value(&text)?));
+}
+
+// a number or a label, the pointer of an indexed indirect operand
+fn parse_byte(text: &str) -> Result<Value, String> {
+    let value = parse_value(text)?;
+    if let Value::Number(v, _) = value {
+        if v > 0xFF {
+            return Err(format!("{} does not fit in a byte", text));
+        }
+    }
+    return Ok(value);
+}
+
+fn parse_value(text: &str) -> Result<Value, String> {
+    if let Some(label) = text.strip_prefix('<').filter(|l| is_label(l)) {
+        return Ok(Value::Low(label.to_string()));
+    }
+    if let Some(label) = text.strip_prefix('>').filter(|l| is_label(l)) {
+        return Ok(Value::High(label.to_string()));
+    }
+    if is_label(text) {
+        return Ok(Value::Label(text.to_string()));
+    }
+    let (value, wide) = parse_number(text)?;
+    return Ok(Value::Number(value, wide));
+}
+
+// returns the value and whether it was written as a 16 bit number
+fn parse_number(text: &str) -> Result<(u16, bool), String> {
+    let parsed = if let Some(hex) = text.strip_prefix('$') {
+        u16::from_str_radix(hex, 16).map(|v| (v, hex.len() > 2))
+    } else if let Some(bin) = text.strip_prefix('%') {
+        u16::from_str_radix(bin, 2).map(|v| (v, bin.len() > 8))
+    } else {
+        text.parse::<u16>().map(|v| (v, v > 0xFF))
+    };
+    return parsed.map_err(|_| format!("invalid number '{}'", text));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_assemble_addressing_modes() {
+        let program = assemble(
+            "LDA #$05
+             LDA $10
+             LDA $10,X
+             LDX $10,Y
+             LDA $1234
+             LDA $0010
+             LDA $1234,X
+             LDA $1234,Y
+             LDA ($20,X)
+             LDA ($20),Y
+             JMP ($1234)",
+        )
+        .unwrap();
+        assert_eq!(
+            program,
+            vec![
+                0xA9, 0x05, 0xA5, 0x10, 0xB5, 0x10, 0xB6, 0x10, 0xAD, 0x34, 0x12, 0xAD, 0x10, 0x00,
+                0xBD, 0x34, 0x12, 0xB9, 0x34, 0x12, 0xA1, 0x20, 0xB1, 0x20, 0x6C, 0x34, 0x12,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_assemble_implied_accumulator_and_comments() {
+        let program = assemble(
+            "; comment only
+             inx        ; lowercase works too
+             ASL A
+             ASL
+             BNE $FC
+             BIT $2000",
+        )
+        .unwrap();
+        assert_eq!(
+            program,
+            vec![0xE8, 0x0A, 0x0A, 0xD0, 0xFC, 0x2C, 0x00, 0x20]
+        );
+    }
+
+    #[test]
+    fn test_assemble_falls_back_to_absolute() {
+        // STY has no absolute,Y form so $10,Y is an error, but LDA $10,Y widens
+        assert!(assemble("STY $10,Y").is_err());
+        assert_eq!(assemble("LDA $10,Y").unwrap(), vec![0xB9, 0x10, 0x00]);
+    }
+
+    #[test]
+    fn test_assemble_labels_and_directives() {
+        let program = assemble_at(
+            "        .org $8000
+             reset:  LDX #$04
+             loop:   DEX
+                     BNE loop     ; back 3
+                     BEQ done     ; on past the table
+             table:  .byte 1, $FF
+             done:   LDA table,X
+                     STA $0200
+                     LDA #<table
+                     LDY #>table
+                     JMP reset
+                     .org $8018
+                     .word reset, done",
+            0,
+        )
+        .unwrap();
+        assert_eq!(
+            program,
+            vec![
+                0xA2, 0x04, 0xCA, 0xD0, 0xFD, 0xF0, 0x02, 0x01, 0xFF, 0xBD, 0x07, 0x80, 0x8D, 0x00,
+                0x02, 0xA9, 0x07, 0xA0, 0x80, 0x4C, 0x00, 0x80, 0x00, 0x00, 0x00, 0x80, 0x09, 0x80,
+            ]
+        );
+
+        // a label known to be in zero page gets the short form
+        assert_eq!(
+            assemble_at(
+                "ptr: .byte 0
+LDA ptr
+LDA (ptr),Y",
+                0x10
+            )
+            .unwrap(),
+            vec![0x00, 0xA5, 0x10, 0xB1, 0x10]
+        );
+    }
+
+    #[test]
+    fn test_assemble_errors() {
+        assert!(assemble("FOO #$01").is_err());
+        assert!(assemble("LDA #$100").is_err());
+        assert!(assemble("LDA ($1234),Y").is_err());
+        let err = assemble("NOP\nLDA #xyz").unwrap_err();
+        assert_eq!(err, "line 2: unknown label xyz");
+        assert_eq!(
+            assemble("a1: NOP\na1: NOP").unwrap_err(),
+            "line 2: label a1 is defined twice"
+        );
+        assert!(assemble(".org $10\nNOP\n.org $08").is_err());
+        assert_eq!(
+            assemble_at("NOP\nNOP\nNOP\nNOP\n.org $8001\nLDA #5", 0x8000).unwrap_err(),
+            "line 5: .org $8001 is before code already placed"
+        );
+        assert!(assemble(".fill 4").is_err());
+        let far = format!("start: NOP\n.byte {}\nBNE start", vec!["0"; 130].join(","));
+        assert_eq!(
+            assemble(&far).unwrap_err(),
+            "line 3: branch to start is out of range"
+        );
+    }
+}
@@ -0,0 +1,306 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+pub mod ring;
+
+/* Audio output
+
+   The audio counterpart of `VideoSink`: speakers, WAV recording and the null
+   sink all take mono f32 samples in -1.0..=1.0 at the given sample rate.
+   `AudioSinks` fans one stream out to several sinks, so playing and
+   recording at the same time is just two attached sinks.
+
+   A sound device plays from its own callback thread, so its sink doesn't
+   write to the device directly: `ring::RingAudioSink` queues samples in a
+   lock-free ring that the callback drains with `SampleConsumer::fill`. The
+   device itself belongs to the frontend that owns the window and event
+   loop, see `frontend::sdl`.
+*/
+
+pub trait AudioSink {
+    fn push_samples(&mut self, samples: &[f32], sample_rate: u32) -> Result<(), String>;
+
+    // called once when the session ends so sinks can flush files or devices
+    fn finish(&mut self) -> Result<(), String> {
+        return Ok(());
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct NullAudioSink;
+
+impl AudioSink for NullAudioSink {
+    fn push_samples(&mut self, _: &[f32], _: u32) -> Result<(), String> {
+        return Ok(());
+    }
+}
+
+// buffers everything it receives, for headless runs and tests
+#[derive(Debug, Default)]
+pub struct CaptureAudioSink {
+    pub samples: Vec<f32>,
+    pub sample_rate: u32,
+}
+
+impl CaptureAudioSink {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+}
+
+impl AudioSink for CaptureAudioSink {
+    fn push_samples(&mut self, samples: &[f32], sample_rate: u32) -> Result<(), String> {
+        if self.sample_rate != 0 && self.sample_rate != sample_rate {
+            return Err(format!(
+                "sample rate changed from {} to {}",
+                self.sample_rate, sample_rate
+            ));
+        }
+        self.sample_rate = sample_rate;
+        self.samples.extend_from_slice(samples);
+        return Ok(());
+    }
+}
+
+// 16 bit PCM, mono unless created with more channels, which then take
+// interleaved samples; the RIFF sizes are patched in by `finish`
+pub struct WavAudioSink<W: Write + Seek> {
+    writer: W,
+    channels: u16,
+    sample_rate: u32,
+    data_bytes: u32,
+}
+
+impl WavAudioSink<BufWriter<File>> {
+    pub fn create(path: &Path) -> Result<Self, String> {
+        return Self::create_with_channels(path, 1);
+    }
+
+    pub fn create_with_channels(path: &Path, channels: u16) -> Result<Self, String> {
+        let file = File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        return Ok(Self::with_channels(BufWriter::new(file), channels));
+    }
+}
+
+impl<W: Write + Seek> WavAudioSink<W> {
+    pub fn new(writer: W) -> Self {
+        return Self::with_channels(writer, 1);
+    }
+
+    pub fn with_channels(writer: W, channels: u16) -> Self {
+        return Self {
+            writer,
+            channels: channels.max(1),
+            sample_rate: 0,
+            data_bytes: 0,
+        };
+    }
+
+    pub fn into_inner(self) -> W {
+        return self.writer;
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let block_align = self.channels * 2;
+        let byte_rate = self.sample_rate * block_align as u32;
+        self.writer.write_all(b"RIFF")?;
+        self.writer
+            .write_all(&(36 + self.data_bytes).to_le_bytes())?;
+        self.writer.write_all(b"WAVEfmt ")?;
+        self.writer.write_all(&16u32.to_le_bytes())?;
+        self.writer.write_all(&1u16.to_le_bytes())?; // PCM
+        self.writer.write_all(&self.channels.to_le_bytes())?;
+        self.writer.write_all(&self.sample_rate.to_le_bytes())?;
+        self.writer.write_all(&byte_rate.to_le_bytes())?;
+        self.writer.write_all(&block_align.to_le_bytes())?;
+        self.writer.write_all(&16u16.to_le_bytes())?; // bits per sample
+        self.writer.write_all(b"data")?;
+        self.writer.write_all(&self.data_bytes.to_le_bytes())?;
+        return Ok(());
+    }
+}
+
+impl<W: Write + Seek> AudioSink for WavAudioSink<W> {
+    fn push_samples(&mut self, samples: &[f32], sample_rate: u32) -> Result<(), String> {
+        if self.sample_rate == 0 {
+            self.sample_rate = sample_rate;
+            self.write_header().map_err(|e| e.to_string())?;
+        } else if self.sample_rate != sample_rate {
+            return Err(format!(
+                "sample rate changed from {} to {}",
+                self.sample_rate, sample_rate
+            ));
+        }
+
+        let mut pcm = Vec::with_capacity(samples.len() * 2);
+        for sample in samples {
+            let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            pcm.extend_from_slice(&value.to_le_bytes());
+        }
+        self.writer.write_all(&pcm).map_err(|e| e.to_string())?;
+        self.data_bytes += pcm.len() as u32;
+        return Ok(());
+    }
+
+    fn finish(&mut self) -> Result<(), String> {
+        if self.sample_rate == 0 {
+            self.sample_rate = 44_100; // nothing was pushed, still leave a valid empty file
+        }
+        let result = self
+            .writer
+            .seek(SeekFrom::Start(0))
+            .and_then(|_| self.write_header())
+            .and_then(|_| self.writer.seek(SeekFrom::End(0)))
+            .and_then(|_| self.writer.flush());
+        return result.map_err(|e| e.to_string());
+    }
+}
+
+#[derive(Default)]
+pub struct AudioSinks {
+    sinks: Vec<Box<dyn AudioSink>>,
+}
+
+impl AudioSinks {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    pub fn attach(&mut self, sink: Box<dyn AudioSink>) {
+        self.sinks.push(sink);
+    }
+
+    pub fn len(&self) -> usize {
+        return self.sinks.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.sinks.is_empty();
+    }
+}
+
+impl AudioSink for AudioSinks {
+    // every sink gets the samples even if an earlier one failed; the first error is reported
+    fn push_samples(&mut self, samples: &[f32], sample_rate: u32) -> Result<(), String> {
+        let mut result = Ok(());
+        for sink in self.sinks.iter_mut() {
+            let pushed = sink.push_samples(samples, sample_rate);
+            if result.is_ok() {
+                result = pushed;
+            }
+        }
+        return result;
+    }
+
+    fn finish(&mut self) -> Result<(), String> {
+        let mut result = Ok(());
+        for sink in self.sinks.iter_mut() {
+            let finished = sink.finish();
+            if result.is_ok() {
+                result = finished;
+            }
+        }
+        return result;
+    }
+}
+
+impl std::fmt::Debug for AudioSinks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return f
+            .debug_struct("AudioSinks")
+            .field("sinks", &self.sinks.len())
+            .finish();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    struct SharedSink(Arc<Mutex<Vec<f32>>>);
+
+    impl AudioSink for SharedSink {
+        fn push_samples(&mut self, samples: &[f32], _: u32) -> Result<(), String> {
+            self.0.lock().unwrap().extend_from_slice(samples);
+            return Ok(());
+        }
+    }
+
+    struct FailingSink;
+
+    impl AudioSink for FailingSink {
+        fn push_samples(&mut self, _: &[f32], _: u32) -> Result<(), String> {
+            return Err("device lost".to_string());
+        }
+    }
+
+    #[test]
+    fn test_capture_sink() {
+        let mut sink = CaptureAudioSink::new();
+        sink.push_samples(&[0.5, -0.5], 44100).unwrap();
+        sink.push_samples(&[0.25], 44100).unwrap();
+        assert_eq!(sink.samples, vec![0.5, -0.5, 0.25]);
+        assert!(sink.push_samples(&[0.0], 48000).is_err());
+    }
+
+    #[test]
+    fn test_fan_out_to_all_sinks() {
+        let first = Arc::new(Mutex::new(Vec::new()));
+        let second = Arc::new(Mutex::new(Vec::new()));
+        let mut sinks = AudioSinks::new();
+        sinks.attach(Box::new(SharedSink(Arc::clone(&first))));
+        sinks.attach(Box::new(FailingSink));
+        sinks.attach(Box::new(SharedSink(Arc::clone(&second))));
+        assert_eq!(sinks.len(), 3);
+
+        let result = sinks.push_samples(&[0.1, 0.2], 48000);
+        assert_eq!(result, Err("device lost".to_string()));
+        assert_eq!(*first.lock().unwrap(), vec![0.1, 0.2]);
+        assert_eq!(*second.lock().unwrap(), vec![0.1, 0.2]);
+        assert!(sinks.finish().is_ok());
+    }
+
+    #[test]
+    fn test_wav_sink() {
+        let mut sink = WavAudioSink::new(std::io::Cursor::new(Vec::new()));
+        sink.push_samples(&[0.0, 1.0, -1.0], 22050).unwrap();
+        sink.push_samples(&[2.0], 22050).unwrap();
+        assert!(sink.push_samples(&[0.0], 44100).is_err());
+        sink.finish().unwrap();
+
+        let wav = sink.into_inner().into_inner();
+        assert_eq!(wav.len(), 44 + 8);
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(wav[4..8].try_into().unwrap()), 36 + 8);
+        assert_eq!(u32::from_le_bytes(wav[24..28].try_into().unwrap()), 22050);
+        assert_eq!(u32::from_le_bytes(wav[40..44].try_into().unwrap()), 8);
+        assert_eq!(i16::from_le_bytes([wav[46], wav[47]]), i16::MAX);
+        assert_eq!(i16::from_le_bytes([wav[50], wav[51]]), i16::MAX); // clamped
+    }
+
+    #[test]
+    fn test_multichannel_wav_sink() {
+        let mut sink = WavAudioSink::with_channels(std::io::Cursor::new(Vec::new()), 5);
+        sink.push_samples(&[0.0; 10], 44100).unwrap();
+        sink.finish().unwrap();
+
+        let wav = sink.into_inner().into_inner();
+        assert_eq!(wav.len(), 44 + 20);
+        assert_eq!(u16::from_le_bytes([wav[22], wav[23]]), 5);
+        assert_eq!(
+            u32::from_le_bytes(wav[28..32].try_into().unwrap()),
+            44100 * 10
+        );
+        assert_eq!(u16::from_le_bytes([wav[32], wav[33]]), 10);
+    }
+
+    #[test]
+    fn test_empty_fan_out() {
+        let mut sinks = AudioSinks::new();
+        assert!(sinks.is_empty());
+        assert!(sinks.push_samples(&[0.0], 44100).is_ok());
+        assert!(NullAudioSink.push_samples(&[0.0], 44100).is_ok());
+    }
+}
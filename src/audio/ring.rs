@@ -0,0 +1,239 @@
+use crate::audio::AudioSink;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/* Sample ring buffer
+
+   The emulation thread and a sound device's callback run at their own pace,
+   and the callback must never wait on a lock. The two share a single
+   producer, single consumer ring: the producer only moves `write`, the
+   consumer only moves `read`, and samples are stored as f32 bits in
+   atomics so neither side needs any unsafe code.
+
+   One slot is always left empty so a full ring can be told from an empty
+   one. When the emulator gets ahead the newest samples are dropped, and
+   when it falls behind the callback repeats the last sample instead of
+   dropping to silence, which would click.
+*/
+
+struct Ring {
+    samples: Box<[AtomicU32]>,
+    read: AtomicUsize,
+    write: AtomicUsize,
+}
+
+impl Ring {
+    fn next(&self, index: usize) -> usize {
+        return (index + 1) % self.samples.len();
+    }
+
+    fn len(&self) -> usize {
+        let read = self.read.load(Ordering::Acquire);
+        let write = self.write.load(Ordering::Acquire);
+        return (write + self.samples.len() - read) % self.samples.len();
+    }
+}
+
+pub struct SampleProducer {
+    ring: Arc<Ring>,
+}
+
+pub struct SampleConsumer {
+    ring: Arc<Ring>,
+    last: f32,
+}
+
+// room for `capacity` samples
+pub fn sample_ring(capacity: usize) -> (SampleProducer, SampleConsumer) {
+    let ring = Arc::new(Ring {
+        samples: (0..capacity + 1).map(|_| AtomicU32::new(0)).collect(),
+        read: AtomicUsize::new(0),
+        write: AtomicUsize::new(0),
+    });
+    let producer = SampleProducer {
+        ring: Arc::clone(&ring),
+    };
+    return (producer, SampleConsumer { ring, last: 0.0 });
+}
+
+impl SampleProducer {
+    // returns how many of `samples` fitted
+    pub fn push(&mut self, samples: &[f32]) -> usize {
+        let ring = &self.ring;
+        let read = ring.read.load(Ordering::Acquire);
+        let mut write = ring.write.load(Ordering::Relaxed);
+        let mut pushed = 0;
+        for sample in samples {
+            let next = ring.next(write);
+            if next == read {
+                break;
+            }
+            ring.samples[write].store(sample.to_bits(), Ordering::Relaxed);
+            write = next;
+            pushed += 1;
+        }
+        ring.write.store(write, Ordering::Release);
+        return pushed;
+    }
+
+    // samples waiting to be played
+    pub fn len(&self) -> usize {
+        return self.ring.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+}
+
+// how full the ring is, for whoever holds neither end, like a host pacing
+// itself by the sound device
+#[derive(Clone)]
+pub struct RingLevel {
+    ring: Arc<Ring>,
+}
+
+impl RingLevel {
+    pub fn len(&self) -> usize {
+        return self.ring.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+}
+
+impl SampleConsumer {
+    // fills all of `out`, returns how many samples actually came from the ring
+    pub fn fill(&mut self, out: &mut [f32]) -> usize {
+        let ring = &self.ring;
+        let write = ring.write.load(Ordering::Acquire);
+        let mut read = ring.read.load(Ordering::Relaxed);
+        let mut filled = 0;
+        for sample in out.iter_mut() {
+            if read == write {
+                *sample = self.last;
+                continue;
+            }
+            self.last = f32::from_bits(ring.samples[read].load(Ordering::Relaxed));
+            *sample = self.last;
+            read = ring.next(read);
+            filled += 1;
+        }
+        ring.read.store(read, Ordering::Release);
+        return filled;
+    }
+}
+
+// the emulator's end of a sound device; the device callback owns the consumer
+pub struct RingAudioSink {
+    producer: SampleProducer,
+    sample_rate: u32,
+    dropped: u64,
+}
+
+impl RingAudioSink {
+    // `sample_rate` is the device's, `capacity` how many samples may queue up
+    pub fn new(sample_rate: u32, capacity: usize) -> (RingAudioSink, SampleConsumer) {
+        let (producer, consumer) = sample_ring(capacity);
+        let sink = RingAudioSink {
+            producer,
+            sample_rate,
+            dropped: 0,
+        };
+        return (sink, consumer);
+    }
+
+    // samples waiting in the ring, for keeping latency in check
+    pub fn queued(&self) -> usize {
+        return self.producer.len();
+    }
+
+    pub fn level(&self) -> RingLevel {
+        return RingLevel {
+            ring: Arc::clone(&self.producer.ring),
+        };
+    }
+
+    // samples thrown away because the device wasn't keeping up
+    pub fn dropped(&self) -> u64 {
+        return self.dropped;
+    }
+}
+
+impl AudioSink for RingAudioSink {
+    fn push_samples(&mut self, samples: &[f32], sample_rate: u32) -> Result<(), String> {
+        if sample_rate != self.sample_rate {
+            return Err(format!(
+                "the output device runs at {}Hz, not {}Hz",
+                self.sample_rate, sample_rate
+            ));
+        }
+        let pushed = self.producer.push(samples);
+        self.dropped += (samples.len() - pushed) as u64;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_push_and_fill() {
+        let (mut producer, mut consumer) = sample_ring(4);
+        assert_eq!(producer.push(&[0.1, 0.2, 0.3]), 3);
+        assert_eq!(producer.push(&[0.4, 0.5]), 1); // full
+        assert_eq!(producer.len(), 4);
+
+        let mut out = [0.0; 6];
+        assert_eq!(consumer.fill(&mut out), 4);
+        // an underrun holds the last sample
+        assert_eq!(out, [0.1, 0.2, 0.3, 0.4, 0.4, 0.4]);
+        assert!(producer.is_empty());
+
+        // and the indices wrap around
+        assert_eq!(producer.push(&[0.6, 0.7, 0.8]), 3);
+        let mut out = [0.0; 3];
+        assert_eq!(consumer.fill(&mut out), 3);
+        assert_eq!(out, [0.6, 0.7, 0.8]);
+    }
+
+    #[test]
+    fn test_across_threads() {
+        let (mut producer, mut consumer) = sample_ring(64);
+        let reader = thread::spawn(move || {
+            let mut received = Vec::new();
+            let mut out = [0.0; 16];
+            while received.len() < 1000 {
+                let filled = consumer.fill(&mut out);
+                received.extend_from_slice(&out[..filled]);
+                thread::yield_now();
+            }
+            return received;
+        });
+        let samples: Vec<f32> = (0..1000).map(|i| i as f32).collect();
+        let mut sent = 0;
+        while sent < samples.len() {
+            sent += producer.push(&samples[sent..]);
+            thread::yield_now();
+        }
+        assert_eq!(reader.join().unwrap(), samples);
+    }
+
+    #[test]
+    fn test_ring_sink() {
+        let (mut sink, mut consumer) = RingAudioSink::new(48000, 2);
+        let level = sink.level();
+        sink.push_samples(&[0.5, 0.25, 0.125], 48000).unwrap();
+        assert_eq!(sink.queued(), 2);
+        assert_eq!(level.len(), 2);
+        assert_eq!(sink.dropped(), 1);
+        assert!(sink.push_samples(&[0.0], 44100).is_err());
+
+        let mut out = [0.0; 2];
+        consumer.fill(&mut out);
+        assert_eq!(out, [0.5, 0.25]);
+    }
+}
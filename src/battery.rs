@@ -0,0 +1,184 @@
+use crate::cartridge::Rom;
+use crate::mapper::Mapper;
+use crate::sync::{self, SyncHooks, SyncKind};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/* Battery-backed PRG RAM
+
+   Cartridges with the battery bit keep $6000-$7FFF alive while the console
+   is off. We mirror that with a `.sav` file named after the ROM, either next
+   to it or in a save directory, loaded at boot and written back on exit and
+   every `interval` while running. Writes only happen when the RAM actually
+   changed and always go through a temporary file, so a crash can't leave a
+   half-written save behind.
+*/
+
+pub const DEFAULT_FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+pub fn save_path(rom_path: &Path, save_dir: Option<&Path>) -> PathBuf {
+    let file_name = rom_path.with_extension("sav");
+    match save_dir {
+        Some(dir) => return dir.join(file_name.file_name().unwrap_or_default()),
+        None => return file_name,
+    }
+}
+
+#[derive(Debug)]
+pub struct BatterySave {
+    path: PathBuf,
+    interval: Duration,
+    last_flush: Instant,
+    // what's on disk, so unchanged RAM isn't rewritten every interval
+    persisted: Vec<u8>,
+}
+
+impl BatterySave {
+    // None when the cartridge has no battery and nothing needs to be persisted
+    pub fn new(rom: &Rom, rom_path: &Path, save_dir: Option<&Path>) -> Option<Self> {
+        if !rom.battery {
+            return None;
+        }
+        return Some(Self::with_path(save_path(rom_path, save_dir)));
+    }
+
+    pub fn with_path(path: PathBuf) -> Self {
+        return Self {
+            path,
+            interval: DEFAULT_FLUSH_INTERVAL,
+            last_flush: Instant::now(),
+            persisted: Vec::new(),
+        };
+    }
+
+    pub fn path(&self) -> &Path {
+        return &self.path;
+    }
+
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+
+    // returns false when there is no save yet, which is normal on first boot
+    pub fn load(&mut self, mapper: &mut dyn Mapper) -> Result<bool, String> {
+        let data = match fs::read(&self.path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(format!("{}: {}", self.path.display(), e)),
+        };
+        let ram = match mapper.prg_ram_mut() {
+            Some(ram) => ram,
+            None => return Err("cartridge has a battery but no PRG RAM".to_string()),
+        };
+        // saves from other emulators are sometimes padded or cut to 8KB; take what overlaps
+        let len = ram.len().min(data.len());
+        ram[..len].copy_from_slice(&data[..len]);
+        self.persisted = ram.to_vec();
+        return Ok(true);
+    }
+
+    // returns true when the file was written
+    pub fn flush(&mut self, mapper: &dyn Mapper, hooks: &SyncHooks) -> io::Result<bool> {
+        self.last_flush = Instant::now();
+        let ram = match mapper.prg_ram() {
+            Some(ram) => ram,
+            None => return Ok(false),
+        };
+        if ram == self.persisted.as_slice() {
+            return Ok(false);
+        }
+        sync::write_and_notify(SyncKind::SaveRam, &self.path, ram, hooks)?;
+        self.persisted = ram.to_vec();
+        return Ok(true);
+    }
+
+    // call once per frame; flushes when the interval has elapsed
+    pub fn tick(
+        &mut self,
+        mapper: &dyn Mapper,
+        hooks: &SyncHooks,
+        now: Instant,
+    ) -> io::Result<bool> {
+        if now.duration_since(self.last_flush) < self.interval {
+            return Ok(false);
+        }
+        return self.flush(mapper, hooks);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+    use crate::mapper;
+    use std::env;
+
+    fn battery_rom() -> Rom {
+        let mut raw = test_rom(0, 1, 1);
+        raw[6] |= 0b0000_0010;
+        return Rom::new(&raw).unwrap();
+    }
+
+    fn temp_save(name: &str) -> PathBuf {
+        return env::temp_dir().join(format!("rustynes-{}-{}.sav", name, std::process::id()));
+    }
+
+    #[test]
+    fn test_save_path() {
+        let rom = Path::new("/games/zelda.nes");
+        assert_eq!(save_path(rom, None), PathBuf::from("/games/zelda.sav"));
+        assert_eq!(
+            save_path(rom, Some(Path::new("/saves"))),
+            PathBuf::from("/saves/zelda.sav")
+        );
+    }
+
+    #[test]
+    fn test_no_battery_no_save() {
+        let rom = Rom::new(&test_rom(0, 1, 1)).unwrap();
+        assert!(BatterySave::new(&rom, Path::new("a.nes"), None).is_none());
+        assert!(BatterySave::new(&battery_rom(), Path::new("a.nes"), None).is_some());
+    }
+
+    #[test]
+    fn test_flush_and_reload() {
+        let path = temp_save("battery");
+        let hooks = SyncHooks::new();
+
+        let mut mapper = mapper::new_mapper(battery_rom()).unwrap();
+        let mut save = BatterySave::with_path(path.clone());
+        assert_eq!(save.load(mapper.as_mut()), Ok(false));
+
+        mapper.cpu_write(0x6000, 0xAB);
+        mapper.cpu_write(0x7FFF, 0xCD);
+        assert!(save.flush(mapper.as_ref(), &hooks).unwrap());
+        // nothing changed since, so nothing is written
+        assert!(!save.flush(mapper.as_ref(), &hooks).unwrap());
+
+        let mut rebooted = mapper::new_mapper(battery_rom()).unwrap();
+        let mut save = BatterySave::with_path(path.clone());
+        assert_eq!(save.load(rebooted.as_mut()), Ok(true));
+        assert_eq!(rebooted.cpu_read(0x6000), 0xAB);
+        assert_eq!(rebooted.cpu_read(0x7FFF), 0xCD);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_tick_waits_for_interval() {
+        let path = temp_save("battery-tick");
+        let hooks = SyncHooks::new();
+        let mut mapper = mapper::new_mapper(battery_rom()).unwrap();
+        let mut save = BatterySave::with_path(path.clone());
+        save.set_interval(Duration::from_secs(60));
+
+        mapper.cpu_write(0x6000, 1);
+        let start = save.last_flush;
+        assert!(!save.tick(mapper.as_ref(), &hooks, start).unwrap());
+        assert!(save
+            .tick(mapper.as_ref(), &hooks, start + Duration::from_secs(61))
+            .unwrap());
+        fs::remove_file(&path).unwrap();
+    }
+}
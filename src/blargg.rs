@@ -0,0 +1,336 @@
+use crate::cartridge::Rom;
+use crate::nes::Nes;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const STATUS: u16 = 0x6000;
+const SIGNATURE: u16 = 0x6001;
+const SIGNATURE_BYTES: [u8; 3] = [0xDE, 0xB0, 0x61];
+const TEXT: u16 = 0x6004;
+const TEXT_END: u16 = 0x7FFF;
+const RUNNING: u8 = 0x80;
+const NEEDS_RESET: u8 = 0x81;
+// the ROMs ask for the button held at least 100ms, six frames or so
+const RESET_DELAY_FRAMES: u64 = 6;
+
+/* Blargg test ROMs
+
+   `rustynes blargg` runs the test ROMs blargg wrote for emulator authors,
+   the instr_test-v5, ppu_vbl_nmi and apu_test suites among them, which all
+   report the same way through the cartridge's PRG RAM:
+
+    $6000       the status, $80 while running, $81 to have reset pressed,
+                otherwise the result, 0 for a pass and a failing test's
+                number for the rest
+    $6001-6003  DE B0 61, once the other bytes mean anything
+    $6004       the text it would put on screen, up to a 0 byte
+
+   A ROM is run a frame at a time until the signature is there and the
+   status is a result, pressing reset when asked, or until the suite's
+   frame limit runs out. A directory runs each ROM in it in name order,
+   the suites' rom_singles, and passes if they all do. Suites differ only
+   in how long they take; the whole of instr_test-v5 in one ROM is the
+   slowest.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Suite {
+    InstrTest,
+    PpuVblNmi,
+    ApuTest,
+    Other,
+}
+
+impl Suite {
+    pub fn parse(name: &str) -> Result<Suite, String> {
+        match name {
+            "instr_test" | "instr_test-v5" => return Ok(Suite::InstrTest),
+            "ppu_vbl_nmi" => return Ok(Suite::PpuVblNmi),
+            "apu_test" => return Ok(Suite::ApuTest),
+            "other" => return Ok(Suite::Other),
+            _ => return Err(format!("unknown suite name {}", name)),
+        }
+    }
+
+    // from the file or directory name, `instr_test-v5/rom_singles/01-basics.nes`
+    pub fn guess(path: &Path) -> Suite {
+        let path = path.to_string_lossy();
+        for (name, suite) in [
+            ("instr_test", Suite::InstrTest),
+            ("ppu_vbl_nmi", Suite::PpuVblNmi),
+            ("apu_test", Suite::ApuTest),
+        ] {
+            if path.contains(name) {
+                return suite;
+            }
+        }
+        return Suite::Other;
+    }
+
+    // how long a ROM gets before it's given up on, with room to spare
+    pub fn frame_limit(self) -> u64 {
+        match self {
+            Suite::InstrTest => return 60 * 60,
+            Suite::PpuVblNmi => return 30 * 60,
+            Suite::ApuTest => return 20 * 60,
+            Suite::Other => return 60 * 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    Failed(u8),
+    TimedOut,
+}
+
+#[derive(Debug)]
+pub struct RomReport {
+    pub name: String,
+    pub outcome: Outcome,
+    pub text: String,
+    pub frames: u64,
+}
+
+#[derive(Debug)]
+pub struct Report {
+    pub roms: Vec<RomReport>,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        return self.roms.iter().all(|rom| rom.outcome == Outcome::Passed);
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for rom in &self.roms {
+            match rom.outcome {
+                Outcome::Passed => writeln!(f, "PASS {} ({} frames)", rom.name, rom.frames)?,
+                Outcome::Failed(code) => writeln!(f, "FAIL {}: result {}", rom.name, code)?,
+                Outcome::TimedOut => writeln!(
+                    f,
+                    "FAIL {}: no result after {} frames",
+                    rom.name, rom.frames
+                )?,
+            }
+            if rom.outcome != Outcome::Passed {
+                for line in rom.text.lines().filter(|line| !line.trim().is_empty()) {
+                    writeln!(f, "  {}", line)?;
+                }
+            }
+        }
+        let passed = self
+            .roms
+            .iter()
+            .filter(|rom| rom.outcome == Outcome::Passed)
+            .count();
+        return writeln!(f, "{}/{} passed", passed, self.roms.len());
+    }
+}
+
+// the status byte, once the signature says it's been written
+fn status(nes: &Nes) -> Option<u8> {
+    let bus = &nes.cpu.bus;
+    let signed = (0..3).all(|i| bus.peek(SIGNATURE + i) == SIGNATURE_BYTES[i as usize]);
+    return signed.then(|| bus.peek(STATUS));
+}
+
+// what the ROM has written at $6004 so far
+pub fn text(nes: &Nes) -> String {
+    let bytes: Vec<u8> = (TEXT..=TEXT_END)
+        .map(|addr| nes.cpu.bus.peek(addr))
+        .take_while(|&byte| byte != 0)
+        .collect();
+    return String::from_utf8_lossy(&bytes).into_owned();
+}
+
+// runs `nes` until a result or `frame_limit` frames
+pub fn run_rom(nes: &mut Nes, name: &str, frame_limit: u64) -> RomReport {
+    let mut reset_at = None;
+    let mut frames = 0;
+    let outcome = loop {
+        if frames == frame_limit {
+            break Outcome::TimedOut;
+        }
+        nes.run_frame();
+        frames += 1;
+        match status(nes) {
+            Some(RUNNING) | None => {}
+            Some(NEEDS_RESET) => {
+                let at = *reset_at.get_or_insert(frames + RESET_DELAY_FRAMES);
+                if frames >= at {
+                    nes.reset();
+                    reset_at = None;
+                }
+            }
+            Some(0) => break Outcome::Passed,
+            Some(code) => break Outcome::Failed(code),
+        }
+    };
+    return RomReport {
+        name: name.to_string(),
+        outcome,
+        text: text(nes),
+        frames,
+    };
+}
+
+// a ROM, or every ROM in a directory
+pub fn run(path: &Path, suite: Option<Suite>, frame_limit: Option<u64>) -> Result<Report, String> {
+    let error = |e: std::io::Error| format!("{}: {}", path.display(), e);
+    let paths = match path.is_dir() {
+        true => {
+            let mut paths: Vec<PathBuf> = fs::read_dir(path)
+                .map_err(error)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<_, _>>()
+                .map_err(error)?;
+            paths.retain(|path| {
+                path.extension()
+                    .is_some_and(|e| e.eq_ignore_ascii_case("nes"))
+            });
+            paths.sort();
+            if paths.is_empty() {
+                return Err(format!("{}: no .nes files", path.display()));
+            }
+            paths
+        }
+        false => vec![path.to_path_buf()],
+    };
+    let suite = suite.unwrap_or_else(|| Suite::guess(path));
+    let frame_limit = frame_limit.unwrap_or(suite.frame_limit());
+    let mut roms = Vec::new();
+    for path in paths {
+        let mut nes = Nes::new(Rom::from_file(&path, None)?)?;
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        roms.push(run_rom(&mut nes, &name, frame_limit));
+    }
+    return Ok(Report { roms });
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Options {
+    pub path: PathBuf,
+    pub suite: Option<Suite>,
+    pub frames: Option<u64>,
+}
+
+impl Options {
+    pub fn parse(args: &[String]) -> Result<Options, String> {
+        let mut path = None;
+        let mut suite = None;
+        let mut frames = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                return args.next().ok_or_else(|| format!("{} needs a value", name));
+            };
+            match arg.as_str() {
+                "--suite" => suite = Some(Suite::parse(value("--suite")?)?),
+                "--frames" => {
+                    let text = value("--frames")?;
+                    let count = text
+                        .parse::<u64>()
+                        .map_err(|_| format!("{} is not a frame count", text))?;
+                    frames = Some(count);
+                }
+                flag if flag.starts_with("--") => return Err(format!("unknown option {}", flag)),
+                arg if path.is_none() => path = Some(PathBuf::from(arg)),
+                extra => return Err(format!("unexpected argument {}", extra)),
+            }
+        }
+        return Ok(Options {
+            path: path.ok_or("missing a test ROM or directory")?,
+            suite,
+            frames,
+        });
+    }
+}
+
+// `rustynes blargg <rom.nes|dir> [--suite NAME] [--frames N]`
+pub fn run_cli(args: &[String]) -> Result<bool, String> {
+    let options = Options::parse(args)?;
+    let report = run(&options.path, options.suite, options.frames)?;
+    print!("{}", report);
+    return Ok(report.passed());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm;
+    use crate::cartridge::test::test_rom;
+
+    // a ROM that signs its output, runs `body` and then spins
+    fn nes_with(body: &str) -> Nes {
+        let source = format!(
+            "reset: LDA #$DE\nSTA $6001\nLDA #$B0\nSTA $6002\nLDA #$61\nSTA $6003\n\
+             {}\nhalt: JMP halt",
+            body
+        );
+        let program = asm::assemble_at(&source, 0x8000).unwrap();
+        let mut raw = test_rom(0, 1, 1);
+        raw[16..16 + program.len()].copy_from_slice(&program);
+        raw[16 + 0x3FFC..16 + 0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        return Nes::new(Rom::new(&raw).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_pass_and_fail() {
+        let mut nes = nes_with("LDA #$4F\nSTA $6004\nLDA #$4B\nSTA $6005\nLDA #$00\nSTA $6000");
+        let report = run_rom(&mut nes, "01-basics.nes", 10);
+        assert_eq!(report.outcome, Outcome::Passed);
+        assert_eq!(report.text, "OK");
+        assert_eq!(report.frames, 1);
+
+        let mut nes = nes_with("LDA #$3F\nSTA $6004\nLDA #$03\nSTA $6000");
+        let report = Report {
+            roms: vec![run_rom(&mut nes, "02-implied.nes", 10)],
+        };
+        assert!(!report.passed());
+        assert_eq!(
+            report.to_string(),
+            "FAIL 02-implied.nes: result 3\n  ?\n0/1 passed\n"
+        );
+    }
+
+    #[test]
+    fn test_running_until_the_limit() {
+        let mut nes = nes_with("LDA #$80\nSTA $6000");
+        let report = run_rom(&mut nes, "slow.nes", 5);
+        assert_eq!(report.outcome, Outcome::TimedOut);
+        assert_eq!(report.frames, 5);
+    }
+
+    #[test]
+    fn test_reset_when_asked() {
+        // asks for reset the first time, passes after it
+        let mut nes = nes_with(
+            "LDA $6000\nCMP #$81\nBEQ again\nLDA #$81\nSTA $6000\nJMP halt\n\
+             again: LDA #$00\nSTA $6000",
+        );
+        let report = run_rom(&mut nes, "reset.nes", 20);
+        assert_eq!(report.outcome, Outcome::Passed);
+        assert!(report.frames > RESET_DELAY_FRAMES);
+    }
+
+    #[test]
+    fn test_options() {
+        let args: Vec<String> = ["instr_test-v5/rom_singles", "--frames", "100"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let options = Options::parse(&args).unwrap();
+        assert_eq!(options.frames, Some(100));
+        assert_eq!(Suite::guess(&options.path), Suite::InstrTest);
+        assert_eq!(
+            Suite::parse("apu_tests").err().unwrap(),
+            "unknown suite name apu_tests"
+        );
+        assert!(Options::parse(&[]).is_err());
+    }
+}
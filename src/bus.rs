@@ -0,0 +1,626 @@
+use crate::apu::Apu;
+use crate::cartridge::Rom;
+use crate::cheat::CheatList;
+use crate::cpu::Mem;
+use crate::debugger::access::{Access, AccessWatch};
+use crate::error::RomError;
+use crate::error::StateError;
+use crate::heatmap::Heatmap;
+use crate::input::joypad::Joypad;
+use crate::input::{ControllerPorts, ControllerSetup, PORT_1, PORT_2};
+use crate::mapper::{self, Mapper};
+use crate::observer::{ConsoleEvent, ConsoleEventKind, EventTap};
+use crate::ppu::events::{EventKind, EventLog};
+use crate::ppu::{Ppu, OAM_SIZE};
+use crate::savestate::{StateReader, StateWriter};
+use std::fmt;
+
+const RAM: u16 = 0x0000;
+const RAM_MIRRORS_END: u16 = 0x1FFF;
+const PPU_REGISTERS: u16 = 0x2000;
+const PPU_REGISTERS_MIRRORS_END: u16 = 0x3FFF;
+const APU_REGISTERS: u16 = 0x4000;
+const APU_REGISTERS_END: u16 = 0x4013;
+const OAM_DMA: u16 = 0x4014;
+const APU_STATUS: u16 = 0x4015;
+const JOYPAD_1: u16 = 0x4016;
+const JOYPAD_2: u16 = 0x4017;
+const CARTRIDGE_SPACE: u16 = 0x4020;
+const DMC_DMA_STALL: u64 = 4;
+
+/* CPU memory map with a cartridge inserted
+    $0000-$07FF  2KB internal RAM
+    $0800-$1FFF  mirrors of $0000-$07FF
+    $2000-$3FFF  PPU registers, mirrored every 8 bytes
+    $4000-$4017  APU and I/O registers (a $4017 write goes to the APU frame counter)
+    $4014        OAM DMA, copies a 256 byte page to OAM and stalls the CPU 513/514 cycles
+    $4018-$401F  normally disabled test registers
+    $4020-$FFFF  cartridge space, handled by the mapper
+
+   Without a cartridge the bus is a flat 64KB of RAM, which is what the unit
+   tests and bare 6502 programs expect. With one, a standard controller is
+   plugged into each port until something else is connected.
+
+   The CPU runs a whole instruction before the rest of the console is ticked,
+   so on its own a PPU register write would land a few dots early and a
+   mid-frame split would wobble. Every access is counted as one cycle into
+   the instruction, and before a PPU register access or a mapper write the
+   PPU is first run up to that cycle; the tick at the end only does the rest.
+   Dummy cycles that don't access memory aren't counted, so the PPU can be a
+   cycle behind but is never ahead.
+
+   The DMC reads its samples from CPU memory too. The APU is ticked a cycle
+   at a time so a fetch it asks for is served on the cycle it wants it, and
+   each one steals 4 cycles from the CPU, taken as a stall like OAM DMA.
+*/
+
+pub struct Bus {
+    ram: Vec<u8>,
+    mapper: Option<Box<dyn Mapper>>,
+    ports: ControllerPorts,
+    cheats: CheatList,
+    pub apu: Apu,
+    pub ppu: Ppu,
+    cpu_cycle: u64,
+    stall_cycles: u64,
+    instruction_accesses: u64,
+    caught_up: u64, // cycles of the current instruction the PPU has already run
+    pub heatmap: Option<Box<Heatmap>>,
+    pub events: Option<Box<EventLog>>,
+    pub access: Option<Box<AccessWatch>>, // for the debugger's access breakpoints
+    pub tap: Option<Box<EventTap>>,       // for whoever is listening to the console
+}
+
+impl Bus {
+    pub fn flat() -> Self {
+        return Self {
+            ram: vec![0; 0x10000],
+            mapper: None,
+            ports: ControllerPorts::new(),
+            cheats: CheatList::new(),
+            apu: Apu::default(),
+            ppu: Ppu::new(),
+            cpu_cycle: 0,
+            stall_cycles: 0,
+            instruction_accesses: 0,
+            caught_up: 0,
+            heatmap: None,
+            events: None,
+            access: None,
+            tap: None,
+        };
+    }
+
+    pub fn new(mapper: Box<dyn Mapper>) -> Self {
+        let mut ports = ControllerPorts::new();
+        ports.set_up(ControllerSetup::Standard);
+        return Self {
+            ram: vec![0; 0x0800],
+            mapper: Some(mapper),
+            ports,
+            cheats: CheatList::new(),
+            apu: Apu::default(),
+            ppu: Ppu::new(),
+            cpu_cycle: 0,
+            stall_cycles: 0,
+            instruction_accesses: 0,
+            caught_up: 0,
+            heatmap: None,
+            events: None,
+            access: None,
+            tap: None,
+        };
+    }
+
+    pub fn from_rom(rom: Rom) -> Result<Self, RomError> {
+        return Ok(Self::new(mapper::new_mapper(rom)?));
+    }
+
+    pub fn mapper(&self) -> Option<&dyn Mapper> {
+        return self.mapper.as_deref();
+    }
+
+    pub fn mapper_mut(&mut self) -> Option<&mut (dyn Mapper + 'static)> {
+        return self.mapper.as_deref_mut();
+    }
+
+    // the console's 2KB of work RAM at $0000-$07FF, all 64KB on the flat bus
+    pub fn ram(&self) -> &[u8] {
+        return &self.ram;
+    }
+
+    pub fn ram_mut(&mut self) -> &mut [u8] {
+        return &mut self.ram;
+    }
+
+    // what a read of `addr` would see, without the read's side effects; the
+    // PPU, APU and controller registers, whose reads change them, are 0
+    pub fn peek(&self, addr: u16) -> u8 {
+        let mapper = match &self.mapper {
+            Some(mapper) => mapper,
+            None => return self.ram[addr as usize],
+        };
+        match addr {
+            RAM..=RAM_MIRRORS_END => return self.ram[(addr & 0b0000_0111_1111_1111) as usize],
+            CARTRIDGE_SPACE..=0xFFFF => return self.cheats.patch(addr, mapper.cpu_read(addr)),
+            _ => return 0,
+        }
+    }
+
+    pub fn ports(&self) -> &ControllerPorts {
+        return &self.ports;
+    }
+
+    pub fn ports_mut(&mut self) -> &mut ControllerPorts {
+        return &mut self.ports;
+    }
+
+    pub fn joypad_mut(&mut self, port: usize) -> Option<&mut Joypad> {
+        return self.ports.joypad_mut(port);
+    }
+
+    pub fn cheats(&self) -> &CheatList {
+        return &self.cheats;
+    }
+
+    pub fn cheats_mut(&mut self) -> &mut CheatList {
+        return &mut self.cheats;
+    }
+
+    // re-pokes the RAM cheats, the frame loop calls this once per frame
+    pub fn apply_cheats(&mut self) {
+        for (addr, value) in self.cheats.ram_writes() {
+            self.mem_write(addr, value);
+        }
+    }
+
+    // the CPU calls this before each instruction, accesses are counted from here
+    pub fn begin_instruction(&mut self) {
+        self.instruction_accesses = 0;
+        self.caught_up = 0;
+    }
+
+    // advances everything that runs off the CPU clock
+    pub fn tick(&mut self, cpu_cycles: u64) {
+        self.cpu_cycle += cpu_cycles;
+        for _ in 0..cpu_cycles {
+            self.apu.tick(1);
+            if let Some(addr) = self.apu.dmc.fetch_address() {
+                self.dmc_dma(addr);
+            }
+        }
+        let from = self.ppu_line();
+        let mapper = self.mapper.as_mut().map(|m| m.as_mut() as &mut dyn Mapper);
+        self.ppu
+            .tick(cpu_cycles.saturating_sub(self.caught_up) * 3, mapper);
+        self.tap_scanlines(from);
+        self.log_sprite_zero_hit();
+        self.begin_instruction();
+    }
+
+    // runs the PPU up to the cycle before the access being made
+    fn catch_up(&mut self) {
+        let target = self.instruction_accesses - 1;
+        if target > self.caught_up {
+            let from = self.ppu_line();
+            let mapper = self.mapper.as_mut().map(|m| m.as_mut() as &mut dyn Mapper);
+            self.ppu.tick((target - self.caught_up) * 3, mapper);
+            self.tap_scanlines(from);
+            self.caught_up = target;
+            self.log_sprite_zero_hit();
+        }
+    }
+
+    // for the event viewer and the tap, at where the PPU is now
+    pub fn log_event(&mut self, kind: EventKind) {
+        let ppu = &self.ppu;
+        if let Some(events) = &mut self.events {
+            events.record(ppu.frame_count(), ppu.scanline(), ppu.dot(), kind);
+        }
+        let kind = match kind {
+            EventKind::Irq => ConsoleEventKind::Irq,
+            EventKind::Write { addr, value } if addr >= CARTRIDGE_SPACE => {
+                ConsoleEventKind::MapperWrite { addr, value }
+            }
+            _ => return,
+        };
+        if let Some(tap) = &mut self.tap {
+            tap.record(ConsoleEvent {
+                frame: ppu.frame_count(),
+                scanline: ppu.scanline(),
+                dot: ppu.dot(),
+                kind,
+            });
+        }
+    }
+
+    fn ppu_line(&self) -> (u64, u16) {
+        return (self.ppu.frame_count(), self.ppu.scanline());
+    }
+
+    // the scanlines the PPU has gone into since it was at `from`
+    fn tap_scanlines(&mut self, from: (u64, u16)) {
+        let to = self.ppu_line();
+        if let Some(tap) = &mut self.tap {
+            tap.crossed(from, to);
+        }
+    }
+
+    fn log_sprite_zero_hit(&mut self) {
+        let hit = self.ppu.take_sprite_zero_hit();
+        if let (Some((scanline, dot)), Some(events)) = (hit, &mut self.events) {
+            events.record(self.ppu.frame_count(), scanline, dot, EventKind::SpriteZero);
+        }
+    }
+
+    pub fn take_nmi(&mut self) -> bool {
+        let nmi = self.ppu.take_nmi();
+        if nmi {
+            self.log_event(EventKind::Nmi);
+        }
+        return nmi;
+    }
+
+    // cycles the CPU has to sit out for DMA since the last call
+    pub fn take_stall(&mut self) -> u64 {
+        let stall = self.stall_cycles;
+        self.stall_cycles = 0;
+        return stall;
+    }
+
+    fn oam_dma(&mut self, page: u8) {
+        let base = (page as u16) << 8;
+        let mut data = [0u8; OAM_SIZE];
+        let accesses = self.instruction_accesses; // DMA cycles are counted as a stall
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = self.mem_read(base + i as u16);
+        }
+        self.instruction_accesses = accesses;
+        self.ppu.oam_dma(&data);
+        // one dummy cycle, plus one more to line up on an even cycle
+        self.stall_cycles += 513 + (self.cpu_cycle % 2);
+    }
+
+    fn dmc_dma(&mut self, addr: u16) {
+        let accesses = self.instruction_accesses;
+        let data = self.mem_read(addr);
+        self.instruction_accesses = accesses;
+        self.apu.dmc.fill(data);
+        self.stall_cycles += DMC_DMA_STALL;
+    }
+
+    // the console reset button, only the cartridge sees it
+    pub fn reset(&mut self) {
+        if let Some(mapper) = &mut self.mapper {
+            mapper.reset();
+        }
+    }
+
+    // without a cartridge the APU isn't mapped, so its IRQs couldn't be acknowledged
+    pub fn irq_pending(&self) -> bool {
+        return self
+            .mapper
+            .as_ref()
+            .is_some_and(|m| m.irq_pending() || self.apu.irq_pending());
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.ram);
+        if let Some(mapper) = &self.mapper {
+            mapper.save_state(writer);
+        }
+        self.ports.save_state(writer);
+        self.apu.save_state(writer);
+        self.ppu.save_state(writer);
+        writer.write_u64(self.cpu_cycle);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        reader.read_into(&mut self.ram)?;
+        if let Some(mapper) = &mut self.mapper {
+            mapper.load_state(reader)?;
+        }
+        self.ports.load_state(reader)?;
+        self.apu.load_state(reader)?;
+        self.ppu.load_state(reader)?;
+        self.cpu_cycle = reader.read_u64()?;
+        self.stall_cycles = 0;
+        return Ok(());
+    }
+}
+
+impl Bus {
+    fn watch_access(&mut self, addr: u16, value: u8, write: bool) {
+        if let Some(watch) = &mut self.access {
+            watch.record(Access {
+                addr,
+                value,
+                write,
+                scanline: self.ppu.scanline(),
+                dot: self.ppu.dot(),
+            });
+        }
+    }
+
+    fn read(&mut self, addr: u16) -> u8 {
+        let mapper = match &mut self.mapper {
+            Some(mapper) => mapper,
+            None => return self.ram[addr as usize],
+        };
+
+        match addr {
+            RAM..=RAM_MIRRORS_END => {
+                let mirror_down_addr = addr & 0b0000_0111_1111_1111;
+                return self.ram[mirror_down_addr as usize];
+            }
+            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
+                return self.ppu.read_register(addr, mapper.as_mut())
+            }
+            APU_STATUS => return self.apu.read_status(),
+            JOYPAD_1 => return self.ports.read(PORT_1),
+            JOYPAD_2 => return self.ports.read(PORT_2),
+            CARTRIDGE_SPACE..=0xFFFF => return self.cheats.patch(addr, mapper.cpu_read(addr)),
+            _ => return 0,
+        }
+    }
+}
+
+impl Mem for Bus {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        self.instruction_accesses += 1;
+        if let Some(heatmap) = &mut self.heatmap {
+            heatmap.read(addr);
+        }
+        if self.mapper.is_some() && (PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END).contains(&addr) {
+            self.catch_up();
+        }
+        let value = self.read(addr);
+        if self.access.is_some() {
+            self.watch_access(addr, value, false);
+        }
+        return value;
+    }
+
+    fn mem_write(&mut self, addr: u16, data: u8) {
+        self.instruction_accesses += 1;
+        if let Some(heatmap) = &mut self.heatmap {
+            heatmap.write(addr);
+        }
+        if self.mapper.is_some()
+            && ((PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END).contains(&addr)
+                || addr >= CARTRIDGE_SPACE)
+        {
+            self.catch_up();
+        }
+        // all but PRG RAM, which is only memory
+        if (self.events.is_some() || self.tap.is_some())
+            && self.mapper.is_some()
+            && matches!(addr, PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END | OAM_DMA | CARTRIDGE_SPACE..=0x5FFF | 0x8000..=0xFFFF)
+        {
+            self.log_event(EventKind::Write { addr, value: data });
+        }
+        if self.access.is_some() {
+            self.watch_access(addr, data, true);
+        }
+        if addr == OAM_DMA && self.mapper.is_some() {
+            self.oam_dma(data);
+            return;
+        }
+        let mapper = match &mut self.mapper {
+            Some(mapper) => mapper,
+            None => {
+                self.ram[addr as usize] = data;
+                return;
+            }
+        };
+
+        match addr {
+            RAM..=RAM_MIRRORS_END => {
+                let mirror_down_addr = addr & 0b0000_0111_1111_1111;
+                self.ram[mirror_down_addr as usize] = data;
+            }
+            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
+                self.ppu.write_register(addr, data, mapper.as_mut())
+            }
+            APU_REGISTERS..=APU_REGISTERS_END | APU_STATUS | JOYPAD_2 => {
+                self.apu.write_register(addr, data)
+            }
+            JOYPAD_1 => self.ports.write(data),
+            CARTRIDGE_SPACE..=0xFFFF => mapper.cpu_write(addr, data),
+            _ => {}
+        }
+    }
+
+    // the 16 bit helpers wrap instead of overflowing at the top of the address space
+    fn mem_read_u16(&mut self, pos: u16) -> u16 {
+        let lo = self.mem_read(pos) as u16;
+        let hi = self.mem_read(pos.wrapping_add(1)) as u16;
+        return (hi << 8) | lo;
+    }
+}
+
+impl fmt::Debug for Bus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f
+            .debug_struct("Bus")
+            .field("ram_size", &self.ram.len())
+            .field("mapper", &self.mapper.is_some())
+            .field("port_1", &self.ports.is_connected(PORT_1))
+            .field("port_2", &self.ports.is_connected(PORT_2))
+            .finish();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+
+    #[test]
+    fn test_flat_bus() {
+        let mut bus = Bus::flat();
+        bus.mem_write(0xFFFF, 0x12);
+        bus.mem_write(0x0000, 0x34);
+        assert_eq!(bus.mem_read(0xFFFF), 0x12);
+        assert_eq!(bus.mem_read_u16(0xFFFF), 0x3412);
+    }
+
+    #[test]
+    fn test_ram_is_mirrored_with_cartridge() {
+        let rom = Rom::new(&test_rom(0, 2, 1)).unwrap();
+        let mut bus = Bus::from_rom(rom).unwrap();
+        bus.mem_write(0x0001, 0x77);
+        assert_eq!(bus.mem_read(0x0801), 0x77);
+        assert_eq!(bus.mem_read(0x1801), 0x77);
+        assert_eq!(bus.mem_read(0xE000), 3);
+        assert_eq!(bus.peek(0x1801), 0x77);
+        assert_eq!(bus.peek(0xE000), 3);
+    }
+
+    #[test]
+    fn test_controller_ports() {
+        use crate::input::miracle::{MemoryMidi, MiraclePiano};
+
+        let rom = Rom::new(&test_rom(0, 2, 1)).unwrap();
+        let mut bus = Bus::from_rom(rom).unwrap();
+        assert_eq!(bus.mem_read(0x4016), 0);
+
+        let mut piano = MiraclePiano::new(MemoryMidi::default());
+        piano.port_mut().incoming.push_back(0x00);
+        bus.ports_mut().connect(PORT_1, Box::new(piano));
+        assert_eq!(bus.mem_read(0x4016), 0); // start bit
+        assert_eq!(bus.mem_read(0x4017), 0); // nothing pressed on port 2
+    }
+
+    #[test]
+    fn test_ppu_registers_and_oam_dma() {
+        let rom = Rom::new(&test_rom(0, 2, 1)).unwrap();
+        let mut bus = Bus::from_rom(rom).unwrap();
+        bus.mem_write(0x3FF9, 0x1E); // $2001 mirror
+        assert_eq!(bus.ppu.mask(), 0x1E);
+
+        for i in 0..=255u16 {
+            bus.mem_write(0x0200 + i, i as u8);
+        }
+        bus.mem_write(0x2003, 0x04);
+        bus.mem_write(0x4014, 0x02);
+        assert_eq!(bus.ppu.oam()[0x04], 0x00);
+        assert_eq!(bus.ppu.oam()[0x03], 0xFF); // wrapped around
+        assert_eq!(bus.take_stall(), 513);
+        assert_eq!(bus.take_stall(), 0);
+
+        bus.tick(1);
+        bus.mem_write(0x4014, 0x02);
+        assert_eq!(bus.take_stall(), 514); // odd cycle waits for alignment
+    }
+
+    #[test]
+    fn test_ppu_catches_up_before_register_writes() {
+        let rom = Rom::new(&test_rom(0, 2, 1)).unwrap();
+        let mut bus = Bus::from_rom(rom).unwrap();
+        // STA $2001: opcode and operand fetches, then the write on the 4th cycle
+        bus.begin_instruction();
+        bus.mem_read(0x8000);
+        bus.mem_read_u16(0x8001);
+        assert_eq!(bus.ppu.dot(), 0);
+        bus.mem_write(0x2001, 0x08);
+        assert_eq!(bus.ppu.dot(), 9);
+        bus.tick(4);
+        assert_eq!(bus.ppu.dot(), 12);
+
+        // RAM accesses don't need the PPU, the tick does it all
+        bus.mem_read(0x0000);
+        bus.mem_write(0x0001, 0);
+        assert_eq!(bus.ppu.dot(), 12);
+        bus.tick(3);
+        assert_eq!(bus.ppu.dot(), 21);
+    }
+
+    #[test]
+    fn test_dmc_fetches_stall_the_cpu() {
+        // NROM-256, $C000 holds bank 2
+        let rom = Rom::new(&test_rom(0, 2, 1)).unwrap();
+        let mut bus = Bus::from_rom(rom).unwrap();
+        bus.mem_write(0x4010, 0x0F);
+        bus.mem_write(0x4013, 0x00); // one byte at $C000
+        bus.mem_write(0x4015, 0b0001_0000);
+        assert_eq!(bus.mem_read(0x4015) & 0b0001_0000, 0b0001_0000);
+
+        bus.tick(1);
+        assert_eq!(bus.apu.dmc.sample_buffer, Some(2));
+        assert_eq!(bus.take_stall(), DMC_DMA_STALL);
+        assert_eq!(bus.mem_read(0x4015) & 0b0001_0000, 0);
+        bus.tick(1000);
+        assert_eq!(bus.take_stall(), 0);
+    }
+
+    #[test]
+    fn test_apu_irqs_reach_the_cpu() {
+        let rom = Rom::new(&test_rom(0, 2, 1)).unwrap();
+        let mut bus = Bus::from_rom(rom).unwrap();
+        bus.tick(29_830);
+        assert!(bus.irq_pending());
+        assert_eq!(bus.mem_read(0x4015) & 0b0100_0000, 0b0100_0000);
+        assert!(!bus.irq_pending());
+
+        // inhibited, the frame IRQ never comes; a finished DMC sample raises its own
+        bus.mem_write(0x4017, 0b0100_0000);
+        bus.mem_write(0x4010, 0b1000_1111);
+        bus.mem_write(0x4015, 0b0001_0000);
+        bus.tick(29_830);
+        assert!(bus.irq_pending());
+        assert_eq!(bus.mem_read(0x4015), 0b1000_0000);
+        assert!(bus.irq_pending());
+        bus.mem_write(0x4015, 0);
+        assert!(!bus.irq_pending());
+
+        let mut flat = Bus::flat();
+        flat.tick(29_830);
+        assert!(!flat.irq_pending());
+    }
+
+    #[test]
+    fn test_joypads_are_plugged_in() {
+        use crate::input::joypad::Button;
+
+        let rom = Rom::new(&test_rom(0, 2, 1)).unwrap();
+        let mut bus = Bus::from_rom(rom).unwrap();
+        bus.joypad_mut(PORT_2).unwrap().set_button(Button::B, true);
+        bus.mem_write(0x4016, 1);
+        bus.mem_write(0x4016, 0);
+        assert_eq!(bus.mem_read(0x4017), 0);
+        assert_eq!(bus.mem_read(0x4017), 1);
+        assert_eq!(bus.mem_read(0x4016), 0);
+        assert!(Bus::flat().joypad_mut(PORT_1).is_none());
+    }
+
+    #[test]
+    fn test_cheats() {
+        // NROM-256, $8000 holds bank 0 and $C000 bank 2
+        let rom = Rom::new(&test_rom(0, 2, 1)).unwrap();
+        let mut bus = Bus::from_rom(rom).unwrap();
+        let gg = bus.cheats_mut().add("C000:EA").unwrap();
+        bus.cheats_mut().add("0010:42").unwrap();
+        assert_eq!(bus.mem_read(0xC000), 0xEA);
+        assert_eq!(bus.mem_read(0xC001), 2);
+
+        bus.cheats_mut().set_enabled(gg, false).unwrap();
+        assert_eq!(bus.mem_read(0xC000), 2);
+
+        bus.mem_write(0x0010, 0);
+        bus.apply_cheats();
+        assert_eq!(bus.mem_read(0x0810), 0x42);
+    }
+
+    #[test]
+    fn test_mapper_irq_is_visible_on_the_bus() {
+        let rom = Rom::new(&test_rom(4, 2, 1)).unwrap();
+        let mut bus = Bus::from_rom(rom).unwrap();
+        bus.mem_write(0xC000, 0);
+        bus.mem_write(0xC001, 0);
+        bus.mem_write(0xE001, 0);
+        assert!(!bus.irq_pending());
+        bus.mapper_mut().unwrap().ppu_bus(0x1000, 100);
+        assert!(bus.irq_pending());
+    }
+}
@@ -0,0 +1,429 @@
+use crate::archive;
+use crate::error::RomError;
+use crate::hash::{self, Crc32, Sha1};
+use crate::romdb::{self, DbEntry};
+use std::path::Path;
+
+const NES_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A]; // "NES" followed by MS-DOS end of file
+const HEADER_SIZE: usize = 16;
+pub const PRG_ROM_PAGE_SIZE: usize = 0x4000;
+pub const CHR_ROM_PAGE_SIZE: usize = 0x2000;
+pub const PRG_RAM_PAGE_SIZE: usize = 0x2000;
+pub const TRAINER_SIZE: usize = 512;
+pub const TRAINER_ADDR: u16 = 0x7000;
+
+/* iNES header outline
+    byte  0-3  "NES" + 0x1A
+    byte  4    PRG ROM size in 16KB units
+    byte  5    CHR ROM size in 8KB units (0 means the board has CHR RAM)
+    byte  6    7654 3210
+               |||| |||+- mirroring: 0 horizontal, 1 vertical
+               |||| ||+-- battery backed PRG RAM at $6000-$7FFF
+               |||| |+--- 512 byte trainer before PRG ROM
+               |||| +---- four screen VRAM
+               ++++------ mapper number, low nibble
+    byte  7    7654 3210
+               |||| ||++- console type
+               |||| ++--- 10 means the rest of the header is NES 2.0
+               ++++------ mapper number, high nibble
+    byte  8    iNES: PRG RAM size in 8KB units / NES 2.0: submapper and mapper bits 8-11
+    byte  9    iNES: bit 0 set for PAL
+    byte 10    NES 2.0: PRG RAM / PRG NVRAM size shifts
+    byte 12    NES 2.0: timing, 0 NTSC, 1 PAL, 2 multi-region, 3 Dendy
+
+   The trainer, when present, sits between the header and PRG ROM and is
+   loaded into PRG RAM at $7000-$71FF before the game starts.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mirroring {
+    Vertical,
+    Horizontal,
+    FourScreen,
+    SingleScreenLower,
+    SingleScreenUpper,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    Ntsc,
+    Pal,
+    Multi,
+    Dendy,
+}
+
+impl Region {
+    pub fn parse(name: &str) -> Result<Region, String> {
+        match name {
+            "ntsc" => return Ok(Region::Ntsc),
+            "pal" => return Ok(Region::Pal),
+            "multi" => return Ok(Region::Multi),
+            "dendy" => return Ok(Region::Dendy),
+            _ => return Err(format!("unknown region {}", name)),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Region::Ntsc => return "ntsc",
+            Region::Pal => return "pal",
+            Region::Multi => return "multi",
+            Region::Dendy => return "dendy",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Rom {
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub screen_mirroring: Mirroring,
+    pub battery: bool,
+    pub prg_ram_size: usize,
+    pub trainer: Option<Vec<u8>>,
+    pub region: Region,
+}
+
+impl Rom {
+    pub fn new(raw: &[u8]) -> Result<Rom, RomError> {
+        if raw.len() < HEADER_SIZE || raw[0..4] != NES_TAG {
+            return Err(RomError::NotINes);
+        }
+
+        let control_1 = raw[6];
+        let control_2 = raw[7];
+        let nes_2 = control_2 & 0b0000_1100 == 0b0000_1000;
+
+        let mut mapper = ((control_2 & 0b1111_0000) | (control_1 >> 4)) as u16;
+        let mut submapper = 0;
+        if nes_2 {
+            mapper |= ((raw[8] & 0b0000_1111) as u16) << 8;
+            submapper = raw[8] >> 4;
+        }
+
+        let four_screen = control_1 & 0b0000_1000 != 0;
+        let vertical_mirroring = control_1 & 0b0000_0001 != 0;
+        let screen_mirroring = match (four_screen, vertical_mirroring) {
+            (true, _) => Mirroring::FourScreen,
+            (false, true) => Mirroring::Vertical,
+            (false, false) => Mirroring::Horizontal,
+        };
+
+        let battery = control_1 & 0b0000_0010 != 0;
+        let has_trainer = control_1 & 0b0000_0100 != 0;
+
+        let region = match (nes_2, raw[12] & 0b11, raw[9] & 1) {
+            (true, 0, _) => Region::Ntsc,
+            (true, 1, _) => Region::Pal,
+            (true, 2, _) => Region::Multi,
+            (true, _, _) => Region::Dendy,
+            (false, _, 1) => Region::Pal,
+            (false, _, _) => Region::Ntsc,
+        };
+
+        let prg_ram_size = match (nes_2, raw[8]) {
+            (false, 0) => PRG_RAM_PAGE_SIZE, // 0 means 8KB for compatibility
+            (false, pages) => pages as usize * PRG_RAM_PAGE_SIZE,
+            (true, _) => nes_2_ram_size(raw[10]).max(nes_2_ram_size(raw[10] >> 4)),
+        };
+
+        let prg_rom_size = raw[4] as usize * PRG_ROM_PAGE_SIZE;
+        let chr_rom_size = raw[5] as usize * CHR_ROM_PAGE_SIZE;
+        let trainer_size = if has_trainer { TRAINER_SIZE } else { 0 };
+        let prg_rom_start = HEADER_SIZE + trainer_size;
+        let chr_rom_start = prg_rom_start + prg_rom_size;
+
+        if raw.len() < chr_rom_start + chr_rom_size {
+            return Err(RomError::Truncated {
+                len: raw.len(),
+                expected: chr_rom_start + chr_rom_size,
+            });
+        }
+        if prg_rom_size == 0 {
+            return Err(RomError::NoPrgRom);
+        }
+
+        let trainer = match has_trainer {
+            true => Some(raw[HEADER_SIZE..prg_rom_start].to_vec()),
+            false => None,
+        };
+
+        return Ok(Rom {
+            prg_rom: raw[prg_rom_start..(prg_rom_start + prg_rom_size)].to_vec(),
+            chr_rom: raw[chr_rom_start..(chr_rom_start + chr_rom_size)].to_vec(),
+            mapper,
+            submapper,
+            screen_mirroring,
+            battery,
+            prg_ram_size,
+            trainer,
+            region,
+        });
+    }
+
+    // a .nes file or a zip holding one; `entry` picks a file inside the zip.
+    // A dump the database knows has its header corrected
+    pub fn from_file(path: &Path, entry: Option<&str>) -> Result<Rom, RomError> {
+        let raw = archive::read_rom(path, entry).map_err(RomError::Read)?;
+        let mut rom = Rom::new(&raw).map_err(|e| RomError::File {
+            path: path.to_path_buf(),
+            source: Box::new(e),
+        })?;
+        rom.correct_header();
+        return Ok(rom);
+    }
+
+    // dumps are identified by PRG followed by CHR, the header is never hashed
+    pub fn crc32(&self) -> u32 {
+        let mut crc = Crc32::new();
+        crc.update(&self.prg_rom);
+        crc.update(&self.chr_rom);
+        return crc.finish();
+    }
+
+    pub fn sha1(&self) -> [u8; 20] {
+        let mut sha = Sha1::new();
+        sha.update(&self.prg_rom);
+        sha.update(&self.chr_rom);
+        return sha.finish();
+    }
+
+    // what `rustynes info` prints, a line per field
+    pub fn info(&self) -> String {
+        let mut lines = vec![
+            format!("mapper      {}.{}", self.mapper, self.submapper),
+            format!("prg rom     {} KB", self.prg_rom.len() / 1024),
+            match self.chr_rom.len() {
+                0 => "chr rom     none (chr ram)".to_string(),
+                len => format!("chr rom     {} KB", len / 1024),
+            },
+            format!("prg ram     {} KB", self.prg_ram_size / 1024),
+            format!("mirroring   {:?}", self.screen_mirroring),
+            format!("battery     {}", if self.battery { "yes" } else { "no" }),
+            format!(
+                "trainer     {}",
+                if self.trainer.is_some() { "yes" } else { "no" }
+            ),
+            format!("region      {}", self.region.name()),
+            format!("crc32       {:08x}", self.crc32()),
+            format!("sha1        {}", hash::to_hex(&self.sha1())),
+        ];
+        if let Some(entry) = self.identify() {
+            lines.push(format!("database    {}", entry.title));
+        }
+        return lines.join("\n") + "\n";
+    }
+
+    pub fn identify(&self) -> Option<DbEntry> {
+        return romdb::lookup(self);
+    }
+
+    // looks the dump up and overrides whatever the header got wrong
+    pub fn correct_header(&mut self) -> Option<DbEntry> {
+        let entry = self.identify()?;
+        if let Some(mapper) = entry.mapper {
+            self.mapper = mapper;
+        }
+        if let Some(submapper) = entry.submapper {
+            self.submapper = submapper;
+        }
+        if let Some(mirroring) = entry.mirroring {
+            self.screen_mirroring = mirroring;
+        }
+        if let Some(battery) = entry.battery {
+            self.battery = battery;
+        }
+        if let Some(region) = entry.region {
+            self.region = region;
+        }
+        return Some(entry);
+    }
+}
+
+fn nes_2_ram_size(shift: u8) -> usize {
+    let shift = shift & 0x0F;
+    if shift == 0 {
+        return 0;
+    }
+    return 64 << shift;
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    pub struct TestRom {
+        pub header: Vec<u8>,
+        pub prg_rom: Vec<u8>,
+        pub chr_rom: Vec<u8>,
+    }
+
+    pub fn create_rom(rom: TestRom) -> Vec<u8> {
+        let mut result =
+            Vec::with_capacity(rom.header.len() + rom.prg_rom.len() + rom.chr_rom.len());
+        result.extend(&rom.header);
+        result.extend(&rom.prg_rom);
+        result.extend(&rom.chr_rom);
+        return result;
+    }
+
+    // builds an iNES image for `mapper` with distinct, recognisable bank contents:
+    // every byte of PRG bank n (8KB) is n and every byte of CHR bank n (1KB) is n
+    pub fn test_rom(mapper: u8, prg_pages: u8, chr_pages: u8) -> Vec<u8> {
+        let mut prg_rom = vec![0; prg_pages as usize * PRG_ROM_PAGE_SIZE];
+        for (i, byte) in prg_rom.iter_mut().enumerate() {
+            *byte = (i / 0x2000) as u8;
+        }
+        let mut chr_rom = vec![0; chr_pages as usize * CHR_ROM_PAGE_SIZE];
+        for (i, byte) in chr_rom.iter_mut().enumerate() {
+            *byte = (i / 0x0400) as u8;
+        }
+        return create_rom(TestRom {
+            header: vec![
+                0x4E,
+                0x45,
+                0x53,
+                0x1A,
+                prg_pages,
+                chr_pages,
+                ((mapper & 0x0F) << 4) | 0b0000_0001,
+                mapper & 0xF0,
+                00,
+                00,
+                00,
+                00,
+                00,
+                00,
+                00,
+                00,
+            ],
+            prg_rom,
+            chr_rom,
+        });
+    }
+
+    #[test]
+    fn test_parses_header() {
+        let raw = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x31, 00, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            prg_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+        let rom = Rom::new(&raw).unwrap();
+
+        assert_eq!(rom.chr_rom, vec![2; CHR_ROM_PAGE_SIZE]);
+        assert_eq!(rom.prg_rom, vec![1; 2 * PRG_ROM_PAGE_SIZE]);
+        assert_eq!(rom.mapper, 3);
+        assert_eq!(rom.screen_mirroring, Mirroring::Vertical);
+        assert!(!rom.battery);
+        assert_eq!(rom.prg_ram_size, PRG_RAM_PAGE_SIZE);
+    }
+
+    #[test]
+    fn test_mapper_high_nibble_battery_and_four_screen() {
+        let mut raw = test_rom(0x42, 1, 0);
+        raw[6] |= 0b0000_1010;
+        let rom = Rom::new(&raw).unwrap();
+        assert_eq!(rom.mapper, 0x42);
+        assert!(rom.battery);
+        assert_eq!(rom.screen_mirroring, Mirroring::FourScreen);
+        assert!(rom.chr_rom.is_empty());
+    }
+
+    #[test]
+    fn test_nes_2_header() {
+        let mut raw = test_rom(0x04, 1, 1);
+        raw[7] |= 0b0000_1000;
+        raw[8] = 0x21; // submapper 2, mapper bits 8-11 = 1
+        raw[10] = 0x07; // 64 << 7 = 8KB of PRG RAM
+        let rom = Rom::new(&raw).unwrap();
+        assert_eq!(rom.mapper, 0x104);
+        assert_eq!(rom.submapper, 2);
+        assert_eq!(rom.prg_ram_size, 0x2000);
+        assert_eq!(rom.region, Region::Ntsc);
+
+        raw[12] = 0x03;
+        assert_eq!(Rom::new(&raw).unwrap().region, Region::Dendy);
+        raw[7] &= !0b0000_1100;
+        raw[9] = 0x01;
+        assert_eq!(Rom::new(&raw).unwrap().region, Region::Pal);
+    }
+
+    #[test]
+    fn test_hashes_cover_prg_and_chr() {
+        let rom = Rom::new(&test_rom(0, 1, 1)).unwrap();
+        let mut data = rom.prg_rom.clone();
+        data.extend(&rom.chr_rom);
+        assert_eq!(rom.crc32(), crate::hash::crc32(&data));
+        assert_eq!(rom.sha1(), crate::hash::sha1(&data));
+    }
+
+    #[test]
+    fn test_info() {
+        let rom = Rom::new(&test_rom(0, 2, 0)).unwrap();
+        let info = rom.info();
+        assert!(info.contains("prg rom     32 KB\n"));
+        assert!(info.contains("chr rom     none (chr ram)\n"));
+        assert!(info.contains(&format!("crc32       {:08x}\n", rom.crc32())));
+    }
+
+    #[test]
+    fn test_trainer_is_skipped_and_kept() {
+        let plain = test_rom(0, 2, 1);
+        let mut raw = plain[..HEADER_SIZE].to_vec();
+        raw[6] |= 0b0000_0100;
+        raw.extend(vec![0xEE; TRAINER_SIZE]);
+        raw.extend(&plain[HEADER_SIZE..]);
+
+        let rom = Rom::new(&raw).unwrap();
+        assert_eq!(rom.trainer, Some(vec![0xEE; TRAINER_SIZE]));
+        assert_eq!(rom.prg_rom[0], 0);
+        assert_eq!(rom.prg_rom[0x2000], 1);
+        assert_eq!(rom.chr_rom[0], 0);
+        assert_eq!(Rom::new(&plain).unwrap().trainer, None);
+    }
+
+    #[test]
+    fn test_rejects_bad_files() {
+        assert_eq!(
+            Rom::new(&[0x4E, 0x45, 0x53]).unwrap_err(),
+            RomError::NotINes
+        );
+        let mut raw = test_rom(0, 2, 1);
+        raw[0] = 0;
+        assert!(Rom::new(&raw).is_err());
+
+        let mut raw = test_rom(0, 2, 1);
+        raw.truncate(raw.len() - 1);
+        assert_eq!(
+            Rom::new(&raw).unwrap_err(),
+            RomError::Truncated {
+                len: raw.len(),
+                expected: raw.len() + 1
+            }
+        );
+
+        let mut raw = test_rom(0, 1, 1);
+        raw[6] |= 0b0000_0100;
+        assert!(Rom::new(&raw).is_err());
+    }
+
+    #[test]
+    fn test_from_zip_file() {
+        use crate::archive::test::create_zip;
+
+        let raw = test_rom(4, 2, 1);
+        let path = std::env::temp_dir().join(format!("rustynes-rom-{}.zip", std::process::id()));
+        std::fs::write(&path, create_zip(&[("info.txt", b"x"), ("game.nes", &raw)])).unwrap();
+
+        let rom = Rom::from_file(&path, None).unwrap();
+        assert_eq!(rom.mapper, 4);
+        assert_eq!(rom.prg_rom[0x2000], 1);
+        assert!(Rom::from_file(&path, Some("info.txt")).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
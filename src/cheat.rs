@@ -0,0 +1,270 @@
+/* Cheat codes
+
+   Game Genie codes sit between the CPU and the cartridge and substitute the
+   byte read from one ROM address, optionally only when the ROM holds an
+   expected compare value (8 letter codes), so banked games only get patched in
+   the right bank. Each letter is a nibble:
+
+    A P Z L G I T Y E O X U K S V N
+    0 1 2 3 4 5 6 7 8 9 A B C D E F
+
+   Pro Action Replay codes are raw "AAAA:VV" pairs in hex. Below $8000 they are
+   poked into memory once per frame, which is how the real device freezes a
+   RAM value; at $8000 and up they patch ROM like a Game Genie code without a
+   compare value.
+*/
+
+const GAME_GENIE_LETTERS: &str = "APZLGITYEOXUKSVN";
+const ROM_START: u16 = 0x8000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Effect {
+    RomPatch {
+        addr: u16,
+        value: u8,
+        compare: Option<u8>,
+    },
+    RamWrite {
+        addr: u16,
+        value: u8,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cheat {
+    pub code: String,
+    pub effect: Effect,
+    pub enabled: bool,
+}
+
+impl Cheat {
+    pub fn parse(code: &str) -> Result<Cheat, String> {
+        let code = code.trim().to_ascii_uppercase();
+        let effect = if code.contains(':') {
+            parse_action_replay(&code)?
+        } else {
+            parse_game_genie(&code)?
+        };
+        return Ok(Cheat {
+            code,
+            effect,
+            enabled: true,
+        });
+    }
+}
+
+fn parse_game_genie(code: &str) -> Result<Effect, String> {
+    let mut n = Vec::with_capacity(8);
+    for letter in code.chars() {
+        match GAME_GENIE_LETTERS.find(letter) {
+            Some(nibble) => n.push(nibble as u16),
+            None => {
+                return Err(format!(
+                    "'{}' is not a Game Genie letter in {}",
+                    letter, code
+                ))
+            }
+        }
+    }
+    if n.len() != 6 && n.len() != 8 {
+        return Err(format!("Game Genie codes are 6 or 8 letters, got {}", code));
+    }
+
+    let addr = ROM_START
+        | ((n[3] & 7) << 12)
+        | ((n[5] & 7) << 8)
+        | ((n[4] & 8) << 8)
+        | ((n[2] & 7) << 4)
+        | ((n[1] & 8) << 4)
+        | (n[4] & 7)
+        | (n[3] & 8);
+    let high = ((n[1] & 7) << 4) | ((n[0] & 8) << 4) | (n[0] & 7);
+
+    if n.len() == 6 {
+        return Ok(Effect::RomPatch {
+            addr,
+            value: (high | (n[5] & 8)) as u8,
+            compare: None,
+        });
+    }
+    let compare = ((n[7] & 7) << 4) | ((n[6] & 8) << 4) | (n[6] & 7) | (n[5] & 8);
+    return Ok(Effect::RomPatch {
+        addr,
+        value: (high | (n[7] & 8)) as u8,
+        compare: Some(compare as u8),
+    });
+}
+
+fn parse_action_replay(code: &str) -> Result<Effect, String> {
+    let (addr, value) = code.split_once(':').unwrap();
+    let addr = u16::from_str_radix(addr, 16)
+        .map_err(|_| format!("bad address in Action Replay code {}", code))?;
+    let value = u8::from_str_radix(value, 16)
+        .map_err(|_| format!("bad value in Action Replay code {}", code))?;
+    if addr >= ROM_START {
+        return Ok(Effect::RomPatch {
+            addr,
+            value,
+            compare: None,
+        });
+    }
+    return Ok(Effect::RamWrite { addr, value });
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CheatList {
+    cheats: Vec<Cheat>,
+}
+
+impl CheatList {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    // returns the index the code can be toggled or removed by
+    pub fn add(&mut self, code: &str) -> Result<usize, String> {
+        self.cheats.push(Cheat::parse(code)?);
+        return Ok(self.cheats.len() - 1);
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Cheat> {
+        if index >= self.cheats.len() {
+            return None;
+        }
+        return Some(self.cheats.remove(index));
+    }
+
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) -> Result<(), String> {
+        match self.cheats.get_mut(index) {
+            Some(cheat) => cheat.enabled = enabled,
+            None => return Err(format!("no cheat at index {}", index)),
+        }
+        return Ok(());
+    }
+
+    pub fn clear(&mut self) {
+        self.cheats.clear();
+    }
+
+    pub fn cheats(&self) -> &[Cheat] {
+        return &self.cheats;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.cheats.is_empty();
+    }
+
+    // the byte the CPU sees for a cartridge read that returned `value`
+    pub fn patch(&self, addr: u16, value: u8) -> u8 {
+        for cheat in self.cheats.iter().filter(|c| c.enabled) {
+            if let Effect::RomPatch {
+                addr: target,
+                value: replacement,
+                compare,
+            } = cheat.effect
+            {
+                if target == addr && compare.is_none_or(|c| c == value) {
+                    return replacement;
+                }
+            }
+        }
+        return value;
+    }
+
+    pub fn ram_writes(&self) -> Vec<(u16, u8)> {
+        let mut writes = Vec::new();
+        for cheat in self.cheats.iter().filter(|c| c.enabled) {
+            if let Effect::RamWrite { addr, value } = cheat.effect {
+                writes.push((addr, value));
+            }
+        }
+        return writes;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_six_letter_game_genie() {
+        // Super Mario Bros. infinite lives
+        let cheat = Cheat::parse("sxiopo").unwrap();
+        assert_eq!(cheat.code, "SXIOPO");
+        assert_eq!(
+            cheat.effect,
+            Effect::RomPatch {
+                addr: 0x91D9,
+                value: 0xAD,
+                compare: None
+            }
+        );
+    }
+
+    #[test]
+    fn test_eight_letter_game_genie() {
+        let cheat = Cheat::parse("SXIOPOZA").unwrap();
+        assert_eq!(
+            cheat.effect,
+            Effect::RomPatch {
+                addr: 0x91D9,
+                value: 0xA5,
+                compare: Some(0x0A)
+            }
+        );
+    }
+
+    #[test]
+    fn test_action_replay() {
+        assert_eq!(
+            Cheat::parse("075A:09").unwrap().effect,
+            Effect::RamWrite {
+                addr: 0x075A,
+                value: 0x09
+            }
+        );
+        assert_eq!(
+            Cheat::parse("C000:EA").unwrap().effect,
+            Effect::RomPatch {
+                addr: 0xC000,
+                value: 0xEA,
+                compare: None
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Cheat::parse("SXIOP").is_err());
+        assert!(Cheat::parse("SXIOPB").is_err());
+        assert!(Cheat::parse("12345:00").is_err());
+        assert!(Cheat::parse("0000:100").is_err());
+    }
+
+    #[test]
+    fn test_patch_honours_compare_and_enable() {
+        let mut list = CheatList::new();
+        let gg = list.add("SXIOPOZA").unwrap(); // $91D9 -> $A5 when it holds $0A
+        assert_eq!(list.patch(0x91D9, 0x0A), 0xA5);
+        assert_eq!(list.patch(0x91D9, 0x0B), 0x0B); // wrong bank
+        assert_eq!(list.patch(0x91DA, 0x0A), 0x0A);
+
+        list.set_enabled(gg, false).unwrap();
+        assert_eq!(list.patch(0x91D9, 0x0A), 0x0A);
+        assert!(list.set_enabled(5, true).is_err());
+    }
+
+    #[test]
+    fn test_ram_writes() {
+        let mut list = CheatList::new();
+        list.add("075A:09").unwrap();
+        let off = list.add("0010:FF").unwrap();
+        list.add("SXIOPO").unwrap();
+        list.set_enabled(off, false).unwrap();
+        assert_eq!(list.ram_writes(), vec![(0x075A, 0x09)]);
+
+        assert!(list.remove(0).is_some());
+        assert!(list.ram_writes().is_empty());
+        assert!(list.remove(9).is_none());
+    }
+}
@@ -0,0 +1,227 @@
+use crate::bus::Bus;
+use crate::cheat::CheatList;
+
+const RAM_SIZE: usize = 0x0800;
+const PRG_RAM_START: u16 = 0x6000;
+const PRG_RAM_WINDOW: usize = 0x2000;
+// more survivors than this and freezing them all would wreck the game
+pub const MAX_FREEZE: usize = 16;
+
+/* Cheat search
+
+   The classic way to find the byte that holds a game's lives or health:
+   take a snapshot of work RAM and any cartridge RAM, play until the value
+   changes, then keep only the addresses whose bytes compare the right way
+   and repeat until few are left. Each filter compares the bytes now with
+   those at the last snapshot, or with a given value, and then takes the
+   snapshot again for the next one:
+
+    equal [N]       the same as before, or N
+    different [N]   changed, or anything but N
+    greater [N]     bigger than before, or than N
+    less [N]        smaller than before, or than N
+    changed-by N    before plus N, wrapping, e.g. -1 for a life lost
+
+   What survives becomes Pro Action Replay freeze cheats, `AAAA:VV`, poked
+   every frame with the byte it holds now or a chosen value. Cartridge RAM
+   is searched as the CPU sees it at $6000-$7FFF, so on boards that bank it
+   only the bank that is in at the snapshot.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    Equal(Option<u8>),
+    Different(Option<u8>),
+    Greater(Option<u8>),
+    Less(Option<u8>),
+    ChangedBy(i16),
+}
+
+impl Filter {
+    // a name and its number, as above; numbers are decimal or $hex
+    pub fn parse(text: &str) -> Result<Filter, String> {
+        let mut words = text.split_whitespace();
+        let name = words.next().unwrap_or("");
+        let value = words.next();
+        if let Some(extra) = words.next() {
+            return Err(format!("unexpected {} after the filter", extra));
+        }
+        let byte = |value: Option<&str>| -> Result<Option<u8>, String> {
+            return match value {
+                Some(value) => match parse_value(value)? {
+                    n @ 0..=0xFF => Ok(Some(n as u8)),
+                    _ => Err(format!("{} does not fit in a byte", value)),
+                },
+                None => Ok(None),
+            };
+        };
+        return match name {
+            "equal" | "=" => Ok(Filter::Equal(byte(value)?)),
+            "different" | "!=" => Ok(Filter::Different(byte(value)?)),
+            "greater" | ">" => Ok(Filter::Greater(byte(value)?)),
+            "less" | "<" => Ok(Filter::Less(byte(value)?)),
+            "changed-by" => {
+                let value = value.ok_or("changed-by needs an amount")?;
+                match parse_value(value)? {
+                    n @ -0xFF..=0xFF => Ok(Filter::ChangedBy(n as i16)),
+                    _ => Err(format!("{} is more than a byte can change by", value)),
+                }
+            }
+            _ => Err(format!("unknown filter name {}", name)),
+        };
+    }
+
+    fn keeps(&self, before: u8, now: u8) -> bool {
+        return match *self {
+            Filter::Equal(value) => now == value.unwrap_or(before),
+            Filter::Different(value) => now != value.unwrap_or(before),
+            Filter::Greater(value) => now > value.unwrap_or(before),
+            Filter::Less(value) => now < value.unwrap_or(before),
+            Filter::ChangedBy(amount) => now == before.wrapping_add(amount as u8),
+        };
+    }
+}
+
+fn parse_value(text: &str) -> Result<i32, String> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let value = match digits.strip_prefix('$') {
+        Some(hex) => i32::from_str_radix(hex, 16),
+        None => digits.parse::<i32>(),
+    }
+    .map_err(|_| format!("{} is not a number", text))?;
+    return Ok(if negative { -value } else { value });
+}
+
+// every searchable address and the byte in it
+fn snapshot(bus: &Bus) -> Vec<(u16, u8)> {
+    let mut bytes: Vec<(u16, u8)> = bus
+        .ram()
+        .iter()
+        .take(RAM_SIZE)
+        .enumerate()
+        .map(|(addr, &value)| (addr as u16, value))
+        .collect();
+    if let Some(prg_ram) = bus.mapper().and_then(|mapper| mapper.prg_ram()) {
+        for (i, &value) in prg_ram.iter().take(PRG_RAM_WINDOW).enumerate() {
+            bytes.push((PRG_RAM_START + i as u16, value));
+        }
+    }
+    return bytes;
+}
+
+fn read(bus: &Bus, addr: u16) -> u8 {
+    if addr < PRG_RAM_START {
+        return bus.ram()[addr as usize];
+    }
+    return bus
+        .mapper()
+        .and_then(|mapper| mapper.prg_ram())
+        .and_then(|prg_ram| prg_ram.get((addr - PRG_RAM_START) as usize).copied())
+        .unwrap_or(0);
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CheatSearch {
+    candidates: Vec<(u16, u8)>, // with their bytes at the last snapshot
+}
+
+impl CheatSearch {
+    // every address is a candidate to begin with
+    pub fn start(bus: &Bus) -> Self {
+        return Self {
+            candidates: snapshot(bus),
+        };
+    }
+
+    // returns how many addresses are left
+    pub fn filter(&mut self, bus: &Bus, filter: Filter) -> usize {
+        self.candidates.retain_mut(|(addr, before)| {
+            let now = read(bus, *addr);
+            let keep = filter.keeps(*before, now);
+            *before = now;
+            return keep;
+        });
+        return self.candidates.len();
+    }
+
+    pub fn candidates(&self) -> &[(u16, u8)] {
+        return &self.candidates;
+    }
+
+    // a freeze cheat for each address left, holding `value` or the byte it
+    // held at the last snapshot, returning the codes
+    pub fn freeze(&self, cheats: &mut CheatList, value: Option<u8>) -> Result<Vec<String>, String> {
+        if self.candidates.is_empty() {
+            return Err("no addresses are left to freeze".to_string());
+        }
+        if self.candidates.len() > MAX_FREEZE {
+            return Err(format!(
+                "{} addresses are left, narrow the search to {} or fewer first",
+                self.candidates.len(),
+                MAX_FREEZE
+            ));
+        }
+        let mut codes = Vec::new();
+        for &(addr, before) in &self.candidates {
+            let code = format!("{:04X}:{:02X}", addr, value.unwrap_or(before));
+            cheats.add(&code)?;
+            codes.push(code);
+        }
+        return Ok(codes);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+    use crate::cartridge::Rom;
+
+    #[test]
+    fn test_filter_parse() {
+        assert_eq!(Filter::parse("equal"), Ok(Filter::Equal(None)));
+        assert_eq!(Filter::parse("< $10"), Ok(Filter::Less(Some(0x10))));
+        assert_eq!(Filter::parse("greater 3"), Ok(Filter::Greater(Some(3))));
+        assert_eq!(Filter::parse("changed-by -1"), Ok(Filter::ChangedBy(-1)));
+        assert!(Filter::parse("equal 256").is_err());
+        assert!(Filter::parse("changed-by").is_err());
+        assert!(Filter::parse("equal 1 2").is_err());
+        assert_eq!(
+            Filter::parse("bigger"),
+            Err("unknown filter name bigger".to_string())
+        );
+    }
+
+    #[test]
+    fn test_search_finds_the_lives_counter() {
+        let mut bus = Bus::from_rom(Rom::new(&test_rom(0, 1, 1)).unwrap()).unwrap();
+        bus.ram_mut()[0x075A] = 3;
+        bus.ram_mut()[0x0100] = 3;
+        bus.ram_mut()[0x0200] = 7;
+        let mut search = CheatSearch::start(&bus);
+        // work RAM and the cartridge's 8KB
+        assert_eq!(search.candidates().len(), 0x800 + 0x2000);
+
+        // a life lost, and something else that happened to go down too
+        bus.ram_mut()[0x075A] = 2;
+        bus.ram_mut()[0x0200] = 6;
+        assert_eq!(search.filter(&bus, Filter::ChangedBy(-1)), 2);
+        bus.ram_mut()[0x0200] = 9;
+        assert_eq!(search.filter(&bus, Filter::Equal(None)), 1);
+        assert_eq!(search.candidates(), &[(0x075A, 2)]);
+
+        let mut cheats = CheatList::new();
+        assert_eq!(
+            search.freeze(&mut cheats, Some(9)),
+            Ok(vec!["075A:09".to_string()])
+        );
+        assert_eq!(cheats.ram_writes(), vec![(0x075A, 9)]);
+
+        search.filter(&bus, Filter::Greater(Some(5)));
+        assert!(search.freeze(&mut cheats, None).is_err());
+        assert!(CheatSearch::start(&bus).freeze(&mut cheats, None).is_err());
+    }
+}
@@ -1,12 +1,24 @@
+use crate::bus::Bus;
+use crate::cycle_profile::CycleProfiler;
+use crate::debugger::call_stack::CallStack;
+use crate::error::CpuError;
+use crate::error::StateError;
+use crate::instruction_stats::InstructionStats;
 use crate::op_codes::{OpCode, NMOS_6502_OPCODES_MAP};
+use crate::ppu::events::EventKind;
 use crate::processor::Processor;
+use crate::savestate::{StateReader, StateWriter};
 use crate::stack::Stack;
+use crate::trace::{Entry, History};
 
 const STACK_BOTTOM: u16 = 0x01FF;
 const STACK_TOP: u16 = 0x0100;
 const STACK_RESET: u8 = STACK_BOTTOM as u8;
+const RESET_VECTOR: u16 = 0xFFFC;
+const NMI_VECTOR: u16 = 0xFFFA;
+const IRQ_VECTOR: u16 = 0xFFFE;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[allow(non_camel_case_types)]
 pub enum AddressingMode {
     Immediate,
@@ -22,16 +34,16 @@ pub enum AddressingMode {
     NoneAddressing, //TODO consider splitting Accumulator mode out of this
 }
 
-trait Mem {
-    fn mem_read(&self, addr: u16) -> u8;
+pub trait Mem {
+    fn mem_read(&mut self, addr: u16) -> u8;
 
     fn mem_write(&mut self, addr: u16, data: u8);
 
-    fn mem_read_u16(&self, pos: u16) -> u16 {
+    fn mem_read_u16(&mut self, pos: u16) -> u16 {
         let lo = self.mem_read(pos) as u16; // lower 8 bits read from current pos
         let hi = self.mem_read(pos + 1) as u16; // upper 8 bits read from next pos
-        return (hi << 8) | (lo as u16); // << high is shifted 8 bit positions left and combined
-                                        // with low to form complete 16 bit value
+        return (hi << 8) | lo; // << high is shifted 8 bit positions left and combined
+                               // with low to form complete 16 bit value
     }
 
     fn mem_write_u16(&mut self, pos: u16, data: u16) {
@@ -43,13 +55,36 @@ trait Mem {
     }
 }
 
+fn sets_program_counter(mnemonic: &str) -> bool {
+    return matches!(
+        mnemonic,
+        "BCC"
+            | "BCS"
+            | "BEQ"
+            | "BMI"
+            | "BNE"
+            | "BPL"
+            | "BVC"
+            | "BVS"
+            | "BRK"
+            | "JMP"
+            | "JSR"
+            | "RTI"
+            | "RTS"
+    );
+}
+
 impl Mem for CPU {
-    fn mem_read(&self, addr: u16) -> u8 {
-        return self.memory[addr as usize];
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        return self.bus.mem_read(addr);
     }
 
     fn mem_write(&mut self, addr: u16, data: u8) {
-        self.memory[addr as usize] = data;
+        self.bus.mem_write(addr, data);
+    }
+
+    fn mem_read_u16(&mut self, pos: u16) -> u16 {
+        return self.bus.mem_read_u16(pos);
     }
 }
 
@@ -61,11 +96,36 @@ pub struct CPU {
     pub status: Processor,
     pub program_counter: u16,
     pub stack: Stack,
-    memory: [u8; 0xFFFF],
+    pub cycles: u64,
+    pub instructions: u64, // executed since power on, for performance readouts
+    // BRK ends `run` so bare programs terminate; with this off it is a real software interrupt
+    pub halt_on_brk: bool,
+    pub bus: Bus,
+    pub history: History, // the instructions that led here
+    pub profiler: Option<Box<CycleProfiler>>,
+    pub stats: Option<Box<InstructionStats>>, // see `instruction_stats`
+    pub calls: CallStack,                     // the subroutines it's in, for the debugger
+    // hit an opcode the CPU can't run, KIL or one that isn't in the table;
+    // the clock carries on but nothing else does until a reset, and `check`
+    // says which
+    pub jammed: bool,
+    fault: Option<CpuError>, // why, as `check` gives it
+    // the last indexed operand address landed on another page than its base
+    page_crossed: bool,
+}
+
+impl Default for CPU {
+    fn default() -> Self {
+        return Self::new();
+    }
 }
 
 impl CPU {
     pub fn new() -> Self {
+        return Self::with_bus(Bus::flat());
+    }
+
+    pub fn with_bus(bus: Bus) -> Self {
         let status = Processor::new();
         let stack = Stack::new(STACK_BOTTOM, STACK_TOP);
         CPU {
@@ -75,202 +135,313 @@ impl CPU {
             status,
             program_counter: 0,
             stack,
-            memory: [0; 0xFFFF],
+            cycles: 0,
+            instructions: 0,
+            halt_on_brk: true,
+            bus,
+            history: History::default(),
+            profiler: None,
+            stats: None,
+            calls: CallStack::default(),
+            jammed: false,
+            fault: None,
+            page_crossed: false,
         }
     }
 
+    // the counts by instruction since `stats` was switched on, if it was
+    pub fn instruction_stats(&self) -> Option<&InstructionStats> {
+        return self.stats.as_deref();
+    }
+
     pub fn run(&mut self) {
-        loop {
-            let code = self.mem_read(self.program_counter);
-            self.program_counter += 1;
+        while self.step() {}
+    }
 
-            let op_code = NMOS_6502_OPCODES_MAP
-                .get(&code)
-                .expect("code not recognized"); // TODO: get rid of unwrap
+    // why the CPU jammed, if it has
+    pub fn check(&self) -> Result<(), CpuError> {
+        if !self.jammed {
+            return Ok(());
+        }
+        // a jam loaded from a save state doesn't come with its reason
+        return Err(self.fault.clone().unwrap_or(CpuError::Jammed {
+            opcode: self.bus.peek(self.program_counter),
+            pc: self.program_counter,
+        }));
+    }
+
+    // executes a single instruction, returns false once BRK has been reached
+    // or the CPU has jammed
+    pub fn step(&mut self) -> bool {
+        if self.jammed {
+            self.cycles += 1;
+            self.bus.tick(1);
+            return false;
+        }
+        if self.history.capacity() > 0 {
+            let entry = Entry::capture(self);
+            self.history.record(entry);
+        }
+        let cycles_before = self.cycles;
+        self.bus.begin_instruction();
+        let running = self.execute();
+        self.instructions += 1;
+        self.cycles += self.bus.take_stall();
+        self.bus.tick(self.cycles - cycles_before);
+        self.calls.unwind(self.stack.ptr() as u8);
+        if let (true, Some(profiler)) = (running, &mut self.profiler) {
+            profiler.end(self.cycles - cycles_before, self.program_counter);
+        }
+        return running;
+    }
 
-            match op_code.mnemonic {
-                "ADC" => {
-                    self.adc(&op_code);
-                }
-                "AND" => {
-                    self.and(&op_code);
-                }
-                "ASL" => {
-                    self.asl(&op_code);
-                }
-                "BCC" => {
-                    self.bcc(&op_code);
-                }
-                "BCS" => {
-                    self.bcs(&op_code);
-                }
-                "BEQ" => {
-                    self.beq(&op_code);
-                }
-                "BIT" => {
-                    self.bit(&op_code);
-                }
-                "BMI" => {
-                    self.bmi(&op_code);
-                }
-                "BNE" => {
-                    self.bne(&op_code);
-                }
-                "BPL" => {
-                    self.bpl(&op_code);
-                }
-                "BRK" => {
-                    return;
-                }
-                "BVC" => {
-                    self.bvc(&op_code);
-                }
-                "BVS" => {
-                    self.bvs(&op_code);
-                }
-                "CLC" => {
-                    self.clc();
-                }
-                "CLD" => {
-                    self.cld();
-                }
-                "CLI" => {
-                    self.cli();
-                }
-                "CLV" => {
-                    self.clv();
-                }
-                "CMP" => {
-                    self.cmp(&op_code);
-                }
-                "CPX" => {
-                    self.cpx(&op_code);
-                }
-                "CPY" => {
-                    self.cpy(&op_code);
-                }
-                "DEC" => {
-                    self.dec(&op_code);
-                }
-                "DEX" => {
-                    self.dex();
-                }
-                "DEY" => {
-                    self.dey();
-                }
-                "EOR" => {
-                    self.eor(&op_code);
-                }
-                "INC" => {
-                    self.inc(&op_code);
-                }
-                "INX" => {
-                    self.inx();
-                }
-                "INY" => {
-                    self.iny();
-                }
-                "JMP" => {
-                    self.jmp(&op_code);
-                }
-                "JSR" => {
-                    self.jsr(&op_code);
-                }
-                "LDA" => {
-                    self.lda(&op_code);
-                }
-                "LDX" => {
-                    self.ldx(&op_code);
-                }
-                "LDY" => {
-                    self.ldy(&op_code);
-                }
-                "LSR" => {
-                    self.lsr(&op_code);
-                }
-                "NOP" => {
-                    self.nop();
-                }
-                "ORA" => {
-                    self.ora(&op_code);
-                }
-                "PHA" => {
-                    self.pha();
-                }
-                "PHP" => {
-                    self.php();
-                }
-                "PLA" => {
-                    self.pla();
-                }
-                "PLP" => {
-                    self.plp();
-                }
-                "ROL" => {
-                    self.rol(&op_code);
-                }
-                "ROR" => {
-                    self.ror(&op_code);
-                }
-                "RTI" => {
-                    self.rti();
-                }
-                "RTS" => {
-                    self.rts();
-                }
-                "SBC" => {
-                    self.sbc(&op_code);
-                }
-                "SEC" => {
-                    self.sec();
-                }
-                "SED" => {
-                    self.sed();
-                }
-                "SEI" => {
-                    self.sei();
-                }
-                "STA" => {
-                    self.sta(&op_code);
-                }
-                "STX" => {
-                    self.stx(&op_code);
-                }
-                "STY" => {
-                    self.sty(&op_code);
-                }
-                "TAX" => {
-                    self.tax();
-                }
-                "TAY" => {
-                    self.tay();
-                }
-                "TSX" => {
-                    self.tsx();
-                }
-                "TXA" => {
-                    self.txa();
-                }
-                "TXS" => {
-                    self.txs();
-                }
-                "TYA" => {
-                    self.tya();
+    fn execute(&mut self) -> bool {
+        if self.bus.take_nmi() {
+            self.interrupt(NMI_VECTOR);
+        } else if self.bus.irq_pending() && self.status.interrupt() == 0 {
+            self.bus.log_event(EventKind::Irq);
+            self.interrupt(IRQ_VECTOR);
+        }
+
+        let at = self.program_counter;
+        let code = self.mem_read(at);
+        self.program_counter = self.program_counter.wrapping_add(1);
+
+        if let Some(profiler) = &mut self.profiler {
+            profiler.begin(self.program_counter.wrapping_sub(1), code);
+        }
+        if let Some(heatmap) = &mut self.bus.heatmap {
+            heatmap.execute(self.program_counter.wrapping_sub(1));
+        }
+        let Some(op_code) = NMOS_6502_OPCODES_MAP.get(&code) else {
+            self.jam(CpuError::Jammed {
+                opcode: code,
+                pc: at,
+            });
+            self.cycles += 1;
+            return false;
+        };
+        if let Some(stats) = &mut self.stats {
+            stats.record(code);
+        }
+        self.page_crossed = false;
+
+        match op_code.mnemonic {
+            "ADC" => {
+                self.adc(op_code);
+            }
+            "AND" => {
+                self.and(op_code);
+            }
+            "ASL" => {
+                self.asl(op_code);
+            }
+            "BCC" => {
+                self.bcc(op_code);
+            }
+            "BCS" => {
+                self.bcs(op_code);
+            }
+            "BEQ" => {
+                self.beq(op_code);
+            }
+            "BIT" => {
+                self.bit(op_code);
+            }
+            "BMI" => {
+                self.bmi(op_code);
+            }
+            "BNE" => {
+                self.bne(op_code);
+            }
+            "BPL" => {
+                self.bpl(op_code);
+            }
+            "BRK" => {
+                if self.halt_on_brk {
+                    return false;
                 }
-                _ => panic!(),
+                self.brk();
             }
+            "BVC" => {
+                self.bvc(op_code);
+            }
+            "BVS" => {
+                self.bvs(op_code);
+            }
+            "CLC" => {
+                self.clc();
+            }
+            "CLD" => {
+                self.cld();
+            }
+            "CLI" => {
+                self.cli();
+            }
+            "CLV" => {
+                self.clv();
+            }
+            "CMP" => {
+                self.cmp(op_code);
+            }
+            "CPX" => {
+                self.cpx(op_code);
+            }
+            "CPY" => {
+                self.cpy(op_code);
+            }
+            "DEC" => {
+                self.dec(op_code);
+            }
+            "DEX" => {
+                self.dex();
+            }
+            "DEY" => {
+                self.dey();
+            }
+            "EOR" => {
+                self.eor(op_code);
+            }
+            "INC" => {
+                self.inc(op_code);
+            }
+            "INX" => {
+                self.inx();
+            }
+            "INY" => {
+                self.iny();
+            }
+            "JMP" => {
+                self.jmp(op_code);
+            }
+            "JSR" => {
+                self.jsr(op_code);
+            }
+            "LDA" => {
+                self.lda(op_code);
+            }
+            "LDX" => {
+                self.ldx(op_code);
+            }
+            "LDY" => {
+                self.ldy(op_code);
+            }
+            "LSR" => {
+                self.lsr(op_code);
+            }
+            "NOP" => {
+                self.nop();
+            }
+            "ORA" => {
+                self.ora(op_code);
+            }
+            "PHA" => {
+                self.pha();
+            }
+            "PHP" => {
+                self.php();
+            }
+            "PLA" => {
+                self.pla();
+            }
+            "PLP" => {
+                self.plp();
+            }
+            "ROL" => {
+                self.rol(op_code);
+            }
+            "ROR" => {
+                self.ror(op_code);
+            }
+            "RTI" => {
+                self.rti();
+            }
+            "RTS" => {
+                self.rts();
+            }
+            "SBC" => {
+                self.sbc(op_code);
+            }
+            "SEC" => {
+                self.sec();
+            }
+            "SED" => {
+                self.sed();
+            }
+            "SEI" => {
+                self.sei();
+            }
+            "STA" => {
+                self.sta(op_code);
+            }
+            "STX" => {
+                self.stx(op_code);
+            }
+            "STY" => {
+                self.sty(op_code);
+            }
+            "TAX" => {
+                self.tax();
+            }
+            "TAY" => {
+                self.tay();
+            }
+            "TSX" => {
+                self.tsx();
+            }
+            "TXA" => {
+                self.txa();
+            }
+            "TXS" => {
+                self.txs();
+            }
+            "TYA" => {
+                self.tya();
+            }
+            mnemonic => self.fault = Some(CpuError::Unimplemented { mnemonic, pc: at }),
+        }
+        // an instruction the table has wrong stops the CPU where it is
+        if let Some(fault) = self.fault.take() {
+            self.jam(fault);
+            return false;
+        }
+        // jumps, branches, returns and BRK set the program counter themselves,
+        // even when it ends up where it was
+        if !sets_program_counter(op_code.mnemonic) {
             self.advance_program_counter(op_code.len);
         }
+        self.cycles += (op_code.cycles + self.page_cross_penalty(op_code)) as u64;
+        return true;
     }
 
     pub fn reset(&mut self) {
+        self.jammed = false;
+        self.fault = None;
+        self.calls.clear();
         self.register_a = 0;
         self.register_x = 0;
         self.register_y = 0;
         // TODO push self.status to self.stack
+        // the console powers on with IRQs masked, as the reset button leaves them
         self.status = Processor::new();
+        self.status.set_interrupt();
+        self.stack.set_ptr(STACK_RESET);
 
-        self.program_counter = self.mem_read_u16(0xFFFC);
+        self.program_counter = self.mem_read_u16(RESET_VECTOR);
+    }
+
+    // the reset button: registers and RAM survive, the CPU runs the three
+    // dummy stack reads of an interrupt and the cartridge sees the reset line
+    pub fn soft_reset(&mut self) {
+        self.jammed = false;
+        self.fault = None;
+        self.calls.clear();
+        self.bus.reset();
+        self.status.set_interrupt();
+        for _ in 0..3 {
+            self.stack.incr_ptr();
+        }
+        self.program_counter = self.mem_read_u16(RESET_VECTOR);
     }
 
     pub fn load_and_run(&mut self, program: Vec<u8>) {
@@ -280,16 +451,142 @@ impl CPU {
     }
 
     pub fn load(&mut self, program: Vec<u8>) {
-        self.memory[0x8000..(0x8000 + program.len())].copy_from_slice(&program[..]);
-        self.mem_write_u16(0xFFFC, 0x8000);
+        // only meaningful on the flat bus, a cartridge's PRG ROM can't be written
+        for (i, byte) in program.iter().enumerate() {
+            self.mem_write(0x8000 + i as u16, *byte);
+        }
+        self.mem_write_u16(RESET_VECTOR, 0x8000);
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.register_a);
+        writer.write_u8(self.register_x);
+        writer.write_u8(self.register_y);
+        writer.write_u8(self.status.bits());
+        writer.write_u16(self.program_counter);
+        writer.write_u8(self.stack.ptr() as u8);
+        writer.write_u64(self.cycles);
+        self.bus.save_state(writer);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.register_a = reader.read_u8()?;
+        self.register_x = reader.read_u8()?;
+        self.register_y = reader.read_u8()?;
+        self.status.set_bits(reader.read_u8()?);
+        self.program_counter = reader.read_u16()?;
+        self.stack.set_ptr(reader.read_u8()?);
+        self.cycles = reader.read_u64()?;
+        self.bus.load_state(reader)?;
+        // the calls that led to the saved state weren't saved with it
+        self.calls.clear();
+        return Ok(());
+    }
+
+    // enters the subroutine at `addr` as if a JSR had been executed, so the
+    // routine's final RTS lands on `return_to`
+    pub fn call(&mut self, addr: u16, return_to: u16) {
+        let sp = self.stack.ptr() as u8;
+        self.calls.call(addr, return_to, sp, false);
+        self.stack_push_u16(return_to.wrapping_sub(1));
+        self.program_counter = addr;
+    }
+
+    // stops at the instruction at `pc`, until a reset
+    fn jam(&mut self, error: CpuError) {
+        let (CpuError::Jammed { pc, .. }
+        | CpuError::Unimplemented { pc, .. }
+        | CpuError::UnsupportedMode { pc, .. }) = error;
+        self.program_counter = pc;
+        self.jammed = true;
+        self.fault = Some(error);
+    }
+
+    fn interrupt(&mut self, vector: u16) {
+        let (sp, return_to) = (self.stack.ptr() as u8, self.program_counter);
+        self.stack_push_u16(self.program_counter);
+        // B is clear for hardware interrupts, bit 5 is always pushed as 1
+        self.stack_push((self.status.bits() & 0b1110_1111) | 0b0010_0000);
+        self.status.set_interrupt();
+        self.program_counter = self.mem_read_u16(vector);
+        self.cycles += 7;
+        self.calls.call(self.program_counter, return_to, sp, true);
+        if let Some(profiler) = &mut self.profiler {
+            profiler.interrupt(self.program_counter);
+        }
+    }
+
+    fn brk(&mut self) {
+        // the byte after BRK is padding, the return address skips it
+        let (sp, return_to) = (self.stack.ptr() as u8, self.program_counter.wrapping_add(1));
+        self.stack_push_u16(return_to);
+        self.stack_push(self.status.bits() | 0b0011_0000);
+        self.status.set_interrupt();
+        self.program_counter = self.mem_read_u16(IRQ_VECTOR);
+        self.calls.call(self.program_counter, return_to, sp, true);
+    }
+
+    fn stack_push(&mut self, data: u8) {
+        self.mem_write(self.stack.addr(), data);
+        self.stack.incr_ptr();
+    }
+
+    fn stack_pop(&mut self) -> u8 {
+        self.stack.decr_ptr();
+        return self.mem_read(self.stack.addr());
+    }
+
+    fn stack_push_u16(&mut self, data: u16) {
+        self.stack_push((data >> 8) as u8);
+        self.stack_push((data & 0xff) as u8);
+    }
+
+    fn stack_pop_u16(&mut self) -> u16 {
+        let lo = self.stack_pop() as u16;
+        let hi = self.stack_pop() as u16;
+        return (hi << 8) | lo;
     }
 
     fn advance_program_counter(&mut self, op_code_len: u8) {
-        self.program_counter += (op_code_len - 1) as u16;
+        self.program_counter = self.program_counter.wrapping_add((op_code_len - 1) as u16);
+    }
+
+    // indexed reads take an extra cycle when the effective address crosses a
+    // page, as `get_operand_address` found while resolving it
+    fn page_cross_penalty(&self, op_code: &OpCode) -> u8 {
+        match op_code.mnemonic {
+            "ADC" | "AND" | "CMP" | "EOR" | "LDA" | "LDX" | "LDY" | "ORA" | "SBC" => {}
+            _ => return 0,
+        }
+        return self.page_crossed as u8;
     }
 
     fn adc(&mut self, op_code: &OpCode) {
-        todo!();
+        let addr = self.get_operand_address(&op_code.mode);
+        let data = self.mem_read(addr);
+        self.add_to_register_a(data);
+    }
+
+    // the NES 2A03 has no decimal mode, so ADC and SBC are always binary
+    fn add_to_register_a(&mut self, data: u8) {
+        let sum = self.register_a as u16 + data as u16 + self.status.carry() as u16;
+        let result = sum as u8;
+
+        if sum > 0xFF {
+            self.status.set_carry();
+        } else {
+            self.status.clear_carry();
+        }
+
+        // signed overflow: both inputs share a sign the result doesn't have
+        if (self.register_a ^ result) & (data ^ result) & 0b1000_0000 != 0 {
+            self.status.set_overflow();
+        } else {
+            self.status.clear_overflow();
+        }
+
+        self.register_a = result;
+        self.update_zero_and_negative_flags(self.register_a);
     }
 
     fn and(&mut self, op_code: &OpCode) {
@@ -314,7 +611,7 @@ impl CPU {
         return data;
     }
 
-    fn handle_accumulator_asl(&mut self, op_code: &OpCode) -> u8 {
+    fn handle_accumulator_asl(&mut self, _op_code: &OpCode) -> u8 {
         let mut data = self.register_a;
 
         if (data >> 7 & 1) == 1 {
@@ -347,42 +644,81 @@ impl CPU {
         return data;
     }
 
-    fn bcc(&mut self, op_code: &OpCode) {
-        todo!();
+    fn bcc(&mut self, _op_code: &OpCode) {
+        self.branch(self.status.carry() == 0);
     }
 
-    fn bcs(&mut self, op_code: &OpCode) {
-        todo!();
+    fn bcs(&mut self, _op_code: &OpCode) {
+        self.branch(self.status.carry() == 1);
     }
 
-    fn beq(&mut self, op_code: &OpCode) {
-        todo!();
+    fn beq(&mut self, _op_code: &OpCode) {
+        self.branch(self.status.zero() == 1);
     }
 
     fn bit(&mut self, op_code: &OpCode) {
-        todo!();
+        let addr = match op_code.code {
+            0x2C => self.mem_read_u16(self.program_counter),
+            _ => self.get_operand_address(&op_code.mode),
+        };
+        let data = self.mem_read(addr);
+
+        if self.register_a & data == 0 {
+            self.status.set_zero();
+        } else {
+            self.status.clear_zero();
+        }
+
+        if data & 0b0100_0000 != 0 {
+            self.status.set_overflow();
+        } else {
+            self.status.clear_overflow();
+        }
+
+        if data & 0b1000_0000 != 0 {
+            self.status.set_negative();
+        } else {
+            self.status.clear_negative();
+        }
     }
 
-    fn bmi(&mut self, op_code: &OpCode) {
-        todo!();
+    fn bmi(&mut self, _op_code: &OpCode) {
+        self.branch(self.status.negative() == 1);
     }
 
-    fn bne(&mut self, op_code: &OpCode) {
-        todo!();
+    fn bne(&mut self, _op_code: &OpCode) {
+        self.branch(self.status.zero() == 0);
     }
 
-    fn bpl(&mut self, op_code: &OpCode) {
-        todo!();
+    fn bpl(&mut self, _op_code: &OpCode) {
+        self.branch(self.status.negative() == 0);
     }
 
     //fn brk(&mut self, _op_code: &OpCode) { no instructions to carry out
 
-    fn bvc(&mut self, op_code: &OpCode) {
-        todo!();
+    // a taken branch costs one extra cycle, two if it lands on another page
+    fn branch(&mut self, condition: bool) {
+        let next = self.program_counter.wrapping_add(1);
+        if !condition {
+            self.program_counter = next;
+            return;
+        }
+        let offset = self.mem_read(self.program_counter) as i8;
+        let target = next.wrapping_add(offset as u16);
+
+        self.cycles += 1;
+        if next & 0xFF00 != target & 0xFF00 {
+            self.cycles += 1;
+        }
+        self.program_counter = target;
+    }
+
+    fn bvc(&mut self, _op_code: &OpCode) {
+        self.branch(self.status.overflow() == 0);
     }
 
-    fn bvs(&mut self, op_code: &OpCode) {
-        todo!();
+    fn bvs(&mut self, _op_code: &OpCode) {
+        self.branch(self.status.overflow() == 1);
     }
 
     fn clc(&mut self) {
@@ -402,55 +738,28 @@ impl CPU {
     }
 
     fn cmp(&mut self, op_code: &OpCode) {
-        let addr = self.get_operand_address(&op_code.mode);
-        let data = self.mem_read(addr);
-        let register_data = self.register_a;
-
-        if register_data >= data {
-            self.status.set_carry();
-        }
-
-        if register_data == data {
-            self.status.set_zero();
-        }
-
-        if ((register_data.wrapping_sub(data)) >> 1 & 1) == 1 {
-            self.status.set_negative();
-        }
+        self.compare(op_code, self.register_a);
     }
 
     fn cpx(&mut self, op_code: &OpCode) {
-        let addr = self.get_operand_address(&op_code.mode);
-        let data = self.mem_read(addr);
-        let register_data = self.register_x;
-
-        if register_data >= data {
-            self.status.set_carry();
-        }
-
-        if register_data == data {
-            self.status.set_zero();
-        }
-
-        if ((register_data.wrapping_sub(data)) >> 1 & 1) == 1 {
-            self.status.set_negative();
-        }
+        self.compare(op_code, self.register_x);
     }
 
     fn cpy(&mut self, op_code: &OpCode) {
+        self.compare(op_code, self.register_y);
+    }
+
+    // CMP, CPX and CPY: carry for no borrow, Z and N from the difference
+    fn compare(&mut self, op_code: &OpCode, register_data: u8) {
         let addr = self.get_operand_address(&op_code.mode);
         let data = self.mem_read(addr);
-        let register_data = self.register_y;
 
-        if self.register_y >= data {
+        if register_data >= data {
             self.status.set_carry();
-        } else if self.register_y == data {
-            self.status.set_zero();
-        }
-
-        if ((self.register_y - data) >> 1 & 1) == 1 {
-            self.status.set_negative();
+        } else {
+            self.status.clear_carry();
         }
+        self.update_zero_and_negative_flags(register_data.wrapping_sub(data));
     }
 
     fn dec(&mut self, op_code: &OpCode) -> u8 {
@@ -531,7 +840,7 @@ impl CPU {
             AddressingMode::NoneAddressing => {
                 let indirect_ref = if addr & 0x00FF == 0x00FF {
                     let lo = self.mem_read(addr);
-                    let hi = self.mem_read(addr & 0x00FF);
+                    let hi = self.mem_read(addr & 0xFF00);
                     (hi as u16) << 8 | (lo as u16)
                 } else {
                     self.mem_read_u16(addr)
@@ -539,12 +848,23 @@ impl CPU {
 
                 self.program_counter = indirect_ref;
             }
-            _ => panic!(),
+            mode => {
+                self.fault = Some(CpuError::UnsupportedMode {
+                    mode,
+                    pc: self.program_counter.wrapping_sub(1),
+                })
+            }
         }
     }
 
-    fn jsr(&mut self, op_code: &OpCode) {
-        todo!();
+    fn jsr(&mut self, _op_code: &OpCode) {
+        // the pushed return address points at the last byte of the JSR itself
+        let target = self.mem_read_u16(self.program_counter);
+        let sp = self.stack.ptr() as u8;
+        self.calls
+            .call(target, self.program_counter.wrapping_add(2), sp, false);
+        self.stack_push_u16(self.program_counter.wrapping_add(1));
+        self.program_counter = target;
     }
 
     fn lda(&mut self, op_code: &OpCode) {
@@ -586,7 +906,7 @@ impl CPU {
         return data;
     }
 
-    fn handle_accumulator_lsr(&mut self, op_code: &OpCode) -> u8 {
+    fn handle_accumulator_lsr(&mut self, _op_code: &OpCode) -> u8 {
         let mut data = self.register_a;
 
         if (data >> 0 & 1) == 1 {
@@ -629,19 +949,23 @@ impl CPU {
     }
 
     fn pha(&mut self) {
-        todo!();
+        self.stack_push(self.register_a);
     }
 
     fn php(&mut self) {
-        todo!();
+        // software pushes always have B and bit 5 set
+        self.stack_push(self.status.bits() | 0b0011_0000);
     }
 
     fn pla(&mut self) {
-        todo!();
+        self.register_a = self.stack_pop();
+        self.update_zero_and_negative_flags(self.register_a);
     }
 
     fn plp(&mut self) {
-        todo!();
+        let flags = self.stack_pop();
+        self.status
+            .set_bits((flags & 0b1100_1111) | (self.status.bits() & 0b0011_0000));
     }
 
     fn rol(&mut self, op_code: &OpCode) -> u8 {
@@ -659,11 +983,11 @@ impl CPU {
         return data;
     }
 
-    fn handle_accumulator_rol(&mut self, op_code: &OpCode) -> u8 {
+    fn handle_accumulator_rol(&mut self, _op_code: &OpCode) -> u8 {
         let mut data = self.register_a;
         let old_carry = self.status.carry();
 
-        if (data >> 0 & 1) == 1 {
+        if (data >> 7 & 1) == 1 {
             self.status.set_carry()
         } else {
             self.status.clear_carry()
@@ -681,7 +1005,7 @@ impl CPU {
         let mut data = self.mem_read(addr);
         let old_carry = self.status.carry();
 
-        if (data >> 0 & 1) == 1 {
+        if (data >> 7 & 1) == 1 {
             self.status.set_carry()
         } else {
             self.status.clear_carry()
@@ -697,7 +1021,7 @@ impl CPU {
     fn ror(&mut self, op_code: &OpCode) -> u8 {
         let data;
         match op_code.code {
-            0x2A => {
+            0x6A => {
                 data = self.handle_accumulator_ror(op_code);
             }
             _ => {
@@ -709,11 +1033,11 @@ impl CPU {
         return data;
     }
 
-    fn handle_accumulator_ror(&mut self, op_code: &OpCode) -> u8 {
+    fn handle_accumulator_ror(&mut self, _op_code: &OpCode) -> u8 {
         let mut data = self.register_a;
         let old_carry = self.status.carry();
 
-        if (data >> 7 & 1) == 1 {
+        if (data >> 0 & 1) == 1 {
             self.status.set_carry()
         } else {
             self.status.clear_carry()
@@ -735,7 +1059,7 @@ impl CPU {
         let mut data = self.mem_read(addr);
         let old_carry = self.status.carry();
 
-        if (data >> 7 & 1) == 1 {
+        if (data >> 0 & 1) == 1 {
             self.status.set_carry()
         } else {
             self.status.clear_carry()
@@ -753,15 +1077,22 @@ impl CPU {
     }
 
     fn rti(&mut self) {
-        todo!();
+        // B and bit 5 don't exist in the status register itself
+        let flags = self.stack_pop();
+        self.status
+            .set_bits((flags & 0b1100_1111) | (self.status.bits() & 0b0011_0000));
+        self.program_counter = self.stack_pop_u16();
     }
 
     fn rts(&mut self) {
-        todo!();
+        self.program_counter = self.stack_pop_u16().wrapping_add(1);
     }
 
     fn sbc(&mut self, op_code: &OpCode) {
-        todo!();
+        // A - M - !C is A + !M + C in two's complement
+        let addr = self.get_operand_address(&op_code.mode);
+        let data = self.mem_read(addr);
+        self.add_to_register_a(!data);
     }
 
     fn sec(&mut self) {
@@ -803,6 +1134,7 @@ impl CPU {
 
     fn tsx(&mut self) {
         self.register_x = self.stack.ptr() as u8;
+        self.update_zero_and_negative_flags(self.register_x);
     }
 
     fn txa(&mut self) {
@@ -833,7 +1165,7 @@ impl CPU {
         }
     }
 
-    fn get_operand_address(&self, mode: &AddressingMode) -> u16 {
+    fn get_operand_address(&mut self, mode: &AddressingMode) -> u16 {
         match mode {
             AddressingMode::Immediate => self.program_counter,
             AddressingMode::ZeroPage => self.mem_read(self.program_counter) as u16,
@@ -851,17 +1183,19 @@ impl CPU {
             AddressingMode::Absolute_X => {
                 let pos = self.mem_read_u16(self.program_counter);
                 let addr = pos.wrapping_add(self.register_x as u16);
+                self.page_crossed = pos & 0xFF00 != addr & 0xFF00;
                 return addr;
             }
             AddressingMode::Absolute_Y => {
                 let pos = self.mem_read_u16(self.program_counter);
                 let addr = pos.wrapping_add(self.register_y as u16);
+                self.page_crossed = pos & 0xFF00 != addr & 0xFF00;
                 return addr;
             }
             AddressingMode::Indirect => {
                 let base = self.mem_read(self.program_counter);
 
-                let ptr: u8 = base as u8;
+                let ptr: u8 = base;
                 let lo = self.mem_read(ptr as u16);
                 let hi = self.mem_read(ptr.wrapping_add(1) as u16);
 
@@ -874,7 +1208,7 @@ impl CPU {
             AddressingMode::Indirect_X => {
                 let base = self.mem_read(self.program_counter);
 
-                let ptr: u8 = (base as u8).wrapping_add(self.register_x);
+                let ptr: u8 = base.wrapping_add(self.register_x);
                 let lo = self.mem_read(ptr as u16);
                 let hi = self.mem_read(ptr.wrapping_add(1) as u16);
                 return (hi as u16) << 8 | (lo as u16);
@@ -883,13 +1217,19 @@ impl CPU {
                 let base = self.mem_read(self.program_counter);
 
                 let lo = self.mem_read(base as u16);
-                let hi = self.mem_read((base as u8).wrapping_add(1) as u16);
+                let hi = self.mem_read(base.wrapping_add(1) as u16);
                 let deref_base = (hi as u16) << 8 | (lo as u16);
                 let deref = deref_base.wrapping_add(self.register_y as u16);
+                self.page_crossed = deref_base & 0xFF00 != deref & 0xFF00;
                 return deref;
             }
+            // the caller goes on with address 0 and the CPU jams after it
             AddressingMode::NoneAddressing => {
-                panic!("mode {:?} is not supported", mode);
+                self.fault = Some(CpuError::UnsupportedMode {
+                    mode: *mode,
+                    pc: self.program_counter.wrapping_sub(1),
+                });
+                return 0;
             }
         }
     }
@@ -904,7 +1244,7 @@ mod test {
         let mut cpu = CPU::new();
         cpu.load_and_run(vec![0xa9, 0x05, 0x85, 0, 0xC6, 0]);
         assert_eq!(cpu.register_a, 0x05);
-        assert_eq!(cpu.memory[0], 0x04);
+        assert_eq!(cpu.mem_read(0), 0x04);
     }
 
     #[test]
@@ -926,7 +1266,7 @@ mod test {
         let mut cpu = CPU::new();
         cpu.load_and_run(vec![0xa9, 0x05, 0x85, 0, 0xE6, 0]);
         assert_eq!(cpu.register_a, 0x05);
-        assert_eq!(cpu.memory[0], 0x06);
+        assert_eq!(cpu.mem_read(0), 0x06);
     }
 
     #[test]
@@ -948,7 +1288,7 @@ mod test {
         let mut cpu = CPU::new();
         cpu.load_and_run(vec![0xa9, 0x05, 0x29, 0x04, 0x8D, 0x00]);
         assert_eq!(cpu.register_a, 0x04);
-        assert_eq!(cpu.memory[0], 0x04);
+        assert_eq!(cpu.mem_read(0), 0x04);
     }
 
     #[test]
@@ -956,7 +1296,7 @@ mod test {
         let mut cpu = CPU::new();
         cpu.load_and_run(vec![0xa9, 0x05, 0x49, 0x04, 0x8D, 0x01]);
         assert_eq!(cpu.register_a, 0x01);
-        assert_eq!(cpu.memory[1], 0x01);
+        assert_eq!(cpu.mem_read(1), 0x01);
     }
 
     #[test]
@@ -964,7 +1304,7 @@ mod test {
         let mut cpu = CPU::new();
         cpu.load_and_run(vec![0xa9, 0x05, 0x09, 0x10, 0x8D, 0x02]);
         assert_eq!(cpu.register_a, 0x15);
-        assert_eq!(cpu.memory[2], 0x15);
+        assert_eq!(cpu.mem_read(2), 0x15);
     }
 
     #[test]
@@ -1078,7 +1418,7 @@ mod test {
     fn test_lda_sta_zeropage() {
         let mut cpu = CPU::new();
         cpu.load_and_run(vec![0xA9, 0x11, 0x85, 0x00]);
-        assert_eq!(cpu.memory[0], 0x11);
+        assert_eq!(cpu.mem_read(0), 0x11);
     }
 
     #[test]
@@ -1086,7 +1426,7 @@ mod test {
         let mut cpu = CPU::new();
         cpu.load_and_run(vec![0xA2, 0x12, 0x86, 0x00]);
         assert_eq!(cpu.register_x, 0x12);
-        assert_eq!(cpu.memory[0], 0x12);
+        assert_eq!(cpu.mem_read(0), 0x12);
     }
 
     #[test]
@@ -1094,7 +1434,7 @@ mod test {
         let mut cpu = CPU::new();
         cpu.load_and_run(vec![0xA0, 0x13, 0x84, 0x00]);
         assert_eq!(cpu.register_y, 0x13);
-        assert_eq!(cpu.memory[0], 0x13);
+        assert_eq!(cpu.mem_read(0), 0x13);
     }
 
     #[test]
@@ -1160,14 +1500,13 @@ mod test {
         assert_eq!(cpu.status.negative(), 0);
     }
 
-    //#[test]
+    #[test]
     fn test_cpy_immediate() {
         let mut cpu = CPU::new();
         cpu.load_and_run(vec![0xA0, 0x02, 0xC0, 0x01]);
 
-        // let y_7th_bit = cpu.register_y >> 7 & 1; TODO determine if this is true
-        // see http://www.6502.org/tutorials/compare_instructions.html
-        // if so, add it to every test that follows in this scope
+        // N is bit 7 of Y - data, see
+        // http://www.6502.org/tutorials/compare_instructions.html
         assert_eq!(cpu.status.carry(), 1);
         assert_eq!(cpu.status.zero(), 0);
         assert_eq!(cpu.status.negative(), 0);
@@ -1198,6 +1537,81 @@ mod test {
         assert_eq!(cpu.program_counter, 32768);
     }
 
+    #[test]
+    fn test_jam_is_an_error_not_a_panic() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xA9, 0x01, 0x02, 0xA9, 0x02]);
+        assert!(cpu.jammed);
+        assert_eq!(cpu.register_a, 0x01);
+        assert_eq!(
+            cpu.check(),
+            Err(CpuError::Jammed {
+                opcode: 0x02,
+                pc: 0x8002
+            })
+        );
+        assert!(!cpu.step());
+        assert_eq!(cpu.program_counter, 0x8002);
+        cpu.reset();
+        assert_eq!(cpu.check(), Ok(()));
+    }
+
+    #[test]
+    fn test_soft_reset_keeps_registers() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xA0, 0x13, 0x84, 0x00]);
+        let sp = cpu.stack.ptr();
+        cpu.soft_reset();
+        assert_eq!(cpu.register_y, 0x13);
+        assert_eq!(cpu.program_counter, 0x8000);
+        assert_eq!(cpu.stack.ptr(), (sp as u8).wrapping_sub(3) as u16);
+        assert_eq!(cpu.status.interrupt(), 1);
+    }
+
+    #[test]
+    fn test_brk_as_interrupt() {
+        let mut cpu = CPU::new();
+        // BRK, padding, LDX #$05; the IRQ handler is a bare RTI
+        cpu.load(vec![0x00, 0xEA, 0xA2, 0x05]);
+        cpu.mem_write_u16(IRQ_VECTOR, 0x9000);
+        cpu.mem_write(0x9000, 0x40);
+        cpu.reset();
+        cpu.halt_on_brk = false;
+
+        assert!(cpu.step());
+        assert_eq!(cpu.program_counter, 0x9000);
+        assert_eq!(cpu.status.interrupt(), 1);
+        assert_eq!(cpu.mem_read(0x01FF), 0x80);
+        assert_eq!(cpu.mem_read(0x01FE), 0x02);
+        assert_eq!(cpu.mem_read(0x01FD) & 0b0011_0000, 0b0011_0000);
+        assert_eq!(cpu.cycles, 7);
+
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.register_x, 0x05);
+    }
+
+    #[test]
+    fn test_nmi_at_vblank() {
+        let mut cpu = CPU::new();
+        // the main loop spins on JMP, the handler sets X
+        cpu.load(vec![0x4C, 0x00, 0x80]);
+        cpu.mem_write_u16(NMI_VECTOR, 0x9000);
+        cpu.mem_write(0x9000, 0xE8); // INX
+        cpu.reset();
+        let mut mapper = crate::ppu::test::chr_ram_mapper();
+        cpu.bus.ppu.write_register(0x2000, 0x80, mapper.as_mut());
+
+        // vblank begins after 241 scanlines of 341 dots, three dots per cycle
+        while cpu.register_x == 0 {
+            cpu.step();
+            assert!(cpu.cycles < 30_000);
+        }
+        assert!(cpu.cycles * 3 > 241 * 341);
+        assert_eq!(cpu.program_counter, 0x9001);
+        assert_eq!(cpu.status.interrupt(), 1);
+    }
+
     #[test]
     fn test_status_flags() {
         let mut cpu = CPU::new();
@@ -1217,4 +1631,268 @@ mod test {
         cpu.clv();
         assert_eq!(cpu.status.overflow(), 0);
     }
+
+    #[test]
+    fn test_adc_carry_and_overflow() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xA9, 0x50, 0x69, 0x50]); // $50 + $50
+        assert_eq!(cpu.register_a, 0xA0);
+        assert_eq!(cpu.status.carry(), 0);
+        assert_eq!(cpu.status.overflow(), 1);
+        assert_eq!(cpu.status.negative(), 1);
+
+        cpu.load_and_run(vec![0xA9, 0xFF, 0x38, 0x69, 0x00]); // $FF + $00 + carry
+        assert_eq!(cpu.register_a, 0x00);
+        assert_eq!(cpu.status.carry(), 1);
+        assert_eq!(cpu.status.zero(), 1);
+        assert_eq!(cpu.status.overflow(), 0);
+    }
+
+    #[test]
+    fn test_sbc_borrow() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xA9, 0x05, 0x38, 0xE9, 0x03]); // 5 - 3
+        assert_eq!(cpu.register_a, 0x02);
+        assert_eq!(cpu.status.carry(), 1);
+
+        cpu.load_and_run(vec![0xA9, 0x03, 0x38, 0xE9, 0x05]); // 3 - 5 borrows
+        assert_eq!(cpu.register_a, 0xFE);
+        assert_eq!(cpu.status.carry(), 0);
+
+        cpu.load_and_run(vec![0xA9, 0x80, 0x38, 0xE9, 0x01]); // -128 - 1 overflows
+        assert_eq!(cpu.register_a, 0x7F);
+        assert_eq!(cpu.status.overflow(), 1);
+    }
+
+    #[test]
+    fn test_branch_loop() {
+        let mut cpu = CPU::new();
+        // LDX #$03, LDY #$00, loop: INY, DEX, BNE loop
+        cpu.load_and_run(vec![0xA2, 0x03, 0xA0, 0x00, 0xC8, 0xCA, 0xD0, 0xFC]);
+        assert_eq!(cpu.register_y, 3);
+        assert_eq!(cpu.register_x, 0);
+    }
+
+    #[test]
+    fn test_branch_cycles() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0x18, 0x90, 0x00, 0x38, 0x90, 0x00]); // CLC, BCC +0, SEC, BCC +0
+        cpu.reset();
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.cycles, 2 + 3); // taken, same page
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.cycles, 5 + 2 + 2); // not taken
+
+        for (i, byte) in [0x90, 0x7F].iter().enumerate() {
+            cpu.mem_write(0x80F0 + i as u16, *byte); // BCC +127 from $80F2 lands on $8171
+        }
+        cpu.reset();
+        cpu.program_counter = 0x80F0;
+        cpu.cycles = 0;
+        cpu.step();
+        assert_eq!(cpu.program_counter, 0x8171);
+        assert_eq!(cpu.cycles, 4);
+    }
+
+    #[test]
+    fn test_indexed_read_cycles() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x20, 0xF0);
+        cpu.mem_write(0x21, 0x10);
+        // LDX #$20, LDA $10F0,X, LDA $1000,X, STA $10F0,X, LDY #$20, LDA ($20),Y
+        cpu.load(vec![
+            0xA2, 0x20, 0xBD, 0xF0, 0x10, 0xBD, 0x00, 0x10, 0x9D, 0xF0, 0x10, 0xA0, 0x20, 0xB1,
+            0x20,
+        ]);
+        cpu.reset();
+        cpu.cycles = 0;
+        let mut taken = Vec::new();
+        for _ in 0..6 {
+            let before = cpu.cycles;
+            cpu.step();
+            taken.push(cpu.cycles - before);
+        }
+        // a read crossing a page pays a cycle, a store never does
+        assert_eq!(taken, vec![2, 5, 4, 5, 2, 6]);
+    }
+
+    #[test]
+    fn test_landing_on_the_operand() {
+        // BCC -1 and JMP $8005 both go to the byte after their opcode
+        let mut cpu = CPU::new();
+        cpu.load(vec![0x18, 0x90, 0xFF, 0xEA, 0x4C, 0x05, 0x80]);
+        cpu.reset();
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.program_counter, 0x8002);
+        cpu.program_counter = 0x8004;
+        cpu.step();
+        assert_eq!(cpu.program_counter, 0x8005);
+    }
+
+    #[test]
+    fn test_jmp_indirect_page_wrap() {
+        // JMP ($10FF) takes its high byte from $1000, not $1100
+        let mut cpu = CPU::new();
+        cpu.load(vec![0x6C, 0xFF, 0x10]);
+        cpu.mem_write(0x10FF, 0x34);
+        cpu.mem_write(0x1000, 0x12);
+        cpu.mem_write(0x1100, 0x56);
+        cpu.reset();
+        cpu.step();
+        assert_eq!(cpu.program_counter, 0x1234);
+    }
+
+    #[test]
+    fn test_every_branch_condition() {
+        // each program sets a flag, branches over LDA #$01 and loads $02 instead
+        for (setup, branch) in [
+            (0x38, 0xB0), // SEC, BCS
+            (0x18, 0x90), // CLC, BCC
+            (0xB8, 0x50), // CLV, BVC
+        ] {
+            let mut cpu = CPU::new();
+            cpu.load_and_run(vec![setup, branch, 0x02, 0xA9, 0x01, 0xA9, 0x02]);
+            assert_eq!(cpu.register_a, 0x02, "branch {:#04x}", branch);
+        }
+        for (load, branch) in [
+            (0x00, 0xF0), // BEQ
+            (0x01, 0xD0), // BNE
+            (0x80, 0x30), // BMI
+            (0x01, 0x10), // BPL
+        ] {
+            let mut cpu = CPU::new();
+            cpu.load_and_run(vec![0xA2, load, branch, 0x02, 0xA9, 0x01, 0xA9, 0x02]);
+            assert_eq!(cpu.register_a, 0x02, "branch {:#04x}", branch);
+        }
+        let mut cpu = CPU::new();
+        // LDA #$40, ADC #$40 sets V, then BVS
+        cpu.load_and_run(vec![
+            0xA9, 0x40, 0x69, 0x40, 0x70, 0x02, 0xA9, 0x01, 0xA9, 0x02,
+        ]);
+        assert_eq!(cpu.register_a, 0x02);
+    }
+
+    #[test]
+    fn test_bit() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0b1100_0000);
+        cpu.mem_write(0x1234, 0b0000_0001);
+        cpu.load_and_run(vec![0xA9, 0x01, 0x24, 0x10]);
+        assert_eq!(cpu.status.zero(), 1);
+        assert_eq!(cpu.status.overflow(), 1);
+        assert_eq!(cpu.status.negative(), 1);
+
+        cpu.load_and_run(vec![0xA9, 0x01, 0x2C, 0x34, 0x12]);
+        assert_eq!(cpu.status.zero(), 0);
+        assert_eq!(cpu.status.overflow(), 0);
+        assert_eq!(cpu.status.negative(), 0);
+    }
+
+    #[test]
+    fn test_jsr_rts() {
+        let mut cpu = CPU::new();
+        // JSR $8006, LDX #$07, BRK, sub: LDA #$05, RTS
+        cpu.load_and_run(vec![0x20, 0x06, 0x80, 0xA2, 0x07, 0x00, 0xA9, 0x05, 0x60]);
+        assert_eq!(cpu.register_a, 0x05);
+        assert_eq!(cpu.register_x, 0x07);
+        assert_eq!(cpu.mem_read(0x01FF), 0x80);
+        assert_eq!(cpu.mem_read(0x01FE), 0x02);
+        assert!(cpu.stack.is_empty());
+    }
+
+    #[test]
+    fn test_stack_push_pull() {
+        let mut cpu = CPU::new();
+        // LDA #$80, PHA, LDA #$00, PLA
+        cpu.load_and_run(vec![0xA9, 0x80, 0x48, 0xA9, 0x00, 0x68]);
+        assert_eq!(cpu.register_a, 0x80);
+        assert_eq!(cpu.status.negative(), 1);
+        assert!(cpu.stack.is_empty());
+
+        // SEC, PHP, CLC, PLP
+        cpu.load_and_run(vec![0x38, 0x08, 0x18, 0x28]);
+        assert_eq!(cpu.mem_read(0x01FF) & 0b0011_0001, 0b0011_0001);
+        assert_eq!(cpu.status.carry(), 1);
+    }
+
+    #[test]
+    fn test_mapper_irq() {
+        use crate::cartridge::test::{create_rom, TestRom};
+        use crate::cartridge::Rom;
+
+        // 32KB of PRG for MMC3: main program at $E000, IRQ handler at $E100
+        let mut prg_rom = vec![0xEA; 0x8000];
+        let program = [
+            0x58, // CLI
+            0xA9, 0x00, // LDA #$00
+            0x8D, 0x00, 0xC0, // STA $C000 (latch)
+            0x8D, 0x01, 0xC0, // STA $C001 (reload)
+            0x8D, 0x01, 0xE0, // STA $E001 (enable)
+        ];
+        prg_rom[0x6000..0x6000 + program.len()].copy_from_slice(&program);
+        let handler = [
+            0xA2, 0x42, // LDX #$42
+            0x8D, 0x00, 0xE0, // STA $E000 (acknowledge)
+            0x40, // RTI
+        ];
+        prg_rom[0x6100..0x6100 + handler.len()].copy_from_slice(&handler);
+        prg_rom[0x7FFC..].copy_from_slice(&[0x00, 0xE0, 0x00, 0xE1]);
+
+        let raw = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x40, 00, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            prg_rom,
+            chr_rom: vec![0; 0x2000],
+        });
+        let mut cpu = CPU::with_bus(Bus::from_rom(Rom::new(&raw).unwrap()).unwrap());
+        cpu.reset();
+        for _ in 0..5 {
+            cpu.step();
+        }
+        assert_eq!(cpu.program_counter, 0xE00C);
+
+        // an A12 rise clocks the counter to zero and raises the IRQ line
+        cpu.bus.mapper_mut().unwrap().ppu_bus(0x1000, 100);
+        assert!(cpu.bus.irq_pending());
+        let pushed_flags = cpu.status.bits();
+        cpu.step(); // interrupt, then LDX in the handler
+        assert_eq!(cpu.register_x, 0x42);
+        assert_eq!(cpu.status.interrupt(), 1);
+        assert_eq!(cpu.mem_read(0x01FF), 0xE0);
+        assert_eq!(cpu.mem_read(0x01FE), 0x0C);
+        assert_eq!(
+            cpu.mem_read(0x01FD),
+            (pushed_flags & 0b1110_1111) | 0b0010_0000
+        );
+
+        cpu.step(); // acknowledge
+        assert!(!cpu.bus.irq_pending());
+        cpu.step(); // RTI
+        assert_eq!(cpu.program_counter, 0xE00C);
+        assert_eq!(cpu.status.interrupt(), 0);
+        assert!(cpu.stack.is_empty());
+    }
+
+    #[test]
+    fn test_power_on_masks_irqs() {
+        use crate::cartridge::test::test_rom;
+        use crate::cartridge::Rom;
+
+        // INC $10, JMP $8000 with no SEI, while the APU's frame IRQ goes off
+        let mut raw = test_rom(0, 1, 1);
+        raw[16..21].copy_from_slice(&[0xE6, 0x10, 0x4C, 0x00, 0x80]);
+        raw[16 + 0x3FFC..16 + 0x4000].copy_from_slice(&[0x00, 0x80, 0xCE, 0xFA]);
+        let mut cpu = CPU::with_bus(Bus::from_rom(Rom::new(&raw).unwrap()).unwrap());
+        cpu.reset();
+        assert_eq!(cpu.status.interrupt(), 1);
+        while cpu.cycles < 2 * 29_781 {
+            cpu.step();
+        }
+        assert!(cpu.bus.irq_pending());
+        assert!((0x8000..0x8005).contains(&cpu.program_counter));
+    }
 }
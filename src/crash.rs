@@ -0,0 +1,97 @@
+use crate::debugger::registers;
+use crate::nes::Nes;
+use crate::video;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/* Crash reports
+
+   When the CPU jams, on KIL or an opcode it doesn't know, or the emulator
+   itself panics, the session writes what it can about the machine to a
+   text file in the capture directory for a bug report: why, the frame and
+   PPU position, the registers, the `trace` history of the instructions
+   that led there and a hex dump of work RAM and any cartridge RAM. A jam
+   is reported once and leaves the picture frozen, as on the console; a
+   panic ends the session.
+*/
+
+pub fn report(nes: &Nes, title: &str, reason: &str) -> String {
+    let cpu = &nes.cpu;
+    let ppu = &cpu.bus.ppu;
+    let mut text = format!(
+        "rustynes crash report\n\ngame      {} (mapper {})\nreason    {}\nframe     {}, scanline {}, dot {}\n\n{}\n",
+        title,
+        nes.rom().mapper,
+        reason,
+        nes.frame_count(),
+        ppu.scanline(),
+        ppu.dot(),
+        registers(cpu)
+    );
+    text += &format!(
+        "\nlast {} instructions, oldest first\n",
+        cpu.history.entries().count()
+    );
+    for entry in cpu.history.entries() {
+        text += &format!("{}\n", entry);
+    }
+    text += "\nwork RAM\n";
+    text += &hex_dump(cpu.bus.ram(), 0x0000);
+    if let Some(prg_ram) = cpu.bus.mapper().and_then(|mapper| mapper.prg_ram()) {
+        text += "\ncartridge RAM\n";
+        text += &hex_dump(prg_ram, 0x6000);
+    }
+    return text;
+}
+
+// 16 bytes a line from `base`
+fn hex_dump(bytes: &[u8], base: usize) -> String {
+    let mut text = String::new();
+    for (row, chunk) in bytes.chunks(16).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|b| format!("{:02X}", b)).collect();
+        text += &format!("{:04X}  {}\n", base + row * 16, hex.join(" "));
+    }
+    return text;
+}
+
+// into `dir`, named after the game and frame like a screenshot
+pub fn write_report(dir: &Path, nes: &Nes, title: &str, reason: &str) -> Result<PathBuf, String> {
+    let name = video::screenshot_name(title, nes.frame_count());
+    let path = dir.join(Path::new(&name).with_extension("crash.txt"));
+    fs::write(&path, report(nes, title, reason))
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    return Ok(path);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+    use crate::cartridge::Rom;
+
+    #[test]
+    fn test_jam_report() {
+        // INX, INX, then KIL
+        let mut raw = test_rom(0, 1, 1);
+        raw[16..19].copy_from_slice(&[0xE8, 0xE8, 0x02]);
+        raw[16 + 0x3FFC..16 + 0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        let mut nes = Nes::new(Rom::new(&raw).unwrap()).unwrap();
+        nes.cpu.bus.ram_mut()[0x0010] = 0xAB;
+        nes.run_frame();
+        assert!(nes.cpu.jammed);
+        assert_eq!(nes.cpu.program_counter, 0x8002);
+        assert_eq!(nes.cpu.register_x, 2);
+
+        let dir = std::env::temp_dir().join(format!("rustynes-crash-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = write_report(&dir, &nes, "Test", "the CPU jammed").unwrap();
+        assert_eq!(path.file_name().unwrap(), "Test-000001.crash.txt");
+        let text = fs::read_to_string(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(text.contains("reason    the CPU jammed\n"));
+        assert!(text.contains("\nPC:8002 A:00 X:02"));
+        assert!(text.contains("last 3 instructions, oldest first\n8000  E8        INX"));
+        assert!(text.contains("\n8002  02        .byte $02 "));
+        assert!(text.contains("\n0010  AB 00"));
+    }
+}
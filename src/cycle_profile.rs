@@ -0,0 +1,274 @@
+use crate::cpu::CPU;
+use crate::debugger::disassemble;
+use std::collections::HashMap;
+
+// rows in each table of the report
+const REPORT_ROWS: usize = 20;
+
+const JSR: u8 = 0x20;
+const RTS: u8 = 0x60;
+const RTI: u8 = 0x40;
+const BRK: u8 = 0x00;
+
+/* Cycle profiler
+
+   Where a game spends its CPU time, for homebrew developers hunting hot
+   loops. Every instruction's cycles, DMA stalls included, go to its
+   address, and to the subroutine it ran in: a JSR enters the subroutine at
+   its target and RTS leaves it, an interrupt or BRK enters its handler and
+   RTI leaves that. What runs outside any call is "main". An RTS with
+   nothing entered to return from, as in a jump table that pushes its
+   address and returns to it, is taken as a jump and leaves the calls as
+   they were.
+
+   Calls are kept as a tree, one node for each path of calls that
+   happened, so the report has each subroutine's own cycles and its total
+   with everything it called, and the folded output is one line a path,
+   `main;$C123;$C456 1234`, which flamegraph.pl and inferno turn into a
+   flame graph. `--cycle-profile FILE` writes the report at the end of the
+   session and `--flamegraph FILE` the folded lines.
+*/
+
+#[derive(Debug, Clone)]
+struct Node {
+    parent: usize,
+    addr: Option<u16>, // None for main
+    cycles: u64,       // in it, not in what it called
+    calls: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct CycleProfiler {
+    nodes: Vec<Node>,
+    children: HashMap<(usize, u16), usize>,
+    current: usize,
+    by_address: HashMap<u16, u64>,
+    running: Option<(u16, u8)>, // the instruction being run and its opcode
+}
+
+impl Default for CycleProfiler {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl CycleProfiler {
+    pub fn new() -> Self {
+        let main = Node {
+            parent: 0,
+            addr: None,
+            cycles: 0,
+            calls: 1,
+        };
+        return Self {
+            nodes: vec![main],
+            children: HashMap::new(),
+            current: 0,
+            by_address: HashMap::new(),
+            running: None,
+        };
+    }
+
+    // an interrupt taken before the instruction `begin` is told about
+    pub fn interrupt(&mut self, handler: u16) {
+        self.enter(handler);
+    }
+
+    pub fn begin(&mut self, pc: u16, code: u8) {
+        self.running = Some((pc, code));
+    }
+
+    // after the instruction, with what it took and where the PC went
+    pub fn end(&mut self, cycles: u64, pc: u16) {
+        let Some((addr, code)) = self.running.take() else {
+            return;
+        };
+        *self.by_address.entry(addr).or_default() += cycles;
+        self.nodes[self.current].cycles += cycles;
+        match code {
+            JSR | BRK => self.enter(pc),
+            RTS | RTI if self.current != 0 => self.current = self.nodes[self.current].parent,
+            _ => {}
+        }
+    }
+
+    fn enter(&mut self, addr: u16) {
+        let next = self.nodes.len();
+        let child = *self.children.entry((self.current, addr)).or_insert(next);
+        if child == next {
+            self.nodes.push(Node {
+                parent: self.current,
+                addr: Some(addr),
+                cycles: 0,
+                calls: 0,
+            });
+        }
+        self.nodes[child].calls += 1;
+        self.current = child;
+    }
+
+    pub fn total(&self) -> u64 {
+        return self.nodes.iter().map(|node| node.cycles).sum();
+    }
+
+    // each subroutine's own cycles, its total with what it called, and how
+    // often it was called, the biggest totals first
+    pub fn subroutines(&self) -> Vec<(Option<u16>, u64, u64, u64)> {
+        // children always come after their parents
+        let mut inclusive: Vec<u64> = self.nodes.iter().map(|node| node.cycles).collect();
+        for id in (1..self.nodes.len()).rev() {
+            inclusive[self.nodes[id].parent] += inclusive[id];
+        }
+        let mut totals: HashMap<Option<u16>, (u64, u64, u64)> = HashMap::new();
+        for (id, node) in self.nodes.iter().enumerate() {
+            let entry = totals.entry(node.addr).or_default();
+            entry.0 += node.cycles;
+            entry.2 += node.calls;
+            // recursion would count the inner calls twice over
+            if !self.has_ancestor(id, node.addr) {
+                entry.1 += inclusive[id];
+            }
+        }
+        let mut rows: Vec<(Option<u16>, u64, u64, u64)> = totals
+            .into_iter()
+            .map(|(addr, (own, total, calls))| (addr, own, total, calls))
+            .collect();
+        rows.sort_by_key(|&(addr, _, total, _)| (std::cmp::Reverse(total), addr));
+        return rows;
+    }
+
+    fn has_ancestor(&self, mut id: usize, addr: Option<u16>) -> bool {
+        while id != 0 {
+            id = self.nodes[id].parent;
+            if self.nodes[id].addr == addr {
+                return true;
+            }
+        }
+        return false;
+    }
+
+    // the addresses that took the most cycles, most first
+    pub fn addresses(&self) -> Vec<(u16, u64)> {
+        let mut rows: Vec<(u16, u64)> = self.by_address.iter().map(|(&a, &c)| (a, c)).collect();
+        rows.sort_by_key(|&(addr, cycles)| (std::cmp::Reverse(cycles), addr));
+        return rows;
+    }
+
+    // with the instructions as they are in `cpu`'s memory now
+    pub fn report(&self, cpu: &CPU) -> String {
+        let total = self.total().max(1);
+        let percent = |cycles: u64| cycles as f64 * 100.0 / total as f64;
+        let mut text = format!("{} cycles\n\n", self.total());
+        text += &format!(
+            "{:<12}{:>18}{:>18}{:>9}\n",
+            "subroutine", "total", "own", "calls"
+        );
+        for (addr, own, inclusive, calls) in self.subroutines().into_iter().take(REPORT_ROWS) {
+            text += &format!(
+                "{:<12}{:>11} {:5.1}%{:>11} {:5.1}%{:>9}\n",
+                name(addr),
+                inclusive,
+                percent(inclusive),
+                own,
+                percent(own),
+                calls
+            );
+        }
+        text += &format!("\n{:<30}{:>18}\n", "address", "cycles");
+        for (addr, cycles) in self.addresses().into_iter().take(REPORT_ROWS) {
+            let (line, _) = disassemble(cpu, addr);
+            text += &format!("{:<30}{:>11} {:5.1}%\n", line, cycles, percent(cycles));
+        }
+        return text;
+    }
+
+    // one line a call path with the cycles spent in it, for flame graphs
+    pub fn folded(&self) -> String {
+        let mut text = String::new();
+        for (id, node) in self.nodes.iter().enumerate() {
+            if node.cycles == 0 {
+                continue;
+            }
+            let mut path = vec![name(node.addr)];
+            let mut at = id;
+            while at != 0 {
+                at = self.nodes[at].parent;
+                path.push(name(self.nodes[at].addr));
+            }
+            path.reverse();
+            text += &format!("{} {}\n", path.join(";"), node.cycles);
+        }
+        return text;
+    }
+}
+
+fn name(addr: Option<u16>) -> String {
+    return match addr {
+        Some(addr) => format!("${:04X}", addr),
+        None => "main".to_string(),
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm;
+    use crate::cpu::Mem;
+
+    #[test]
+    fn test_cycles_by_subroutine() {
+        let source = "
+            JSR outer
+            JSR leaf
+            BRK
+            outer: JSR leaf
+            RTS
+            leaf: NOP
+            RTS";
+        let mut cpu = CPU::new();
+        for (i, byte) in asm::assemble_at(source, 0x8000)
+            .unwrap()
+            .into_iter()
+            .enumerate()
+        {
+            cpu.mem_write(0x8000 + i as u16, byte);
+        }
+        cpu.mem_write_u16(0xFFFC, 0x8000);
+        cpu.reset();
+        cpu.profiler = Some(Box::default());
+        cpu.run();
+        let profiler = cpu.profiler.take().unwrap();
+
+        // JSR and RTS take 6 cycles, NOP 2; the BRK that stops `run` isn't
+        // counted
+        assert_eq!(profiler.total(), 40);
+        let rows = profiler.subroutines();
+        assert_eq!(rows[0], (None, 12, 40, 1));
+        assert_eq!(rows[1], (Some(0x8007), 12, 20, 1));
+        assert_eq!(rows[2], (Some(0x800B), 16, 16, 2));
+        assert_eq!(profiler.addresses()[0], (0x800C, 12));
+
+        let folded = profiler.folded();
+        assert!(folded.contains("main 12\n"));
+        assert!(folded.contains("main;$8007 12\n"));
+        assert!(folded.contains("main;$8007;$800B 8\n"));
+        assert!(folded.contains("main;$800B 8\n"));
+        let report = profiler.report(&cpu);
+        assert!(report.starts_with("40 cycles\n"));
+        assert!(report.contains("\n$8007                20  50.0%         12  30.0%        1\n"));
+        assert!(report.contains("\n800C  60        RTS                    12  30.0%\n"));
+    }
+
+    #[test]
+    fn test_unmatched_return_is_a_jump() {
+        let mut profiler = CycleProfiler::new();
+        profiler.begin(0x8000, RTS);
+        profiler.end(6, 0x9000);
+        profiler.interrupt(0xC000);
+        profiler.begin(0xC000, RTI);
+        profiler.end(6, 0x9000);
+        let rows = profiler.subroutines();
+        assert_eq!(rows[0], (None, 6, 12, 1));
+        assert_eq!(rows[1], (Some(0xC000), 6, 6, 1));
+    }
+}
@@ -0,0 +1,178 @@
+use std::fmt;
+
+// accesses kept between two instructions; one instruction makes a few, an
+// OAM DMA 256
+const MAX_ACCESSES: usize = 0x200;
+
+/* Access breakpoints
+
+   Reads and writes of addresses the debugger was asked to stop on, seen
+   by the bus as it dispatches them, so every addressing mode, DMA and
+   the mirrors count: `break write 2006` stops on STA $2006, STX $3FFE
+   and a write through a pointer alike, and `break write 4016` on the
+   controller strobe. A single address is matched by what it mirrors, the
+   PPU registers every 8 bytes up to $3FFF and RAM every $800 bytes up to
+   $1FFF; the ends of a range are taken as they are.
+
+   The debugger stops ahead of the instruction after the one that made
+   the access, the soonest it can, and says what it was, with where the
+   PPU was at the time: `$2006 = $21 written at scanline 17, dot 90`.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+    Any,
+}
+
+impl AccessKind {
+    pub fn parse(name: &str) -> Result<AccessKind, String> {
+        match name {
+            "read" => return Ok(AccessKind::Read),
+            "write" => return Ok(AccessKind::Write),
+            "access" => return Ok(AccessKind::Any),
+            _ => return Err(format!("unknown access name {}", name)),
+        }
+    }
+
+    fn matches(self, write: bool) -> bool {
+        return match self {
+            AccessKind::Read => !write,
+            AccessKind::Write => write,
+            AccessKind::Any => true,
+        };
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Access {
+    pub addr: u16,
+    pub value: u8,
+    pub write: bool,
+    pub scanline: u16,
+    pub dot: u16,
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let how = if self.write { "written" } else { "read" };
+        return write!(
+            f,
+            "${:04X} = ${:02X} {} at scanline {}, dot {}",
+            self.addr, self.value, how, self.scanline, self.dot
+        );
+    }
+}
+
+// one address or range of them to stop on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccessRange {
+    pub kind: AccessKind,
+    pub from: u16,
+    pub to: u16,
+}
+
+impl AccessRange {
+    // `2006` or `2000-2007`
+    pub fn parse(
+        kind: AccessKind,
+        text: &str,
+        addr: impl Fn(&str) -> Result<u16, String>,
+    ) -> Result<Self, String> {
+        let (from, to) = match text.split_once('-') {
+            Some((from, to)) => (addr(from)?, addr(to)?),
+            None => {
+                let at = mirrored(addr(text)?);
+                (at, at)
+            }
+        };
+        if from > to {
+            return Err(format!("{} ends before it starts", text));
+        }
+        return Ok(Self { kind, from, to });
+    }
+
+    pub fn matches(&self, access: &Access) -> bool {
+        let inside = |addr: u16| (self.from..=self.to).contains(&addr);
+        return self.kind.matches(access.write)
+            && (inside(access.addr) || inside(mirrored(access.addr)));
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AccessWatch {
+    ranges: Vec<AccessRange>,
+    accesses: Vec<Access>,
+}
+
+impl AccessWatch {
+    pub fn new(ranges: Vec<AccessRange>) -> Self {
+        return Self {
+            ranges,
+            accesses: Vec::new(),
+        };
+    }
+
+    // from the bus, every access the CPU and DMA make
+    pub fn record(&mut self, access: Access) {
+        if self.accesses.len() < MAX_ACCESSES && self.ranges.iter().any(|r| r.matches(&access)) {
+            self.accesses.push(access);
+        }
+    }
+
+    // the accesses since the last call that a range matched
+    pub fn take(&mut self) -> Vec<Access> {
+        return std::mem::take(&mut self.accesses);
+    }
+}
+
+// the address `addr` is a mirror of, on the console's bus
+fn mirrored(addr: u16) -> u16 {
+    return match addr {
+        0x0000..=0x1FFF => addr & 0x07FF,
+        0x2000..=0x3FFF => 0x2000 | (addr & 0x0007),
+        _ => addr,
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ranges_and_mirrors() {
+        let hex = |text: &str| u16::from_str_radix(text, 16).map_err(|e| e.to_string());
+        let access = |addr, write| Access {
+            addr,
+            value: 0x21,
+            write,
+            scanline: 17,
+            dot: 90,
+        };
+        let scroll = AccessRange::parse(AccessKind::Write, "3ffd", hex).unwrap();
+        assert_eq!((scroll.from, scroll.to), (0x2005, 0x2005));
+        assert!(scroll.matches(&access(0x2005, true)));
+        assert!(scroll.matches(&access(0x2F0D, true)));
+        assert!(!scroll.matches(&access(0x2005, false)));
+        assert!(!scroll.matches(&access(0x2006, true)));
+
+        let ram = AccessRange::parse(AccessKind::Any, "0300-03ff", hex).unwrap();
+        assert!(ram.matches(&access(0x0B10, false)));
+        assert!(!ram.matches(&access(0x0400, true)));
+        assert!(AccessRange::parse(AccessKind::Any, "0400-0300", hex).is_err());
+        assert!(AccessKind::parse("poke").is_err());
+
+        let mut watch = AccessWatch::new(vec![scroll, ram]);
+        watch.record(access(0x2006, true));
+        watch.record(access(0x2005, true));
+        watch.record(access(0x0310, false));
+        let taken = watch.take();
+        assert_eq!(taken.len(), 2);
+        assert_eq!(
+            taken[0].to_string(),
+            "$2005 = $21 written at scanline 17, dot 90"
+        );
+        assert!(watch.take().is_empty());
+    }
+}
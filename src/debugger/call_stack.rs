@@ -0,0 +1,77 @@
+/* Call stack
+
+   A shadow of the subroutines the CPU is in, kept beside the real stack
+   since the 6502's own holds only return addresses mixed in with whatever
+   else was pushed. A JSR, BRK or interrupt adds a frame with where it went
+   and where it will come back to, and the stack pointer before it pushed
+   anything. Frames go once the stack pointer is back up to where theirs
+   was, by an RTS or RTI or any other way: games pull return addresses to
+   abandon calls, push addresses to RTS through jump tables and reset the
+   stack with TXS, and none of that leaves stale frames or pops live ones.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    pub entry: u16,     // where it went
+    pub return_to: u16, // where it carries on once it returns
+    pub interrupt: bool,
+    sp: u8, // the stack pointer before the call
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CallStack {
+    frames: Vec<Frame>,
+}
+
+impl CallStack {
+    pub fn call(&mut self, entry: u16, return_to: u16, sp: u8, interrupt: bool) {
+        self.frames.push(Frame {
+            entry,
+            return_to,
+            interrupt,
+            sp,
+        });
+    }
+
+    // after each instruction, with where the stack pointer is now
+    pub fn unwind(&mut self, sp: u8) {
+        while self.frames.last().is_some_and(|frame| frame.sp <= sp) {
+            self.frames.pop();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    // the outermost call first
+    pub fn frames(&self) -> &[Frame] {
+        return &self.frames;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_frames_go_with_the_stack() {
+        let mut calls = CallStack::default();
+        calls.call(0x8100, 0x8003, 0xFD, false);
+        calls.unwind(0xFB);
+        calls.call(0x8200, 0x8103, 0xFB, false);
+        calls.unwind(0xF9);
+        // a jump table's push and RTS leaves both
+        calls.unwind(0xF7);
+        calls.unwind(0xF9);
+        assert_eq!(calls.frames().len(), 2);
+        assert_eq!(calls.frames()[1].entry, 0x8200);
+
+        // the inner return address pulled off, abandoning that call
+        calls.unwind(0xFB);
+        assert_eq!(calls.frames().len(), 1);
+        // the stack reset
+        calls.unwind(0xFF);
+        assert!(calls.frames().is_empty());
+    }
+}
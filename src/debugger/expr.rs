@@ -0,0 +1,351 @@
+use super::symbols::Symbols;
+use crate::cpu::CPU;
+
+/* Expressions
+
+   The debugger's one language for the machine's state, for breakpoint
+   conditions, which hold when not 0, `watch` and `print`:
+
+    a x y sp p pc       the registers
+    scanline dot        where the PPU is
+    carry zero interrupt decimal overflow negative
+                        the flags, 0 or 1
+    [ADDR]              the byte at an address, itself an expression, so
+                        `[$0200 + x]` follows the index register and
+                        `[[$20] + x]` a zero page pointer's low byte
+    0x40 $40 64         numbers, hex either way or decimal
+    lives               a label from `--symbols`, its address, so
+                        `[lives]` is the byte there
+
+   with, from the tightest binding to the loosest, `!` and unary `-`,
+   `* / %`, `+ -`, `<< >>`, `&`, `^`, `|`, the comparisons
+   `== != < <= > >=`, `&&` and `||`, and parentheses for anything else.
+   That is Rust's order, so `p & 0x80 != 0` means what it looks like.
+   Dividing by 0 gives 0 rather than stopping anything. The register and
+   flag names take any case and come before labels of the same name;
+   labels are as the symbols spell them. Memory is peeked, so looking never
+   changes what the game sees.
+*/
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(i64),
+    Register(Register),
+    Flag(u8), // the status bit
+    Memory(Box<Expr>),
+    Not(Box<Expr>),
+    Negate(Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    A,
+    X,
+    Y,
+    Sp,
+    P,
+    Pc,
+    Scanline,
+    Dot,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Symbol(&'static str),
+}
+
+// longest first, so `<=` isn't read as `<` then `=`
+const SYMBOLS: [&str; 24] = [
+    "&&", "||", "==", "!=", "<=", ">=", "<<", ">>", "<", ">", "+", "-", "*", "/", "%", "&", "^",
+    "|", "!", "(", ")", "[", "]", "=",
+];
+
+// loosest first, each level's operators
+const LEVELS: [&[&str]; 9] = [
+    &["||"],
+    &["&&"],
+    &["==", "!=", "<", "<=", ">", ">="],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        if let Some(symbol) = SYMBOLS.iter().find(|s| rest.starts_with(**s)) {
+            if *symbol == "=" {
+                return Err("use == to compare".to_string());
+            }
+            tokens.push(Token::Symbol(symbol));
+            rest = &rest[symbol.len()..];
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '$' || c == '_'))
+                .unwrap_or(rest.len());
+            if end == 0 {
+                return Err(format!("unexpected {}", rest.chars().next().unwrap()));
+            }
+            let word = &rest[..end];
+            tokens.push(word_token(word)?);
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+    }
+    return Ok(tokens);
+}
+
+fn word_token(word: &str) -> Result<Token, String> {
+    let hex = word.strip_prefix("0x").or_else(|| word.strip_prefix('$'));
+    let number = match hex {
+        Some(digits) => i64::from_str_radix(digits, 16),
+        None if word.starts_with(|c: char| c.is_ascii_digit()) => word.parse(),
+        None => return Ok(Token::Name(word.to_string())),
+    };
+    return number
+        .map(Token::Number)
+        .map_err(|_| format!("invalid number {}", word));
+}
+
+pub fn parse(text: &str) -> Result<Expr, String> {
+    return parse_with(text, &Symbols::default());
+}
+
+// with labels from `symbols` as well
+pub fn parse_with(text: &str, symbols: &Symbols) -> Result<Expr, String> {
+    let tokens = tokenize(text)?;
+    let mut parser = Parser {
+        tokens,
+        next: 0,
+        symbols,
+    };
+    let expr = parser.binary(0)?;
+    if let Some(token) = parser.peek() {
+        return Err(format!("unexpected {:?} after the expression", token));
+    }
+    return Ok(expr);
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    next: usize,
+    symbols: &'a Symbols,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        return self.tokens.get(self.next);
+    }
+
+    fn take(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.next).cloned();
+        self.next += 1;
+        return token;
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), String> {
+        match self.take() {
+            Some(Token::Symbol(s)) if s == symbol => return Ok(()),
+            _ => return Err(format!("expected {}", symbol)),
+        }
+    }
+
+    // left to right within a level, so `1 - 2 - 3` is -4
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut left = self.binary(level + 1)?;
+        while let Some(&Token::Symbol(op)) = self.peek() {
+            if !LEVELS[level].contains(&op) {
+                break;
+            }
+            self.next += 1;
+            let right = self.binary(level + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        return Ok(left);
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.take() {
+            Some(Token::Symbol("!")) => return Ok(Expr::Not(Box::new(self.unary()?))),
+            Some(Token::Symbol("-")) => return Ok(Expr::Negate(Box::new(self.unary()?))),
+            Some(Token::Symbol("(")) => {
+                let expr = self.binary(0)?;
+                self.expect(")")?;
+                return Ok(expr);
+            }
+            Some(Token::Symbol("[")) => {
+                let addr = self.binary(0)?;
+                self.expect("]")?;
+                return Ok(Expr::Memory(Box::new(addr)));
+            }
+            Some(Token::Number(n)) => return Ok(Expr::Number(n)),
+            Some(Token::Name(name)) => return name_expr(&name, self.symbols),
+            Some(token) => return Err(format!("unexpected {:?}", token)),
+            None => return Err("the expression ends too soon".to_string()),
+        }
+    }
+}
+
+fn name_expr(name: &str, symbols: &Symbols) -> Result<Expr, String> {
+    let expr = match name.to_ascii_lowercase().as_str() {
+        "a" => Expr::Register(Register::A),
+        "x" => Expr::Register(Register::X),
+        "y" => Expr::Register(Register::Y),
+        "sp" => Expr::Register(Register::Sp),
+        "p" => Expr::Register(Register::P),
+        "pc" => Expr::Register(Register::Pc),
+        "scanline" => Expr::Register(Register::Scanline),
+        "dot" => Expr::Register(Register::Dot),
+        "carry" => Expr::Flag(0b0000_0001),
+        "zero" => Expr::Flag(0b0000_0010),
+        "interrupt" => Expr::Flag(0b0000_0100),
+        "decimal" => Expr::Flag(0b0000_1000),
+        "overflow" => Expr::Flag(0b0100_0000),
+        "negative" => Expr::Flag(0b1000_0000),
+        _ => match symbols.lookup(name) {
+            Some(addr) => Expr::Number(addr as i64),
+            None => return Err(format!("unknown name {}", name)),
+        },
+    };
+    return Ok(expr);
+}
+
+impl Expr {
+    pub fn eval(&self, cpu: &CPU) -> i64 {
+        match self {
+            Expr::Number(n) => return *n,
+            Expr::Register(register) => {
+                return match register {
+                    Register::A => cpu.register_a as i64,
+                    Register::X => cpu.register_x as i64,
+                    Register::Y => cpu.register_y as i64,
+                    Register::Sp => cpu.stack.ptr() as i64,
+                    Register::P => cpu.status.bits() as i64,
+                    Register::Pc => cpu.program_counter as i64,
+                    Register::Scanline => cpu.bus.ppu.scanline() as i64,
+                    Register::Dot => cpu.bus.ppu.dot() as i64,
+                }
+            }
+            Expr::Flag(bit) => return (cpu.status.bits() & bit != 0) as i64,
+            Expr::Memory(addr) => return cpu.bus.peek(addr.eval(cpu) as u16) as i64,
+            Expr::Not(expr) => return (expr.eval(cpu) == 0) as i64,
+            Expr::Negate(expr) => return expr.eval(cpu).wrapping_neg(),
+            Expr::Binary(op, left, right) => {
+                let left = left.eval(cpu);
+                // && and || don't look at the right when the left decides
+                match *op {
+                    "&&" => return (left != 0 && right.eval(cpu) != 0) as i64,
+                    "||" => return (left != 0 || right.eval(cpu) != 0) as i64,
+                    _ => {}
+                }
+                let right = right.eval(cpu);
+                match *op {
+                    "==" => return (left == right) as i64,
+                    "!=" => return (left != right) as i64,
+                    "<" => return (left < right) as i64,
+                    "<=" => return (left <= right) as i64,
+                    ">" => return (left > right) as i64,
+                    ">=" => return (left >= right) as i64,
+                    "|" => return left | right,
+                    "^" => return left ^ right,
+                    "&" => return left & right,
+                    "<<" => return left.wrapping_shl(right as u32),
+                    ">>" => return left.wrapping_shr(right as u32),
+                    "+" => return left.wrapping_add(right),
+                    "-" => return left.wrapping_sub(right),
+                    "*" => return left.wrapping_mul(right),
+                    "/" => return left.checked_div(right).unwrap_or(0),
+                    _ => return left.checked_rem(right).unwrap_or(0),
+                }
+            }
+        }
+    }
+
+    pub fn is_true(&self, cpu: &CPU) -> bool {
+        return self.eval(cpu) != 0;
+    }
+}
+
+// a value as `print` and the watches show it, in hex as a byte or an
+// address would be and in decimal
+pub fn show(value: i64) -> String {
+    return match value {
+        0..=0xFF => format!("${:02X} {}", value, value),
+        0x100..=0xFFFF => format!("${:04X} {}", value, value),
+        _ => value.to_string(),
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::Mem;
+    use crate::debugger::symbols::parse_labels;
+
+    #[test]
+    fn test_conditions() {
+        let mut cpu = CPU::new();
+        cpu.register_a = 0x40;
+        cpu.register_x = 2;
+        cpu.status.set_zero();
+        cpu.mem_write(0x00F0, 5);
+        cpu.mem_write(0x0202, 9);
+        let eval = |text: &str| parse(text).unwrap().eval(&cpu);
+
+        assert_eq!(eval("a == 0x40 && zero == 1 && [0x00F0] > 3"), 1);
+        assert_eq!(eval("a == $41 || carry"), 0);
+        assert_eq!(eval("[$0200 + x]"), 9);
+        assert_eq!(eval("p & 0x02 != 0"), 1);
+        assert_eq!(eval("1 - 2 - 3"), -4);
+        assert_eq!(
+            eval("scanline < 240"),
+            (cpu.bus.ppu.scanline() < 240) as i64
+        );
+        assert_eq!(eval("!(x >= 2) | 8"), 8);
+        assert_eq!(eval("-A + 64"), 0);
+
+        assert_eq!(parse("a = 1").err().unwrap(), "use == to compare");
+        assert!(parse("a ==").is_err());
+        assert!(parse("[0x10").is_err());
+        assert!(parse("a b").is_err());
+        assert_eq!(parse("q > 1").err().unwrap(), "unknown name q");
+    }
+
+    #[test]
+    fn test_symbols_and_arithmetic() {
+        let mut cpu = CPU::new();
+        cpu.register_x = 3;
+        cpu.mem_write(0x0020, 0x40);
+        cpu.mem_write(0x0043, 7);
+        cpu.mem_write(0x00F0, 2);
+        let symbols = parse_labels("al 00F0 .lives\nal 0020 .X").unwrap();
+        let eval = |text: &str| parse_with(text, &symbols).unwrap().eval(&cpu);
+
+        assert_eq!(eval("[[0x20]+x]"), 7);
+        assert_eq!(eval("[lives] * 3 + 1"), 7);
+        assert_eq!(eval("1 + 2 * 3 << 1"), 14);
+        assert_eq!(eval("x ^ 1 | 0x10 & 0x30"), 0x12);
+        assert_eq!(eval("-7 / 2 + 7 % 3"), -2);
+        assert_eq!(eval("a / 0 + a % 0"), 0);
+        // the register, not the label
+        assert_eq!(eval("X"), 3);
+        assert_eq!(
+            parse_with("Lives", &symbols).err().unwrap(),
+            "unknown name Lives"
+        );
+
+        assert_eq!(show(0x40), "$40 64");
+        assert_eq!(show(0x1234), "$1234 4660");
+        assert_eq!(show(-1), "-1");
+    }
+}
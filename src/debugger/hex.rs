@@ -0,0 +1,530 @@
+use super::symbols::Symbols;
+use crate::cpu::{Mem, CPU};
+
+const ROW_BYTES: usize = 0x10;
+const PAGE_BYTES: usize = 0x100;
+
+/* Hex editor
+
+   The full-screen view's hex dump, over any of the console's memory
+   rather than only what the CPU sees:
+
+    cpu     $0000-$FFFF as the CPU reads it, with registers as 00
+    vram    the PPU's nametable RAM
+    oam     the sprites' 256 bytes
+    prg     the whole PRG ROM, every bank, from its first byte
+    chr     the pattern memory, CHR ROM or RAM
+
+   Editing puts a cursor on a byte, and two hex digits replace it: the
+   CPU's bytes are written the way the CPU would write them, and the rest
+   are patched in place, ROM included, until the cartridge is loaded
+   again. `goto` moves the view and `find` looks for bytes, `find a9 00
+   8d`, or text in quotes, `find "score"`, after the cursor and round from
+   the start, the last one again without either. Text matches letters in
+   either case, since command lines are taken in lowercase. Bytes that
+   changed since the game last ran, a step or up to a continue, are shown
+   in bold.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Space {
+    #[default]
+    Cpu,
+    Vram,
+    Oam,
+    Prg,
+    Chr,
+}
+
+const SPACES: [Space; 5] = [Space::Cpu, Space::Vram, Space::Oam, Space::Prg, Space::Chr];
+
+impl Space {
+    pub fn parse(name: &str) -> Result<Space, String> {
+        match name {
+            "cpu" => return Ok(Space::Cpu),
+            "vram" => return Ok(Space::Vram),
+            "oam" => return Ok(Space::Oam),
+            "prg" => return Ok(Space::Prg),
+            "chr" => return Ok(Space::Chr),
+            _ => return Err(format!("unknown memory space name {}", name)),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Space::Cpu => return "cpu",
+            Space::Vram => return "vram",
+            Space::Oam => return "oam",
+            Space::Prg => return "prg",
+            Space::Chr => return "chr",
+        }
+    }
+
+    // bytes in it, 0 for the cartridge's without one
+    pub fn size(self, cpu: &CPU) -> usize {
+        return match self {
+            Space::Cpu => 0x10000,
+            Space::Vram => cpu.bus.ppu.vram().len(),
+            Space::Oam => cpu.bus.ppu.oam().len(),
+            Space::Prg => cpu
+                .bus
+                .mapper()
+                .and_then(|m| m.prg_rom())
+                .map_or(0, |b| b.len()),
+            Space::Chr => cpu
+                .bus
+                .mapper()
+                .and_then(|m| m.chr())
+                .map_or(0, |b| b.len()),
+        };
+    }
+
+    pub fn peek(self, cpu: &CPU, addr: usize) -> u8 {
+        let bytes = match self {
+            Space::Cpu => return cpu.bus.peek(addr as u16),
+            Space::Vram => Some(cpu.bus.ppu.vram()),
+            Space::Oam => Some(cpu.bus.ppu.oam()),
+            Space::Prg => cpu.bus.mapper().and_then(|m| m.prg_rom()),
+            Space::Chr => cpu.bus.mapper().and_then(|m| m.chr()),
+        };
+        return bytes.and_then(|b| b.get(addr).copied()).unwrap_or(0);
+    }
+
+    pub fn poke(self, cpu: &mut CPU, addr: usize, value: u8) {
+        let bytes = match self {
+            Space::Cpu => return cpu.mem_write(addr as u16, value),
+            Space::Vram => Some(cpu.bus.ppu.vram_mut()),
+            Space::Oam => Some(cpu.bus.ppu.oam_mut()),
+            Space::Prg => cpu.bus.mapper_mut().and_then(|m| m.prg_rom_mut()),
+            Space::Chr => cpu.bus.mapper_mut().and_then(|m| m.chr_mut()),
+        };
+        if let Some(byte) = bytes.and_then(|b| b.get_mut(addr)) {
+            *byte = value;
+        }
+    }
+
+    // all of it, to search and to see what changed
+    fn bytes(self, cpu: &CPU) -> Vec<u8> {
+        return (0..self.size(cpu))
+            .map(|addr| self.peek(cpu, addr))
+            .collect();
+    }
+}
+
+// what `find` looks for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    bytes: Vec<u8>,
+    text: bool, // letters match in either case
+}
+
+impl Pattern {
+    pub fn parse(text: &str) -> Result<Pattern, String> {
+        let text = text.trim();
+        let pattern = match text.strip_prefix('"') {
+            Some(quoted) => Pattern {
+                bytes: quoted
+                    .strip_suffix('"')
+                    .unwrap_or(quoted)
+                    .as_bytes()
+                    .to_vec(),
+                text: true,
+            },
+            None => {
+                let bytes = text
+                    .split_whitespace()
+                    .map(|word| {
+                        let digits = word.strip_prefix('$').unwrap_or(word);
+                        return u8::from_str_radix(digits, 16)
+                            .map_err(|_| format!("{} is not a hex byte", word));
+                    })
+                    .collect::<Result<_, _>>()?;
+                Pattern { bytes, text: false }
+            }
+        };
+        if pattern.bytes.is_empty() {
+            return Err("find needs bytes or \"text\" to look for".to_string());
+        }
+        return Ok(pattern);
+    }
+
+    fn matches(&self, bytes: &[u8]) -> bool {
+        if self.text {
+            return bytes.eq_ignore_ascii_case(&self.bytes);
+        }
+        return bytes == self.bytes;
+    }
+}
+
+// where `pattern` is first after `from` in `bytes`, going round to the
+// start and back to `from` itself last
+pub fn find(bytes: &[u8], from: usize, pattern: &Pattern) -> Option<usize> {
+    let len = pattern.bytes.len();
+    if len > bytes.len() {
+        return None;
+    }
+    let starts = bytes.len() - len + 1;
+    return (1..=starts)
+        .map(|n| (from + n) % starts)
+        .find(|&at| pattern.matches(&bytes[at..at + len]));
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct HexView {
+    pub space: Space,
+    pub start: usize,      // the first byte shown
+    cursor: Option<usize>, // the byte being edited, while editing
+    high: Option<u8>,      // its first digit, once typed
+    before: Vec<u8>,       // the space as it was when the game last ran
+    last_find: Option<Pattern>,
+}
+
+impl HexView {
+    pub fn editing(&self) -> bool {
+        return self.cursor.is_some();
+    }
+
+    pub fn set_space(&mut self, space: Space) {
+        self.space = space;
+        self.start = 0;
+        self.cursor = self.cursor.map(|_| 0);
+        self.high = None;
+        self.before.clear();
+    }
+
+    // the next space along that has anything in it
+    fn next_space(&mut self, cpu: &CPU) {
+        let at = SPACES
+            .iter()
+            .position(|&space| space == self.space)
+            .unwrap();
+        for n in 1..=SPACES.len() {
+            let space = SPACES[(at + n) % SPACES.len()];
+            if space.size(cpu) > 0 {
+                return self.set_space(space);
+            }
+        }
+    }
+
+    // the view from `addr`, with the cursor on it when editing
+    pub fn goto(&mut self, cpu: &CPU, addr: usize) -> Result<(), String> {
+        if addr >= self.space.size(cpu) {
+            return Err(format!("{} has no ${:X}", self.space.name(), addr));
+        }
+        self.start = addr;
+        if self.cursor.is_some() {
+            self.cursor = Some(addr);
+            self.high = None;
+        }
+        return Ok(());
+    }
+
+    // what the space holds now, so the bytes the game changes from here show
+    pub fn mark(&mut self, cpu: &CPU) {
+        self.before = self.space.bytes(cpu);
+    }
+
+    // a key for the hex dump, false if it isn't one; `rows` are shown
+    pub fn key(&mut self, key: &str, cpu: &mut CPU, rows: usize) -> bool {
+        let size = self.space.size(cpu).max(1);
+        let Some(cursor) = self.cursor else {
+            match key {
+                "Up" => self.scroll(-(ROW_BYTES as isize), size),
+                "Down" => self.scroll(ROW_BYTES as isize, size),
+                "Left" => self.scroll(-(PAGE_BYTES as isize), size),
+                "Right" => self.scroll(PAGE_BYTES as isize, size),
+                "Tab" => self.next_space(cpu),
+                "H" => self.cursor = Some(self.start % size),
+                _ => return false,
+            }
+            return true;
+        };
+        let by = match key {
+            "Up" => -(ROW_BYTES as isize),
+            "Down" => ROW_BYTES as isize,
+            "Left" => -1,
+            "Right" => 1,
+            "Tab" => {
+                self.next_space(cpu);
+                return true;
+            }
+            "H" | "Escape" => {
+                self.cursor = None;
+                self.high = None;
+                return true;
+            }
+            digit if digit.len() == 1 && digit.chars().all(|c| c.is_ascii_hexdigit()) => {
+                let digit = u8::from_str_radix(digit, 16).unwrap();
+                match self.high.take() {
+                    None => {
+                        self.high = Some(digit);
+                        return true;
+                    }
+                    Some(high) => self.space.poke(cpu, cursor, high << 4 | digit),
+                }
+                1
+            }
+            _ => return false,
+        };
+        let cursor = (cursor as isize + by).rem_euclid(size as isize) as usize;
+        self.cursor = Some(cursor);
+        self.high = None;
+        // scrolled along with it, a row at a time
+        while (cursor + size - self.start) % size >= rows * ROW_BYTES {
+            self.scroll(by.signum() * ROW_BYTES as isize, size);
+        }
+        return true;
+    }
+
+    // round the end of the space
+    fn scroll(&mut self, by: isize, size: usize) {
+        self.start = (self.start as isize + by).rem_euclid(size as isize) as usize;
+    }
+
+    // `goto [SPACE] ADDR` and `find [BYTES|"TEXT"]`, the view's own commands,
+    // and None for any other
+    pub fn command(
+        &mut self,
+        cpu: &CPU,
+        line: &str,
+        symbols: &Symbols,
+    ) -> Option<Result<String, String>> {
+        let line = line.trim();
+        let (name, rest) = line.split_once(' ').unwrap_or((line, ""));
+        match name {
+            "goto" | "g" => return Some(self.goto_command(cpu, rest, symbols)),
+            "find" | "f" => {
+                let pattern = match rest.trim() {
+                    "" => None,
+                    text => match Pattern::parse(text) {
+                        Ok(pattern) => Some(pattern),
+                        Err(e) => return Some(Err(e)),
+                    },
+                };
+                return Some(
+                    self.find(cpu, pattern)
+                        .map(|at| format!("found at ${:04X}", at)),
+                );
+            }
+            _ => return None,
+        }
+    }
+
+    fn goto_command(&mut self, cpu: &CPU, rest: &str, symbols: &Symbols) -> Result<String, String> {
+        let words: Vec<&str> = rest.split_whitespace().collect();
+        let (space, text) = match words[..] {
+            [text] => (self.space, text),
+            [space, text] => (Space::parse(space)?, text),
+            _ => {
+                return Err(
+                    "goto needs an address, and a space before it if not this one".to_string(),
+                )
+            }
+        };
+        let addr = match symbols.lookup(text) {
+            Some(addr) if space == Space::Cpu => addr as usize,
+            _ => {
+                let digits = text.strip_prefix('$').unwrap_or(text);
+                usize::from_str_radix(digits, 16)
+                    .map_err(|_| format!("{} is not a hex number", text))?
+            }
+        };
+        if space != self.space {
+            if space.size(cpu) <= addr {
+                return Err(format!("{} has no ${:X}", space.name(), addr));
+            }
+            self.set_space(space);
+        }
+        self.goto(cpu, addr)?;
+        return Ok(String::new());
+    }
+
+    // the next place `pattern` is after the cursor, or the first byte shown,
+    // the last pattern found again without one
+    fn find(&mut self, cpu: &CPU, pattern: Option<Pattern>) -> Result<usize, String> {
+        let pattern = pattern
+            .or_else(|| self.last_find.clone())
+            .ok_or("find needs bytes or \"text\" to look for")?;
+        let from = self.cursor.unwrap_or(self.start);
+        let at = find(&self.space.bytes(cpu), from, &pattern);
+        self.last_find = Some(pattern);
+        let at = at.ok_or("not found")?;
+        self.goto(cpu, at)?;
+        return Ok(at);
+    }
+
+    // the pane's title, with the keys for what it's doing
+    pub fn title(&self, cpu: &CPU) -> String {
+        let keys = match self.editing() {
+            true => "editing  0-F type  arrows move  h or esc stop",
+            false => "tab space  h edit  g goto  / find",
+        };
+        let size = self.space.size(cpu);
+        return format!(
+            " Memory {} ${:0width$X}   {}",
+            self.space.name(),
+            self.start,
+            keys,
+            width = digits(size)
+        );
+    }
+
+    // `rows` rows of 16 bytes from the start, the cursor in reverse video and
+    // what changed in bold, then as text
+    pub fn rows(&self, cpu: &CPU, rows: usize) -> Vec<String> {
+        let size = self.space.size(cpu);
+        if size == 0 {
+            return vec![String::new(); rows];
+        }
+        let mut lines = Vec::new();
+        for row in 0..rows {
+            let first = (self.start + row * ROW_BYTES) % size;
+            let addrs: Vec<usize> = (0..ROW_BYTES).map(|i| (first + i) % size).collect();
+            let bytes: Vec<u8> = addrs.iter().map(|&a| self.space.peek(cpu, a)).collect();
+            let hex: Vec<String> = addrs
+                .iter()
+                .zip(&bytes)
+                .map(|(&addr, &byte)| {
+                    if self.cursor == Some(addr) {
+                        return match self.high {
+                            Some(high) => format!("\x1b[7m{:X}_\x1b[0m", high),
+                            None => format!("\x1b[7m{:02X}\x1b[0m", byte),
+                        };
+                    }
+                    if self.before.get(addr).is_some_and(|&was| was != byte) {
+                        return format!("\x1b[1m{:02X}\x1b[0m", byte);
+                    }
+                    return format!("{:02X}", byte);
+                })
+                .collect();
+            let text: String = bytes
+                .iter()
+                .map(|&b| if b.is_ascii_graphic() { b as char } else { '.' })
+                .collect();
+            lines.push(format!(
+                "{:0width$X}  {}  {}",
+                first,
+                hex.join(" "),
+                text,
+                width = digits(size)
+            ));
+        }
+        return lines;
+    }
+}
+
+// hex digits for an address in a space of `size` bytes, 4 at least
+fn digits(size: usize) -> usize {
+    let mut digits = 4;
+    while size > 1 << (digits * 4) {
+        digits += 1;
+    }
+    return digits;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::cartridge::test::test_rom;
+    use crate::cartridge::Rom;
+
+    #[test]
+    fn test_spaces_and_editing() {
+        let mut raw = test_rom(0, 1, 1);
+        raw[16 + 0x10] = 0x4C;
+        let mut cpu = CPU::with_bus(Bus::from_rom(Rom::new(&raw).unwrap()).unwrap());
+        let mut view = HexView::default();
+        assert_eq!(Space::Prg.size(&cpu), 0x4000);
+        assert_eq!(
+            Space::parse("sram").err().unwrap(),
+            "unknown memory space name sram"
+        );
+
+        // on to the PRG ROM and into it a row
+        for key in ["Tab", "Tab", "Tab", "Down", "H"] {
+            assert!(view.key(key, &mut cpu, 6));
+        }
+        assert_eq!(view.space, Space::Prg);
+        assert!(view.title(&cpu).starts_with(" Memory prg $0010   editing"));
+        assert!(view.rows(&cpu, 1)[0].starts_with("0010  \x1b[7m4C\x1b[0m 00"));
+        view.mark(&cpu);
+        for key in ["Right", "E", "A"] {
+            assert!(view.key(key, &mut cpu, 6));
+        }
+        // a ROM patch, where the CPU sees it too
+        assert_eq!(cpu.bus.peek(0x8011), 0xEA);
+        assert_eq!(cpu.bus.peek(0xC011), 0xEA);
+        let row = &view.rows(&cpu, 1)[0];
+        assert!(row.starts_with("0010  4C \x1b[1mEA\x1b[0m \x1b[7m00\x1b[0m 00"));
+        view.key("Left", &mut cpu, 6);
+        view.key("0", &mut cpu, 6);
+        assert!(view.rows(&cpu, 1)[0].starts_with("0010  4C \x1b[7m0_\x1b[0m 00"));
+        assert!(!view.key("S", &mut cpu, 6));
+
+        // the cursor takes the view with it
+        for _ in 0..6 {
+            view.key("Down", &mut cpu, 6);
+        }
+        assert_eq!(view.start, 0x0020);
+        view.key("Escape", &mut cpu, 6);
+        view.key("Up", &mut cpu, 6);
+        view.key("Up", &mut cpu, 6);
+        view.key("Up", &mut cpu, 6);
+        assert_eq!(view.start, 0x3FF0);
+
+        view.key("Tab", &mut cpu, 6);
+        assert_eq!(view.space, Space::Chr);
+        view.key("Tab", &mut cpu, 6);
+        view.key("H", &mut cpu, 6);
+        for key in ["4", "1"] {
+            view.key(key, &mut cpu, 6);
+        }
+        assert_eq!(cpu.bus.peek(0x0000), 0x41);
+    }
+
+    #[test]
+    fn test_goto_and_find() {
+        let mut cpu = CPU::new();
+        for (i, &byte) in b"LIVES\0\xA9\x00\x8D".iter().enumerate() {
+            cpu.mem_write(0x0300 + i as u16, byte);
+        }
+        let symbols = crate::debugger::symbols::parse_labels("al 0300 .lives").unwrap();
+        let mut view = HexView::default();
+        let command = |view: &mut HexView, line: &str| {
+            return view.command(&cpu, line, &symbols).unwrap();
+        };
+
+        assert_eq!(
+            command(&mut view, "find a9 00 8d"),
+            Ok("found at $0306".to_string())
+        );
+        assert_eq!(command(&mut view, "goto 0"), Ok(String::new()));
+        assert_eq!(
+            command(&mut view, "f \"lives\""),
+            Ok("found at $0300".to_string())
+        );
+        // going round to it again
+        assert_eq!(command(&mut view, "find"), Ok("found at $0300".to_string()));
+        assert_eq!(
+            command(&mut view, "find 12 34"),
+            Err("not found".to_string())
+        );
+        assert_eq!(
+            command(&mut view, "find 123"),
+            Err("123 is not a hex byte".to_string())
+        );
+
+        command(&mut view, "goto lives").unwrap();
+        assert_eq!(view.start, 0x0300);
+        command(&mut view, "g oam 10").unwrap();
+        assert_eq!((view.space, view.start), (Space::Oam, 0x10));
+        assert_eq!(
+            command(&mut view, "goto 100"),
+            Err("oam has no $100".to_string())
+        );
+        assert!(command(&mut view, "goto prg 0").is_err());
+        assert_eq!(view.space, Space::Oam);
+        assert!(view.command(&cpu, "read 0300", &symbols).is_none());
+    }
+}
@@ -0,0 +1,1767 @@
+use crate::apu::scope::waveform;
+use crate::asm;
+use crate::cheat_search::{CheatSearch, Filter};
+use crate::cpu::{AddressingMode, Mem, CPU};
+use crate::nes::Nes;
+use crate::op_codes::NMOS_6502_OPCODES_MAP;
+use access::{Access, AccessKind, AccessRange, AccessWatch};
+use call_stack::Frame;
+use expr::Expr;
+use hex::Space;
+use rewind::Rewind;
+use std::io::{self, BufRead, Write};
+use symbols::Symbols;
+use tui::{Action, Tui};
+
+pub mod access;
+pub mod call_stack;
+pub mod expr;
+pub mod hex;
+pub mod rewind;
+pub mod symbols;
+pub mod tui;
+
+// instructions `disasm` shows when not given a count
+const DISASM_LINES: usize = 8;
+// bytes `read` shows when not given a length
+const READ_LENGTH: u16 = 16;
+// levels of each channel's waveform `apu` shows
+const SCOPE_COLUMNS: usize = 64;
+// instructions `over` and `out` run before giving up on a return
+const STEP_LIMIT: u32 = 1_000_000;
+const JSR: u8 = 0x20;
+const RTS: u8 = 0x60;
+const RTI: u8 = 0x40;
+
+/* Debugger
+
+   A command prompt for looking into the console while it stands still.
+   `--debug` opens it on the terminal before the first frame, and after
+   that the break hotkey (F8) opens it again between frames. `--debug-tui`
+   shows the same debugger full screen instead, see `tui`. Nothing runs
+   while it waits for a command:
+
+    step [N]            s  run N instructions, 1 by default, and show the next
+    over                o  a step, except that a JSR runs until it returns
+    out                 u  runs until the subroutine returns to its caller
+    step-back [N]       sb back N instructions, 1 by default
+    reverse-continue    rc back to the last breakpoint hit, or as far as
+                           it can go
+    continue            c  back to the game
+    regs                r  the registers, flags and cycle count
+    read ADDR [LEN]     m  a hex dump, 16 bytes by default
+    write ADDR BYTE...  w  bytes written as the CPU would write them
+    disasm [ADDR] [N]   d  N instructions from ADDR, the PC by default
+    pc ADDR                moves the PC
+    asm ADDR CODE       a  an instruction through the mini assembler,
+                           written at ADDR, e.g. `asm 0300 LDA #$01`
+    break ADDR [if COND]
+                        b  stops before the instruction at ADDR runs, when
+                           COND holds if there is one
+    break if COND       b  stops as soon as COND becomes true, wherever
+    break read|write|access ADDR[-ADDR] [if COND]
+                        b  stops after an instruction or DMA reads, writes
+                           or does either to ADDR or its mirrors, or
+                           anywhere in the range, see `access`
+    breaks                 the breakpoints, numbered
+    calls               bt the subroutines the CPU is in, innermost first,
+                           each with where it returns to
+    delete N               removes breakpoint N
+    print EXPR          p  an expression's value, `print [[$20] + x]`
+    watch EXPR             an expression to keep an eye on, shown with its
+                           value whenever the debugger stops
+    watches                the watches, numbered, with their values
+    unwatch N              removes watch N
+    events on|off          the PPU event log, see `ppu::events`
+    events                 the last frame's events, each at its scanline
+                           and dot
+    apu on|off             the APU scope, see `apu::scope`
+    apu                    each sound channel's period, volume, duty and
+                           length counter, and its waveform with the scope
+                           on
+    unofficial break|log|off
+                           stops, or writes a line, the first time each
+                           opcode outside the official 151 is about to run
+    search start           a cheat search over RAM, see `cheat_search`
+    search FILTER          keeps the addresses that pass, `search less`
+    search list            the addresses left and their bytes
+    search freeze [BYTE]   freeze cheats for them, holding BYTE if given
+    quit                q  ends the session
+
+   Addresses and bytes are hex, with or without the $. With `--symbols
+   FILE` loaded an address can also be a label or a `file:line`, and the
+   disassembly shows labels for the addresses it has names for. An empty
+   line does
+   the last command again, so stepping is a matter of pressing enter. Reads
+   are peeks that leave the machine as it was, which means the PPU, APU and
+   controller registers read as 00 here; writes do go through, registers
+   included. At the end of the input the game continues.
+
+   Conditions, watches and `print` are `expr` expressions, `a == 0x40 &&
+   zero && [$F0] > 3`, with the symbols' labels as their addresses.
+   One without an address is checked before every instruction and stops
+   when it goes from false to true, not on each instruction it stays true
+   for, so continuing from it doesn't stop again straight away. With any
+   breakpoints set frames run an instruction at a time, to look before
+   each one, which is slower than the console's own loop.
+
+   `over` and `out` go by the stack pointer rather than counting calls: a
+   JSR is over when the PC is back after it with the stack where it was,
+   and a subroutine is out when an RTS or RTI leaves the stack above where
+   it started. Recursion and an interrupt taken partway through, whose
+   handler pushes and pulls below that, don't end them early, and `out`
+   in an interrupt handler stops where its RTI returns to. Both stop at a
+   breakpoint on the way.
+
+   Going backwards runs forward again from a snapshot, see `rewind`, so it
+   lands on the very instruction it would have on the way there.
+   `reverse-continue` goes over the instructions since each snapshot,
+   newest first, for the last place a breakpoint would have stopped before
+   where the debugger is: with `break if [$0300] == $FF` that is just after
+   the write that put $FF there, and `step-back` shows the write. Changes
+   made from the prompt are undone going back past them.
+
+   `break write 2006 if scanline < 240` catches a game writing the PPU
+   address in the middle of rendering, `break write 4014` each OAM DMA
+   and `break write 4016` the controller strobe; `scanline` and `dot` in
+   a condition are where the PPU is.
+
+   The CPU runs only the official opcodes and jams on the rest, the way it
+   does on KIL, so `unofficial break` is the chance to see how a game got
+   to one before it's too late to look: a homebrew program jumping into
+   data, or a game that needs the unofficial opcodes and won't run here.
+   The line the log writes and the stop give the PC and the instruction's
+   bytes, `unofficial opcode $0B at $C123: 0B 10 F0`.
+*/
+
+struct Watch {
+    expr: Expr,
+    text: String,
+}
+
+struct Breakpoint {
+    at: Option<u16>,
+    access: Option<AccessRange>,
+    condition: Option<Expr>,
+    text: String, // as it was typed, for `breaks`
+    was_true: bool,
+}
+
+impl Breakpoint {
+    fn hits(&mut self, cpu: &CPU, accesses: &[Access]) -> bool {
+        if self.access.is_some() {
+            return self.accessed(accesses).is_some()
+                && self.condition.as_ref().is_none_or(|c| c.is_true(cpu));
+        }
+        if self.at.is_some_and(|at| at != cpu.program_counter) {
+            return false;
+        }
+        let Some(condition) = &self.condition else {
+            return true;
+        };
+        let is_true = condition.is_true(cpu);
+        if self.at.is_some() {
+            return is_true;
+        }
+        let hit = is_true && !self.was_true;
+        self.was_true = is_true;
+        return hit;
+    }
+
+    // the first of `accesses` it stops on
+    fn accessed<'a>(&self, accesses: &'a [Access]) -> Option<&'a Access> {
+        let range = self.access?;
+        return accesses.iter().find(|access| range.matches(access));
+    }
+
+    // a condition-only breakpoint starts over from how things are now,
+    // so it stops the next time it becomes true rather than straight away
+    fn settle(&mut self, cpu: &CPU) {
+        if let (None, None, Some(condition)) = (self.at, self.access, &self.condition) {
+            self.was_true = condition.is_true(cpu);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OnUnofficial {
+    Off,
+    Break,
+    Log,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resume {
+    Continue,
+    Quit,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Reply {
+    Text(String),
+    Resume(Resume),
+}
+
+pub struct Debugger {
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+    last: String,
+    breaking: bool,
+    breakpoints: Vec<Breakpoint>,
+    resumed: bool, // the next instruction is the one the prompt stopped at
+    tui: Option<Tui>,
+    stopped: bool,   // in the full-screen view, waiting for keys
+    advance: bool,   // stops again when the frame ends
+    mid_frame: bool, // a breakpoint stopped the frame partway through
+    dirty: bool,     // the screen needs drawing again
+    search: Option<CheatSearch>,
+    symbols: Symbols,
+    watches: Vec<Watch>,
+    rewind: Rewind,
+    unofficial: OnUnofficial,
+    unofficial_seen: [bool; 256],
+}
+
+impl Debugger {
+    // stopped, so the prompt comes up before anything has run
+    pub fn new(input: Box<dyn BufRead>, output: Box<dyn Write>) -> Self {
+        return Self {
+            input,
+            output,
+            last: String::new(),
+            breaking: true,
+            breakpoints: Vec::new(),
+            resumed: false,
+            tui: None,
+            stopped: false,
+            advance: false,
+            mid_frame: false,
+            dirty: false,
+            search: None,
+            symbols: Symbols::default(),
+            watches: Vec::new(),
+            rewind: Rewind::default(),
+            unofficial: OnUnofficial::Off,
+            unofficial_seen: [false; 256],
+        };
+    }
+
+    pub fn stdio() -> Self {
+        return Self::new(Box::new(io::stdin().lock()), Box::new(io::stdout()));
+    }
+
+    // drawn on `output`, with the keys coming through `key`
+    pub fn full_screen(output: Box<dyn Write>) -> Self {
+        let mut debugger = Self::new(Box::new(io::empty()), output);
+        debugger.tui = Some(Tui::default());
+        return debugger;
+    }
+
+    // the prompt comes up at the next chance
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    pub fn request_break(&mut self) {
+        self.breaking = true;
+    }
+
+    pub fn take_break(&mut self) -> bool {
+        return std::mem::take(&mut self.breaking);
+    }
+
+    pub fn has_breakpoints(&self) -> bool {
+        return !self.breakpoints.is_empty();
+    }
+
+    // waiting in the full-screen view, so the session doesn't run frames
+    pub fn is_stopped(&self) -> bool {
+        return self.stopped;
+    }
+
+    // a frame of `nes`, or the rest of the one a breakpoint stopped in the
+    // full-screen view, stopping wherever a breakpoint hits
+    pub fn run_frame(&mut self, nes: &mut Nes) -> Result<Resume, String> {
+        let mid_frame = std::mem::take(&mut self.mid_frame);
+        // a snapshot as the frame starts, once any movie has set the
+        // controllers for it
+        let mut starting = !mid_frame;
+        let each_instruction = self.has_breakpoints() || self.unofficial != OnUnofficial::Off;
+        if !mid_frame && !each_instruction && !self.advance {
+            let rewind = &mut self.rewind;
+            nes.run_frame_with(|cpu| {
+                if std::mem::take(&mut starting) {
+                    rewind.save(cpu);
+                }
+                return true;
+            });
+            return Ok(Resume::Continue);
+        }
+        let mut outcome = Ok(Resume::Continue);
+        let mut before = |cpu: &mut CPU| {
+            if std::mem::take(&mut starting) {
+                self.rewind.save(cpu);
+            }
+            if std::mem::take(&mut self.resumed) {
+                take_accesses(cpu);
+                return true;
+            }
+            let hit = match self.hit(cpu) {
+                Ok(Some(hit)) => hit,
+                Ok(None) => return true,
+                Err(e) => {
+                    outcome = Err(e);
+                    return false;
+                }
+            };
+            outcome = self.stop(cpu, &hit);
+            return outcome == Ok(Resume::Continue) && !self.stopped;
+        };
+        let finished = match mid_frame {
+            true => nes.finish_frame_with(&mut before),
+            false => nes.run_frame_with(&mut before),
+        };
+        self.mid_frame = !finished;
+        if finished && std::mem::take(&mut self.advance) && !self.stopped {
+            let frame = format!("frame {}", nes.frame_count());
+            outcome = self.stop(&mut nes.cpu, &frame);
+        }
+        return outcome;
+    }
+
+    // why to stop ahead of the instruction at the PC, if there's a reason
+    fn hit(&mut self, cpu: &mut CPU) -> Result<Option<String>, String> {
+        let accesses = take_accesses(cpu);
+        let hit = self
+            .breakpoints
+            .iter_mut()
+            .position(|b| b.hits(cpu, &accesses));
+        if let Some(n) = hit {
+            return Ok(Some(self.hit_reason(n, &accesses)));
+        }
+        let code = cpu.bus.peek(cpu.program_counter);
+        if self.unofficial == OnUnofficial::Off
+            || NMOS_6502_OPCODES_MAP.contains_key(&code)
+            || std::mem::replace(&mut self.unofficial_seen[code as usize], true)
+        {
+            return Ok(None);
+        }
+        let bytes: Vec<String> = (0..3)
+            .map(|i| format!("{:02X}", cpu.bus.peek(cpu.program_counter.wrapping_add(i))))
+            .collect();
+        let line = format!(
+            "unofficial opcode ${:02X} at ${:04X}: {}",
+            code,
+            cpu.program_counter,
+            bytes.join(" ")
+        );
+        if self.unofficial == OnUnofficial::Break {
+            return Ok(Some(line));
+        }
+        match &mut self.tui {
+            Some(tui) => tui.message = line,
+            None => writeln!(self.output, "{}", line).map_err(|e| format!("debugger: {}", e))?,
+        }
+        return Ok(None);
+    }
+
+    // stops the console for the prompt, or the full-screen view, saying why
+    pub fn stop(&mut self, cpu: &mut CPU, reason: &str) -> Result<Resume, String> {
+        if let Some(tui) = &mut self.tui {
+            self.resumed = true;
+            self.stopped = true;
+            self.dirty = true;
+            tui.message = reason.to_string();
+            return Ok(Resume::Continue);
+        }
+        if !reason.is_empty() {
+            writeln!(self.output, "{}", reason).map_err(|e| format!("debugger: {}", e))?;
+        }
+        return self.prompt(cpu);
+    }
+
+    // a key for the full-screen view, while it is stopped
+    pub fn key(&mut self, key: &str, cpu: &mut CPU) -> Result<Resume, String> {
+        let Some(tui) = &mut self.tui else {
+            return Ok(Resume::Continue);
+        };
+        self.dirty = true;
+        let action = tui.key(key, cpu);
+        if matches!(
+            action,
+            Action::Step
+                | Action::StepOver
+                | Action::StepOut
+                | Action::StepBack
+                | Action::ReverseContinue
+                | Action::Frame
+                | Action::Continue
+        ) {
+            tui.hex.mark(cpu);
+        }
+        let reply = match action {
+            Action::None => return Ok(Resume::Continue),
+            Action::Step => {
+                self.step(cpu);
+                Ok(Reply::Text(String::new()))
+            }
+            Action::StepBack => self.step_back(cpu, 1).map(Reply::Text),
+            Action::ReverseContinue => self.reverse_continue(cpu).map(Reply::Text),
+            Action::StepOver => Ok(Reply::Text(self.step_over(cpu))),
+            Action::StepOut => Ok(Reply::Text(self.step_out(cpu))),
+            Action::Frame => {
+                self.stopped = false;
+                self.advance = true;
+                return Ok(Resume::Continue);
+            }
+            Action::Events => {
+                let tui = self.tui.as_mut().unwrap();
+                tui.events = !tui.events;
+                tui.apu = false;
+                if tui.events && cpu.bus.events.is_none() {
+                    cpu.bus.events = Some(Box::default());
+                    Ok(Reply::Text("recording events from here".to_string()))
+                } else {
+                    Ok(Reply::Text(String::new()))
+                }
+            }
+            Action::Apu => {
+                let tui = self.tui.as_mut().unwrap();
+                tui.apu = !tui.apu;
+                tui.events = false;
+                if tui.apu && cpu.bus.apu.scope.is_none() {
+                    cpu.bus.apu.scope = Some(Box::default());
+                    Ok(Reply::Text("recording the APU from here".to_string()))
+                } else {
+                    Ok(Reply::Text(String::new()))
+                }
+            }
+            Action::Continue => Ok(Reply::Resume(Resume::Continue)),
+            Action::Quit => Ok(Reply::Resume(Resume::Quit)),
+            Action::Command(line) => {
+                let hex = &mut self.tui.as_mut().unwrap().hex;
+                if let Some(reply) = hex.command(cpu, &line, &self.symbols) {
+                    reply.map(Reply::Text)
+                } else {
+                    hex.mark(cpu);
+                    let reply = self.command(cpu, &line);
+                    let mut words = line.split_whitespace();
+                    if let (Ok(_), Some("read" | "m"), Some(addr)) =
+                        (&reply, words.next(), words.next())
+                    {
+                        let addr = address(&self.symbols, addr)?;
+                        let hex = &mut self.tui.as_mut().unwrap().hex;
+                        if hex.space != Space::Cpu {
+                            hex.set_space(Space::Cpu);
+                        }
+                        hex.goto(cpu, addr as usize)?;
+                    }
+                    reply
+                }
+            }
+        };
+        let tui = self.tui.as_mut().unwrap();
+        match reply {
+            Ok(Reply::Text(text)) => tui.message = text,
+            Ok(Reply::Resume(Resume::Continue)) => {
+                self.stopped = false;
+                // the game's picture is drawn over a clean screen
+                write!(self.output, "\x1b[2J").map_err(|e| format!("debugger: {}", e))?;
+            }
+            Ok(Reply::Resume(Resume::Quit)) => return Ok(Resume::Quit),
+            Err(e) => tui.message = format!("error: {}", e),
+        }
+        return Ok(Resume::Continue);
+    }
+
+    // the full-screen view, when something changed since it was drawn
+    pub fn draw(&mut self, cpu: &CPU) -> Result<(), String> {
+        let Some(tui) = &self.tui else {
+            return Ok(());
+        };
+        if !std::mem::take(&mut self.dirty) {
+            return Ok(());
+        }
+        let breakpoints: Vec<String> = self.breakpoints.iter().map(|b| b.text.clone()).collect();
+        let screen = tui.render(cpu, &breakpoints, &self.watch_lines(cpu), &self.symbols);
+        return self
+            .output
+            .write_all(screen.as_bytes())
+            .and_then(|_| self.output.flush())
+            .map_err(|e| format!("debugger: {}", e));
+    }
+
+    // commands until one resumes the console
+    pub fn prompt(&mut self, cpu: &mut CPU) -> Result<Resume, String> {
+        self.resumed = true;
+        let error = |e: io::Error| format!("debugger: {}", e);
+        writeln!(self.output, "{}", show_next(cpu, &self.symbols)).map_err(error)?;
+        for line in self.watch_lines(cpu) {
+            writeln!(self.output, "{}", line).map_err(error)?;
+        }
+        loop {
+            write!(self.output, "(rustynes) ").map_err(error)?;
+            self.output.flush().map_err(error)?;
+            let mut line = String::new();
+            if self.input.read_line(&mut line).map_err(error)? == 0 {
+                return Ok(Resume::Continue);
+            }
+            let line = match line.trim() {
+                "" => self.last.clone(),
+                line => line.to_string(),
+            };
+            self.last = line.clone();
+            match self.command(cpu, &line) {
+                Ok(Reply::Text(text)) => write!(self.output, "{}", text).map_err(error)?,
+                Ok(Reply::Resume(resume)) => return Ok(resume),
+                Err(e) => writeln!(self.output, "error: {}", e).map_err(error)?,
+            }
+        }
+    }
+
+    // a line a watch, `1  [lives] = $03 3`
+    fn watch_lines(&self, cpu: &CPU) -> Vec<String> {
+        return self
+            .watches
+            .iter()
+            .enumerate()
+            .map(|(n, watch)| {
+                let value = expr::show(watch.expr.eval(cpu));
+                return format!("{}  {} = {}", n + 1, watch.text, value);
+            })
+            .collect();
+    }
+
+    // past the instruction at the PC, and the subroutine if it's a JSR;
+    // returns why it stopped early, if it did
+    fn step_over(&mut self, cpu: &mut CPU) -> String {
+        let (pc, sp) = (cpu.program_counter, cpu.stack.ptr());
+        if cpu.bus.peek(pc) != JSR {
+            self.step(cpu);
+            return String::new();
+        }
+        let back = pc.wrapping_add(3);
+        return self.step_until(cpu, |cpu, _| {
+            cpu.program_counter == back && cpu.stack.ptr() == sp
+        });
+    }
+
+    fn step_out(&mut self, cpu: &mut CPU) -> String {
+        let sp = cpu.stack.ptr();
+        return self.step_until(cpu, |cpu, code| {
+            matches!(code, RTS | RTI) && cpu.stack.ptr() > sp
+        });
+    }
+
+    // an instruction, with a snapshot first if one is due
+    fn step(&mut self, cpu: &mut CPU) -> bool {
+        self.rewind.checkpoint(cpu);
+        return cpu.step();
+    }
+
+    // back `count` instructions; says why not if it can't
+    fn step_back(&mut self, cpu: &mut CPU, count: u64) -> Result<String, String> {
+        let target = cpu.instructions.saturating_sub(count);
+        self.rewind.checkpoint(cpu);
+        self.rewind.go_to(cpu, target)?;
+        self.settle(cpu);
+        return Ok(String::new());
+    }
+
+    // back to the last instruction before this one where a breakpoint
+    // would have stopped the console, or to the earliest snapshot
+    fn reverse_continue(&mut self, cpu: &mut CPU) -> Result<String, String> {
+        if self.breakpoints.is_empty() {
+            return Err("no breakpoints to go back to".to_string());
+        }
+        let now = cpu.instructions;
+        self.rewind.checkpoint(cpu);
+        let mut last = None;
+        let breakpoints = &mut self.breakpoints;
+        self.rewind.search_back(cpu, now, |cpu, first| {
+            let mut accesses = take_accesses(cpu);
+            if first {
+                // left from the stretch before
+                accesses.clear();
+                for breakpoint in breakpoints.iter_mut() {
+                    breakpoint.settle(cpu);
+                }
+            }
+            let hit = breakpoints.iter_mut().position(|b| b.hits(cpu, &accesses));
+            if let Some(n) = hit {
+                last = Some((cpu.instructions, n, accesses));
+            }
+            return hit.is_some();
+        })?;
+        let reason = match last {
+            Some((at, n, accesses)) => {
+                self.rewind.go_to(cpu, at)?;
+                self.hit_reason(n, &accesses) + "\n"
+            }
+            None => {
+                let earliest = self.rewind.earliest().unwrap_or(now);
+                self.rewind.go_to(cpu, earliest)?;
+                format!(
+                    "no breakpoint in the last {} instructions\n",
+                    now - earliest
+                )
+            }
+        };
+        self.settle(cpu);
+        return Ok(reason);
+    }
+
+    // `breakpoint 2: write 2006, $2006 = $21 written at scanline 17, dot 90`
+    fn hit_reason(&self, n: usize, accesses: &[Access]) -> String {
+        let breakpoint = &self.breakpoints[n];
+        let mut reason = format!("breakpoint {}: {}", n + 1, breakpoint.text);
+        if let Some(access) = breakpoint.accessed(accesses) {
+            reason += &format!(", {}", access);
+        }
+        return reason;
+    }
+
+    // the bus watches the addresses of the access breakpoints, if any
+    fn watch_accesses(&self, cpu: &mut CPU) {
+        let ranges: Vec<AccessRange> = self.breakpoints.iter().filter_map(|b| b.access).collect();
+        cpu.bus.access = match ranges.is_empty() {
+            true => None,
+            false => Some(Box::new(AccessWatch::new(ranges))),
+        };
+    }
+
+    fn settle(&mut self, cpu: &mut CPU) {
+        take_accesses(cpu);
+        for breakpoint in &mut self.breakpoints {
+            breakpoint.settle(cpu);
+        }
+    }
+
+    // instructions until `done`, given each one's opcode after it ran, or a
+    // breakpoint before one, or a jam
+    fn step_until(&mut self, cpu: &mut CPU, mut done: impl FnMut(&CPU, u8) -> bool) -> String {
+        take_accesses(cpu);
+        for n in 0..STEP_LIMIT {
+            if n > 0 {
+                match self.hit(cpu) {
+                    Ok(Some(hit)) => return hit + "\n",
+                    Ok(None) => {}
+                    Err(e) => return e + "\n",
+                }
+            }
+            let code = cpu.bus.peek(cpu.program_counter);
+            if !self.step(cpu) {
+                return "the CPU stopped\n".to_string();
+            }
+            if done(cpu, code) {
+                return String::new();
+            }
+        }
+        return format!("still going after {} instructions\n", STEP_LIMIT);
+    }
+
+    pub fn command(&mut self, cpu: &mut CPU, line: &str) -> Result<Reply, String> {
+        let mut words = line.split_whitespace();
+        let name = words.next().unwrap_or("");
+        let args: Vec<&str> = words.collect();
+        let arg = |n: usize| args.get(n).copied();
+        match name {
+            "step" | "s" => {
+                let count = match arg(0) {
+                    Some(count) => count
+                        .parse::<u32>()
+                        .map_err(|_| format!("{} is not a count", count))?,
+                    None => 1,
+                };
+                for _ in 0..count {
+                    self.step(cpu);
+                }
+                return Ok(Reply::Text(show_next(cpu, &self.symbols) + "\n"));
+            }
+            "step-back" | "sb" => {
+                let count = match arg(0) {
+                    Some(count) => count
+                        .parse::<u64>()
+                        .map_err(|_| format!("{} is not a count", count))?,
+                    None => 1,
+                };
+                let reason = self.step_back(cpu, count)?;
+                return Ok(Reply::Text(reason + &show_next(cpu, &self.symbols) + "\n"));
+            }
+            "reverse-continue" | "rc" => {
+                let reason = self.reverse_continue(cpu)?;
+                return Ok(Reply::Text(reason + &show_next(cpu, &self.symbols) + "\n"));
+            }
+            "over" | "o" => {
+                let reason = self.step_over(cpu);
+                return Ok(Reply::Text(reason + &show_next(cpu, &self.symbols) + "\n"));
+            }
+            "out" | "u" => {
+                let reason = self.step_out(cpu);
+                return Ok(Reply::Text(reason + &show_next(cpu, &self.symbols) + "\n"));
+            }
+            "continue" | "c" => return Ok(Reply::Resume(Resume::Continue)),
+            "quit" | "q" => return Ok(Reply::Resume(Resume::Quit)),
+            "regs" | "r" => return Ok(Reply::Text(registers(cpu) + "\n")),
+            "read" | "m" => {
+                let addr = address(&self.symbols, arg(0).ok_or("read needs an address")?)?;
+                let len = arg(1).map_or(Ok(READ_LENGTH), parse_hex)?;
+                return Ok(Reply::Text(hex_dump(cpu, addr, len)));
+            }
+            "write" | "w" => {
+                let addr = address(&self.symbols, arg(0).ok_or("write needs an address")?)?;
+                if args.len() < 2 {
+                    return Err("write needs bytes to write".to_string());
+                }
+                for (i, text) in args[1..].iter().enumerate() {
+                    let value = parse_hex(text)?;
+                    if value > 0xFF {
+                        return Err(format!("{} does not fit in a byte", text));
+                    }
+                    cpu.mem_write(addr.wrapping_add(i as u16), value as u8);
+                }
+                return Ok(Reply::Text(String::new()));
+            }
+            "disasm" | "d" => {
+                let addr =
+                    arg(0).map_or(Ok(cpu.program_counter), |text| address(&self.symbols, text))?;
+                let count = match arg(1) {
+                    Some(count) => count
+                        .parse::<usize>()
+                        .map_err(|_| format!("{} is not a count", count))?,
+                    None => DISASM_LINES,
+                };
+                let mut text = String::new();
+                let mut addr = addr;
+                for _ in 0..count {
+                    if let Some(name) = self.symbols.name(addr) {
+                        text += &format!("{}:\n", name);
+                    }
+                    let (line, len) = disassemble_with(cpu, addr, &self.symbols);
+                    let marker = if addr == cpu.program_counter {
+                        ">"
+                    } else {
+                        " "
+                    };
+                    text += &format!("{} {}\n", marker, line);
+                    addr = addr.wrapping_add(len);
+                }
+                return Ok(Reply::Text(text));
+            }
+            "pc" => {
+                cpu.program_counter = address(&self.symbols, arg(0).ok_or("pc needs an address")?)?;
+                return Ok(Reply::Text(show_next(cpu, &self.symbols) + "\n"));
+            }
+            "asm" | "a" => {
+                let addr = address(&self.symbols, arg(0).ok_or("asm needs an address")?)?;
+                let code = args[1..].join(" ");
+                if code.is_empty() {
+                    return Err("asm needs an instruction".to_string());
+                }
+                let bytes = asm::assemble_at(&code, addr)?;
+                for (i, &byte) in bytes.iter().enumerate() {
+                    cpu.mem_write(addr.wrapping_add(i as u16), byte);
+                }
+                return Ok(Reply::Text(
+                    disassemble_with(cpu, addr, &self.symbols).0 + "\n",
+                ));
+            }
+            "break" | "b" => {
+                let rest = line[name.len()..].trim();
+                let (kind, range) = match rest.split_once(' ') {
+                    Some((word @ ("read" | "write" | "access"), range)) => {
+                        (Some(AccessKind::parse(word)?), range.trim())
+                    }
+                    _ => (None, rest),
+                };
+                if let Some(kind) = kind {
+                    let (range, condition) = match range.split_once(" if ") {
+                        Some((range, condition)) => (range.trim(), Some(condition)),
+                        None => (range, None),
+                    };
+                    let access =
+                        AccessRange::parse(kind, range, |text| address(&self.symbols, text))?;
+                    let condition = condition
+                        .map(|condition| expr::parse_with(condition, &self.symbols))
+                        .transpose()?;
+                    self.breakpoints.push(Breakpoint {
+                        at: None,
+                        access: Some(access),
+                        condition,
+                        text: rest.to_string(),
+                        was_true: false,
+                    });
+                    self.watch_accesses(cpu);
+                    return Ok(Reply::Text(format!(
+                        "breakpoint {}\n",
+                        self.breakpoints.len()
+                    )));
+                }
+                let (at, condition) = match rest.strip_prefix("if ") {
+                    Some(condition) => (None, Some(condition)),
+                    None => match rest.split_once(" if ") {
+                        Some((at, condition)) => (Some(at), Some(condition)),
+                        None => (Some(rest), None),
+                    },
+                };
+                let at = match at {
+                    Some("") => return Err("break needs an address or a condition".to_string()),
+                    Some(at) => Some(address(&self.symbols, at.trim())?),
+                    None => None,
+                };
+                let condition = condition
+                    .map(|condition| expr::parse_with(condition, &self.symbols))
+                    .transpose()?;
+                self.breakpoints.push(Breakpoint {
+                    at,
+                    access: None,
+                    condition,
+                    text: rest.to_string(),
+                    was_true: false,
+                });
+                return Ok(Reply::Text(format!(
+                    "breakpoint {}\n",
+                    self.breakpoints.len()
+                )));
+            }
+            "calls" | "bt" => {
+                let lines = call_lines(cpu, &self.symbols);
+                return Ok(Reply::Text(
+                    lines.iter().map(|line| line.clone() + "\n").collect(),
+                ));
+            }
+            "breaks" => {
+                let mut text = String::new();
+                for (n, breakpoint) in self.breakpoints.iter().enumerate() {
+                    text += &format!("{}  {}\n", n + 1, breakpoint.text);
+                }
+                return Ok(Reply::Text(text));
+            }
+            "delete" => {
+                let n = arg(0).ok_or("delete needs a breakpoint number")?;
+                match n.parse::<usize>() {
+                    Ok(n) if (1..=self.breakpoints.len()).contains(&n) => {
+                        self.breakpoints.remove(n - 1);
+                        self.watch_accesses(cpu);
+                        return Ok(Reply::Text(String::new()));
+                    }
+                    _ => return Err(format!("no breakpoint {}", n)),
+                }
+            }
+            "print" | "p" => {
+                let text = line[name.len()..].trim();
+                let expr = expr::parse_with(text, &self.symbols)?;
+                return Ok(Reply::Text(expr::show(expr.eval(cpu)) + "\n"));
+            }
+            "watch" => {
+                let text = line[name.len()..].trim();
+                let expr = expr::parse_with(text, &self.symbols)?;
+                self.watches.push(Watch {
+                    expr,
+                    text: text.to_string(),
+                });
+                return Ok(Reply::Text(format!("watch {}\n", self.watches.len())));
+            }
+            "watches" => {
+                let lines = self.watch_lines(cpu);
+                return Ok(Reply::Text(
+                    lines.iter().map(|line| line.clone() + "\n").collect(),
+                ));
+            }
+            "unwatch" => {
+                let n = arg(0).ok_or("unwatch needs a watch number")?;
+                match n.parse::<usize>() {
+                    Ok(n) if (1..=self.watches.len()).contains(&n) => {
+                        self.watches.remove(n - 1);
+                        return Ok(Reply::Text(String::new()));
+                    }
+                    _ => return Err(format!("no watch {}", n)),
+                }
+            }
+            "events" => match arg(0) {
+                Some("on") => {
+                    cpu.bus.events.get_or_insert_with(Box::default);
+                    return Ok(Reply::Text(String::new()));
+                }
+                Some("off") => {
+                    cpu.bus.events = None;
+                    return Ok(Reply::Text(String::new()));
+                }
+                Some(other) => return Err(format!("events takes on or off, not {}", other)),
+                None => {
+                    let log = cpu
+                        .bus
+                        .events
+                        .as_ref()
+                        .ok_or("no event log, try events on")?;
+                    let Some((frame, events)) = log.last() else {
+                        return Ok(Reply::Text("no frame finished yet\n".to_string()));
+                    };
+                    let mut text = format!("frame {}\n", frame);
+                    for event in events {
+                        text += &format!("{}\n", event);
+                    }
+                    return Ok(Reply::Text(text));
+                }
+            },
+            "apu" => match arg(0) {
+                Some("on") => {
+                    cpu.bus.apu.scope.get_or_insert_with(Box::default);
+                    return Ok(Reply::Text(String::new()));
+                }
+                Some("off") => {
+                    cpu.bus.apu.scope = None;
+                    return Ok(Reply::Text(String::new()));
+                }
+                Some(other) => return Err(format!("apu takes on or off, not {}", other)),
+                None => {
+                    let apu = &cpu.bus.apu;
+                    let mut text = String::new();
+                    for (n, channel) in apu.channels().iter().enumerate() {
+                        text += &format!("{}\n", channel);
+                        if let Some(scope) = &apu.scope {
+                            let levels = scope.history(n, SCOPE_COLUMNS);
+                            text += &format!("  {}\n", waveform(&levels, channel.max, 1)[0]);
+                        }
+                    }
+                    return Ok(Reply::Text(text));
+                }
+            },
+            "unofficial" => {
+                self.unofficial = match arg(0) {
+                    Some("break") => OnUnofficial::Break,
+                    Some("log") => OnUnofficial::Log,
+                    Some("off") => OnUnofficial::Off,
+                    _ => return Err("unofficial takes break, log or off".to_string()),
+                };
+                self.unofficial_seen = [false; 256];
+                return Ok(Reply::Text(String::new()));
+            }
+            "search" => {
+                let rest = line[name.len()..].trim();
+                match arg(0) {
+                    None => return Err("search needs start, a filter, list or freeze".to_string()),
+                    Some("start") => {
+                        let search = CheatSearch::start(&cpu.bus);
+                        let count = search.candidates().len();
+                        self.search = Some(search);
+                        return Ok(Reply::Text(format!("{} addresses\n", count)));
+                    }
+                    Some(_) => {}
+                }
+                let search = self.search.as_mut().ok_or("no search, try search start")?;
+                match arg(0) {
+                    Some("list") => {
+                        let mut text = String::new();
+                        for (addr, value) in search.candidates().iter().take(READ_LENGTH as usize) {
+                            text += &format!("{:04X}  {:02X}\n", addr, value);
+                        }
+                        if search.candidates().len() > READ_LENGTH as usize {
+                            text += &format!(
+                                "and {} more\n",
+                                search.candidates().len() - READ_LENGTH as usize
+                            );
+                        }
+                        return Ok(Reply::Text(text));
+                    }
+                    Some("freeze") => {
+                        let value = match arg(1) {
+                            Some(text) => match parse_hex(text)? {
+                                value @ 0..=0xFF => Some(value as u8),
+                                _ => return Err(format!("{} does not fit in a byte", text)),
+                            },
+                            None => None,
+                        };
+                        let codes = search.freeze(cpu.bus.cheats_mut(), value)?;
+                        return Ok(Reply::Text(format!("cheats {}\n", codes.join(" "))));
+                    }
+                    _ => {
+                        let count = search.filter(&cpu.bus, Filter::parse(rest)?);
+                        return Ok(Reply::Text(format!("{} addresses\n", count)));
+                    }
+                }
+            }
+            "help" | "h" | "?" => {
+                return Ok(Reply::Text(
+                    "step [N], over, out, continue, regs, read ADDR [LEN], write ADDR BYTE..., \
+                 disasm [ADDR] [N], pc ADDR, asm ADDR CODE, break ADDR [if COND], \
+                 break if COND, breaks, calls, delete N, print EXPR, watch EXPR, watches, \
+                 unwatch N, events [on|off], search start|FILTER|list|freeze [BYTE], quit\n"
+                        .to_string(),
+                ))
+            }
+            _ => return Err(format!("unknown command {}, try help", name)),
+        }
+    }
+}
+
+fn parse_hex(text: &str) -> Result<u16, String> {
+    let digits = text.strip_prefix('$').unwrap_or(text);
+    return u16::from_str_radix(digits, 16).map_err(|_| format!("{} is not a hex number", text));
+}
+
+// the calls the CPU is in, the outermost first
+pub fn call_stack(cpu: &CPU) -> &[Frame] {
+    return cpu.calls.frames();
+}
+
+// a line a call, the innermost first: where it went, where it returns to
+// and whether it was an interrupt
+// what the bus saw of the access breakpoints' addresses since last time
+fn take_accesses(cpu: &mut CPU) -> Vec<Access> {
+    return cpu
+        .bus
+        .access
+        .as_mut()
+        .map_or(Vec::new(), |watch| watch.take());
+}
+
+fn call_lines(cpu: &CPU, symbols: &Symbols) -> Vec<String> {
+    return call_stack(cpu)
+        .iter()
+        .rev()
+        .map(|frame| {
+            let entry = symbols
+                .name(frame.entry)
+                .map_or(format!("{:04X}", frame.entry), |name| name.to_string());
+            let kind = if frame.interrupt { "  interrupt" } else { "" };
+            return format!("{:<16} {:04X}{}", entry, frame.return_to, kind);
+        })
+        .collect();
+}
+
+// a label or `file:line` when the symbols know it, otherwise hex
+fn address(symbols: &Symbols, text: &str) -> Result<u16, String> {
+    return match symbols.lookup(text) {
+        Some(addr) => Ok(addr),
+        None => parse_hex(text),
+    };
+}
+
+pub fn registers(cpu: &CPU) -> String {
+    let flags: String = "NV-BDIZC"
+        .chars()
+        .enumerate()
+        .map(|(i, name)| match cpu.status.bits() & (0x80 >> i) {
+            0 => name.to_ascii_lowercase(),
+            _ => name,
+        })
+        .collect();
+    return format!(
+        "PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} {} SP:{:02X} CYC:{}",
+        cpu.program_counter,
+        cpu.register_a,
+        cpu.register_x,
+        cpu.register_y,
+        cpu.status.bits(),
+        flags,
+        cpu.stack.ptr(),
+        cpu.cycles
+    );
+}
+
+// the registers and the instruction about to run, with its source line
+// when the symbols have one
+fn show_next(cpu: &CPU, symbols: &Symbols) -> String {
+    let pc = cpu.program_counter;
+    let source = match symbols.source(pc) {
+        Some(source) => format!("{}\n", source),
+        None => String::new(),
+    };
+    return format!(
+        "{}\n{}> {}",
+        registers(cpu),
+        source,
+        disassemble_with(cpu, pc, symbols).0
+    );
+}
+
+fn hex_dump(cpu: &CPU, addr: u16, len: u16) -> String {
+    let mut text = String::new();
+    for row in (0..len).step_by(16) {
+        let start = addr.wrapping_add(row);
+        let bytes: Vec<String> = (0..16.min(len - row))
+            .map(|i| format!("{:02X}", cpu.bus.peek(start.wrapping_add(i))))
+            .collect();
+        text += &format!("{:04X}  {}\n", start, bytes.join(" "));
+    }
+    return text;
+}
+
+// the instruction at `addr` as `8000  A9 05     LDA #$05`, and its length
+pub fn disassemble(cpu: &CPU, addr: u16) -> (String, u16) {
+    return disassemble_with(cpu, addr, &Symbols::default());
+}
+
+// with the operand's label, `JSR read_pad`, where `symbols` has one
+pub fn disassemble_with(cpu: &CPU, addr: u16, symbols: &Symbols) -> (String, u16) {
+    let bytes = [0, 1, 2].map(|offset| cpu.bus.peek(addr.wrapping_add(offset)));
+    return disassemble_labelled(addr, bytes, symbols);
+}
+
+// the same for an instruction's bytes as they were, looked at again later
+pub fn disassemble_bytes(addr: u16, bytes: [u8; 3]) -> (String, u16) {
+    return disassemble_labelled(addr, bytes, &Symbols::default());
+}
+
+fn disassemble_labelled(addr: u16, bytes: [u8; 3], symbols: &Symbols) -> (String, u16) {
+    let (text, len) = instruction(addr, bytes, |addr| symbols.name(addr).map(str::to_string));
+    let hex: Vec<String> = (0..len)
+        .map(|i| format!("{:02X}", bytes[i as usize]))
+        .collect();
+    let text = format!("{:04X}  {:<8}  {}", addr, hex.join(" "), text);
+    return (text, len);
+}
+
+// the instruction alone, `LDA #$05` or `.byte $02` for an opcode that isn't
+// one, with `name`'s names for the addresses it has them for, and its length
+pub fn instruction(
+    addr: u16,
+    bytes: [u8; 3],
+    name: impl Fn(u16) -> Option<String>,
+) -> (String, u16) {
+    let peek = |offset: u16| bytes[offset as usize];
+    let code = peek(0);
+    let Some(op) = NMOS_6502_OPCODES_MAP.get(&code) else {
+        return (format!(".byte ${:02X}", code), 1);
+    };
+    let len = op.len as u16;
+    let byte = peek(1);
+    let word = (peek(2) as u16) << 8 | byte as u16;
+    // labels where there are names, hex where not
+    let zero_page = name(byte as u16).unwrap_or(format!("${:02X}", byte));
+    let absolute = |target: u16| {
+        return name(target).unwrap_or(format!("${:04X}", target));
+    };
+    let operand = match (op.mode, len) {
+        (AddressingMode::Immediate, _) => format!("#${:02X}", byte),
+        (AddressingMode::ZeroPage, _) => zero_page,
+        (AddressingMode::ZeroPage_X, _) => format!("{},X", zero_page),
+        (AddressingMode::ZeroPage_Y, _) => format!("{},Y", zero_page),
+        (AddressingMode::Absolute, _) => absolute(word),
+        (AddressingMode::Absolute_X, _) => format!("{},X", absolute(word)),
+        (AddressingMode::Absolute_Y, _) => format!("{},Y", absolute(word)),
+        (AddressingMode::Indirect_X, _) => format!("({},X)", zero_page),
+        (AddressingMode::Indirect_Y, _) => format!("({}),Y", zero_page),
+        (AddressingMode::Indirect, _) => format!("({})", absolute(word)),
+        // the table's NoneAddressing, which also covers JMP indirect
+        (AddressingMode::NoneAddressing, 3) if code == 0x6C => format!("({})", absolute(word)),
+        (AddressingMode::NoneAddressing, 3) => absolute(word),
+        // branches, shown with where they go
+        (AddressingMode::NoneAddressing, 2) => {
+            absolute(addr.wrapping_add(2).wrapping_add(byte as i8 as u16))
+        }
+        (AddressingMode::NoneAddressing, _) if matches!(code, 0x0A | 0x2A | 0x4A | 0x6A) => {
+            "A".to_string()
+        }
+        (AddressingMode::NoneAddressing, _) => String::new(),
+    };
+    return (
+        format!("{} {}", op.mnemonic, operand)
+            .trim_end()
+            .to_string(),
+        len,
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+    use crate::cartridge::Rom;
+    use std::io::Cursor;
+    use std::sync::{Arc, Mutex};
+
+    struct SharedOutput(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedOutput {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(data);
+            return Ok(data.len());
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            return Ok(());
+        }
+    }
+
+    fn cpu_with(source: &str) -> CPU {
+        let mut cpu = CPU::new();
+        for (i, byte) in asm::assemble_at(source, 0x0600)
+            .unwrap()
+            .into_iter()
+            .enumerate()
+        {
+            cpu.mem_write(0x0600 + i as u16, byte);
+        }
+        cpu.program_counter = 0x0600;
+        return cpu;
+    }
+
+    #[test]
+    fn test_commands() {
+        let mut cpu = cpu_with("LDX #$04\nloop: DEX\nBNE loop\nJMP ($1234)\nASL A\nSTA $10,X");
+        let mut debugger = Debugger::new(Box::new(io::empty()), Box::new(io::sink()));
+        let text = |reply: Result<Reply, String>| match reply.unwrap() {
+            Reply::Text(text) => return text,
+            reply => panic!("{:?}", reply),
+        };
+        assert_eq!(
+            text(debugger.command(&mut cpu, "d 0600 6")),
+            "> 0600  A2 04     LDX #$04\n  \
+             0602  CA        DEX\n  \
+             0603  D0 FD     BNE $0602\n  \
+             0605  6C 34 12  JMP ($1234)\n  \
+             0608  0A        ASL A\n  \
+             0609  95 10     STA $10,X\n"
+        );
+        let stepped = text(debugger.command(&mut cpu, "step 2"));
+        assert!(stepped.starts_with("PC:0603 A:00 X:03 Y:00"));
+        assert!(stepped.ends_with("> 0603  D0 FD     BNE $0602\n"));
+
+        text(debugger.command(&mut cpu, "w $10 AB cd"));
+        assert_eq!(
+            text(debugger.command(&mut cpu, "m 10 3")),
+            "0010  AB CD 00\n"
+        );
+        assert_eq!(
+            text(debugger.command(&mut cpu, "asm 0700 LDA #$01")),
+            "0700  A9 01     LDA #$01\n"
+        );
+        text(debugger.command(&mut cpu, "pc 0700"));
+        debugger.command(&mut cpu, "s").unwrap();
+        assert_eq!(cpu.register_a, 1);
+
+        assert_eq!(
+            debugger.command(&mut cpu, "q"),
+            Ok(Reply::Resume(Resume::Quit))
+        );
+        assert!(debugger.command(&mut cpu, "m").is_err());
+        assert!(debugger.command(&mut cpu, "w 10 100").is_err());
+        assert!(debugger.command(&mut cpu, "jump").is_err());
+    }
+
+    #[test]
+    fn test_conditional_breakpoints() {
+        let mut raw = test_rom(0, 1, 1);
+        let program = asm::assemble_at("reset: INX\nSTX $10\nJMP reset", 0x8000).unwrap();
+        raw[16..16 + program.len()].copy_from_slice(&program);
+        raw[16 + 0x3FFC..16 + 0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        let mut nes = Nes::new(Rom::new(&raw).unwrap()).unwrap();
+
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let input = Cursor::new("r\nc\nq\n");
+        let mut debugger =
+            Debugger::new(Box::new(input), Box::new(SharedOutput(Arc::clone(&output))));
+        debugger.command(&mut nes.cpu, "b 8003 if x == 5").unwrap();
+        debugger
+            .command(&mut nes.cpu, "break if [$10] == 3")
+            .unwrap();
+        assert!(debugger.command(&mut nes.cpu, "b").is_err());
+        assert!(debugger.command(&mut nes.cpu, "b if x =").is_err());
+        assert_eq!(
+            debugger.command(&mut nes.cpu, "breaks"),
+            Ok(Reply::Text(
+                "1  8003 if x == 5\n2  if [$10] == 3\n".to_string()
+            ))
+        );
+
+        // the memory one first, once only while it stays true, then the other
+        assert_eq!(debugger.run_frame(&mut nes), Ok(Resume::Quit));
+        assert_eq!(nes.cpu.register_x, 5);
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        let first = output.find("breakpoint 2: if [$10] == 3\nPC:8003").unwrap();
+        let second = output
+            .find("breakpoint 1: 8003 if x == 5\nPC:8003")
+            .unwrap();
+        assert!(first < second);
+        assert_eq!(output.matches("breakpoint").count(), 2);
+
+        debugger.command(&mut nes.cpu, "delete 2").unwrap();
+        assert!(debugger.command(&mut nes.cpu, "delete 2").is_err());
+        assert!(debugger.has_breakpoints());
+    }
+
+    #[test]
+    fn test_full_screen() {
+        let mut raw = test_rom(0, 1, 1);
+        let program = asm::assemble_at("reset: INX\nSTX $10\nJMP reset", 0x8000).unwrap();
+        raw[16..16 + program.len()].copy_from_slice(&program);
+        raw[16 + 0x3FFC..16 + 0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        let mut nes = Nes::new(Rom::new(&raw).unwrap()).unwrap();
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let mut debugger = Debugger::full_screen(Box::new(SharedOutput(Arc::clone(&output))));
+        let screen = |output: &Arc<Mutex<Vec<u8>>>| {
+            let text = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+            output.lock().unwrap().clear();
+            return text;
+        };
+
+        assert!(debugger.take_break());
+        assert_eq!(debugger.stop(&mut nes.cpu, ""), Ok(Resume::Continue));
+        assert!(debugger.is_stopped());
+        debugger.draw(&nes.cpu).unwrap();
+        assert!(screen(&output).contains("PC:8000"));
+        debugger.draw(&nes.cpu).unwrap();
+        assert_eq!(screen(&output), "");
+
+        debugger.key("S", &mut nes.cpu).unwrap();
+        assert_eq!(nes.cpu.register_x, 1);
+        let typed = ": B Space 8 0 0 3 Space I F Space X Space = = Space 3 Return";
+        for key in typed.split(' ') {
+            debugger.key(key, &mut nes.cpu).unwrap();
+        }
+        debugger.draw(&nes.cpu).unwrap();
+        assert!(screen(&output).contains("1  8003 if x == 3"));
+        debugger.key("C", &mut nes.cpu).unwrap();
+        assert!(!debugger.is_stopped());
+        assert!(screen(&output).starts_with("\x1b[2J"));
+
+        // stopped partway through the frame, then on to its end
+        assert_eq!(debugger.run_frame(&mut nes), Ok(Resume::Continue));
+        assert!(debugger.is_stopped());
+        assert_eq!(nes.cpu.register_x, 3);
+        assert_eq!(nes.frame_count(), 0);
+        debugger.draw(&nes.cpu).unwrap();
+        assert!(screen(&output).contains("breakpoint 1: 8003 if x == 3"));
+        // x comes round to 3 again within the frame
+        debugger.command(&mut nes.cpu, "delete 1").unwrap();
+        debugger.key("N", &mut nes.cpu).unwrap();
+        debugger.run_frame(&mut nes).unwrap();
+        assert!(debugger.is_stopped());
+        assert_eq!(nes.frame_count(), 1);
+        debugger.draw(&nes.cpu).unwrap();
+        assert!(screen(&output).contains("frame 1"));
+
+        // what the game changes shows in bold
+        for key in ": R E A D Space 1 0 Return : S Space 3 Return".split(' ') {
+            debugger.key(key, &mut nes.cpu).unwrap();
+        }
+        debugger.draw(&nes.cpu).unwrap();
+        let x = nes.cpu.register_x;
+        assert!(screen(&output).contains(&format!("0010  \x1b[1m{:02X}\x1b[0m 00", x)));
+        assert_eq!(debugger.key("Q", &mut nes.cpu), Ok(Resume::Quit));
+    }
+
+    #[test]
+    fn test_symbols() {
+        let mut cpu = cpu_with("start: JSR $0610\nLDA $F0\nBNE start");
+        let mut debugger = Debugger::new(Box::new(io::empty()), Box::new(io::sink()));
+        let text = "version\tmajor=2,minor=0\n\
+            file\tid=0,name=\"main.s\",size=1,mtime=0,mod=0\n\
+            seg\tid=0,name=\"CODE\",start=0x000600,size=0x20,type=rw\n\
+            span\tid=0,seg=0,start=3,size=2\n\
+            line\tid=0,file=0,line=7,span=0\n\
+            sym\tid=0,name=\"start\",val=0x600,type=lab\n\
+            sym\tid=1,name=\"read_pad\",val=0x610,type=lab\n\
+            sym\tid=2,name=\"lives\",val=0xF0,type=lab\n";
+        debugger.set_symbols(symbols::parse_dbg(text).unwrap());
+        assert_eq!(
+            debugger.command(&mut cpu, "d start 3"),
+            Ok(Reply::Text(
+                "start:\n> 0600  20 10 06  JSR read_pad\n  \
+                 0603  A5 F0     LDA lives\n  \
+                 0605  D0 F9     BNE start\n"
+                    .to_string()
+            ))
+        );
+        debugger.command(&mut cpu, "b main.s:7").unwrap();
+        assert_eq!(debugger.breakpoints[0].at, Some(0x0603));
+        debugger.command(&mut cpu, "pc 0603").unwrap();
+        assert!(show_next(&cpu, &debugger.symbols).contains("\nmain.s:7\n> 0603"));
+        assert!(debugger.command(&mut cpu, "b main.s:8").is_err());
+    }
+
+    #[test]
+    fn test_print_and_watches() {
+        let mut cpu = cpu_with("LDX #$02\nINC $43");
+        cpu.mem_write(0x0020, 0x41);
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let mut debugger = Debugger::new(
+            Box::new(Cursor::new("s\n")),
+            Box::new(SharedOutput(Arc::clone(&output))),
+        );
+        debugger.set_symbols(symbols::parse_labels("al 0020 .ptr").unwrap());
+        assert_eq!(
+            debugger.command(&mut cpu, "print [ptr] + 2 << 4"),
+            Ok(Reply::Text("$0430 1072\n".to_string()))
+        );
+        assert!(debugger.command(&mut cpu, "p [ptr").is_err());
+
+        debugger.command(&mut cpu, "watch x").unwrap();
+        assert_eq!(
+            debugger.command(&mut cpu, "watch [[ptr] + x]"),
+            Ok(Reply::Text("watch 2\n".to_string()))
+        );
+        debugger.command(&mut cpu, "b if [[ptr] + x] == 1").unwrap();
+        debugger.command(&mut cpu, "s 2").unwrap();
+        assert_eq!(
+            debugger.command(&mut cpu, "watches"),
+            Ok(Reply::Text(
+                "1  x = $02 2\n2  [[ptr] + x] = $01 1\n".to_string()
+            ))
+        );
+        assert!(debugger.breakpoints[0].hits(&cpu, &[]));
+
+        // shown whenever it stops
+        debugger.prompt(&mut cpu).unwrap();
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert!(output.contains("\n1  x = $02 2\n2  [[ptr] + x] = $01 1\n(rustynes) "));
+
+        debugger.command(&mut cpu, "unwatch 1").unwrap();
+        assert!(debugger.command(&mut cpu, "unwatch 2").is_err());
+        assert_eq!(debugger.watches[0].text, "[[ptr] + x]");
+    }
+
+    #[test]
+    fn test_events() {
+        let mut raw = test_rom(0, 1, 1);
+        let program = asm::assemble_at(
+            "reset: SEI\nLDA #$80\nSTA $2000\nhalt: JMP halt\nnmi: LDA #$07\nSTA $2005\nRTI",
+            0x8000,
+        )
+        .unwrap();
+        raw[16..16 + program.len()].copy_from_slice(&program);
+        raw[16 + 0x3FFA..16 + 0x3FFC].copy_from_slice(&[0x09, 0x80]);
+        raw[16 + 0x3FFC..16 + 0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        let mut nes = Nes::new(Rom::new(&raw).unwrap()).unwrap();
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let mut debugger = Debugger::full_screen(Box::new(SharedOutput(Arc::clone(&output))));
+        assert_eq!(
+            debugger.command(&mut nes.cpu, "events").err().unwrap(),
+            "no event log, try events on"
+        );
+        debugger.command(&mut nes.cpu, "events on").unwrap();
+        assert_eq!(
+            debugger.command(&mut nes.cpu, "events"),
+            Ok(Reply::Text("no frame finished yet\n".to_string()))
+        );
+
+        nes.run_frame();
+        nes.run_frame();
+        let Ok(Reply::Text(text)) = debugger.command(&mut nes.cpu, "events") else {
+            panic!("no events");
+        };
+        // the second frame's NMI is still to come, so the first is the last one
+        assert_eq!(
+            text,
+            "frame 0\n  0, 21  $2000 = 80\n241,  4  NMI\n241, 40  $2005 = 07\n"
+        );
+
+        // and as the full-screen grid, in place of the panes
+        assert!(debugger.take_break());
+        debugger.stop(&mut nes.cpu, "").unwrap();
+        debugger.key("E", &mut nes.cpu).unwrap();
+        debugger.draw(&nes.cpu).unwrap();
+        let screen = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        let rows: Vec<&str> = screen.split("\r\n").collect();
+        assert_eq!(rows.len(), 25);
+        assert!(rows[2].contains(" Events, frame 0: 3 "));
+        assert!(rows[3].starts_with("  0 ....C..."));
+        assert!(rows[20].starts_with("234 N.......S"));
+        assert!(!screen.contains(" Disassembly"));
+    }
+
+    #[test]
+    fn test_apu() {
+        let mut raw = test_rom(0, 1, 1);
+        let program = asm::assemble_at(
+            "reset: SEI\nLDA #$01\nSTA $4015\nLDA #$BF\nSTA $4000\nLDA #$FD\nSTA $4002\n\
+             LDA #$08\nSTA $4003\nhalt: JMP halt",
+            0x8000,
+        )
+        .unwrap();
+        raw[16..16 + program.len()].copy_from_slice(&program);
+        raw[16 + 0x3FFC..16 + 0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        let mut nes = Nes::new(Rom::new(&raw).unwrap()).unwrap();
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let mut debugger = Debugger::full_screen(Box::new(SharedOutput(Arc::clone(&output))));
+        let text = |reply: Result<Reply, String>| match reply.unwrap() {
+            Reply::Text(text) => return text,
+            reply => panic!("{:?}", reply),
+        };
+        assert_eq!(
+            text(debugger.command(&mut nes.cpu, "apu")).lines().count(),
+            5
+        );
+        assert!(debugger.command(&mut nes.cpu, "apu loud").is_err());
+
+        debugger.command(&mut nes.cpu, "apu on").unwrap();
+        nes.run_frame();
+        let shown = text(debugger.command(&mut nes.cpu, "apu"));
+        let lines: Vec<&str> = shown.lines().collect();
+        assert_eq!(lines.len(), 10);
+        assert!(lines[0].starts_with("pulse 1   $0FD   440.4 Hz  volume  15  duty 50%"));
+        assert_eq!(lines[1].chars().filter(|&c| c == '█').count(), 32);
+        assert_eq!(lines[3].trim(), "");
+
+        // and full screen, in place of the panes
+        assert!(debugger.take_break());
+        debugger.stop(&mut nes.cpu, "").unwrap();
+        debugger.key("A", &mut nes.cpu).unwrap();
+        debugger.draw(&nes.cpu).unwrap();
+        let screen = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        let rows: Vec<&str> = screen.split("\r\n").collect();
+        assert_eq!(rows.len(), 25);
+        assert!(rows[2].contains(" APU, 64 CPU cycles a column"));
+        assert!(rows[3].starts_with("pulse 1 "));
+        assert!(rows[4].contains("████"));
+        assert!(rows[6].starts_with("pulse 2 "));
+        assert!(rows[15].starts_with("dmc "));
+        debugger.command(&mut nes.cpu, "apu off").unwrap();
+        assert!(nes.cpu.bus.apu.scope.is_none());
+    }
+
+    #[test]
+    fn test_unofficial_opcodes() {
+        let mut raw = test_rom(0, 1, 1);
+        let program =
+            asm::assemble_at("reset: SEI\nLDX #$03\nDEX\nBNE $FD\n.byte $0B, $10", 0x8000);
+        let program = program.unwrap();
+        raw[16..16 + program.len()].copy_from_slice(&program);
+        raw[16 + 0x3FFC..16 + 0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        let rom = Rom::new(&raw).unwrap();
+
+        // logged on the way into the jam
+        let mut nes = Nes::new(rom.clone()).unwrap();
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let mut debugger = Debugger::new(
+            Box::new(io::empty()),
+            Box::new(SharedOutput(Arc::clone(&output))),
+        );
+        assert!(debugger.command(&mut nes.cpu, "unofficial").is_err());
+        debugger.command(&mut nes.cpu, "unofficial log").unwrap();
+        debugger.run_frame(&mut nes).unwrap();
+        assert!(nes.cpu.jammed);
+        let logged = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert_eq!(logged, "unofficial opcode $0B at $8006: 0B 10 00\n");
+
+        // or stopped in front of it, with the X loop done
+        let mut nes = Nes::new(rom).unwrap();
+        let mut debugger = Debugger::full_screen(Box::new(io::sink()));
+        debugger.command(&mut nes.cpu, "unofficial break").unwrap();
+        debugger.run_frame(&mut nes).unwrap();
+        assert!(debugger.is_stopped());
+        assert!(!nes.cpu.jammed);
+        assert_eq!(nes.cpu.program_counter, 0x8006);
+        assert_eq!(nes.cpu.register_x, 0);
+        debugger.key("C", &mut nes.cpu).unwrap();
+        debugger.run_frame(&mut nes).unwrap();
+        assert!(nes.cpu.jammed);
+    }
+
+    #[test]
+    fn test_access_breakpoints() {
+        let mut raw = test_rom(0, 1, 1);
+        let program = asm::assemble_at(
+            "reset: SEI\nLDA #$21\nSTA $3FFE\nLDX #$01\nSTX $4016\nloop: JMP loop",
+            0x8000,
+        );
+        let program = program.unwrap();
+        raw[16..16 + program.len()].copy_from_slice(&program);
+        raw[16 + 0x3FFC..16 + 0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        let mut nes = Nes::new(Rom::new(&raw).unwrap()).unwrap();
+        let mut debugger = Debugger::full_screen(Box::new(io::sink()));
+        assert!(debugger
+            .command(&mut nes.cpu, "break read 2007-2000")
+            .is_err());
+        assert!(nes.cpu.bus.access.is_none());
+
+        // the write through the mirror, stopped just after it
+        debugger.command(&mut nes.cpu, "break write 2006").unwrap();
+        debugger
+            .command(&mut nes.cpu, "break write 4016 if x == 1")
+            .unwrap();
+        debugger.run_frame(&mut nes).unwrap();
+        assert!(debugger.is_stopped());
+        assert_eq!(nes.cpu.program_counter, 0x8006);
+        let message = debugger.tui.as_ref().unwrap().message.clone();
+        assert!(message.starts_with("breakpoint 1: write 2006, $3FFE = $21 written at scanline"));
+
+        debugger.key("C", &mut nes.cpu).unwrap();
+        debugger.run_frame(&mut nes).unwrap();
+        assert!(debugger.is_stopped());
+        assert_eq!(nes.cpu.program_counter, 0x800B);
+        let message = debugger.tui.as_ref().unwrap().message.clone();
+        assert!(message.starts_with("breakpoint 2: write 4016 if x == 1, $4016 = $01 written"));
+
+        debugger.command(&mut nes.cpu, "delete 1").unwrap();
+        assert!(nes.cpu.bus.access.is_some());
+        debugger.command(&mut nes.cpu, "delete 1").unwrap();
+        assert!(nes.cpu.bus.access.is_none());
+    }
+
+    #[test]
+    fn test_step_over_and_out() {
+        let mut cpu = cpu_with("JSR sub\nINX\nBRK\nsub: JSR leaf\nINY\nRTS\nleaf: LDA #$01\nRTS");
+        let mut debugger = Debugger::new(Box::new(io::empty()), Box::new(io::sink()));
+        let next = |reply: Result<Reply, String>| match reply.unwrap() {
+            Reply::Text(text) => return text.lines().last().unwrap().to_string(),
+            reply => panic!("{:?}", reply),
+        };
+        assert_eq!(
+            next(debugger.command(&mut cpu, "o")),
+            "> 0603  E8        INX"
+        );
+        assert_eq!((cpu.register_a, cpu.register_y), (1, 1));
+        assert_eq!(
+            next(debugger.command(&mut cpu, "o")),
+            "> 0604  00        BRK"
+        );
+
+        debugger.command(&mut cpu, "pc 0600").unwrap();
+        debugger.command(&mut cpu, "s 2").unwrap();
+        assert_eq!(
+            next(debugger.command(&mut cpu, "u")),
+            "> 0608  C8        INY"
+        );
+        assert_eq!(
+            next(debugger.command(&mut cpu, "out")),
+            "> 0603  E8        INX"
+        );
+
+        debugger.command(&mut cpu, "pc 0600").unwrap();
+        debugger.command(&mut cpu, "b 060C").unwrap();
+        let Ok(Reply::Text(text)) = debugger.command(&mut cpu, "over") else {
+            panic!();
+        };
+        assert!(text.starts_with("breakpoint 1: 060C\nPC:060C"));
+    }
+
+    #[test]
+    fn test_step_back_and_reverse_continue() {
+        let mut raw = test_rom(0, 1, 1);
+        let program = asm::assemble_at("reset: SEI\nloop: INX\nSTX $0300\nJMP loop", 0x8000);
+        let program = program.unwrap();
+        raw[16..16 + program.len()].copy_from_slice(&program);
+        raw[16 + 0x3FFC..16 + 0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        let mut nes = Nes::new(Rom::new(&raw).unwrap()).unwrap();
+        let mut debugger = Debugger::full_screen(Box::new(io::sink()));
+        let text = |reply: Result<Reply, String>| match reply.unwrap() {
+            Reply::Text(text) => return text,
+            reply => panic!("{:?}", reply),
+        };
+        debugger.run_frame(&mut nes).unwrap();
+        debugger.run_frame(&mut nes).unwrap();
+        let (x, pc) = (nes.cpu.register_x, nes.cpu.program_counter);
+
+        // a time round the loop is three instructions
+        text(debugger.command(&mut nes.cpu, "sb 3"));
+        assert_eq!(nes.cpu.register_x, x.wrapping_sub(1));
+        assert_eq!(nes.cpu.program_counter, pc);
+        assert!(debugger.command(&mut nes.cpu, "rc").is_err());
+
+        // just after the write that made it so, and the write before that
+        debugger
+            .command(&mut nes.cpu, "break if [$0300] == 3")
+            .unwrap();
+        let stopped = text(debugger.command(&mut nes.cpu, "rc"));
+        assert!(stopped.starts_with("breakpoint 1: if [$0300] == 3\nPC:8005 A:00 X:03"));
+        let at = nes.cpu.instructions;
+        let stepped = text(debugger.command(&mut nes.cpu, "step-back"));
+        assert!(stepped.ends_with("> 8002  8E 00 03  STX $0300\n"));
+        assert_eq!(nes.cpu.bus.peek(0x0300), 2);
+        text(debugger.command(&mut nes.cpu, "reverse-continue"));
+        assert_eq!(nes.cpu.instructions, at - 256 * 3);
+
+        // going forwards again from there stops at the next one
+        text(debugger.command(&mut nes.cpu, "s 4"));
+        debugger.key("B", &mut nes.cpu).unwrap();
+        assert_eq!(nes.cpu.instructions, at - 256 * 3 + 3);
+        debugger.key("R", &mut nes.cpu).unwrap();
+        assert_eq!(nes.cpu.instructions, at - 256 * 3);
+        // the first frame's snapshot is as far as it goes
+        text(debugger.command(&mut nes.cpu, "sb 1000000"));
+        assert_eq!(nes.cpu.instructions, 0);
+        assert_eq!(nes.cpu.program_counter, 0x8000);
+    }
+
+    #[test]
+    fn test_step_over_an_interrupt() {
+        // the subroutine waits for the NMI handler to set $10
+        let mut raw = test_rom(0, 1, 1);
+        let program = asm::assemble_at(
+            "nmi: INC $10\nRTI\n\
+             reset: LDA #$80\nSTA $2000\n\
+             loop: JSR wait\nINX\nJMP loop\n\
+             wait: LDA $10\nBEQ wait\nRTS",
+            0x8000,
+        )
+        .unwrap();
+        raw[16..16 + program.len()].copy_from_slice(&program);
+        raw[16 + 0x3FFA..16 + 0x3FFE].copy_from_slice(&[0x00, 0x80, 0x03, 0x80]);
+        let mut nes = Nes::new(Rom::new(&raw).unwrap()).unwrap();
+        let mut debugger = Debugger::new(Box::new(io::empty()), Box::new(io::sink()));
+        debugger.command(&mut nes.cpu, "s 2").unwrap();
+        assert_eq!(nes.cpu.program_counter, 0x8008);
+        let sp = nes.cpu.stack.ptr();
+        debugger.command(&mut nes.cpu, "over").unwrap();
+        assert_eq!(nes.cpu.program_counter, 0x800B);
+        assert_eq!(nes.cpu.stack.ptr(), sp);
+        assert_eq!(nes.cpu.bus.peek(0x0010), 1);
+    }
+
+    #[test]
+    fn test_call_stack() {
+        let mut cpu = cpu_with("JSR sub\nBRK\nsub: JSR leaf\nRTS\nleaf: PLA\nPLA\nRTS");
+        let mut debugger = Debugger::new(Box::new(io::empty()), Box::new(io::sink()));
+        debugger.set_symbols(symbols::parse_labels("al 0604 .sub").unwrap());
+        debugger.command(&mut cpu, "s 2").unwrap();
+        assert_eq!(
+            debugger.command(&mut cpu, "bt"),
+            Ok(Reply::Text(
+                "0608             0607\nsub              0603\n".to_string()
+            ))
+        );
+        // leaf pulls its own return address and returns straight to the top
+        debugger.command(&mut cpu, "s 3").unwrap();
+        assert_eq!(cpu.program_counter, 0x0603);
+        assert!(call_stack(&cpu).is_empty());
+    }
+
+    #[test]
+    fn test_cheat_search() {
+        let mut cpu = cpu_with("DEC $75");
+        cpu.mem_write(0x75, 3);
+        let mut debugger = Debugger::new(Box::new(io::empty()), Box::new(io::sink()));
+        assert!(debugger.command(&mut cpu, "search less").is_err());
+        assert_eq!(
+            debugger.command(&mut cpu, "search start"),
+            Ok(Reply::Text("2048 addresses\n".to_string()))
+        );
+        debugger.command(&mut cpu, "s").unwrap();
+        assert_eq!(
+            debugger.command(&mut cpu, "search changed-by -1"),
+            Ok(Reply::Text("1 addresses\n".to_string()))
+        );
+        assert_eq!(
+            debugger.command(&mut cpu, "search list"),
+            Ok(Reply::Text("0075  02\n".to_string()))
+        );
+        assert_eq!(
+            debugger.command(&mut cpu, "search freeze 9"),
+            Ok(Reply::Text("cheats 0075:09\n".to_string()))
+        );
+        assert_eq!(cpu.bus.cheats().ram_writes(), vec![(0x0075, 9)]);
+        assert!(debugger.command(&mut cpu, "search bigger").is_err());
+    }
+
+    #[test]
+    fn test_prompt_repeats_the_last_command() {
+        let mut cpu = cpu_with("INX\nINX\nINX\nINX");
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let input = Cursor::new("s\n\nbogus\nc\ns\n");
+        let mut debugger =
+            Debugger::new(Box::new(input), Box::new(SharedOutput(Arc::clone(&output))));
+        assert!(debugger.take_break());
+        assert!(!debugger.take_break());
+        assert_eq!(debugger.prompt(&mut cpu), Ok(Resume::Continue));
+        assert_eq!(cpu.register_x, 2);
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert!(output.contains("error: unknown command bogus"));
+
+        // the input ran out after one more step
+        assert_eq!(debugger.prompt(&mut cpu), Ok(Resume::Continue));
+        assert_eq!(cpu.register_x, 3);
+    }
+}
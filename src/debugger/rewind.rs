@@ -0,0 +1,207 @@
+use super::call_stack::CallStack;
+use crate::cpu::CPU;
+use crate::savestate;
+use std::collections::VecDeque;
+
+// instructions between snapshots when not taken at the start of a frame
+const SNAPSHOT_INTERVAL: u64 = 10_000;
+// about ten seconds of frames
+const MAX_SNAPSHOTS: usize = 600;
+
+/* Rewind
+
+   The snapshots that let the debugger go backwards. Moving to an earlier
+   instruction loads the latest snapshot from before it and runs forward
+   from there until the CPU is about to execute that instruction again.
+   Instructions are counted from power on, so where the console is can be
+   given as the number of instructions run so far. The debugger saves a
+   snapshot at the start of every frame, which is also where the session
+   sets the controllers, and every `SNAPSHOT_INTERVAL` instructions while
+   it steps, and keeps the last `MAX_SNAPSHOTS`.
+
+   A snapshot is a save state plus what a save state leaves out: the
+   instruction count, the call stack and whether the CPU had jammed. The
+   profiler, heatmap, instruction statistics, event log and APU scope are
+   set aside while instructions run again, so nothing is counted twice.
+   Going back forgets the snapshots after where it went, so changes made
+   from there stay. A power cycle starts the count again and drops them all.
+*/
+
+#[derive(Debug, Clone)]
+struct Snapshot {
+    instructions: u64,
+    state: Vec<u8>,
+    calls: CallStack,
+    jammed: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Rewind {
+    snapshots: VecDeque<Snapshot>,
+}
+
+impl Rewind {
+    // a snapshot of `cpu` as it is, unless there is one already
+    pub fn save(&mut self, cpu: &CPU) {
+        if let Some(last) = self.snapshots.back() {
+            if last.instructions == cpu.instructions {
+                return;
+            }
+            if last.instructions > cpu.instructions {
+                self.snapshots.clear();
+            }
+        }
+        if self.snapshots.len() == MAX_SNAPSHOTS {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(Snapshot {
+            instructions: cpu.instructions,
+            state: savestate::save(cpu),
+            calls: cpu.calls.clone(),
+            jammed: cpu.jammed,
+        });
+    }
+
+    // a snapshot if it has been `SNAPSHOT_INTERVAL` instructions since the
+    // last one, or there isn't one
+    pub fn checkpoint(&mut self, cpu: &CPU) {
+        let due = self.snapshots.back().is_none_or(|last| {
+            cpu.instructions < last.instructions
+                || cpu.instructions - last.instructions >= SNAPSHOT_INTERVAL
+        });
+        if due {
+            self.save(cpu);
+        }
+    }
+
+    // the furthest back it can go
+    pub fn earliest(&self) -> Option<u64> {
+        return self.snapshots.front().map(|first| first.instructions);
+    }
+
+    // just before the instruction numbered `target` runs
+    pub fn go_to(&mut self, cpu: &mut CPU, target: u64) -> Result<(), String> {
+        let Some(n) = self
+            .snapshots
+            .iter()
+            .rposition(|s| s.instructions <= target)
+        else {
+            return match self.earliest() {
+                Some(earliest) if cpu.instructions >= earliest => Err(format!(
+                    "can't go back that far, {} instructions are kept",
+                    cpu.instructions - earliest
+                )),
+                _ => Err("nothing to go back to yet".to_string()),
+            };
+        };
+        restore(cpu, &self.snapshots[n])?;
+        replay(cpu, target, |_, _| {});
+        self.snapshots.truncate(n + 1);
+        return Ok(());
+    }
+
+    // runs the instructions between the snapshots and `now` again, a
+    // stretch from one snapshot to the next at a time, newest first,
+    // calling `look` ahead of each with whether it's the stretch's first;
+    // stops after the first stretch where `look` returned true, leaving
+    // the CPU wherever that was, for the caller to `go_to` what it found
+    pub fn search_back(
+        &mut self,
+        cpu: &mut CPU,
+        now: u64,
+        mut look: impl FnMut(&mut CPU, bool) -> bool,
+    ) -> Result<(), String> {
+        let starts: Vec<u64> = self.snapshots.iter().map(|s| s.instructions).collect();
+        for n in (0..self.snapshots.len()).rev() {
+            if starts[n] >= now {
+                continue;
+            }
+            let end = starts.get(n + 1).map_or(now, |&next| next.min(now));
+            restore(cpu, &self.snapshots[n])?;
+            let mut found = false;
+            replay(cpu, end, |cpu, first| found |= look(cpu, first));
+            if found {
+                return Ok(());
+            }
+        }
+        return Ok(());
+    }
+}
+
+fn restore(cpu: &mut CPU, snapshot: &Snapshot) -> Result<(), String> {
+    savestate::load(cpu, &snapshot.state)?;
+    cpu.instructions = snapshot.instructions;
+    cpu.calls = snapshot.calls.clone();
+    cpu.jammed = snapshot.jammed;
+    return Ok(());
+}
+
+// steps until `target` instructions have run, calling `before` ahead of
+// each, or until the CPU jams
+fn replay(cpu: &mut CPU, target: u64, mut before: impl FnMut(&mut CPU, bool)) {
+    let profiler = cpu.profiler.take();
+    let stats = cpu.stats.take();
+    let heatmap = cpu.bus.heatmap.take();
+    let events = cpu.bus.events.take();
+    let scope = cpu.bus.apu.scope.take();
+    let mut first = true;
+    while cpu.instructions < target && !cpu.jammed {
+        before(cpu, std::mem::take(&mut first));
+        cpu.step();
+    }
+    cpu.profiler = profiler;
+    cpu.stats = stats;
+    cpu.bus.heatmap = heatmap;
+    cpu.bus.events = events;
+    cpu.bus.apu.scope = scope;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm;
+    use crate::cartridge::test::test_rom;
+    use crate::cartridge::Rom;
+    use crate::nes::Nes;
+
+    #[test]
+    fn test_back_to_any_instruction() {
+        let mut raw = test_rom(0, 1, 1);
+        let program = asm::assemble_at(
+            "reset: SEI\nloop: INX\nSTX $10\nLDA $2002\nADC $10\nSTA $0300,X\nJMP loop",
+            0x8000,
+        )
+        .unwrap();
+        raw[16..16 + program.len()].copy_from_slice(&program);
+        raw[16 + 0x3FFC..16 + 0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        let mut nes = Nes::new(Rom::new(&raw).unwrap()).unwrap();
+        let mut rewind = Rewind::default();
+        assert_eq!(
+            rewind.go_to(&mut nes.cpu, 0).unwrap_err(),
+            "nothing to go back to yet"
+        );
+
+        // where the console was ahead of each instruction, over three frames
+        let mut seen = Vec::new();
+        for _ in 0..3 {
+            rewind.save(&nes.cpu);
+            nes.run_frame_with(|cpu| {
+                seen.push(savestate::save(cpu));
+                return true;
+            });
+        }
+        let now = nes.cpu.instructions;
+        assert_eq!(seen.len() as u64, now);
+
+        for target in [now - 1, now - 5000, 10, 0] {
+            rewind.go_to(&mut nes.cpu, target).unwrap();
+            assert_eq!(nes.cpu.instructions, target);
+            assert!(savestate::save(&nes.cpu) == seen[target as usize]);
+        }
+        // everything after where it went is gone
+        assert_eq!(rewind.earliest(), Some(0));
+        assert_eq!(rewind.snapshots.len(), 1);
+        rewind.go_to(&mut nes.cpu, 100).unwrap();
+        assert!(savestate::save(&nes.cpu) == seen[100]);
+    }
+}
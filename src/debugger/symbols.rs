@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/* Debug symbols
+
+   Names for addresses from the cc65 toolchain, so the debugger can say
+   `JSR read_pad` and take `break main.s:42`. Two kinds of file load:
+
+   - ld65's debug info, `ld65 --dbgfile game.dbg`, a line a record of
+     `key=value` pairs. Its `sym` records of type `lab` give the labels,
+     and its `line` records, through the spans of bytes they made, which
+     source line each address came from.
+   - VICE label files, `ld65 -Ln game.lbl` and what other assemblers write
+     for VICE, `al C:8000 .reset` lines.
+
+   Addresses are as the CPU sees them, so in a game that switches banks the
+   same address can be more than one thing; the first name given for it is
+   the one shown, and any of them can be looked up.
+*/
+
+// a line of source that made bytes, as `file:line`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLine {
+    pub file: String,
+    pub line: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Symbols {
+    names: HashMap<u16, String>,
+    addresses: HashMap<String, u16>,
+    sources: HashMap<u16, SourceLine>,
+    lines: Vec<(SourceLine, u16)>, // each line's lowest address
+}
+
+impl Symbols {
+    // either kind of file, told apart by the ld65 version record
+    pub fn load(path: &Path) -> Result<Symbols, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let symbols = if text.trim_start().starts_with("version") {
+            parse_dbg(&text)
+        } else {
+            parse_labels(&text)
+        };
+        return symbols.map_err(|e| format!("{}: {}", path.display(), e));
+    }
+
+    fn add_name(&mut self, name: &str, addr: u16) {
+        self.names.entry(addr).or_insert_with(|| name.to_string());
+        self.addresses.entry(name.to_string()).or_insert(addr);
+    }
+
+    pub fn name(&self, addr: u16) -> Option<&str> {
+        return self.names.get(&addr).map(|name| name.as_str());
+    }
+
+    pub fn source(&self, addr: u16) -> Option<&SourceLine> {
+        return self.sources.get(&addr);
+    }
+
+    // a label, or a `file:line` where the file is the name in the debug
+    // info or its end, `main.s` for `src/main.s`
+    pub fn lookup(&self, text: &str) -> Option<u16> {
+        if let Some(&addr) = self.addresses.get(text) {
+            return Some(addr);
+        }
+        let (file, line) = text.rsplit_once(':')?;
+        let line = line.parse::<u32>().ok()?;
+        return self
+            .lines
+            .iter()
+            .find(|(source, _)| source.line == line && ends_with_path(&source.file, file))
+            .map(|&(_, addr)| addr);
+    }
+}
+
+impl std::fmt::Display for SourceLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return write!(f, "{}:{}", self.file, self.line);
+    }
+}
+
+fn ends_with_path(file: &str, end: &str) -> bool {
+    let file = file.replace('\\', "/");
+    return file == end || file.ends_with(&format!("/{}", end));
+}
+
+// `al C:8000 .reset`, the `C:` and the dot both optional
+pub fn parse_labels(text: &str) -> Result<Symbols, String> {
+    let mut symbols = Symbols::default();
+    for (n, line) in text.lines().enumerate() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.first() != Some(&"al") {
+            continue;
+        }
+        let (Some(addr), Some(name)) = (words.get(1), words.get(2)) else {
+            return Err(format!("line {}: al needs an address and a name", n + 1));
+        };
+        let digits = addr.rsplit(':').next().unwrap();
+        let addr = u32::from_str_radix(digits, 16)
+            .map_err(|_| format!("line {}: {} is not a hex address", n + 1, addr))?;
+        symbols.add_name(name.trim_start_matches('.'), addr as u16);
+    }
+    return Ok(symbols);
+}
+
+// a record's `key=value` pairs, with quoted values unquoted
+fn fields(record: &str) -> HashMap<&str, &str> {
+    let mut fields = HashMap::new();
+    let mut rest = record;
+    while !rest.is_empty() {
+        let Some((key, value)) = rest.split_once('=') else {
+            break;
+        };
+        let (value, after) = match value.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"').unwrap_or(quoted.len());
+                (&quoted[..end], quoted[end..].trim_start_matches('"'))
+            }
+            None => value.split_once(',').map_or((value, ""), |(v, a)| (v, a)),
+        };
+        fields.insert(key.trim(), value);
+        rest = after.trim_start_matches(',');
+    }
+    return fields;
+}
+
+fn number(value: Option<&&str>) -> Option<u32> {
+    let value = value?;
+    return match value.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => value.parse::<u32>().ok(),
+    };
+}
+
+// ld65's debug info, version 2
+pub fn parse_dbg(text: &str) -> Result<Symbols, String> {
+    let mut files: HashMap<u32, String> = HashMap::new();
+    let mut segments: HashMap<u32, u32> = HashMap::new(); // their start addresses
+    let mut spans: HashMap<u32, (u32, u32)> = HashMap::new(); // segment and offset
+    let mut lines: Vec<(u32, u32, String)> = Vec::new(); // file, line, spans
+    let mut symbols = Symbols::default();
+    for (n, record) in text.lines().enumerate() {
+        let Some((kind, rest)) = record.split_once(char::is_whitespace) else {
+            continue;
+        };
+        let fields = fields(rest.trim());
+        let id = number(fields.get("id"));
+        let bad = || format!("line {}: a {} record without what it needs", n + 1, kind);
+        match kind {
+            "version" if number(fields.get("major")) != Some(2) => {
+                return Err("only version 2 ld65 debug info is understood".to_string());
+            }
+            "file" => {
+                let name = fields.get("name").ok_or_else(bad)?;
+                files.insert(id.ok_or_else(bad)?, name.to_string());
+            }
+            "seg" => {
+                let start = number(fields.get("start")).ok_or_else(bad)?;
+                segments.insert(id.ok_or_else(bad)?, start);
+            }
+            "span" => {
+                let seg = number(fields.get("seg")).ok_or_else(bad)?;
+                let start = number(fields.get("start")).ok_or_else(bad)?;
+                spans.insert(id.ok_or_else(bad)?, (seg, start));
+            }
+            // macro expansions would point into the macro, not where it's used
+            "line" if fields.get("type") != Some(&"2") => {
+                if let Some(span) = fields.get("span") {
+                    let file = number(fields.get("file")).ok_or_else(bad)?;
+                    let line = number(fields.get("line")).ok_or_else(bad)?;
+                    lines.push((file, line, span.to_string()));
+                }
+            }
+            "sym" if fields.get("type") == Some(&"lab") => {
+                let name = fields.get("name").ok_or_else(bad)?;
+                let val = number(fields.get("val")).ok_or_else(bad)?;
+                symbols.add_name(name, val as u16);
+            }
+            _ => {}
+        }
+    }
+
+    for (file, line, span_ids) in lines {
+        let Some(file) = files.get(&file) else {
+            continue;
+        };
+        let source = SourceLine {
+            file: file.clone(),
+            line,
+        };
+        let addrs = span_ids
+            .split('+')
+            .filter_map(|id| spans.get(&id.parse::<u32>().ok()?))
+            .filter_map(|(seg, offset)| Some((segments.get(seg)? + offset) as u16));
+        let mut lowest = None;
+        for addr in addrs {
+            symbols
+                .sources
+                .entry(addr)
+                .or_insert_with(|| source.clone());
+            lowest = Some(lowest.map_or(addr, |low: u16| low.min(addr)));
+        }
+        if let Some(addr) = lowest {
+            symbols.lines.push((source, addr));
+        }
+    }
+    return Ok(symbols);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_vice_labels() {
+        let symbols = parse_labels("al C:8000 .reset\nal 00F0 .frame_count\nbreak 8000\n").unwrap();
+        assert_eq!(symbols.name(0x8000), Some("reset"));
+        assert_eq!(symbols.lookup("frame_count"), Some(0x00F0));
+        assert_eq!(symbols.lookup("nmi"), None);
+        assert!(parse_labels("al C:80G0 .reset").is_err());
+        assert!(parse_labels("al 8000").is_err());
+    }
+
+    #[test]
+    fn test_ld65_debug_info() {
+        let text = "version\tmajor=2,minor=0\n\
+            file\tid=0,name=\"src/main.s\",size=100,mtime=0x5F000000,mod=0\n\
+            seg\tid=0,name=\"CODE\",start=0x008000,size=0x0010,addrsize=absolute,type=ro\n\
+            span\tid=0,seg=0,start=0,size=2\n\
+            span\tid=1,seg=0,start=2,size=3\n\
+            span\tid=2,seg=0,start=5,size=1\n\
+            line\tid=0,file=0,line=10,span=0\n\
+            line\tid=1,file=0,line=11,span=2+1\n\
+            line\tid=2,file=0,line=3,type=2,span=2\n\
+            sym\tid=0,name=\"reset\",addrsize=absolute,scope=0,def=0,val=0x8000,seg=0,type=lab\n\
+            sym\tid=1,name=\"SPEED\",addrsize=zeropage,scope=0,def=1,val=0x8002,type=equ\n";
+        let symbols = parse_dbg(text).unwrap();
+        assert_eq!(symbols.name(0x8000), Some("reset"));
+        assert_eq!(symbols.name(0x8002), None);
+        assert_eq!(symbols.lookup("main.s:11"), Some(0x8002));
+        assert_eq!(symbols.lookup("src/main.s:10"), Some(0x8000));
+        assert_eq!(symbols.lookup("ain.s:10"), None);
+        assert_eq!(symbols.source(0x8005).unwrap().to_string(), "src/main.s:11");
+        assert!(parse_dbg("version\tmajor=3,minor=0\n").is_err());
+        assert!(parse_dbg("version\tmajor=2,minor=0\nseg\tid=0\n").is_err());
+    }
+}
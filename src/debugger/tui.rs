@@ -0,0 +1,384 @@
+use super::hex::HexView;
+use super::symbols::Symbols;
+use super::{call_lines, disassemble, disassemble_with, registers};
+use crate::apu::scope::{waveform, SAMPLE_CYCLES};
+use crate::apu::CHANNEL_COUNT;
+use crate::cpu::CPU;
+use crate::ppu::events;
+use crate::ppu::SCANLINES_PER_FRAME;
+
+// laid out for an 80x24 terminal, the size every SSH client starts at
+const WIDTH: usize = 80;
+const LEFT_WIDTH: usize = 48;
+const DISASM_ROWS: usize = 12;
+const DISASM_BEFORE: usize = 4; // instructions shown ahead of the PC
+const STACK_ROWS: usize = 3;
+const CALL_ROWS: usize = 2;
+const WATCH_ROWS: usize = 2;
+const BREAKPOINT_ROWS: usize = DISASM_ROWS - STACK_ROWS - CALL_ROWS - WATCH_ROWS - 3;
+const MEMORY_ROWS: usize = 6;
+// the event grid in place of everything between the registers and the message
+const EVENT_ROWS: usize = DISASM_ROWS + MEMORY_ROWS + 2;
+const EVENT_COLUMNS: usize = WIDTH - 4;
+// and the APU's channels in the same place, a line and a waveform each
+const WAVE_ROWS: usize = (EVENT_ROWS - 1) / CHANNEL_COUNT - 1;
+
+/* Full-screen debugger
+
+   `--debug-tui` shows the debugger as panes that fill the terminal
+   instead of a prompt: the registers along the top, the disassembly
+   around the PC, the stack, the breakpoints, the `call_stack` innermost
+   first, the watches with their values and a hex dump, redrawn after
+   every key. It works over SSH and next to the terminal renderer, which
+   it takes the screen from while the game is stopped. There is no ratatui
+   in this build, so it is drawn with the same escape sequences as the
+   renderer and reads the keys through the session's host like the game
+   does:
+
+    s         step an instruction
+    o         step over it, a JSR runs to its return
+    u         out of the subroutine, to where it returns
+    n         to the end of the frame, or a breakpoint before it
+    c         continue
+    b         back an instruction
+    r         back to the last breakpoint hit
+    e         the last frame's `events` as a grid, scanlines down and
+              dots across, in place of the panes and back; it starts the
+              event log if it isn't on
+    a         the sound channels with their waveforms the same way, see
+              `apu::scope`
+    Up Down   the hex dump a row back or on
+    Left Right
+              256 bytes back or on
+    Tab       the hex dump's next memory space, see `hex`
+    h         editing there: the arrows move the cursor a byte or a
+              row, hex digits type over the byte under it, so a, b, c
+              and e are digits rather than their keys, and h or Escape
+              stop
+    g         a command line with `goto` on it, for `goto 0300` or
+              `goto prg 1c000`
+    /         one with `find` on it, `find a9 00` or `find "lives"`
+    :         a line for any of the prompt's commands, `break 8000 if
+              x == 2`, `read 0300` to move the hex dump there and so on,
+              Return runs it and Escape drops it
+    q         quit
+
+   Hosts send letter keys in capitals, so command lines are taken in
+   lowercase, which everything they go to accepts but labels spelled with
+   capitals.
+*/
+
+// what a key asks the debugger to do
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    None,
+    Step,
+    StepOver,
+    StepOut,
+    StepBack,
+    ReverseContinue,
+    Frame,
+    Events,
+    Apu,
+    Continue,
+    Quit,
+    Command(String),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Tui {
+    pub hex: HexView,
+    pub message: String,
+    pub events: bool,        // showing the event grid
+    pub apu: bool,           // or the APU's channels
+    command: Option<String>, // being typed
+}
+
+impl Tui {
+    // a key by its host name, as the bindings use them
+    pub fn key(&mut self, key: &str, cpu: &mut CPU) -> Action {
+        if let Some(line) = &mut self.command {
+            match key {
+                "Return" => {
+                    let line = self.command.take().unwrap();
+                    return Action::Command(line);
+                }
+                "Escape" => self.command = None,
+                "Backspace" => {
+                    line.pop();
+                }
+                "Space" => line.push(' '),
+                key if key.chars().count() == 1 => line.push_str(&key.to_ascii_lowercase()),
+                _ => {}
+            }
+            return Action::None;
+        }
+        if self.hex.key(key, cpu, MEMORY_ROWS) {
+            return Action::None;
+        }
+        match key {
+            "S" => return Action::Step,
+            "O" => return Action::StepOver,
+            "U" => return Action::StepOut,
+            "B" => return Action::StepBack,
+            "R" => return Action::ReverseContinue,
+            "N" => return Action::Frame,
+            "E" => return Action::Events,
+            "A" => return Action::Apu,
+            "C" => return Action::Continue,
+            "Q" => return Action::Quit,
+            ":" => self.command = Some(String::new()),
+            "G" => self.command = Some("goto ".to_string()),
+            "/" => self.command = Some("find ".to_string()),
+            _ => {}
+        }
+        return Action::None;
+    }
+
+    // the whole screen, from the top left
+    pub fn render(
+        &self,
+        cpu: &CPU,
+        breakpoints: &[String],
+        watches: &[String],
+        symbols: &Symbols,
+    ) -> String {
+        let mut rows = vec![
+            heading(
+                " rustynes  s step  o over  u out  n frame  c continue  e events  : cmd  q quit",
+            ),
+            registers(cpu),
+        ];
+        if self.events {
+            rows.extend(event_rows(cpu));
+            return self.finish(rows);
+        }
+        if self.apu {
+            rows.extend(apu_rows(cpu));
+            return self.finish(rows);
+        }
+
+        let mut right = vec![heading(" Stack")];
+        let stack_top = cpu.stack.ptr() + 1;
+        for row in 0..STACK_ROWS as u16 {
+            let addr = 0x0100 + stack_top + row * 8;
+            if addr > 0x01FF {
+                right.push(String::new());
+                continue;
+            }
+            let bytes: Vec<String> = (addr..(addr + 8).min(0x0200))
+                .map(|a| format!("{:02X}", cpu.bus.peek(a)))
+                .collect();
+            right.push(format!("{:04X}  {}", addr, bytes.join(" ")));
+        }
+        right.push(heading(" Breakpoints"));
+        for n in 0..BREAKPOINT_ROWS {
+            right.push(match breakpoints.get(n) {
+                Some(text) => format!("{}  {}", n + 1, text),
+                None => String::new(),
+            });
+        }
+        right.push(heading(" Calls"));
+        let calls = call_lines(cpu, symbols);
+        for n in 0..CALL_ROWS {
+            right.push(calls.get(n).cloned().unwrap_or_default());
+        }
+        right.push(heading(" Watch"));
+        for n in 0..WATCH_ROWS {
+            right.push(watches.get(n).cloned().unwrap_or_default());
+        }
+
+        let mut left = vec![heading(" Disassembly")];
+        let mut addr = lead_in(cpu, DISASM_BEFORE);
+        for _ in 0..DISASM_ROWS {
+            let (line, len) = disassemble_with(cpu, addr, symbols);
+            let marker = if addr == cpu.program_counter {
+                '>'
+            } else {
+                ' '
+            };
+            left.push(format!("{}{}", marker, line));
+            addr = addr.wrapping_add(len);
+        }
+        for (left, right) in left.iter().zip(&right) {
+            rows.push(format!("{} {}", fit(left, LEFT_WIDTH), right));
+        }
+
+        rows.push(heading(&self.hex.title(cpu)));
+        rows.extend(self.hex.rows(cpu, MEMORY_ROWS));
+        return self.finish(rows);
+    }
+
+    // the message and command lines under `rows`, and all of it as output
+    fn finish(&self, mut rows: Vec<String>) -> String {
+        rows.push(self.message.lines().next().unwrap_or("").to_string());
+        rows.push(match &self.command {
+            Some(line) => format!(":{}", line),
+            None => String::new(),
+        });
+
+        let mut out = String::from("\x1b[H");
+        for row in &rows {
+            out += &fit(row, WIDTH);
+            out += "\x1b[K\r\n";
+        }
+        return out;
+    }
+}
+
+// the last frame's events, a row a few scanlines with the first on the left
+fn event_rows(cpu: &CPU) -> Vec<String> {
+    let last = cpu.bus.events.as_ref().and_then(|log| log.last());
+    let title = match last {
+        Some((frame, events)) => format!(" Events, frame {}: {}", frame, events.len()),
+        None => " Events, no frame finished yet".to_string(),
+    };
+    let mut rows = vec![heading(&format!(
+        "{:<32}N nmi  I irq  0 sprite 0  B mapper  CMOSAD $200x",
+        title
+    ))];
+    let grid = events::grid(
+        last.map_or(&[], |(_, events)| events),
+        EVENT_ROWS - 1,
+        EVENT_COLUMNS,
+    );
+    for (n, line) in grid.iter().enumerate() {
+        let scanline = n * SCANLINES_PER_FRAME as usize / (EVENT_ROWS - 1);
+        rows.push(format!("{:3} {}", scanline, line));
+    }
+    return rows;
+}
+
+// each channel as it is, over the last levels the scope kept
+fn apu_rows(cpu: &CPU) -> Vec<String> {
+    let apu = &cpu.bus.apu;
+    let mut rows = vec![heading(&format!(
+        " APU, {} CPU cycles a column",
+        SAMPLE_CYCLES
+    ))];
+    for (n, channel) in apu.channels().iter().enumerate() {
+        rows.push(channel.to_string());
+        let levels = apu
+            .scope
+            .as_ref()
+            .map_or(Vec::new(), |scope| scope.history(n, EVENT_COLUMNS));
+        for line in waveform(&levels, channel.max, WAVE_ROWS) {
+            rows.push(format!("    {}", line));
+        }
+    }
+    rows.resize(EVENT_ROWS, String::new());
+    return rows;
+}
+
+// reverse video, across the pane
+fn heading(text: &str) -> String {
+    return format!("\x1b[7m{}\x1b[0m", text);
+}
+
+// padded or cut to `width` characters, not counting escape sequences
+fn fit(text: &str, width: usize) -> String {
+    let mut out = String::new();
+    let mut shown = 0;
+    let mut escape = false;
+    for c in text.chars() {
+        if c == '\x1b' {
+            escape = true;
+        }
+        if escape {
+            out.push(c);
+            escape = !c.is_ascii_alphabetic();
+            continue;
+        }
+        if shown < width {
+            out.push(c);
+            shown += 1;
+        }
+    }
+    out.extend(std::iter::repeat_n(' ', width - shown));
+    return out;
+}
+
+// where to start disassembling to show `before` instructions ahead of the
+// PC: the furthest back start that decodes into the PC instruction by
+// instruction, since code can't be read backwards
+fn lead_in(cpu: &CPU, before: usize) -> u16 {
+    let pc = cpu.program_counter;
+    for back in (1..=before as u16 * 3).rev() {
+        let mut starts = vec![pc.wrapping_sub(back)];
+        let mut addr = starts[0];
+        while addr != pc && starts.len() <= before * 3 {
+            addr = addr.wrapping_add(disassemble(cpu, addr).1);
+            starts.push(addr);
+            if addr.wrapping_sub(pc) < 0x8000 && addr != pc {
+                break; // went past it
+            }
+        }
+        if addr == pc {
+            return starts[starts.len().saturating_sub(before + 1)];
+        }
+    }
+    return pc;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm;
+    use crate::cpu::Mem;
+
+    #[test]
+    fn test_screen_and_keys() {
+        let mut cpu = CPU::new();
+        let program = asm::assemble_at("LDX #$04\nDEX\nDEX\nDEX\nDEX\nBNE $FB\nBRK", 0x0600);
+        for (i, byte) in program.unwrap().into_iter().enumerate() {
+            cpu.mem_write(0x0600 + i as u16, byte);
+        }
+        cpu.program_counter = 0x0606;
+        cpu.mem_write(0x0300, b'H');
+        cpu.mem_write(0x01FE, 0x06);
+        cpu.stack.set_ptr(0xFC);
+        let mut tui = Tui::default();
+        for key in ["Down", "Right", "Right", "Right"] {
+            tui.key(key, &mut cpu);
+        }
+        assert_eq!(tui.hex.start, 0x0310);
+        tui.key("Up", &mut cpu);
+
+        let screen = tui.render(
+            &cpu,
+            &["0606 if x == 2".to_string()],
+            &["1  x = $00 0".to_string()],
+            &Symbols::default(),
+        );
+        let rows: Vec<&str> = screen.split("\r\n").collect();
+        assert_eq!(rows.len(), 25); // and the cursor left on the next
+        assert!(rows[1].starts_with("PC:0606 A:00"));
+        // four instructions lead up to the PC, every one decoded in step
+        assert!(rows[3].starts_with(" 0602  CA        DEX "));
+        assert!(rows[7].starts_with(">0606  D0 FB     BNE $0603 "));
+        assert!(rows[8].starts_with(" 0608  00        BRK "));
+        assert!(rows[3].contains("01FD  00 06 00   "));
+        assert!(rows[4].ends_with(" \x1b[K"));
+        assert!(rows[7].contains("1  0606 if x == 2"));
+        assert!(rows[9].contains(" Calls"));
+        assert!(rows[12].contains(" Watch"));
+        assert!(rows[13].contains("1  x = $00 0"));
+        assert!(rows[16].starts_with("0300  48 00"));
+        assert!(rows[16].contains("  H..............."));
+
+        assert_eq!(tui.key("S", &mut cpu), Action::Step);
+        assert_eq!(tui.key("O", &mut cpu), Action::StepOver);
+        assert_eq!(tui.key(":", &mut cpu), Action::None);
+        for key in ["B", "Space", "0", "6", "X", "Backspace", "0", "0"] {
+            assert_eq!(tui.key(key, &mut cpu), Action::None);
+        }
+        assert!(tui
+            .render(&cpu, &[], &[], &Symbols::default())
+            .contains("\r\n:b 0600 "));
+        assert_eq!(
+            tui.key("Return", &mut cpu),
+            Action::Command("b 0600".to_string())
+        );
+        assert_eq!(tui.key("Q", &mut cpu), Action::Quit);
+    }
+}
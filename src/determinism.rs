@@ -0,0 +1,118 @@
+use crate::cartridge::Rom;
+use crate::hash::{self, Sha1};
+use crate::movie::Movie;
+use crate::nes::Nes;
+use crate::savestate;
+use std::path::Path;
+
+/* Determinism
+
+   The same cartridge, start state and input give the same run, frame for
+   frame; movies, rewind, the TAS editor and run-ahead all count on it.
+   What keeps the core that way:
+
+    - work RAM comes up as the builder's `RamInit` says, zeroed, all $FF
+      or noise from a fixed seed, and VRAM and cartridge RAM zeroed, not
+      random as on the console, so a game reading memory it never wrote
+      reads the same every time with the same settings
+    - nothing the console does reads the host's clock; frame pacing, the
+      profiler's timings and the stats are the frontend's, outside it
+    - console state is held in arrays and vectors, the hash maps are the
+      tools' (symbols, disassembly, profiles), so no iteration order gets in
+    - the sound's resampler and filters come after the APU and nothing is
+      fed back from them
+    - the only inputs are the buttons held each frame and the reset and
+      power buttons, which is what a movie records
+
+   `check` holds the core to it by playing a movie twice. The first run
+   goes straight through; the second saves the console at the end of
+   every frame and goes on from that state loaded into a new console. The
+   state and picture are hashed after each frame of both, and the first
+   frame they differ on is reported. Anything from outside the console
+   leaking in shows up this way, and so does console state a save state
+   leaves out, which is as bad for rewind and run-ahead.
+   `rustynes determinism ROM MOVIE` runs it on a game.
+*/
+
+// the SHA-1 of the console's save state and picture
+fn frame_hash(nes: &Nes) -> [u8; 20] {
+    let mut sha1 = Sha1::new();
+    sha1.update(&savestate::save(&nes.cpu));
+    sha1.update(&nes.frame().pixels);
+    return sha1.finish();
+}
+
+// a hash after each frame of `movie`, with the console saved and loaded
+// into a new one between frames when `round_trip`
+pub fn frame_hashes(rom: &Rom, movie: &Movie, round_trip: bool) -> Result<Vec<[u8; 20]>, String> {
+    let mut nes = Nes::new(rom.clone())?;
+    savestate::load(&mut nes.cpu, &movie.start)?;
+    let mut hashes = Vec::with_capacity(movie.frames.len());
+    for &input in &movie.frames {
+        nes.run_input_frame(input);
+        hashes.push(frame_hash(&nes));
+        if round_trip {
+            let state = savestate::save(&nes.cpu);
+            nes = Nes::new(rom.clone())?;
+            savestate::load(&mut nes.cpu, &state)?;
+        }
+    }
+    return Ok(hashes);
+}
+
+// the frames the movie ran for, or the first where the two runs differ
+pub fn check(rom: &Rom, movie: &Movie) -> Result<usize, String> {
+    let straight = frame_hashes(rom, movie, false)?;
+    let round_trip = frame_hashes(rom, movie, true)?;
+    if let Some(frame) = (0..straight.len()).find(|&n| straight[n] != round_trip[n]) {
+        return Err(format!(
+            "frame {} differs between runs, {} then {}",
+            frame,
+            hash::to_hex(&straight[frame]),
+            hash::to_hex(&round_trip[frame])
+        ));
+    }
+    return Ok(straight.len());
+}
+
+// `rustynes determinism <rom.nes> <movie>`
+pub fn run_cli(args: &[String]) -> Result<bool, String> {
+    let [rom, movie] = args else {
+        return Err("usage: rustynes determinism <rom.nes> <movie>".to_string());
+    };
+    let rom = Rom::from_file(Path::new(rom), None)?;
+    let movie = Movie::load(Path::new(movie))?;
+    match check(&rom, &movie) {
+        Ok(frames) => {
+            println!("{} frames, the same both times", frames);
+            return Ok(true);
+        }
+        Err(e) => {
+            println!("{}", e);
+            return Ok(false);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::movie::{MovieEvent, MovieFrame};
+    use crate::nes::test::test_nes;
+
+    #[test]
+    fn test_movie_runs_the_same_twice() {
+        let nes = test_nes();
+        let mut movie = Movie::new(savestate::save(&nes.cpu));
+        for n in 0..6 {
+            movie.frames.push(MovieFrame {
+                buttons: [(n % 2) as u8, 0, 0, 0],
+                event: (n == 3).then_some(MovieEvent::Reset),
+            });
+        }
+        assert_eq!(check(nes.rom(), &movie), Ok(6));
+        let hashes = frame_hashes(nes.rom(), &movie, false).unwrap();
+        assert_ne!(hashes[0], hashes[1]);
+        assert!(run_cli(&["game.nes".to_string()]).is_err());
+    }
+}
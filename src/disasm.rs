@@ -0,0 +1,506 @@
+use crate::cartridge::{Rom, PRG_ROM_PAGE_SIZE};
+use crate::cpu::AddressingMode;
+use crate::debugger::instruction;
+use crate::op_codes::NMOS_6502_OPCODES_MAP;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+// FCEUX's code/data log, a byte of flags for each byte of PRG ROM, then CHR
+const CDL_CODE: u8 = 0x01;
+const VECTORS: u16 = 0xFFFA;
+const BYTES_PER_LINE: usize = 8;
+const JSR: u8 = 0x20;
+const JMP: u8 = 0x4C;
+const BRANCHES: [u8; 8] = [0x10, 0x30, 0x50, 0x70, 0x90, 0xB0, 0xD0, 0xF0];
+// BRK, RTI, JMP, RTS and JMP indirect, which don't go on to the next instruction
+const ENDS: [u8; 5] = [0x00, 0x40, 0x4C, 0x60, 0x6C];
+
+/* ROM disassembly
+
+   `rustynes disasm game.nes` writes the PRG ROM out as ca65 source that
+   assembles back to the same bytes:
+
+   - the targets of the reset, NMI and IRQ vectors are labelled `reset`,
+     `nmi` and `irq`, and the vectors are a `.word` of them
+   - a JSR's target is a subroutine, `sub_C123`, and a JMP's or a
+     branch's a label, `L_C140`
+   - code comes out as instructions and everything else as `.byte`s
+
+   With `--cdl game.cdl`, a code/data log from FCEUX or an emulator that
+   writes the same format, code is what the log saw run. Without one it
+   is what can be reached from the vectors by following calls, jumps and
+   branches, which misses code only reached through a jump table.
+
+   A ROM of 32KB or less is one `CODE` segment where the CPU sees it. A
+   bigger one is taken as 16KB banks, the last fixed at $C000 and the
+   others switched in at $8000 as UxROM and MMC1 games have them, each its
+   own segment, `BANK_00` on, for a linker config to lay out in order.
+   Code in a bank reaches that bank and the fixed one, and labels in a
+   switched bank end in its number, `sub_8012_3`. Absolute operands in
+   the zero page are written `a:$0010` so ca65 keeps them 3 bytes long.
+*/
+
+// a bank where the CPU sees it
+#[derive(Debug, Clone, Copy)]
+struct Bank {
+    start: usize, // in the PRG ROM
+    len: usize,
+    org: u16,
+}
+
+fn banks(prg: usize) -> Vec<Bank> {
+    if prg <= 2 * PRG_ROM_PAGE_SIZE {
+        let org = (0x10000 - prg) as u16;
+        return vec![Bank {
+            start: 0,
+            len: prg,
+            org,
+        }];
+    }
+    let count = prg.div_ceil(PRG_ROM_PAGE_SIZE);
+    return (0..count)
+        .map(|n| Bank {
+            start: n * PRG_ROM_PAGE_SIZE,
+            len: PRG_ROM_PAGE_SIZE.min(prg - n * PRG_ROM_PAGE_SIZE),
+            org: if n == count - 1 { 0xC000 } else { 0x8000 },
+        })
+        .collect();
+}
+
+// what's at a PRG ROM offset
+enum Item {
+    Code(usize, usize), // an instruction and its length
+    Byte(usize),
+}
+
+struct Disassembly<'a> {
+    prg: &'a [u8],
+    banks: Vec<Bank>,
+    code: Vec<bool>,
+    labels: HashMap<usize, String>, // by PRG ROM offset
+}
+
+impl Disassembly<'_> {
+    fn fixed(&self) -> usize {
+        return self.banks.len() - 1;
+    }
+
+    // the PRG ROM offset of `addr` to code in `bank`, there or in the fixed bank
+    fn locate(&self, bank: usize, addr: u16) -> Option<usize> {
+        for n in [bank, self.fixed()] {
+            let bank = self.banks[n];
+            if addr >= bank.org && ((addr - bank.org) as usize) < bank.len {
+                return Some(bank.start + (addr - bank.org) as usize);
+            }
+        }
+        return None;
+    }
+
+    fn bank_of(&self, offset: usize) -> usize {
+        if self.banks.len() == 1 {
+            return 0;
+        }
+        return offset / PRG_ROM_PAGE_SIZE;
+    }
+
+    fn addr_of(&self, offset: usize) -> u16 {
+        let bank = self.banks[self.bank_of(offset)];
+        return bank.org.wrapping_add((offset - bank.start) as u16);
+    }
+
+    // the instruction's bytes, 0 past its end or the bank's
+    fn bytes(&self, offset: usize) -> [u8; 3] {
+        let bank = self.banks[self.bank_of(offset)];
+        let end = bank.start + bank.len;
+        return [0, 1, 2].map(|i| {
+            self.prg
+                .get(offset + i)
+                .filter(|_| offset + i < end)
+                .copied()
+                .unwrap_or(0)
+        });
+    }
+
+    // the code reachable from `entries`
+    fn trace(&mut self, entries: &[(usize, u16)]) {
+        let mut todo = entries.to_vec();
+        while let Some((mut bank, mut addr)) = todo.pop() {
+            while let Some(offset) = self.locate(bank, addr) {
+                bank = self.bank_of(offset);
+                let here = self.banks[bank];
+                let code = self.prg[offset];
+                let Some(op) = NMOS_6502_OPCODES_MAP.get(&code) else {
+                    break;
+                };
+                let len = op.len as usize;
+                if self.code[offset] || offset + len > here.start + here.len {
+                    break;
+                }
+                self.code[offset..offset + len].fill(true);
+                let bytes = self.bytes(offset);
+                let word = (bytes[2] as u16) << 8 | bytes[1] as u16;
+                match code {
+                    JSR | JMP => todo.push((bank, word)),
+                    code if BRANCHES.contains(&code) => {
+                        let target = addr.wrapping_add(2).wrapping_add(bytes[1] as i8 as u16);
+                        todo.push((bank, target));
+                    }
+                    _ => {}
+                }
+                if ENDS.contains(&code) {
+                    break;
+                }
+                addr = addr.wrapping_add(len as u16);
+            }
+        }
+    }
+
+    // a bank's instructions and bytes, in order
+    fn items(&self, bank: usize) -> Vec<Item> {
+        let Bank { start, len, .. } = self.banks[bank];
+        let mut items = Vec::new();
+        let mut offset = start;
+        while offset < start + len {
+            let size = NMOS_6502_OPCODES_MAP
+                .get(&self.prg[offset])
+                .map_or(0, |op| op.len as usize);
+            let end = offset + size;
+            if size > 0 && end <= start + len && self.code[offset..end].iter().all(|&c| c) {
+                items.push(Item::Code(offset, size));
+                offset = end;
+            } else {
+                items.push(Item::Byte(offset));
+                offset += 1;
+            }
+        }
+        return items;
+    }
+
+    // `kind_C123`, and the bank's number after it for a switched bank
+    fn label_name(&self, kind: &str, offset: usize) -> String {
+        let bank = self.bank_of(offset);
+        let name = format!("{}_{:04X}", kind, self.addr_of(offset));
+        if bank == self.fixed() {
+            return name;
+        }
+        return format!("{}_{}", name, bank);
+    }
+
+    fn label(&mut self, kind: &str, offset: usize) {
+        if !self.labels.contains_key(&offset) {
+            let name = self.label_name(kind, offset);
+            self.labels.insert(offset, name);
+        }
+    }
+
+    // the targets of the calls, then of the jumps and branches
+    fn find_labels(&mut self) {
+        let mut calls = Vec::new();
+        let mut jumps = Vec::new();
+        for bank in 0..self.banks.len() {
+            for item in self.items(bank) {
+                let Item::Code(offset, _) = item else {
+                    continue;
+                };
+                let bytes = self.bytes(offset);
+                let word = (bytes[2] as u16) << 8 | bytes[1] as u16;
+                let addr = self.addr_of(offset);
+                let (targets, target) = match bytes[0] {
+                    JSR => (&mut calls, word),
+                    JMP => (&mut jumps, word),
+                    code if BRANCHES.contains(&code) => (
+                        &mut jumps,
+                        addr.wrapping_add(2).wrapping_add(bytes[1] as i8 as u16),
+                    ),
+                    _ => continue,
+                };
+                if let Some(target) = self.locate(bank, target) {
+                    targets.push(target);
+                }
+            }
+        }
+        for offset in calls {
+            self.label("sub", offset);
+        }
+        for offset in jumps {
+            self.label("L", offset);
+        }
+    }
+
+    // the label for `addr` as code in `bank` would write it
+    fn name(&self, bank: usize, addr: u16) -> Option<String> {
+        return self
+            .locate(bank, addr)
+            .and_then(|offset| self.labels.get(&offset).cloned());
+    }
+
+    fn write_bank(&self, bank: usize, out: &mut String) {
+        let Bank { org, .. } = self.banks[bank];
+        let vectors = self
+            .locate(self.fixed(), VECTORS)
+            .filter(|_| bank == self.fixed());
+        let mut data: Vec<String> = Vec::new();
+        let flush = |data: &mut Vec<String>, out: &mut String| {
+            if !data.is_empty() {
+                *out += &format!("    .byte {}\n", data.join(", "));
+                data.clear();
+            }
+        };
+        *out += &format!(".org ${:04X}\n", org);
+        let items = self.items(bank);
+        let mut items = items.iter().peekable();
+        while let Some(item) = items.next() {
+            let offset = match *item {
+                Item::Code(offset, _) | Item::Byte(offset) => offset,
+            };
+            if let Some(label) = self.labels.get(&offset) {
+                flush(&mut data, out);
+                *out += &format!("{}:\n", label);
+            }
+            match *item {
+                Item::Byte(_)
+                    if vectors == Some(offset)
+                        && !self.code[offset..offset + 6].contains(&true) =>
+                {
+                    flush(&mut data, out);
+                    let words: Vec<String> = (0..3)
+                        .map(|i| {
+                            let at = offset + i * 2;
+                            let word = (self.prg[at + 1] as u16) << 8 | self.prg[at] as u16;
+                            return self.name(bank, word).unwrap_or(format!("${:04X}", word));
+                        })
+                        .collect();
+                    *out += &format!("    .word {}\n", words.join(", "));
+                    for _ in 0..5 {
+                        items.next();
+                    }
+                }
+                Item::Byte(_) => {
+                    data.push(format!("${:02X}", self.prg[offset]));
+                    if data.len() == BYTES_PER_LINE {
+                        flush(&mut data, out);
+                    }
+                }
+                Item::Code(_, len) => {
+                    flush(&mut data, out);
+                    let addr = self.addr_of(offset);
+                    let (mut text, _) =
+                        instruction(addr, self.bytes(offset), |at| self.name(bank, at));
+                    let op = &NMOS_6502_OPCODES_MAP[&self.prg[offset]];
+                    let absolute = matches!(
+                        op.mode,
+                        AddressingMode::Absolute
+                            | AddressingMode::Absolute_X
+                            | AddressingMode::Absolute_Y
+                    );
+                    if absolute && self.prg[offset + 2] == 0 && text.contains(" $00") {
+                        text = text.replacen(" $", " a:$", 1);
+                    }
+                    *out += &format!("    {}\n", text);
+                    // labels that land inside it, for what jumps there
+                    for inside in offset + 1..offset + len {
+                        if let Some(label) = self.labels.get(&inside) {
+                            *out += &format!("{} = ${:04X}\n", label, self.addr_of(inside));
+                        }
+                    }
+                }
+            }
+        }
+        flush(&mut data, out);
+    }
+}
+
+// `rom`'s PRG ROM as ca65 source, telling code from data by `cdl` when given
+pub fn disassemble(rom: &Rom, name: &str, cdl: Option<&[u8]>) -> Result<String, String> {
+    let prg = &rom.prg_rom;
+    if prg.is_empty() {
+        return Err("the ROM has no PRG ROM".to_string());
+    }
+    let mut disassembly = Disassembly {
+        prg,
+        banks: banks(prg.len()),
+        code: vec![false; prg.len()],
+        labels: HashMap::new(),
+    };
+    let fixed = disassembly.fixed();
+    let vectors = disassembly
+        .locate(fixed, VECTORS)
+        .filter(|&at| at + 6 <= prg.len());
+    let mut entries = Vec::new();
+    if let Some(at) = vectors {
+        for (i, kind) in ["nmi", "reset", "irq"].iter().enumerate() {
+            let addr = (prg[at + i * 2 + 1] as u16) << 8 | prg[at + i * 2] as u16;
+            entries.push((fixed, addr, *kind));
+        }
+        // reset's name wins where they share a handler
+        entries.sort_by_key(|&(_, _, kind)| kind != "reset");
+    }
+    let source = match cdl {
+        Some(cdl) => {
+            if cdl.len() < prg.len() {
+                return Err(format!(
+                    "the code/data log is {} bytes, too short for {} of PRG ROM",
+                    cdl.len(),
+                    prg.len()
+                ));
+            }
+            for (code, flags) in disassembly.code.iter_mut().zip(cdl) {
+                *code = flags & CDL_CODE != 0;
+            }
+            "the code/data log"
+        }
+        None => {
+            let starts: Vec<(usize, u16)> = entries
+                .iter()
+                .map(|&(bank, addr, _)| (bank, addr))
+                .collect();
+            disassembly.trace(&starts);
+            "what the vectors reach"
+        }
+    };
+    for &(bank, addr, kind) in &entries {
+        if let Some(offset) = disassembly.locate(bank, addr) {
+            disassembly.labels.entry(offset).or_insert(kind.to_string());
+        }
+    }
+    disassembly.find_labels();
+
+    let code = disassembly.code.iter().filter(|&&c| c).count();
+    let mut out = format!(
+        "; {}, disassembled by rustynes\n\
+         ; mapper {}, {}KB of PRG ROM, {} bytes of it code by {}; CHR isn't included\n",
+        name,
+        rom.mapper,
+        prg.len() / 1024,
+        code,
+        source
+    );
+    for bank in 0..disassembly.banks.len() {
+        let segment = match disassembly.banks.len() {
+            1 => "CODE".to_string(),
+            _ => format!("BANK_{:02}", bank),
+        };
+        out += &format!("\n.segment \"{}\"\n", segment);
+        disassembly.write_bank(bank, &mut out);
+    }
+    return Ok(out);
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Options {
+    pub rom: PathBuf,
+    pub cdl: Option<PathBuf>,
+    pub out: Option<PathBuf>,
+}
+
+impl Options {
+    pub fn parse(args: &[String]) -> Result<Options, String> {
+        let mut rom = None;
+        let mut cdl = None;
+        let mut out = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                return args.next().ok_or_else(|| format!("{} needs a value", name));
+            };
+            match arg.as_str() {
+                "--cdl" => cdl = Some(PathBuf::from(value("--cdl")?)),
+                "--out" => out = Some(PathBuf::from(value("--out")?)),
+                flag if flag.starts_with("--") => return Err(format!("unknown option {}", flag)),
+                arg if rom.is_none() => rom = Some(PathBuf::from(arg)),
+                extra => return Err(format!("unexpected argument {}", extra)),
+            }
+        }
+        return Ok(Options {
+            rom: rom.ok_or("missing a ROM to disassemble")?,
+            cdl,
+            out,
+        });
+    }
+}
+
+// `rustynes disasm <rom.nes> [--cdl FILE] [--out FILE]`
+pub fn run_cli(args: &[String]) -> Result<(), String> {
+    let options = Options::parse(args)?;
+    let rom = Rom::from_file(&options.rom, None)?;
+    let cdl = match &options.cdl {
+        Some(path) => Some(fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?),
+        None => None,
+    };
+    let name = options
+        .rom
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let source = disassemble(&rom, &name, cdl.as_deref())?;
+    match &options.out {
+        Some(path) => {
+            return fs::write(path, source).map_err(|e| format!("{}: {}", path.display(), e))
+        }
+        None => print!("{}", source),
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm;
+    use crate::cartridge::test::test_rom;
+
+    // a 16KB ROM at $C000 with `source` at its start and the vectors set
+    fn rom_with(source: &str, nmi: u16, irq: u16) -> Rom {
+        let program = asm::assemble_at(source, 0xC000).unwrap();
+        let mut raw = test_rom(0, 1, 1);
+        raw[16..16 + program.len()].copy_from_slice(&program);
+        let vectors = [nmi, 0xC000, irq].map(u16::to_le_bytes).concat();
+        raw[16 + 0x3FFA..16 + 0x4000].copy_from_slice(&vectors);
+        return Rom::new(&raw).unwrap();
+    }
+
+    #[test]
+    fn test_traced_from_the_vectors() {
+        let rom = rom_with(
+            "reset: SEI\nJSR sub\nloop: JMP loop\n\
+             sub: LDX #$00\nBNE done\n.byte $9D, $10, $00\ndone: RTS\n\
+             nmi: RTI",
+            0xC00F,
+            0xC00F,
+        );
+        let source = disassemble(&rom, "test.nes", None).unwrap();
+        assert!(source.starts_with("; test.nes, disassembled by rustynes\n"));
+        // the STA is absolute into the zero page, which ca65 would shorten
+        assert!(source.contains(
+            "\n.segment \"CODE\"\n.org $C000\nreset:\n    SEI\n    JSR sub_C007\n\
+             L_C004:\n    JMP L_C004\nsub_C007:\n    LDX #$00\n    BNE L_C00E\n    \
+             STA a:$0010,X\nL_C00E:\n    RTS\nnmi:\n    RTI\n    .byte $00, $00, $00,"
+        ));
+        assert!(source.ends_with("    .word nmi, reset, nmi\n"));
+    }
+
+    #[test]
+    fn test_code_from_a_log() {
+        let rom = rom_with("reset: LDA #$01\nJMP reset\n.byte $A9, $02", 0xC000, 0xC000);
+        let mut cdl = vec![0; rom.prg_rom.len() + rom.chr_rom.len()];
+        // the log saw the first instruction run and the bytes after the jump read
+        cdl[0..2].fill(CDL_CODE);
+        cdl[5..7].fill(0x02);
+        let source = disassemble(&rom, "log.nes", Some(&cdl)).unwrap();
+        assert!(source.contains("by the code/data log;"));
+        assert!(source.contains("reset:\n    LDA #$01\n    .byte $4C, $00, $C0, $A9, $02, $00,"));
+        assert!(disassemble(&rom, "log.nes", Some(&cdl[..0x100])).is_err());
+
+        // past 32KB, 16KB banks with the last one fixed
+        let orgs: Vec<u16> = banks(0x10000).iter().map(|bank| bank.org).collect();
+        assert_eq!(orgs, vec![0x8000, 0x8000, 0x8000, 0xC000]);
+
+        let args: Vec<String> = ["game.nes", "--cdl", "game.cdl"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let options = Options::parse(&args).unwrap();
+        assert_eq!(options.cdl, Some(PathBuf::from("game.cdl")));
+        assert!(Options::parse(&[]).is_err());
+    }
+}
@@ -0,0 +1,236 @@
+use crate::asm;
+use crate::cpu::{Mem, CPU};
+use crate::frontend::{self, FramePacer, HostEvent, Renderer};
+use crate::video::Frame;
+use std::fs;
+use std::path::PathBuf;
+
+pub const LOAD_ADDR: u16 = 0x0600;
+const RANDOM_ADDR: u16 = 0x00FE;
+const KEY_ADDR: u16 = 0x00FF;
+const DISPLAY_START: u16 = 0x0200;
+pub const DISPLAY_SIZE: usize = 32;
+
+// about what the original simulator gets through between screen updates
+pub const DEFAULT_SPEED: u32 = 1600;
+
+/* easy6502 environment
+
+   The machine of Nick Morgan's easy6502 tutorial, which a lot of 6502
+   introductions (the snake game most of all) are written against, on the
+   flat bus without any of the NES:
+
+    $00FE        a new random byte before every instruction
+    $00FF        the ASCII code of the last key pressed, lowercase
+    $0200-$05FF  a 32x32 display, a byte per pixel, left to right and top
+                 to bottom, in the low nibble's colour of `COLOURS`
+    $0600        where the program is loaded and starts
+
+   `rustynes easy6502 <file>` runs a raw binary, or a .asm file through the
+   mini assembler, with its labels at the addresses the program runs from.
+   A frame runs `speed` instructions and shows the display; BRK ends the
+   program. WASD are the keys tutorials use for directions and the arrows
+   send them too.
+*/
+
+pub const COLOURS: [(u8, u8, u8); 16] = [
+    (0x00, 0x00, 0x00), // black
+    (0xFF, 0xFF, 0xFF), // white
+    (0x88, 0x00, 0x00), // red
+    (0xAA, 0xFF, 0xEE), // cyan
+    (0xCC, 0x44, 0xCC), // purple
+    (0x00, 0xCC, 0x55), // green
+    (0x00, 0x00, 0xAA), // blue
+    (0xEE, 0xEE, 0x77), // yellow
+    (0xDD, 0x88, 0x55), // orange
+    (0x66, 0x44, 0x00), // brown
+    (0xFF, 0x77, 0x77), // light red
+    (0x33, 0x33, 0x33), // dark grey
+    (0x77, 0x77, 0x77), // grey
+    (0xAA, 0xFF, 0x66), // light green
+    (0x00, 0x88, 0xFF), // light blue
+    (0xBB, 0xBB, 0xBB), // light grey
+];
+
+pub struct Easy6502 {
+    pub cpu: CPU,
+    rng: u32,
+    halted: bool,
+}
+
+impl Easy6502 {
+    // `seed` picks the random bytes, the same seed gives the same run
+    pub fn new(program: &[u8], seed: u32) -> Result<Self, String> {
+        if program.len() > 0x10000 - LOAD_ADDR as usize {
+            return Err(format!("program is {} bytes, too large", program.len()));
+        }
+        let mut cpu = CPU::new();
+        for (i, &byte) in program.iter().enumerate() {
+            cpu.mem_write(LOAD_ADDR + i as u16, byte);
+        }
+        cpu.program_counter = LOAD_ADDR;
+        return Ok(Self {
+            cpu,
+            rng: seed.max(1),
+            halted: false,
+        });
+    }
+
+    pub fn is_halted(&self) -> bool {
+        return self.halted;
+    }
+
+    pub fn press_key(&mut self, ascii: u8) {
+        self.cpu.mem_write(KEY_ADDR, ascii);
+    }
+
+    // up to `instructions` of the program, less when it reaches BRK
+    pub fn run_frame(&mut self, instructions: u32) {
+        for _ in 0..instructions {
+            if self.halted {
+                return;
+            }
+            // xorshift32
+            self.rng ^= self.rng << 13;
+            self.rng ^= self.rng >> 17;
+            self.rng ^= self.rng << 5;
+            self.cpu.mem_write(RANDOM_ADDR, self.rng as u8);
+            self.halted = !self.cpu.step();
+        }
+    }
+
+    pub fn frame(&self) -> Frame {
+        let mut frame = Frame::new(DISPLAY_SIZE, DISPLAY_SIZE);
+        // the flat bus's RAM is the whole address space
+        let display = &self.cpu.bus.ram()[DISPLAY_START as usize..];
+        for (pixel, &byte) in frame.pixels.chunks_mut(4).zip(display) {
+            let (r, g, b) = COLOURS[(byte & 0x0F) as usize];
+            pixel.copy_from_slice(&[r, g, b, 0xFF]);
+        }
+        return frame;
+    }
+}
+
+// the ASCII code a host key sends
+pub fn key_code(key: &str) -> Option<u8> {
+    match key {
+        "Up" => return Some(b'w'),
+        "Left" => return Some(b'a'),
+        "Down" => return Some(b's'),
+        "Right" => return Some(b'd'),
+        "Return" => return Some(b'\r'),
+        "Space" => return Some(b' '),
+        _ => {}
+    }
+    match key.as_bytes() {
+        &[byte] if byte.is_ascii_graphic() => return Some(byte.to_ascii_lowercase()),
+        _ => return None,
+    }
+}
+
+struct Options {
+    path: PathBuf,
+    speed: u32,
+    frames: Option<u64>,
+    renderer: Renderer,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Options, String> {
+        let mut path = None;
+        let mut options = Options {
+            path: PathBuf::new(),
+            speed: DEFAULT_SPEED,
+            frames: None,
+            renderer: Renderer::Terminal(frontend::terminal::TerminalMode::HalfBlock),
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                return args.next().ok_or_else(|| format!("{} needs a value", name));
+            };
+            match arg.as_str() {
+                "--speed" => options.speed = parse_number(value("--speed")?)?,
+                "--frames" => options.frames = Some(parse_number(value("--frames")?)?),
+                "--renderer" => options.renderer = Renderer::parse(value("--renderer")?)?,
+                flag if flag.starts_with("--") => return Err(format!("unknown option {}", flag)),
+                file if path.is_none() => path = Some(PathBuf::from(file)),
+                extra => return Err(format!("unexpected argument {}", extra)),
+            }
+        }
+        options.path = path.ok_or("missing program file")?;
+        return Ok(options);
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    return value
+        .parse()
+        .map_err(|_| format!("{} is not a valid number", value));
+}
+
+pub fn run_cli(args: &[String]) -> Result<(), String> {
+    let options = Options::parse(args)?;
+    let error = |e: std::io::Error| format!("{}: {}", options.path.display(), e);
+    let program = match options.path.extension().and_then(|e| e.to_str()) {
+        Some("asm" | "s") => asm::assemble_at(
+            &fs::read_to_string(&options.path).map_err(error)?,
+            LOAD_ADDR,
+        )
+        .map_err(|e| format!("{}: {}", options.path.display(), e))?,
+        _ => fs::read(&options.path).map_err(error)?,
+    };
+    let mut machine = Easy6502::new(&program, std::process::id())?;
+    let mut host = frontend::open_host("easy6502", options.renderer, 2, 1, false)?;
+    let mut pacer = FramePacer::default();
+    let mut frames = 0;
+    while !machine.is_halted() && options.frames.is_none_or(|limit| frames < limit) {
+        for event in host.poll_events() {
+            match event {
+                HostEvent::Quit => return Ok(()),
+                HostEvent::KeyDown(key) => {
+                    if let Some(ascii) = key_code(&key) {
+                        machine.press_key(ascii);
+                    }
+                }
+                _ => {}
+            }
+        }
+        machine.run_frame(options.speed);
+        host.present(&machine.frame())?;
+        frames += 1;
+        pacer.wait();
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_display_keys_and_random() {
+        // copies random bytes to the last two pixels until there is a key,
+        // which goes to the first
+        let program = asm::assemble_at(
+            "wait: LDA $FE\nSTA $05FE\nLDA $FE\nSTA $05FF\nLDA $FF\nBEQ wait\nSTA $0200\nBRK",
+            LOAD_ADDR,
+        )
+        .unwrap();
+        let mut machine = Easy6502::new(&program, 1).unwrap();
+        machine.run_frame(100);
+        assert!(!machine.is_halted());
+        // consecutive instructions see different random bytes
+        assert_ne!(machine.cpu.mem_read(0x05FE), machine.cpu.mem_read(0x05FF));
+
+        machine.press_key(key_code("Left").unwrap());
+        machine.run_frame(100);
+        assert!(machine.is_halted());
+        let frame = machine.frame();
+        assert_eq!((frame.width, frame.height), (32, 32));
+        // 'a' is $61, its low nibble the first colour after black
+        assert_eq!(frame.pixel(0, 0), [0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(key_code("W"), Some(b'w'));
+        assert_eq!(key_code("F1"), None);
+    }
+}
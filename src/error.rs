@@ -0,0 +1,127 @@
+use crate::cpu::AddressingMode;
+use std::path::PathBuf;
+use thiserror::Error;
+
+/* Errors
+
+   What can go wrong in the core, as types a program embedding it can
+   match on instead of strings to show: `RomError` from reading and
+   checking a cartridge and building its board, `CpuError` from an
+   instruction the CPU can't carry on past, `StateError` from a save
+   state that doesn't load. `EmuError` is any of them, what the `Nes`
+   API returns.
+
+   None of them aborts anything. A CPU that runs into trouble jams, the
+   way the console does on a KIL, and says why with `CPU::check`; the
+   rest come back as the `Err` of whatever was asked for. The frontend
+   and tools outside the core still deal in strings, so every one of
+   these turns into a `String` with `?`.
+*/
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum RomError {
+    #[error("file is not in iNES file format")]
+    NotINes,
+    #[error("file is {len} bytes but the header describes {expected} bytes")]
+    Truncated { len: usize, expected: usize },
+    #[error("ROM has no PRG data")]
+    NoPrgRom,
+    #[error("mapper {0} is not supported")]
+    UnsupportedMapper(u16),
+    #[error("ROM has a trainer but the board has no PRG RAM for it")]
+    NoRamForTrainer,
+    #[error("{0}")]
+    Read(String), // the file or the zip around it, which say where
+    #[error("{}: {source}", path.display())]
+    File {
+        path: PathBuf,
+        source: Box<RomError>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum CpuError {
+    #[error("the CPU jammed on opcode ${opcode:02X} at ${pc:04X}")]
+    Jammed { opcode: u8, pc: u16 },
+    #[error("{mnemonic} at ${pc:04X} has no implementation")]
+    Unimplemented { mnemonic: &'static str, pc: u16 },
+    #[error("the instruction at ${pc:04X} can't use {mode:?} addressing")]
+    UnsupportedMode { mode: AddressingMode, pc: u16 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum StateError {
+    #[error("not a rustynes save state")]
+    NotAState,
+    #[error("unsupported save state version {0}")]
+    Version(u8),
+    #[error("unexpected end of save state")]
+    Truncated,
+    #[error("trailing data after save state")]
+    TrailingData,
+    #[error("state block is {len} bytes, expected {expected}")]
+    BlockSize { len: usize, expected: usize },
+    #[error("{0}")]
+    Invalid(String), // a value a component won't take
+    #[error("{}: {message}", path.display())]
+    File { path: PathBuf, message: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum EmuError {
+    #[error(transparent)]
+    Rom(#[from] RomError),
+    #[error(transparent)]
+    Cpu(#[from] CpuError),
+    #[error(transparent)]
+    State(#[from] StateError),
+}
+
+impl From<RomError> for String {
+    fn from(e: RomError) -> String {
+        return e.to_string();
+    }
+}
+
+impl From<CpuError> for String {
+    fn from(e: CpuError) -> String {
+        return e.to_string();
+    }
+}
+
+impl From<StateError> for String {
+    fn from(e: StateError) -> String {
+        return e.to_string();
+    }
+}
+
+impl From<EmuError> for String {
+    fn from(e: EmuError) -> String {
+        return e.to_string();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_messages() {
+        let e = RomError::File {
+            path: PathBuf::from("game.nes"),
+            source: Box::new(RomError::UnsupportedMapper(5)),
+        };
+        assert_eq!(e.to_string(), "game.nes: mapper 5 is not supported");
+        let e = EmuError::from(CpuError::Jammed {
+            opcode: 0x02,
+            pc: 0xC123,
+        });
+        assert_eq!(String::from(e), "the CPU jammed on opcode $02 at $C123");
+        let e: String = StateError::BlockSize {
+            len: 3,
+            expected: 2,
+        }
+        .into();
+        assert_eq!(e, "state block is 3 bytes, expected 2");
+    }
+}
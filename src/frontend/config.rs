@@ -0,0 +1,335 @@
+use super::display::Rotation;
+use crate::cartridge::{Region, Rom};
+use crate::hash;
+use crate::input::bindings::Bindings;
+use crate::input::ControllerSetup;
+use crate::ppu::palette::PaletteChoice;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// games kept in the recent list, most recent first
+const RECENT_LIMIT: usize = 10;
+
+/* Configuration
+
+   Settings kept between runs, in the user's config directory
+   ($XDG_CONFIG_HOME/rustynes, ~/.config/rustynes on most systems) or the
+   file given with `--config`. The top of the file applies to every game,
+   and a section headed with a ROM's CRC32 or SHA-1, as `rustynes info`
+   prints them, overrides it for that game alone:
+
+    palette = ntsc
+
+    [d445f698]  # Super Mario Bros.
+    crop_overscan = yes
+    cheat = SXIOPO
+    p1.a = Space
+
+   The settings are `region`, `palette`, `controllers`, `crop_overscan` and
+   `rotation`, with the same values as their command line options, `cheat`, which may
+   be given any number of times, `resume`, yes unless turned off, which
+   saves the game as it's left and offers to carry on from there next
+   time, and bindings lines as in a bindings file, which replace the
+   inputs of only the buttons they name. Options given
+   on the command line win over both the top of the file and the game's
+   section. Anything after a # is a comment.
+
+   `romdb = FILE`, at the top of the file only, loads a ROM database of
+   header fixes over the built-in one (see `romdb`), before the game does.
+   `save_dir = DIR`, also at the top only, keeps the battery saves there
+   instead of next to each ROM.
+
+   Next to it, `recent` lists the games last booted, one path per line.
+*/
+
+// the settings a game boots with, each None where nothing chose one
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GameConfig {
+    pub region: Option<Region>,
+    pub palette: Option<PaletteChoice>,
+    pub controllers: Option<ControllerSetup>,
+    pub crop_overscan: Option<bool>,
+    pub rotation: Option<Rotation>,
+    pub resume: Option<bool>,
+    pub cheats: Vec<String>,
+    pub bindings: Bindings, // over the usual ones, button by button
+}
+
+impl GameConfig {
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "region" => self.region = Some(parse_region(value)?),
+            "palette" => self.palette = Some(PaletteChoice::parse(value)?),
+            "controllers" => self.controllers = Some(ControllerSetup::parse(value)?),
+            "crop_overscan" => self.crop_overscan = Some(parse_bool(value)?),
+            "rotation" => self.rotation = Some(Rotation::parse(value)?),
+            "resume" => self.resume = Some(parse_bool(value)?),
+            "cheat" => self.cheats.push(value.to_string()),
+            binding if binding.contains('.') => {
+                let line = format!("{} = {}", binding, value);
+                self.bindings.overlay(&Bindings::parse(&line)?);
+            }
+            _ => return Err(format!("unknown setting {}", key)),
+        }
+        return Ok(());
+    }
+
+    // `other`'s settings wherever it has one, the cheats of both
+    pub fn merge(&mut self, other: &GameConfig) {
+        self.region = other.region.or(self.region);
+        self.palette = other.palette.clone().or(self.palette.take());
+        self.controllers = other.controllers.or(self.controllers);
+        self.crop_overscan = other.crop_overscan.or(self.crop_overscan);
+        self.rotation = other.rotation.or(self.rotation);
+        self.resume = other.resume.or(self.resume);
+        self.cheats.extend(other.cheats.iter().cloned());
+        self.bindings.overlay(&other.bindings);
+    }
+}
+
+// a region to run a game as, which for now is only NTSC: that's the one
+// timing emulated, and PAL or Dendy would only slow the pacing down
+pub fn parse_region(value: &str) -> Result<Region, String> {
+    match Region::parse(value)? {
+        Region::Ntsc => return Ok(Region::Ntsc),
+        _ => {
+            return Err(format!(
+                "only NTSC timing is emulated, so the region can't be {}",
+                value
+            ))
+        }
+    }
+}
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value {
+        "yes" | "true" | "on" => return Ok(true),
+        "no" | "false" | "off" => return Ok(false),
+        _ => return Err(format!("expected yes or no, got {}", value)),
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Config {
+    pub global: GameConfig,
+    pub romdb: Option<PathBuf>,          // over the built-in one
+    pub save_dir: Option<PathBuf>,       // for battery saves, instead of next to the ROM
+    games: BTreeMap<String, GameConfig>, // by lowercase hex CRC32 or SHA-1
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let mut config = Config::default();
+        let mut section = None;
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |e: String| format!("line {}: {}", number + 1, e);
+            if let Some(hash) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                let hash = hash.trim().to_ascii_lowercase();
+                if !matches!(hash.len(), 8 | 40) || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(error(format!("expected a CRC32 or SHA-1, got {}", hash)));
+                }
+                config.games.entry(hash.clone()).or_default();
+                section = Some(hash);
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| error(format!("expected <setting> = <value>, got {}", line)))?;
+            let (key, value) = (key.trim(), value.trim());
+            let settings = match &section {
+                Some(_) if matches!(key, "romdb" | "save_dir") => {
+                    return Err(error(format!("{} goes at the top of the file", key)))
+                }
+                None if key == "romdb" => {
+                    config.romdb = Some(PathBuf::from(value));
+                    continue;
+                }
+                None if key == "save_dir" => {
+                    config.save_dir = Some(PathBuf::from(value));
+                    continue;
+                }
+                Some(hash) => config.games.get_mut(hash).unwrap(),
+                None => &mut config.global,
+            };
+            settings.set(key, value).map_err(error)?;
+        }
+        return Ok(config);
+    }
+
+    // an empty configuration when there is no file yet
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+        return Config::parse(&text).map_err(|e| format!("{}: {}", path.display(), e));
+    }
+
+    // the top of the file with the game's section over it
+    pub fn for_rom(&self, rom: &Rom) -> GameConfig {
+        let mut settings = self.global.clone();
+        for hash in [format!("{:08x}", rom.crc32()), hash::to_hex(&rom.sha1())] {
+            if let Some(game) = self.games.get(&hash) {
+                settings.merge(game);
+            }
+        }
+        return settings;
+    }
+}
+
+// None when there is no home directory to put it in
+pub fn config_dir() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        return Some(PathBuf::from(dir).join("rustynes"));
+    }
+    if let Some(dir) = env::var_os("APPDATA") {
+        return Some(PathBuf::from(dir).join("rustynes"));
+    }
+    return env::var_os("HOME").map(|home| PathBuf::from(home).join(".config").join("rustynes"));
+}
+
+pub fn default_config_path() -> Option<PathBuf> {
+    return config_dir().map(|dir| dir.join("config"));
+}
+
+// where the `state_slots` go without --state-dir
+pub fn default_state_dir() -> Option<PathBuf> {
+    return config_dir().map(|dir| dir.join("states"));
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecentRoms {
+    path: PathBuf,
+    roms: Vec<PathBuf>,
+}
+
+impl RecentRoms {
+    // an empty list when there is no file yet
+    pub fn load(path: &Path) -> Result<RecentRoms, String> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+        return Ok(RecentRoms {
+            path: path.to_path_buf(),
+            roms: text
+                .lines()
+                .filter(|line| !line.is_empty())
+                .map(PathBuf::from)
+                .collect(),
+        });
+    }
+
+    pub fn default_path() -> Option<PathBuf> {
+        return config_dir().map(|dir| dir.join("recent"));
+    }
+
+    pub fn roms(&self) -> &[PathBuf] {
+        return &self.roms;
+    }
+
+    // puts `rom` at the top, by its full path so it opens from anywhere
+    pub fn add(&mut self, rom: &Path) {
+        let rom = fs::canonicalize(rom).unwrap_or_else(|_| rom.to_path_buf());
+        self.roms.retain(|path| *path != rom);
+        self.roms.insert(0, rom);
+        self.roms.truncate(RECENT_LIMIT);
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let error = |e: io::Error| format!("{}: {}", self.path.display(), e);
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).map_err(error)?;
+        }
+        let mut text = String::new();
+        for rom in &self.roms {
+            text += &format!("{}\n", rom.display());
+        }
+        return fs::write(&self.path, text).map_err(error);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+    use crate::input::joypad::Button;
+    use crate::input::PORT_1;
+
+    #[test]
+    fn test_game_sections_override_the_top() {
+        let rom = Rom::new(&test_rom(0, 1, 1)).unwrap();
+        let text = format!(
+            "palette = ntsc\ncheat = SXIOPO\n\n[{:08X}]  # the test game\nregion = ntsc\ncheat = AAAAAA\np1.a = Space\n\n[{}]\ncrop_overscan = yes\nrotation = 270\nresume = no\n\n[deadbeef]\nresume = yes\n",
+            rom.crc32(),
+            crate::hash::to_hex(&rom.sha1())
+        );
+        let config = Config::parse(&text).unwrap();
+        let settings = config.for_rom(&rom);
+        assert_eq!(settings.region, Some(Region::Ntsc));
+        assert!(matches!(settings.palette, Some(PaletteChoice::Ntsc(_))));
+        assert_eq!(settings.crop_overscan, Some(true));
+        assert_eq!(settings.rotation, Some(Rotation::Left));
+        assert_eq!(settings.resume, Some(false));
+        assert_eq!(settings.cheats, vec!["SXIOPO", "AAAAAA"]);
+        assert_eq!(
+            settings.bindings.inputs_for(PORT_1, Button::A),
+            vec!["Space"]
+        );
+
+        // another game only gets the top
+        let other = Rom::new(&test_rom(0, 2, 1)).unwrap();
+        assert_eq!(config.for_rom(&other).region, None);
+
+        assert_eq!(
+            Config::parse("region = ntsc\n[mario]\n").err().unwrap(),
+            "line 2: expected a CRC32 or SHA-1, got mario"
+        );
+        assert!(Config::parse("speed = 2").is_err());
+        assert!(Config::parse("crop_overscan = maybe").is_err());
+        assert_eq!(
+            Config::parse("region = pal").err().unwrap(),
+            "line 1: only NTSC timing is emulated, so the region can't be pal"
+        );
+
+        let config = Config::parse("romdb = fixes.txt\n").unwrap();
+        assert_eq!(config.romdb, Some(PathBuf::from("fixes.txt")));
+        assert_eq!(
+            Config::parse("[deadbeef]\nromdb = fixes.txt")
+                .err()
+                .unwrap(),
+            "line 2: romdb goes at the top of the file"
+        );
+        let config = Config::parse("save_dir = saves\n").unwrap();
+        assert_eq!(config.save_dir, Some(PathBuf::from("saves")));
+        assert!(Config::parse("[deadbeef]\nsave_dir = saves").is_err());
+    }
+
+    #[test]
+    fn test_recent_roms() {
+        let dir = env::temp_dir().join(format!("rustynes-recent-{}", std::process::id()));
+        let path = dir.join("recent");
+        let mut recent = RecentRoms::load(&path).unwrap();
+        assert!(recent.roms().is_empty());
+        for n in 0..12 {
+            recent.add(Path::new(&format!("/games/{}.nes", n)));
+        }
+        recent.add(Path::new("/games/5.nes"));
+        recent.save().unwrap();
+
+        let recent = RecentRoms::load(&path).unwrap();
+        assert_eq!(recent.roms().len(), RECENT_LIMIT);
+        assert_eq!(recent.roms()[0], PathBuf::from("/games/5.nes"));
+        assert_eq!(recent.roms()[1], PathBuf::from("/games/11.nes"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,86 @@
+use std::io;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+// what the dialog offers, the same files `Rom::from_file` takes
+const ROM_PATTERNS: [&str; 2] = ["*.nes", "*.zip"];
+
+/* Open ROM dialog
+
+   The file picker behind the Open ROM hotkey. Neither window backend
+   brings one of its own, so it is the desktop's, run as a separate
+   program the way recordings run ffmpeg: zenity on GNOME and most other
+   desktops, kdialog on KDE, whichever is installed. Either prints the
+   chosen path and exits with 0, or exits with 1 when the user cancels.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Picker {
+    Zenity,
+    Kdialog,
+}
+
+impl Picker {
+    fn command(&self) -> Command {
+        match self {
+            Picker::Zenity => {
+                let mut command = Command::new("zenity");
+                command
+                    .arg("--file-selection")
+                    .arg("--title=Open ROM")
+                    .arg(format!(
+                        "--file-filter=NES ROMs | {}",
+                        ROM_PATTERNS.join(" ")
+                    ));
+                return command;
+            }
+            Picker::Kdialog => {
+                let mut command = Command::new("kdialog");
+                command
+                    .arg("--title")
+                    .arg("Open ROM")
+                    .arg("--getopenfilename")
+                    .arg(".")
+                    .arg(format!("NES ROMs ({})", ROM_PATTERNS.join(" ")));
+                return command;
+            }
+        }
+    }
+}
+
+// None when the user cancelled
+pub fn open_rom() -> Result<Option<PathBuf>, String> {
+    for picker in [Picker::Zenity, Picker::Kdialog] {
+        let output = match picker.command().stderr(Stdio::null()).output() {
+            Ok(output) => output,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(format!("{:?}: {}", picker, e)),
+        };
+        return Ok(chosen_path(output.status.success(), &output.stdout));
+    }
+    return Err("no file dialog available (install zenity or kdialog)".to_string());
+}
+
+fn chosen_path(success: bool, stdout: &[u8]) -> Option<PathBuf> {
+    let path = String::from_utf8_lossy(stdout);
+    let path = path.trim_end_matches(['\r', '\n']);
+    if !success || path.is_empty() {
+        return None;
+    }
+    return Some(PathBuf::from(path));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_chosen_path() {
+        assert_eq!(
+            chosen_path(true, b"/games/Super Mario Bros.nes\n"),
+            Some(PathBuf::from("/games/Super Mario Bros.nes"))
+        );
+        assert_eq!(chosen_path(false, b""), None);
+        assert_eq!(chosen_path(true, b"\n"), None);
+    }
+}
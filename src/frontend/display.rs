@@ -0,0 +1,225 @@
+use crate::video::Frame;
+
+// the NES pixel is a little wider than tall, 8:7 on an NTSC television
+const PIXEL_ASPECT: f64 = 8.0 / 7.0;
+
+/* Display options
+
+   How a window host fits the 256x240 picture into its window. With aspect
+   correction each NES pixel is drawn 8/7 as wide as it is tall, the shape
+   it had on a television; without it pixels are square. Integer scaling
+   keeps the height an exact multiple of 240 so every NES pixel covers the
+   same number of rows, whatever is left over becomes a black border. The
+   picture is always centred and always drawn nearest-neighbour, never
+   filtered.
+
+   Hosts drawing through a GPU use `viewport` for the destination of their
+   texture, software hosts can `blit` straight into a window-sized buffer.
+   Fullscreen is borderless, at the desktop resolution, and toggled with
+   the fullscreen hotkey. `vsync` is set when the session paces itself by
+   the display, see `pacing`.
+
+   `rotation` turns the picture for games drawn for a monitor on its side,
+   TATE as arcade players call it: vertical shooters and homebrew that
+   want a tall screen. The session turns each frame before presenting it,
+   so hosts get the picture as it should look; `viewport` then stretches
+   a sideways picture's rows rather than its columns, since those are
+   what was the television's width, and `window_size` swaps the sides.
+   It can be set per game in the config.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AspectRatio {
+    #[default]
+    Television, // 8:7 pixels
+    Square,
+}
+
+impl AspectRatio {
+    pub fn parse(name: &str) -> Result<AspectRatio, String> {
+        match name {
+            "8:7" => return Ok(AspectRatio::Television),
+            "1:1" | "square" => return Ok(AspectRatio::Square),
+            _ => return Err(format!("unknown aspect ratio {}", name)),
+        }
+    }
+
+    fn pixel_aspect(self) -> f64 {
+        match self {
+            AspectRatio::Television => return PIXEL_ASPECT,
+            AspectRatio::Square => return 1.0,
+        }
+    }
+}
+
+// clockwise
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Rotation {
+    #[default]
+    None,
+    Right, // 90 degrees
+    UpsideDown,
+    Left, // 270 degrees
+}
+
+impl Rotation {
+    pub fn parse(name: &str) -> Result<Rotation, String> {
+        match name {
+            "0" | "none" => return Ok(Rotation::None),
+            "90" | "right" => return Ok(Rotation::Right),
+            "180" => return Ok(Rotation::UpsideDown),
+            "270" | "left" => return Ok(Rotation::Left),
+            _ => return Err(format!("unknown rotation {}", name)),
+        }
+    }
+
+    pub fn quarter_turns(self) -> u32 {
+        return self as u32;
+    }
+
+    // whether the picture's width becomes its height
+    pub fn is_sideways(self) -> bool {
+        return matches!(self, Rotation::Right | Rotation::Left);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisplayOptions {
+    pub fullscreen: bool,
+    pub vsync: bool,
+    pub integer_scale: bool,
+    pub aspect: AspectRatio,
+    pub rotation: Rotation,
+}
+
+impl Default for DisplayOptions {
+    fn default() -> Self {
+        return Self {
+            fullscreen: false,
+            vsync: false,
+            integer_scale: true,
+            aspect: AspectRatio::default(),
+            rotation: Rotation::default(),
+        };
+    }
+}
+
+// where in the window the picture goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Viewport {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl DisplayOptions {
+    // the window size for a picture shown `scale` times its height, the
+    // picture's size before it is rotated
+    pub fn window_size(&self, width: usize, height: usize, scale: usize) -> (usize, usize) {
+        let width = (width as f64 * self.aspect.pixel_aspect() * scale as f64).round();
+        if self.rotation.is_sideways() {
+            return (height * scale, width as usize);
+        }
+        return (width as usize, height * scale);
+    }
+
+    // `frame` is the size of the picture as presented, after rotation
+    pub fn viewport(&self, frame: (usize, usize), window: (usize, usize)) -> Viewport {
+        let (mut shown_width, mut shown_height) = (frame.0 as f64, frame.1 as f64);
+        if self.rotation.is_sideways() {
+            shown_height *= self.aspect.pixel_aspect();
+        } else {
+            shown_width *= self.aspect.pixel_aspect();
+        }
+        let mut scale = (window.0 as f64 / shown_width).min(window.1 as f64 / shown_height);
+        if self.integer_scale && scale >= 1.0 {
+            scale = scale.floor();
+        }
+        let width = ((shown_width * scale).round() as usize).min(window.0);
+        let height = ((shown_height * scale).round() as usize).min(window.1);
+        return Viewport {
+            x: (window.0 - width) / 2,
+            y: (window.1 - height) / 2,
+            width,
+            height,
+        };
+    }
+
+    // `frame` scaled into `target` nearest-neighbour, with black borders around it
+    pub fn blit(&self, frame: &Frame, target: &mut Frame) {
+        let view = self.viewport((frame.width, frame.height), (target.width, target.height));
+        target
+            .pixels
+            .chunks_mut(4)
+            .for_each(|pixel| pixel.copy_from_slice(&[0, 0, 0, 0xFF]));
+        for y in 0..view.height {
+            let source = frame.row(y * frame.height / view.height);
+            let row = &mut target.row_mut(view.y + y)[view.x * 4..(view.x + view.width) * 4];
+            for (x, pixel) in row.chunks_mut(4).enumerate() {
+                let offset = x * frame.width / view.width * 4;
+                pixel.copy_from_slice(&source[offset..offset + 4]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_viewport() {
+        let mut display = DisplayOptions::default();
+        assert_eq!(display.window_size(256, 240, 3), (878, 720));
+        // a 1080p screen fits 4 whole multiples of 240, centred
+        let view = display.viewport((256, 240), (1920, 1080));
+        assert_eq!((view.width, view.height), (1170, 960));
+        assert_eq!((view.x, view.y), (375, 60));
+
+        display.integer_scale = false;
+        assert_eq!(display.viewport((256, 240), (1920, 1080)).height, 1080);
+        display.aspect = AspectRatio::Square;
+        let view = display.viewport((256, 240), (512, 600));
+        assert_eq!((view.x, view.y, view.width, view.height), (0, 60, 512, 480));
+
+        assert_eq!(AspectRatio::parse("square"), Ok(AspectRatio::Square));
+        assert!(AspectRatio::parse("4:3").is_err());
+    }
+
+    #[test]
+    fn test_sideways_stretches_the_height() {
+        let display = DisplayOptions {
+            rotation: Rotation::parse("90").unwrap(),
+            ..DisplayOptions::default()
+        };
+        assert_eq!(display.window_size(256, 240, 3), (720, 878));
+        // 240 wide after the turn, 4 whole multiples on a 1080x1920 screen
+        let view = display.viewport((240, 256), (1080, 1920));
+        assert_eq!((view.width, view.height), (960, 1170));
+        assert_eq!((view.x, view.y), (60, 375));
+
+        assert_eq!(Rotation::parse("270"), Ok(Rotation::Left));
+        assert_eq!(Rotation::Left.quarter_turns(), 3);
+        assert!(!Rotation::UpsideDown.is_sideways());
+        assert!(Rotation::parse("45").is_err());
+    }
+
+    #[test]
+    fn test_blit_is_nearest_neighbour() {
+        let mut frame = Frame::new(2, 1);
+        frame.pixels[..4].copy_from_slice(&[0xFF, 0, 0, 0xFF]);
+        let display = DisplayOptions {
+            aspect: AspectRatio::Square,
+            ..DisplayOptions::default()
+        };
+        let mut target = Frame::new(6, 5);
+        target.pixels.fill(0x80);
+        display.blit(&frame, &mut target);
+        // scaled by 3, one black row above and below
+        assert_eq!(target.pixel(0, 0), [0, 0, 0, 0xFF]);
+        assert_eq!(target.pixel(2, 1), [0xFF, 0, 0, 0xFF]);
+        assert_eq!(target.pixel(3, 3), [0, 0, 0, 0xFF]);
+        assert_eq!(target.pixel(5, 4), [0, 0, 0, 0xFF]);
+    }
+}
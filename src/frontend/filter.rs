@@ -0,0 +1,228 @@
+use crate::video::Frame;
+use std::borrow::Cow;
+
+// how wide the blur is for each YIQ component
+const LUMA_RADIUS: usize = 1;
+const I_RADIUS: usize = 2;
+const Q_RADIUS: usize = 3;
+
+// brightness of the dark rows between scanlines, and of the other two
+// colours in each mask stripe
+const SCANLINE_LEVEL: f32 = 0.55;
+const MASK_LEVEL: f32 = 0.7;
+
+/* Video filters
+
+   An optional stage between the PPU and the host, for the look of a game
+   on a television instead of on a monitor:
+
+    composite  the NTSC signal's limited bandwidth, the colour carrier being
+               much narrower than brightness. Each row goes to YIQ and the
+               I and Q components are blurred over a few pixels, Y over
+               fewer, which blends the dithered stripes and checkerboards
+               many games draw into the solid colours and transparency
+               their artists meant
+    scanlines  the dark gaps between the beam's lines
+    mask       the red, green and blue phosphor stripes of an aperture
+               grille
+
+   They combine with '+', "crt" is all three, for example
+   `--filter composite+scanlines`. The composite blend is worked out from
+   the finished RGB picture rather than modelled from the PPU's signal, so
+   it has none of the signal's dot crawl or per-frame fringing.
+
+   Scanlines and the mask need more pixels than the picture has, the
+   filtered frame is 2x as large for scanlines and 3x with the mask, which
+   keeps the picture's proportions for `display`.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct VideoFilter {
+    pub composite: bool,
+    pub scanlines: bool,
+    pub mask: bool,
+}
+
+impl VideoFilter {
+    pub fn parse(spec: &str) -> Result<VideoFilter, String> {
+        let mut filter = VideoFilter::default();
+        if spec == "none" {
+            return Ok(filter);
+        }
+        for name in spec.split('+') {
+            match name {
+                "composite" => filter.composite = true,
+                "scanlines" => filter.scanlines = true,
+                "mask" => filter.mask = true,
+                "crt" => {
+                    filter = VideoFilter {
+                        composite: true,
+                        scanlines: true,
+                        mask: true,
+                    }
+                }
+                _ => return Err(format!("unknown video filter {}", name)),
+            }
+        }
+        return Ok(filter);
+    }
+
+    pub fn is_none(&self) -> bool {
+        return *self == VideoFilter::default();
+    }
+
+    // how many times larger the filtered frame is in each direction
+    pub fn scale(&self) -> usize {
+        if self.mask {
+            return 3;
+        }
+        if self.scanlines {
+            return 2;
+        }
+        return 1;
+    }
+
+    pub fn apply<'a>(&self, frame: &'a Frame) -> Cow<'a, Frame> {
+        if self.is_none() {
+            return Cow::Borrowed(frame);
+        }
+        let mut frame = Cow::Borrowed(frame);
+        if self.composite {
+            frame = Cow::Owned(composite(&frame));
+        }
+        if self.scale() > 1 {
+            frame = Cow::Owned(self.enlarge(&frame));
+        }
+        return frame;
+    }
+
+    // each pixel becomes a scale x scale block, its last row the scanline gap
+    // and its columns the mask's stripes
+    fn enlarge(&self, frame: &Frame) -> Frame {
+        let scale = self.scale();
+        let mut out = Frame::new(frame.width * scale, frame.height * scale);
+        for y in 0..out.height {
+            let source = frame.row(y / scale);
+            let gap = self.scanlines && y % scale == scale - 1;
+            let row = out.row_mut(y);
+            for (x, pixel) in row.chunks_mut(4).enumerate() {
+                let offset = x / scale * 4;
+                for channel in 0..3 {
+                    let mut level = source[offset + channel] as f32;
+                    if gap {
+                        level *= SCANLINE_LEVEL;
+                    }
+                    if self.mask && x % scale != channel {
+                        level *= MASK_LEVEL;
+                    }
+                    pixel[channel] = level.round() as u8;
+                }
+                pixel[3] = 0xFF;
+            }
+        }
+        return out;
+    }
+}
+
+fn composite(frame: &Frame) -> Frame {
+    let mut out = frame.clone();
+    let mut yiq = [
+        vec![0.0; frame.width],
+        vec![0.0; frame.width],
+        vec![0.0; frame.width],
+    ];
+    for y in 0..frame.height {
+        for (x, pixel) in frame.row(y).chunks(4).enumerate() {
+            let (r, g, b) = (pixel[0] as f32, pixel[1] as f32, pixel[2] as f32);
+            yiq[0][x] = 0.299 * r + 0.587 * g + 0.114 * b;
+            yiq[1][x] = 0.596 * r - 0.274 * g - 0.322 * b;
+            yiq[2][x] = 0.211 * r - 0.523 * g + 0.312 * b;
+        }
+        let luma = blur(&yiq[0], LUMA_RADIUS);
+        let i = blur(&yiq[1], I_RADIUS);
+        let q = blur(&yiq[2], Q_RADIUS);
+        for (x, pixel) in out.row_mut(y).chunks_mut(4).enumerate() {
+            let rgb = [
+                luma[x] + 0.956 * i[x] + 0.621 * q[x],
+                luma[x] - 0.272 * i[x] - 0.647 * q[x],
+                luma[x] - 1.106 * i[x] + 1.703 * q[x],
+            ];
+            for channel in 0..3 {
+                pixel[channel] = rgb[channel].round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+    return out;
+}
+
+// a triangle kernel reaching `radius` pixels each side, the ends of the row repeat
+fn blur(row: &[f32], radius: usize) -> Vec<f32> {
+    let last = row.len() as isize - 1;
+    return (0..row.len())
+        .map(|x| {
+            let mut sum = 0.0;
+            let mut total = 0.0;
+            for offset in -(radius as isize)..=radius as isize {
+                let weight = (radius as isize + 1 - offset.abs()) as f32;
+                sum += row[(x as isize + offset).clamp(0, last) as usize] * weight;
+                total += weight;
+            }
+            return sum / total;
+        })
+        .collect();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_composite_blends_dithering() {
+        // alternating red and blue columns
+        let mut frame = Frame::new(8, 1);
+        for (x, pixel) in frame.row_mut(0).chunks_mut(4).enumerate() {
+            let colour = if x % 2 == 0 {
+                [0xFF, 0, 0]
+            } else {
+                [0, 0, 0xFF]
+            };
+            pixel[..3].copy_from_slice(&colour);
+        }
+        let filter = VideoFilter::parse("composite").unwrap();
+        let out = filter.apply(&frame);
+        assert_eq!((out.width, out.height), (8, 1));
+        // the middle columns come out much closer to each other than red and blue are
+        let (a, b) = (out.pixel(3, 0), out.pixel(4, 0));
+        assert!((a[0] as i32 - b[0] as i32).abs() < 0x80);
+        assert!((a[2] as i32 - b[2] as i32).abs() < 0x80);
+
+        // a flat colour stays as it was
+        let mut grey = Frame::new(4, 1);
+        grey.pixels.fill(0x80);
+        grey.pixels.chunks_mut(4).for_each(|pixel| pixel[3] = 0xFF);
+        assert_eq!(filter.apply(&grey).pixel(2, 0), [0x80, 0x80, 0x80, 0xFF]);
+    }
+
+    #[test]
+    fn test_scanlines_and_mask() {
+        let mut frame = Frame::new(1, 1);
+        frame.pixels.copy_from_slice(&[200, 200, 200, 0xFF]);
+        assert!(matches!(
+            VideoFilter::default().apply(&frame),
+            Cow::Borrowed(_)
+        ));
+
+        let out = VideoFilter::parse("scanlines").unwrap().apply(&frame);
+        assert_eq!((out.width, out.height), (2, 2));
+        assert_eq!(out.pixel(1, 0), [200, 200, 200, 0xFF]);
+        assert_eq!(out.pixel(1, 1), [110, 110, 110, 0xFF]);
+
+        let out = VideoFilter::parse("scanlines+mask").unwrap().apply(&frame);
+        assert_eq!((out.width, out.height), (3, 3));
+        assert_eq!(out.pixel(1, 0), [140, 200, 140, 0xFF]);
+        assert_eq!(out.pixel(2, 2), [77, 77, 110, 0xFF]);
+
+        assert_eq!(VideoFilter::parse("crt").unwrap().scale(), 3);
+        assert!(VideoFilter::parse("composite+blur").is_err());
+    }
+}
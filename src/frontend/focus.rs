@@ -0,0 +1,57 @@
+use std::time::Duration;
+
+// how long the session sleeps between frames, or between looks at the
+// host's events while paused, when the window is in the background
+pub const BACKGROUND_PERIOD: Duration = Duration::from_millis(100);
+
+/* Focus
+
+   What the session does while its window isn't the one in front, set with
+   `--unfocused`:
+
+    run       carry on as if nothing happened (the default)
+    pause     stop the console when the window loses focus and go on where
+              it was when it gets it back. Only the host's events are
+              looked at meanwhile, a few times a second
+    throttle  keep the game going, muted, at a frame every
+              `BACKGROUND_PERIOD`, a sixth of full speed for a small
+              fraction of the CPU. For games left to idle, such as a
+              music test, without the fans spinning up on a laptop
+
+   Either way every held input is let go when focus goes, since the key
+   ups go to whichever window has it. Hosts that can't tell when they have
+   the focus, like the terminal, never send the events and always run.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Unfocused {
+    #[default]
+    Run,
+    Pause,
+    Throttle,
+}
+
+impl Unfocused {
+    pub fn parse(name: &str) -> Result<Unfocused, String> {
+        match name {
+            "run" => return Ok(Unfocused::Run),
+            "pause" => return Ok(Unfocused::Pause),
+            "throttle" => return Ok(Unfocused::Throttle),
+            _ => return Err(format!("unknown unfocused mode {}", name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Unfocused::parse("throttle"), Ok(Unfocused::Throttle));
+        assert_eq!(
+            Unfocused::parse("sleep").err().unwrap(),
+            "unknown unfocused mode sleep"
+        );
+    }
+}
@@ -0,0 +1,1932 @@
+use crate::audio::{AudioSink, AudioSinks};
+use crate::battery::BatterySave;
+use crate::cartridge::{Region, Rom};
+use crate::crash;
+use crate::debugger::symbols::Symbols;
+use crate::debugger::{Debugger, Resume};
+use crate::hash::{self, Sha1};
+use crate::input::bindings::{Bindings, InputMapper};
+use crate::input::gamepad::{self, GamepadEvent, Gamepads};
+use crate::input::joypad::Button;
+use crate::input::{ControllerSetup, PLAYERS, PORT_1};
+use crate::movie::Movie;
+use crate::nes::Nes;
+use crate::ppu::palette::PaletteChoice;
+use crate::ppu::RenderMode;
+use crate::romdb;
+use crate::runner;
+use crate::savestate;
+use crate::state_slots::{self, SlotFile, StateSlots, SLOTS};
+use crate::sync::SyncHooks;
+use crate::tas::TasEditor;
+use crate::trace::{History, DEFAULT_HISTORY};
+use crate::video::record::{Recording, VideoFormat};
+use crate::video::{self, Frame, FrameTiming, ScreenshotOptions, NTSC_FRAME_DURATION};
+use config::{Config, GameConfig, RecentRoms};
+use display::{AspectRatio, DisplayOptions, Rotation};
+use filter::VideoFilter;
+use focus::{Unfocused, BACKGROUND_PERIOD};
+use osd::{FpsCounter, Osd};
+use pacing::{AudioPacer, SyncMode, AUDIO_LATENCY};
+use stats::{Stats, StatsMeter};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use terminal::{TerminalHost, TerminalMode, TerminalStyle};
+use watch::FileWatch;
+
+pub mod config;
+pub mod dialog;
+pub mod display;
+pub mod filter;
+pub mod focus;
+pub mod osd;
+pub mod pacing;
+#[cfg(feature = "pixels")]
+pub mod pixels;
+#[cfg(feature = "sdl")]
+pub mod sdl;
+pub mod stats;
+pub mod terminal;
+pub mod watch;
+
+// this far behind, e.g. after the window was dragged, the pacer gives up catching up
+const MAX_LAG: Duration = Duration::from_millis(100);
+// piano roll rows shown while a movie is paused, and those before the cursor
+const ROLL_ROWS: usize = 8;
+const ROLL_BEFORE: usize = 3;
+// the most frames --run-ahead takes, more than any game lags
+const MAX_RUN_AHEAD: usize = 4;
+
+/* Frontend
+
+   The real-time loop a windowed frontend runs: each frame it drains the
+   host's events into the input layer, runs the console for one frame,
+   hands the picture to the host and then waits out the rest of the frame,
+   at the NTSC console's 60.0988 Hz, the only timing emulated so far, which
+   is why `--region` takes nothing but ntsc. Headless runs
+   skip the wait, and the window, and go as fast as they can. `pacing`
+   has the other ways of deciding when a frame is due, and `focus` what
+   happens while the window is in the background. With `--frames N
+   --hash` a headless run is a batch job, printing hashes of where the
+   game ended up (see `hash_report`) for compatibility sweeps and bug
+   reports that anyone can reproduce.
+
+   The platform side (a window with a streaming texture, the keyboard and
+   controller event pump) is a `Host`, so the loop is the same whatever
+   draws the picture. `--renderer` picks the host:
+
+    sdl               an SDL2 window and event pump, see `sdl`
+    pixels            a winit window drawn through pixels/wgpu, for platforms
+                      where SDL2 is awkward, see `pixels`
+    terminal          text in the terminal itself, see `terminal`
+    terminal:braille  the same in braille dots
+
+   The SDL2 window needs the `sdl` feature, and the SDL2 library to link
+   against, and the pixels window the `pixels` feature. The default
+   renderer is the SDL2 window when it's built in, then the pixels one,
+   and the terminal otherwise. The terminal has no Shift key to send, so Tab is bound to
+   Select there.
+
+   Hotkeys are host keys the session handles itself instead of passing
+   them to the controllers: F11 toggles fullscreen, F12 saves a screenshot
+   and F9 starts and stops a gameplay recording, both named after the game
+   and put in the capture directory. F5 saves the console to the selected
+   `state_slots` slot and F7 loads it back, the number keys pick a slot
+   and F6 the next one; the slots live in `states` in the config
+   directory, or in `--state-dir DIR`. With `--debug`, F8 stops the game at
+   the `debugger` prompt on the terminal, or with `--debug-tui` in its
+   full-screen view, which takes the keys while it is up, and `--symbols
+   FILE` gives it the game's labels. Each frame's audio goes to the
+   session's audio sinks, and to the recording while there is one. How the
+   picture is scaled into the window is up to `display`, and the picture
+   goes through the `filter` stage first when one is set, is turned for
+   `--rotate`, then gets the `osd` notices drawn over it. What a hotkey
+   did, or why it failed, shows up there instead of stopping the session.
+   `stats` keeps track of how well the session is keeping up. A CPU jam or
+   a panic leaves a `crash` report in the capture directory.
+
+   `--tas MOVIE` makes or changes a movie a frame at a time with the `tas`
+   editor, a new one if there's no such file, saved at the end. It starts
+   paused, showing the piano roll around the frame about to run: Space
+   plays and pauses, . runs a frame and , goes back one, F10 switches
+   between playing the movie's input and recording the controllers over
+   it, and Insert and Delete add and take away a frame there.
+
+   A movie played with `--play` that stops matching the checksums it was
+   recorded with says so on the screen at the frame it was caught, and
+   the run ends in an error once it's over, so `--headless --play`
+   makes a regression test of a recorded game.
+
+   `--run-ahead N` shows each frame as it will be N frames on, to make up
+   for games that take a frame or two to act on a button, see
+   `Nes::run_frame_ahead`. One frame is enough for most games and each
+   costs a frame's emulation; it's left off while the debugger or the TAS
+   editor is running the console, as they need it where it is.
+
+   Leaving a game, by quitting or loading another, saves it next to its
+   state slots, battery RAM and all. The next time it's started the
+   session holds it at the first frame and asks whether to resume from
+   there: Return does, Escape boots it as usual. `--fresh` boots without
+   asking, `resume = no` in the config turns it off for every game or
+   one, and headless runs, batch jobs that should start the same way
+   every time, never do it.
+
+   Another game can be started without going back to the command line,
+   by dropping its .nes or .zip on the window or picking it with F3's
+   file dialog, see `dialog`. The old cartridge's battery save is written
+   out before it is swapped for the new one, whose save is loaded in turn;
+   while running, battery RAM is also flushed every few seconds and on
+   exit. Saves go next to the ROM, or in `--save-dir DIR` or the config's
+   `save_dir` when there is one. A game that fails to load leaves the current one running.
+
+   Every game boots with the settings `config` has for it, its section of
+   the config file over the top of the file, with the command line's on
+   top of both, and goes to the front of the recent list. `--romdb FILE`
+   loads a ROM database of header fixes over the built-in one, and over
+   the config's `romdb`, before the game is read. `watch` reloads
+   it whenever its file changes.
+*/
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HostEvent {
+    Quit,
+    KeyDown(String), // the key's name, as used in the bindings file
+    KeyUp(String),
+    KeyRepeat(String), // pressed again while held, which games don't see
+    Gamepad(GamepadEvent),
+    FocusLost,
+    FocusGained,
+    FileDropped(PathBuf),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Renderer {
+    #[cfg(feature = "sdl")]
+    Sdl,
+    #[cfg(feature = "pixels")]
+    Pixels,
+    Terminal(TerminalMode),
+}
+
+impl Default for Renderer {
+    // the window when there is one
+    fn default() -> Self {
+        #[cfg(feature = "sdl")]
+        return Renderer::Sdl;
+        #[cfg(all(feature = "pixels", not(feature = "sdl")))]
+        return Renderer::Pixels;
+        #[cfg(not(any(feature = "sdl", feature = "pixels")))]
+        return Renderer::Terminal(TerminalMode::HalfBlock);
+    }
+}
+
+impl Renderer {
+    pub fn parse(name: &str) -> Result<Renderer, String> {
+        match name {
+            #[cfg(feature = "sdl")]
+            "sdl" => return Ok(Renderer::Sdl),
+            #[cfg(not(feature = "sdl"))]
+            "sdl" => {
+                return Err("this build has no SDL2 window, it needs the sdl feature".to_string())
+            }
+            #[cfg(feature = "pixels")]
+            "pixels" => return Ok(Renderer::Pixels),
+            #[cfg(not(feature = "pixels"))]
+            "pixels" => {
+                return Err(
+                    "this build has no pixels window, it needs the pixels feature".to_string(),
+                )
+            }
+            "terminal" => return Ok(Renderer::Terminal(TerminalMode::HalfBlock)),
+            "terminal:braille" => return Ok(Renderer::Terminal(TerminalMode::Braille)),
+            _ => return Err(format!("unknown renderer {}", name)),
+        }
+    }
+}
+
+pub trait Host {
+    // everything that happened since the last call
+    fn poll_events(&mut self) -> Vec<HostEvent>;
+
+    fn present(&mut self, frame: &Frame) -> Result<(), String>;
+
+    // called at the start and whenever a hotkey changes them, hosts without
+    // a window can ignore it
+    fn set_display(&mut self, _display: &DisplayOptions) {}
+
+    // whether `present` waits for the display's refresh, with vsync asked for
+    fn has_vsync(&self) -> bool {
+        return false;
+    }
+
+    // a sound device playing at `sample_rate`, and the sink that feeds
+    // it; None for hosts without one
+    fn open_audio(&mut self, _sample_rate: u32) -> Result<Option<Box<dyn AudioSink>>, String> {
+        return Ok(None);
+    }
+
+    // samples the sound device has yet to play, for audio pacing
+    fn audio_queued(&self) -> Option<usize> {
+        return None;
+    }
+
+    // the window's, when another game is loaded
+    fn set_title(&mut self, _title: &str) {}
+
+    // the ROM to load next, None when the user cancelled
+    fn open_file_dialog(&mut self) -> Result<Option<PathBuf>, String> {
+        return dialog::open_rom();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hotkey {
+    ToggleFullscreen,
+    Screenshot,
+    ToggleRecording,
+    OpenRom,
+    Break, // into the debugger, when there is one
+    SaveState,
+    LoadState,
+    NextSlot,
+    SelectSlot(u8),
+    TasPause, // with --tas
+    TasAdvance,
+    TasBack,
+    TasRecord,
+    TasInsert,
+    TasDelete,
+}
+
+pub fn default_hotkeys() -> BTreeMap<String, Hotkey> {
+    let mut hotkeys = BTreeMap::from([
+        ("F11".to_string(), Hotkey::ToggleFullscreen),
+        ("F12".to_string(), Hotkey::Screenshot),
+        ("F9".to_string(), Hotkey::ToggleRecording),
+        ("F3".to_string(), Hotkey::OpenRom),
+        ("F8".to_string(), Hotkey::Break),
+        ("F5".to_string(), Hotkey::SaveState),
+        ("F7".to_string(), Hotkey::LoadState),
+        ("F6".to_string(), Hotkey::NextSlot),
+    ]);
+    for slot in 0..SLOTS {
+        hotkeys.insert(slot.to_string(), Hotkey::SelectSlot(slot));
+    }
+    return hotkeys;
+}
+
+// the TAS editor's, over the others with --tas
+pub fn tas_hotkeys() -> BTreeMap<String, Hotkey> {
+    return BTreeMap::from([
+        ("Space".to_string(), Hotkey::TasPause),
+        (".".to_string(), Hotkey::TasAdvance),
+        (",".to_string(), Hotkey::TasBack),
+        ("F10".to_string(), Hotkey::TasRecord),
+        ("Insert".to_string(), Hotkey::TasInsert),
+        ("Delete".to_string(), Hotkey::TasDelete),
+    ]);
+}
+
+// deadlines for the start of each frame, a fixed duration apart
+#[derive(Debug, Clone)]
+pub struct FramePacer {
+    pub frame_duration: Duration,
+    next: Option<Instant>,
+}
+
+impl Default for FramePacer {
+    fn default() -> Self {
+        return Self::new(NTSC_FRAME_DURATION);
+    }
+}
+
+impl FramePacer {
+    pub fn new(frame_duration: Duration) -> Self {
+        return Self {
+            frame_duration,
+            next: None,
+        };
+    }
+
+    // how long to sleep at `now` for the next frame to start on time
+    pub fn delay(&mut self, now: Instant) -> Duration {
+        let next = self.next.unwrap_or(now) + self.frame_duration;
+        if now > next + MAX_LAG {
+            self.next = Some(now);
+            return Duration::ZERO;
+        }
+        self.next = Some(next);
+        return next.saturating_duration_since(now);
+    }
+
+    pub fn wait(&mut self) {
+        let delay = self.delay(Instant::now());
+        if !delay.is_zero() {
+            thread::sleep(delay);
+        }
+    }
+}
+
+pub struct Session {
+    pub nes: Nes,
+    pub mapper: InputMapper,
+    pub gamepads: Gamepads,
+    pub pacer: Option<FramePacer>, // None runs as fast as the host allows
+    pub sync: SyncMode,
+    pub frame_limit: Option<u64>,
+    pub frames: u64,
+    pub display: DisplayOptions,
+    pub filter: VideoFilter,
+    pub hotkeys: BTreeMap<String, Hotkey>,
+    pub title: String, // the game's, for file names
+    pub screenshots: ScreenshotOptions,
+    pub capture_dir: PathBuf,
+    pub audio: AudioSinks,
+    pub video_format: VideoFormat,
+    pub recording: Option<Recording>,
+    pub osd: Osd,
+    pub battery: Option<BatterySave>, // None without a battery on the cartridge
+    pub save_dir: Option<PathBuf>,    // where battery saves go, None next to the ROM
+    pub states: StateSlots,
+    pub hooks: SyncHooks,
+    pub config: Config,
+    pub overrides: GameConfig, // the command line's, over every game's config
+    pub bindings: Bindings,    // before the game's own
+    pub recent: Option<RecentRoms>,
+    pub show_stats: bool,         // in the title and the OSD
+    pub watch: Option<FileWatch>, // the ROM file, for --watch
+    pub keep_ram: bool,           // across reloads
+    pub unfocused: Unfocused,
+    pub debugger: Option<Debugger>,     // for --debug
+    pub cycle_profile: Option<PathBuf>, // where the CPU's profile goes at the end
+    pub flamegraph: Option<PathBuf>,    // and its folded lines
+    pub heatmap: Option<PathBuf>,
+    pub instruction_stats: Option<PathBuf>,
+    pub tas: Option<TasEditor>,
+    pub tas_movie: Option<PathBuf>, // where the edited movie is saved
+    pub run_ahead: usize,           // frames, for --run-ahead
+    pub resume: bool,               // save the game as it's left, unless its config says not
+    offer: Option<SlotFile>,        // the resume state, until taken or turned down
+    focused: bool,
+    jam_reported: bool,
+    desync_reported: bool,
+    fps: FpsCounter,
+    stats: StatsMeter,
+}
+
+impl Session {
+    // the default keyboard and gamepad bindings
+    pub fn new(nes: Nes) -> Self {
+        let mut bindings = Bindings::default_keyboard();
+        gamepad::default_gamepad_bindings(&mut bindings);
+        let states = StateSlots::new(PathBuf::from("states"), nes.rom());
+        return Self {
+            nes,
+            mapper: InputMapper::new(bindings.clone()),
+            gamepads: Gamepads::new(PLAYERS),
+            pacer: Some(FramePacer::default()),
+            sync: SyncMode::default(),
+            frame_limit: None,
+            frames: 0,
+            display: DisplayOptions::default(),
+            filter: VideoFilter::default(),
+            hotkeys: default_hotkeys(),
+            title: "rustynes".to_string(),
+            screenshots: ScreenshotOptions::default(),
+            capture_dir: PathBuf::from("."),
+            audio: AudioSinks::new(),
+            video_format: VideoFormat::default(),
+            recording: None,
+            osd: Osd::new(),
+            battery: None,
+            save_dir: None,
+            states,
+            hooks: SyncHooks::new(),
+            config: Config::default(),
+            overrides: GameConfig::default(),
+            bindings,
+            recent: None,
+            watch: None,
+            keep_ram: false,
+            unfocused: Unfocused::default(),
+            debugger: None,
+            cycle_profile: None,
+            flamegraph: None,
+            heatmap: None,
+            instruction_stats: None,
+            tas: None,
+            tas_movie: None,
+            run_ahead: 0,
+            resume: false,
+            offer: None,
+            focused: true,
+            jam_reported: false,
+            desync_reported: false,
+            fps: FpsCounter::default(),
+            stats: StatsMeter::default(),
+            show_stats: false,
+        };
+    }
+
+    // one frame without the wait, false once the host asked to quit
+    pub fn step(&mut self, host: &mut dyn Host) -> Result<bool, String> {
+        let started = Instant::now();
+        for event in host.poll_events() {
+            match event {
+                HostEvent::Quit => return Ok(false),
+                HostEvent::KeyDown(key) if self.offer.is_some() => self.answer_offer(&key),
+                HostEvent::KeyDown(key) | HostEvent::KeyRepeat(key) if self.debugger_stopped() => {
+                    let debugger = self.debugger.as_mut().unwrap();
+                    if debugger.key(&key, &mut self.nes.cpu)? == Resume::Quit {
+                        return Ok(false);
+                    }
+                }
+                HostEvent::KeyDown(key) => match self.hotkeys.get(&key) {
+                    Some(&hotkey) => {
+                        if let Err(e) = self.hotkey(hotkey, host) {
+                            self.osd.notify(&e);
+                        }
+                    }
+                    None => self.mapper.set_held(&key, true),
+                },
+                HostEvent::KeyUp(key) => self.mapper.set_held(&key, false),
+                HostEvent::KeyRepeat(_) => {}
+                HostEvent::Gamepad(event) => self.gamepads.handle(&event, &mut self.mapper),
+                HostEvent::FocusLost => {
+                    self.mapper.release_all();
+                    self.focused = false;
+                }
+                HostEvent::FocusGained => self.focused = true,
+                HostEvent::FileDropped(path) => {
+                    if let Err(e) = self.load_rom(&path, host) {
+                        self.osd.notify(&e);
+                    }
+                }
+            }
+        }
+        if self.offer.is_some() {
+            self.present(host)?;
+            return Ok(true);
+        }
+        if let Some(debugger) = &mut self.debugger {
+            if debugger.take_break() {
+                self.mapper.release_all();
+                if debugger.stop(&mut self.nes.cpu, "")? == Resume::Quit {
+                    return Ok(false);
+                }
+            }
+            if debugger.is_stopped() {
+                debugger.draw(&self.nes.cpu)?;
+                return Ok(true);
+            }
+        }
+        if self.in_background() == Some(Unfocused::Pause) {
+            return Ok(true);
+        }
+        if let Some(watch) = &mut self.watch {
+            if watch.poll(Instant::now()) {
+                let path = watch.path().to_path_buf();
+                if let Err(e) = self.reload(&path, host) {
+                    self.osd.notify(&e);
+                }
+            }
+        }
+        self.mapper.apply(self.nes.ports_mut());
+        match (&mut self.tas, &mut self.debugger) {
+            (Some(tas), _) => {
+                if !tas.paused {
+                    let live = live_buttons(&mut self.nes);
+                    tas.advance(&mut self.nes, live);
+                }
+                self.osd.panel = tas_panel(tas);
+            }
+            (None, Some(debugger)) => {
+                if debugger.run_frame(&mut self.nes)? == Resume::Quit {
+                    return Ok(false);
+                }
+            }
+            (None, None) => self.nes.run_frame_ahead(self.run_ahead),
+        }
+        self.mapper.end_frame();
+        self.frames += 1;
+        if self.nes.cpu.jammed != self.jam_reported {
+            self.jam_reported = self.nes.cpu.jammed;
+            if self.jam_reported {
+                self.report_jam();
+            }
+        }
+        if let (Some(desync), false) = (self.nes.desync(), self.desync_reported) {
+            self.desync_reported = true;
+            self.osd.notify(&desync.to_string());
+        }
+        if let Err(e) = self.tick_battery() {
+            self.osd.notify(&e);
+        }
+        self.output_frame()?;
+        if let Some(fps) = self.fps.tick(Instant::now()) {
+            self.osd.set_fps(fps);
+        }
+        self.present(host)?;
+        self.update_stats(started, host);
+        return Ok(true);
+    }
+
+    // the picture through the filter, turned, with the notices over it
+    fn present(&mut self, host: &mut dyn Host) -> Result<(), String> {
+        let mut frame = self.filter.apply(self.nes.frame());
+        // turned before the notices so they read the right way up
+        if self.display.rotation != Rotation::None {
+            frame = Cow::Owned(frame.rotated(self.display.rotation.quarter_turns()));
+        }
+        if !self.osd.is_empty() {
+            self.osd.draw(frame.to_mut(), self.filter.scale());
+        }
+        host.present(&frame)?;
+        self.osd.end_frame();
+        return Ok(());
+    }
+
+    // whether leaving the game saves it, to be offered back next time
+    fn resumes(&self) -> bool {
+        return self.resume && self.settings_for(self.nes.rom()).resume != Some(false);
+    }
+
+    // not while a movie drives the console, nor with the last one still
+    // on offer
+    fn save_resume(&self) -> Result<(), String> {
+        if !self.resumes() || self.offer.is_some() || self.nes.is_playing() || self.tas.is_some() {
+            return Ok(());
+        }
+        return self
+            .states
+            .save_resume(&self.nes, SystemTime::now(), &self.hooks);
+    }
+
+    // holds the game at its first frame, asking whether to carry on from
+    // where it was left, if it was
+    pub fn offer_resume(&mut self) {
+        if !self.resumes() || self.nes.is_recording() || self.nes.is_playing() || self.tas.is_some()
+        {
+            return;
+        }
+        match self.states.read_resume() {
+            Ok(Some(file)) => {
+                self.osd.panel = vec![
+                    format!("Resume from {}?", state_slots::timestamp(file.saved)),
+                    "Return resumes, Escape starts afresh".to_string(),
+                ];
+                self.offer = Some(file);
+            }
+            Ok(None) => {}
+            Err(e) => self.osd.notify(&e),
+        }
+    }
+
+    fn answer_offer(&mut self, key: &str) {
+        let file = match key {
+            "Return" => self.offer.take(),
+            "Escape" => self.offer.take().and(None),
+            _ => return,
+        };
+        self.osd.panel.clear();
+        if let Some(file) = file {
+            match savestate::load(&mut self.nes.cpu, &file.state) {
+                Ok(()) => self.osd.notify(&format!(
+                    "Resumed from {}",
+                    state_slots::timestamp(file.saved)
+                )),
+                Err(e) => self.osd.notify(&e.to_string()),
+            }
+        }
+    }
+
+    fn debugger_stopped(&self) -> bool {
+        return self.debugger.as_ref().is_some_and(|d| d.is_stopped());
+    }
+
+    // the latest, a second's worth, None in the first second
+    pub fn stats(&self) -> Option<&Stats> {
+        return self.stats.latest();
+    }
+
+    fn update_stats(&mut self, started: Instant, host: &mut dyn Host) {
+        let now = Instant::now();
+        let sample_rate = self.nes.cpu.bus.apu.sample_rate() as f64;
+        let audio = host
+            .audio_queued()
+            .map(|queued| Duration::from_secs_f64(queued as f64 / sample_rate));
+        let frame_rate = 1.0 / NTSC_FRAME_DURATION.as_secs_f64();
+        let stats = self.stats.record(
+            now,
+            self.nes.cpu.instructions,
+            now - started,
+            audio,
+            frame_rate,
+        );
+        if let (true, Some(stats)) = (self.show_stats, stats) {
+            host.set_title(&format!("{} - {}", self.title, stats.summary()));
+            self.osd.status = Some(stats.brief());
+        }
+    }
+
+    fn hotkey(&mut self, hotkey: Hotkey, host: &mut dyn Host) -> Result<(), String> {
+        match hotkey {
+            Hotkey::ToggleFullscreen => {
+                self.display.fullscreen = !self.display.fullscreen;
+                host.set_display(&self.display);
+            }
+            Hotkey::Screenshot => {
+                let name = video::screenshot_name(&self.title, self.nes.frame_count());
+                self.nes
+                    .screenshot(&self.capture_dir.join(&name), &self.screenshots)?;
+                self.osd.notify(&format!("Saved {}", name));
+            }
+            Hotkey::ToggleRecording => match self.recording.take() {
+                Some(recording) => {
+                    let path = recording.path().to_path_buf();
+                    recording.finish()?;
+                    self.osd.notify(&format!("Saved {}", path.display()));
+                }
+                None => {
+                    self.start_recording()?;
+                    self.osd.notify("Recording");
+                }
+            },
+            Hotkey::OpenRom => {
+                if let Some(path) = host.open_file_dialog()? {
+                    self.load_rom(&path, host)?;
+                }
+            }
+            Hotkey::Break => {
+                if let Some(debugger) = &mut self.debugger {
+                    debugger.request_break();
+                }
+            }
+            Hotkey::SaveState => {
+                let slot = self.states.selected;
+                self.states
+                    .save(slot, &self.nes, SystemTime::now(), &self.hooks)?;
+                self.osd.notify(&format!("State {} saved", slot));
+            }
+            Hotkey::LoadState => {
+                if self.nes.is_recording() || self.nes.is_playing() || self.tas.is_some() {
+                    return Err("stop the movie before loading a state".to_string());
+                }
+                let slot = self.states.selected;
+                let saved = self.states.load(slot, &mut self.nes.cpu)?;
+                self.osd.notify(&format!(
+                    "State {} loaded, from {}",
+                    slot,
+                    state_slots::timestamp(saved)
+                ));
+            }
+            Hotkey::NextSlot => {
+                let slot = self.states.select_next();
+                self.select_slot(slot)?;
+            }
+            Hotkey::SelectSlot(slot) => self.select_slot(slot)?,
+            Hotkey::TasPause
+            | Hotkey::TasAdvance
+            | Hotkey::TasBack
+            | Hotkey::TasRecord
+            | Hotkey::TasInsert
+            | Hotkey::TasDelete => self.tas_hotkey(hotkey)?,
+        }
+        return Ok(());
+    }
+
+    fn tas_hotkey(&mut self, hotkey: Hotkey) -> Result<(), String> {
+        let Some(tas) = &mut self.tas else {
+            return Err("no movie to edit, see --tas".to_string());
+        };
+        let frame = tas.frame();
+        match hotkey {
+            Hotkey::TasPause => tas.paused = !tas.paused,
+            Hotkey::TasAdvance => {
+                tas.paused = true;
+                self.mapper.apply(self.nes.ports_mut());
+                let live = live_buttons(&mut self.nes);
+                tas.advance(&mut self.nes, live);
+            }
+            Hotkey::TasBack => {
+                tas.paused = true;
+                tas.seek(&mut self.nes, frame.saturating_sub(1))?;
+            }
+            Hotkey::TasRecord => {
+                tas.recording = !tas.recording;
+                let mode = if tas.recording {
+                    "Recording"
+                } else {
+                    "Playing"
+                };
+                self.osd.notify(&format!("{} from frame {}", mode, frame));
+            }
+            Hotkey::TasInsert => tas.insert(&mut self.nes, frame, 1)?,
+            Hotkey::TasDelete => tas.delete(&mut self.nes, frame, 1)?,
+            _ => {}
+        }
+        self.osd.panel = tas_panel(tas);
+        return Ok(());
+    }
+
+    // says what's in the slot as it becomes the one the hotkeys use
+    fn select_slot(&mut self, slot: u8) -> Result<(), String> {
+        self.states.selected = slot;
+        match self.states.read(slot)? {
+            Some(file) => self.osd.notify(&format!(
+                "State {}, saved {}",
+                slot,
+                state_slots::timestamp(file.saved)
+            )),
+            None => self.osd.notify(&format!("State {}, empty", slot)),
+        }
+        return Ok(());
+    }
+
+    // swaps the running game for the one at `path`, which boots with its own
+    // settings and the same render mode
+    pub fn load_rom(&mut self, path: &Path, host: &mut dyn Host) -> Result<(), String> {
+        if self.nes.is_recording() || self.nes.is_playing() || self.tas.is_some() {
+            return Err("stop the movie before changing games".to_string());
+        }
+        let mut rom = Rom::from_file(path, None)?;
+        let settings = self.settings_for(&rom);
+        rom.region = settings.region.unwrap_or(rom.region);
+        let palette = settings.palette.clone().unwrap_or_default().colours()?;
+        let mut battery = BatterySave::new(&rom, path, self.save_dir.as_deref());
+        self.flush_battery()?;
+        self.save_resume()?;
+        if let Some(recording) = self.recording.take() {
+            recording.finish()?;
+        }
+        self.nes.swap_cartridge(rom)?;
+        self.states.set_rom(self.nes.rom());
+        self.offer = None;
+        self.osd.panel.clear();
+        self.nes.cpu.bus.ppu.set_palette(&palette);
+        self.apply_settings(&settings)?;
+        host.set_display(&self.display);
+        if let (Some(battery), Some(mapper)) = (&mut battery, self.nes.cpu.bus.mapper_mut()) {
+            battery.load(mapper)?;
+        }
+        self.battery = battery;
+        self.mapper.release_all();
+        self.title = game_title(self.nes.rom(), path);
+        host.set_title(&self.title);
+        self.osd.notify(&format!("Loaded {}", self.title));
+        self.offer_resume();
+        self.remember(path);
+        if let Some(watch) = &mut self.watch {
+            if watch.path() != path {
+                *watch = FileWatch::new(path, Instant::now());
+            }
+        }
+        return Ok(());
+    }
+
+    // the game at `path` again after it changed, with the RAM it had if
+    // `keep_ram` is set
+    fn reload(&mut self, path: &Path, host: &mut dyn Host) -> Result<(), String> {
+        let bus = &self.nes.cpu.bus;
+        let ram = bus.ram().to_vec();
+        let prg_ram = bus
+            .mapper()
+            .and_then(|m| m.prg_ram())
+            .map(|ram| ram.to_vec());
+        self.load_rom(path, host)?;
+        if self.keep_ram {
+            let bus = &mut self.nes.cpu.bus;
+            bus.ram_mut().copy_from_slice(&ram);
+            if let (Some(old), Some(new)) =
+                (prg_ram, bus.mapper_mut().and_then(|m| m.prg_ram_mut()))
+            {
+                let len = old.len().min(new.len());
+                new[..len].copy_from_slice(&old[..len]);
+            }
+        }
+        return Ok(());
+    }
+
+    // what `rom` boots with, the command line's settings over its config
+    pub fn settings_for(&self, rom: &Rom) -> GameConfig {
+        let mut settings = self.config.for_rom(rom);
+        settings.merge(&self.overrides);
+        return settings;
+    }
+
+    // the settings that can change with the console already built, the
+    // region and palette are up to the caller
+    fn apply_settings(&mut self, settings: &GameConfig) -> Result<(), String> {
+        self.nes
+            .ports_mut()
+            .set_up(settings.controllers.unwrap_or_default());
+        self.screenshots.crop_overscan = settings.crop_overscan.unwrap_or(false);
+        self.display.rotation = settings.rotation.unwrap_or_default();
+        let cheats = self.nes.cpu.bus.cheats_mut();
+        cheats.clear();
+        for code in &settings.cheats {
+            cheats.add(code)?;
+        }
+        let mut bindings = self.bindings.clone();
+        bindings.overlay(&settings.bindings);
+        self.mapper.bindings = bindings;
+        return Ok(());
+    }
+
+    // puts the game at the top of the recent list, if there is one
+    fn remember(&mut self, path: &Path) {
+        if let Some(recent) = &mut self.recent {
+            recent.add(path);
+            if let Err(e) = recent.save() {
+                self.osd.notify(&e);
+            }
+        }
+    }
+
+    // writes battery RAM out if it changed
+    pub fn flush_battery(&mut self) -> Result<(), String> {
+        if let (Some(battery), Some(mapper)) = (&mut self.battery, self.nes.cpu.bus.mapper()) {
+            battery
+                .flush(mapper, &self.hooks)
+                .map_err(|e| format!("{}: {}", battery.path().display(), e))?;
+        }
+        return Ok(());
+    }
+
+    fn tick_battery(&mut self) -> Result<(), String> {
+        if let (Some(battery), Some(mapper)) = (&mut self.battery, self.nes.cpu.bus.mapper()) {
+            battery
+                .tick(mapper, &self.hooks, Instant::now())
+                .map_err(|e| format!("{}: {}", battery.path().display(), e))?;
+        }
+        return Ok(());
+    }
+
+    pub fn start_recording(&mut self) -> Result<(), String> {
+        let name = video::screenshot_name(&self.title, self.nes.frame_count());
+        let path = self
+            .capture_dir
+            .join(name)
+            .with_extension(self.video_format.extension());
+        self.recording = Some(Recording::start(&path, self.video_format)?);
+        return Ok(());
+    }
+
+    // the finished frame's sound, and both to the recording
+    fn output_frame(&mut self) -> Result<(), String> {
+        let apu = &mut self.nes.cpu.bus.apu;
+        let samples = apu.take_samples();
+        let sample_rate = apu.sample_rate();
+        if self.in_background() != Some(Unfocused::Throttle) {
+            self.audio.push_samples(&samples, sample_rate)?;
+        }
+        if let Some(recording) = &mut self.recording {
+            let timing = FrameTiming {
+                frame: self.nes.frame_count(),
+                cpu_cycle: self.nes.cpu.cycles,
+                frame_duration: NTSC_FRAME_DURATION,
+            };
+            recording.push_frame(self.nes.frame(), &timing)?;
+            recording.push_samples(&samples, sample_rate)?;
+        }
+        return Ok(());
+    }
+
+    // saves battery RAM, the console to resume from and the movie being
+    // edited, ends the recording, flushes the audio sinks and writes out the
+    // cycle profile, heatmap and instruction statistics
+    pub fn finish(&mut self) -> Result<(), String> {
+        self.flush_battery()?;
+        self.save_resume()?;
+        if let (Some(tas), Some(path)) = (&self.tas, &self.tas_movie) {
+            tas.movie().save(path)?;
+        }
+        if let Some(recording) = self.recording.take() {
+            recording.finish()?;
+        }
+        self.audio.finish()?;
+        if let (Some(heatmap), Some(path)) = (&self.nes.cpu.bus.heatmap, &self.heatmap) {
+            heatmap.save(path)?;
+        }
+        if let (Some(stats), Some(path)) =
+            (self.nes.cpu.instruction_stats(), &self.instruction_stats)
+        {
+            std::fs::write(path, stats.report())
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+        }
+        if let Some(profiler) = &self.nes.cpu.profiler {
+            let outputs = [
+                (&self.cycle_profile, profiler.report(&self.nes.cpu)),
+                (&self.flamegraph, profiler.folded()),
+            ];
+            for (path, text) in outputs {
+                if let Some(path) = path {
+                    std::fs::write(path, text).map_err(|e| format!("{}: {}", path.display(), e))?;
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    pub fn run(&mut self, host: &mut dyn Host) -> Result<(), String> {
+        self.display.vsync = self.sync == SyncMode::Vsync;
+        host.set_display(&self.display);
+        while self.frame_limit.is_none_or(|limit| self.frames < limit) {
+            let running = match panic::catch_unwind(AssertUnwindSafe(|| self.step(host))) {
+                Ok(running) => running?,
+                Err(payload) => return Err(self.report_panic(payload)),
+            };
+            if !running {
+                break;
+            }
+            self.wait(host);
+        }
+        return Ok(());
+    }
+
+    fn report_jam(&mut self) {
+        let Err(reason) = self.nes.cpu.check() else {
+            return;
+        };
+        let pc = self.nes.cpu.program_counter;
+        let reason = reason.to_string();
+        match crash::write_report(&self.capture_dir, &self.nes, &self.title, &reason) {
+            Ok(path) => self.osd.notify(&format!(
+                "The CPU jammed at ${:04X}, see {}",
+                pc,
+                path.display()
+            )),
+            Err(e) => self.osd.notify(&e),
+        }
+    }
+
+    // the error the session ends with
+    fn report_panic(&mut self, payload: Box<dyn std::any::Any + Send>) -> String {
+        let message = runner::panic_message(payload);
+        let reason = format!("panic: {}", message);
+        match crash::write_report(&self.capture_dir, &self.nes, &self.title, &reason) {
+            Ok(path) => return format!("crashed ({}), report in {}", message, path.display()),
+            Err(e) => return format!("crashed ({}), and the report failed: {}", message, e),
+        }
+    }
+
+    // what the window losing focus has the session do, None while it has it
+    fn in_background(&self) -> Option<Unfocused> {
+        if self.focused || self.unfocused == Unfocused::Run {
+            return None;
+        }
+        return Some(self.unfocused);
+    }
+
+    // until the next frame is due
+    fn wait(&mut self, host: &dyn Host) {
+        if self.pacer.is_some() && self.in_background().is_some() {
+            thread::sleep(BACKGROUND_PERIOD);
+            return;
+        }
+        let Some(pacer) = &mut self.pacer else {
+            return;
+        };
+        match (self.sync, host.audio_queued()) {
+            (SyncMode::Vsync, _) if host.has_vsync() => {}
+            (SyncMode::Audio, Some(queued)) => {
+                let apu = &mut self.nes.cpu.bus.apu;
+                let (ratio, delay) =
+                    AudioPacer::new(apu.sample_rate(), AUDIO_LATENCY).update(queued);
+                apu.set_rate_ratio(ratio);
+                thread::sleep(delay);
+            }
+            _ => pacer.wait(),
+        }
+    }
+}
+
+// no window and no input, for --headless
+#[derive(Debug, Default)]
+pub struct HeadlessHost;
+
+impl Host for HeadlessHost {
+    fn poll_events(&mut self) -> Vec<HostEvent> {
+        return Vec::new();
+    }
+
+    fn present(&mut self, _: &Frame) -> Result<(), String> {
+        return Ok(());
+    }
+}
+
+pub fn open_window(title: &str, options: &RunOptions) -> Result<Box<dyn Host>, String> {
+    let vsync = options.sync == SyncMode::Vsync;
+    return open_host(
+        title,
+        options.renderer,
+        options.scale,
+        options.shrink,
+        vsync,
+    );
+}
+
+// `scale` is for a window, its pixels per picture pixel, and `shrink` for
+// the terminal, picture pixels per terminal pixel
+#[cfg_attr(not(any(feature = "sdl", feature = "pixels")), allow(unused_variables))]
+pub fn open_host(
+    title: &str,
+    renderer: Renderer,
+    scale: u32,
+    shrink: usize,
+    vsync: bool,
+) -> Result<Box<dyn Host>, String> {
+    match renderer {
+        #[cfg(feature = "sdl")]
+        Renderer::Sdl => return Ok(Box::new(sdl::SdlHost::open(title, scale, vsync)?)),
+        #[cfg(feature = "pixels")]
+        Renderer::Pixels => return Ok(Box::new(pixels::PixelsHost::open(title, scale, vsync)?)),
+        Renderer::Terminal(mode) => {
+            let style = TerminalStyle {
+                mode,
+                scale: shrink,
+                truecolor: terminal::supports_truecolor(),
+            };
+            return Ok(Box::new(TerminalHost::open(style)?));
+        }
+    }
+}
+
+pub struct RunOptions {
+    pub path: PathBuf,
+    pub region: Option<Region>, // instead of the header's
+    pub scale: u32,             // window pixels per NES pixel
+    pub shrink: usize,          // NES pixels per terminal pixel
+    pub display: DisplayOptions,
+    pub rotation: Option<Rotation>, // instead of the game's
+    pub filter: VideoFilter,
+    pub screenshots: ScreenshotOptions,
+    pub capture_dir: PathBuf,
+    pub state_dir: Option<PathBuf>, // instead of the one in the config directory
+    pub video_format: VideoFormat,
+    pub record_video: bool,
+    pub show_fps: bool,
+    pub show_stats: bool,
+    pub sync: SyncMode,
+    pub unfocused: Unfocused,
+    pub debug: bool,
+    pub debug_tui: bool,
+    pub symbols: Option<PathBuf>, // cc65 debug info or VICE labels for the debugger
+    pub trace_history: usize,     // instructions kept for crash reports
+    pub cycle_profile: Option<PathBuf>,
+    pub flamegraph: Option<PathBuf>,
+    pub heatmap: Option<PathBuf>,
+    pub instruction_stats: Option<PathBuf>,
+    pub headless: bool,
+    pub frames: Option<u64>,
+    pub hash: bool, // print the final frame's hash
+    pub watch: bool,
+    pub watch_keep_ram: bool,
+    pub hash_ram: bool, // and RAM's
+    pub renderer: Renderer,
+    pub palette: Option<PaletteChoice>,
+    pub render_mode: RenderMode,
+    pub controllers: Option<ControllerSetup>,
+    pub cheats: Vec<String>,
+    pub bindings: Option<PathBuf>,
+    pub config: Option<PathBuf>, // instead of the one in the config directory
+    pub romdb: Option<PathBuf>,  // over the built-in one and the config's
+    pub save_dir: Option<PathBuf>, // for battery saves, over the config's
+    pub play: Option<PathBuf>,
+    pub record: Option<PathBuf>,
+    pub tas: Option<PathBuf>,
+    pub run_ahead: usize,
+    pub fresh: bool, // boot without offering to resume
+}
+
+impl RunOptions {
+    pub fn parse(args: &[String]) -> Result<RunOptions, String> {
+        let mut path = None;
+        let mut options = RunOptions {
+            path: PathBuf::new(),
+            region: None,
+            scale: 3,
+            shrink: TerminalStyle::default().scale,
+            display: DisplayOptions::default(),
+            rotation: None,
+            filter: VideoFilter::default(),
+            screenshots: ScreenshotOptions::default(),
+            capture_dir: PathBuf::from("."),
+            state_dir: None,
+            video_format: VideoFormat::default(),
+            record_video: false,
+            show_fps: false,
+            show_stats: false,
+            sync: SyncMode::default(),
+            unfocused: Unfocused::default(),
+            debug: false,
+            debug_tui: false,
+            symbols: None,
+            trace_history: DEFAULT_HISTORY,
+            cycle_profile: None,
+            flamegraph: None,
+            heatmap: None,
+            instruction_stats: None,
+            headless: false,
+            frames: None,
+            hash: false,
+            watch: false,
+            watch_keep_ram: false,
+            hash_ram: false,
+            renderer: Renderer::default(),
+            palette: None,
+            render_mode: RenderMode::default(),
+            controllers: None,
+            cheats: Vec::new(),
+            bindings: None,
+            config: None,
+            romdb: None,
+            save_dir: None,
+            play: None,
+            record: None,
+            tas: None,
+            run_ahead: 0,
+            fresh: false,
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                return args.next().ok_or_else(|| format!("{} needs a value", name));
+            };
+            match arg.as_str() {
+                "--region" => options.region = Some(config::parse_region(value("--region")?)?),
+                "--scale" => options.scale = parse_number(value("--scale")?)?,
+                "--shrink" => options.shrink = parse_number(value("--shrink")?)?,
+                "--fullscreen" => options.display.fullscreen = true,
+                "--no-integer-scale" => options.display.integer_scale = false,
+                "--aspect" => options.display.aspect = AspectRatio::parse(value("--aspect")?)?,
+                "--rotate" => options.rotation = Some(Rotation::parse(value("--rotate")?)?),
+                "--filter" => options.filter = VideoFilter::parse(value("--filter")?)?,
+                "--screenshot-scale" => {
+                    options.screenshots.scale = parse_number(value("--screenshot-scale")?)?
+                }
+                "--crop-overscan" => options.screenshots.crop_overscan = true,
+                "--capture-dir" => options.capture_dir = PathBuf::from(value("--capture-dir")?),
+                "--state-dir" => options.state_dir = Some(PathBuf::from(value("--state-dir")?)),
+                "--romdb" => options.romdb = Some(PathBuf::from(value("--romdb")?)),
+                "--save-dir" => options.save_dir = Some(PathBuf::from(value("--save-dir")?)),
+                "--video-format" => {
+                    options.video_format = VideoFormat::parse(value("--video-format")?)?
+                }
+                "--record-video" => options.record_video = true,
+                "--show-fps" => options.show_fps = true,
+                "--show-stats" => options.show_stats = true,
+                "--sync" => options.sync = SyncMode::parse(value("--sync")?)?,
+                "--debug" => options.debug = true,
+                "--debug-tui" => options.debug_tui = true,
+                "--symbols" => options.symbols = Some(PathBuf::from(value("--symbols")?)),
+                "--unfocused" => options.unfocused = Unfocused::parse(value("--unfocused")?)?,
+                "--headless" => options.headless = true,
+                "--frames" => options.frames = Some(parse_number(value("--frames")?)?),
+                "--cycle-profile" => {
+                    options.cycle_profile = Some(PathBuf::from(value("--cycle-profile")?))
+                }
+                "--heatmap" => options.heatmap = Some(PathBuf::from(value("--heatmap")?)),
+                "--instruction-stats" => {
+                    options.instruction_stats = Some(PathBuf::from(value("--instruction-stats")?))
+                }
+                "--flamegraph" => options.flamegraph = Some(PathBuf::from(value("--flamegraph")?)),
+                "--trace-history" => {
+                    options.trace_history = parse_number(value("--trace-history")?)?
+                }
+                "--hash" => options.hash = true,
+                "--watch" => options.watch = true,
+                "--watch-keep-ram" => {
+                    options.watch = true;
+                    options.watch_keep_ram = true;
+                }
+                "--hash-ram" => options.hash_ram = true,
+                "--renderer" => options.renderer = Renderer::parse(value("--renderer")?)?,
+                "--palette" => options.palette = Some(PaletteChoice::parse(value("--palette")?)?),
+                "--render-mode" => {
+                    options.render_mode = RenderMode::parse(value("--render-mode")?)?
+                }
+                "--controllers" => {
+                    options.controllers = Some(ControllerSetup::parse(value("--controllers")?)?)
+                }
+                "--cheat" => options.cheats.push(value("--cheat")?.clone()),
+                "--config" => options.config = Some(PathBuf::from(value("--config")?)),
+                "--bindings" => options.bindings = Some(PathBuf::from(value("--bindings")?)),
+                "--play" => options.play = Some(PathBuf::from(value("--play")?)),
+                "--record" => options.record = Some(PathBuf::from(value("--record")?)),
+                "--tas" => options.tas = Some(PathBuf::from(value("--tas")?)),
+                "--run-ahead" => options.run_ahead = parse_number(value("--run-ahead")?)?,
+                "--fresh" => options.fresh = true,
+                flag if flag.starts_with("--") => return Err(format!("unknown option {}", flag)),
+                file if path.is_none() => path = Some(PathBuf::from(file)),
+                extra => return Err(format!("unexpected argument {}", extra)),
+            }
+        }
+        options.path = path.ok_or("missing ROM file")?;
+        if options.scale == 0 || options.shrink == 0 || options.screenshots.scale == 0 {
+            return Err("--scale, --shrink and --screenshot-scale must be at least 1".to_string());
+        }
+        if options.run_ahead > MAX_RUN_AHEAD {
+            return Err(format!("--run-ahead goes up to {} frames", MAX_RUN_AHEAD));
+        }
+        return Ok(options);
+    }
+
+    // the settings that win over the config file's
+    pub fn overrides(&self) -> GameConfig {
+        return GameConfig {
+            region: self.region,
+            palette: self.palette.clone(),
+            controllers: self.controllers,
+            crop_overscan: self.screenshots.crop_overscan.then_some(true),
+            rotation: self.rotation,
+            resume: None,
+            cheats: self.cheats.clone(),
+            bindings: Bindings::new(),
+        };
+    }
+}
+
+// what each player holds, as the bindings have it this frame
+fn live_buttons(nes: &mut Nes) -> [u8; PLAYERS] {
+    let ports = nes.ports_mut();
+    return std::array::from_fn(|player| ports.buttons(player));
+}
+
+// the TAS editor's status, and the piano roll around the cursor when paused
+fn tas_panel(tas: &TasEditor) -> Vec<String> {
+    let mode = match (tas.paused, tas.recording) {
+        (true, _) => "paused",
+        (false, true) => "recording",
+        (false, false) => "playing",
+    };
+    let mut lines = vec![format!(
+        "frame {}/{}  rerecords {}  {}",
+        tas.frame(),
+        tas.len(),
+        tas.rerecords(),
+        mode
+    )];
+    if tas.paused {
+        let from = tas.frame().saturating_sub(ROLL_BEFORE);
+        lines.extend(tas.piano_roll(from, ROLL_ROWS));
+    }
+    return lines;
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    return value
+        .parse()
+        .map_err(|_| format!("{} is not a valid number", value));
+}
+
+// the database's name for the game, or the file's
+fn game_title(rom: &Rom, path: &Path) -> String {
+    match rom.identify() {
+        Some(entry) => return entry.title,
+        None => {
+            return path
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string()
+        }
+    }
+}
+
+pub fn run(options: &RunOptions) -> Result<(), String> {
+    let config = match options.config.clone().or_else(config::default_config_path) {
+        Some(path) => Config::load(&path)?,
+        None => Config::default(),
+    };
+    // the config's database, then the command line's over it
+    for path in config.romdb.iter().chain(&options.romdb) {
+        romdb::load_file(path)?;
+    }
+    let mut rom = Rom::from_file(&options.path, None)?;
+    let mut settings = config.for_rom(&rom);
+    settings.merge(&options.overrides());
+    rom.region = settings.region.unwrap_or(rom.region);
+    let palette = settings.palette.clone().unwrap_or_default();
+    let nes = Nes::builder()
+        .palette(palette.colours()?)
+        .render_mode(options.render_mode)
+        .build(rom)?;
+
+    let mut session = Session::new(nes);
+    session.config = config;
+    session.overrides = options.overrides();
+    if let Some(path) = &options.bindings {
+        session.bindings = Bindings::load(path)?;
+    } else if matches!(options.renderer, Renderer::Terminal(_)) {
+        session.bindings.bind("Tab", PORT_1, Button::Select);
+    }
+    session.screenshots = options.screenshots;
+    session.display = options.display;
+    session.apply_settings(&settings)?;
+    session.recent = RecentRoms::default_path().and_then(|path| RecentRoms::load(&path).ok());
+    session.remember(&options.path);
+    if options.watch {
+        session.watch = Some(FileWatch::new(&options.path, Instant::now()));
+        session.keep_ram = options.watch_keep_ram;
+    }
+    session.frame_limit = options.frames;
+    session.filter = options.filter;
+    session.capture_dir = options.capture_dir.clone();
+    if let Some(dir) = options.state_dir.clone().or_else(config::default_state_dir) {
+        session.states = StateSlots::new(dir, session.nes.rom());
+    }
+    session.nes.cpu.history = History::new(options.trace_history);
+    session.run_ahead = options.run_ahead;
+    if options.cycle_profile.is_some() || options.flamegraph.is_some() {
+        session.nes.cpu.profiler = Some(Box::default());
+        session.cycle_profile = options.cycle_profile.clone();
+        session.flamegraph = options.flamegraph.clone();
+    }
+    if let Some(path) = &options.instruction_stats {
+        session.nes.cpu.stats = Some(Box::default());
+        session.instruction_stats = Some(path.clone());
+    }
+    if let Some(path) = &options.heatmap {
+        session.nes.cpu.bus.heatmap = Some(Box::default());
+        session.heatmap = Some(path.clone());
+    }
+    session.video_format = options.video_format;
+    session.osd.show_fps = options.show_fps;
+    session.show_stats = options.show_stats;
+    session.sync = options.sync;
+    session.unfocused = options.unfocused;
+    if options.debug_tui {
+        if options.headless {
+            return Err("--debug-tui needs the terminal renderer to take keys".to_string());
+        }
+        session.debugger = Some(Debugger::full_screen(Box::new(std::io::stdout())));
+    } else if options.debug {
+        session.debugger = Some(Debugger::stdio());
+    }
+    if let Some(path) = &options.symbols {
+        let debugger = session
+            .debugger
+            .as_mut()
+            .ok_or("--symbols needs --debug or --debug-tui")?;
+        debugger.set_symbols(Symbols::load(path)?);
+    }
+    session.title = game_title(session.nes.rom(), &options.path);
+    session.save_dir = options
+        .save_dir
+        .clone()
+        .or_else(|| session.config.save_dir.clone());
+    session.battery = BatterySave::new(
+        session.nes.rom(),
+        &options.path,
+        session.save_dir.as_deref(),
+    );
+    if let (Some(battery), Some(mapper)) = (&mut session.battery, session.nes.cpu.bus.mapper_mut())
+    {
+        battery.load(mapper)?;
+    }
+    session.pacer = Some(FramePacer::default());
+    let mut host: Box<dyn Host> = if options.headless {
+        session.pacer = None;
+        Box::new(HeadlessHost)
+    } else {
+        open_window(&session.title, options)?
+    };
+    match host.open_audio(session.nes.sample_rate()) {
+        Ok(Some(sink)) => session.audio.attach(sink),
+        Ok(None) => {}
+        Err(e) => session.osd.notify(&format!("No sound: {}", e)),
+    }
+
+    if let Some(path) = &options.play {
+        session.nes.play(Movie::load(path)?)?;
+    }
+    if options.record.is_some() {
+        session.nes.start_recording();
+    }
+    if let Some(path) = &options.tas {
+        if options.play.is_some() || options.record.is_some() || session.debugger.is_some() {
+            return Err("--tas doesn't go with --play, --record or the debugger".to_string());
+        }
+        let movie = match path.exists() {
+            true => Movie::load(path)?,
+            false => Movie::new(savestate::save(&session.nes.cpu)),
+        };
+        session.tas = Some(TasEditor::new(&mut session.nes, movie)?);
+        session.tas_movie = Some(path.clone());
+        session.hotkeys.extend(tas_hotkeys());
+    }
+    // a batch run starts from power on every time, and nobody is there to ask
+    session.resume = !options.headless;
+    if !options.fresh {
+        session.offer_resume();
+    }
+    if options.record_video {
+        session.start_recording()?;
+    }
+    let result = session.run(host.as_mut());
+    drop(host);
+    let result = result.and(session.finish());
+    if let (Some(path), Some(movie)) = (&options.record, session.nes.stop()) {
+        movie.save(path)?;
+    }
+    if result.is_ok() && (options.hash || options.hash_ram) {
+        print!("{}", hash_report(&session.nes, options.hash_ram));
+    }
+    if let (Ok(()), Some(desync)) = (&result, session.nes.desync()) {
+        return Err(desync.to_string());
+    }
+    return result;
+}
+
+// what --hash prints: SHA-1s of the last frame's RGBA pixels, in the
+// palette it was drawn with, and with `ram` of work RAM followed by any
+// cartridge RAM, for telling runs apart without comparing screenshots
+pub fn hash_report(nes: &Nes, ram: bool) -> String {
+    let mut lines = vec![
+        format!("frames  {}", nes.frame_count()),
+        format!("frame   {}", hash::to_hex(&hash::sha1(&nes.frame().pixels))),
+    ];
+    if ram {
+        let mut sha = Sha1::new();
+        sha.update(nes.cpu.bus.ram());
+        if let Some(prg_ram) = nes.cpu.bus.mapper().and_then(|mapper| mapper.prg_ram()) {
+            sha.update(prg_ram);
+        }
+        lines.push(format!("ram     {}", hash::to_hex(&sha.finish())));
+    }
+    return lines.join("\n") + "\n";
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+    use crate::cpu::Mem;
+    use crate::nes::test::test_nes;
+    use std::collections::VecDeque;
+
+    // replays a script of events, one batch per frame
+    struct ScriptedHost {
+        events: VecDeque<Vec<HostEvent>>,
+        presented: usize,
+    }
+
+    impl Host for ScriptedHost {
+        fn poll_events(&mut self) -> Vec<HostEvent> {
+            return self.events.pop_front().unwrap_or(vec![HostEvent::Quit]);
+        }
+
+        fn present(&mut self, frame: &Frame) -> Result<(), String> {
+            assert_eq!(frame.pixels.len(), frame.width * frame.height * 4);
+            self.presented += 1;
+            return Ok(());
+        }
+    }
+
+    #[test]
+    fn test_session_feeds_input_and_presents() {
+        let mut session = Session::new(test_nes());
+        session.pacer = None;
+        let mut host = ScriptedHost {
+            events: VecDeque::from([
+                vec![],
+                vec![HostEvent::KeyDown("X".to_string())],
+                vec![HostEvent::FocusLost],
+            ]),
+            presented: 0,
+        };
+        assert!(session.step(&mut host).unwrap());
+        assert_eq!(session.nes.cpu.mem_read(0x10), 0);
+        assert!(session.step(&mut host).unwrap());
+        assert!(session.nes.input(0).is_pressed(Button::A));
+        assert_ne!(session.nes.cpu.mem_read(0x10), 0);
+
+        session.run(&mut host).unwrap();
+        assert_eq!(host.presented, 3);
+        assert_eq!(session.nes.input(0).bits(), 0);
+    }
+
+    #[test]
+    fn test_run_options() {
+        #[cfg(feature = "pixels")]
+        assert_eq!(Renderer::parse("pixels"), Ok(Renderer::Pixels));
+        #[cfg(not(feature = "pixels"))]
+        assert!(Renderer::parse("pixels").is_err());
+        #[cfg(feature = "sdl")]
+        assert_eq!(Renderer::parse("sdl"), Ok(Renderer::Sdl));
+        #[cfg(not(feature = "sdl"))]
+        assert!(Renderer::parse("sdl").is_err());
+        assert_eq!(
+            Renderer::parse("terminal:braille"),
+            Ok(Renderer::Terminal(TerminalMode::Braille))
+        );
+        assert!(Renderer::parse("opengl").is_err());
+
+        let parse = |line: &str| {
+            let args: Vec<String> = line.split(' ').map(String::from).collect();
+            return RunOptions::parse(&args);
+        };
+        let options = parse(
+            "game.nes --region ntsc --scale 4 --headless --frames 60 --controllers fourscore",
+        )
+        .unwrap();
+        assert_eq!(options.path, PathBuf::from("game.nes"));
+        assert_eq!(options.region, Some(Region::Ntsc));
+        assert!(parse("game.nes --region dendy").is_err());
+        assert_eq!((options.scale, options.headless), (4, true));
+        assert_eq!(options.frames, Some(60));
+        assert_eq!(options.controllers, Some(ControllerSetup::FourScore));
+        let options = parse("game.nes --rotate 270 --romdb fixes.txt --save-dir saves").unwrap();
+        assert_eq!(options.romdb, Some(PathBuf::from("fixes.txt")));
+        assert_eq!(options.save_dir, Some(PathBuf::from("saves")));
+        assert_eq!(options.overrides().rotation, Some(Rotation::Left));
+        assert!(
+            parse("game.nes --headless --frames 600 --hash")
+                .unwrap()
+                .hash
+        );
+
+        assert_eq!(
+            parse("game.nes --renderer vulkan").err().unwrap(),
+            "unknown renderer vulkan"
+        );
+        assert_eq!(parse("--headless").err().unwrap(), "missing ROM file");
+        assert!(parse("game.nes --scale 0").is_err());
+        assert!(parse("game.nes --fast").is_err());
+        assert_eq!(parse("game.nes --run-ahead 1").unwrap().run_ahead, 1);
+        assert!(parse("game.nes --run-ahead 9").is_err());
+        assert_eq!(
+            parse("game.nes --unfocused pause").unwrap().unfocused,
+            Unfocused::Pause
+        );
+    }
+
+    #[test]
+    fn test_pausing_in_the_background() {
+        use std::sync::{Arc, Mutex};
+
+        let mut session = Session::new(test_nes());
+        session.pacer = None;
+        session.unfocused = Unfocused::Pause;
+        let mut host = ScriptedHost {
+            events: VecDeque::from([
+                vec![],
+                vec![HostEvent::KeyDown("X".to_string()), HostEvent::FocusLost],
+                vec![],
+                vec![HostEvent::FocusGained],
+            ]),
+            presented: 0,
+        };
+        session.run(&mut host).unwrap();
+        assert_eq!((session.frames, host.presented), (2, 2));
+        assert_eq!(session.nes.input(0).bits(), 0);
+
+        // throttled, the game goes on without its sound
+        struct CountingSink(Arc<Mutex<usize>>);
+        impl AudioSink for CountingSink {
+            fn push_samples(&mut self, samples: &[f32], _: u32) -> Result<(), String> {
+                *self.0.lock().unwrap() += samples.len();
+                return Ok(());
+            }
+        }
+        let pushed = Arc::new(Mutex::new(0));
+        let mut session = Session::new(test_nes());
+        session.pacer = None;
+        session.unfocused = Unfocused::Throttle;
+        session
+            .audio
+            .attach(Box::new(CountingSink(Arc::clone(&pushed))));
+        let mut host = ScriptedHost {
+            events: VecDeque::from([vec![HostEvent::FocusLost], vec![]]),
+            presented: 0,
+        };
+        session.run(&mut host).unwrap();
+        assert_eq!(session.frames, 2);
+        assert_eq!(*pushed.lock().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_a_jam_is_reported_once() {
+        let mut raw = test_rom(0, 1, 1);
+        raw[16] = 0x02; // KIL
+        raw[16 + 0x3FFC..16 + 0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        let mut session = Session::new(Nes::new(Rom::new(&raw).unwrap()).unwrap());
+        session.pacer = None;
+        session.frame_limit = Some(3);
+        session.title = "Jam".to_string();
+        let dir = std::env::temp_dir().join(format!("rustynes-jam-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        session.capture_dir = dir.clone();
+        session.run(&mut HeadlessHost).unwrap();
+        let report = std::fs::read_to_string(dir.join("Jam-000001.crash.txt"));
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(report
+            .unwrap()
+            .contains("reason    the CPU jammed on opcode $02 at $8000\n"));
+        let messages: Vec<&str> = session.osd.messages().collect();
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].starts_with("The CPU jammed at $8000, see "));
+    }
+
+    #[test]
+    fn test_hash_report_is_reproducible() {
+        let report = || {
+            let mut session = Session::new(test_nes());
+            session.pacer = None;
+            session.frame_limit = Some(3);
+            session.run(&mut HeadlessHost).unwrap();
+            return hash_report(&session.nes, true);
+        };
+        let first = report();
+        assert_eq!(first, report());
+        let lines: Vec<&str> = first.lines().collect();
+        assert_eq!(lines[0], "frames  3");
+        assert!(lines[1].starts_with("frame   ") && lines[1].len() == 48);
+        assert!(lines[2].starts_with("ram     "));
+        assert_eq!(hash_report(&test_nes(), false).lines().count(), 2);
+    }
+
+    #[test]
+    fn test_frame_limit() {
+        let mut session = Session::new(test_nes());
+        session.pacer = None;
+        session.frame_limit = Some(5);
+        session.run(&mut HeadlessHost).unwrap();
+        assert_eq!(session.frames, 5);
+        assert_eq!(session.nes.frame_count(), 5);
+    }
+
+    #[test]
+    fn test_screenshot_hotkey() {
+        let dir = std::env::temp_dir().join(format!("rustynes-shots-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut session = Session::new(test_nes());
+        session.pacer = None;
+        session.title = "Test Game".to_string();
+        session.capture_dir = dir.clone();
+        let mut host = ScriptedHost {
+            events: VecDeque::from([vec![], vec![HostEvent::KeyDown("F12".to_string())]]),
+            presented: 0,
+        };
+        session.run(&mut host).unwrap();
+        let png = std::fs::read(dir.join("Test Game-000001.png")).unwrap();
+        assert_eq!(&png[1..4], b"PNG");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_state_slot_hotkeys() {
+        let dir = std::env::temp_dir().join(format!("rustynes-states-{}", std::process::id()));
+        let mut session = Session::new(test_nes());
+        session.pacer = None;
+        session.states = StateSlots::new(dir.clone(), session.nes.rom());
+        let press = |session: &mut Session, keys: &[&str]| {
+            let events = keys.iter().map(|key| HostEvent::KeyDown(key.to_string()));
+            let mut host = ScriptedHost {
+                events: VecDeque::from([events.collect()]),
+                presented: 0,
+            };
+            session.step(&mut host).unwrap();
+            return session.osd.messages().last().unwrap_or("").to_string();
+        };
+        assert_eq!(press(&mut session, &["3"]), "State 3, empty");
+        assert_eq!(press(&mut session, &["F7"]), "State 3 is empty");
+        session.nes.cpu.bus.ram_mut()[0x0300] = 0x42;
+        assert_eq!(press(&mut session, &["F5"]), "State 3 saved");
+        assert!(session.states.path(3).exists());
+
+        session.nes.cpu.bus.ram_mut()[0x0300] = 0;
+        assert!(press(&mut session, &["F7"]).starts_with("State 3 loaded, from 20"));
+        assert_eq!(session.nes.cpu.bus.ram()[0x0300], 0x42);
+        assert_eq!(press(&mut session, &["F6"]), "State 4, empty");
+        assert!(press(&mut session, &["F6", "3"]).starts_with("State 3, saved 20"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_tas_hotkeys() {
+        let path = std::env::temp_dir().join(format!("rustynes-tas-{}.movie", std::process::id()));
+        let mut session = Session::new(test_nes());
+        session.pacer = None;
+        let movie = Movie::new(savestate::save(&session.nes.cpu));
+        session.tas = Some(TasEditor::new(&mut session.nes, movie).unwrap());
+        session.tas_movie = Some(path.clone());
+        session.hotkeys.extend(tas_hotkeys());
+        let press = |session: &mut Session, keys: &[&str]| {
+            let events = keys.iter().map(|key| HostEvent::KeyDown(key.to_string()));
+            let mut host = ScriptedHost {
+                events: VecDeque::from([events.collect()]),
+                presented: 0,
+            };
+            session.step(&mut host).unwrap();
+        };
+
+        // paused, so only . moves it on
+        press(&mut session, &[]);
+        press(&mut session, &[".", ".", "."]);
+        assert_eq!(session.osd.panel[0], "frame 3/3  rerecords 0  paused");
+        assert_eq!(session.osd.panel[1], "      0* ........");
+        assert_eq!(session.osd.panel[4], ">     3* end");
+        press(&mut session, &[","]);
+        assert_eq!(session.tas.as_ref().unwrap().frame(), 2);
+
+        // A recorded over frame 2
+        press(&mut session, &["X", "F10", "."]);
+        assert_eq!(session.osd.panel[0], "frame 3/3  rerecords 1  paused");
+        press(&mut session, &["Space"]);
+        assert_eq!(session.osd.panel, vec!["frame 4/4  rerecords 1  recording"]);
+        session.finish().unwrap();
+        let movie = Movie::load(&path).unwrap();
+        assert_eq!(movie.frames[2].buttons[0], Button::A.bit());
+        assert_eq!(movie.rerecords, 1);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_dropping_a_rom_swaps_the_game() {
+        let dir = std::env::temp_dir().join(format!("rustynes-drop-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // both spin on a JMP to themselves at $8000
+        let looping = |prg_pages: u8| {
+            let mut raw = test_rom(0, prg_pages, 1);
+            let vector = 16 + prg_pages as usize * 0x4000 - 4;
+            raw[16..19].copy_from_slice(&[0x4C, 0x00, 0x80]);
+            raw[vector..vector + 2].copy_from_slice(&[0x00, 0x80]);
+            return raw;
+        };
+        let mut raw = looping(1);
+        raw[6] |= 0b0000_0010;
+        let first = dir.join("first.nes");
+        std::fs::write(&first, &raw).unwrap();
+        let second = dir.join("second.nes");
+        std::fs::write(&second, looping(2)).unwrap();
+
+        let rom = Rom::from_file(&first, None).unwrap();
+        let mut session = Session::new(Nes::new(rom.clone()).unwrap());
+        session.pacer = None;
+        session.save_dir = Some(dir.join("saves"));
+        std::fs::create_dir_all(dir.join("saves")).unwrap();
+        session.battery = BatterySave::new(&rom, &first, session.save_dir.as_deref());
+        let crc = Rom::from_file(&second, None).unwrap().crc32();
+        let text = format!(
+            "[{:08x}]\nregion = ntsc\nrotation = 90\np1.a = Space\n",
+            crc
+        );
+        session.config = Config::parse(&text).unwrap();
+        session.nes.cpu.mem_write(0x6000, 0xAB);
+        let mut host = ScriptedHost {
+            events: VecDeque::from([
+                vec![HostEvent::FileDropped(dir.join("missing.nes"))],
+                vec![HostEvent::FileDropped(second.clone())],
+            ]),
+            presented: 0,
+        };
+        session.run(&mut host).unwrap();
+        // the first game's save was written on the way out
+        assert_eq!(std::fs::read(dir.join("saves/first.sav")).unwrap()[0], 0xAB);
+        assert_eq!(session.title, "second");
+        // with the settings in its section of the config
+        assert_eq!(session.nes.rom().region, Region::Ntsc);
+        assert_eq!(session.display.rotation, Rotation::Right);
+        assert_eq!(
+            session.mapper.bindings.inputs_for(PORT_1, Button::A),
+            vec!["Space"]
+        );
+        assert_eq!(session.nes.rom().prg_rom.len(), 0x8000);
+        assert!(session.battery.is_none());
+        assert!(session.osd.messages().any(|m| m.contains("missing.nes")));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_reload_can_keep_ram() {
+        let path = std::env::temp_dir().join(format!("rustynes-reload-{}.nes", std::process::id()));
+        let mut raw = test_rom(0, 1, 1);
+        raw[16..19].copy_from_slice(&[0x4C, 0x00, 0x80]);
+        raw[16 + 0x3FFC..16 + 0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        std::fs::write(&path, &raw).unwrap();
+        let mut session = Session::new(Nes::new(Rom::from_file(&path, None).unwrap()).unwrap());
+        session.watch = Some(FileWatch::new(&path, Instant::now()));
+        let mut host = HeadlessHost;
+
+        session.nes.cpu.mem_write(0x0300, 0x42);
+        session.reload(&path, &mut host).unwrap();
+        assert_eq!(session.nes.cpu.mem_read(0x0300), 0);
+
+        session.keep_ram = true;
+        session.nes.cpu.mem_write(0x0300, 0x42);
+        session.reload(&path, &mut host).unwrap();
+        assert_eq!(session.nes.cpu.mem_read(0x0300), 0x42);
+        assert_eq!(session.watch.as_ref().unwrap().path(), path);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_recording_hotkey() {
+        let dir = std::env::temp_dir().join(format!("rustynes-video-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut session = Session::new(test_nes());
+        session.pacer = None;
+        session.title = "Test Game".to_string();
+        session.capture_dir = dir.clone();
+        let toggle = vec![HostEvent::KeyDown("F9".to_string())];
+        let mut host = ScriptedHost {
+            events: VecDeque::from([toggle.clone(), vec![], vec![], toggle]),
+            presented: 0,
+        };
+        session.run(&mut host).unwrap();
+        assert!(session.recording.is_none());
+        let apng = std::fs::read(dir.join("Test Game-000000.apng")).unwrap();
+        assert_eq!(&apng[37..41], b"acTL");
+        assert_eq!(apng[41..45], 3u32.to_be_bytes());
+        let wav = std::fs::read(dir.join("Test Game-000000.wav")).unwrap();
+        assert!(wav.len() > 44);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_hotkey_errors_go_to_the_osd() {
+        let mut session = Session::new(test_nes());
+        session.pacer = None;
+        session.capture_dir = PathBuf::from("/nonexistent/rustynes");
+        let mut host = ScriptedHost {
+            events: VecDeque::from([vec![HostEvent::KeyDown("F12".to_string())]]),
+            presented: 0,
+        };
+        assert!(session.step(&mut host).unwrap());
+        assert!(session
+            .osd
+            .messages()
+            .next()
+            .unwrap()
+            .contains("/nonexistent"));
+    }
+
+    #[test]
+    fn test_fullscreen_hotkey() {
+        let mut session = Session::new(test_nes());
+        session.pacer = None;
+        let mut host = ScriptedHost {
+            events: VecDeque::from([vec![HostEvent::KeyDown("F11".to_string())]]),
+            presented: 0,
+        };
+        // the key never reaches the controllers, even if bound
+        session.mapper.bindings.bind("F11", PORT_1, Button::A);
+        session.run(&mut host).unwrap();
+        assert!(session.display.fullscreen);
+        assert!(!session.nes.input(0).a);
+    }
+
+    struct AudioHost(usize);
+
+    impl Host for AudioHost {
+        fn poll_events(&mut self) -> Vec<HostEvent> {
+            return Vec::new();
+        }
+
+        fn present(&mut self, _: &Frame) -> Result<(), String> {
+            return Ok(());
+        }
+
+        fn audio_queued(&self) -> Option<usize> {
+            return Some(self.0);
+        }
+    }
+
+    #[test]
+    fn test_audio_sync_bends_the_rate() {
+        let mut session = Session::new(test_nes());
+        session.sync = SyncMode::Audio;
+        session.frame_limit = Some(1);
+        // an empty queue never sleeps, and asks for more sound
+        session.run(&mut AudioHost(0)).unwrap();
+        assert!((session.nes.cpu.bus.apu.rate_ratio() - 1.005).abs() < 1e-9);
+        // and one right on the latency target leaves it alone
+        let target = session.nes.cpu.bus.apu.sample_rate() as usize / 20;
+        session.frame_limit = Some(2);
+        session.run(&mut AudioHost(target)).unwrap();
+        assert_eq!(session.nes.cpu.bus.apu.rate_ratio(), 1.0);
+    }
+
+    #[test]
+    fn test_pacer_keeps_a_steady_rate() {
+        let start = Instant::now();
+        let frame = Duration::from_millis(10);
+        let mut pacer = FramePacer::new(frame);
+        assert_eq!(pacer.delay(start), frame);
+        // 4ms into the second frame, 6ms of it are left to sleep
+        assert_eq!(
+            pacer.delay(start + Duration::from_millis(14)),
+            Duration::from_millis(6)
+        );
+        // far behind, it starts over from now
+        let late = start + Duration::from_secs(1);
+        assert_eq!(pacer.delay(late), Duration::ZERO);
+        assert_eq!(pacer.delay(late), frame);
+    }
+}
@@ -0,0 +1,294 @@
+use crate::video::{Frame, OVERSCAN};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 7;
+const ADVANCE: usize = GLYPH_WIDTH + 1;
+const LINE_HEIGHT: usize = GLYPH_HEIGHT + 2;
+const MARGIN: usize = 4;
+
+const MESSAGE_FRAMES: u32 = 120; // two seconds
+const MAX_MESSAGES: usize = 4;
+const FPS_INTERVAL: Duration = Duration::from_millis(500);
+
+const TEXT: [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
+const SHADOW: [u8; 4] = [0x00, 0x00, 0x00, 0xFF];
+
+// 5x7 glyphs for ' ' to '_', a row a byte with bit 4 the leftmost column
+const FONT: [[u8; GLYPH_HEIGHT]; 64] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+    [0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x04], // !
+    [0x0A, 0x0A, 0x00, 0x00, 0x00, 0x00, 0x00], // "
+    [0x0A, 0x0A, 0x1F, 0x0A, 0x1F, 0x0A, 0x0A], // #
+    [0x04, 0x0F, 0x14, 0x0E, 0x05, 0x1E, 0x04], // $
+    [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03], // %
+    [0x0C, 0x12, 0x14, 0x08, 0x15, 0x12, 0x0D], // &
+    [0x04, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00], // '
+    [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02], // (
+    [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08], // )
+    [0x00, 0x04, 0x15, 0x0E, 0x15, 0x04, 0x00], // *
+    [0x00, 0x04, 0x04, 0x1F, 0x04, 0x04, 0x00], // +
+    [0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08], // ,
+    [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00], // -
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C], // .
+    [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00], // /
+    [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E], // 0
+    [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E], // 1
+    [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F], // 2
+    [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E], // 3
+    [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02], // 4
+    [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E], // 5
+    [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E], // 6
+    [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08], // 7
+    [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E], // 8
+    [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C], // 9
+    [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00], // :
+    [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x04, 0x08], // ;
+    [0x02, 0x04, 0x08, 0x10, 0x08, 0x04, 0x02], // <
+    [0x00, 0x00, 0x1F, 0x00, 0x1F, 0x00, 0x00], // =
+    [0x08, 0x04, 0x02, 0x01, 0x02, 0x04, 0x08], // >
+    [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04], // ?
+    [0x0E, 0x11, 0x01, 0x0D, 0x15, 0x15, 0x0E], // @
+    [0x0E, 0x11, 0x11, 0x11, 0x1F, 0x11, 0x11], // A
+    [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E], // B
+    [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E], // C
+    [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C], // D
+    [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F], // E
+    [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10], // F
+    [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F], // G
+    [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11], // H
+    [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E], // I
+    [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C], // J
+    [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11], // K
+    [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F], // L
+    [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11], // M
+    [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11], // N
+    [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E], // O
+    [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10], // P
+    [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D], // Q
+    [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11], // R
+    [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E], // S
+    [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04], // T
+    [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E], // U
+    [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04], // V
+    [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A], // W
+    [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11], // X
+    [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04], // Y
+    [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F], // Z
+    [0x0E, 0x08, 0x08, 0x08, 0x08, 0x08, 0x0E], // [
+    [0x00, 0x10, 0x08, 0x04, 0x02, 0x01, 0x00], // \
+    [0x0E, 0x02, 0x02, 0x02, 0x02, 0x02, 0x0E], // ]
+    [0x04, 0x0A, 0x11, 0x00, 0x00, 0x00, 0x00], // ^
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F], // _
+];
+
+/* On-screen display
+
+   Short notices drawn over the picture just before the host gets it, so
+   every frontend shows them the same way without a font or a UI toolkit of
+   its own. Messages ("State 3 saved", "Rewinding", "2x speed") stack up in
+   the bottom left corner, newest at the bottom, and each goes away after
+   two seconds; the frame rate the host is actually reaching can sit in the
+   top right corner for as long as it's turned on, and a status line, such
+   as the performance stats, in the top left, with any lines of the panel
+   under it, such as the TAS editor's piano roll.
+
+   Text is a built-in 5x7 font with a shadow one pixel down and right, so it
+   reads on any background. It covers ASCII from space to '_', lower case
+   is drawn as upper case and anything else as '?'. The text stays inside
+   the overscan, and is scaled up with the picture when a video filter made
+   the frame larger.
+*/
+
+#[derive(Debug, Clone, Default)]
+pub struct Osd {
+    messages: VecDeque<(String, u32)>, // and the frames each has left
+    pub show_fps: bool,
+    fps: Option<f64>,
+    pub status: Option<String>,
+    pub panel: Vec<String>,
+}
+
+impl Osd {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    pub fn notify(&mut self, text: &str) {
+        if self.messages.len() == MAX_MESSAGES {
+            self.messages.pop_front();
+        }
+        self.messages.push_back((text.to_string(), MESSAGE_FRAMES));
+    }
+
+    pub fn messages(&self) -> impl Iterator<Item = &str> {
+        return self.messages.iter().map(|(text, _)| text.as_str());
+    }
+
+    pub fn set_fps(&mut self, fps: f64) {
+        self.fps = Some(fps);
+    }
+
+    // ages the messages by one frame
+    pub fn end_frame(&mut self) {
+        for (_, frames) in self.messages.iter_mut() {
+            *frames -= 1;
+        }
+        self.messages.retain(|(_, frames)| *frames > 0);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.messages.is_empty()
+            && !(self.show_fps && self.fps.is_some())
+            && self.status.is_none()
+            && self.panel.is_empty();
+    }
+
+    // over `frame`, whose pixels are `scale` times the NES's
+    pub fn draw(&self, frame: &mut Frame, scale: usize) {
+        let bottom = (frame.height / scale).saturating_sub(OVERSCAN);
+        let first = bottom.saturating_sub(self.messages.len() * LINE_HEIGHT);
+        for (line, (text, _)) in self.messages.iter().enumerate() {
+            draw_text(frame, scale, MARGIN, first + line * LINE_HEIGHT, text);
+        }
+        if let (true, Some(fps)) = (self.show_fps, self.fps) {
+            let text = format!("{:.1} FPS", fps);
+            let x = (frame.width / scale).saturating_sub(MARGIN + text.len() * ADVANCE);
+            draw_text(frame, scale, x, OVERSCAN + 2, &text);
+        }
+        if let Some(status) = &self.status {
+            draw_text(frame, scale, MARGIN, OVERSCAN + 2, status);
+        }
+        for (line, text) in self.panel.iter().enumerate() {
+            let y = OVERSCAN + 2 + (line + 1) * LINE_HEIGHT;
+            draw_text(frame, scale, MARGIN, y, text);
+        }
+    }
+}
+
+pub fn draw_text(frame: &mut Frame, scale: usize, x: usize, y: usize, text: &str) {
+    for (i, c) in text.chars().enumerate() {
+        let glyph = glyph(c);
+        for colour in [SHADOW, TEXT] {
+            let offset = if colour == SHADOW { 1 } else { 0 };
+            for (row, bits) in glyph.iter().enumerate() {
+                for column in 0..GLYPH_WIDTH {
+                    if bits & (0x10 >> column) != 0 {
+                        let left = x + i * ADVANCE + column + offset;
+                        fill(frame, scale, left, y + row + offset, colour);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn glyph(c: char) -> &'static [u8; GLYPH_HEIGHT] {
+    let c = c.to_ascii_uppercase();
+    if (' '..='_').contains(&c) {
+        return &FONT[c as usize - ' ' as usize];
+    }
+    return &FONT['?' as usize - ' ' as usize];
+}
+
+// one NES-sized pixel, clipped to the frame
+fn fill(frame: &mut Frame, scale: usize, x: usize, y: usize, colour: [u8; 4]) {
+    for y in y * scale..(y + 1) * scale {
+        if y >= frame.height {
+            return;
+        }
+        let row = frame.row_mut(y);
+        for x in x * scale..((x + 1) * scale).min(row.len() / 4) {
+            row[x * 4..x * 4 + 4].copy_from_slice(&colour);
+        }
+    }
+}
+
+// frames a second over the last half second or so
+#[derive(Debug, Clone, Default)]
+pub struct FpsCounter {
+    start: Option<Instant>,
+    frames: u32,
+}
+
+impl FpsCounter {
+    // counts a presented frame, a new rate whenever one is due
+    pub fn tick(&mut self, now: Instant) -> Option<f64> {
+        let start = *self.start.get_or_insert(now);
+        self.frames += 1;
+        let elapsed = now - start;
+        if elapsed < FPS_INTERVAL {
+            return None;
+        }
+        let fps = (self.frames - 1) as f64 / elapsed.as_secs_f64();
+        self.start = Some(now);
+        self.frames = 1;
+        return Some(fps);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn lit(frame: &Frame) -> usize {
+        return frame
+            .pixels
+            .chunks(4)
+            .filter(|pixel| *pixel == TEXT)
+            .count();
+    }
+
+    #[test]
+    fn test_messages_expire() {
+        let mut osd = Osd::new();
+        for slot in 1..=5 {
+            osd.notify(&format!("State {} saved", slot));
+        }
+        assert_eq!(osd.messages().count(), MAX_MESSAGES);
+        assert_eq!(osd.messages().next(), Some("State 2 saved"));
+
+        let mut frame = Frame::new(256, 240);
+        osd.draw(&mut frame, 1);
+        assert!(lit(&frame) > 0);
+        // nothing lands in the overscan at the bottom
+        assert!((240 - OVERSCAN..240).all(|y| !frame.row(y).chunks(4).any(|p| p == TEXT)));
+
+        for _ in 0..MESSAGE_FRAMES {
+            osd.end_frame();
+        }
+        assert!(osd.is_empty());
+    }
+
+    #[test]
+    fn test_text_and_scale() {
+        let mut frame = Frame::new(12, 8);
+        draw_text(&mut frame, 1, 0, 0, "I");
+        // the I's top bar, the shadow below and right of its stem
+        assert_eq!(frame.pixel(1, 0), TEXT);
+        assert_eq!(frame.pixel(3, 0), TEXT);
+        assert_eq!(frame.pixel(3, 2), SHADOW);
+        let one = lit(&frame);
+
+        let mut big = Frame::new(36, 24);
+        draw_text(&mut big, 3, 0, 0, "i");
+        assert_eq!(lit(&big), one * 9);
+        assert_eq!(glyph('~'), glyph('?'));
+    }
+
+    #[test]
+    fn test_fps_counter() {
+        let start = Instant::now();
+        let mut counter = FpsCounter::default();
+        assert_eq!(counter.tick(start), None);
+        for frame in 1..30 {
+            assert_eq!(
+                counter.tick(start + Duration::from_millis(frame * 10)),
+                None
+            );
+        }
+        let fps = counter.tick(start + Duration::from_millis(500)).unwrap();
+        assert!((fps - 60.0).abs() < 0.01);
+    }
+}
@@ -0,0 +1,99 @@
+use std::time::Duration;
+
+// how far the audio rate may be bent to catch up, inaudible at half a percent
+const MAX_RATE_ADJUST: f64 = 0.005;
+
+// the sound queued ahead of the speakers the audio pacer aims for
+pub const AUDIO_LATENCY: Duration = Duration::from_millis(50);
+
+/* Frame pacing
+
+   What decides when the next frame starts. The console runs at 60.0988 Hz
+   and neither monitors nor sound cards agree with it exactly, so each
+   choice gives something up:
+
+    timer  sleep until the next frame is due by the system clock (the
+           default). Right on average on any monitor, 48, 60 or 144 Hz, but
+           not locked to the display's refresh, so motion can judder or
+           tear, and the sound card's clock slowly drifts against it
+    vsync  no sleeping, the host's present waits for the display's
+           refresh. Perfectly smooth on a 60 Hz display, but the game runs
+           at the display's rate, 2.4x fast on a 144 Hz one, and audio
+           drifts. Falls back to the timer with hosts that can't wait
+    audio  pace by the sound card: sleep while more than `AUDIO_LATENCY`
+           of sound is queued ahead of it, and bend the resampling rate by
+           up to half a percent to keep the queue near that level (dynamic
+           rate control). No crackles or drift, at the cost of frames
+           landing a little unevenly. Falls back to the timer with hosts
+           that don't report their audio queue
+
+   Headless runs have no pacer at all.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SyncMode {
+    #[default]
+    Timer,
+    Vsync,
+    Audio,
+}
+
+impl SyncMode {
+    pub fn parse(name: &str) -> Result<SyncMode, String> {
+        match name {
+            "timer" => return Ok(SyncMode::Timer),
+            "vsync" => return Ok(SyncMode::Vsync),
+            "audio" => return Ok(SyncMode::Audio),
+            _ => return Err(format!("unknown sync mode {}", name)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AudioPacer {
+    sample_rate: u32,
+    target: usize, // samples
+}
+
+impl AudioPacer {
+    pub fn new(sample_rate: u32, latency: Duration) -> Self {
+        return Self {
+            sample_rate,
+            target: (sample_rate as f64 * latency.as_secs_f64()) as usize,
+        };
+    }
+
+    // with `queued` samples waiting to be played, the resampling ratio for
+    // the next frame and how long to sleep before running it
+    pub fn update(&self, queued: usize) -> (f64, Duration) {
+        let target = self.target.max(1) as f64;
+        let error = ((target - queued as f64) / target).clamp(-1.0, 1.0);
+        let ratio = 1.0 + MAX_RATE_ADJUST * error;
+        let excess = queued.saturating_sub(self.target);
+        let delay = Duration::from_secs_f64(excess as f64 / self.sample_rate as f64);
+        return (ratio, delay);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_audio_pacer() {
+        let pacer = AudioPacer::new(48_000, AUDIO_LATENCY);
+        // on target, the real rate and no waiting
+        assert_eq!(pacer.update(2400), (1.0, Duration::ZERO));
+        // starved, a little more sound per frame and straight on
+        let (ratio, delay) = pacer.update(0);
+        assert!((ratio - 1.005).abs() < 1e-9);
+        assert_eq!(delay, Duration::ZERO);
+        // well ahead, a little less, after waiting out the surplus
+        let (ratio, delay) = pacer.update(7200);
+        assert!((ratio - 0.995).abs() < 1e-9);
+        assert_eq!(delay, Duration::from_millis(100));
+
+        assert_eq!(SyncMode::parse("audio"), Ok(SyncMode::Audio));
+        assert!(SyncMode::parse("gsync").is_err());
+    }
+}
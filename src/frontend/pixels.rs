@@ -0,0 +1,312 @@
+use crate::frontend::display::DisplayOptions;
+use crate::frontend::{Host, HostEvent};
+use crate::video::Frame;
+use pixels::{Pixels, PixelsBuilder, SurfaceTexture};
+use std::sync::Arc;
+use std::time::Duration;
+use winit::application::ApplicationHandler;
+use winit::dpi::LogicalSize;
+use winit::event::{ElementState, WindowEvent};
+use winit::event_loop::{ActiveEventLoop, EventLoop};
+use winit::keyboard::{KeyCode, PhysicalKey};
+use winit::platform::pump_events::{EventLoopExtPumpEvents, PumpStatus};
+use winit::window::{Fullscreen, Window, WindowId};
+
+// the picture's size before anything is shown, for the window's
+const PICTURE: (usize, usize) = (256, 240);
+
+/* pixels host
+
+   The window frontend with the `pixels` feature, for platforms where
+   SDL2 is awkward: a winit window whose surface pixels draws through
+   wgpu, on Vulkan, Metal, DX12 or GL, whichever the platform has. The
+   session's loop stays in charge, pumping winit's events once a frame
+   rather than handing it the thread, and they come out as the same
+   `HostEvent`s the SDL2 window gives.
+
+   The buffer pixels uploads is the window's size, and the picture is
+   `blit` into it, so fitting it to the window is `display`'s as with the
+   other hosts rather than pixels' own scaling, which knows neither 8:7
+   pixels nor the rotated picture. That leaves the GPU a plain copy for
+   now, and `Pixels::render_with` a place for shaders later.
+
+   Keys go by where they are on a US keyboard, winit's physical key
+   codes, named as SDL names them so a bindings file works with either
+   window. Vsync is settled when the window opens, like SDL2's, and there
+   is no sound device or gamepad here: `--sync audio` falls back to the
+   clock, and pads need the SDL2 window.
+*/
+
+pub struct PixelsHost {
+    event_loop: EventLoop<()>,
+    app: App,
+    display: DisplayOptions,
+}
+
+// what winit hands the event loop's callbacks: the window once it's made,
+// and the events since the session last asked
+struct App {
+    title: String,
+    size: LogicalSize<u32>,
+    vsync: bool,
+    window: Option<Surface>,
+    failed: Option<String>, // why the window couldn't be made
+    events: Vec<HostEvent>,
+}
+
+struct Surface {
+    window: Arc<Window>,
+    pixels: Pixels<'static>,
+    canvas: Frame, // the window's size, what the picture is scaled into
+}
+
+impl App {
+    fn open(&mut self, event_loop: &ActiveEventLoop) -> Result<Surface, String> {
+        let attributes = Window::default_attributes()
+            .with_title(self.title.as_str())
+            .with_inner_size(self.size);
+        let window = Arc::new(
+            event_loop
+                .create_window(attributes)
+                .map_err(|e| e.to_string())?,
+        );
+        let size = window.inner_size();
+        let (width, height) = (size.width.max(1), size.height.max(1));
+        let surface = SurfaceTexture::new(width, height, window.clone());
+        let pixels = PixelsBuilder::new(width, height, surface)
+            .enable_vsync(self.vsync)
+            .build()
+            .map_err(|e| e.to_string())?;
+        return Ok(Surface {
+            window,
+            pixels,
+            canvas: Frame::new(width as usize, height as usize),
+        });
+    }
+}
+
+impl ApplicationHandler for App {
+    // called once the event loop is running, which is when a window can be made
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        if self.window.is_some() {
+            return;
+        }
+        match self.open(event_loop) {
+            Ok(surface) => self.window = Some(surface),
+            Err(e) => self.failed = Some(e),
+        }
+    }
+
+    fn window_event(&mut self, _: &ActiveEventLoop, _: WindowId, event: WindowEvent) {
+        match event {
+            WindowEvent::CloseRequested => self.events.push(HostEvent::Quit),
+            WindowEvent::KeyboardInput { event, .. } => {
+                let PhysicalKey::Code(code) = event.physical_key else {
+                    return;
+                };
+                let Some(name) = key_name(code) else {
+                    return;
+                };
+                match (event.state, event.repeat) {
+                    (ElementState::Pressed, true) => self.events.push(HostEvent::KeyRepeat(name)),
+                    (ElementState::Pressed, false) => self.events.push(HostEvent::KeyDown(name)),
+                    (ElementState::Released, _) => self.events.push(HostEvent::KeyUp(name)),
+                }
+            }
+            WindowEvent::DroppedFile(path) => self.events.push(HostEvent::FileDropped(path)),
+            WindowEvent::Focused(true) => self.events.push(HostEvent::FocusGained),
+            WindowEvent::Focused(false) => self.events.push(HostEvent::FocusLost),
+            _ => {}
+        }
+    }
+}
+
+impl PixelsHost {
+    // a window `scale` times the picture's height
+    pub fn open(title: &str, scale: u32, vsync: bool) -> Result<Self, String> {
+        let event_loop = EventLoop::new().map_err(|e| e.to_string())?;
+        let display = DisplayOptions::default();
+        let (width, height) = display.window_size(PICTURE.0, PICTURE.1, scale.max(1) as usize);
+        let mut host = Self {
+            event_loop,
+            app: App {
+                title: title.to_string(),
+                size: LogicalSize::new(width as u32, height as u32),
+                vsync,
+                window: None,
+                failed: None,
+                events: Vec::new(),
+            },
+            display,
+        };
+        // winit makes the window from inside its loop, on the first pump
+        while host.app.window.is_none() {
+            if let Some(e) = host.app.failed.take() {
+                return Err(e);
+            }
+            let status = host
+                .event_loop
+                .pump_app_events(Some(Duration::ZERO), &mut host.app);
+            if let PumpStatus::Exit(_) = status {
+                return Err("the window closed as it opened".to_string());
+            }
+        }
+        return Ok(host);
+    }
+
+    fn surface(&mut self) -> Result<&mut Surface, String> {
+        return self
+            .app
+            .window
+            .as_mut()
+            .ok_or_else(|| "the window is gone".to_string());
+    }
+}
+
+// SDL's name for the key in this place on a US keyboard, as the bindings
+// know it
+fn key_name(code: KeyCode) -> Option<String> {
+    let name = match code {
+        KeyCode::KeyA => "A",
+        KeyCode::KeyB => "B",
+        KeyCode::KeyC => "C",
+        KeyCode::KeyD => "D",
+        KeyCode::KeyE => "E",
+        KeyCode::KeyF => "F",
+        KeyCode::KeyG => "G",
+        KeyCode::KeyH => "H",
+        KeyCode::KeyI => "I",
+        KeyCode::KeyJ => "J",
+        KeyCode::KeyK => "K",
+        KeyCode::KeyL => "L",
+        KeyCode::KeyM => "M",
+        KeyCode::KeyN => "N",
+        KeyCode::KeyO => "O",
+        KeyCode::KeyP => "P",
+        KeyCode::KeyQ => "Q",
+        KeyCode::KeyR => "R",
+        KeyCode::KeyS => "S",
+        KeyCode::KeyT => "T",
+        KeyCode::KeyU => "U",
+        KeyCode::KeyV => "V",
+        KeyCode::KeyW => "W",
+        KeyCode::KeyX => "X",
+        KeyCode::KeyY => "Y",
+        KeyCode::KeyZ => "Z",
+        KeyCode::Digit0 => "0",
+        KeyCode::Digit1 => "1",
+        KeyCode::Digit2 => "2",
+        KeyCode::Digit3 => "3",
+        KeyCode::Digit4 => "4",
+        KeyCode::Digit5 => "5",
+        KeyCode::Digit6 => "6",
+        KeyCode::Digit7 => "7",
+        KeyCode::Digit8 => "8",
+        KeyCode::Digit9 => "9",
+        KeyCode::F1 => "F1",
+        KeyCode::F2 => "F2",
+        KeyCode::F3 => "F3",
+        KeyCode::F4 => "F4",
+        KeyCode::F5 => "F5",
+        KeyCode::F6 => "F6",
+        KeyCode::F7 => "F7",
+        KeyCode::F8 => "F8",
+        KeyCode::F9 => "F9",
+        KeyCode::F10 => "F10",
+        KeyCode::F11 => "F11",
+        KeyCode::F12 => "F12",
+        KeyCode::ArrowUp => "Up",
+        KeyCode::ArrowDown => "Down",
+        KeyCode::ArrowLeft => "Left",
+        KeyCode::ArrowRight => "Right",
+        KeyCode::Enter => "Return",
+        KeyCode::Escape => "Escape",
+        KeyCode::Space => "Space",
+        KeyCode::Tab => "Tab",
+        KeyCode::Backspace => "Backspace",
+        KeyCode::Insert => "Insert",
+        KeyCode::Delete => "Delete",
+        KeyCode::Home => "Home",
+        KeyCode::End => "End",
+        KeyCode::PageUp => "PageUp",
+        KeyCode::PageDown => "PageDown",
+        KeyCode::ShiftLeft => "LShift",
+        KeyCode::ShiftRight => "RShift",
+        KeyCode::ControlLeft | KeyCode::ControlRight => "Ctrl",
+        KeyCode::AltLeft | KeyCode::AltRight => "Alt",
+        KeyCode::Comma => ",",
+        KeyCode::Period => ".",
+        KeyCode::Slash => "/",
+        KeyCode::Semicolon => ";",
+        KeyCode::Quote => "'",
+        KeyCode::BracketLeft => "[",
+        KeyCode::BracketRight => "]",
+        KeyCode::Minus => "-",
+        KeyCode::Equal => "=",
+        KeyCode::Backslash => "\\",
+        KeyCode::Backquote => "`",
+        _ => return None,
+    };
+    return Some(name.to_string());
+}
+
+impl Host for PixelsHost {
+    fn poll_events(&mut self) -> Vec<HostEvent> {
+        let status = self
+            .event_loop
+            .pump_app_events(Some(Duration::ZERO), &mut self.app);
+        let mut events = std::mem::take(&mut self.app.events);
+        if let PumpStatus::Exit(_) = status {
+            events.push(HostEvent::Quit);
+        }
+        return events;
+    }
+
+    fn present(&mut self, frame: &Frame) -> Result<(), String> {
+        let display = self.display;
+        let surface = self.surface()?;
+        let size = surface.window.inner_size();
+        // minimised, there's nothing to draw into
+        if size.width == 0 || size.height == 0 {
+            return Ok(());
+        }
+        let (width, height) = (size.width as usize, size.height as usize);
+        if (surface.canvas.width, surface.canvas.height) != (width, height) {
+            surface
+                .pixels
+                .resize_surface(size.width, size.height)
+                .map_err(|e| e.to_string())?;
+            surface
+                .pixels
+                .resize_buffer(size.width, size.height)
+                .map_err(|e| e.to_string())?;
+            surface.canvas = Frame::new(width, height);
+        }
+        display.blit(frame, &mut surface.canvas);
+        surface
+            .pixels
+            .frame_mut()
+            .copy_from_slice(&surface.canvas.pixels);
+        return surface.pixels.render().map_err(|e| e.to_string());
+    }
+
+    fn set_display(&mut self, display: &DisplayOptions) {
+        self.display = *display;
+        if let Ok(surface) = self.surface() {
+            if surface.window.fullscreen().is_some() != display.fullscreen {
+                let fullscreen = display.fullscreen.then_some(Fullscreen::Borderless(None));
+                surface.window.set_fullscreen(fullscreen);
+            }
+        }
+    }
+
+    fn has_vsync(&self) -> bool {
+        return self.app.vsync;
+    }
+
+    fn set_title(&mut self, title: &str) {
+        if let Ok(surface) = self.surface() {
+            surface.window.set_title(title);
+        }
+    }
+}
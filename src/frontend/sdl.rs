@@ -0,0 +1,286 @@
+use crate::audio::ring::{RingAudioSink, RingLevel, SampleConsumer};
+use crate::audio::AudioSink;
+use crate::frontend::display::DisplayOptions;
+use crate::frontend::{Host, HostEvent};
+use crate::input::gamepad::{Axis, GamepadButton, GamepadEvent};
+use crate::video::Frame;
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::controller::{self, GameController};
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{Canvas, Texture, TextureCreator};
+use sdl2::video::{FullscreenType, Window, WindowContext};
+use sdl2::{EventPump, GameControllerSubsystem, Sdl};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+// the picture's size before anything is shown, for the window's
+const PICTURE: (usize, usize) = (256, 240);
+// samples the device asks for at a time, and how many may wait in the ring
+const DEVICE_BUFFER: u16 = 1024;
+const RING_SECONDS: f64 = 0.25;
+
+/* SDL2 host
+
+   The window frontend, with the `sdl` feature: an SDL2 window with a
+   streaming texture the size of the picture, drawn nearest-neighbour into
+   the viewport `display` works out for the window's size, and SDL's event
+   pump turned into `HostEvent`s. Keys go by SDL's names for them, with the
+   modifiers shortened to the names the bindings and the Family BASIC
+   keyboard use ("LShift", "Ctrl").
+
+   Vsync is settled when the window opens, as SDL2's renderer can't change
+   it afterwards; `--sync vsync` asks for it.
+
+   The sound device plays mono f32 at the APU's sample rate, which SDL
+   converts for the hardware if it has to. Its callback drains the ring
+   behind the `RingAudioSink` the session is given, and how full that ring
+   is is what `--sync audio` paces by.
+
+   Controllers come through SDL's game controller API, which knows the
+   layout of most pads and names their buttons the Xbox way. Each is opened
+   as SDL reports it plugged in, the ones there at the start included, and
+   its events become `GamepadEvent`s under its joystick id for `gamepad`
+   to assign to a player.
+*/
+
+pub struct SdlHost {
+    sdl: Sdl,
+    canvas: Canvas<Window>,
+    textures: TextureCreator<WindowContext>,
+    texture: Option<(Texture, (usize, usize))>, // and the size it was made for
+    events: EventPump,
+    vsync: bool,
+    display: DisplayOptions,
+    audio: Option<(AudioDevice<RingCallback>, RingLevel)>,
+    controllers: GameControllerSubsystem,
+    pads: HashMap<u32, GameController>, // by joystick id
+}
+
+// what the sound device plays: the ring, or the last sample again when
+// the emulator is behind
+struct RingCallback(SampleConsumer);
+
+impl AudioCallback for RingCallback {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        self.0.fill(out);
+    }
+}
+
+impl SdlHost {
+    // a window `scale` times the picture's height
+    pub fn open(title: &str, scale: u32, vsync: bool) -> Result<Self, String> {
+        let sdl = sdl2::init()?;
+        let video = sdl.video()?;
+        let display = DisplayOptions::default();
+        let (width, height) = display.window_size(PICTURE.0, PICTURE.1, scale.max(1) as usize);
+        let window = video
+            .window(title, width as u32, height as u32)
+            .position_centered()
+            .resizable()
+            .allow_highdpi()
+            .build()
+            .map_err(|e| e.to_string())?;
+        let mut canvas = window.into_canvas().accelerated();
+        if vsync {
+            canvas = canvas.present_vsync();
+        }
+        let canvas = canvas.build().map_err(|e| e.to_string())?;
+        let textures = canvas.texture_creator();
+        return Ok(Self {
+            controllers: sdl.game_controller()?,
+            pads: HashMap::new(),
+            events: sdl.event_pump()?,
+            sdl,
+            canvas,
+            textures,
+            texture: None,
+            vsync,
+            display,
+            audio: None,
+        });
+    }
+
+    // one the size of `frame`, made again when that changes
+    fn texture_for(&mut self, size: (usize, usize)) -> Result<&mut Texture, String> {
+        if self.texture.as_ref().is_none_or(|(_, made)| *made != size) {
+            let texture = self
+                .textures
+                .create_texture_streaming(PixelFormatEnum::RGBA32, size.0 as u32, size.1 as u32)
+                .map_err(|e| e.to_string())?;
+            if let Some((old, _)) = self.texture.replace((texture, size)) {
+                // SAFETY: the renderer that made it is still alive, and
+                // nothing else holds the texture
+                unsafe { old.destroy() };
+            }
+        }
+        return Ok(&mut self.texture.as_mut().unwrap().0);
+    }
+}
+
+// the same button on our pad, where it has one
+fn gamepad_button(button: controller::Button) -> Option<GamepadButton> {
+    match button {
+        controller::Button::A => return Some(GamepadButton::South),
+        controller::Button::B => return Some(GamepadButton::East),
+        controller::Button::X => return Some(GamepadButton::West),
+        controller::Button::Y => return Some(GamepadButton::North),
+        controller::Button::Back => return Some(GamepadButton::Select),
+        controller::Button::Start => return Some(GamepadButton::Start),
+        controller::Button::LeftShoulder => return Some(GamepadButton::LeftShoulder),
+        controller::Button::RightShoulder => return Some(GamepadButton::RightShoulder),
+        controller::Button::DPadUp => return Some(GamepadButton::DPadUp),
+        controller::Button::DPadDown => return Some(GamepadButton::DPadDown),
+        controller::Button::DPadLeft => return Some(GamepadButton::DPadLeft),
+        controller::Button::DPadRight => return Some(GamepadButton::DPadRight),
+        _ => return None,
+    }
+}
+
+// SDL's name for a key, as the bindings know it
+fn key_name(key: Keycode) -> String {
+    let name = key.name();
+    match name.as_str() {
+        "Left Shift" => return "LShift".to_string(),
+        "Right Shift" => return "RShift".to_string(),
+        "Left Ctrl" | "Right Ctrl" => return "Ctrl".to_string(),
+        "Left Alt" | "Right Alt" => return "Alt".to_string(),
+        _ => return name,
+    }
+}
+
+impl Host for SdlHost {
+    fn poll_events(&mut self) -> Vec<HostEvent> {
+        let mut events = Vec::new();
+        for event in self.events.poll_iter() {
+            match event {
+                Event::Quit { .. } => events.push(HostEvent::Quit),
+                Event::KeyDown {
+                    keycode: Some(key),
+                    repeat,
+                    ..
+                } => match repeat {
+                    true => events.push(HostEvent::KeyRepeat(key_name(key))),
+                    false => events.push(HostEvent::KeyDown(key_name(key))),
+                },
+                Event::KeyUp {
+                    keycode: Some(key), ..
+                } => events.push(HostEvent::KeyUp(key_name(key))),
+                Event::DropFile { filename, .. } => {
+                    events.push(HostEvent::FileDropped(PathBuf::from(filename)))
+                }
+                Event::ControllerDeviceAdded { which, .. } => {
+                    // one SDL can't open is left out, like one past the last player
+                    let Ok(pad) = self.controllers.open(which) else {
+                        continue;
+                    };
+                    let id = pad.instance_id();
+                    let event = GamepadEvent::Connected(id as usize, pad.name());
+                    events.push(HostEvent::Gamepad(event));
+                    self.pads.insert(id, pad);
+                }
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    self.pads.remove(&which);
+                    let event = GamepadEvent::Disconnected(which as usize);
+                    events.push(HostEvent::Gamepad(event));
+                }
+                Event::ControllerButtonDown { which, button, .. }
+                | Event::ControllerButtonUp { which, button, .. } => {
+                    let pressed = matches!(event, Event::ControllerButtonDown { .. });
+                    if let Some(button) = gamepad_button(button) {
+                        let event = GamepadEvent::Button(which as usize, button, pressed);
+                        events.push(HostEvent::Gamepad(event));
+                    }
+                }
+                Event::ControllerAxisMotion {
+                    which, axis, value, ..
+                } => {
+                    let axis = match axis {
+                        controller::Axis::LeftX => Axis::LeftStickX,
+                        controller::Axis::LeftY => Axis::LeftStickY,
+                        _ => continue,
+                    };
+                    let value = (value as f32 / i16::MAX as f32).max(-1.0);
+                    let event = GamepadEvent::Axis(which as usize, axis, value);
+                    events.push(HostEvent::Gamepad(event));
+                }
+                Event::Window { win_event, .. } => match win_event {
+                    WindowEvent::FocusGained => events.push(HostEvent::FocusGained),
+                    WindowEvent::FocusLost => events.push(HostEvent::FocusLost),
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+        return events;
+    }
+
+    fn present(&mut self, frame: &Frame) -> Result<(), String> {
+        let size = (frame.width, frame.height);
+        let texture = self.texture_for(size)?;
+        texture
+            .update(None, &frame.pixels, frame.width * 4)
+            .map_err(|e| e.to_string())?;
+        let (width, height) = self.canvas.output_size()?;
+        let view = self
+            .display
+            .viewport(size, (width as usize, height as usize));
+        let target = Rect::new(
+            view.x as i32,
+            view.y as i32,
+            view.width as u32,
+            view.height as u32,
+        );
+        self.canvas.set_draw_color(Color::BLACK);
+        self.canvas.clear();
+        let texture = &self.texture.as_ref().unwrap().0;
+        self.canvas.copy(texture, None, target)?;
+        self.canvas.present();
+        return Ok(());
+    }
+
+    fn set_display(&mut self, display: &DisplayOptions) {
+        let fullscreen = match display.fullscreen {
+            true => FullscreenType::Desktop,
+            false => FullscreenType::Off,
+        };
+        if fullscreen != self.canvas.window().fullscreen_state() {
+            // a window manager that won't is no reason to stop the game
+            let _ = self.canvas.window_mut().set_fullscreen(fullscreen);
+        }
+        self.display = *display;
+    }
+
+    fn open_audio(&mut self, sample_rate: u32) -> Result<Option<Box<dyn AudioSink>>, String> {
+        let capacity = (sample_rate as f64 * RING_SECONDS) as usize;
+        let (sink, consumer) = RingAudioSink::new(sample_rate, capacity);
+        let spec = AudioSpecDesired {
+            freq: Some(sample_rate as i32),
+            channels: Some(1),
+            samples: Some(DEVICE_BUFFER),
+        };
+        let device = self
+            .sdl
+            .audio()?
+            .open_playback(None, &spec, |_| RingCallback(consumer))?;
+        device.resume();
+        self.audio = Some((device, sink.level()));
+        return Ok(Some(Box::new(sink)));
+    }
+
+    fn audio_queued(&self) -> Option<usize> {
+        return self.audio.as_ref().map(|(_, level)| level.len());
+    }
+
+    fn has_vsync(&self) -> bool {
+        return self.vsync;
+    }
+
+    fn set_title(&mut self, title: &str) {
+        let _ = self.canvas.window_mut().set_title(title);
+    }
+}
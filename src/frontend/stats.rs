@@ -0,0 +1,132 @@
+use std::time::{Duration, Instant};
+
+pub const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+/* Performance stats
+
+   How the session is keeping up, worked out once a second from what the
+   frames in that second did:
+
+    fps             frames run a second
+    speed           that as a percentage of the console's own frame rate,
+                    so 100% is full speed whatever the region
+    mips            CPU instructions a second, in millions
+    audio_buffered  sound waiting in the host's device, None when the host
+                    doesn't say. Near zero means crackling is close
+    frame_time      the 50th, 95th and 99th percentile of the time a frame
+                    took to run and present, not counting the wait for the
+                    next one, which is the headroom the machine has left
+
+   `Session::stats` has the latest for benchmarking harnesses, and
+   `--show-stats` puts them in the window title and the corner of the
+   picture.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Stats {
+    pub fps: f64,
+    pub speed: f64,
+    pub mips: f64,
+    pub audio_buffered: Option<Duration>,
+    pub frame_time: [Duration; 3],
+}
+
+impl Stats {
+    // for the title bar
+    pub fn summary(&self) -> String {
+        let [p50, p95, p99] = self.frame_time.map(|time| time.as_secs_f64() * 1000.0);
+        let mut text = format!(
+            "{:.1} fps, {:.0}%, {:.2} MIPS, frame {:.1}/{:.1}/{:.1} ms",
+            self.fps, self.speed, self.mips, p50, p95, p99
+        );
+        if let Some(audio) = self.audio_buffered {
+            text += &format!(", audio {} ms", audio.as_millis());
+        }
+        return text;
+    }
+
+    // short enough for the on-screen display
+    pub fn brief(&self) -> String {
+        return format!("{:.0}% {:.2} MIPS", self.speed, self.mips);
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct StatsMeter {
+    start: Option<(Instant, u64)>, // and the instruction count then
+    frame_times: Vec<Duration>,
+    latest: Option<Stats>,
+}
+
+impl StatsMeter {
+    pub fn latest(&self) -> Option<&Stats> {
+        return self.latest.as_ref();
+    }
+
+    // after each frame, with the CPU's instruction count and the rate the
+    // console runs at; new stats whenever they are due
+    pub fn record(
+        &mut self,
+        now: Instant,
+        instructions: u64,
+        frame_time: Duration,
+        audio_buffered: Option<Duration>,
+        frame_rate: f64,
+    ) -> Option<Stats> {
+        let (start, first) = *self.start.get_or_insert((now, instructions));
+        self.frame_times.push(frame_time);
+        let elapsed = (now - start).as_secs_f64();
+        if now - start < STATS_INTERVAL {
+            return None;
+        }
+        let frames = self.frame_times.len() as f64;
+        self.frame_times.sort();
+        let percentile = |p: usize| self.frame_times[(self.frame_times.len() - 1) * p / 100];
+        let stats = Stats {
+            fps: frames / elapsed,
+            speed: frames / elapsed / frame_rate * 100.0,
+            mips: (instructions - first) as f64 / elapsed / 1e6,
+            audio_buffered,
+            frame_time: [percentile(50), percentile(95), percentile(99)],
+        };
+        self.start = Some((now, instructions));
+        self.frame_times.clear();
+        self.latest = Some(stats);
+        return Some(stats);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_stats_over_a_second() {
+        let start = Instant::now();
+        let mut meter = StatsMeter::default();
+        let frame = Duration::from_secs(1) / 50;
+        for n in 1..50 {
+            let time = Duration::from_millis(if n == 49 { 15 } else { 2 });
+            let stats = meter.record(start + frame * n, n as u64 * 30_000, time, None, 50.0);
+            assert_eq!(stats, None);
+        }
+        let stats = meter
+            .record(
+                start + frame * 51,
+                51 * 30_000,
+                Duration::from_millis(3),
+                None,
+                50.0,
+            )
+            .unwrap();
+        assert!((stats.fps - 50.0).abs() < 1e-9);
+        assert!((stats.speed - 100.0).abs() < 1e-9);
+        assert!((stats.mips - 1.5).abs() < 1e-9);
+        assert_eq!(stats.frame_time[0], Duration::from_millis(2));
+        assert_eq!(stats.frame_time[2], Duration::from_millis(3));
+        assert_eq!(meter.latest(), Some(&stats));
+        assert!(stats
+            .summary()
+            .starts_with("50.0 fps, 100%, 1.50 MIPS, frame 2.0/"));
+    }
+}
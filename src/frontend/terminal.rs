@@ -0,0 +1,389 @@
+use crate::frontend::{Host, HostEvent};
+use crate::video::Frame;
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+// a first press lasts past the terminal's key repeat delay, each repeat a little longer
+const INITIAL_HOLD_FRAMES: u32 = 32;
+const REPEAT_HOLD_FRAMES: u32 = 4;
+
+const UPPER_HALF_BLOCK: char = '\u{2580}';
+const BRAILLE_BLANK: u32 = 0x2800;
+// the braille dot for each pixel of a 2x4 cell, by row then column
+const BRAILLE_DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+/* Terminal renderer
+
+   Draws frames with text, for SSH sessions and CI logs where there is no
+   window. Half-block mode makes each character cell two pixels tall by
+   printing an upper half block with the top pixel as the foreground colour
+   and the bottom one as the background. Braille mode packs a 2x4 pixel
+   block into each cell, one dot per pixel brighter than the block's
+   average, with the average colours of the lit and unlit pixels as the
+   cell's two colours: more detail, less faithful colour.
+
+   Colours go out as 24 bit escapes when $COLORTERM says the terminal takes
+   them, otherwise as the nearest entry of the 256 colour cube. The picture
+   is scaled down by an integer factor, 2 by default, so it fits in 128
+   columns.
+
+   Terminals only send key presses, no releases, so a key counts as held
+   for a while after each press or auto-repeat. Ctrl-C quits; the terminal
+   is put in raw mode with stty while running and restored on exit.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TerminalMode {
+    #[default]
+    HalfBlock,
+    Braille,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TerminalStyle {
+    pub mode: TerminalMode,
+    pub scale: usize,
+    pub truecolor: bool,
+}
+
+impl Default for TerminalStyle {
+    fn default() -> Self {
+        return Self {
+            mode: TerminalMode::HalfBlock,
+            scale: 2,
+            truecolor: true,
+        };
+    }
+}
+
+type Rgb = (u8, u8, u8);
+
+// the frame as escape sequences and characters, starting at the top left of the screen
+pub fn render(frame: &Frame, style: &TerminalStyle) -> String {
+    let scale = style.scale.max(1);
+    let width = frame.width / scale;
+    let height = frame.height / scale;
+    let sample = |x: usize, y: usize| -> Rgb {
+        let [r, g, b, _] = frame.pixel(x * scale, y * scale);
+        return (r, g, b);
+    };
+
+    let mut out = String::from("\x1b[H");
+    let mut colours: Option<(Rgb, Rgb)> = None;
+    let (cell_width, cell_height) = match style.mode {
+        TerminalMode::HalfBlock => (1, 2),
+        TerminalMode::Braille => (2, 4),
+    };
+    for row in 0..height / cell_height {
+        for column in 0..width / cell_width {
+            let (x, y) = (column * cell_width, row * cell_height);
+            let (glyph, fg, bg) = match style.mode {
+                TerminalMode::HalfBlock => (UPPER_HALF_BLOCK, sample(x, y), sample(x, y + 1)),
+                TerminalMode::Braille => braille_cell(|dx, dy| sample(x + dx, y + dy)),
+            };
+            if colours != Some((fg, bg)) {
+                out += &colour_escape(38, fg, style.truecolor);
+                out += &colour_escape(48, bg, style.truecolor);
+                colours = Some((fg, bg));
+            }
+            out.push(glyph);
+        }
+        out += "\x1b[0m\r\n";
+        colours = None;
+    }
+    return out;
+}
+
+fn braille_cell(pixel: impl Fn(usize, usize) -> Rgb) -> (char, Rgb, Rgb) {
+    let luma = |(r, g, b): Rgb| r as u32 * 299 + g as u32 * 587 + b as u32 * 114;
+    let mut pixels = Vec::with_capacity(8);
+    for (dy, dots) in BRAILLE_DOTS.iter().enumerate() {
+        for (dx, &dot) in dots.iter().enumerate() {
+            pixels.push((pixel(dx, dy), dot));
+        }
+    }
+    let mean = pixels.iter().map(|&(rgb, _)| luma(rgb)).sum::<u32>() / 8;
+    let (lit, unlit): (Vec<_>, Vec<_>) = pixels.iter().partition(|&&(rgb, _)| luma(rgb) > mean);
+    let dots = lit.iter().fold(0, |dots, &(_, dot)| dots | dot);
+    let glyph = char::from_u32(BRAILLE_BLANK + dots).unwrap_or(' ');
+    let fg = average(&lit.iter().map(|&(rgb, _)| rgb).collect::<Vec<_>>());
+    let bg = average(&unlit.iter().map(|&(rgb, _)| rgb).collect::<Vec<_>>());
+    return (
+        glyph,
+        fg.unwrap_or_default(),
+        bg.unwrap_or(fg.unwrap_or_default()),
+    );
+}
+
+fn average(colours: &[Rgb]) -> Option<Rgb> {
+    if colours.is_empty() {
+        return None;
+    }
+    let n = colours.len() as u32;
+    let sum = colours.iter().fold((0, 0, 0), |(r, g, b), c| {
+        (r + c.0 as u32, g + c.1 as u32, b + c.2 as u32)
+    });
+    return Some(((sum.0 / n) as u8, (sum.1 / n) as u8, (sum.2 / n) as u8));
+}
+
+// `layer` 38 for the foreground, 48 for the background
+fn colour_escape(layer: u8, (r, g, b): Rgb, truecolor: bool) -> String {
+    let mut escape = String::new();
+    if truecolor {
+        let _ = write!(escape, "\x1b[{};2;{};{};{}m", layer, r, g, b);
+    } else {
+        let level = |c: u8| (c as u16 * 5 + 127) / 255;
+        let index = 16 + 36 * level(r) + 6 * level(g) + level(b);
+        let _ = write!(escape, "\x1b[{};5;{}m", layer, index);
+    }
+    return escape;
+}
+
+// key names for raw terminal input, as the keyboard bindings use them
+fn parse_keys(bytes: &[u8]) -> Vec<Option<String>> {
+    let mut keys = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let key = match bytes[i] {
+            0x03 => None, // Ctrl-C
+            0x1B if bytes[i + 1..].starts_with(b"[") && i + 2 < bytes.len() => {
+                i += 2;
+                match bytes[i] {
+                    b'1' if bytes[i..].starts_with(b"19~") => {
+                        i += 2;
+                        Some("F8".to_string())
+                    }
+                    b'A' => Some("Up".to_string()),
+                    b'B' => Some("Down".to_string()),
+                    b'C' => Some("Right".to_string()),
+                    b'D' => Some("Left".to_string()),
+                    _ => Some("Escape".to_string()),
+                }
+            }
+            0x1B => Some("Escape".to_string()),
+            b'\r' | b'\n' => Some("Return".to_string()),
+            b'\t' => Some("Tab".to_string()),
+            b' ' => Some("Space".to_string()),
+            0x7F => Some("Backspace".to_string()),
+            byte if byte.is_ascii_graphic() => {
+                Some((byte as char).to_ascii_uppercase().to_string())
+            }
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+        keys.push(key);
+        i += 1;
+    }
+    return keys;
+}
+
+pub struct TerminalHost<W: Write> {
+    out: W,
+    style: TerminalStyle,
+    input: Receiver<u8>,
+    held: HashMap<String, u32>, // key, frames left
+    saved_tty: Option<String>,
+}
+
+impl TerminalHost<io::Stdout> {
+    // takes over the terminal this process runs in
+    pub fn open(style: TerminalStyle) -> Result<Self, String> {
+        let saved_tty = stty(&["-g"])?;
+        stty(&["raw", "-echo"])?;
+        let (sender, input) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buf = [0; 64];
+            while let Ok(n @ 1..) = io::stdin().read(&mut buf) {
+                if buf[..n].iter().any(|&byte| sender.send(byte).is_err()) {
+                    return;
+                }
+            }
+        });
+        let mut host = TerminalHost::new(io::stdout(), style, input);
+        host.saved_tty = Some(saved_tty.trim().to_string());
+        host.out
+            .write_all(b"\x1b[?25l\x1b[2J")
+            .map_err(|e| e.to_string())?;
+        return Ok(host);
+    }
+}
+
+impl<W: Write> TerminalHost<W> {
+    pub fn new(out: W, style: TerminalStyle, input: Receiver<u8>) -> Self {
+        return Self {
+            out,
+            style,
+            input,
+            held: HashMap::new(),
+            saved_tty: None,
+        };
+    }
+}
+
+// whether the terminal we run in takes 24 bit colour
+pub fn supports_truecolor() -> bool {
+    return matches!(
+        env::var("COLORTERM").as_deref(),
+        Ok("truecolor") | Ok("24bit")
+    );
+}
+
+fn stty(args: &[&str]) -> Result<String, String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .output()
+        .map_err(|e| format!("stty: {}", e))?;
+    if !output.status.success() {
+        return Err("stty: the input is not a terminal".to_string());
+    }
+    return Ok(String::from_utf8_lossy(&output.stdout).into_owned());
+}
+
+impl<W: Write> Host for TerminalHost<W> {
+    fn poll_events(&mut self) -> Vec<HostEvent> {
+        let mut events = Vec::new();
+        for frames in self.held.values_mut() {
+            *frames -= 1;
+        }
+        let mut bytes = Vec::new();
+        loop {
+            match self.input.try_recv() {
+                Ok(byte) => bytes.push(byte),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    events.push(HostEvent::Quit);
+                    break;
+                }
+            }
+        }
+        for key in parse_keys(&bytes) {
+            let Some(key) = key else {
+                events.push(HostEvent::Quit);
+                continue;
+            };
+            match self.held.get_mut(&key) {
+                Some(frames) => {
+                    *frames = (*frames).max(REPEAT_HOLD_FRAMES);
+                    events.push(HostEvent::KeyRepeat(key));
+                }
+                None => {
+                    events.push(HostEvent::KeyDown(key.clone()));
+                    self.held.insert(key, INITIAL_HOLD_FRAMES);
+                }
+            }
+        }
+        self.held.retain(|key, frames| {
+            if *frames == 0 {
+                events.push(HostEvent::KeyUp(key.clone()));
+            }
+            return *frames > 0;
+        });
+        return events;
+    }
+
+    fn present(&mut self, frame: &Frame) -> Result<(), String> {
+        let text = render(frame, &self.style);
+        self.out
+            .write_all(text.as_bytes())
+            .and_then(|_| self.out.flush())
+            .map_err(|e| e.to_string())?;
+        return Ok(());
+    }
+}
+
+impl<W: Write> Drop for TerminalHost<W> {
+    fn drop(&mut self) {
+        let _ = self.out.write_all(b"\x1b[0m\x1b[?25h\r\n");
+        let _ = self.out.flush();
+        if let Some(saved) = &self.saved_tty {
+            let _ = stty(&[saved]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn frame(pixels: &[Rgb], width: usize) -> Frame {
+        let mut frame = Frame::new(width, pixels.len() / width);
+        for (i, &(r, g, b)) in pixels.iter().enumerate() {
+            frame.pixels[i * 4..i * 4 + 4].copy_from_slice(&[r, g, b, 0xFF]);
+        }
+        return frame;
+    }
+
+    #[test]
+    fn test_half_blocks() {
+        let red = (255, 0, 0);
+        let blue = (0, 0, 255);
+        let style = TerminalStyle {
+            scale: 1,
+            ..TerminalStyle::default()
+        };
+        let text = render(&frame(&[red, red, blue, blue], 2), &style);
+        assert_eq!(
+            text,
+            "\x1b[H\x1b[38;2;255;0;0m\x1b[48;2;0;0;255m\u{2580}\u{2580}\x1b[0m\r\n"
+        );
+
+        let style = TerminalStyle {
+            truecolor: false,
+            ..style
+        };
+        assert!(render(&frame(&[red, blue], 1), &style).contains("\x1b[38;5;196m\x1b[48;5;21m"));
+    }
+
+    #[test]
+    fn test_braille_dots() {
+        let (on, off) = ((255, 255, 255), (0, 0, 0));
+        // the left column lit, the right one dark
+        let pixels = [on, off, on, off, on, off, on, off];
+        let style = TerminalStyle {
+            mode: TerminalMode::Braille,
+            scale: 1,
+            truecolor: true,
+        };
+        let text = render(&frame(&pixels, 2), &style);
+        assert!(text.contains('\u{2847}'));
+        assert!(text.contains("\x1b[38;2;255;255;255m\x1b[48;2;0;0;0m"));
+    }
+
+    #[test]
+    fn test_keys_are_held_between_presses() {
+        let (sender, input) = mpsc::channel();
+        let mut host = TerminalHost::new(Vec::new(), TerminalStyle::default(), input);
+        for &byte in b"x\x1b[A\x1b[19~" {
+            sender.send(byte).unwrap();
+        }
+        assert_eq!(
+            host.poll_events(),
+            vec![
+                HostEvent::KeyDown("X".to_string()),
+                HostEvent::KeyDown("Up".to_string()),
+                HostEvent::KeyDown("F8".to_string())
+            ]
+        );
+        for _ in 1..INITIAL_HOLD_FRAMES {
+            sender.send(b'x').unwrap();
+            assert_eq!(
+                host.poll_events(),
+                vec![HostEvent::KeyRepeat("X".to_string())]
+            );
+        }
+        let released = host.poll_events();
+        assert!(released.contains(&HostEvent::KeyUp("Up".to_string())));
+        assert!(released.contains(&HostEvent::KeyUp("F8".to_string())));
+
+        sender.send(0x03).unwrap();
+        assert_eq!(host.poll_events(), vec![HostEvent::Quit]);
+    }
+}
@@ -0,0 +1,98 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+// how often the file is looked at
+pub const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/* ROM hot reload
+
+   For homebrew: `--watch` reloads the game whenever its file changes, so
+   saving in the editor and running make is enough to see the result. The
+   file's size and modification time are polled rather than watched with
+   the platform's notification API, which this build has no crate for, and
+   a change only counts once they have held still for a poll, so a linker
+   still writing the file is never caught halfway.
+
+   The reload resets the console with the new ROM. With `--watch-keep-ram`
+   work RAM and cartridge RAM carry over, so a game under development can
+   be changed without playing back to where it was, as long as the new
+   code agrees with the old about what lives where.
+*/
+
+type Stamp = Option<(SystemTime, u64)>;
+
+fn stamp(path: &Path) -> Stamp {
+    let metadata = fs::metadata(path).ok()?;
+    return Some((metadata.modified().ok()?, metadata.len()));
+}
+
+#[derive(Debug, Clone)]
+pub struct FileWatch {
+    path: PathBuf,
+    loaded: Stamp,          // what was on disk when the game was last loaded
+    pending: Option<Stamp>, // a change seen on the last poll, waiting to settle
+    next_poll: Instant,
+}
+
+impl FileWatch {
+    pub fn new(path: &Path, now: Instant) -> Self {
+        return Self {
+            path: path.to_path_buf(),
+            loaded: stamp(path),
+            pending: None,
+            next_poll: now + POLL_INTERVAL,
+        };
+    }
+
+    pub fn path(&self) -> &Path {
+        return &self.path;
+    }
+
+    // true once the file has changed and then stayed the same for a poll
+    pub fn poll(&mut self, now: Instant) -> bool {
+        if now < self.next_poll {
+            return false;
+        }
+        self.next_poll = now + POLL_INTERVAL;
+        let current = stamp(&self.path);
+        if current == self.loaded || current.is_none() {
+            self.pending = None;
+            return false;
+        }
+        if self.pending != Some(current) {
+            self.pending = Some(current);
+            return false;
+        }
+        self.loaded = current;
+        self.pending = None;
+        return true;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_change_is_seen_once_settled() {
+        let path = std::env::temp_dir().join(format!("rustynes-watch-{}.nes", std::process::id()));
+        fs::write(&path, b"one").unwrap();
+        let start = Instant::now();
+        let mut watch = FileWatch::new(&path, start);
+        let tick = |n: u32| start + POLL_INTERVAL * n;
+        assert!(!watch.poll(tick(1)));
+
+        // a different size is a change even within the clock's resolution
+        fs::write(&path, b"two!").unwrap();
+        assert!(!watch.poll(start)); // not due yet
+        assert!(!watch.poll(tick(2))); // seen, settling
+        assert!(watch.poll(tick(3)));
+        assert!(!watch.poll(tick(4)));
+
+        // missing for a moment, as while a build replaces it, isn't a change
+        fs::remove_file(&path).unwrap();
+        assert!(!watch.poll(tick(5)));
+        assert!(!watch.poll(tick(6)));
+    }
+}
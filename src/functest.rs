@@ -0,0 +1,241 @@
+use crate::cpu::{Mem, CPU};
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+/* Raw binary functional tests
+
+   Suites like Klaus Dormann's 6502_functional_test.bin are a full 64KB memory
+   image rather than a cartridge: the image is copied to $0000 on the flat bus
+   and execution starts at a fixed address ($0400 for that suite). They signal
+   their result by trapping, i.e. jumping or branching to themselves, so the
+   runner stops as soon as an instruction leaves PC where it was. Landing on the
+   documented success address is a pass, any other trap is the failing test.
+   BRK is exercised by these suites, so it runs as a real interrupt here.
+
+   The NES CPU has no decimal mode, assemble the suite with disable_decimal = 1.
+*/
+
+const IMAGE_SIZE: usize = 0x10000;
+const DEFAULT_START: u16 = 0x0400;
+const DEFAULT_MAX_STEPS: u64 = 100_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Trapped { pc: u16 },
+    StepLimit { pc: u16 },
+}
+
+#[derive(Debug)]
+pub struct Report {
+    pub outcome: Outcome,
+    pub success: Option<u16>,
+    pub steps: u64,
+    pub cycles: u64,
+}
+
+impl Report {
+    // without a success address any trap counts, the caller just learns where
+    pub fn passed(&self) -> bool {
+        match self.outcome {
+            Outcome::Trapped { pc } => return self.success.is_none_or(|s| s == pc),
+            Outcome::StepLimit { .. } => return false,
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.outcome {
+            Outcome::Trapped { pc } => write!(f, "trapped at ${:04X}", pc)?,
+            Outcome::StepLimit { pc } => write!(f, "step limit reached at ${:04X}", pc)?,
+        }
+        write!(
+            f,
+            " after {} instructions ({} cycles)",
+            self.steps, self.cycles
+        )?;
+        match (self.success, self.passed()) {
+            (Some(_), true) => return writeln!(f, ": success"),
+            (Some(success), false) => {
+                return writeln!(f, ": failure, success trap is ${:04X}", success)
+            }
+            (None, _) => return writeln!(f),
+        }
+    }
+}
+
+pub fn load_image(image: &[u8], start: u16) -> Result<CPU, String> {
+    if image.len() > IMAGE_SIZE {
+        return Err(format!(
+            "image is {} bytes, at most {} fit in the address space",
+            image.len(),
+            IMAGE_SIZE
+        ));
+    }
+    let mut cpu = CPU::new();
+    for (addr, byte) in image.iter().enumerate() {
+        cpu.mem_write(addr as u16, *byte);
+    }
+    cpu.halt_on_brk = false;
+    cpu.program_counter = start;
+    return Ok(cpu);
+}
+
+pub fn run_until_trap(cpu: &mut CPU, success: Option<u16>, max_steps: u64) -> Report {
+    let cycles_before = cpu.cycles;
+    let mut steps = 0;
+    let outcome = loop {
+        if steps >= max_steps {
+            break Outcome::StepLimit {
+                pc: cpu.program_counter,
+            };
+        }
+        let pc = cpu.program_counter;
+        cpu.step();
+        steps += 1;
+        if cpu.program_counter == pc {
+            break Outcome::Trapped { pc };
+        }
+    };
+    return Report {
+        outcome,
+        success,
+        steps,
+        cycles: cpu.cycles - cycles_before,
+    };
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Options {
+    pub path: PathBuf,
+    pub start: u16,
+    pub success: Option<u16>,
+    pub max_steps: u64,
+}
+
+impl Options {
+    pub fn parse(args: &[String]) -> Result<Options, String> {
+        let mut path = None;
+        let mut options = Options {
+            path: PathBuf::new(),
+            start: DEFAULT_START,
+            success: None,
+            max_steps: DEFAULT_MAX_STEPS,
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                return args.next().ok_or_else(|| format!("{} needs a value", name));
+            };
+            match arg.as_str() {
+                "--start" => options.start = parse_address(value("--start")?)?,
+                "--success" => options.success = Some(parse_address(value("--success")?)?),
+                "--max-steps" => {
+                    let steps = value("--max-steps")?;
+                    options.max_steps = steps
+                        .parse()
+                        .map_err(|_| format!("{} is not a valid number", steps))?;
+                }
+                flag if flag.starts_with("--") => return Err(format!("unknown option {}", flag)),
+                file if path.is_none() => path = Some(PathBuf::from(file)),
+                extra => return Err(format!("unexpected argument {}", extra)),
+            }
+        }
+        options.path = path.ok_or("missing binary file")?;
+        return Ok(options);
+    }
+}
+
+// addresses are always hex, with an optional $ or 0x prefix
+fn parse_address(value: &str) -> Result<u16, String> {
+    let digits = value
+        .strip_prefix('$')
+        .or_else(|| value.strip_prefix("0x"))
+        .unwrap_or(value);
+    return u16::from_str_radix(digits, 16).map_err(|_| format!("{} is not an address", value));
+}
+
+// `rustynes functest <file> [--start ADDR] [--success ADDR] [--max-steps N]`
+pub fn run_cli(args: &[String]) -> Result<bool, String> {
+    let options = Options::parse(args)?;
+    let image =
+        fs::read(&options.path).map_err(|e| format!("{}: {}", options.path.display(), e))?;
+    let mut cpu = load_image(&image, options.start)?;
+    let report = run_until_trap(&mut cpu, options.success, options.max_steps);
+    print!("{}", report);
+    return Ok(report.passed());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm;
+
+    fn image(origin: u16, source: &str) -> Vec<u8> {
+        let mut image = vec![0; IMAGE_SIZE];
+        let code = asm::assemble(source).unwrap();
+        image[origin as usize..origin as usize + code.len()].copy_from_slice(&code);
+        return image;
+    }
+
+    #[test]
+    fn test_trap_at_success_address() {
+        // LDX #$03, loop: DEX, BNE loop, done: JMP done
+        let image = image(0x0400, "LDX #$03\nDEX\nBNE $FD\nJMP $0405");
+        let mut cpu = load_image(&image, 0x0400).unwrap();
+        let report = run_until_trap(&mut cpu, Some(0x0405), 1000);
+        assert_eq!(report.outcome, Outcome::Trapped { pc: 0x0405 });
+        assert_eq!(report.steps, 1 + 3 * 2 + 1);
+        assert!(report.passed());
+        assert!(report.to_string().ends_with(": success\n"));
+    }
+
+    #[test]
+    fn test_trap_elsewhere_is_a_failure() {
+        // fail: BEQ fail (Z is set by LDA #$00)
+        let image = image(0x0400, "LDA #$00\nBEQ $FE");
+        let mut cpu = load_image(&image, 0x0400).unwrap();
+        let report = run_until_trap(&mut cpu, Some(0x3469), 1000);
+        assert_eq!(report.outcome, Outcome::Trapped { pc: 0x0402 });
+        assert!(!report.passed());
+        assert!(report
+            .to_string()
+            .contains("failure, success trap is $3469"));
+    }
+
+    #[test]
+    fn test_step_limit() {
+        let image = image(0x0400, "NOP\nNOP\nNOP");
+        let mut cpu = load_image(&image, 0x0400).unwrap();
+        let report = run_until_trap(&mut cpu, None, 2);
+        assert_eq!(report.outcome, Outcome::StepLimit { pc: 0x0402 });
+        assert!(!report.passed());
+    }
+
+    #[test]
+    fn test_load_image_rejects_oversized() {
+        assert!(load_image(&vec![0; IMAGE_SIZE + 1], 0).is_err());
+        let cpu = load_image(&[0xA9, 0x01], 0x0000).unwrap();
+        assert_eq!(cpu.program_counter, 0x0000);
+        assert!(!cpu.halt_on_brk);
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let args: Vec<String> = ["test.bin", "--start", "$0400", "--success", "0x3469"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let options = Options::parse(&args).unwrap();
+        assert_eq!(options.start, 0x0400);
+        assert_eq!(options.success, Some(0x3469));
+        assert_eq!(options.max_steps, DEFAULT_MAX_STEPS);
+
+        assert!(Options::parse(&["--start".to_string()]).is_err());
+        assert!(
+            Options::parse(&["a.bin".to_string(), "--start".to_string(), "zz".to_string()])
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,291 @@
+use crate::cpu::{Mem, CPU};
+use crate::trace::{self, History};
+use reference::State;
+use std::fmt;
+
+pub mod reference;
+
+const DEFAULT_CASES: u64 = 1000;
+const DEFAULT_STEPS: u64 = 1000;
+// bits 4 and 5 of P only exist when it's pushed
+const STATUS_MASK: u8 = 0b1100_1111;
+
+/* Differential CPU fuzzer
+
+   `rustynes fuzz` holds the CPU against the `reference` one on random
+   programs: each case fills all 64KB of a flat bus and the registers from
+   its seed, then runs both an instruction at a time. Random memory is
+   mostly opcodes the reference doesn't do, so wherever the PC lands on one
+   it is swapped, in both, for a random official opcode, which makes the
+   program up as it goes while jumps, branches, returns and BRK send it
+   all over memory.
+
+   After every instruction the registers, P but for bits 4 and 5, the
+   cycles and each byte the reference wrote must agree, and at the end of
+   the case all of memory. The first difference is reported with the seed
+   to run again, the step, and the trace line of what ran, the state it
+   ran in. Cases are numbered from `--seed`, so a failure is also
+   `--seed SEED --cases 1`.
+*/
+
+// xorshift64, from a seed spread out so nearby seeds don't start alike
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        return Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1);
+    }
+
+    fn byte(&mut self) -> u8 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        return (self.0 >> 32) as u8;
+    }
+
+    fn opcode(&mut self) -> u8 {
+        loop {
+            let code = self.byte();
+            if reference::decode(code).is_some() {
+                return code;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub seed: u64,
+    pub step: u64,
+    pub ran: String, // the instruction and the state it ran in, as a trace line
+    pub field: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "seed {}, step {}: {} expected {}, got {}",
+            self.seed, self.step, self.field, self.expected, self.actual
+        )?;
+        return writeln!(f, "  after  {}", self.ran);
+    }
+}
+
+// the reference's starting state and a CPU in the same one
+fn setup(seed: u64) -> (State, CPU) {
+    let mut rng = Rng::new(seed);
+    let memory: Vec<u8> = (0..0x10000).map(|_| rng.byte()).collect();
+    let mut state = State::new(memory);
+    state.a = rng.byte();
+    state.x = rng.byte();
+    state.y = rng.byte();
+    state.p = rng.byte();
+    state.sp = rng.byte();
+    state.pc = u16::from_le_bytes([rng.byte(), rng.byte()]);
+
+    let mut cpu = CPU::new();
+    cpu.bus.ram_mut().copy_from_slice(&state.memory);
+    cpu.register_a = state.a;
+    cpu.register_x = state.x;
+    cpu.register_y = state.y;
+    cpu.status.set_bits(state.p);
+    cpu.stack.set_ptr(state.sp);
+    cpu.program_counter = state.pc;
+    cpu.halt_on_brk = false;
+    cpu.history = History::new(0);
+    return (state, cpu);
+}
+
+// the first thing the CPU has that the reference doesn't
+fn difference(state: &State, cpu: &CPU) -> Option<(String, String, String)> {
+    let registers = [
+        ("A", state.a, cpu.register_a),
+        ("X", state.x, cpu.register_x),
+        ("Y", state.y, cpu.register_y),
+        ("P", state.p & STATUS_MASK, cpu.status.bits() & STATUS_MASK),
+        ("SP", state.sp, cpu.stack.ptr() as u8),
+    ];
+    for (name, expected, actual) in registers {
+        if expected != actual {
+            let hex = |value: u8| format!("{:02X}", value);
+            return Some((name.to_string(), hex(expected), hex(actual)));
+        }
+    }
+    if state.pc != cpu.program_counter {
+        let hex = |value: u16| format!("{:04X}", value);
+        return Some(("PC".to_string(), hex(state.pc), hex(cpu.program_counter)));
+    }
+    if state.cycles != cpu.cycles {
+        return Some((
+            "CYC".to_string(),
+            state.cycles.to_string(),
+            cpu.cycles.to_string(),
+        ));
+    }
+    return memory_difference(state, cpu, state.written.iter().copied());
+}
+
+fn memory_difference(
+    state: &State,
+    cpu: &CPU,
+    addrs: impl Iterator<Item = u16>,
+) -> Option<(String, String, String)> {
+    for addr in addrs {
+        let (expected, actual) = (state.memory[addr as usize], cpu.bus.peek(addr));
+        if expected != actual {
+            return Some((
+                format!("${:04X}", addr),
+                format!("{:02X}", expected),
+                format!("{:02X}", actual),
+            ));
+        }
+    }
+    return None;
+}
+
+pub fn run_case(seed: u64, steps: u64) -> Option<Divergence> {
+    let (mut state, mut cpu) = setup(seed);
+    let mut rng = Rng::new(!seed);
+    let mut ran = String::new();
+    for step in 0..steps {
+        let pc = state.pc;
+        if reference::decode(state.memory[pc as usize]).is_none() {
+            let code = rng.opcode();
+            state.memory[pc as usize] = code;
+            cpu.mem_write(pc, code);
+        }
+        ran = trace::line(&cpu);
+        state.step();
+        cpu.step();
+        if let Some((field, expected, actual)) = difference(&state, &cpu) {
+            return Some(Divergence {
+                seed,
+                step,
+                ran,
+                field,
+                expected,
+                actual,
+            });
+        }
+    }
+    let (field, expected, actual) = memory_difference(&state, &cpu, 0..=0xFFFF)?;
+    return Some(Divergence {
+        seed,
+        step: steps,
+        ran,
+        field,
+        expected,
+        actual,
+    });
+}
+
+// the first case from `seed` on that diverges
+pub fn run(seed: u64, cases: u64, steps: u64) -> Option<Divergence> {
+    return (seed..seed.saturating_add(cases)).find_map(|seed| run_case(seed, steps));
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Options {
+    pub seed: u64,
+    pub cases: u64,
+    pub steps: u64,
+}
+
+impl Options {
+    pub fn parse(args: &[String]) -> Result<Options, String> {
+        let mut options = Options {
+            seed: 0,
+            cases: DEFAULT_CASES,
+            steps: DEFAULT_STEPS,
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("{} needs a value", name))?;
+                return value
+                    .parse::<u64>()
+                    .map_err(|_| format!("{} is not a valid number", value));
+            };
+            match arg.as_str() {
+                "--seed" => options.seed = value("--seed")?,
+                "--cases" => options.cases = value("--cases")?,
+                "--steps" => options.steps = value("--steps")?,
+                flag if flag.starts_with("--") => return Err(format!("unknown option {}", flag)),
+                extra => return Err(format!("unexpected argument {}", extra)),
+            }
+        }
+        return Ok(options);
+    }
+}
+
+// `rustynes fuzz [--seed N] [--cases N] [--steps N]`
+pub fn run_cli(args: &[String]) -> Result<bool, String> {
+    let options = Options::parse(args)?;
+    match run(options.seed, options.cases, options.steps) {
+        Some(divergence) => {
+            print!("{}", divergence);
+            return Ok(false);
+        }
+        None => {
+            println!(
+                "{} cases of {} instructions agree",
+                options.cases, options.steps
+            );
+            return Ok(true);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cases_agree() {
+        assert_eq!(run(0, 200, 500), None);
+    }
+
+    #[test]
+    fn test_a_divergence_is_reported() {
+        // the same case with the CPU's carry flipped before it runs
+        let (mut state, mut cpu) = setup(7);
+        cpu.status.set_bits(cpu.status.bits() ^ 0x01);
+        assert_eq!(
+            difference(&state, &cpu),
+            Some((
+                "P".to_string(),
+                format!("{:02X}", state.p & STATUS_MASK),
+                format!("{:02X}", (state.p ^ 0x01) & STATUS_MASK)
+            ))
+        );
+        state.memory[0x1234] ^= 0xFF;
+        cpu.status.set_bits(state.p);
+        assert_eq!(
+            memory_difference(&state, &cpu, 0..=0xFFFF).map(|d| d.0),
+            Some("$1234".to_string())
+        );
+    }
+
+    #[test]
+    fn test_options() {
+        let args: Vec<String> = ["--seed", "5", "--cases", "1"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(
+            Options::parse(&args),
+            Ok(Options {
+                seed: 5,
+                cases: 1,
+                steps: DEFAULT_STEPS
+            })
+        );
+        assert!(Options::parse(&["--steps".to_string()]).is_err());
+        assert!(Options::parse(&["--speed".to_string()]).is_err());
+    }
+}
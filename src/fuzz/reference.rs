@@ -0,0 +1,543 @@
+/* Reference 6502
+
+   A second CPU for the fuzzer to hold the real one against, written to be
+   plainly right rather than fast or complete: the 151 official opcodes
+   over a flat 64KB, each spelled out in the table with its addressing mode
+   and cycles, and each instruction done in a few lines straight from the
+   datasheet. It shares no code with `cpu`, so a mistake would have to be
+   made twice, the same way, to go unnoticed. There is no decimal mode, as
+   on the NES, and no interrupts but BRK.
+
+   Every write is noted, so the fuzzer can check the same bytes changed in
+   the real core after each instruction without comparing all of memory.
+*/
+
+const CARRY: u8 = 0x01;
+const ZERO: u8 = 0x02;
+const INTERRUPT: u8 = 0x04;
+const DECIMAL: u8 = 0x08;
+const BREAK: u8 = 0x10;
+const UNUSED: u8 = 0x20;
+const OVERFLOW: u8 = 0x40;
+const NEGATIVE: u8 = 0x80;
+const IRQ_VECTOR: u16 = 0xFFFE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Implied,
+    Accumulator,
+    Immediate,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    IndirectX,
+    IndirectY,
+    Indirect,
+    Relative,
+}
+
+impl Mode {
+    pub fn size(self) -> u16 {
+        return match self {
+            Mode::Implied | Mode::Accumulator => 1,
+            Mode::Absolute | Mode::AbsoluteX | Mode::AbsoluteY | Mode::Indirect => 3,
+            _ => 2,
+        };
+    }
+}
+
+// the mnemonic, mode and cycles before any page crossing or branch taken
+pub fn decode(code: u8) -> Option<(&'static str, Mode, u64)> {
+    use Mode::*;
+    let op = match code {
+        0x69 => ("ADC", Immediate, 2),
+        0x65 => ("ADC", ZeroPage, 3),
+        0x75 => ("ADC", ZeroPageX, 4),
+        0x6D => ("ADC", Absolute, 4),
+        0x7D => ("ADC", AbsoluteX, 4),
+        0x79 => ("ADC", AbsoluteY, 4),
+        0x61 => ("ADC", IndirectX, 6),
+        0x71 => ("ADC", IndirectY, 5),
+        0x29 => ("AND", Immediate, 2),
+        0x25 => ("AND", ZeroPage, 3),
+        0x35 => ("AND", ZeroPageX, 4),
+        0x2D => ("AND", Absolute, 4),
+        0x3D => ("AND", AbsoluteX, 4),
+        0x39 => ("AND", AbsoluteY, 4),
+        0x21 => ("AND", IndirectX, 6),
+        0x31 => ("AND", IndirectY, 5),
+        0x0A => ("ASL", Accumulator, 2),
+        0x06 => ("ASL", ZeroPage, 5),
+        0x16 => ("ASL", ZeroPageX, 6),
+        0x0E => ("ASL", Absolute, 6),
+        0x1E => ("ASL", AbsoluteX, 7),
+        0x90 => ("BCC", Relative, 2),
+        0xB0 => ("BCS", Relative, 2),
+        0xF0 => ("BEQ", Relative, 2),
+        0x30 => ("BMI", Relative, 2),
+        0xD0 => ("BNE", Relative, 2),
+        0x10 => ("BPL", Relative, 2),
+        0x50 => ("BVC", Relative, 2),
+        0x70 => ("BVS", Relative, 2),
+        0x24 => ("BIT", ZeroPage, 3),
+        0x2C => ("BIT", Absolute, 4),
+        0x00 => ("BRK", Implied, 7),
+        0x18 => ("CLC", Implied, 2),
+        0xD8 => ("CLD", Implied, 2),
+        0x58 => ("CLI", Implied, 2),
+        0xB8 => ("CLV", Implied, 2),
+        0xC9 => ("CMP", Immediate, 2),
+        0xC5 => ("CMP", ZeroPage, 3),
+        0xD5 => ("CMP", ZeroPageX, 4),
+        0xCD => ("CMP", Absolute, 4),
+        0xDD => ("CMP", AbsoluteX, 4),
+        0xD9 => ("CMP", AbsoluteY, 4),
+        0xC1 => ("CMP", IndirectX, 6),
+        0xD1 => ("CMP", IndirectY, 5),
+        0xE0 => ("CPX", Immediate, 2),
+        0xE4 => ("CPX", ZeroPage, 3),
+        0xEC => ("CPX", Absolute, 4),
+        0xC0 => ("CPY", Immediate, 2),
+        0xC4 => ("CPY", ZeroPage, 3),
+        0xCC => ("CPY", Absolute, 4),
+        0xC6 => ("DEC", ZeroPage, 5),
+        0xD6 => ("DEC", ZeroPageX, 6),
+        0xCE => ("DEC", Absolute, 6),
+        0xDE => ("DEC", AbsoluteX, 7),
+        0xCA => ("DEX", Implied, 2),
+        0x88 => ("DEY", Implied, 2),
+        0x49 => ("EOR", Immediate, 2),
+        0x45 => ("EOR", ZeroPage, 3),
+        0x55 => ("EOR", ZeroPageX, 4),
+        0x4D => ("EOR", Absolute, 4),
+        0x5D => ("EOR", AbsoluteX, 4),
+        0x59 => ("EOR", AbsoluteY, 4),
+        0x41 => ("EOR", IndirectX, 6),
+        0x51 => ("EOR", IndirectY, 5),
+        0xE6 => ("INC", ZeroPage, 5),
+        0xF6 => ("INC", ZeroPageX, 6),
+        0xEE => ("INC", Absolute, 6),
+        0xFE => ("INC", AbsoluteX, 7),
+        0xE8 => ("INX", Implied, 2),
+        0xC8 => ("INY", Implied, 2),
+        0x4C => ("JMP", Absolute, 3),
+        0x6C => ("JMP", Indirect, 5),
+        0x20 => ("JSR", Absolute, 6),
+        0xA9 => ("LDA", Immediate, 2),
+        0xA5 => ("LDA", ZeroPage, 3),
+        0xB5 => ("LDA", ZeroPageX, 4),
+        0xAD => ("LDA", Absolute, 4),
+        0xBD => ("LDA", AbsoluteX, 4),
+        0xB9 => ("LDA", AbsoluteY, 4),
+        0xA1 => ("LDA", IndirectX, 6),
+        0xB1 => ("LDA", IndirectY, 5),
+        0xA2 => ("LDX", Immediate, 2),
+        0xA6 => ("LDX", ZeroPage, 3),
+        0xB6 => ("LDX", ZeroPageY, 4),
+        0xAE => ("LDX", Absolute, 4),
+        0xBE => ("LDX", AbsoluteY, 4),
+        0xA0 => ("LDY", Immediate, 2),
+        0xA4 => ("LDY", ZeroPage, 3),
+        0xB4 => ("LDY", ZeroPageX, 4),
+        0xAC => ("LDY", Absolute, 4),
+        0xBC => ("LDY", AbsoluteX, 4),
+        0x4A => ("LSR", Accumulator, 2),
+        0x46 => ("LSR", ZeroPage, 5),
+        0x56 => ("LSR", ZeroPageX, 6),
+        0x4E => ("LSR", Absolute, 6),
+        0x5E => ("LSR", AbsoluteX, 7),
+        0xEA => ("NOP", Implied, 2),
+        0x09 => ("ORA", Immediate, 2),
+        0x05 => ("ORA", ZeroPage, 3),
+        0x15 => ("ORA", ZeroPageX, 4),
+        0x0D => ("ORA", Absolute, 4),
+        0x1D => ("ORA", AbsoluteX, 4),
+        0x19 => ("ORA", AbsoluteY, 4),
+        0x01 => ("ORA", IndirectX, 6),
+        0x11 => ("ORA", IndirectY, 5),
+        0x48 => ("PHA", Implied, 3),
+        0x08 => ("PHP", Implied, 3),
+        0x68 => ("PLA", Implied, 4),
+        0x28 => ("PLP", Implied, 4),
+        0x2A => ("ROL", Accumulator, 2),
+        0x26 => ("ROL", ZeroPage, 5),
+        0x36 => ("ROL", ZeroPageX, 6),
+        0x2E => ("ROL", Absolute, 6),
+        0x3E => ("ROL", AbsoluteX, 7),
+        0x6A => ("ROR", Accumulator, 2),
+        0x66 => ("ROR", ZeroPage, 5),
+        0x76 => ("ROR", ZeroPageX, 6),
+        0x6E => ("ROR", Absolute, 6),
+        0x7E => ("ROR", AbsoluteX, 7),
+        0x40 => ("RTI", Implied, 6),
+        0x60 => ("RTS", Implied, 6),
+        0xE9 => ("SBC", Immediate, 2),
+        0xE5 => ("SBC", ZeroPage, 3),
+        0xF5 => ("SBC", ZeroPageX, 4),
+        0xED => ("SBC", Absolute, 4),
+        0xFD => ("SBC", AbsoluteX, 4),
+        0xF9 => ("SBC", AbsoluteY, 4),
+        0xE1 => ("SBC", IndirectX, 6),
+        0xF1 => ("SBC", IndirectY, 5),
+        0x38 => ("SEC", Implied, 2),
+        0xF8 => ("SED", Implied, 2),
+        0x78 => ("SEI", Implied, 2),
+        0x85 => ("STA", ZeroPage, 3),
+        0x95 => ("STA", ZeroPageX, 4),
+        0x8D => ("STA", Absolute, 4),
+        0x9D => ("STA", AbsoluteX, 5),
+        0x99 => ("STA", AbsoluteY, 5),
+        0x81 => ("STA", IndirectX, 6),
+        0x91 => ("STA", IndirectY, 6),
+        0x86 => ("STX", ZeroPage, 3),
+        0x96 => ("STX", ZeroPageY, 4),
+        0x8E => ("STX", Absolute, 4),
+        0x84 => ("STY", ZeroPage, 3),
+        0x94 => ("STY", ZeroPageX, 4),
+        0x8C => ("STY", Absolute, 4),
+        0xAA => ("TAX", Implied, 2),
+        0xA8 => ("TAY", Implied, 2),
+        0xBA => ("TSX", Implied, 2),
+        0x8A => ("TXA", Implied, 2),
+        0x9A => ("TXS", Implied, 2),
+        0x98 => ("TYA", Implied, 2),
+        _ => return None,
+    };
+    return Some(op);
+}
+
+// reads that take a cycle more when indexing crosses a page
+const PAGE_PENALTY: [&str; 9] = [
+    "ADC", "AND", "CMP", "EOR", "LDA", "LDX", "LDY", "ORA", "SBC",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct State {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub sp: u8,
+    pub pc: u16,
+    pub cycles: u64,
+    pub memory: Vec<u8>,
+    pub written: Vec<u16>, // by the last instruction
+}
+
+impl State {
+    pub fn new(memory: Vec<u8>) -> Self {
+        assert_eq!(memory.len(), 0x10000);
+        return Self {
+            a: 0,
+            x: 0,
+            y: 0,
+            p: UNUSED | INTERRUPT,
+            sp: 0xFD,
+            pc: 0,
+            cycles: 0,
+            memory,
+            written: Vec::new(),
+        };
+    }
+
+    fn read(&self, addr: u16) -> u8 {
+        return self.memory[addr as usize];
+    }
+
+    // little endian, the high byte from the same page when `wrap` asks
+    fn read_word(&self, addr: u16, page_wrap: bool) -> u16 {
+        let high = match page_wrap {
+            true => (addr & 0xFF00) | (addr.wrapping_add(1) & 0x00FF),
+            false => addr.wrapping_add(1),
+        };
+        return u16::from_le_bytes([self.read(addr), self.read(high)]);
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        self.memory[addr as usize] = value;
+        self.written.push(addr);
+    }
+
+    fn push(&mut self, value: u8) {
+        self.write(0x0100 + self.sp as u16, value);
+        self.sp = self.sp.wrapping_sub(1);
+    }
+
+    fn pull(&mut self) -> u8 {
+        self.sp = self.sp.wrapping_add(1);
+        return self.read(0x0100 + self.sp as u16);
+    }
+
+    fn flag(&self, flag: u8) -> bool {
+        return self.p & flag != 0;
+    }
+
+    fn set_flag(&mut self, flag: u8, on: bool) {
+        match on {
+            true => self.p |= flag,
+            false => self.p &= !flag,
+        }
+    }
+
+    fn set_zn(&mut self, value: u8) {
+        self.set_flag(ZERO, value == 0);
+        self.set_flag(NEGATIVE, value & 0x80 != 0);
+    }
+
+    // the effective address, and whether indexing crossed a page
+    fn address(&self, mode: Mode) -> (u16, bool) {
+        let operand = self.pc.wrapping_add(1);
+        let byte = self.read(operand);
+        let word = self.read_word(operand, false);
+        let indexed = |base: u16, index: u8| {
+            let addr = base.wrapping_add(index as u16);
+            return (addr, addr & 0xFF00 != base & 0xFF00);
+        };
+        return match mode {
+            Mode::Immediate => (operand, false),
+            Mode::ZeroPage => (byte as u16, false),
+            Mode::ZeroPageX => (byte.wrapping_add(self.x) as u16, false),
+            Mode::ZeroPageY => (byte.wrapping_add(self.y) as u16, false),
+            Mode::Absolute => (word, false),
+            Mode::AbsoluteX => indexed(word, self.x),
+            Mode::AbsoluteY => indexed(word, self.y),
+            Mode::IndirectX => (
+                self.read_word(byte.wrapping_add(self.x) as u16, true),
+                false,
+            ),
+            Mode::IndirectY => indexed(self.read_word(byte as u16, true), self.y),
+            Mode::Indirect => (self.read_word(word, true), false),
+            Mode::Relative => {
+                let next = self.pc.wrapping_add(2);
+                let target = next.wrapping_add(byte as i8 as u16);
+                (target, target & 0xFF00 != next & 0xFF00)
+            }
+            Mode::Implied | Mode::Accumulator => (0, false),
+        };
+    }
+
+    fn add(&mut self, value: u8) {
+        let sum = self.a as u16 + value as u16 + self.flag(CARRY) as u16;
+        let result = sum as u8;
+        self.set_flag(CARRY, sum > 0xFF);
+        self.set_flag(OVERFLOW, (self.a ^ result) & (value ^ result) & 0x80 != 0);
+        self.a = result;
+        self.set_zn(result);
+    }
+
+    fn compare(&mut self, register: u8, value: u8) {
+        self.set_flag(CARRY, register >= value);
+        self.set_zn(register.wrapping_sub(value));
+    }
+
+    // the shifts and rotates, on A or memory
+    fn modify(&mut self, mode: Mode, addr: u16, f: impl Fn(u8, bool) -> (u8, bool)) {
+        let value = match mode {
+            Mode::Accumulator => self.a,
+            _ => self.read(addr),
+        };
+        let (result, carry) = f(value, self.flag(CARRY));
+        self.set_flag(CARRY, carry);
+        self.set_zn(result);
+        match mode {
+            Mode::Accumulator => self.a = result,
+            _ => self.write(addr, result),
+        }
+    }
+
+    // one instruction, or None for an opcode that isn't official
+    pub fn step(&mut self) -> Option<()> {
+        let (name, mode, cycles) = decode(self.read(self.pc))?;
+        self.written.clear();
+        let (addr, crossed) = self.address(mode);
+        // reads have no side effects here, so stores can read too
+        let value = self.read(addr);
+        let next = self.pc.wrapping_add(mode.size());
+        let mut pc = next;
+        self.cycles += cycles;
+        if crossed && PAGE_PENALTY.contains(&name) {
+            self.cycles += 1;
+        }
+        match name {
+            "ADC" => self.add(value),
+            "SBC" => self.add(!value),
+            "AND" => {
+                self.a &= value;
+                self.set_zn(self.a);
+            }
+            "ORA" => {
+                self.a |= value;
+                self.set_zn(self.a);
+            }
+            "EOR" => {
+                self.a ^= value;
+                self.set_zn(self.a);
+            }
+            "ASL" => self.modify(mode, addr, |v, _| (v << 1, v & 0x80 != 0)),
+            "LSR" => self.modify(mode, addr, |v, _| (v >> 1, v & 1 != 0)),
+            "ROL" => self.modify(mode, addr, |v, c| (v << 1 | c as u8, v & 0x80 != 0)),
+            "ROR" => self.modify(mode, addr, |v, c| (v >> 1 | (c as u8) << 7, v & 1 != 0)),
+            "BCC" | "BCS" | "BNE" | "BEQ" | "BPL" | "BMI" | "BVC" | "BVS" => {
+                let (flag, set) = match name {
+                    "BCC" => (CARRY, false),
+                    "BCS" => (CARRY, true),
+                    "BNE" => (ZERO, false),
+                    "BEQ" => (ZERO, true),
+                    "BPL" => (NEGATIVE, false),
+                    "BMI" => (NEGATIVE, true),
+                    "BVC" => (OVERFLOW, false),
+                    _ => (OVERFLOW, true),
+                };
+                if self.flag(flag) == set {
+                    self.cycles += 1 + crossed as u64;
+                    pc = addr;
+                }
+            }
+            "BIT" => {
+                self.set_flag(ZERO, self.a & value == 0);
+                self.set_flag(NEGATIVE, value & 0x80 != 0);
+                self.set_flag(OVERFLOW, value & 0x40 != 0);
+            }
+            "BRK" => {
+                let [low, high] = next.wrapping_add(1).to_le_bytes();
+                self.push(high);
+                self.push(low);
+                self.push(self.p | BREAK | UNUSED);
+                self.set_flag(INTERRUPT, true);
+                pc = self.read_word(IRQ_VECTOR, false);
+            }
+            "CLC" => self.set_flag(CARRY, false),
+            "CLD" => self.set_flag(DECIMAL, false),
+            "CLI" => self.set_flag(INTERRUPT, false),
+            "CLV" => self.set_flag(OVERFLOW, false),
+            "SEC" => self.set_flag(CARRY, true),
+            "SED" => self.set_flag(DECIMAL, true),
+            "SEI" => self.set_flag(INTERRUPT, true),
+            "CMP" => self.compare(self.a, value),
+            "CPX" => self.compare(self.x, value),
+            "CPY" => self.compare(self.y, value),
+            "DEC" | "INC" => {
+                let result = match name {
+                    "DEC" => value.wrapping_sub(1),
+                    _ => value.wrapping_add(1),
+                };
+                self.write(addr, result);
+                self.set_zn(result);
+            }
+            "DEX" => {
+                self.x = self.x.wrapping_sub(1);
+                self.set_zn(self.x);
+            }
+            "DEY" => {
+                self.y = self.y.wrapping_sub(1);
+                self.set_zn(self.y);
+            }
+            "INX" => {
+                self.x = self.x.wrapping_add(1);
+                self.set_zn(self.x);
+            }
+            "INY" => {
+                self.y = self.y.wrapping_add(1);
+                self.set_zn(self.y);
+            }
+            "JMP" => pc = addr,
+            "JSR" => {
+                let [low, high] = next.wrapping_sub(1).to_le_bytes();
+                self.push(high);
+                self.push(low);
+                pc = addr;
+            }
+            "RTS" => {
+                let low = self.pull();
+                let high = self.pull();
+                pc = u16::from_le_bytes([low, high]).wrapping_add(1);
+            }
+            "RTI" => {
+                self.p = self.pull();
+                let low = self.pull();
+                let high = self.pull();
+                pc = u16::from_le_bytes([low, high]);
+            }
+            "LDA" => {
+                self.a = value;
+                self.set_zn(self.a);
+            }
+            "LDX" => {
+                self.x = value;
+                self.set_zn(self.x);
+            }
+            "LDY" => {
+                self.y = value;
+                self.set_zn(self.y);
+            }
+            "NOP" => {}
+            "PHA" => self.push(self.a),
+            "PHP" => self.push(self.p | BREAK | UNUSED),
+            "PLA" => {
+                self.a = self.pull();
+                self.set_zn(self.a);
+            }
+            "PLP" => self.p = self.pull(),
+            "STA" => self.write(addr, self.a),
+            "STX" => self.write(addr, self.x),
+            "STY" => self.write(addr, self.y),
+            "TAX" => {
+                self.x = self.a;
+                self.set_zn(self.x);
+            }
+            "TAY" => {
+                self.y = self.a;
+                self.set_zn(self.y);
+            }
+            "TSX" => {
+                self.x = self.sp;
+                self.set_zn(self.x);
+            }
+            "TXA" => {
+                self.a = self.x;
+                self.set_zn(self.a);
+            }
+            "TXS" => self.sp = self.x,
+            "TYA" => {
+                self.a = self.y;
+                self.set_zn(self.a);
+            }
+            _ => unreachable!("{} is in the table", name),
+        }
+        self.pc = pc;
+        return Some(());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_table_has_every_official_opcode() {
+        assert_eq!((0..=255).filter_map(decode).count(), 151);
+    }
+
+    #[test]
+    fn test_a_short_program() {
+        // LDA #$50, ADC #$50, CMP #$A1, BNE +1, BRK, TAX
+        let mut memory = vec![0; 0x10000];
+        memory[0x8000..0x800A]
+            .copy_from_slice(&[0xA9, 0x50, 0x69, 0x50, 0xC9, 0xA1, 0xD0, 0x01, 0x00, 0xAA]);
+        let mut state = State::new(memory);
+        state.pc = 0x8000;
+        for _ in 0..5 {
+            state.step().unwrap();
+        }
+        assert_eq!((state.a, state.x, state.pc), (0xA0, 0xA0, 0x800A));
+        // overflow from the add, carry clear and negative from the compare
+        assert_eq!(state.p & (OVERFLOW | CARRY | NEGATIVE), OVERFLOW | NEGATIVE);
+        assert_eq!(state.cycles, 2 + 2 + 2 + 3 + 2);
+        memory = state.memory.clone();
+        memory[0x800A] = 0x02;
+        assert_eq!(State { memory, ..state }.step(), None);
+    }
+}
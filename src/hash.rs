@@ -0,0 +1,229 @@
+/* Checksums
+
+   CRC32 (the zip/PNG polynomial) and SHA-1, used to identify ROM dumps and
+   to verify archive entries, plus the Adler-32 that ends a zlib stream.
+   All are small enough that pulling in a crate for them isn't worth it.
+*/
+
+const CRC32_POLYNOMIAL: u32 = 0xEDB8_8320;
+
+lazy_static! {
+    static ref CRC32_TABLE: [u32; 256] = {
+        let mut table = [0u32; 256];
+        for (i, entry) in table.iter_mut().enumerate() {
+            let mut crc = i as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 {
+                    (crc >> 1) ^ CRC32_POLYNOMIAL
+                } else {
+                    crc >> 1
+                };
+            }
+            *entry = crc;
+        }
+        table
+    };
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Crc32 {
+    crc: u32,
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl Crc32 {
+    pub fn new() -> Self {
+        return Self { crc: 0xFFFF_FFFF };
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            let index = ((self.crc ^ *byte as u32) & 0xFF) as usize;
+            self.crc = (self.crc >> 8) ^ CRC32_TABLE[index];
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        return !self.crc;
+    }
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    return crc.finish();
+}
+
+#[derive(Debug, Clone)]
+pub struct Sha1 {
+    state: [u32; 5],
+    block: Vec<u8>,
+    length: u64,
+}
+
+impl Default for Sha1 {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl Sha1 {
+    pub fn new() -> Self {
+        return Self {
+            state: [
+                0x6745_2301,
+                0xEFCD_AB89,
+                0x98BA_DCFE,
+                0x1032_5476,
+                0xC3D2_E1F0,
+            ],
+            block: Vec::with_capacity(64),
+            length: 0,
+        };
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.length += data.len() as u64;
+        while !data.is_empty() {
+            let take = (64 - self.block.len()).min(data.len());
+            self.block.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.block.len() == 64 {
+                let block: [u8; 64] = self.block[..].try_into().unwrap();
+                self.compress(&block);
+                self.block.clear();
+            }
+        }
+    }
+
+    pub fn finish(mut self) -> [u8; 20] {
+        let bit_length = self.length * 8;
+        let mut padding = vec![0x80];
+        let padded = (self.block.len() + 1) % 64;
+        let zeros = if padded <= 56 {
+            56 - padded
+        } else {
+            120 - padded
+        };
+        padding.extend(vec![0; zeros]);
+        padding.extend_from_slice(&bit_length.to_be_bytes());
+        let length = self.length;
+        self.update(&padding);
+        self.length = length;
+
+        let mut digest = [0u8; 20];
+        for (chunk, word) in digest.chunks_mut(4).zip(self.state.iter()) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        return digest;
+    }
+
+    fn compress(&mut self, block: &[u8; 64]) {
+        let mut w = [0u32; 80];
+        for (i, chunk) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes(chunk.try_into().unwrap());
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = self.state;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ED9_EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+                _ => (b ^ c ^ d, 0xCA62_C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d, e]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+}
+
+pub fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut sha = Sha1::new();
+    sha.update(data);
+    return sha.finish();
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    return b << 16 | a;
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    return bytes.iter().map(|b| format!("{:02x}", b)).collect();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+
+        let mut crc = Crc32::new();
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(crc.finish(), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_adler32() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn test_sha1() {
+        assert_eq!(
+            to_hex(&sha1(b"")),
+            "da39a3ee5e6b4b0d3255bfef95601890afd80709"
+        );
+        assert_eq!(
+            to_hex(&sha1(b"abc")),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        // two blocks, and padding that spills into an extra block
+        assert_eq!(
+            to_hex(&sha1(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            )),
+            "84983e441c3bd26ebaae4aa1f95129e5e54670f1"
+        );
+
+        let mut sha = Sha1::new();
+        for _ in 0..1000 {
+            sha.update(&[b'a'; 1000]);
+        }
+        assert_eq!(
+            to_hex(&sha.finish()),
+            "34aa973cd4c4daa4f61eeb2bdbad27316534016f"
+        );
+    }
+}
@@ -0,0 +1,154 @@
+use crate::video::{png, Frame};
+use std::path::Path;
+
+const ADDRESSES: usize = 0x10000;
+
+/* Memory heatmap
+
+   How often the CPU read, wrote and ran each of the 64KB of addresses over
+   a session, `--heatmap FILE`, to find RAM a game never touches, the I/O
+   registers it polls hardest and writes that land where they shouldn't.
+   Addresses are counted as the CPU asked for them, so a mirror of RAM or
+   the PPU registers counts apart from what it mirrors. The opcode byte of
+   each instruction counts as executed and read both, its operands as read.
+
+   A file ending in .png gets a 256x256 picture with a pixel an address,
+   $xx00 to $xxFF along each row from $00xx at the top: writes in red,
+   executes in green and reads in blue, each brighter the more often on a
+   log scale against the busiest address. Anything else gets CSV, an
+   address a line with its three counts.
+*/
+
+#[derive(Debug, Clone)]
+pub struct Heatmap {
+    reads: Vec<u64>,
+    writes: Vec<u64>,
+    executes: Vec<u64>,
+}
+
+impl Default for Heatmap {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl Heatmap {
+    pub fn new() -> Self {
+        return Self {
+            reads: vec![0; ADDRESSES],
+            writes: vec![0; ADDRESSES],
+            executes: vec![0; ADDRESSES],
+        };
+    }
+
+    pub fn read(&mut self, addr: u16) {
+        self.reads[addr as usize] += 1;
+    }
+
+    pub fn write(&mut self, addr: u16) {
+        self.writes[addr as usize] += 1;
+    }
+
+    pub fn execute(&mut self, addr: u16) {
+        self.executes[addr as usize] += 1;
+    }
+
+    // reads, writes and executes
+    pub fn counts(&self, addr: u16) -> (u64, u64, u64) {
+        let i = addr as usize;
+        return (self.reads[i], self.writes[i], self.executes[i]);
+    }
+
+    pub fn csv(&self) -> String {
+        let mut text = String::from("address,reads,writes,executes\n");
+        for addr in 0..ADDRESSES {
+            text += &format!(
+                "${:04X},{},{},{}\n",
+                addr, self.reads[addr], self.writes[addr], self.executes[addr]
+            );
+        }
+        return text;
+    }
+
+    pub fn image(&self) -> Frame {
+        let mut frame = Frame::new(256, 256);
+        let scales = [&self.writes, &self.executes, &self.reads]
+            .map(|counts| ((*counts.iter().max().unwrap() + 1) as f64).ln());
+        for addr in 0..ADDRESSES {
+            let counts = [self.writes[addr], self.executes[addr], self.reads[addr]];
+            let pixel = &mut frame.pixels[addr * 4..addr * 4 + 3];
+            for channel in 0..3 {
+                if counts[channel] > 0 {
+                    let level = ((counts[channel] + 1) as f64).ln() / scales[channel];
+                    pixel[channel] = (level * 255.0).round() as u8;
+                }
+            }
+        }
+        return frame;
+    }
+
+    // PNG or CSV by the file's extension
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        if path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("png"))
+        {
+            return png::save(&self.image(), path);
+        }
+        return std::fs::write(path, self.csv()).map_err(|e| format!("{}: {}", path.display(), e));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+    use crate::cartridge::Rom;
+    use crate::nes::Nes;
+
+    #[test]
+    fn test_counts_a_running_game() {
+        // reset: LDA $0010, STA $2006, JMP reset
+        let mut raw = test_rom(0, 1, 1);
+        raw[16..25].copy_from_slice(&[0xAD, 0x10, 0x00, 0x8D, 0x06, 0x20, 0x4C, 0x00, 0x80]);
+        raw[16 + 0x3FFC..16 + 0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        let mut nes = Nes::new(Rom::new(&raw).unwrap()).unwrap();
+        nes.cpu.bus.heatmap = Some(Box::new(Heatmap::new()));
+        for _ in 0..30 {
+            nes.cpu.step();
+        }
+        let heatmap = nes.cpu.bus.heatmap.take().unwrap();
+        assert_eq!(heatmap.counts(0x8000), (10, 0, 10));
+        assert_eq!(heatmap.counts(0x8001), (10, 0, 0));
+        assert_eq!(heatmap.counts(0x0010), (10, 0, 0));
+        assert_eq!(heatmap.counts(0x2006), (0, 10, 0));
+        assert_eq!(heatmap.counts(0x0011), (0, 0, 0));
+
+        let csv = heatmap.csv();
+        assert_eq!(csv.lines().count(), 1 + 0x10000);
+        assert!(csv.contains("\n$2006,0,10,0\n"));
+        let image = heatmap.image();
+        assert_eq!(image.pixel(0x06, 0x20), [255, 0, 0, 255]);
+        assert_eq!(image.pixel(0x00, 0x80), [0, 255, 255, 255]);
+        assert_eq!(image.pixel(0x11, 0x00), [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_indexed_operands_counted_once() {
+        // reset: LDA $0010,X, LDA ($20),Y, JMP reset
+        let mut raw = test_rom(0, 1, 1);
+        raw[16..24].copy_from_slice(&[0xBD, 0x10, 0x00, 0xB1, 0x20, 0x4C, 0x00, 0x80]);
+        raw[16 + 0x3FFC..16 + 0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        let mut nes = Nes::new(Rom::new(&raw).unwrap()).unwrap();
+        nes.cpu.bus.heatmap = Some(Box::new(Heatmap::new()));
+        for _ in 0..30 {
+            nes.cpu.step();
+        }
+        let heatmap = nes.cpu.bus.heatmap.take().unwrap();
+        assert_eq!(heatmap.counts(0x8001), (10, 0, 0));
+        assert_eq!(heatmap.counts(0x8004), (10, 0, 0));
+        assert_eq!(heatmap.counts(0x0010), (10, 0, 0));
+        assert_eq!(heatmap.counts(0x0020), (10, 0, 0));
+        assert_eq!(heatmap.counts(0x0021), (10, 0, 0));
+    }
+}
@@ -0,0 +1,531 @@
+use crate::input::joypad::Button;
+use crate::input::keyboard::{self, FamilyKeyboard};
+use crate::input::{ControllerPorts, PLAYERS, PORT_1};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::Path;
+
+/* Input bindings
+
+   Maps whatever the frontend calls its physical inputs, key names like
+   "Z" or "Return", to controller buttons. An input can drive several
+   buttons and a button can have any number of inputs; it is pressed while
+   at least one of them is held.
+
+   The bindings file has one line per button, listing its inputs:
+
+    # player 1
+    p1.a = X, K
+    p1.start = Return
+    p1.turbo_a = S
+
+   A turbo binding presses and releases its button in a fixed pattern of
+   frames for as long as it is held, starting pressed. The pattern counts
+   the frames the mapper is told about, not host time, so it comes out the
+   same on every run and what reaches the controller, and so any movie, is
+   plain button presses.
+
+   With a Family BASIC keyboard connected the frontend can switch the
+   mapper into key capture, for typing: held inputs that name a keyboard
+   key (see `keyboard::MATRIX`) then go to the keyboard and stop pressing
+   the buttons they are bound to, while gamepad inputs keep working.
+
+   The Famicom microphone on the second controller is bound as p2.mic; it
+   picks up full volume while one of its inputs is held.
+
+   Players are numbered from 1, p3 and p4 need a Four Score to have a
+   controller to press. Buttons are named as in `Button::name`. Key names
+   are the frontend's and may not contain commas; anything after a # is a
+   comment.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Binding {
+    pub player: usize,
+    pub button: Button,
+    pub turbo: bool,
+}
+
+// frames pressed then frames released while a turbo input is held
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TurboRate {
+    pub on: u32,
+    pub off: u32,
+}
+
+impl Default for TurboRate {
+    // 15 presses a second
+    fn default() -> Self {
+        return Self { on: 2, off: 2 };
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Bindings {
+    inputs: BTreeMap<String, Vec<Binding>>,
+    microphone: BTreeSet<String>,
+}
+
+impl Bindings {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    // arrows for the d-pad, X/Z for A/B, Return/Shift for Start/Select on port 1
+    pub fn default_keyboard() -> Self {
+        let mut bindings = Self::new();
+        for (key, button) in [
+            ("X", Button::A),
+            ("Z", Button::B),
+            ("RShift", Button::Select),
+            ("Return", Button::Start),
+            ("Up", Button::Up),
+            ("Down", Button::Down),
+            ("Left", Button::Left),
+            ("Right", Button::Right),
+        ] {
+            bindings.bind(key, PORT_1, button);
+        }
+        return bindings;
+    }
+
+    pub fn bind(&mut self, input: &str, player: usize, button: Button) {
+        self.insert(input, Binding::new(player, button, false));
+    }
+
+    pub fn bind_turbo(&mut self, input: &str, player: usize, button: Button) {
+        self.insert(input, Binding::new(player, button, true));
+    }
+
+    fn insert(&mut self, input: &str, binding: Binding) {
+        let targets = self.inputs.entry(input.to_string()).or_default();
+        if !targets.contains(&binding) {
+            targets.push(binding);
+        }
+    }
+
+    pub fn bind_microphone(&mut self, input: &str) {
+        self.microphone.insert(input.to_string());
+    }
+
+    pub fn unbind(&mut self, input: &str) {
+        self.inputs.remove(input);
+        self.microphone.remove(input);
+    }
+
+    // removes every input of one button, turbo ones too, before rebinding it from scratch
+    pub fn clear_button(&mut self, player: usize, button: Button) {
+        for targets in self.inputs.values_mut() {
+            targets.retain(|target| target.player != player || target.button != button);
+        }
+        self.inputs.retain(|_, targets| !targets.is_empty());
+    }
+
+    // `other`'s buttons replace these ones' inputs, the rest stay as they are
+    pub fn overlay(&mut self, other: &Bindings) {
+        for binding in other.inputs.values().flatten() {
+            for targets in self.inputs.values_mut() {
+                targets.retain(|target| target != binding);
+            }
+        }
+        self.inputs.retain(|_, targets| !targets.is_empty());
+        for (input, targets) in &other.inputs {
+            for &binding in targets {
+                self.insert(input, binding);
+            }
+        }
+        if !other.microphone.is_empty() {
+            self.microphone = other.microphone.clone();
+        }
+    }
+
+    pub fn targets(&self, input: &str) -> &[Binding] {
+        return self.inputs.get(input).map(|t| t.as_slice()).unwrap_or(&[]);
+    }
+
+    pub fn inputs_for(&self, player: usize, button: Button) -> Vec<&str> {
+        return self.inputs_for_binding(Binding::new(player, button, false));
+    }
+
+    pub fn turbo_inputs_for(&self, player: usize, button: Button) -> Vec<&str> {
+        return self.inputs_for_binding(Binding::new(player, button, true));
+    }
+
+    pub fn microphone_inputs(&self) -> Vec<&str> {
+        return self.microphone.iter().map(|input| input.as_str()).collect();
+    }
+
+    fn inputs_for_binding(&self, binding: Binding) -> Vec<&str> {
+        return self
+            .inputs
+            .iter()
+            .filter(|(_, targets)| targets.contains(&binding))
+            .map(|(input, _)| input.as_str())
+            .collect();
+    }
+
+    pub fn parse(text: &str) -> Result<Bindings, String> {
+        let mut bindings = Bindings::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (target, inputs) =
+                parse_line(line).map_err(|e| format!("line {}: {}", number + 1, e))?;
+            for input in inputs {
+                match target {
+                    Target::Button(binding) => bindings.insert(input, binding),
+                    Target::Microphone => bindings.bind_microphone(input),
+                }
+            }
+        }
+        return Ok(bindings);
+    }
+
+    pub fn to_config(&self) -> String {
+        let mut text = String::new();
+        for player in 0..PLAYERS {
+            for turbo in [false, true] {
+                for button in Button::ALL {
+                    let binding = Binding::new(player, button, turbo);
+                    let inputs = self.inputs_for_binding(binding);
+                    if !inputs.is_empty() {
+                        text += &format!(
+                            "p{}.{} = {}\n",
+                            player + 1,
+                            binding.name(),
+                            inputs.join(", ")
+                        );
+                    }
+                }
+            }
+        }
+        if !self.microphone.is_empty() {
+            text += &format!("p2.mic = {}\n", self.microphone_inputs().join(", "));
+        }
+        return text;
+    }
+
+    pub fn load(path: &Path) -> Result<Bindings, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        return Bindings::parse(&text).map_err(|e| format!("{}: {}", path.display(), e));
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        return fs::write(path, self.to_config()).map_err(|e| format!("{}: {}", path.display(), e));
+    }
+}
+
+impl Binding {
+    pub fn new(player: usize, button: Button, turbo: bool) -> Self {
+        return Self {
+            player,
+            button,
+            turbo,
+        };
+    }
+
+    // the button part of a bindings file line, "a" or "turbo_a"
+    fn name(&self) -> String {
+        if self.turbo {
+            return format!("turbo_{}", self.button.name());
+        }
+        return self.button.name().to_string();
+    }
+}
+
+// what the left-hand side of a bindings file line names
+#[derive(Debug, Clone, Copy)]
+enum Target {
+    Button(Binding),
+    Microphone,
+}
+
+fn parse_line(line: &str) -> Result<(Target, Vec<&str>), String> {
+    let (target, inputs) = line
+        .split_once('=')
+        .ok_or_else(|| format!("expected p<player>.<button> = <inputs>, got {}", line))?;
+    let inputs = inputs
+        .split(',')
+        .map(|input| input.trim())
+        .filter(|input| !input.is_empty())
+        .collect();
+    if target.trim() == "p2.mic" {
+        return Ok((Target::Microphone, inputs));
+    }
+    let (player, button) = target
+        .trim()
+        .split_once('.')
+        .ok_or_else(|| format!("expected p<player>.<button>, got {}", target.trim()))?;
+    let player = match player
+        .strip_prefix('p')
+        .and_then(|n| n.parse::<usize>().ok())
+    {
+        Some(n) if (1..=PLAYERS).contains(&n) => n - 1,
+        _ => return Err(format!("unknown player {}", player)),
+    };
+    let binding = match button.strip_prefix("turbo_") {
+        Some(button) => Binding::new(player, Button::parse(button)?, true),
+        None => Binding::new(player, Button::parse(button)?, false),
+    };
+    return Ok((Target::Button(binding), inputs));
+}
+
+// tracks which inputs are held and turns them into button states
+#[derive(Debug, Clone, Default)]
+pub struct InputMapper {
+    pub bindings: Bindings,
+    pub turbo: TurboRate,
+    pub key_capture: bool,
+    held: HashMap<String, u64>, // input, frame it was pressed on
+    frame: u64,
+}
+
+impl InputMapper {
+    pub fn new(bindings: Bindings) -> Self {
+        return Self {
+            bindings,
+            turbo: TurboRate::default(),
+            key_capture: false,
+            held: HashMap::new(),
+            frame: 0,
+        };
+    }
+
+    // the frontend's key down / key up events
+    pub fn set_held(&mut self, input: &str, held: bool) {
+        if !held {
+            self.held.remove(input);
+        } else if !self.held.contains_key(input) {
+            self.held.insert(input.to_string(), self.frame);
+        }
+    }
+
+    // e.g. when the window loses focus and key ups would go missing
+    pub fn release_all(&mut self) {
+        self.held.clear();
+    }
+
+    // advances the turbo pattern, once per emulated frame
+    pub fn end_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    // `player` is 0-3
+    pub fn buttons(&self, player: usize) -> u8 {
+        let period = (self.turbo.on + self.turbo.off).max(1) as u64;
+        let mut buttons = 0;
+        for (input, pressed) in &self.held {
+            if self.key_capture && keyboard::is_key(input) {
+                continue;
+            }
+            for target in self.bindings.targets(input) {
+                if target.player != player {
+                    continue;
+                }
+                if !target.turbo || (self.frame - pressed) % period < self.turbo.on as u64 {
+                    buttons |= target.button.bit();
+                }
+            }
+        }
+        return buttons;
+    }
+
+    // sets the controllers of every player and the keyboard, other devices are left alone
+    pub fn apply(&self, ports: &mut ControllerPorts) {
+        for player in 0..PLAYERS {
+            ports.set_buttons(player, self.buttons(player));
+        }
+        // without a bound input the level is left to the frontend, e.g. a real microphone
+        if !self.bindings.microphone.is_empty() {
+            let held = self.bindings.microphone.iter().any(|input| {
+                self.held.contains_key(input) && !(self.key_capture && keyboard::is_key(input))
+            });
+            ports.set_microphone(if held { 1.0 } else { 0.0 });
+        }
+        if let Some(keyboard) = ports.expansion_mut::<FamilyKeyboard>() {
+            keyboard.release_all();
+            if self.key_capture {
+                for input in self.held.keys() {
+                    keyboard.set_key(input, true);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::input::four_score::FourScore;
+    use crate::input::joypad::Joypad;
+    use crate::input::{ControllerSetup, PORT_2};
+
+    #[test]
+    fn test_parse_and_write_config() {
+        let text = "# player 1\np1.a = X, K\n\np1.start = Return # pause\np2.left = J\n";
+        let bindings = Bindings::parse(text).unwrap();
+        assert_eq!(bindings.inputs_for(PORT_1, Button::A), vec!["K", "X"]);
+        assert_eq!(
+            bindings.targets("J"),
+            &[Binding::new(PORT_2, Button::Left, false)]
+        );
+        let written = bindings.to_config();
+        assert_eq!(written, "p1.a = K, X\np1.start = Return\np2.left = J\n");
+        assert_eq!(Bindings::parse(&written).unwrap(), bindings);
+
+        assert_eq!(
+            Bindings::parse("p1.a = X\np5.a = Y"),
+            Err("line 2: unknown player p5".to_string())
+        );
+        assert!(Bindings::parse("p1.turbo = X").is_err());
+        assert!(Bindings::parse("p1.a X").is_err());
+    }
+
+    #[test]
+    fn test_any_bound_input_presses_the_button() {
+        let mut bindings = Bindings::default_keyboard();
+        bindings.bind("K", PORT_1, Button::A);
+        let mut mapper = InputMapper::new(bindings);
+        mapper.set_held("X", true);
+        mapper.set_held("K", true);
+        mapper.set_held("Left", true);
+        assert_eq!(mapper.buttons(PORT_1), Button::A.bit() | Button::Left.bit());
+        mapper.set_held("X", false);
+        assert_eq!(mapper.buttons(PORT_1), Button::A.bit() | Button::Left.bit());
+        mapper.set_held("K", false);
+        assert_eq!(mapper.buttons(PORT_1), Button::Left.bit());
+        assert_eq!(mapper.buttons(PORT_2), 0);
+
+        let mut ports = ControllerPorts::new();
+        ports.connect(PORT_1, Box::new(Joypad::new()));
+        mapper.apply(&mut ports);
+        assert!(ports.joypad_mut(PORT_1).unwrap().is_pressed(Button::Left));
+        mapper.release_all();
+        mapper.apply(&mut ports);
+        assert_eq!(ports.joypad_mut(PORT_1).unwrap().buttons(), 0);
+    }
+
+    #[test]
+    fn test_four_players() {
+        let bindings = Bindings::parse("p3.a = I\np4.turbo_start = O").unwrap();
+        assert_eq!(bindings.targets("I"), &[Binding::new(2, Button::A, false)]);
+        assert_eq!(bindings.to_config(), "p3.a = I\np4.turbo_start = O\n");
+
+        let mut mapper = InputMapper::new(bindings);
+        mapper.set_held("I", true);
+        mapper.set_held("O", true);
+        let mut ports = ControllerPorts::new();
+        ports.set_up(ControllerSetup::FourScore);
+        mapper.apply(&mut ports);
+        let port_1 = ports.device_mut::<FourScore>(PORT_1).unwrap();
+        assert_eq!(port_1.buttons(1), Button::A.bit());
+        let port_2 = ports.device_mut::<FourScore>(PORT_2).unwrap();
+        assert_eq!(port_2.buttons(1), Button::Start.bit());
+    }
+
+    #[test]
+    fn test_key_capture() {
+        let mut bindings = Bindings::default_keyboard();
+        bindings.bind("pad1.a", PORT_1, Button::A);
+        let mut mapper = InputMapper::new(bindings);
+        let mut ports = ControllerPorts::new();
+        ports.set_up(ControllerSetup::Standard);
+        ports.connect_expansion(Box::new(FamilyKeyboard::new()));
+        mapper.set_held("Z", true);
+        mapper.set_held("pad1.a", true);
+
+        mapper.apply(&mut ports);
+        assert!(!ports
+            .expansion_mut::<FamilyKeyboard>()
+            .unwrap()
+            .is_held("Z"));
+        assert_eq!(ports.joypad_mut(PORT_1).unwrap().buttons(), 0b11);
+
+        mapper.key_capture = true;
+        mapper.apply(&mut ports);
+        assert!(ports
+            .expansion_mut::<FamilyKeyboard>()
+            .unwrap()
+            .is_held("Z"));
+        assert_eq!(ports.joypad_mut(PORT_1).unwrap().buttons(), Button::A.bit());
+    }
+
+    #[test]
+    fn test_microphone_binding() {
+        let bindings = Bindings::parse("p1.a = X\np2.mic = M").unwrap();
+        assert_eq!(bindings.microphone_inputs(), vec!["M"]);
+        assert_eq!(bindings.to_config(), "p1.a = X\np2.mic = M\n");
+        assert!(Bindings::parse("p1.mic = M").is_err());
+
+        let mut mapper = InputMapper::new(bindings);
+        let mut ports = ControllerPorts::new();
+        mapper.set_held("M", true);
+        mapper.apply(&mut ports);
+        assert_eq!(ports.microphone(), 1.0);
+        mapper.set_held("M", false);
+        mapper.apply(&mut ports);
+        assert_eq!(ports.microphone(), 0.0);
+    }
+
+    #[test]
+    fn test_rebinding() {
+        let mut bindings = Bindings::default_keyboard();
+        bindings.clear_button(PORT_1, Button::A);
+        assert!(bindings.targets("X").is_empty());
+        bindings.bind("Space", PORT_1, Button::A);
+        bindings.bind("Space", PORT_1, Button::A);
+        assert_eq!(bindings.inputs_for(PORT_1, Button::A), vec!["Space"]);
+        bindings.unbind("Space");
+        assert!(bindings.inputs_for(PORT_1, Button::A).is_empty());
+
+        // an overlay only touches the buttons it binds
+        let mut bindings = Bindings::default_keyboard();
+        bindings.overlay(&Bindings::parse("p1.a = Space\np1.turbo_b = A").unwrap());
+        assert_eq!(bindings.inputs_for(PORT_1, Button::A), vec!["Space"]);
+        assert_eq!(bindings.inputs_for(PORT_1, Button::B), vec!["Z"]);
+        assert_eq!(bindings.turbo_inputs_for(PORT_1, Button::B), vec!["A"]);
+        assert!(bindings.targets("X").is_empty());
+    }
+
+    #[test]
+    fn test_turbo() {
+        let mut bindings = Bindings::parse("p1.a = X\np1.turbo_a = S\np2.turbo_b = D").unwrap();
+        assert_eq!(bindings.turbo_inputs_for(PORT_1, Button::A), vec!["S"]);
+        assert_eq!(
+            bindings.to_config(),
+            "p1.a = X\np1.turbo_a = S\np2.turbo_b = D\n"
+        );
+
+        let mut mapper = InputMapper::new(bindings.clone());
+        mapper.turbo = TurboRate { on: 1, off: 2 };
+        mapper.end_frame();
+        mapper.set_held("S", true);
+        let mut pattern = Vec::new();
+        for _ in 0..6 {
+            pattern.push(mapper.buttons(PORT_1));
+            mapper.end_frame();
+        }
+        assert_eq!(pattern, vec![1, 0, 0, 1, 0, 0]);
+
+        // holding the plain button as well keeps it down
+        mapper.set_held("X", true);
+        assert_eq!(mapper.buttons(PORT_1), Button::A.bit());
+        mapper.end_frame();
+        assert_eq!(mapper.buttons(PORT_1), Button::A.bit());
+
+        bindings.clear_button(PORT_2, Button::B);
+        assert!(bindings.turbo_inputs_for(PORT_2, Button::B).is_empty());
+    }
+
+    #[test]
+    fn test_load_and_save() {
+        let path = std::env::temp_dir().join(format!("rustynes-keys-{}.cfg", std::process::id()));
+        let bindings = Bindings::default_keyboard();
+        bindings.save(&path).unwrap();
+        assert_eq!(Bindings::load(&path).unwrap(), bindings);
+        std::fs::remove_file(&path).unwrap();
+        assert!(Bindings::load(&path).is_err());
+    }
+}
@@ -0,0 +1,135 @@
+use crate::error::StateError;
+use crate::input::{InputDevice, PORT_1};
+use crate::savestate::{StateReader, StateWriter};
+
+// the signature byte each port ends its report with, in read order from bit 0
+const SIGNATURE_PORT_1: u8 = 0b0000_1000;
+const SIGNATURE_PORT_2: u8 = 0b0000_0100;
+
+/* Four Score
+
+   A four player adapter: controllers 1 and 3 plug into the side of it
+   that sits on port 1, controllers 2 and 4 into the port 2 side. With its
+   switch on four players, each port returns a 24 bit report per strobe
+   instead of 8:
+
+    reads 1-8    the first controller, in the standard button order
+    reads 9-16   the second controller on that port
+    reads 17-24  a signature, 00010000 on port 1 and 00100000 on port 2
+
+   after which it returns 1 like a standard controller. Games check the
+   signature to tell the adapter from two plain controllers. While the
+   strobe is high every read returns the first controller's A button.
+
+   Each port is its own device here, holding the two controllers plugged
+   into that side.
+*/
+
+#[derive(Debug, Clone)]
+pub struct FourScore {
+    buttons: [u8; 2],
+    signature: u8,
+    strobe: bool,
+    shift: u32,
+}
+
+impl FourScore {
+    // the half of the adapter that connects to `port`
+    pub fn new(port: usize) -> Self {
+        let signature = if port == PORT_1 {
+            SIGNATURE_PORT_1
+        } else {
+            SIGNATURE_PORT_2
+        };
+        return Self {
+            buttons: [0; 2],
+            signature,
+            strobe: false,
+            shift: 0,
+        };
+    }
+
+    // `slot` 0 is controller 1 or 2, slot 1 is controller 3 or 4
+    pub fn set_buttons(&mut self, slot: usize, buttons: u8) {
+        self.buttons[slot] = buttons;
+    }
+
+    pub fn buttons(&self, slot: usize) -> u8 {
+        return self.buttons[slot];
+    }
+
+    fn report(&self) -> u32 {
+        return self.buttons[0] as u32
+            | (self.buttons[1] as u32) << 8
+            | (self.signature as u32) << 16
+            | 0xFF00_0000;
+    }
+}
+
+impl InputDevice for FourScore {
+    fn write(&mut self, data: u8) {
+        self.strobe = data & 1 != 0;
+        if self.strobe {
+            self.shift = self.report();
+        }
+    }
+
+    fn read(&mut self) -> u8 {
+        if self.strobe {
+            return self.buttons[0] & 1;
+        }
+        let bit = (self.shift & 1) as u8;
+        self.shift = (self.shift >> 1) | 0x8000_0000;
+        return bit;
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.strobe);
+        writer.write_u32(self.shift);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.strobe = reader.read_bool()?;
+        self.shift = reader.read_u32()?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::input::joypad::Button;
+    use crate::input::PORT_2;
+
+    fn read_report(device: &mut FourScore) -> Vec<u8> {
+        device.write(1);
+        device.write(0);
+        return (0..26).map(|_| device.read()).collect();
+    }
+
+    #[test]
+    fn test_report_layout() {
+        let mut port_1 = FourScore::new(PORT_1);
+        port_1.set_buttons(0, Button::A.bit());
+        port_1.set_buttons(1, Button::Right.bit());
+        let report = read_report(&mut port_1);
+        assert_eq!(report[..8], [1, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(report[8..16], [0, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(report[16..24], [0, 0, 0, 1, 0, 0, 0, 0]);
+        assert_eq!(report[24..], [1, 1]);
+
+        let mut port_2 = FourScore::new(PORT_2);
+        assert_eq!(read_report(&mut port_2)[16..24], [0, 0, 1, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_strobe_high_returns_first_a() {
+        let mut device = FourScore::new(PORT_1);
+        device.set_buttons(1, Button::A.bit());
+        device.write(1);
+        assert_eq!(device.read(), 0);
+        device.set_buttons(0, Button::A.bit());
+        assert_eq!(device.read(), 1);
+        assert_eq!(device.buttons(1), Button::A.bit());
+    }
+}
@@ -0,0 +1,345 @@
+use crate::input::bindings::{Bindings, InputMapper};
+use crate::input::joypad::Button;
+use crate::input::{PORT_1, PORT_2};
+
+pub const DEFAULT_DEAD_ZONE: f32 = 0.35;
+
+/* Gamepads
+
+   The backend (SDL's game controller API in `frontend::sdl`, a browser's
+   Gamepad API) only has to translate its events into `GamepadEvent`s;
+   everything else is handled here, the same way for every backend.
+
+   Each connected pad is assigned a player slot, the first free one when
+   it is plugged in, and keeps it until it is unplugged, so a pad that
+   drops out and comes back doesn't swap players around. Its inputs then
+   reach the bindings as "pad<player>.<name>", like "pad1.south" or
+   "pad2.left", and are bound like keys:
+
+    p1.a = X, pad1.south
+    p1.left = Left, pad1.left
+
+   The left stick and the d-pad both produce the up/down/left/right
+   inputs. Stick movement inside the dead zone counts as centred.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GamepadButton {
+    South, // A on an Xbox pad, cross on a PlayStation one
+    East,
+    West,
+    North,
+    Select,
+    Start,
+    LeftShoulder,
+    RightShoulder,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+impl GamepadButton {
+    pub fn name(self) -> &'static str {
+        match self {
+            GamepadButton::South => return "south",
+            GamepadButton::East => return "east",
+            GamepadButton::West => return "west",
+            GamepadButton::North => return "north",
+            GamepadButton::Select => return "select",
+            GamepadButton::Start => return "start",
+            GamepadButton::LeftShoulder => return "l1",
+            GamepadButton::RightShoulder => return "r1",
+            GamepadButton::DPadUp => return "up",
+            GamepadButton::DPadDown => return "down",
+            GamepadButton::DPadLeft => return "left",
+            GamepadButton::DPadRight => return "right",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Axis {
+    LeftStickX, // -1.0 left to 1.0 right
+    LeftStickY, // -1.0 up to 1.0 down
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GamepadEvent {
+    Connected(usize, String), // backend's device id, device name
+    Disconnected(usize),
+    Button(usize, GamepadButton, bool),
+    Axis(usize, Axis, f32),
+}
+
+#[derive(Debug, Clone)]
+struct Pad {
+    id: usize,
+    name: String,
+    stick: (f32, f32),
+    dpad: [bool; 4], // up, down, left, right from the d-pad buttons
+}
+
+#[derive(Debug, Clone)]
+pub struct Gamepads {
+    slots: Vec<Option<Pad>>, // one per player
+    pub dead_zone: f32,
+}
+
+impl Gamepads {
+    pub fn new(players: usize) -> Self {
+        return Self {
+            slots: vec![None; players],
+            dead_zone: DEFAULT_DEAD_ZONE,
+        };
+    }
+
+    // the player a device is assigned to, counting from 1
+    pub fn player(&self, id: usize) -> Option<usize> {
+        return self
+            .slots
+            .iter()
+            .position(|slot| slot.as_ref().is_some_and(|pad| pad.id == id))
+            .map(|slot| slot + 1);
+    }
+
+    pub fn device_name(&self, player: usize) -> Option<&str> {
+        let pad = self.slots.get(player.checked_sub(1)?)?.as_ref()?;
+        return Some(&pad.name);
+    }
+
+    // moves a connected device to another player, swapping with whoever had it
+    pub fn assign(
+        &mut self,
+        id: usize,
+        player: usize,
+        mapper: &mut InputMapper,
+    ) -> Result<(), String> {
+        let from = self
+            .player(id)
+            .ok_or_else(|| format!("no gamepad with id {} is connected", id))?;
+        if player == 0 || player > self.slots.len() {
+            return Err(format!("there is no player {}", player));
+        }
+        release(from, mapper);
+        release(player, mapper);
+        self.slots.swap(from - 1, player - 1);
+        return Ok(());
+    }
+
+    pub fn handle(&mut self, event: &GamepadEvent, mapper: &mut InputMapper) {
+        match event {
+            GamepadEvent::Connected(id, name) => {
+                if self.player(*id).is_some() {
+                    return;
+                }
+                // if every slot is taken the pad is ignored
+                if let Some(slot) = self.slots.iter_mut().find(|slot| slot.is_none()) {
+                    *slot = Some(Pad {
+                        id: *id,
+                        name: name.clone(),
+                        stick: (0.0, 0.0),
+                        dpad: [false; 4],
+                    });
+                }
+            }
+            GamepadEvent::Disconnected(id) => {
+                if let Some(player) = self.player(*id) {
+                    release(player, mapper);
+                    self.slots[player - 1] = None;
+                }
+            }
+            GamepadEvent::Button(id, button, pressed) => {
+                let player = match self.player(*id) {
+                    Some(player) => player,
+                    None => return,
+                };
+                let pad = self.slots[player - 1].as_mut().unwrap();
+                let direction = match button {
+                    GamepadButton::DPadUp => Some(0),
+                    GamepadButton::DPadDown => Some(1),
+                    GamepadButton::DPadLeft => Some(2),
+                    GamepadButton::DPadRight => Some(3),
+                    _ => None,
+                };
+                match direction {
+                    Some(direction) => {
+                        pad.dpad[direction] = *pressed;
+                        self.update_directions(player, mapper);
+                    }
+                    None => mapper.set_held(&input_name(player, button.name()), *pressed),
+                }
+            }
+            GamepadEvent::Axis(id, axis, value) => {
+                let player = match self.player(*id) {
+                    Some(player) => player,
+                    None => return,
+                };
+                let pad = self.slots[player - 1].as_mut().unwrap();
+                match axis {
+                    Axis::LeftStickX => pad.stick.0 = *value,
+                    Axis::LeftStickY => pad.stick.1 = *value,
+                }
+                self.update_directions(player, mapper);
+            }
+        }
+    }
+
+    fn update_directions(&self, player: usize, mapper: &mut InputMapper) {
+        let pad = match &self.slots[player - 1] {
+            Some(pad) => pad,
+            None => return,
+        };
+        let (x, y) = pad.stick;
+        let held = [
+            pad.dpad[0] || y < -self.dead_zone,
+            pad.dpad[1] || y > self.dead_zone,
+            pad.dpad[2] || x < -self.dead_zone,
+            pad.dpad[3] || x > self.dead_zone,
+        ];
+        for (name, held) in DIRECTIONS.iter().zip(held) {
+            mapper.set_held(&input_name(player, name), held);
+        }
+    }
+}
+
+const DIRECTIONS: [&str; 4] = ["up", "down", "left", "right"];
+
+pub fn input_name(player: usize, name: &str) -> String {
+    return format!("pad{}.{}", player, name);
+}
+
+fn release(player: usize, mapper: &mut InputMapper) {
+    for button in ALL_BUTTONS {
+        mapper.set_held(&input_name(player, button.name()), false);
+    }
+}
+
+const ALL_BUTTONS: [GamepadButton; 12] = [
+    GamepadButton::South,
+    GamepadButton::East,
+    GamepadButton::West,
+    GamepadButton::North,
+    GamepadButton::Select,
+    GamepadButton::Start,
+    GamepadButton::LeftShoulder,
+    GamepadButton::RightShoulder,
+    GamepadButton::DPadUp,
+    GamepadButton::DPadDown,
+    GamepadButton::DPadLeft,
+    GamepadButton::DPadRight,
+];
+
+// pad 1 on port 1 and pad 2 on port 2, south/west as A/B like the NES layout
+pub fn default_gamepad_bindings(bindings: &mut Bindings) {
+    for (player, port) in [(1, PORT_1), (2, PORT_2)] {
+        for (name, button) in [
+            ("south", Button::A),
+            ("west", Button::B),
+            ("select", Button::Select),
+            ("start", Button::Start),
+            ("up", Button::Up),
+            ("down", Button::Down),
+            ("left", Button::Left),
+            ("right", Button::Right),
+        ] {
+            bindings.bind(&input_name(player, name), port, button);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn setup() -> (Gamepads, InputMapper) {
+        let mut bindings = Bindings::new();
+        default_gamepad_bindings(&mut bindings);
+        return (Gamepads::new(2), InputMapper::new(bindings));
+    }
+
+    #[test]
+    fn test_hot_plug_assigns_players() {
+        let (mut pads, mut mapper) = setup();
+        pads.handle(
+            &GamepadEvent::Connected(7, "first".to_string()),
+            &mut mapper,
+        );
+        pads.handle(
+            &GamepadEvent::Connected(3, "second".to_string()),
+            &mut mapper,
+        );
+        pads.handle(
+            &GamepadEvent::Connected(9, "third".to_string()),
+            &mut mapper,
+        );
+        assert_eq!(pads.player(7), Some(1));
+        assert_eq!(pads.player(3), Some(2));
+        assert_eq!(pads.player(9), None);
+
+        // player 1 drops out, holding A; the button is released and the slot reused
+        pads.handle(
+            &GamepadEvent::Button(7, GamepadButton::South, true),
+            &mut mapper,
+        );
+        assert_eq!(mapper.buttons(PORT_1), Button::A.bit());
+        pads.handle(&GamepadEvent::Disconnected(7), &mut mapper);
+        assert_eq!(mapper.buttons(PORT_1), 0);
+        pads.handle(
+            &GamepadEvent::Connected(9, "third".to_string()),
+            &mut mapper,
+        );
+        assert_eq!(pads.player(9), Some(1));
+        assert_eq!(pads.device_name(1), Some("third"));
+        assert_eq!(pads.player(3), Some(2));
+    }
+
+    #[test]
+    fn test_buttons_follow_assignment() {
+        let (mut pads, mut mapper) = setup();
+        pads.handle(&GamepadEvent::Connected(1, "pad".to_string()), &mut mapper);
+        pads.handle(
+            &GamepadEvent::Button(1, GamepadButton::Start, true),
+            &mut mapper,
+        );
+        assert_eq!(mapper.buttons(PORT_1), Button::Start.bit());
+
+        pads.assign(1, 2, &mut mapper).unwrap();
+        assert_eq!(mapper.buttons(PORT_1), 0);
+        pads.handle(
+            &GamepadEvent::Button(1, GamepadButton::West, true),
+            &mut mapper,
+        );
+        assert_eq!(mapper.buttons(PORT_2), Button::B.bit());
+        assert!(pads.assign(1, 3, &mut mapper).is_err());
+        assert!(pads.assign(5, 1, &mut mapper).is_err());
+    }
+
+    #[test]
+    fn test_stick_and_dpad_with_dead_zone() {
+        let (mut pads, mut mapper) = setup();
+        pads.handle(&GamepadEvent::Connected(1, "pad".to_string()), &mut mapper);
+        pads.handle(&GamepadEvent::Axis(1, Axis::LeftStickX, -0.2), &mut mapper);
+        assert_eq!(mapper.buttons(PORT_1), 0);
+        pads.handle(&GamepadEvent::Axis(1, Axis::LeftStickX, -0.8), &mut mapper);
+        pads.handle(&GamepadEvent::Axis(1, Axis::LeftStickY, 0.9), &mut mapper);
+        assert_eq!(
+            mapper.buttons(PORT_1),
+            Button::Left.bit() | Button::Down.bit()
+        );
+
+        // the d-pad holds a direction even with the stick centred
+        pads.handle(
+            &GamepadEvent::Button(1, GamepadButton::DPadLeft, true),
+            &mut mapper,
+        );
+        pads.handle(&GamepadEvent::Axis(1, Axis::LeftStickX, 0.0), &mut mapper);
+        pads.handle(&GamepadEvent::Axis(1, Axis::LeftStickY, 0.0), &mut mapper);
+        assert_eq!(mapper.buttons(PORT_1), Button::Left.bit());
+        pads.handle(
+            &GamepadEvent::Button(1, GamepadButton::DPadLeft, false),
+            &mut mapper,
+        );
+        assert_eq!(mapper.buttons(PORT_1), 0);
+    }
+}
@@ -0,0 +1,243 @@
+use crate::error::StateError;
+use crate::input::InputDevice;
+use crate::savestate::{StateReader, StateWriter};
+
+/* Standard controller
+
+   Eight buttons behind a 4021 shift register. While OUT0 (the strobe) is
+   high the register keeps reloading from the buttons, so every read
+   returns the live state of A. Once the strobe goes low the latched state
+   is shifted out one button per read, in this order:
+
+    A, B, Select, Start, Up, Down, Left, Right
+
+   After the eighth read the register is empty and an official controller
+   returns 1 from then on.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Button {
+    A,
+    B,
+    Select,
+    Start,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Button {
+    pub const ALL: [Button; 8] = [
+        Button::A,
+        Button::B,
+        Button::Select,
+        Button::Start,
+        Button::Up,
+        Button::Down,
+        Button::Left,
+        Button::Right,
+    ];
+
+    // the button's bit in a report, A in bit 0
+    pub fn bit(self) -> u8 {
+        return 1 << self as u8;
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Button::A => return "a",
+            Button::B => return "b",
+            Button::Select => return "select",
+            Button::Start => return "start",
+            Button::Up => return "up",
+            Button::Down => return "down",
+            Button::Left => return "left",
+            Button::Right => return "right",
+        }
+    }
+
+    pub fn parse(name: &str) -> Result<Button, String> {
+        return Button::ALL
+            .into_iter()
+            .find(|button| button.name() == name)
+            .ok_or_else(|| format!("unknown button {}", name));
+    }
+}
+
+// a whole controller's buttons at once, for code driving the console directly
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Hash)]
+pub struct JoypadState {
+    pub a: bool,
+    pub b: bool,
+    pub select: bool,
+    pub start: bool,
+    pub up: bool,
+    pub down: bool,
+    pub left: bool,
+    pub right: bool,
+}
+
+impl JoypadState {
+    pub fn pressing(buttons: &[Button]) -> Self {
+        let bits = buttons.iter().fold(0, |bits, button| bits | button.bit());
+        return Self::from_bits(bits);
+    }
+
+    // one bit per button as in `Button::bit`
+    pub fn from_bits(bits: u8) -> Self {
+        let pressed = |button: Button| bits & button.bit() != 0;
+        return Self {
+            a: pressed(Button::A),
+            b: pressed(Button::B),
+            select: pressed(Button::Select),
+            start: pressed(Button::Start),
+            up: pressed(Button::Up),
+            down: pressed(Button::Down),
+            left: pressed(Button::Left),
+            right: pressed(Button::Right),
+        };
+    }
+
+    pub fn bits(&self) -> u8 {
+        let mut bits = 0;
+        for (button, pressed) in Button::ALL.into_iter().zip([
+            self.a,
+            self.b,
+            self.select,
+            self.start,
+            self.up,
+            self.down,
+            self.left,
+            self.right,
+        ]) {
+            if pressed {
+                bits |= button.bit();
+            }
+        }
+        return bits;
+    }
+
+    pub fn is_pressed(&self, button: Button) -> bool {
+        return self.bits() & button.bit() != 0;
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Joypad {
+    buttons: u8,
+    strobe: bool,
+    shift: u8,
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        if pressed {
+            self.buttons |= button.bit();
+        } else {
+            self.buttons &= !button.bit();
+        }
+    }
+
+    pub fn is_pressed(&self, button: Button) -> bool {
+        return self.buttons & button.bit() != 0;
+    }
+
+    // all eight at once, one bit per button as in `Button::bit`
+    pub fn buttons(&self) -> u8 {
+        return self.buttons;
+    }
+
+    pub fn set_buttons(&mut self, buttons: u8) {
+        self.buttons = buttons;
+    }
+}
+
+impl InputDevice for Joypad {
+    fn write(&mut self, data: u8) {
+        self.strobe = data & 1 != 0;
+        if self.strobe {
+            self.shift = self.buttons;
+        }
+    }
+
+    fn read(&mut self) -> u8 {
+        if self.strobe {
+            return self.buttons & 1;
+        }
+        let bit = self.shift & 1;
+        self.shift = (self.shift >> 1) | 0x80;
+        return bit;
+    }
+
+    // the buttons are host input, only the register is console state
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.strobe);
+        writer.write_u8(self.shift);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.strobe = reader.read_bool()?;
+        self.shift = reader.read_u8()?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn read_report(joypad: &mut Joypad) -> Vec<u8> {
+        joypad.write(1);
+        joypad.write(0);
+        return (0..10).map(|_| joypad.read()).collect();
+    }
+
+    #[test]
+    fn test_report_order() {
+        let mut joypad = Joypad::new();
+        joypad.set_button(Button::A, true);
+        joypad.set_button(Button::Start, true);
+        joypad.set_button(Button::Left, true);
+        assert_eq!(read_report(&mut joypad), vec![1, 0, 0, 1, 0, 0, 1, 0, 1, 1]);
+
+        joypad.set_button(Button::A, false);
+        assert!(!joypad.is_pressed(Button::A));
+        assert_eq!(joypad.buttons(), Button::Start.bit() | Button::Left.bit());
+    }
+
+    #[test]
+    fn test_joypad_state_bits() {
+        let state = JoypadState::pressing(&[Button::Start, Button::Left]);
+        assert!(state.start && state.left && !state.a);
+        assert_eq!(state.bits(), Button::Start.bit() | Button::Left.bit());
+        assert_eq!(JoypadState::from_bits(state.bits()), state);
+        assert!(state.is_pressed(Button::Left));
+        assert_eq!(JoypadState::from_bits(0xFF).bits(), 0xFF);
+    }
+
+    #[test]
+    fn test_strobe_high_returns_a() {
+        let mut joypad = Joypad::new();
+        joypad.write(1);
+        assert_eq!(joypad.read(), 0);
+        joypad.set_button(Button::A, true);
+        assert_eq!(joypad.read(), 1);
+        assert_eq!(joypad.read(), 1);
+    }
+
+    #[test]
+    fn test_latched_state_survives_button_changes() {
+        let mut joypad = Joypad::new();
+        joypad.set_button(Button::B, true);
+        joypad.write(1);
+        joypad.write(0);
+        joypad.set_button(Button::B, false);
+        assert_eq!(joypad.read(), 0);
+        assert_eq!(joypad.read(), 1);
+    }
+}
@@ -0,0 +1,195 @@
+use crate::error::StateError;
+use crate::input::{ExpansionDevice, PORT_2};
+use crate::savestate::{StateReader, StateWriter};
+
+pub const ROWS: usize = 9;
+
+const OUT_RESET: u8 = 0b0000_0001;
+const OUT_COLUMN: u8 = 0b0000_0010;
+const OUT_ENABLE: u8 = 0b0000_0100;
+
+// key names per row and column, in the order they appear on D1-D4
+pub const MATRIX: [[[&str; 4]; 2]; ROWS] = [
+    [
+        ["]", "[", "Return", "F8"],
+        ["Stop", "Yen", "RShift", "Kana"],
+    ],
+    [[";", ":", "@", "F7"], ["^", "-", "/", "_"]],
+    [["K", "L", "O", "F6"], ["0", "P", ",", "."]],
+    [["J", "U", "I", "F5"], ["8", "9", "N", "M"]],
+    [["H", "G", "Y", "F4"], ["6", "7", "V", "B"]],
+    [["D", "R", "T", "F3"], ["4", "5", "C", "F"]],
+    [["A", "S", "W", "F2"], ["3", "E", "Z", "X"]],
+    [["Ctrl", "Q", "Escape", "F1"], ["2", "1", "Grph", "LShift"]],
+    [
+        ["Left", "Right", "Up", "Home"],
+        ["Insert", "Delete", "Space", "Down"],
+    ],
+];
+
+/* Family BASIC keyboard
+
+   The Famicom keyboard sits on the expansion port and is scanned as a
+   matrix of 9 rows by 2 columns of 4 keys. The program drives it through
+   the $4016 outputs:
+
+    xxxx xKCR
+          ||+- reset, back to row 0
+          |+-- column select, going from 1 to 0 moves on to the next row
+          +--- keyboard enable
+
+   and reads the selected column from $4017 D1-D4, a 0 bit for each key held
+   down. Family BASIC writes $05 to restart the scan, then alternates $04
+   and $06, reading once after each. Past the last row, and with the
+   keyboard disabled, nothing reads as held.
+
+   Keys are named after the frontend's key names where there is an obvious
+   match ("A", "Return", "Space", "LShift"), the Famicom-only ones as
+   printed on the keys ("Stop", "Kana", "Grph", "Yen").
+*/
+
+#[derive(Debug, Clone, Default)]
+pub struct FamilyKeyboard {
+    keys: [[u8; 2]; ROWS], // held keys per row and column, bit 0 for D1
+    enabled: bool,
+    row: usize,
+    column: usize,
+}
+
+impl FamilyKeyboard {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    // false if the keyboard has no such key
+    pub fn set_key(&mut self, name: &str, held: bool) -> bool {
+        let Some((row, column, bit)) = key_position(name) else {
+            return false;
+        };
+        if held {
+            self.keys[row][column] |= 1 << bit;
+        } else {
+            self.keys[row][column] &= !(1 << bit);
+        }
+        return true;
+    }
+
+    pub fn is_held(&self, name: &str) -> bool {
+        return key_position(name)
+            .is_some_and(|(row, column, bit)| self.keys[row][column] & (1 << bit) != 0);
+    }
+
+    pub fn release_all(&mut self) {
+        self.keys = [[0; 2]; ROWS];
+    }
+}
+
+pub fn is_key(name: &str) -> bool {
+    return key_position(name).is_some();
+}
+
+fn key_position(name: &str) -> Option<(usize, usize, usize)> {
+    for (row, columns) in MATRIX.iter().enumerate() {
+        for (column, keys) in columns.iter().enumerate() {
+            if let Some(bit) = keys.iter().position(|&key| key == name) {
+                return Some((row, column, bit));
+            }
+        }
+    }
+    return None;
+}
+
+impl ExpansionDevice for FamilyKeyboard {
+    fn write(&mut self, data: u8) {
+        self.enabled = data & OUT_ENABLE != 0;
+        if !self.enabled {
+            return;
+        }
+        let column = ((data & OUT_COLUMN) >> 1) as usize;
+        if self.column == 1 && column == 0 && self.row < ROWS {
+            self.row += 1;
+        }
+        self.column = column;
+        if data & OUT_RESET != 0 {
+            self.row = 0;
+        }
+    }
+
+    fn read(&mut self, port: usize) -> u8 {
+        if port != PORT_2 || !self.enabled {
+            return 0;
+        }
+        if self.row >= ROWS {
+            return 0b0001_1110;
+        }
+        return (!self.keys[self.row][self.column] & 0b1111) << 1;
+    }
+
+    // the keys are host input, only the scan position is console state
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.enabled);
+        writer.write_u8(self.row as u8);
+        writer.write_u8(self.column as u8);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = reader.read_bool()?;
+        self.row = (reader.read_u8()? as usize).min(ROWS);
+        self.column = reader.read_u8()? as usize & 1;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::input::PORT_1;
+
+    // one full scan as Family BASIC does it, a byte of held-key bits per row and column
+    fn scan(keyboard: &mut FamilyKeyboard) -> Vec<u8> {
+        let mut rows = Vec::new();
+        keyboard.write(0x05);
+        for _ in 0..ROWS {
+            for out in [0x04, 0x06] {
+                keyboard.write(out);
+                rows.push(!(keyboard.read(PORT_2) >> 1) & 0b1111);
+            }
+        }
+        return rows;
+    }
+
+    #[test]
+    fn test_matrix_scan() {
+        let mut keyboard = FamilyKeyboard::new();
+        assert!(keyboard.set_key("Return", true));
+        assert!(keyboard.set_key("X", true));
+        assert!(keyboard.set_key("Space", true));
+        assert!(!keyboard.set_key("PageUp", true));
+
+        let rows = scan(&mut keyboard);
+        assert_eq!(rows[0], 0b0100); // row 0 column 0, Return
+        assert_eq!(rows[13], 0b1000); // row 6 column 1, X
+        assert_eq!(rows[17], 0b0100); // row 8 column 1, Space
+        assert_eq!(rows.iter().filter(|&&keys| keys != 0).count(), 3);
+
+        // past the last row nothing is held
+        keyboard.write(0x04);
+        assert_eq!(keyboard.read(PORT_2), 0b0001_1110);
+    }
+
+    #[test]
+    fn test_disabled_and_port_1() {
+        let mut keyboard = FamilyKeyboard::new();
+        keyboard.set_key("]", true);
+        keyboard.write(0x05);
+        keyboard.write(0x04);
+        assert_eq!(keyboard.read(PORT_1), 0);
+        assert_eq!(keyboard.read(PORT_2), 0b0001_1100);
+        keyboard.write(0x00);
+        assert_eq!(keyboard.read(PORT_2), 0);
+
+        assert!(keyboard.is_held("]"));
+        keyboard.release_all();
+        assert!(!keyboard.is_held("]"));
+    }
+}
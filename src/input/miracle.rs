@@ -0,0 +1,294 @@
+use crate::error::StateError;
+use crate::input::InputDevice;
+use crate::savestate::{StateReader, StateWriter};
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+/* Miracle Piano Teaching System
+
+   The keyboard plugs into a controller port and talks MIDI over a serial link
+   clocked by the console: every read of the port shifts one bit out of the
+   keyboard on D0 while the keyboard samples OUT0 (bit 0 of the last $4016
+   write) as one bit going the other way. Both directions use UART framing:
+
+    idle 1 | start 0 | d0 d1 d2 d3 d4 d5 d6 d7 | stop 1
+
+   The bytes themselves are plain MIDI (note on/off, program change, ...), so
+   the emulated keyboard just forwards them to a `MidiPort` on the host: the
+   piano's key presses come from host MIDI input and the sounds the game asks
+   the keyboard to play go to host MIDI output.
+*/
+
+const FRAME_BITS: u8 = 10;
+
+pub trait MidiPort: Send {
+    fn send(&mut self, data: &[u8]);
+
+    // bytes received from the host since the last call
+    fn receive(&mut self) -> Vec<u8>;
+}
+
+// a keyboard with nothing plugged into its MIDI jacks
+#[derive(Debug, Default)]
+pub struct NullMidi;
+
+impl MidiPort for NullMidi {
+    fn send(&mut self, _: &[u8]) {}
+
+    fn receive(&mut self) -> Vec<u8> {
+        return Vec::new();
+    }
+}
+
+// an in-memory port, for tests and for frontends that synthesize MIDI themselves
+#[derive(Debug, Default)]
+pub struct MemoryMidi {
+    pub sent: Vec<u8>,
+    pub incoming: VecDeque<u8>,
+}
+
+impl MidiPort for MemoryMidi {
+    fn send(&mut self, data: &[u8]) {
+        self.sent.extend_from_slice(data);
+    }
+
+    fn receive(&mut self) -> Vec<u8> {
+        return self.incoming.drain(..).collect();
+    }
+}
+
+// a raw MIDI device node (/dev/snd/midiC1D0 on ALSA, /dev/midi1 on OSS) or a fifo;
+// reads happen on a background thread so the emulation never blocks on the host
+pub struct RawMidi {
+    output: File,
+    input: Receiver<u8>,
+}
+
+impl RawMidi {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let output = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut reader = output
+            .try_clone()
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        let (sender, input) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buf = [0u8; 64];
+            while let Ok(n) = reader.read(&mut buf) {
+                if n == 0 || buf[..n].iter().any(|b| sender.send(*b).is_err()) {
+                    break;
+                }
+            }
+        });
+        return Ok(Self { output, input });
+    }
+}
+
+impl MidiPort for RawMidi {
+    fn send(&mut self, data: &[u8]) {
+        // a vanished device shouldn't take the emulator down with it
+        let _ = self.output.write_all(data);
+    }
+
+    fn receive(&mut self) -> Vec<u8> {
+        return self.input.try_iter().collect();
+    }
+}
+
+pub struct MiraclePiano<P: MidiPort> {
+    port: P,
+    out0: bool,
+    // keyboard -> console
+    tx_queue: VecDeque<u8>,
+    tx_frame: u16,
+    tx_bits: u8,
+    // console -> keyboard
+    rx_shift: u16,
+    rx_bits: u8,
+}
+
+impl<P: MidiPort> MiraclePiano<P> {
+    pub fn new(port: P) -> Self {
+        return Self {
+            port,
+            out0: true,
+            tx_queue: VecDeque::new(),
+            tx_frame: 0,
+            tx_bits: 0,
+            rx_shift: 0,
+            rx_bits: 0,
+        };
+    }
+
+    pub fn port(&self) -> &P {
+        return &self.port;
+    }
+
+    pub fn port_mut(&mut self) -> &mut P {
+        return &mut self.port;
+    }
+
+    fn next_tx_bit(&mut self) -> bool {
+        if self.tx_bits == 0 {
+            self.tx_queue.extend(self.port.receive());
+            match self.tx_queue.pop_front() {
+                Some(byte) => {
+                    // stop bit, data lsb first, start bit, shifted out from bit 0
+                    self.tx_frame = (1 << 9) | ((byte as u16) << 1);
+                    self.tx_bits = FRAME_BITS;
+                }
+                None => return true, // idle line
+            }
+        }
+        let bit = self.tx_frame & 1 != 0;
+        self.tx_frame >>= 1;
+        self.tx_bits -= 1;
+        return bit;
+    }
+
+    fn sample_rx_bit(&mut self) {
+        if self.rx_bits == 0 && self.out0 {
+            return; // waiting for a start bit
+        }
+        self.rx_shift |= (self.out0 as u16) << self.rx_bits;
+        self.rx_bits += 1;
+        if self.rx_bits == FRAME_BITS {
+            // drop frames with a broken stop bit, like a real UART would
+            if self.rx_shift & (1 << 9) != 0 {
+                self.port.send(&[(self.rx_shift >> 1) as u8]);
+            }
+            self.rx_shift = 0;
+            self.rx_bits = 0;
+        }
+    }
+}
+
+impl<P: MidiPort + 'static> InputDevice for MiraclePiano<P> {
+    fn write(&mut self, data: u8) {
+        self.out0 = data & 1 != 0;
+    }
+
+    fn read(&mut self) -> u8 {
+        self.sample_rx_bit();
+        return self.next_tx_bit() as u8;
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.out0);
+        writer.write_bytes(&self.tx_queue.iter().copied().collect::<Vec<u8>>());
+        writer.write_u16(self.tx_frame);
+        writer.write_u8(self.tx_bits);
+        writer.write_u16(self.rx_shift);
+        writer.write_u8(self.rx_bits);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.out0 = reader.read_bool()?;
+        self.tx_queue = reader.read_bytes()?.iter().copied().collect();
+        self.tx_frame = reader.read_u16()?;
+        self.tx_bits = reader.read_u8()?;
+        self.rx_shift = reader.read_u16()?;
+        self.rx_bits = reader.read_u8()?;
+        if self.tx_bits > FRAME_BITS || self.rx_bits >= FRAME_BITS {
+            return Err(StateError::Invalid(
+                "corrupt Miracle Piano state".to_string(),
+            ));
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // what a game does: drive OUT0 with one frame bit per port read
+    fn console_send(piano: &mut MiraclePiano<MemoryMidi>, byte: u8) {
+        let frame = (1u16 << 9) | ((byte as u16) << 1);
+        for i in 0..FRAME_BITS {
+            piano.write(((frame >> i) & 1) as u8);
+            piano.read();
+        }
+        piano.write(1);
+    }
+
+    fn console_receive(piano: &mut MiraclePiano<MemoryMidi>) -> Option<u8> {
+        // wait for the start bit
+        let mut polls = 0;
+        while piano.read() & 1 == 1 {
+            polls += 1;
+            if polls > 100 {
+                return None;
+            }
+        }
+        let mut byte = 0;
+        for i in 0..8 {
+            byte |= (piano.read() & 1) << i;
+        }
+        assert_eq!(piano.read() & 1, 1, "missing stop bit");
+        return Some(byte);
+    }
+
+    #[test]
+    fn test_console_to_host() {
+        let mut piano = MiraclePiano::new(MemoryMidi::default());
+        // idle line reads are ignored
+        piano.read();
+        piano.read();
+        for byte in [0x90, 0x3C, 0x7F] {
+            console_send(&mut piano, byte);
+        }
+        assert_eq!(piano.port().sent, vec![0x90, 0x3C, 0x7F]);
+    }
+
+    #[test]
+    fn test_framing_error_is_dropped() {
+        let mut piano = MiraclePiano::new(MemoryMidi::default());
+        for _ in 0..FRAME_BITS {
+            piano.write(0); // the stop bit never comes
+            piano.read();
+        }
+        assert!(piano.port().sent.is_empty());
+    }
+
+    #[test]
+    fn test_host_to_console() {
+        let mut piano = MiraclePiano::new(MemoryMidi::default());
+        assert_eq!(console_receive(&mut piano), None);
+
+        piano.port_mut().incoming.extend([0x80, 0x3C, 0x00]);
+        assert_eq!(console_receive(&mut piano), Some(0x80));
+        assert_eq!(console_receive(&mut piano), Some(0x3C));
+        assert_eq!(console_receive(&mut piano), Some(0x00));
+        assert_eq!(console_receive(&mut piano), None);
+    }
+
+    #[test]
+    fn test_save_load_state_mid_frame() {
+        let mut piano = MiraclePiano::new(MemoryMidi::default());
+        piano.port_mut().incoming.extend([0xC0, 0x05]);
+        piano.read(); // start bit of 0xC0 goes out, 0x05 stays queued
+
+        let mut writer = StateWriter::new();
+        piano.save_state(&mut writer);
+        let data = writer.into_bytes();
+
+        let mut restored = MiraclePiano::new(MemoryMidi::default());
+        restored.load_state(&mut StateReader::new(&data)).unwrap();
+        let mut byte = 0;
+        for i in 0..8 {
+            byte |= (restored.read() & 1) << i;
+        }
+        assert_eq!(byte, 0xC0);
+        restored.read();
+        assert_eq!(console_receive(&mut restored), Some(0x05));
+    }
+}
@@ -0,0 +1,371 @@
+use crate::error::StateError;
+use crate::savestate::{StateReader, StateWriter};
+use std::any::Any;
+
+pub mod bindings;
+pub mod four_score;
+pub mod gamepad;
+pub mod joypad;
+pub mod keyboard;
+pub mod miracle;
+
+use four_score::FourScore;
+use joypad::Joypad;
+
+/* Controller ports
+
+   $4016 writes drive the OUT0-OUT2 lines shared by both ports (OUT0 is the
+   strobe a standard controller latches its buttons on). Reading $4016 or
+   $4017 pulses that port's clock line and returns whatever the device puts
+   on D0-D4. Unconnected ports read back 0.
+
+   The Famicom's second controller is hardwired and has a microphone in
+   place of Select and Start. Its amplified signal shows up on $4016 D2,
+   whatever is on port 1; we read it as 1 while the level the frontend
+   reports, a hotkey or a real microphone, is above a threshold.
+
+   The Famicom also has an expansion port, for the keyboard and other
+   add-ons, that sees the same writes and drives D1-D4 of either register
+   alongside whatever the controller puts on them.
+
+   Devices are boxed trait objects, but the frontend still needs the
+   concrete one to feed it input: `device_mut` hands it back by type.
+
+   Players are numbered across the ports: players 1 and 2 are the
+   controllers on ports 1 and 2, and with a Four Score players 3 and 4 are
+   the second controller on each.
+*/
+
+pub const PORT_1: usize = 0;
+pub const PORT_2: usize = 1;
+pub const PLAYERS: usize = 4;
+
+const MICROPHONE_BIT: u8 = 0b0000_0100;
+const MICROPHONE_THRESHOLD: f32 = 0.1;
+
+// what is plugged into the ports: "standard" or "fourscore"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ControllerSetup {
+    #[default]
+    Standard,
+    FourScore,
+}
+
+impl ControllerSetup {
+    pub fn parse(name: &str) -> Result<ControllerSetup, String> {
+        match name {
+            "standard" => return Ok(ControllerSetup::Standard),
+            "fourscore" => return Ok(ControllerSetup::FourScore),
+            _ => return Err(format!("unknown controller setup {}", name)),
+        }
+    }
+}
+
+pub trait InputDevice: Any + Send {
+    // the value written to $4016, only the low three bits reach the port
+    fn write(&mut self, data: u8);
+
+    // one clocked read of the port, returns D0-D4 in the low five bits
+    fn read(&mut self) -> u8;
+
+    fn save_state(&self, writer: &mut StateWriter);
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError>;
+}
+
+pub trait ExpansionDevice: Any + Send {
+    // the value written to $4016, only the low three bits reach the port
+    fn write(&mut self, data: u8);
+
+    // one read of $4016 (PORT_1) or $4017 (PORT_2), D1-D4 are used
+    fn read(&mut self, port: usize) -> u8;
+
+    fn save_state(&self, writer: &mut StateWriter);
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError>;
+}
+
+#[derive(Default)]
+pub struct ControllerPorts {
+    ports: [Option<Box<dyn InputDevice>>; 2],
+    expansion: Option<Box<dyn ExpansionDevice>>,
+    microphone: f32,
+}
+
+impl ControllerPorts {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    pub fn connect(&mut self, port: usize, device: Box<dyn InputDevice>) {
+        self.ports[port] = Some(device);
+    }
+
+    pub fn disconnect(&mut self, port: usize) -> Option<Box<dyn InputDevice>> {
+        return self.ports[port].take();
+    }
+
+    pub fn is_connected(&self, port: usize) -> bool {
+        return self.ports[port].is_some();
+    }
+
+    // the device on `port`, if there is one and it is a `T`
+    pub fn device_mut<T: InputDevice>(&mut self, port: usize) -> Option<&mut T> {
+        let device = self.ports[port].as_deref_mut()? as &mut dyn Any;
+        return device.downcast_mut();
+    }
+
+    pub fn joypad_mut(&mut self, port: usize) -> Option<&mut Joypad> {
+        return self.device_mut(port);
+    }
+
+    pub fn connect_expansion(&mut self, device: Box<dyn ExpansionDevice>) {
+        self.expansion = Some(device);
+    }
+
+    pub fn disconnect_expansion(&mut self) -> Option<Box<dyn ExpansionDevice>> {
+        return self.expansion.take();
+    }
+
+    pub fn expansion_mut<T: ExpansionDevice>(&mut self) -> Option<&mut T> {
+        let device = self.expansion.as_deref_mut()? as &mut dyn Any;
+        return device.downcast_mut();
+    }
+
+    // replaces whatever is on both ports
+    pub fn set_up(&mut self, setup: ControllerSetup) {
+        for port in [PORT_1, PORT_2] {
+            match setup {
+                ControllerSetup::Standard => self.connect(port, Box::new(Joypad::new())),
+                ControllerSetup::FourScore => self.connect(port, Box::new(FourScore::new(port))),
+            }
+        }
+    }
+
+    // `player` is 0-3, a player with no controller to press is ignored
+    pub fn set_buttons(&mut self, player: usize, buttons: u8) {
+        let (port, slot) = (player % 2, player / 2);
+        if slot == 0 {
+            if let Some(joypad) = self.joypad_mut(port) {
+                joypad.set_buttons(buttons);
+                return;
+            }
+        }
+        if let Some(four_score) = self.device_mut::<FourScore>(port) {
+            four_score.set_buttons(slot, buttons);
+        }
+    }
+
+    // 0.0 for silence to 1.0 at full volume, host input so not saved
+    pub fn set_microphone(&mut self, level: f32) {
+        self.microphone = level;
+    }
+
+    pub fn microphone(&self) -> f32 {
+        return self.microphone;
+    }
+
+    // what `player` is pressing, 0 if nothing can report it
+    pub fn buttons(&mut self, player: usize) -> u8 {
+        let (port, slot) = (player % 2, player / 2);
+        if slot == 0 {
+            if let Some(joypad) = self.joypad_mut(port) {
+                return joypad.buttons();
+            }
+        }
+        return self
+            .device_mut::<FourScore>(port)
+            .map_or(0, |four_score| four_score.buttons(slot));
+    }
+
+    pub fn write(&mut self, data: u8) {
+        for device in self.ports.iter_mut().flatten() {
+            device.write(data & 0b0000_0111);
+        }
+        if let Some(device) = &mut self.expansion {
+            device.write(data & 0b0000_0111);
+        }
+    }
+
+    pub fn read(&mut self, port: usize) -> u8 {
+        let mut data = match &mut self.ports[port] {
+            Some(device) => device.read() & 0b0001_1111,
+            None => 0,
+        };
+        if let Some(device) = &mut self.expansion {
+            data |= device.read(port) & 0b0001_1110;
+        }
+        if port == PORT_1 && self.microphone > MICROPHONE_THRESHOLD {
+            data |= MICROPHONE_BIT;
+        }
+        return data;
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        for port in &self.ports {
+            writer.write_bool(port.is_some());
+            if let Some(device) = port {
+                device.save_state(writer);
+            }
+        }
+        writer.write_bool(self.expansion.is_some());
+        if let Some(device) = &self.expansion {
+            device.save_state(writer);
+        }
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        for (i, port) in self.ports.iter_mut().enumerate() {
+            let saved = reader.read_bool()?;
+            match port {
+                Some(device) if saved => device.load_state(reader)?,
+                None if !saved => {}
+                _ => {
+                    return Err(StateError::Invalid(format!(
+                        "save state does not match the device on port {}",
+                        i + 1
+                    )))
+                }
+            }
+        }
+        let saved = reader.read_bool()?;
+        match &mut self.expansion {
+            Some(device) if saved => device.load_state(reader)?,
+            None if !saved => {}
+            _ => {
+                return Err(StateError::Invalid(
+                    "save state does not match the expansion port device".to_string(),
+                ))
+            }
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Counter {
+        reads: u8,
+    }
+
+    impl InputDevice for Counter {
+        fn write(&mut self, data: u8) {
+            assert!(data <= 0b0000_0111);
+        }
+
+        fn read(&mut self) -> u8 {
+            self.reads += 1;
+            return 0xE0 | self.reads;
+        }
+
+        fn save_state(&self, writer: &mut StateWriter) {
+            writer.write_u8(self.reads);
+        }
+
+        fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+            self.reads = reader.read_u8()?;
+            return Ok(());
+        }
+    }
+
+    #[test]
+    fn test_ports_route_reads_and_writes() {
+        let mut ports = ControllerPorts::new();
+        ports.connect(PORT_2, Box::new(Counter { reads: 0 }));
+
+        assert_eq!(ports.read(PORT_1), 0);
+        assert_eq!(ports.read(PORT_2), 1); // upper bits never reach the bus
+        assert_eq!(ports.read(PORT_2), 2);
+        ports.write(0xFF);
+
+        let mut writer = StateWriter::new();
+        ports.save_state(&mut writer);
+        let data = writer.into_bytes();
+
+        let mut restored = ControllerPorts::new();
+        restored.connect(PORT_2, Box::new(Counter { reads: 0 }));
+        restored.load_state(&mut StateReader::new(&data)).unwrap();
+        assert_eq!(restored.read(PORT_2), 3);
+
+        assert!(restored.device_mut::<Counter>(PORT_2).is_some());
+        assert!(restored.joypad_mut(PORT_2).is_none());
+        assert!(restored.joypad_mut(PORT_1).is_none());
+
+        // a state taken with a device attached can't be loaded into an empty port
+        assert!(ControllerPorts::new()
+            .load_state(&mut StateReader::new(&data))
+            .is_err());
+    }
+
+    #[test]
+    fn test_expansion_drives_the_upper_bits() {
+        use keyboard::FamilyKeyboard;
+
+        let mut ports = ControllerPorts::new();
+        ports.set_up(ControllerSetup::Standard);
+        ports.connect_expansion(Box::new(FamilyKeyboard::new()));
+        ports
+            .joypad_mut(PORT_2)
+            .unwrap()
+            .set_button(joypad::Button::A, true);
+        ports
+            .expansion_mut::<FamilyKeyboard>()
+            .unwrap()
+            .set_key("[", true);
+        ports.write(0x05);
+        ports.write(0x04);
+        assert_eq!(ports.read(PORT_2), 0b0001_1011); // "[" held, A from the joypad
+        assert_eq!(ports.read(PORT_1), 0);
+
+        let mut writer = StateWriter::new();
+        ports.save_state(&mut writer);
+        let data = writer.into_bytes();
+        assert!(ports.load_state(&mut StateReader::new(&data)).is_ok());
+        ports.disconnect_expansion();
+        assert!(ports.load_state(&mut StateReader::new(&data)).is_err());
+    }
+
+    #[test]
+    fn test_microphone_on_4016_bit_2() {
+        let mut ports = ControllerPorts::new();
+        ports.set_up(ControllerSetup::Standard);
+        ports
+            .joypad_mut(PORT_1)
+            .unwrap()
+            .set_button(joypad::Button::A, true);
+        ports.write(1);
+        ports.write(0);
+        assert_eq!(ports.read(PORT_1), 0b0000_0001);
+        ports.set_microphone(0.5);
+        assert_eq!(ports.read(PORT_1), 0b0000_0100);
+        assert_eq!(ports.read(PORT_2), 0);
+        ports.set_microphone(0.05);
+        assert_eq!(ports.read(PORT_1), 0);
+    }
+
+    #[test]
+    fn test_setup_and_player_buttons() {
+        assert_eq!(
+            ControllerSetup::parse("fourscore"),
+            Ok(ControllerSetup::FourScore)
+        );
+        assert!(ControllerSetup::parse("zapper").is_err());
+
+        let mut ports = ControllerPorts::new();
+        ports.set_up(ControllerSetup::Standard);
+        ports.set_buttons(1, 0x12);
+        ports.set_buttons(3, 0xFF); // no fourth controller to press
+        assert_eq!(ports.joypad_mut(PORT_2).unwrap().buttons(), 0x12);
+
+        ports.set_up(ControllerSetup::FourScore);
+        assert!(ports.joypad_mut(PORT_1).is_none());
+        ports.set_buttons(0, 0x01);
+        ports.set_buttons(2, 0x80);
+        let four_score = ports.device_mut::<FourScore>(PORT_1).unwrap();
+        assert_eq!((four_score.buttons(0), four_score.buttons(1)), (0x01, 0x80));
+        assert_eq!(ports.buttons(2), 0x80);
+        assert_eq!(ports.buttons(1), 0);
+    }
+}
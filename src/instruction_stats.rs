@@ -0,0 +1,174 @@
+use crate::cpu::AddressingMode;
+use crate::op_codes::{OpCode, NMOS_6502_OPCODES_MAP};
+use std::collections::HashMap;
+
+// rows in the report's opcode table
+const REPORT_ROWS: usize = 30;
+
+/* Instruction statistics
+
+   How often each instruction ran, for deciding which of the emulator's
+   instructions are worth making faster and for homebrew developers
+   weighing one way of writing a loop against another. Counts are kept by
+   opcode, so each one is a mnemonic in an addressing mode, and read back
+   by mnemonic, by mode or by opcode, most first. The table's
+   NoneAddressing is split into the modes a 6502 manual would name:
+   implied, accumulator, relative and JMP's absolute and indirect.
+   `--instruction-stats FILE` writes the report at the end of the session.
+*/
+
+#[derive(Debug, Clone)]
+pub struct InstructionStats {
+    counts: [u64; 256],
+}
+
+impl Default for InstructionStats {
+    fn default() -> Self {
+        return Self { counts: [0; 256] };
+    }
+}
+
+impl InstructionStats {
+    // an instruction the CPU ran
+    pub fn record(&mut self, code: u8) {
+        self.counts[code as usize] += 1;
+    }
+
+    pub fn total(&self) -> u64 {
+        return self.counts.iter().sum();
+    }
+
+    pub fn count(&self, code: u8) -> u64 {
+        return self.counts[code as usize];
+    }
+
+    // every opcode that ran with its count, most first
+    pub fn opcodes(&self) -> Vec<(u8, u64)> {
+        let mut rows: Vec<(u8, u64)> = (0..=255u8)
+            .map(|code| (code, self.count(code)))
+            .filter(|&(_, count)| count > 0)
+            .collect();
+        rows.sort_by_key(|&(code, count)| (std::cmp::Reverse(count), code));
+        return rows;
+    }
+
+    pub fn by_mnemonic(&self) -> Vec<(&'static str, u64)> {
+        return self.grouped(|op| op.mnemonic);
+    }
+
+    pub fn by_mode(&self) -> Vec<(&'static str, u64)> {
+        return self.grouped(mode_name);
+    }
+
+    fn grouped(&self, key: impl Fn(&OpCode) -> &'static str) -> Vec<(&'static str, u64)> {
+        let mut totals: HashMap<&'static str, u64> = HashMap::new();
+        for (code, count) in self.opcodes() {
+            if let Some(op) = NMOS_6502_OPCODES_MAP.get(&code) {
+                *totals.entry(key(op)).or_default() += count;
+            }
+        }
+        let mut rows: Vec<(&'static str, u64)> = totals.into_iter().collect();
+        rows.sort_by_key(|&(name, count)| (std::cmp::Reverse(count), name));
+        return rows;
+    }
+
+    pub fn report(&self) -> String {
+        let total = self.total().max(1);
+        let percent = |count: u64| count as f64 * 100.0 / total as f64;
+        let mut text = format!("{} instructions\n\n", self.total());
+        text += &format!("{:<12}{:>18}\n", "mnemonic", "count");
+        for (mnemonic, count) in self.by_mnemonic() {
+            text += &format!("{:<12}{:>11} {:5.1}%\n", mnemonic, count, percent(count));
+        }
+        text += &format!("\n{:<12}{:>18}\n", "mode", "count");
+        for (mode, count) in self.by_mode() {
+            text += &format!("{:<12}{:>11} {:5.1}%\n", mode, count, percent(count));
+        }
+        text += &format!("\n{:<24}{:>18}\n", "opcode", "count");
+        for (code, count) in self.opcodes().into_iter().take(REPORT_ROWS) {
+            let Some(op) = NMOS_6502_OPCODES_MAP.get(&code) else {
+                continue;
+            };
+            let name = format!("{:02X}  {} {}", code, op.mnemonic, mode_name(op));
+            text += &format!("{:<24}{:>11} {:5.1}%\n", name, count, percent(count));
+        }
+        return text;
+    }
+}
+
+// the addressing mode as a 6502 manual names it
+pub fn mode_name(op: &OpCode) -> &'static str {
+    return match op.mode {
+        AddressingMode::Immediate => "immediate",
+        AddressingMode::ZeroPage => "zp",
+        AddressingMode::ZeroPage_X => "zp,x",
+        AddressingMode::ZeroPage_Y => "zp,y",
+        AddressingMode::Absolute => "abs",
+        AddressingMode::Absolute_X => "abs,x",
+        AddressingMode::Absolute_Y => "abs,y",
+        AddressingMode::Indirect => "(ind)",
+        AddressingMode::Indirect_X => "(zp,x)",
+        AddressingMode::Indirect_Y => "(zp),y",
+        AddressingMode::NoneAddressing => match op.len {
+            3 if op.code == 0x6C => "(ind)",
+            3 => "abs",
+            2 => "relative",
+            _ if matches!(op.code, 0x0A | 0x2A | 0x4A | 0x6A) => "accumulator",
+            _ => "implied",
+        },
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm;
+    use crate::cpu::{Mem, CPU};
+
+    #[test]
+    fn test_counts_by_mnemonic_and_mode() {
+        let source = "
+            LDX #$03
+            loop: LDA $10,X
+            ASL A
+            STA $0300,X
+            DEX
+            BNE loop
+            JMP ($0200)";
+        let mut cpu = CPU::new();
+        for (i, byte) in asm::assemble_at(source, 0x8000)
+            .unwrap()
+            .into_iter()
+            .enumerate()
+        {
+            cpu.mem_write(0x8000 + i as u16, byte);
+        }
+        // the indirect jump lands on a BRK
+        cpu.mem_write_u16(0x0200, 0x9000);
+        cpu.mem_write_u16(0xFFFC, 0x8000);
+        cpu.reset();
+        assert!(cpu.instruction_stats().is_none());
+        cpu.stats = Some(Box::new(InstructionStats::default()));
+        cpu.run();
+        let stats = cpu.instruction_stats().unwrap();
+
+        // the BRK that stops `run` is counted too
+        assert_eq!(stats.total(), 18);
+        assert_eq!(stats.count(0xB5), 3);
+        let mnemonics = stats.by_mnemonic();
+        assert_eq!(&mnemonics[..2], &[("ASL", 3), ("BNE", 3)]);
+        assert_eq!(mnemonics.last(), Some(&("LDX", 1)));
+        let modes = stats.by_mode();
+        assert!(modes.contains(&("implied", 4)));
+        assert!(modes.contains(&("accumulator", 3)));
+        assert!(modes.contains(&("relative", 3)));
+        assert!(modes.contains(&("(ind)", 1)));
+        assert_eq!(stats.opcodes()[0], (0x0A, 3));
+
+        let report = stats.report();
+        assert!(report.starts_with("18 instructions\n"));
+        assert!(report.contains("\nDEX                   3  16.7%\n"));
+        assert!(report.contains("\nzp,x                  3  16.7%\n"));
+        assert!(report.contains("\n9D  STA abs,x                     3  16.7%\n"));
+    }
+}
@@ -0,0 +1,85 @@
+#![allow(clippy::needless_return)]
+#![allow(clippy::assign_op_pattern)]
+#![allow(clippy::identity_op)]
+#![allow(clippy::needless_late_init)]
+
+/* rustynes
+
+   The emulator as a library, with the `rustynes` binary as one frontend
+   over it. Embedding the core takes little more than `Nes`:
+
+       let mut nes = Nes::with_rom(&std::fs::read("game.nes")?)?;
+       nes.set_input(0, JoypadState::pressing(&[Button::Start]));
+       nes.run_frame();
+       let picture = nes.frame();           // RGBA, see `video::Frame`
+       let sound = nes.audio_samples();     // at `nes.sample_rate()`
+       let state = nes.save_state();        // for `nes.load_state`
+
+   A program that needs to know when things happen, for an overlay or an
+   achievement, subscribes to the console's events rather than polling:
+
+       nes.on_vblank(|event, cpu| println!("{} {}", event.frame, cpu.bus.ram()[0x10]));
+       nes.watch(Topic::MapperWrite);
+       nes.run_frame();
+       let writes = nes.drain_events();
+
+   Everything else is public too, for tools that need more than a frame
+   at a time: the `cpu`, `bus`, `ppu` and `apu` themselves, `movie`s,
+   `cheat`s, the `debugger`, and the `frontend` the binary runs.
+*/
+
+pub mod apu;
+pub mod archive;
+pub mod asm;
+pub mod audio;
+pub mod battery;
+pub mod blargg;
+pub mod bus;
+pub mod cartridge;
+pub mod cheat;
+pub mod cheat_search;
+pub mod cpu;
+pub mod crash;
+pub mod cycle_profile;
+pub mod debugger;
+pub mod determinism;
+pub mod disasm;
+pub mod easy6502;
+pub mod error;
+pub mod frontend;
+pub mod functest;
+pub mod fuzz;
+pub mod hash;
+pub mod heatmap;
+pub mod input;
+pub mod instruction_stats;
+pub mod mapper;
+pub mod movie;
+pub mod nes;
+pub mod nestest;
+pub mod nsf;
+pub mod observer;
+pub mod op_codes;
+pub mod ppu;
+pub mod processor;
+pub mod profile;
+pub mod romdb;
+pub mod runner;
+pub mod savestate;
+pub mod selftest;
+pub mod stack;
+pub mod state_slots;
+pub mod sync;
+pub mod tas;
+pub mod trace;
+pub mod video;
+pub mod web;
+
+pub use cartridge::{Region, Rom};
+pub use error::EmuError;
+pub use input::joypad::{Button, JoypadState};
+pub use nes::{Nes, NesBuilder, RamInit};
+pub use observer::{ConsoleEvent, ConsoleEventKind, Subscription, Topic};
+
+#[macro_use]
+extern crate lazy_static;
@@ -1,11 +1,165 @@
-pub mod cpu;
-pub mod op_codes;
-pub mod processor;
-pub mod stack;
+#![allow(clippy::needless_return)]
+#![allow(clippy::assign_op_pattern)]
+#![allow(clippy::identity_op)]
+#![allow(clippy::needless_late_init)]
 
-#[macro_use]
-extern crate lazy_static;
+use rustynes::cartridge::Rom;
+use rustynes::{
+    blargg, determinism, disasm, easy6502, frontend, functest, fuzz, nestest, nsf, selftest,
+};
+use std::env;
+use std::path::Path;
+use std::process;
 
 fn main() {
-    println!("Hello, world!");
+    let args: Vec<String> = env::args().collect();
+    match args.get(1).map(|arg| arg.as_str()) {
+        Some("selftest") => {
+            let report = selftest::run();
+            print!("{}", report);
+            if !report.passed() {
+                process::exit(1);
+            }
+        }
+        Some("functest") => match functest::run_cli(&args[2..]) {
+            Ok(true) => {}
+            Ok(false) => process::exit(1),
+            Err(e) => {
+                eprintln!("error: {}", e);
+                process::exit(1);
+            }
+        },
+        Some("nestest") => match nestest::run_cli(&args[2..]) {
+            Ok(true) => {}
+            Ok(false) => process::exit(1),
+            Err(e) => {
+                eprintln!("error: {}", e);
+                process::exit(1);
+            }
+        },
+        Some("blargg") => match blargg::run_cli(&args[2..]) {
+            Ok(true) => {}
+            Ok(false) => process::exit(1),
+            Err(e) => {
+                eprintln!("error: {}", e);
+                process::exit(1);
+            }
+        },
+        Some("fuzz") => match fuzz::run_cli(&args[2..]) {
+            Ok(true) => {}
+            Ok(false) => process::exit(1),
+            Err(e) => {
+                eprintln!("error: {}", e);
+                process::exit(1);
+            }
+        },
+        Some("determinism") => match determinism::run_cli(&args[2..]) {
+            Ok(true) => {}
+            Ok(false) => process::exit(1),
+            Err(e) => {
+                eprintln!("error: {}", e);
+                process::exit(1);
+            }
+        },
+        Some("easy6502") => {
+            if let Err(e) = easy6502::run_cli(&args[2..]) {
+                eprintln!("error: {}", e);
+                process::exit(1);
+            }
+        }
+        Some("disasm") => {
+            if let Err(e) = disasm::run_cli(&args[2..]) {
+                eprintln!("error: {}", e);
+                process::exit(1);
+            }
+        }
+        Some("nsf") => {
+            if let Err(e) = nsf::run_cli(&args[2..]) {
+                eprintln!("error: {}", e);
+                process::exit(1);
+            }
+        }
+        Some("run") => run(&args[2..]),
+        Some("info") => match args.get(2) {
+            Some(path) => match Rom::from_file(Path::new(path), None) {
+                Ok(rom) => print!("{}", rom.info()),
+                Err(e) => {
+                    eprintln!("error: {}", e);
+                    process::exit(1);
+                }
+            },
+            None => usage(),
+        },
+        Some("recent") => {
+            let recent = frontend::config::RecentRoms::default_path()
+                .map(|path| frontend::config::RecentRoms::load(&path));
+            match recent {
+                Some(Ok(recent)) => {
+                    for rom in recent.roms() {
+                        println!("{}", rom.display());
+                    }
+                }
+                Some(Err(e)) => {
+                    eprintln!("error: {}", e);
+                    process::exit(1);
+                }
+                None => {}
+            }
+        }
+        // a bare ROM is short for `run`
+        Some(arg) if !arg.starts_with('-') => run(&args[1..]),
+        _ => usage(),
+    }
+}
+
+fn run(args: &[String]) {
+    let result = frontend::RunOptions::parse(args).and_then(|options| frontend::run(&options));
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn usage() {
+    eprintln!("usage: rustynes run <rom.nes> [options]");
+    eprintln!("         --region ntsc  --scale N  --headless  --frames N");
+    eprintln!("         --hash  --hash-ram  --watch  --watch-keep-ram");
+    // the windows this build has, then the terminal
+    let windows = [
+        ("sdl", cfg!(feature = "sdl")),
+        ("pixels", cfg!(feature = "pixels")),
+    ];
+    let mut renderers: Vec<&str> = windows.iter().filter(|w| w.1).map(|w| w.0).collect();
+    renderers.extend(["terminal", "terminal:braille"]);
+    eprintln!("         --renderer {}  --shrink N", renderers.join("|"));
+    eprintln!("         --fullscreen  --no-integer-scale  --aspect 8:7|1:1");
+    eprintln!("         --rotate 0|90|180|270");
+    eprintln!("         --filter none|crt|composite+scanlines+mask");
+    eprintln!("         --capture-dir DIR  --screenshot-scale N  --crop-overscan");
+    eprintln!("         --record-video  --video-format apng|ffmpeg  --show-fps");
+    eprintln!("         --show-stats  --state-dir DIR  --romdb FILE  --save-dir DIR");
+    eprintln!("         --sync timer|vsync|audio  --unfocused run|pause|throttle");
+    eprintln!("         --palette NAME|FILE  --render-mode scanline|dot");
+    eprintln!("         --controllers standard|fourscore  --bindings FILE  --cheat CODE");
+    eprintln!("         --config FILE  --debug  --debug-tui  --symbols FILE  --trace-history N");
+    eprintln!("         --cycle-profile FILE  --flamegraph FILE  --heatmap FILE");
+    eprintln!("         --instruction-stats FILE");
+    eprintln!("         --play MOVIE  --record MOVIE  --tas MOVIE  --run-ahead N  --fresh");
+    eprintln!("       rustynes <rom.nes> [options]");
+    eprintln!("       rustynes info <rom.nes>");
+    eprintln!("       rustynes recent");
+    eprintln!("       rustynes selftest");
+    eprintln!(
+        "       rustynes nsf <file> [--track N] [--seconds S] [--rate HZ] [--quality fast|good|best] [--out FILE]"
+    );
+    eprintln!(
+        "       rustynes easy6502 <program.bin|.asm> [--speed N] [--frames N] [--renderer NAME]"
+    );
+    eprintln!("       rustynes functest <file> [--start ADDR] [--success ADDR] [--max-steps N]");
+    eprintln!("       rustynes nestest <nestest.nes> <nestest.log> [--official]");
+    eprintln!("       rustynes fuzz [--seed N] [--cases N] [--steps N]");
+    eprintln!("       rustynes blargg <rom.nes|dir> [--suite NAME] [--frames N]");
+    eprintln!("       rustynes disasm <rom.nes> [--cdl FILE] [--out FILE]");
+    eprintln!("       rustynes determinism <rom.nes> <movie>");
+    process::exit(2);
 }
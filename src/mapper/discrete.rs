@@ -0,0 +1,334 @@
+use crate::cartridge::{Mirroring, Rom, CHR_ROM_PAGE_SIZE, PRG_RAM_PAGE_SIZE};
+use crate::error::StateError;
+use crate::mapper::{ChrMemory, Mapper};
+use crate::savestate::{StateReader, StateWriter};
+
+const PRG_BANK_SIZE: usize = 0x8000;
+const CHR_BANK_SIZE: usize = 0x1000;
+
+/* Discrete logic boards
+
+   All of these switch a 32KB PRG bank (and some an 8KB CHR bank) through a
+   single latch written anywhere in $8000-$FFFF:
+
+    mapper  board        latch bits                     notes
+    7       AxROM        ...M .PPP                      M selects the single screen nametable, CHR RAM
+    11      Color Dreams CCCC ..PP                      bus conflicts
+    34      BNROM        .... ..PP                      bus conflicts, CHR RAM
+    34      NINA-001     $7FFD PRG, $7FFE/$7FFF 4KB CHR  used when the cartridge has more than 8KB of CHR ROM
+    66      GxROM        ..PP ..CC                      bus conflicts
+
+   With bus conflicts the ROM drives the data bus at the same time as the CPU,
+   so the value that reaches the latch is the AND of both.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Board {
+    AxRom,
+    ColorDreams,
+    BnRom,
+    Nina001,
+    GxRom,
+}
+
+impl Board {
+    fn from_rom(rom: &Rom) -> Board {
+        match rom.mapper {
+            7 => return Board::AxRom,
+            11 => return Board::ColorDreams,
+            34 => {
+                let nina = match rom.submapper {
+                    1 => true,
+                    2 => false,
+                    _ => rom.chr_rom.len() > CHR_ROM_PAGE_SIZE,
+                };
+                if nina {
+                    return Board::Nina001;
+                }
+                return Board::BnRom;
+            }
+            _ => return Board::GxRom,
+        }
+    }
+
+    fn bus_conflicts(&self) -> bool {
+        return matches!(self, Board::ColorDreams | Board::BnRom | Board::GxRom);
+    }
+}
+
+pub struct Discrete {
+    board: Board,
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: ChrMemory,
+    mirroring: Mirroring,
+    prg_bank: usize,
+    chr_banks: [usize; 2], // 4KB banks for $0000 and $1000
+}
+
+impl Discrete {
+    pub fn new(rom: Rom) -> Self {
+        let board = Board::from_rom(&rom);
+        let mirroring = match board {
+            Board::AxRom => Mirroring::SingleScreenLower,
+            _ => rom.screen_mirroring,
+        };
+        let prg_ram = match board {
+            Board::Nina001 => vec![0; PRG_RAM_PAGE_SIZE],
+            _ => Vec::new(),
+        };
+        return Self {
+            board,
+            prg_rom: rom.prg_rom,
+            prg_ram,
+            chr: ChrMemory::new(rom.chr_rom),
+            mirroring,
+            prg_bank: 0,
+            chr_banks: [0, 1],
+        };
+    }
+
+    pub fn board(&self) -> Board {
+        return self.board;
+    }
+
+    fn select_chr_8k(&mut self, bank: usize) {
+        self.chr_banks = [bank * 2, bank * 2 + 1];
+    }
+
+    fn write_latch(&mut self, data: u8) {
+        match self.board {
+            Board::AxRom => {
+                self.prg_bank = (data & 0b0000_0111) as usize;
+                self.mirroring = if data & 0b0001_0000 != 0 {
+                    Mirroring::SingleScreenUpper
+                } else {
+                    Mirroring::SingleScreenLower
+                };
+            }
+            Board::ColorDreams => {
+                self.prg_bank = (data & 0b0000_0011) as usize;
+                self.select_chr_8k((data >> 4) as usize);
+            }
+            Board::BnRom => self.prg_bank = data as usize,
+            Board::Nina001 => {} // NINA-001 latches live in PRG RAM space
+            Board::GxRom => {
+                self.prg_bank = ((data >> 4) & 0b0000_0011) as usize;
+                self.select_chr_8k((data & 0b0000_0011) as usize);
+            }
+        }
+    }
+}
+
+impl Mapper for Discrete {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                return self.prg_ram[(addr - 0x6000) as usize];
+            }
+            0x8000..=0xFFFF => {
+                let banks = self.prg_rom.len().div_ceil(PRG_BANK_SIZE);
+                let offset = (self.prg_bank % banks) * PRG_BANK_SIZE + (addr - 0x8000) as usize;
+                return self.prg_rom[offset % self.prg_rom.len()];
+            }
+            _ => return 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF if self.board == Board::Nina001 => {
+                self.prg_ram[(addr - 0x6000) as usize] = data;
+                match addr {
+                    0x7FFD => self.prg_bank = (data & 0b0000_0001) as usize,
+                    0x7FFE => self.chr_banks[0] = (data & 0b0000_1111) as usize,
+                    0x7FFF => self.chr_banks[1] = (data & 0b0000_1111) as usize,
+                    _ => {}
+                }
+            }
+            0x8000..=0xFFFF => {
+                let data = if self.board.bus_conflicts() {
+                    data & self.cpu_read(addr)
+                } else {
+                    data
+                };
+                self.write_latch(data);
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        let bank = self.chr_banks[(addr as usize >> 12) & 1];
+        return self
+            .chr
+            .read(bank * CHR_BANK_SIZE + (addr as usize % CHR_BANK_SIZE));
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        let bank = self.chr_banks[(addr as usize >> 12) & 1];
+        self.chr
+            .write(bank * CHR_BANK_SIZE + (addr as usize % CHR_BANK_SIZE), data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        return self.mirroring;
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        if self.prg_ram.is_empty() {
+            return None;
+        }
+        return Some(&self.prg_ram);
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        if self.prg_ram.is_empty() {
+            return None;
+        }
+        return Some(&mut self.prg_ram);
+    }
+
+    fn prg_rom(&self) -> Option<&[u8]> {
+        return Some(&self.prg_rom);
+    }
+
+    fn prg_rom_mut(&mut self) -> Option<&mut [u8]> {
+        return Some(&mut self.prg_rom);
+    }
+
+    fn chr(&self) -> Option<&[u8]> {
+        return Some(self.chr.data());
+    }
+
+    fn chr_mut(&mut self) -> Option<&mut [u8]> {
+        return Some(self.chr.data_mut());
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.prg_ram);
+        self.chr.save_state(writer);
+        writer.write_u8(match self.mirroring {
+            Mirroring::SingleScreenUpper => 1,
+            _ => 0,
+        });
+        writer.write_u32(self.prg_bank as u32);
+        writer.write_u32(self.chr_banks[0] as u32);
+        writer.write_u32(self.chr_banks[1] as u32);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        reader.read_into(&mut self.prg_ram)?;
+        self.chr.load_state(reader)?;
+        let upper = reader.read_u8()? == 1;
+        if self.board == Board::AxRom {
+            self.mirroring = if upper {
+                Mirroring::SingleScreenUpper
+            } else {
+                Mirroring::SingleScreenLower
+            };
+        }
+        self.prg_bank = reader.read_u32()? as usize;
+        self.chr_banks[0] = reader.read_u32()? as usize;
+        self.chr_banks[1] = reader.read_u32()? as usize;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+
+    fn board(mapper: u8, prg_pages: u8, chr_pages: u8) -> Discrete {
+        return Discrete::new(Rom::new(&test_rom(mapper, prg_pages, chr_pages)).unwrap());
+    }
+
+    // test_rom fills each 8KB PRG bank with its index, so a 32KB bank n starts with 4n
+    fn prg_bank_at_8000(mapper: &Discrete) -> u8 {
+        return mapper.cpu_read(0x8000) / 4;
+    }
+
+    #[test]
+    fn test_axrom() {
+        let mut mapper = board(7, 16, 0); // 256KB PRG, CHR RAM
+        assert_eq!(mapper.board(), Board::AxRom);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenLower);
+
+        mapper.cpu_write(0x8000, 0b0001_0101);
+        assert_eq!(prg_bank_at_8000(&mapper), 5);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenUpper);
+
+        mapper.ppu_write(0x1234, 0x77);
+        assert_eq!(mapper.ppu_read(0x1234), 0x77);
+    }
+
+    #[test]
+    fn test_color_dreams() {
+        let mut mapper = board(11, 8, 4); // 128KB PRG, 32KB CHR
+                                          // latch directly, bus conflicts are covered separately
+        mapper.write_latch(0b0010_0011);
+        assert_eq!(prg_bank_at_8000(&mapper), 3);
+        assert_eq!(mapper.ppu_read(0x0000), 16); // 8KB CHR bank 2 = 1KB bank 16
+        assert_eq!(mapper.ppu_read(0x1C00), 23);
+    }
+
+    #[test]
+    fn test_bus_conflicts() {
+        let mut mapper = board(66, 8, 4);
+        // the ROM at $8000 holds 0, so nothing gets through
+        mapper.cpu_write(0x8000, 0b0011_0011);
+        assert_eq!(prg_bank_at_8000(&mapper), 0);
+        assert_eq!(mapper.ppu_read(0x0000), 0);
+
+        // the ROM at $FFFF holds 3, only the CHR bits survive
+        mapper.cpu_write(0xFFFF, 0b0011_0011);
+        assert_eq!(prg_bank_at_8000(&mapper), 0);
+        assert_eq!(mapper.ppu_read(0x0000), 24);
+    }
+
+    #[test]
+    fn test_gxrom() {
+        let mut mapper = board(66, 8, 4);
+        mapper.write_latch(0b0010_0001);
+        assert_eq!(prg_bank_at_8000(&mapper), 2);
+        assert_eq!(mapper.cpu_read(0xFFFF), 11);
+        assert_eq!(mapper.ppu_read(0x0000), 8);
+    }
+
+    #[test]
+    fn test_bnrom() {
+        let mut mapper = board(34, 8, 0);
+        assert_eq!(mapper.board(), Board::BnRom);
+        mapper.cpu_write(0xFFFF, 0x03);
+        assert_eq!(prg_bank_at_8000(&mapper), 3);
+    }
+
+    #[test]
+    fn test_nina_001() {
+        let mut mapper = board(34, 4, 8); // 64KB PRG, 64KB CHR
+        assert_eq!(mapper.board(), Board::Nina001);
+        mapper.cpu_write(0x7FFD, 1);
+        mapper.cpu_write(0x7FFE, 3);
+        mapper.cpu_write(0x7FFF, 9);
+        assert_eq!(prg_bank_at_8000(&mapper), 1);
+        assert_eq!(mapper.ppu_read(0x0000), 12); // 4KB bank 3 = 1KB bank 12
+        assert_eq!(mapper.ppu_read(0x1000), 36);
+        mapper.cpu_write(0x6000, 0x42);
+        assert_eq!(mapper.cpu_read(0x6000), 0x42);
+    }
+
+    #[test]
+    fn test_save_load_state() {
+        let mut mapper = board(7, 8, 0);
+        mapper.cpu_write(0x8000, 0b0001_0011);
+        let mut writer = StateWriter::new();
+        mapper.save_state(&mut writer);
+        let data = writer.into_bytes();
+
+        let mut restored = board(7, 8, 0);
+        restored.load_state(&mut StateReader::new(&data)).unwrap();
+        assert_eq!(prg_bank_at_8000(&restored), 3);
+        assert_eq!(restored.mirroring(), Mirroring::SingleScreenUpper);
+    }
+}
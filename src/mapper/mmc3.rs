@@ -0,0 +1,494 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::error::StateError;
+use crate::mapper::{ChrMemory, Mapper};
+use crate::savestate::{StateReader, StateWriter};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+// A12 has to stay low this many PPU cycles before a rise clocks the counter
+// again; this filters out the short dips between sprite pattern fetches
+const A12_LOW_FILTER: u64 = 10;
+
+/* MMC3 register outline (mapper 4)
+    $8000 even  bank select   CP.. .RRR  C: CHR A12 inversion, P: PRG mode, R: target register
+    $8001 odd   bank data     value for R0-R7
+    $A000 even  mirroring     .... ...M  0 vertical, 1 horizontal
+    $A001 odd   PRG RAM       EW.. ....  E: chip enable, W: write protect
+    $C000 even  IRQ latch     reload value for the scanline counter
+    $C001 odd   IRQ reload    counter is reloaded from the latch on the next A12 rise
+    $E000 even  IRQ disable   also acknowledges a pending IRQ
+    $E001 odd   IRQ enable
+
+   NES-QJ (mapper 47) is a 2-in-1 built from an MMC3 and a latch at
+   $6000-$7FFF (written only while PRG RAM is enabled and writable) whose bit 0
+   picks one of two 128KB PRG / 128KB CHR blocks. The MMC3 itself only ever
+   sees the selected block.
+*/
+
+const QJ_PRG_BLOCK_BANKS: usize = 16; // 128KB of 8KB banks
+const QJ_CHR_BLOCK_BANKS: usize = 128; // 128KB of 1KB banks
+
+pub struct Mmc3 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: ChrMemory,
+    four_screen: bool,
+    multicart: bool,
+    block: usize,
+
+    bank_select: u8,
+    registers: [u8; 8],
+    horizontal_mirroring: bool,
+    prg_ram_enabled: bool,
+    prg_ram_write_protect: bool,
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+
+    a12_high: bool,
+    a12_low_since: u64,
+}
+
+impl Mmc3 {
+    pub fn new(rom: Rom) -> Self {
+        return Self {
+            prg_ram: vec![0; rom.prg_ram_size.max(0x2000)],
+            prg_rom: rom.prg_rom,
+            chr: ChrMemory::new(rom.chr_rom),
+            four_screen: rom.screen_mirroring == Mirroring::FourScreen,
+            multicart: false,
+            block: 0,
+            bank_select: 0,
+            registers: [0, 2, 4, 5, 6, 7, 0, 1],
+            horizontal_mirroring: rom.screen_mirroring == Mirroring::Horizontal,
+            prg_ram_enabled: true,
+            prg_ram_write_protect: false,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+            a12_high: false,
+            a12_low_since: 0,
+        };
+    }
+
+    pub fn new_qj(rom: Rom) -> Self {
+        let mut mapper = Self::new(rom);
+        mapper.multicart = true;
+        return mapper;
+    }
+
+    // banks visible to the MMC3, the selected block on a multicart
+    fn prg_bank_count(&self) -> usize {
+        let total = self.prg_rom.len() / PRG_BANK_SIZE;
+        if self.multicart {
+            return total.min(QJ_PRG_BLOCK_BANKS);
+        }
+        return total;
+    }
+
+    fn prg_block_offset(&self) -> usize {
+        if !self.multicart {
+            return 0;
+        }
+        return (self.block * QJ_PRG_BLOCK_BANKS) % (self.prg_rom.len() / PRG_BANK_SIZE);
+    }
+
+    fn chr_block_offset(&self) -> usize {
+        if !self.multicart {
+            return 0;
+        }
+        return self.block * QJ_CHR_BLOCK_BANKS;
+    }
+
+    fn prg_bank(&self, slot: usize) -> usize {
+        let second_last = self.prg_bank_count() - 2;
+        let swapped = self.bank_select & 0b0100_0000 != 0;
+        let bank = match (slot, swapped) {
+            (0, false) => self.registers[6] as usize,
+            (0, true) => second_last,
+            (1, _) => self.registers[7] as usize,
+            (2, false) => second_last,
+            (2, true) => self.registers[6] as usize,
+            _ => self.prg_bank_count() - 1,
+        };
+        return bank % self.prg_bank_count();
+    }
+
+    fn chr_bank(&self, slot: usize) -> usize {
+        // with inversion the 2KB banks move to $1000 and the 1KB banks to $0000
+        let slot = if self.bank_select & 0b1000_0000 != 0 {
+            slot ^ 4
+        } else {
+            slot
+        };
+        return match slot {
+            0 => (self.registers[0] & 0xFE) as usize,
+            1 => (self.registers[0] | 0x01) as usize,
+            2 => (self.registers[1] & 0xFE) as usize,
+            3 => (self.registers[1] | 0x01) as usize,
+            _ => self.registers[slot - 2] as usize,
+        };
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let slot = (addr as usize & 0x1FFF) / CHR_BANK_SIZE;
+        let mut bank = self.chr_bank(slot);
+        if self.multicart {
+            bank = bank % QJ_CHR_BLOCK_BANKS + self.chr_block_offset();
+        }
+        return bank * CHR_BANK_SIZE + (addr as usize % CHR_BANK_SIZE);
+    }
+
+    fn clock_irq_counter(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => {
+                if !self.prg_ram_enabled || self.multicart {
+                    return 0; // open bus
+                }
+                return self.prg_ram[(addr - 0x6000) as usize];
+            }
+            0x8000..=0xFFFF => {
+                let slot = (addr - 0x8000) as usize / PRG_BANK_SIZE;
+                let offset = addr as usize % PRG_BANK_SIZE;
+                let bank = self.prg_block_offset() + self.prg_bank(slot);
+                return self.prg_rom[bank * PRG_BANK_SIZE + offset];
+            }
+            _ => return 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        let even = addr & 1 == 0;
+        match (addr, even) {
+            (0x6000..=0x7FFF, _) if self.prg_ram_enabled && !self.prg_ram_write_protect => {
+                if self.multicart {
+                    self.block = (data & 1) as usize;
+                } else {
+                    self.prg_ram[(addr - 0x6000) as usize] = data;
+                }
+            }
+            (0x8000..=0x9FFF, true) => self.bank_select = data,
+            (0x8000..=0x9FFF, false) => {
+                self.registers[(self.bank_select & 0b0000_0111) as usize] = data;
+            }
+            (0xA000..=0xBFFF, true) => self.horizontal_mirroring = data & 1 != 0,
+            (0xA000..=0xBFFF, false) => {
+                self.prg_ram_enabled = data & 0b1000_0000 != 0;
+                self.prg_ram_write_protect = data & 0b0100_0000 != 0;
+            }
+            (0xC000..=0xDFFF, true) => self.irq_latch = data,
+            (0xC000..=0xDFFF, false) => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            (0xE000..=0xFFFF, true) => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            (0xE000..=0xFFFF, false) => self.irq_enabled = true,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        return self.chr.read(self.chr_offset(addr));
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        let offset = self.chr_offset(addr);
+        self.chr.write(offset, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.four_screen {
+            return Mirroring::FourScreen;
+        }
+        if self.horizontal_mirroring {
+            return Mirroring::Horizontal;
+        }
+        return Mirroring::Vertical;
+    }
+
+    fn irq_pending(&self) -> bool {
+        return self.irq_pending;
+    }
+
+    fn ppu_bus(&mut self, addr: u16, ppu_cycle: u64) {
+        let a12 = addr & 0x1000 != 0;
+        if a12 && !self.a12_high {
+            if ppu_cycle.saturating_sub(self.a12_low_since) >= A12_LOW_FILTER {
+                self.clock_irq_counter();
+            }
+            self.a12_high = true;
+        } else if !a12 && self.a12_high {
+            self.a12_high = false;
+            self.a12_low_since = ppu_cycle;
+        }
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        if self.multicart {
+            return None; // the block latch sits where the RAM would be
+        }
+        return Some(&self.prg_ram);
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        if self.multicart {
+            return None;
+        }
+        return Some(&mut self.prg_ram);
+    }
+
+    fn prg_rom(&self) -> Option<&[u8]> {
+        return Some(&self.prg_rom);
+    }
+
+    fn prg_rom_mut(&mut self) -> Option<&mut [u8]> {
+        return Some(&mut self.prg_rom);
+    }
+
+    fn chr(&self) -> Option<&[u8]> {
+        return Some(self.chr.data());
+    }
+
+    fn chr_mut(&mut self) -> Option<&mut [u8]> {
+        return Some(self.chr.data_mut());
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.prg_ram);
+        self.chr.save_state(writer);
+        writer.write_u8(self.block as u8);
+        writer.write_u8(self.bank_select);
+        writer.write_bytes(&self.registers);
+        writer.write_bool(self.horizontal_mirroring);
+        writer.write_bool(self.prg_ram_enabled);
+        writer.write_bool(self.prg_ram_write_protect);
+        writer.write_u8(self.irq_latch);
+        writer.write_u8(self.irq_counter);
+        writer.write_bool(self.irq_reload);
+        writer.write_bool(self.irq_enabled);
+        writer.write_bool(self.irq_pending);
+        writer.write_bool(self.a12_high);
+        writer.write_u64(self.a12_low_since);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        reader.read_into(&mut self.prg_ram)?;
+        self.chr.load_state(reader)?;
+        self.block = (reader.read_u8()? & 1) as usize;
+        self.bank_select = reader.read_u8()?;
+        reader.read_into(&mut self.registers)?;
+        self.horizontal_mirroring = reader.read_bool()?;
+        self.prg_ram_enabled = reader.read_bool()?;
+        self.prg_ram_write_protect = reader.read_bool()?;
+        self.irq_latch = reader.read_u8()?;
+        self.irq_counter = reader.read_u8()?;
+        self.irq_reload = reader.read_bool()?;
+        self.irq_enabled = reader.read_bool()?;
+        self.irq_pending = reader.read_bool()?;
+        self.a12_high = reader.read_bool()?;
+        self.a12_low_since = reader.read_u64()?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+
+    // 128KB PRG (16 banks of 8KB), 128KB CHR (128 banks of 1KB)
+    fn mmc3() -> Mmc3 {
+        return Mmc3::new(Rom::new(&test_rom(4, 8, 16)).unwrap());
+    }
+
+    // one scanline worth of A12 activity: background at $0000, sprites at $1000
+    fn scanline(mapper: &mut Mmc3, line: u64) {
+        let start = line * 341;
+        mapper.ppu_bus(0x0000, start);
+        for sprite in 0..8 {
+            let dot = start + 257 + sprite * 8;
+            mapper.ppu_bus(0x2000, dot);
+            mapper.ppu_bus(0x1000, dot + 4);
+            mapper.ppu_bus(0x1008, dot + 6);
+        }
+        mapper.ppu_bus(0x0000, start + 321);
+    }
+
+    #[test]
+    fn test_prg_banking_modes() {
+        let mut mapper = mmc3();
+        mapper.cpu_write(0x8000, 6);
+        mapper.cpu_write(0x8001, 3);
+        mapper.cpu_write(0x8000, 7);
+        mapper.cpu_write(0x8001, 5);
+
+        assert_eq!(mapper.cpu_read(0x8000), 3);
+        assert_eq!(mapper.cpu_read(0xA000), 5);
+        assert_eq!(mapper.cpu_read(0xC000), 14);
+        assert_eq!(mapper.cpu_read(0xE000), 15);
+
+        // PRG mode 1 swaps $8000 and $C000
+        mapper.cpu_write(0x8000, 0b0100_0110);
+        assert_eq!(mapper.cpu_read(0x8000), 14);
+        assert_eq!(mapper.cpu_read(0xA000), 5);
+        assert_eq!(mapper.cpu_read(0xC000), 3);
+        assert_eq!(mapper.cpu_read(0xFFFF), 15);
+    }
+
+    #[test]
+    fn test_chr_banking_and_inversion() {
+        let mut mapper = mmc3();
+        let values = [9, 20, 40, 41, 42, 43];
+        for (register, value) in values.iter().enumerate() {
+            mapper.cpu_write(0x8000, register as u8);
+            mapper.cpu_write(0x8001, *value);
+        }
+
+        // R0 ignores its low bit and covers two 1KB banks
+        assert_eq!(mapper.ppu_read(0x0000), 8);
+        assert_eq!(mapper.ppu_read(0x0400), 9);
+        assert_eq!(mapper.ppu_read(0x0800), 20);
+        assert_eq!(mapper.ppu_read(0x0C00), 21);
+        assert_eq!(mapper.ppu_read(0x1000), 40);
+        assert_eq!(mapper.ppu_read(0x1C00), 43);
+
+        mapper.cpu_write(0x8000, 0b1000_0000);
+        assert_eq!(mapper.ppu_read(0x0000), 40);
+        assert_eq!(mapper.ppu_read(0x0C00), 43);
+        assert_eq!(mapper.ppu_read(0x1000), 8);
+        assert_eq!(mapper.ppu_read(0x1C00), 21);
+    }
+
+    #[test]
+    fn test_mirroring_control() {
+        let mut mapper = mmc3();
+        assert_eq!(mapper.mirroring(), Mirroring::Vertical);
+        mapper.cpu_write(0xA000, 1);
+        assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+        mapper.cpu_write(0xA000, 0);
+        assert_eq!(mapper.mirroring(), Mirroring::Vertical);
+    }
+
+    #[test]
+    fn test_prg_ram_protection() {
+        let mut mapper = mmc3();
+        mapper.cpu_write(0x6000, 0x11);
+        assert_eq!(mapper.cpu_read(0x6000), 0x11);
+
+        mapper.cpu_write(0xA001, 0b1100_0000); // enabled, write protected
+        mapper.cpu_write(0x6000, 0x22);
+        assert_eq!(mapper.cpu_read(0x6000), 0x11);
+
+        mapper.cpu_write(0xA001, 0b0000_0000); // disabled
+        assert_eq!(mapper.cpu_read(0x6000), 0);
+        mapper.cpu_write(0xA001, 0b1000_0000);
+        mapper.cpu_write(0x6000, 0x33);
+        assert_eq!(mapper.cpu_read(0x6000), 0x33);
+    }
+
+    #[test]
+    fn test_scanline_irq() {
+        let mut mapper = mmc3();
+        mapper.cpu_write(0xC000, 3); // latch
+        mapper.cpu_write(0xC001, 0); // reload
+        mapper.cpu_write(0xE001, 0); // enable
+
+        scanline(&mut mapper, 0); // reload to 3
+        scanline(&mut mapper, 1); // 2
+        scanline(&mut mapper, 2); // 1
+        assert!(!mapper.irq_pending());
+        scanline(&mut mapper, 3); // 0
+        assert!(mapper.irq_pending());
+
+        // acknowledge and keep counting from the latch
+        mapper.cpu_write(0xE000, 0);
+        assert!(!mapper.irq_pending());
+        mapper.cpu_write(0xE001, 0);
+        scanline(&mut mapper, 4); // reload to 3
+        assert!(!mapper.irq_pending());
+    }
+
+    #[test]
+    fn test_a12_filter_counts_once_per_scanline() {
+        let mut mapper = mmc3();
+        mapper.cpu_write(0xC000, 10);
+        mapper.cpu_write(0xC001, 0);
+        scanline(&mut mapper, 0);
+        assert_eq!(mapper.irq_counter, 10);
+        scanline(&mut mapper, 1);
+        scanline(&mut mapper, 2);
+        assert_eq!(mapper.irq_counter, 8);
+    }
+
+    #[test]
+    fn test_disabled_irq_never_fires() {
+        let mut mapper = mmc3();
+        mapper.cpu_write(0xC000, 0);
+        mapper.cpu_write(0xC001, 0);
+        for line in 0..4 {
+            scanline(&mut mapper, line);
+        }
+        assert!(!mapper.irq_pending());
+    }
+
+    #[test]
+    fn test_qj_blocks() {
+        // 256KB PRG, 256KB CHR: two games of 128KB each
+        let mut mapper = Mmc3::new_qj(Rom::new(&test_rom(47, 16, 32)).unwrap());
+        assert_eq!(mapper.cpu_read(0xE000), 15);
+        assert_eq!(mapper.ppu_read(0x1C00), 7);
+
+        mapper.cpu_write(0x6000, 1);
+        assert_eq!(mapper.cpu_read(0xE000), 31);
+        assert_eq!(mapper.cpu_read(0x8000), 16);
+        assert_eq!(mapper.ppu_read(0x0000), 128);
+        assert!(mapper.prg_ram().is_none());
+
+        // the latch is locked while PRG RAM is write protected
+        mapper.cpu_write(0xA001, 0b1100_0000);
+        mapper.cpu_write(0x6000, 0);
+        assert_eq!(mapper.cpu_read(0xE000), 31);
+    }
+
+    #[test]
+    fn test_save_load_state() {
+        let mut mapper = mmc3();
+        mapper.cpu_write(0x8000, 6);
+        mapper.cpu_write(0x8001, 7);
+        mapper.cpu_write(0x6010, 0x99);
+        mapper.cpu_write(0xC000, 5);
+        let mut writer = StateWriter::new();
+        mapper.save_state(&mut writer);
+        let data = writer.into_bytes();
+
+        let mut restored = mmc3();
+        restored.load_state(&mut StateReader::new(&data)).unwrap();
+        assert_eq!(restored.cpu_read(0x8000), 7);
+        assert_eq!(restored.cpu_read(0x6010), 0x99);
+        assert_eq!(restored.irq_latch, 5);
+    }
+}
@@ -0,0 +1,200 @@
+use crate::cartridge::{Mirroring, Rom, CHR_ROM_PAGE_SIZE, TRAINER_ADDR};
+use crate::error::RomError;
+use crate::error::StateError;
+use crate::savestate::{StateReader, StateWriter};
+
+pub mod discrete;
+pub mod mmc3;
+pub mod multicart;
+pub mod nrom;
+pub mod nsf;
+
+/* Cartridge boards
+
+   A mapper sees every CPU access in $4020-$FFFF and every PPU pattern table
+   access in $0000-$1FFF. Boards that snoop the PPU address lines (MMC3's A12
+   scanline counter) additionally get `ppu_bus` for each PPU bus access,
+   stamped with the PPU cycle it happened on.
+*/
+
+pub trait Mapper: Send {
+    fn cpu_read(&self, addr: u16) -> u8;
+
+    fn cpu_write(&mut self, addr: u16, data: u8);
+
+    fn ppu_read(&mut self, addr: u16) -> u8;
+
+    fn ppu_write(&mut self, addr: u16, data: u8);
+
+    fn mirroring(&self) -> Mirroring;
+
+    fn irq_pending(&self) -> bool {
+        return false;
+    }
+
+    fn ppu_bus(&mut self, _addr: u16, _ppu_cycle: u64) {}
+
+    // soft reset, which reset-based multicarts count to pick the next game
+    fn reset(&mut self) {}
+
+    // the RAM at $6000-$7FFF, which is what a battery keeps alive between sessions
+    fn prg_ram(&self) -> Option<&[u8]> {
+        return None;
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        return None;
+    }
+
+    // all of the PRG ROM and pattern memory, whatever is banked in, for the
+    // debugger's hex editor; a patch lasts until the cartridge is loaded again
+    fn prg_rom(&self) -> Option<&[u8]> {
+        return None;
+    }
+
+    fn prg_rom_mut(&mut self) -> Option<&mut [u8]> {
+        return None;
+    }
+
+    fn chr(&self) -> Option<&[u8]> {
+        return None;
+    }
+
+    fn chr_mut(&mut self) -> Option<&mut [u8]> {
+        return None;
+    }
+
+    fn save_state(&self, writer: &mut StateWriter);
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError>;
+}
+
+pub fn new_mapper(rom: Rom) -> Result<Box<dyn Mapper>, RomError> {
+    let trainer = rom.trainer.clone();
+    let mut mapper: Box<dyn Mapper> = match rom.mapper {
+        0 => Box::new(nrom::Nrom::new(rom)),
+        4 => Box::new(mmc3::Mmc3::new(rom)),
+        7 | 11 | 34 | 66 => Box::new(discrete::Discrete::new(rom)),
+        47 => Box::new(mmc3::Mmc3::new_qj(rom)),
+        60 | 228 => Box::new(multicart::Multicart::new(rom)),
+        n => return Err(RomError::UnsupportedMapper(n)),
+    };
+    if let Some(trainer) = trainer {
+        let offset = (TRAINER_ADDR - 0x6000) as usize;
+        match mapper.prg_ram_mut() {
+            Some(ram) if ram.len() >= offset + trainer.len() => {
+                ram[offset..offset + trainer.len()].copy_from_slice(&trainer);
+            }
+            _ => return Err(RomError::NoRamForTrainer),
+        }
+    }
+    return Ok(mapper);
+}
+
+// pattern memory, either the CHR ROM from the cartridge or 8KB of CHR RAM
+#[derive(Debug, Clone)]
+pub struct ChrMemory {
+    data: Vec<u8>,
+    writable: bool,
+}
+
+impl ChrMemory {
+    pub fn new(chr_rom: Vec<u8>) -> Self {
+        if chr_rom.is_empty() {
+            return Self {
+                data: vec![0; CHR_ROM_PAGE_SIZE],
+                writable: true,
+            };
+        }
+        return Self {
+            data: chr_rom,
+            writable: false,
+        };
+    }
+
+    pub fn len(&self) -> usize {
+        return self.data.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.data.is_empty();
+    }
+
+    pub fn read(&self, offset: usize) -> u8 {
+        return self.data[offset % self.data.len()];
+    }
+
+    pub fn write(&mut self, offset: usize, data: u8) {
+        if self.writable {
+            let len = self.data.len();
+            self.data[offset % len] = data;
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        return &self.data;
+    }
+
+    // CHR ROM included, for patching it from the debugger
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        return &mut self.data;
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        if self.writable {
+            writer.write_bytes(&self.data);
+        }
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        if self.writable {
+            reader.read_into(&mut self.data)?;
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+
+    #[test]
+    fn test_new_mapper() {
+        assert!(new_mapper(Rom::new(&test_rom(0, 1, 1)).unwrap()).is_ok());
+        assert!(new_mapper(Rom::new(&test_rom(4, 2, 1)).unwrap()).is_ok());
+        assert!(new_mapper(Rom::new(&test_rom(66, 4, 2)).unwrap()).is_ok());
+        assert!(new_mapper(Rom::new(&test_rom(47, 16, 32)).unwrap()).is_ok());
+        assert!(new_mapper(Rom::new(&test_rom(228, 32, 8)).unwrap()).is_ok());
+        let err = new_mapper(Rom::new(&test_rom(99, 1, 1)).unwrap()).err();
+        assert_eq!(err, Some(RomError::UnsupportedMapper(99)));
+    }
+
+    #[test]
+    fn test_trainer_is_loaded_at_7000() {
+        let mut rom = Rom::new(&test_rom(0, 1, 1)).unwrap();
+        rom.trainer = Some((0..=255).chain(0..=255).collect());
+        let mapper = new_mapper(rom).unwrap();
+        assert_eq!(mapper.cpu_read(0x6FFF), 0);
+        assert_eq!(mapper.cpu_read(0x7000), 0);
+        assert_eq!(mapper.cpu_read(0x70FF), 0xFF);
+        assert_eq!(mapper.cpu_read(0x71FF), 0xFF);
+
+        let mut rom = Rom::new(&test_rom(7, 2, 0)).unwrap();
+        rom.trainer = Some(vec![0; 512]);
+        assert!(new_mapper(rom).is_err());
+    }
+
+    #[test]
+    fn test_chr_memory() {
+        let mut rom = ChrMemory::new(vec![1, 2, 3, 4]);
+        rom.write(0, 9);
+        assert_eq!(rom.read(0), 1);
+        assert_eq!(rom.read(5), 2); // mirrored
+
+        let mut ram = ChrMemory::new(vec![]);
+        assert_eq!(ram.len(), CHR_ROM_PAGE_SIZE);
+        ram.write(0x10, 9);
+        assert_eq!(ram.read(0x10), 9);
+    }
+}
@@ -0,0 +1,274 @@
+use crate::cartridge::{Mirroring, Rom, CHR_ROM_PAGE_SIZE, PRG_ROM_PAGE_SIZE};
+use crate::error::StateError;
+use crate::mapper::{ChrMemory, Mapper};
+use crate::savestate::{StateReader, StateWriter};
+
+/* Pirate multicarts
+
+   Boards that glue several games onto one cartridge with a little extra logic
+   instead of a menu-capable ASIC:
+
+    mapper  board      selection
+    60      4-in-1     each console reset advances to the next 16KB PRG / 8KB CHR game
+    228     Action 52  the write address is the register, see below
+
+   Action 52 latches a write anywhere in $8000-$FFFF as
+
+    A13       mirroring, 1 = horizontal
+    A12-A11   PRG chip, the board has no chip 2 so files store chip 3 there
+    A10-A6    16KB PRG page within the 512KB chip
+    A5        1 = 16KB PRG mode, 0 = 32KB
+    A3-A0 D1-D0  8KB CHR bank
+
+   plus four nibbles of RAM mirrored through $4020-$5FFF that the menu uses to
+   remember which game was picked. MMC3 based multicarts (47) live with the
+   MMC3 since they only add an outer bank.
+*/
+
+const CHIP_PAGES: usize = 32; // 512KB chip of 16KB pages
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Board {
+    ResetBased,
+    Action52,
+}
+
+pub struct Multicart {
+    board: Board,
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    mirroring: Mirroring,
+    prg_banks: [usize; 2], // 16KB banks for $8000 and $C000
+    chr_bank: usize,
+    game: usize,
+    nibble_ram: [u8; 4],
+}
+
+impl Multicart {
+    pub fn new(rom: Rom) -> Self {
+        let board = match rom.mapper {
+            60 => Board::ResetBased,
+            _ => Board::Action52,
+        };
+        let mut mapper = Self {
+            board,
+            prg_rom: rom.prg_rom,
+            chr: ChrMemory::new(rom.chr_rom),
+            mirroring: rom.screen_mirroring,
+            prg_banks: [0, 1],
+            chr_bank: 0,
+            game: 0,
+            nibble_ram: [0; 4],
+        };
+        match board {
+            Board::ResetBased => mapper.select_game(0),
+            Board::Action52 => mapper.write_action52(0x8000, 0),
+        }
+        return mapper;
+    }
+
+    pub fn board(&self) -> Board {
+        return self.board;
+    }
+
+    pub fn game(&self) -> usize {
+        return self.game;
+    }
+
+    fn select_game(&mut self, game: usize) {
+        self.game = game;
+        self.prg_banks = [game, game];
+        self.chr_bank = game;
+    }
+
+    fn write_action52(&mut self, addr: u16, data: u8) {
+        let addr = addr as usize;
+        let chip = match (addr >> 11) & 0b11 {
+            3 => 2,
+            chip => chip,
+        };
+        let page = chip * CHIP_PAGES + ((addr >> 6) & 0b1_1111);
+        self.prg_banks = if addr & 0x0020 != 0 {
+            [page, page]
+        } else {
+            [page & !1, page | 1]
+        };
+        self.chr_bank = ((addr & 0x0F) << 2) | (data & 0b11) as usize;
+        self.mirroring = if addr & 0x2000 != 0 {
+            Mirroring::Horizontal
+        } else {
+            Mirroring::Vertical
+        };
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        return self.chr_bank * CHR_ROM_PAGE_SIZE + (addr as usize & 0x1FFF);
+    }
+}
+
+impl Mapper for Multicart {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x4020..=0x5FFF if self.board == Board::Action52 => {
+                return self.nibble_ram[(addr & 0b11) as usize];
+            }
+            0x8000..=0xFFFF => {
+                let banks = self.prg_rom.len().div_ceil(PRG_ROM_PAGE_SIZE);
+                let bank = self.prg_banks[((addr >> 14) & 1) as usize] % banks;
+                let offset = bank * PRG_ROM_PAGE_SIZE + (addr as usize & 0x3FFF);
+                return self.prg_rom[offset % self.prg_rom.len()];
+            }
+            _ => return 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if self.board != Board::Action52 {
+            return;
+        }
+        match addr {
+            0x4020..=0x5FFF => self.nibble_ram[(addr & 0b11) as usize] = data & 0x0F,
+            0x8000..=0xFFFF => self.write_action52(addr, data),
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        return self.chr.read(self.chr_offset(addr));
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        let offset = self.chr_offset(addr);
+        self.chr.write(offset, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        return self.mirroring;
+    }
+
+    fn prg_rom(&self) -> Option<&[u8]> {
+        return Some(&self.prg_rom);
+    }
+
+    fn prg_rom_mut(&mut self) -> Option<&mut [u8]> {
+        return Some(&mut self.prg_rom);
+    }
+
+    fn chr(&self) -> Option<&[u8]> {
+        return Some(self.chr.data());
+    }
+
+    fn chr_mut(&mut self) -> Option<&mut [u8]> {
+        return Some(self.chr.data_mut());
+    }
+
+    fn reset(&mut self) {
+        if self.board == Board::ResetBased {
+            self.select_game((self.game + 1) % 4);
+        }
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.chr.save_state(writer);
+        writer.write_bool(self.mirroring == Mirroring::Horizontal);
+        writer.write_u32(self.prg_banks[0] as u32);
+        writer.write_u32(self.prg_banks[1] as u32);
+        writer.write_u32(self.chr_bank as u32);
+        writer.write_u8(self.game as u8);
+        writer.write_bytes(&self.nibble_ram);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.chr.load_state(reader)?;
+        self.mirroring = if reader.read_bool()? {
+            Mirroring::Horizontal
+        } else {
+            Mirroring::Vertical
+        };
+        self.prg_banks[0] = reader.read_u32()? as usize;
+        self.prg_banks[1] = reader.read_u32()? as usize;
+        self.chr_bank = reader.read_u32()? as usize;
+        self.game = (reader.read_u8()? % 4) as usize;
+        reader.read_into(&mut self.nibble_ram)?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+
+    #[test]
+    fn test_reset_based_cycles_games() {
+        let mut mapper = Multicart::new(Rom::new(&test_rom(60, 4, 4)).unwrap());
+        assert_eq!(mapper.board(), Board::ResetBased);
+        // game 0 is mirrored into both halves
+        assert_eq!(mapper.cpu_read(0x8000), 0);
+        assert_eq!(mapper.cpu_read(0xC000), 0);
+
+        mapper.cpu_write(0x8000, 0xFF); // no latch, ignored
+        mapper.reset();
+        assert_eq!(mapper.game(), 1);
+        assert_eq!(mapper.cpu_read(0x8000), 2);
+        assert_eq!(mapper.cpu_read(0xE000), 3);
+        assert_eq!(mapper.ppu_read(0x0000), 8);
+
+        for _ in 0..3 {
+            mapper.reset();
+        }
+        assert_eq!(mapper.game(), 0);
+        assert_eq!(mapper.cpu_read(0xC000), 0);
+    }
+
+    #[test]
+    fn test_action52_address_latch() {
+        // 1.5MB: chips 0, 1 and 3 stored as 0, 1, 2
+        let mut mapper = Multicart::new(Rom::new(&test_rom(228, 96, 64)).unwrap());
+        assert_eq!(mapper.cpu_read(0x8000), 0);
+        assert_eq!(mapper.cpu_read(0xC000), 2);
+
+        // chip 1, page 3, 16KB mode, horizontal, CHR (5 << 2) | 2
+        let addr = 0x8000 | 0x2000 | (1 << 11) | (3 << 6) | 0x20 | 5;
+        mapper.cpu_write(addr, 2);
+        let page = 32 + 3;
+        assert_eq!(mapper.cpu_read(0x8000), (page * 2) as u8);
+        assert_eq!(mapper.cpu_read(0xC000), (page * 2) as u8);
+        assert_eq!(mapper.ppu_read(0x0000), (22 * 8) as u8);
+        assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+
+        // chip 3 is the third chip in the file, 32KB mode pairs the pages
+        mapper.cpu_write(0x8000 | (3 << 11) | (5 << 6), 0);
+        let page = 64 + 4;
+        assert_eq!(mapper.cpu_read(0x8000), (page * 2) as u8);
+        assert_eq!(mapper.cpu_read(0xC000), ((page + 1) * 2) as u8);
+        assert_eq!(mapper.mirroring(), Mirroring::Vertical);
+    }
+
+    #[test]
+    fn test_action52_nibble_ram() {
+        let mut mapper = Multicart::new(Rom::new(&test_rom(228, 32, 8)).unwrap());
+        mapper.cpu_write(0x5FF1, 0xAB);
+        assert_eq!(mapper.cpu_read(0x5FF1), 0x0B);
+        assert_eq!(mapper.cpu_read(0x4021), 0x0B); // mirrored
+        mapper.reset(); // the menu survives a reset
+        assert_eq!(mapper.cpu_read(0x5FF1), 0x0B);
+    }
+
+    #[test]
+    fn test_save_load_state() {
+        let mut mapper = Multicart::new(Rom::new(&test_rom(60, 4, 4)).unwrap());
+        mapper.reset();
+        mapper.reset();
+        let mut writer = StateWriter::new();
+        mapper.save_state(&mut writer);
+        let data = writer.into_bytes();
+
+        let mut restored = Multicart::new(Rom::new(&test_rom(60, 4, 4)).unwrap());
+        restored.load_state(&mut StateReader::new(&data)).unwrap();
+        assert_eq!(restored.game(), 2);
+        assert_eq!(restored.cpu_read(0x8000), 4);
+        restored.reset();
+        assert_eq!(restored.game(), 3);
+    }
+}
@@ -0,0 +1,118 @@
+use crate::cartridge::{Mirroring, Rom, PRG_RAM_PAGE_SIZE};
+use crate::error::StateError;
+use crate::mapper::{ChrMemory, Mapper};
+use crate::savestate::{StateReader, StateWriter};
+
+// mapper 0: 16KB or 32KB of PRG ROM, 8KB of CHR, no bank switching
+pub struct Nrom {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: ChrMemory,
+    mirroring: Mirroring,
+}
+
+impl Nrom {
+    pub fn new(rom: Rom) -> Self {
+        return Self {
+            prg_rom: rom.prg_rom,
+            prg_ram: vec![0; PRG_RAM_PAGE_SIZE],
+            chr: ChrMemory::new(rom.chr_rom),
+            mirroring: rom.screen_mirroring,
+        };
+    }
+}
+
+impl Mapper for Nrom {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => return self.prg_ram[(addr - 0x6000) as usize],
+            0x8000..=0xFFFF => {
+                // a 16KB board mirrors its single bank into $C000-$FFFF
+                let offset = (addr - 0x8000) as usize % self.prg_rom.len();
+                return self.prg_rom[offset];
+            }
+            _ => return 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if let 0x6000..=0x7FFF = addr {
+            self.prg_ram[(addr - 0x6000) as usize] = data;
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        return self.chr.read(addr as usize);
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.chr.write(addr as usize, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        return self.mirroring;
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        return Some(&self.prg_ram);
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        return Some(&mut self.prg_ram);
+    }
+
+    fn prg_rom(&self) -> Option<&[u8]> {
+        return Some(&self.prg_rom);
+    }
+
+    fn prg_rom_mut(&mut self) -> Option<&mut [u8]> {
+        return Some(&mut self.prg_rom);
+    }
+
+    fn chr(&self) -> Option<&[u8]> {
+        return Some(self.chr.data());
+    }
+
+    fn chr_mut(&mut self) -> Option<&mut [u8]> {
+        return Some(self.chr.data_mut());
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.prg_ram);
+        self.chr.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        reader.read_into(&mut self.prg_ram)?;
+        return self.chr.load_state(reader);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+
+    #[test]
+    fn test_16kb_prg_is_mirrored() {
+        let mut raw = test_rom(0, 1, 1);
+        raw[16] = 0xAB; // first PRG byte
+        let nrom = Nrom::new(Rom::new(&raw).unwrap());
+        assert_eq!(nrom.cpu_read(0x8000), 0xAB);
+        assert_eq!(nrom.cpu_read(0xC000), 0xAB);
+        assert_eq!(nrom.cpu_read(0xA000), 1);
+        assert_eq!(nrom.cpu_read(0xE000), 1);
+    }
+
+    #[test]
+    fn test_prg_ram_and_chr_rom() {
+        let mut nrom = Nrom::new(Rom::new(&test_rom(0, 2, 1)).unwrap());
+        nrom.cpu_write(0x6123, 0x55);
+        assert_eq!(nrom.cpu_read(0x6123), 0x55);
+        assert_eq!(nrom.cpu_read(0xE000), 3);
+
+        nrom.ppu_write(0x0400, 0xFF);
+        assert_eq!(nrom.ppu_read(0x0400), 1);
+        assert_eq!(nrom.mirroring(), Mirroring::Vertical);
+    }
+}
@@ -0,0 +1,143 @@
+use crate::cartridge::{Mirroring, PRG_RAM_PAGE_SIZE};
+use crate::error::StateError;
+use crate::mapper::Mapper;
+use crate::nsf::Nsf;
+use crate::savestate::{StateReader, StateWriter};
+
+const BANK_SIZE: usize = 0x1000;
+const BANK_REGISTERS: u16 = 0x5FF8;
+
+/* NSF board
+
+   Not a real cartridge, just the memory layout NSF rips expect: 8KB of RAM at
+   $6000-$7FFF and $8000-$FFFF split into eight 4KB windows. Bankswitched
+   tunes select each window through $5FF8-$5FFF; for the others the data is
+   simply placed at its load address.
+*/
+
+pub struct NsfMapper {
+    prg: Vec<u8>,
+    prg_ram: Vec<u8>,
+    banks: [u8; 8],
+    bankswitched: bool,
+}
+
+impl NsfMapper {
+    pub fn new(nsf: &Nsf) -> Self {
+        let bankswitched = nsf.is_bankswitched();
+        let (prg, initial_banks) = if bankswitched {
+            // the data is laid out from the start of the 4KB bank its load address falls in
+            let padding = (nsf.load_addr as usize) & (BANK_SIZE - 1);
+            let mut prg = vec![0; padding];
+            prg.extend_from_slice(&nsf.data);
+            prg.resize(prg.len().div_ceil(BANK_SIZE) * BANK_SIZE, 0);
+            (prg, nsf.bankswitch_init)
+        } else {
+            let mut prg = vec![0; 0x8000];
+            let start = (nsf.load_addr as usize).saturating_sub(0x8000);
+            let len = nsf.data.len().min(prg.len() - start);
+            prg[start..start + len].copy_from_slice(&nsf.data[..len]);
+            (prg, [0, 1, 2, 3, 4, 5, 6, 7])
+        };
+        return Self {
+            prg,
+            prg_ram: vec![0; PRG_RAM_PAGE_SIZE],
+            banks: initial_banks,
+            bankswitched,
+        };
+    }
+
+    pub fn banks(&self) -> [u8; 8] {
+        return self.banks;
+    }
+}
+
+impl Mapper for NsfMapper {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => return self.prg_ram[(addr - 0x6000) as usize],
+            0x8000..=0xFFFF => {
+                let window = (addr - 0x8000) as usize / BANK_SIZE;
+                let bank = self.banks[window] as usize % (self.prg.len() / BANK_SIZE);
+                return self.prg[bank * BANK_SIZE + (addr as usize % BANK_SIZE)];
+            }
+            _ => return 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            BANK_REGISTERS..=0x5FFF if self.bankswitched => {
+                self.banks[(addr - BANK_REGISTERS) as usize] = data;
+            }
+            0x6000..=0x7FFF => self.prg_ram[(addr - 0x6000) as usize] = data,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, _addr: u16) -> u8 {
+        return 0;
+    }
+
+    fn ppu_write(&mut self, _addr: u16, _data: u8) {}
+
+    fn mirroring(&self) -> Mirroring {
+        return Mirroring::Horizontal;
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        return Some(&self.prg_ram);
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        return Some(&mut self.prg_ram);
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.prg_ram);
+        writer.write_bytes(&self.banks);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        reader.read_into(&mut self.prg_ram)?;
+        reader.read_into(&mut self.banks)?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::nsf::test::test_nsf;
+
+    #[test]
+    fn test_flat_layout() {
+        let nsf = Nsf::parse(&test_nsf(0xC000, [0; 8], &[0x11, 0x22])).unwrap();
+        let mut mapper = NsfMapper::new(&nsf);
+        assert_eq!(mapper.cpu_read(0xC000), 0x11);
+        assert_eq!(mapper.cpu_read(0xC001), 0x22);
+        assert_eq!(mapper.cpu_read(0x8000), 0);
+        mapper.cpu_write(0x5FF8, 3); // ignored without bankswitching
+        assert_eq!(mapper.banks()[0], 0);
+    }
+
+    #[test]
+    fn test_bankswitching() {
+        // three 4KB banks filled with their index, loaded at $8100
+        let mut data = Vec::new();
+        for bank in 0..3u8 {
+            data.extend(vec![bank; BANK_SIZE]);
+        }
+        let nsf = Nsf::parse(&test_nsf(0x8100, [2, 1, 0, 0, 0, 0, 0, 0], &data)).unwrap();
+        // the 256 byte padding shifts every 4KB bank of the image by $100
+        let mut mapper = NsfMapper::new(&nsf);
+        assert_eq!(mapper.cpu_read(0x8000), 1);
+        assert_eq!(mapper.cpu_read(0x8100), 2);
+        assert_eq!(mapper.cpu_read(0x9000), 0);
+        assert_eq!(mapper.cpu_read(0x9100), 1);
+        mapper.cpu_write(0x5FFF, 3);
+        assert_eq!(mapper.cpu_read(0xF000), 2);
+        assert_eq!(mapper.cpu_read(0xF100), 0);
+        assert_eq!(mapper.banks(), [2, 1, 0, 0, 0, 0, 0, 3]);
+    }
+}
@@ -0,0 +1,182 @@
+use crate::input::PLAYERS;
+use crate::savestate::{StateReader, StateWriter};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"RNMV";
+const VERSION: u8 = 3;
+// recording keeps a checksum of the console every this many frames
+pub const CHECKSUM_INTERVAL: usize = 60;
+
+/* Input movies
+
+   A movie is the save state it starts from plus, for every frame after
+   it, the buttons each player held and whether the console was reset or
+   power cycled just before the frame ran. Since the core is deterministic,
+   loading the state and feeding the frames back in reproduces the run
+   exactly. Only controller buttons are recorded, a run that relies on
+   other devices (the keyboard, the Miracle Piano) won't play back.
+
+   The file is "RNMV", a version byte, the start state as a length
+   prefixed block, a u32 frame count, then per frame one event byte
+   (0 none, 1 reset, 2 power) and one button byte per player, and last a
+   u32 count of rerecords, the times the run's input was redone from an
+   earlier point (see `tas`). Then a u32 count of checksums, each a u32
+   frame number and the CRC-32 of the save state at the start of that
+   frame. Version 1 files end before the rerecords and version 2 before
+   the checksums, and load with none.
+
+   The checksums are how a movie notices it no longer plays the way it
+   was recorded, after a change to the core, say: recording takes one
+   every `CHECKSUM_INTERVAL` frames, playback compares them as it gets
+   there (see `nes`) and reports the first that's off. That makes a
+   movie of a game a regression test that runs as long as the movie.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MovieEvent {
+    Reset,
+    Power,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MovieFrame {
+    pub buttons: [u8; PLAYERS],
+    pub event: Option<MovieEvent>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Movie {
+    pub start: Vec<u8>, // a save state, as from `savestate::save`
+    pub frames: Vec<MovieFrame>,
+    pub rerecords: u32,
+    pub checksums: BTreeMap<usize, u32>, // the console at the start of frame n
+}
+
+impl Movie {
+    pub fn new(start: Vec<u8>) -> Self {
+        return Self {
+            start,
+            frames: Vec::new(),
+            rerecords: 0,
+            checksums: BTreeMap::new(),
+        };
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        for &byte in MAGIC {
+            writer.write_u8(byte);
+        }
+        writer.write_u8(VERSION);
+        writer.write_bytes(&self.start);
+        writer.write_u32(self.frames.len() as u32);
+        for frame in &self.frames {
+            writer.write_u8(match frame.event {
+                None => 0,
+                Some(MovieEvent::Reset) => 1,
+                Some(MovieEvent::Power) => 2,
+            });
+            for &buttons in &frame.buttons {
+                writer.write_u8(buttons);
+            }
+        }
+        writer.write_u32(self.rerecords);
+        writer.write_u32(self.checksums.len() as u32);
+        for (&frame, &checksum) in &self.checksums {
+            writer.write_u32(frame as u32);
+            writer.write_u32(checksum);
+        }
+        return writer.into_bytes();
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Movie, String> {
+        let mut reader = StateReader::new(data);
+        for &byte in MAGIC {
+            if reader.read_u8().ok() != Some(byte) {
+                return Err("not a rustynes movie".to_string());
+            }
+        }
+        let version = reader.read_u8()?;
+        if version == 0 || version > VERSION {
+            return Err(format!("unsupported movie version {}", version));
+        }
+        let mut movie = Movie::new(reader.read_bytes()?.to_vec());
+        for _ in 0..reader.read_u32()? {
+            let event = match reader.read_u8()? {
+                0 => None,
+                1 => Some(MovieEvent::Reset),
+                2 => Some(MovieEvent::Power),
+                event => return Err(format!("unknown movie event {}", event)),
+            };
+            let mut frame = MovieFrame {
+                event,
+                ..MovieFrame::default()
+            };
+            for buttons in frame.buttons.iter_mut() {
+                *buttons = reader.read_u8()?;
+            }
+            movie.frames.push(frame);
+        }
+        if version > 1 {
+            movie.rerecords = reader.read_u32()?;
+        }
+        if version > 2 {
+            for _ in 0..reader.read_u32()? {
+                let frame = reader.read_u32()? as usize;
+                movie.checksums.insert(frame, reader.read_u32()?);
+            }
+        }
+        if !reader.is_empty() {
+            return Err("trailing data after movie".to_string());
+        }
+        return Ok(movie);
+    }
+
+    pub fn load(path: &Path) -> Result<Movie, String> {
+        let data = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        return Movie::from_bytes(&data).map_err(|e| format!("{}: {}", path.display(), e));
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        return fs::write(path, self.to_bytes()).map_err(|e| format!("{}: {}", path.display(), e));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bytes_round_trip() {
+        let mut movie = Movie::new(vec![1, 2, 3]);
+        movie.frames.push(MovieFrame {
+            buttons: [0x01, 0x80, 0, 0],
+            event: None,
+        });
+        movie.frames.push(MovieFrame {
+            buttons: [0; PLAYERS],
+            event: Some(MovieEvent::Power),
+        });
+        movie.rerecords = 7;
+        movie.checksums.insert(60, 0xDEADBEEF);
+        let data = movie.to_bytes();
+        assert_eq!(Movie::from_bytes(&data), Ok(movie.clone()));
+
+        // a version 2 file, without the checksums
+        let mut old = data[..data.len() - 12].to_vec();
+        old[4] = 2;
+        movie.checksums.clear();
+        assert_eq!(Movie::from_bytes(&old), Ok(movie.clone()));
+
+        // and version 1, without the rerecords either
+        old.truncate(old.len() - 4);
+        old[4] = 1;
+        movie.rerecords = 0;
+        assert_eq!(Movie::from_bytes(&old), Ok(movie));
+
+        assert!(Movie::from_bytes(b"RNES").is_err());
+        assert!(Movie::from_bytes(&data[..data.len() - 1]).is_err());
+    }
+}
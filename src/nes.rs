@@ -0,0 +1,917 @@
+use crate::apu::Apu;
+use crate::bus::Bus;
+use crate::cartridge::Rom;
+use crate::cpu::CPU;
+use crate::error::{EmuError, RomError};
+use crate::hash;
+use crate::input::joypad::JoypadState;
+use crate::input::{ControllerPorts, PLAYERS};
+use crate::movie::{Movie, MovieEvent, MovieFrame, CHECKSUM_INTERVAL};
+use crate::observer::{
+    ConsoleEvent, ConsoleEventKind, EventCallback, EventTap, Observers, Subscription, Topic,
+};
+use crate::ppu::palette::{Colours, SYSTEM_PALETTE};
+use crate::ppu::RenderMode;
+use crate::savestate;
+use crate::trace::History;
+use crate::video::{self, Frame, ScreenshotOptions};
+use std::path::Path;
+
+/* Console
+
+   The console as a frontend sees it: a cartridge in a running CPU and bus,
+   driven a frame at a time. A frame runs from the start of the visible
+   picture to the start of the next, so input set before `run_frame` is
+   what the game reads during it. Library code can set it directly with
+   `set_input`, a player at a time, without going through bindings, and
+   take the picture with `frame`, the sound with `audio_samples` and the
+   whole console with `save_state`.
+
+   It also records and plays back input movies. Recording starts from a
+   save state of the moment it began and samples the controllers at the
+   start of every frame; reset and power presses in between are stored
+   with the frame that follows them. Playback loads the movie's state and,
+   frame by frame, replaces whatever the frontend set with the recorded
+   buttons, until the movie runs out or is stopped. Recording also
+   checksums the console every `CHECKSUM_INTERVAL` frames, and playback
+   checks it against those; the first that doesn't match is kept as the
+   movie's `Desync`, and the movie goes on playing, as the frontend may
+   want to see how far off it is.
+
+   How the console is put together is up to `NesBuilder`, from
+   `Nes::builder()`: the mapper when the header has it wrong, the palette,
+   what work RAM holds at power on, the renderer, whether the sprite
+   overflow bug is reproduced and the sound's sample rate. There is no
+   region to choose, as only NTSC timing is emulated: a PAL game's header
+   says so, but it runs NTSC's scanlines and clocks like any other.
+   `Nes::new` is the builder left as it is. Power cycling and swapping the
+   cartridge keep all but the mapper, which is the game's. A dump
+   `romdb` knows has its header corrected before any of that, here and
+   when the cartridge is swapped.
+
+   Library code can also listen to the console, with a callback on each
+   frame finished, vblank, a scanline, IRQ or mapper write, or by watching
+   some of those and draining their events once a frame; see `observer`.
+
+   Run-ahead hides the frames of lag a game adds between reading the
+   controllers and showing the result: after each frame it saves the
+   console, runs `frames` more with the input held as it is, keeps the
+   last of their pictures and loads the state back. The sound and the
+   debugging recorders only hear the real frame. What a game does with a
+   button shows up that many frames sooner, at the cost of running that
+   many frames more; more frames than the game's own lag get ahead of it
+   and it starts to look jumpy.
+*/
+
+enum MovieMode {
+    Idle,
+    Recording(Movie),
+    Playing(Movie, usize), // the next frame to play
+}
+
+// where a movie stopped playing the way it was recorded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Desync {
+    pub frame: usize,              // the first frame whose checksum didn't match
+    pub last_match: Option<usize>, // the one before it that did
+    pub expected: u32,
+    pub actual: u32,
+}
+
+impl std::fmt::Display for Desync {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "movie desynced by frame {} (checksum {:08X}, recorded {:08X})",
+            self.frame, self.actual, self.expected
+        )?;
+        if let Some(frame) = self.last_match {
+            write!(f, ", it matched at frame {}", frame)?;
+        }
+        return Ok(());
+    }
+}
+
+// what work RAM holds at power on; the console's is whatever the chips
+// settle to, which some games read before writing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RamInit {
+    #[default]
+    Zeros,
+    AllFF,
+    Random(u64), // from the seed, the same at every power on
+}
+
+impl RamInit {
+    pub fn fill(self, ram: &mut [u8]) {
+        match self {
+            RamInit::Zeros => ram.fill(0),
+            RamInit::AllFF => ram.fill(0xFF),
+            RamInit::Random(seed) => {
+                // xorshift64*, which needs a state that isn't 0
+                let mut state = seed | 1;
+                for byte in ram.iter_mut() {
+                    state ^= state >> 12;
+                    state ^= state << 25;
+                    state ^= state >> 27;
+                    *byte = (state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NesBuilder {
+    mapper: Option<u16>,
+    palette: Colours,
+    ram_init: RamInit,
+    render_mode: RenderMode,
+    accurate_sprite_overflow: bool,
+    sample_rate: Option<u32>,
+}
+
+impl Default for NesBuilder {
+    fn default() -> Self {
+        return Self {
+            mapper: None,
+            palette: SYSTEM_PALETTE,
+            ram_init: RamInit::default(),
+            render_mode: RenderMode::default(),
+            accurate_sprite_overflow: true,
+            sample_rate: None,
+        };
+    }
+}
+
+impl NesBuilder {
+    // over the header's, for a dump with the wrong number in it
+    pub fn mapper_override(mut self, mapper: u16) -> Self {
+        self.mapper = Some(mapper);
+        return self;
+    }
+
+    pub fn palette(mut self, colours: Colours) -> Self {
+        self.palette = colours;
+        return self;
+    }
+
+    pub fn ram_init(mut self, init: RamInit) -> Self {
+        self.ram_init = init;
+        return self;
+    }
+
+    pub fn render_mode(mut self, mode: RenderMode) -> Self {
+        self.render_mode = mode;
+        return self;
+    }
+
+    // on unless turned off, see `ppu::sprite`
+    pub fn accurate_sprite_overflow(mut self, accurate: bool) -> Self {
+        self.accurate_sprite_overflow = accurate;
+        return self;
+    }
+
+    pub fn sample_rate(mut self, rate: u32) -> Self {
+        self.sample_rate = Some(rate);
+        return self;
+    }
+
+    // the console switched on with `rom` in it
+    pub fn build(self, mut rom: Rom) -> Result<Nes, EmuError> {
+        rom.correct_header();
+        rom.mapper = self.mapper.unwrap_or(rom.mapper);
+        let mut bus = Bus::from_rom(rom.clone())?;
+        bus.ppu.set_palette(&self.palette);
+        bus.ppu.render_mode = self.render_mode;
+        bus.ppu.accurate_sprite_overflow = self.accurate_sprite_overflow;
+        if let Some(rate) = self.sample_rate {
+            bus.apu = Apu::new(rate);
+        }
+        self.ram_init.fill(bus.ram_mut());
+        let mut cpu = CPU::with_bus(bus);
+        cpu.halt_on_brk = false;
+        cpu.reset();
+        return Ok(Nes {
+            cpu,
+            rom,
+            ram_init: self.ram_init,
+            movie: MovieMode::Idle,
+            pending_event: None,
+            last_match: None,
+            desync: None,
+            observers: Observers::default(),
+        });
+    }
+}
+
+pub struct Nes {
+    pub cpu: CPU,
+    rom: Rom,
+    ram_init: RamInit,
+    movie: MovieMode,
+    pending_event: Option<MovieEvent>,
+    last_match: Option<usize>,
+    desync: Option<Desync>,
+    observers: Observers,
+}
+
+impl Nes {
+    pub fn new(rom: Rom) -> Result<Nes, EmuError> {
+        return Nes::builder().build(rom);
+    }
+
+    pub fn builder() -> NesBuilder {
+        return NesBuilder::default();
+    }
+
+    // from the bytes of a .nes file
+    pub fn with_rom(data: &[u8]) -> Result<Nes, EmuError> {
+        return Nes::new(Rom::new(data)?);
+    }
+
+    pub fn rom(&self) -> &Rom {
+        return &self.rom;
+    }
+
+    pub fn ports_mut(&mut self) -> &mut ControllerPorts {
+        return self.cpu.bus.ports_mut();
+    }
+
+    // `player` 0-3, players 1 and 2 being the controllers on ports 1 and 2;
+    // holds until changed, and is overridden while a movie plays
+    pub fn set_input(&mut self, player: usize, state: JoypadState) {
+        self.cpu.bus.ports_mut().set_buttons(player, state.bits());
+    }
+
+    pub fn input(&mut self, player: usize) -> JoypadState {
+        return JoypadState::from_bits(self.cpu.bus.ports_mut().buttons(player));
+    }
+
+    pub fn frame(&self) -> &Frame {
+        return self.cpu.bus.ppu.frame();
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        return self.cpu.bus.ppu.frame_count();
+    }
+
+    // the sound since the last call, mono at `sample_rate`
+    pub fn audio_samples(&mut self) -> Vec<f32> {
+        return self.cpu.bus.apu.take_samples();
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        return self.cpu.bus.apu.sample_rate();
+    }
+
+    // the console as `savestate` writes it, for `load_state`
+    pub fn save_state(&self) -> Vec<u8> {
+        return savestate::save(&self.cpu);
+    }
+
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), EmuError> {
+        return Ok(savestate::load(&mut self.cpu, state)?);
+    }
+
+    // the last finished frame as a PNG
+    pub fn screenshot(&self, path: &Path, options: &ScreenshotOptions) -> Result<(), String> {
+        return video::save_screenshot(self.frame(), path, options);
+    }
+
+    pub fn run_frame(&mut self) {
+        self.run_frame_with(|_| true);
+    }
+
+    // with `before` looking at the CPU ahead of each instruction, false if
+    // it stopped the frame there
+    pub fn run_frame_with(&mut self, before: impl FnMut(&mut CPU) -> bool) -> bool {
+        self.update_movie();
+        return self.finish_frame_with(before);
+    }
+
+    // the rest of a frame `run_frame_with` stopped partway through
+    pub fn finish_frame_with(&mut self, before: impl FnMut(&mut CPU) -> bool) -> bool {
+        if !self.run_to_frame_end(before) {
+            return false;
+        }
+        self.checksum_movie();
+        let ppu = &self.cpu.bus.ppu;
+        let event = ConsoleEvent {
+            frame: ppu.frame_count() - 1,
+            scanline: ppu.scanline(),
+            dot: ppu.dot(),
+            kind: ConsoleEventKind::FrameComplete,
+        };
+        self.observers.dispatch(&event, &self.cpu);
+        return true;
+    }
+
+    fn run_to_frame_end(&mut self, mut before: impl FnMut(&mut CPU) -> bool) -> bool {
+        let frame = self.frame_count();
+        while self.frame_count() == frame {
+            if !before(&mut self.cpu) {
+                return false;
+            }
+            self.cpu.step();
+            if self
+                .cpu
+                .bus
+                .tap
+                .as_ref()
+                .is_some_and(|tap| tap.has_pending())
+            {
+                self.deliver_events();
+            }
+        }
+        return true;
+    }
+
+    // what the bus heard during the last instruction, to whoever is listening
+    fn deliver_events(&mut self) {
+        let Some(tap) = &mut self.cpu.bus.tap else {
+            return;
+        };
+        for event in tap.take() {
+            self.observers.dispatch(&event, &self.cpu);
+        }
+    }
+
+    // `callback` on every frame run, after it ends
+    pub fn on_frame_complete(
+        &mut self,
+        callback: impl FnMut(&ConsoleEvent, &CPU) + Send + 'static,
+    ) -> Subscription {
+        return self.subscribe(Topic::FrameComplete, Box::new(callback));
+    }
+
+    // `callback` at the start of vblank, scanline 241
+    pub fn on_vblank(
+        &mut self,
+        callback: impl FnMut(&ConsoleEvent, &CPU) + Send + 'static,
+    ) -> Subscription {
+        return self.subscribe(Topic::Vblank, Box::new(callback));
+    }
+
+    // `callback` as the PPU starts `scanline`, 0-261
+    pub fn on_scanline(
+        &mut self,
+        scanline: u16,
+        callback: impl FnMut(&ConsoleEvent, &CPU) + Send + 'static,
+    ) -> Subscription {
+        return self.subscribe(Topic::Scanline(scanline), Box::new(callback));
+    }
+
+    // `callback` as the CPU takes an IRQ, from the mapper or the APU
+    pub fn on_irq(
+        &mut self,
+        callback: impl FnMut(&ConsoleEvent, &CPU) + Send + 'static,
+    ) -> Subscription {
+        return self.subscribe(Topic::Irq, Box::new(callback));
+    }
+
+    // `callback` on a CPU write to the cartridge, PRG RAM aside
+    pub fn on_mapper_write(
+        &mut self,
+        callback: impl FnMut(&ConsoleEvent, &CPU) + Send + 'static,
+    ) -> Subscription {
+        return self.subscribe(Topic::MapperWrite, Box::new(callback));
+    }
+
+    pub fn subscribe(&mut self, topic: Topic, callback: EventCallback) -> Subscription {
+        let subscription = self.observers.subscribe(topic, callback);
+        self.update_tap();
+        return subscription;
+    }
+
+    pub fn unsubscribe(&mut self, subscription: Subscription) {
+        self.observers.unsubscribe(subscription);
+        self.update_tap();
+    }
+
+    // queues `topic`'s events for `drain_events`
+    pub fn watch(&mut self, topic: Topic) {
+        self.observers.watch(topic);
+        self.update_tap();
+    }
+
+    pub fn unwatch(&mut self, topic: Topic) {
+        self.observers.unwatch(topic);
+        self.update_tap();
+    }
+
+    // the watched events since the last call, oldest first
+    pub fn drain_events(&mut self) -> Vec<ConsoleEvent> {
+        return self.observers.drain();
+    }
+
+    // a tap on the bus for the topics it can hear, none when nobody's listening
+    fn update_tap(&mut self) {
+        let topics: Vec<Topic> = self
+            .observers
+            .topics()
+            .into_iter()
+            .filter(|&topic| topic != Topic::FrameComplete)
+            .collect();
+        self.deliver_events();
+        self.cpu.bus.tap = (!topics.is_empty()).then(|| Box::new(EventTap::new(topics)));
+    }
+
+    // a frame with `input` held, after its reset or power press, as a
+    // movie plays it
+    pub fn run_input_frame(&mut self, input: MovieFrame) {
+        match input.event {
+            Some(MovieEvent::Reset) => self.reset(),
+            Some(MovieEvent::Power) => self.power_cycle(),
+            None => {}
+        }
+        let ports = self.cpu.bus.ports_mut();
+        for player in 0..PLAYERS {
+            ports.set_buttons(player, input.buttons[player]);
+        }
+        self.run_frame();
+    }
+
+    // a frame, then `frames` more run and rolled back, leaving their last
+    // picture to show
+    pub fn run_frame_ahead(&mut self, frames: usize) {
+        self.run_frame();
+        if frames == 0 {
+            return;
+        }
+        let state = savestate::save(&self.cpu);
+        let output = self.cpu.bus.apu.output_stage();
+        let (instructions, jammed) = (self.cpu.instructions, self.cpu.jammed);
+        let calls = self.cpu.calls.clone();
+        let history = std::mem::take(&mut self.cpu.history);
+        let profiler = self.cpu.profiler.take();
+        let stats = self.cpu.stats.take();
+        let heatmap = self.cpu.bus.heatmap.take();
+        let events = self.cpu.bus.events.take();
+        let access = self.cpu.bus.access.take();
+        let tap = self.cpu.bus.tap.take();
+        let scope = self.cpu.bus.apu.scope.take();
+        for _ in 0..frames {
+            self.run_to_frame_end(|_| true);
+        }
+        savestate::load(&mut self.cpu, &state).expect("a state saved a moment ago");
+        self.cpu.bus.apu.set_output_stage(output);
+        (self.cpu.instructions, self.cpu.jammed) = (instructions, jammed);
+        self.cpu.calls = calls;
+        self.cpu.history = history;
+        self.cpu.profiler = profiler;
+        self.cpu.stats = stats;
+        self.cpu.bus.heatmap = heatmap;
+        self.cpu.bus.events = events;
+        self.cpu.bus.access = access;
+        self.cpu.bus.tap = tap;
+        self.cpu.bus.apu.scope = scope;
+    }
+
+    // the reset button
+    pub fn reset(&mut self) {
+        self.cpu.soft_reset();
+        self.record_event(MovieEvent::Reset);
+    }
+
+    // switching the console off and on again, the same cartridge and controllers stay in
+    pub fn power_cycle(&mut self) {
+        self.power_on();
+        self.record_event(MovieEvent::Power);
+    }
+
+    // pulling the cartridge and putting `rom` in its place, then switching
+    // on; the controllers, palette and render mode stay as they were, the
+    // cheats and any movie go with the old game
+    pub fn swap_cartridge(&mut self, mut rom: Rom) -> Result<(), EmuError> {
+        rom.correct_header();
+        let mut bus = self.new_bus(rom.clone())?;
+        *bus.ports_mut() = std::mem::take(self.cpu.bus.ports_mut());
+        let history = History::new(self.cpu.history.capacity());
+        let profiler = self.cpu.profiler.take();
+        let stats = self.cpu.stats.take();
+        bus.heatmap = self.cpu.bus.heatmap.take();
+        bus.events = self.cpu.bus.events.take();
+        bus.access = self.cpu.bus.access.take();
+        bus.tap = self.cpu.bus.tap.take();
+        self.cpu = CPU::with_bus(bus);
+        self.cpu.history = history;
+        self.cpu.profiler = profiler;
+        self.cpu.stats = stats;
+        self.cpu.halt_on_brk = false;
+        self.cpu.reset();
+        self.rom = rom;
+        self.movie = MovieMode::Idle;
+        self.pending_event = None;
+        self.desync = None;
+        return Ok(());
+    }
+
+    // a bus for `rom` built the way this one was, with the palette and
+    // renderer as they are now
+    fn new_bus(&self, rom: Rom) -> Result<Bus, RomError> {
+        let mut bus = Bus::from_rom(rom)?;
+        let (ppu, apu) = (&self.cpu.bus.ppu, &self.cpu.bus.apu);
+        bus.ppu.set_palette(ppu.system_palette());
+        bus.ppu.render_mode = ppu.render_mode;
+        bus.ppu.accurate_sprite_overflow = ppu.accurate_sprite_overflow;
+        if apu.sample_rate() != bus.apu.sample_rate() {
+            bus.apu = Apu::new(apu.sample_rate());
+        }
+        self.ram_init.fill(bus.ram_mut());
+        return Ok(bus);
+    }
+
+    fn power_on(&mut self) {
+        let ports = std::mem::take(self.cpu.bus.ports_mut());
+        let cheats = std::mem::take(self.cpu.bus.cheats_mut());
+        let mut bus = self
+            .new_bus(self.rom.clone())
+            .expect("the ROM loaded before");
+        *bus.ports_mut() = ports;
+        *bus.cheats_mut() = cheats;
+        bus.heatmap = self.cpu.bus.heatmap.take();
+        bus.events = self.cpu.bus.events.take();
+        bus.access = self.cpu.bus.access.take();
+        bus.tap = self.cpu.bus.tap.take();
+        let history = std::mem::take(&mut self.cpu.history);
+        let profiler = self.cpu.profiler.take();
+        let stats = self.cpu.stats.take();
+        self.cpu = CPU::with_bus(bus);
+        self.cpu.history = history;
+        self.cpu.profiler = profiler;
+        self.cpu.stats = stats;
+        self.cpu.halt_on_brk = false;
+        self.cpu.reset();
+    }
+
+    fn record_event(&mut self, event: MovieEvent) {
+        if let MovieMode::Recording(_) = self.movie {
+            self.pending_event = Some(event);
+        }
+    }
+
+    pub fn start_recording(&mut self) {
+        self.movie = MovieMode::Recording(Movie::new(savestate::save(&self.cpu)));
+        self.pending_event = None;
+    }
+
+    pub fn play(&mut self, movie: Movie) -> Result<(), EmuError> {
+        savestate::load(&mut self.cpu, &movie.start)?;
+        self.movie = MovieMode::Playing(movie, 0);
+        self.pending_event = None;
+        self.last_match = None;
+        self.desync = None;
+        return Ok(());
+    }
+
+    // ends recording or playback, handing back the movie
+    pub fn stop(&mut self) -> Option<Movie> {
+        match std::mem::replace(&mut self.movie, MovieMode::Idle) {
+            MovieMode::Idle => return None,
+            MovieMode::Recording(movie) | MovieMode::Playing(movie, _) => return Some(movie),
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        return matches!(self.movie, MovieMode::Recording(_));
+    }
+
+    pub fn is_playing(&self) -> bool {
+        return matches!(self.movie, MovieMode::Playing(..));
+    }
+
+    // the first frame the movie played differently from its recording,
+    // kept after it ends until another is played
+    pub fn desync(&self) -> Option<Desync> {
+        return self.desync;
+    }
+
+    // the CRC-32 of the console's save state, as movies keep it
+    pub fn state_checksum(&self) -> u32 {
+        return hash::crc32(&savestate::save(&self.cpu));
+    }
+
+    // at the end of a frame, what the movie has to say about the console
+    // at the start of the next one
+    fn checksum_movie(&mut self) {
+        match &mut self.movie {
+            MovieMode::Idle => {}
+            MovieMode::Recording(movie) => {
+                let frame = movie.frames.len();
+                if frame.is_multiple_of(CHECKSUM_INTERVAL) {
+                    let checksum = hash::crc32(&savestate::save(&self.cpu));
+                    movie.checksums.insert(frame, checksum);
+                }
+            }
+            MovieMode::Playing(movie, next) => {
+                let Some(&expected) = movie.checksums.get(next) else {
+                    return;
+                };
+                if self.desync.is_some() {
+                    return;
+                }
+                let actual = hash::crc32(&savestate::save(&self.cpu));
+                if actual == expected {
+                    self.last_match = Some(*next);
+                } else {
+                    self.desync = Some(Desync {
+                        frame: *next,
+                        last_match: self.last_match,
+                        expected,
+                        actual,
+                    });
+                }
+            }
+        }
+    }
+
+    fn update_movie(&mut self) {
+        match &mut self.movie {
+            MovieMode::Idle => {}
+            MovieMode::Recording(movie) => {
+                let ports = self.cpu.bus.ports_mut();
+                let mut frame = MovieFrame {
+                    event: self.pending_event.take(),
+                    ..MovieFrame::default()
+                };
+                for (player, buttons) in frame.buttons.iter_mut().enumerate() {
+                    *buttons = ports.buttons(player);
+                }
+                movie.frames.push(frame);
+            }
+            MovieMode::Playing(movie, next) => {
+                let Some(&frame) = movie.frames.get(*next) else {
+                    self.movie = MovieMode::Idle;
+                    return;
+                };
+                *next += 1;
+                match frame.event {
+                    Some(MovieEvent::Reset) => self.cpu.soft_reset(),
+                    Some(MovieEvent::Power) => self.power_on(),
+                    None => {}
+                }
+                let ports = self.cpu.bus.ports_mut();
+                for player in 0..PLAYERS {
+                    ports.set_buttons(player, frame.buttons[player]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+    use crate::cpu::Mem;
+    use crate::input::joypad::Button;
+
+    // with IRQs off, strobes the first controller and adds its A bit to $10, over and over
+    const PROGRAM: [u8; 24] = [
+        0x78, 0xA9, 0x01, 0x8D, 0x16, 0x40, 0xA9, 0x00, 0x8D, 0x16, 0x40, 0xAD, 0x16, 0x40, 0x29,
+        0x01, 0x18, 0x65, 0x10, 0x85, 0x10, 0x4C, 0x01, 0x80,
+    ];
+
+    pub fn test_nes() -> Nes {
+        let mut raw = test_rom(0, 1, 1);
+        raw[16..16 + PROGRAM.len()].copy_from_slice(&PROGRAM);
+        raw[16 + 0x3FFC] = 0x00;
+        raw[16 + 0x3FFD] = 0x80;
+        return Nes::new(Rom::new(&raw).unwrap()).unwrap();
+    }
+
+    fn press_a(nes: &mut Nes, pressed: bool) {
+        let state = JoypadState {
+            a: pressed,
+            ..JoypadState::default()
+        };
+        nes.set_input(0, state);
+    }
+
+    #[test]
+    fn test_run_frame() {
+        let mut nes = test_nes();
+        nes.run_frame();
+        nes.run_frame();
+        assert_eq!(nes.frame_count(), 2);
+        assert_eq!(nes.cpu.mem_read(0x10), 0);
+        press_a(&mut nes, true);
+        assert!(nes.input(0).a);
+        nes.run_frame();
+        assert_ne!(nes.cpu.mem_read(0x10), 0);
+
+        // a player with no controller plugged in reads as idle
+        nes.set_input(3, JoypadState::pressing(&[Button::Start]));
+        assert_eq!(nes.input(3), JoypadState::default());
+    }
+
+    #[test]
+    fn test_embedding_api() {
+        let mut raw = test_rom(0, 1, 1);
+        raw[16..16 + PROGRAM.len()].copy_from_slice(&PROGRAM);
+        raw[16 + 0x3FFC] = 0x00;
+        raw[16 + 0x3FFD] = 0x80;
+        let mut nes = Nes::with_rom(&raw).unwrap();
+        assert!(Nes::with_rom(b"NES").is_err());
+        press_a(&mut nes, true);
+        nes.run_frame();
+        let samples = nes.audio_samples();
+        assert!(!samples.is_empty() && samples.len() < nes.sample_rate() as usize);
+        assert!(nes.audio_samples().is_empty());
+        assert_eq!(nes.frame().pixels.len(), 256 * 240 * 4);
+
+        let state = nes.save_state();
+        let count = nes.cpu.mem_read(0x10);
+        nes.run_frame();
+        assert_ne!(nes.cpu.mem_read(0x10), count);
+        nes.load_state(&state).unwrap();
+        assert_eq!(nes.cpu.mem_read(0x10), count);
+        assert!(nes.load_state(b"RNES").is_err());
+    }
+
+    #[test]
+    fn test_events() {
+        use std::sync::{Arc, Mutex};
+        // with IRQs on, writes $8000 over and over, acknowledging the APU's
+        // frame IRQ with a $4015 read
+        const WRITER: [u8; 11] = [
+            0x58, 0x8D, 0x00, 0x80, 0x4C, 0x01, 0x80, 0xAD, 0x15, 0x40, 0x40,
+        ];
+        let mut raw = test_rom(0, 1, 1);
+        raw[16..16 + WRITER.len()].copy_from_slice(&WRITER);
+        raw[16 + 0x3FFC..16 + 0x4000].copy_from_slice(&[0x00, 0x80, 0x07, 0x80]);
+        let mut nes = Nes::with_rom(&raw).unwrap();
+
+        let heard = Arc::new(Mutex::new(Vec::new()));
+        let log = heard.clone();
+        let vblank = nes.on_vblank(move |event, cpu| {
+            log.lock()
+                .unwrap()
+                .push((event.kind, event.frame, cpu.bus.ram()[0]));
+        });
+        let log = heard.clone();
+        nes.on_scanline(100, move |event, _| {
+            log.lock()
+                .unwrap()
+                .push((event.kind, event.frame, event.scanline as u8));
+        });
+        let log = heard.clone();
+        nes.on_frame_complete(move |event, _| {
+            log.lock().unwrap().push((event.kind, event.frame, 0));
+        });
+        let irqs = Arc::new(Mutex::new(0));
+        let count = irqs.clone();
+        nes.on_irq(move |_, _| *count.lock().unwrap() += 1);
+        nes.watch(Topic::MapperWrite);
+        nes.run_frame();
+        assert_eq!(
+            *heard.lock().unwrap(),
+            vec![
+                (ConsoleEventKind::Scanline, 0, 100),
+                (ConsoleEventKind::Vblank, 0, 0),
+                (ConsoleEventKind::FrameComplete, 0, 0),
+            ]
+        );
+        let writes = nes.drain_events();
+        assert!(writes.len() > 1000);
+        let write = ConsoleEventKind::MapperWrite {
+            addr: 0x8000,
+            value: 0x00,
+        };
+        assert!(writes.iter().all(|event| event.kind == write));
+        assert!(nes.drain_events().is_empty());
+
+        // run-ahead's frames aren't heard, and nor is anything unsubscribed
+        heard.lock().unwrap().clear();
+        nes.unsubscribe(vblank);
+        nes.unwatch(Topic::MapperWrite);
+        nes.run_frame_ahead(2);
+        assert_eq!(heard.lock().unwrap().len(), 2);
+        assert!(nes.drain_events().is_empty());
+        nes.run_frame();
+        assert!(*irqs.lock().unwrap() > 0);
+    }
+
+    #[test]
+    fn test_builder() {
+        let rom = Rom::new(&test_rom(0, 1, 1)).unwrap();
+        let mut colours = SYSTEM_PALETTE;
+        colours[0x0F] = (1, 2, 3);
+        let nes = Nes::builder()
+            .mapper_override(4)
+            .palette(colours)
+            .ram_init(RamInit::AllFF)
+            .sample_rate(22_050)
+            .build(rom.clone())
+            .unwrap();
+        assert_eq!(nes.rom().mapper, 4);
+        assert_eq!(nes.cpu.bus.ppu.system_palette()[0x0F], (1, 2, 3));
+        assert_eq!(nes.sample_rate(), 22_050);
+        assert!(nes.cpu.bus.ram().iter().all(|&byte| byte == 0xFF));
+        assert_eq!(
+            Nes::builder().mapper_override(99).build(rom.clone()).err(),
+            Some(EmuError::Rom(RomError::UnsupportedMapper(99)))
+        );
+
+        // the same seed, the same RAM, power cycle after power cycle
+        let mut nes = Nes::builder()
+            .ram_init(RamInit::Random(7))
+            .build(rom.clone())
+            .unwrap();
+        let ram = nes.cpu.bus.ram().to_vec();
+        assert!(ram.iter().any(|&byte| byte != ram[0]));
+        nes.power_cycle();
+        assert_eq!(nes.cpu.bus.ram(), &ram[..]);
+        let mut other = [0; 16];
+        RamInit::Random(8).fill(&mut other);
+        assert_ne!(other, ram[..16]);
+        assert_eq!(Nes::new(rom).unwrap().cpu.bus.ram()[0], 0);
+    }
+
+    #[test]
+    fn test_movie_replays_the_run() {
+        let mut nes = test_nes();
+        nes.run_frame();
+        nes.start_recording();
+        assert!(nes.is_recording());
+        for frame in 0..10 {
+            press_a(&mut nes, frame % 3 == 0);
+            if frame == 4 {
+                nes.reset();
+            }
+            if frame == 7 {
+                nes.power_cycle();
+            }
+            nes.run_frame();
+        }
+        let expected = savestate::save(&nes.cpu);
+        let movie = nes.stop().unwrap();
+        assert_eq!(movie.frames.len(), 10);
+        assert_eq!(movie.frames[4].event, Some(MovieEvent::Reset));
+        assert_eq!(movie.frames[7].event, Some(MovieEvent::Power));
+        assert_eq!(movie.frames[3].buttons[0], Button::A.bit());
+
+        // the frontend's input is ignored while the movie plays
+        let mut replay = test_nes();
+        replay.play(movie).unwrap();
+        for _ in 0..10 {
+            press_a(&mut replay, true);
+            replay.run_frame();
+        }
+        assert_eq!(savestate::save(&replay.cpu), expected);
+
+        // and handed back once it runs out
+        replay.run_frame();
+        assert!(!replay.is_playing());
+        assert!(replay.stop().is_none());
+    }
+
+    #[test]
+    fn test_movie_checksums_catch_a_desync() {
+        let mut nes = test_nes();
+        nes.start_recording();
+        for frame in 0..CHECKSUM_INTERVAL * 3 {
+            press_a(&mut nes, frame % 7 == 0);
+            nes.run_frame();
+        }
+        let movie = nes.stop().unwrap();
+        let checked: Vec<usize> = movie.checksums.keys().copied().collect();
+        assert_eq!(checked, vec![60, 120, 180]);
+
+        let play = |movie: &Movie| {
+            let mut replay = test_nes();
+            replay.play(movie.clone()).unwrap();
+            for _ in 0..CHECKSUM_INTERVAL * 3 {
+                replay.run_frame();
+            }
+            return replay.desync();
+        };
+        assert_eq!(play(&movie), None);
+
+        // A held a frame longer in the middle throws the rest off
+        let mut changed = movie.clone();
+        changed.frames[71].buttons[0] = Button::A.bit();
+        let desync = play(&changed).unwrap();
+        assert_eq!((desync.frame, desync.last_match), (120, Some(60)));
+        assert_eq!(desync.expected, movie.checksums[&120]);
+        assert!(desync.to_string().contains("frame 120"));
+    }
+
+    #[test]
+    fn test_running_ahead_leaves_the_console_behind() {
+        let (mut nes, mut twin) = (test_nes(), test_nes());
+        for n in 0..3 {
+            press_a(&mut nes, n == 1);
+            press_a(&mut twin, n == 1);
+            nes.run_frame_ahead(2);
+            twin.run_frame();
+        }
+        assert_eq!(nes.frame_count(), 3);
+        assert_eq!(savestate::save(&nes.cpu), savestate::save(&twin.cpu));
+        assert_eq!(nes.cpu.instructions, twin.cpu.instructions);
+        // the sound is the real frames', none of the ones run ahead
+        let samples = nes.cpu.bus.apu.take_samples();
+        assert_eq!(samples, twin.cpu.bus.apu.take_samples());
+    }
+}
@@ -0,0 +1,397 @@
+use crate::cartridge::Rom;
+use crate::nes::Nes;
+use crate::op_codes::NMOS_6502_OPCODES_MAP;
+use crate::trace;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+// nestest's automated mode, which needs no PPU to report through
+const START: u16 = 0xC000;
+// the state nestest.log starts in, reset having taken 7 cycles
+const START_STATUS: u8 = 0x24;
+const START_SP: u8 = 0xFD;
+const START_CYCLES: u64 = 7;
+
+/* nestest.log comparison
+
+   `rustynes nestest nestest.nes nestest.log` runs the nestest ROM from
+   $C000, its mode for emulators without a working PPU, and checks the
+   state before every instruction against the matching line of the
+   reference log, a `trace` line each: the address, the instruction's
+   bytes, A, X, Y, P, SP, and the PPU position and cycle count where the
+   log has them. It stops at the first line that differs and shows it next
+   to ours and the line before, which is usually the instruction that got
+   it wrong.
+
+   The official opcodes take the first 5003 lines; the rest are the
+   unofficial ones, marked with a `*` in the log. `--official` stops at the
+   first of those and counts getting there as a pass, for a CPU that
+   doesn't do them yet.
+*/
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Matched,
+    ReachedUnofficial,
+    Diverged {
+        field: &'static str,
+        expected: String,
+        actual: String,
+    },
+}
+
+#[derive(Debug)]
+pub struct Report {
+    pub outcome: Outcome,
+    pub lines: usize, // that matched
+    pub previous: Option<String>,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        return !matches!(self.outcome, Outcome::Diverged { .. });
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.outcome {
+            Outcome::Matched => return writeln!(f, "all {} lines match", self.lines),
+            Outcome::ReachedUnofficial => {
+                return writeln!(
+                    f,
+                    "{} lines match, stopped at the unofficial opcodes on line {}",
+                    self.lines,
+                    self.lines + 1
+                )
+            }
+            Outcome::Diverged {
+                field,
+                expected,
+                actual,
+            } => {
+                writeln!(
+                    f,
+                    "line {} differs in {}: expected {}, got {}",
+                    self.lines + 1,
+                    field,
+                    expected,
+                    actual
+                )?;
+                if let Some(previous) = &self.previous {
+                    writeln!(f, "  after     {}", previous)?;
+                }
+                writeln!(f, "  expected  {}", self.expected)?;
+                return writeln!(f, "  got       {}", self.actual);
+            }
+        }
+    }
+}
+
+// what a log line says about the machine
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct State {
+    pc: u16,
+    bytes: Vec<u8>,
+    registers: [(&'static str, u8); 5],
+    ppu: Option<(u16, u16)>,
+    cycles: Option<u64>,
+    unofficial: bool,
+}
+
+const REGISTERS: [&str; 5] = ["A", "X", "Y", "P", "SP"];
+
+fn parse_line(line: &str) -> Result<State, String> {
+    let invalid = || format!("not a nestest.log line: {}", line);
+    let pc = line.get(0..4).ok_or_else(invalid)?;
+    let pc = u16::from_str_radix(pc, 16).map_err(|_| invalid())?;
+    let bytes = line
+        .get(6..14)
+        .ok_or_else(invalid)?
+        .split_whitespace()
+        .map(|byte| u8::from_str_radix(byte, 16).map_err(|_| invalid()))
+        .collect::<Result<Vec<u8>, String>>()?;
+    let field = |name: &str| {
+        let start = line.find(&format!(" {}:", name))? + name.len() + 2;
+        return line[start..].split_whitespace().next();
+    };
+    let mut registers = [("", 0); 5];
+    for (register, name) in registers.iter_mut().zip(REGISTERS) {
+        let value = field(name).ok_or_else(invalid)?;
+        *register = (name, u8::from_str_radix(value, 16).map_err(|_| invalid())?);
+    }
+    let ppu = line.find(" PPU:").and_then(|start| {
+        let (scanline, dot) = line[start + 5..].split_once(',')?;
+        let dot = dot.split_whitespace().next()?;
+        return Some((scanline.trim().parse().ok()?, dot.parse().ok()?));
+    });
+    let cycles = field("CYC").and_then(|cycles| cycles.parse().ok());
+    return Ok(State {
+        pc,
+        bytes,
+        registers,
+        ppu,
+        cycles,
+        unofficial: line.get(15..16) == Some("*"),
+    });
+}
+
+fn state_of(nes: &Nes, len: usize) -> State {
+    let cpu = &nes.cpu;
+    let values = [
+        cpu.register_a,
+        cpu.register_x,
+        cpu.register_y,
+        cpu.status.bits(),
+        cpu.stack.ptr() as u8,
+    ];
+    let mut registers = [("", 0); 5];
+    for ((register, name), value) in registers.iter_mut().zip(REGISTERS).zip(values) {
+        *register = (name, value);
+    }
+    return State {
+        pc: cpu.program_counter,
+        bytes: (0..len as u16)
+            .map(|i| cpu.bus.peek(cpu.program_counter.wrapping_add(i)))
+            .collect(),
+        registers,
+        ppu: Some((cpu.bus.ppu.scanline(), cpu.bus.ppu.dot())),
+        cycles: Some(cpu.cycles),
+        unofficial: false,
+    };
+}
+
+// the first field of ours that isn't what the log has, the fields the log
+// leaves out not counting
+fn difference(expected: &State, actual: &State) -> Option<(&'static str, String, String)> {
+    if expected.pc != actual.pc {
+        return Some((
+            "PC",
+            format!("{:04X}", expected.pc),
+            format!("{:04X}", actual.pc),
+        ));
+    }
+    if expected.bytes != actual.bytes {
+        let hex = |bytes: &[u8]| {
+            let bytes: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+            return bytes.join(" ");
+        };
+        return Some(("bytes", hex(&expected.bytes), hex(&actual.bytes)));
+    }
+    for (&(name, expected), &(_, actual)) in expected.registers.iter().zip(&actual.registers) {
+        if expected != actual {
+            return Some((name, format!("{:02X}", expected), format!("{:02X}", actual)));
+        }
+    }
+    if let (Some(expected), Some(actual)) = (expected.ppu, actual.ppu) {
+        if expected != actual {
+            return Some((
+                "PPU",
+                format!("{},{}", expected.0, expected.1),
+                format!("{},{}", actual.0, actual.1),
+            ));
+        }
+    }
+    if let (Some(expected), Some(actual)) = (expected.cycles, actual.cycles) {
+        if expected != actual {
+            return Some(("CYC", expected.to_string(), actual.to_string()));
+        }
+    }
+    return None;
+}
+
+// the console nestest.log starts from, at $C000
+pub fn load(rom: Rom) -> Result<Nes, String> {
+    let mut nes = Nes::new(rom)?;
+    let cpu = &mut nes.cpu;
+    cpu.program_counter = START;
+    cpu.status.set_bits(START_STATUS);
+    cpu.stack.set_ptr(START_SP);
+    cpu.cycles = START_CYCLES;
+    cpu.bus.tick(START_CYCLES);
+    return Ok(nes);
+}
+
+pub fn compare(nes: &mut Nes, log: &str, official_only: bool) -> Result<Report, String> {
+    let mut previous: Option<&str> = None;
+    let mut lines = 0;
+    for line in log
+        .lines()
+        .map(str::trim_end)
+        .filter(|line| !line.is_empty())
+    {
+        let expected = parse_line(line)?;
+        let report = |outcome| {
+            return Report {
+                outcome,
+                lines,
+                previous: previous.map(str::to_string),
+                expected: line.to_string(),
+                actual: trace::line(&nes.cpu),
+            };
+        };
+        if official_only && expected.unofficial {
+            return Ok(report(Outcome::ReachedUnofficial));
+        }
+        let actual = state_of(nes, expected.bytes.len());
+        if let Some((field, expected, actual)) = difference(&expected, &actual) {
+            return Ok(report(Outcome::Diverged {
+                field,
+                expected,
+                actual,
+            }));
+        }
+        let code = nes.cpu.bus.peek(nes.cpu.program_counter);
+        if !NMOS_6502_OPCODES_MAP.contains_key(&code) {
+            return Ok(report(Outcome::Diverged {
+                field: "opcode",
+                expected: format!("{:02X} to run", code),
+                actual: "no such instruction".to_string(),
+            }));
+        }
+        nes.cpu.step();
+        previous = Some(line);
+        lines += 1;
+    }
+    return Ok(Report {
+        outcome: Outcome::Matched,
+        lines,
+        previous: None,
+        expected: String::new(),
+        actual: String::new(),
+    });
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Options {
+    pub rom: PathBuf,
+    pub log: PathBuf,
+    pub official_only: bool,
+}
+
+impl Options {
+    pub fn parse(args: &[String]) -> Result<Options, String> {
+        let mut paths = Vec::new();
+        let mut official_only = false;
+        for arg in args {
+            match arg.as_str() {
+                "--official" => official_only = true,
+                flag if flag.starts_with("--") => return Err(format!("unknown option {}", flag)),
+                path if paths.len() < 2 => paths.push(PathBuf::from(path)),
+                extra => return Err(format!("unexpected argument {}", extra)),
+            }
+        }
+        let mut paths = paths.into_iter();
+        return Ok(Options {
+            rom: paths.next().ok_or("missing nestest.nes")?,
+            log: paths.next().ok_or("missing nestest.log")?,
+            official_only,
+        });
+    }
+}
+
+// `rustynes nestest <nestest.nes> <nestest.log> [--official]`
+pub fn run_cli(args: &[String]) -> Result<bool, String> {
+    let options = Options::parse(args)?;
+    let rom = Rom::from_file(&options.rom, None)?;
+    let log = fs::read_to_string(&options.log)
+        .map_err(|e| format!("{}: {}", options.log.display(), e))?;
+    let mut nes = load(rom)?;
+    let report = compare(&mut nes, &log, options.official_only)?;
+    print!("{}", report);
+    return Ok(report.passed());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm;
+    use crate::cartridge::test::test_rom;
+
+    fn nes_with(source: &str) -> Nes {
+        let mut raw = test_rom(0, 1, 1);
+        let program = asm::assemble_at(source, START).unwrap();
+        raw[16..16 + program.len()].copy_from_slice(&program);
+        return load(Rom::new(&raw).unwrap()).unwrap();
+    }
+
+    const PROGRAM: &str = "LDX #$02\nloop: DEX\nBNE loop\nLDA #$40\nSTA $10\nhalt: JMP halt";
+
+    // what the program's log would be, from our own trace
+    fn reference() -> Vec<String> {
+        let mut nes = nes_with(PROGRAM);
+        let mut log = Vec::new();
+        for _ in 0..9 {
+            log.push(trace::line(&nes.cpu));
+            nes.cpu.step();
+        }
+        return log;
+    }
+
+    #[test]
+    fn test_matching_log() {
+        let log = reference();
+        assert!(log[0].starts_with("C000  A2 02     LDX #$02"));
+        assert!(log[0].ends_with(" A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7"));
+        let report = compare(&mut nes_with(PROGRAM), &log.join("\r\n"), false).unwrap();
+        assert_eq!(report.outcome, Outcome::Matched);
+        assert_eq!(report.lines, 9);
+        assert_eq!(report.to_string(), "all 9 lines match\n");
+    }
+
+    #[test]
+    fn test_first_divergence() {
+        let mut log = reference();
+        log[6] = log[6].replace("A:40", "A:41");
+        log[7] = log[7].replace("CYC:", "CYC:9");
+        let report = compare(&mut nes_with(PROGRAM), &log.join("\n"), false).unwrap();
+        assert!(!report.passed());
+        assert_eq!(
+            report.outcome,
+            Outcome::Diverged {
+                field: "A",
+                expected: "41".to_string(),
+                actual: "40".to_string()
+            }
+        );
+        let text = report.to_string();
+        assert!(text.starts_with("line 7 differs in A: expected 41, got 40\n"));
+        assert!(text.contains("  after     C005  A9 40     LDA #$40"));
+        assert!(text.contains("  got       C007  85 10     STA $10"));
+    }
+
+    #[test]
+    fn test_unofficial_opcodes() {
+        // *NOP $A9, which only `--official` gets past
+        let mut nes = nes_with("LDA #$01\n.byte $04, $A9");
+        let log = [
+            trace::line(&nes.cpu),
+            "C002  04 A9    *NOP $A9 = 00                    A:01 X:00 Y:00 P:24 SP:FD".to_string(),
+        ];
+        let report = compare(&mut nes, &log.join("\n"), true).unwrap();
+        assert_eq!(report.outcome, Outcome::ReachedUnofficial);
+        assert!(report.to_string().contains("unofficial opcodes on line 2"));
+
+        let mut nes = nes_with("LDA #$01\n.byte $04, $A9");
+        let report = compare(&mut nes, &log.join("\n"), false).unwrap();
+        assert!(report
+            .to_string()
+            .starts_with("line 2 differs in opcode: expected 04 to run, got no such instruction"));
+        assert!(compare(&mut nes, "C000", false).is_err());
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let args: Vec<String> = ["nestest.nes", "--official", "nestest.log"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let options = Options::parse(&args).unwrap();
+        assert_eq!(options.log, PathBuf::from("nestest.log"));
+        assert!(options.official_only);
+        assert!(Options::parse(&args[..1]).is_err());
+    }
+}
@@ -0,0 +1,408 @@
+use crate::apu::resample::ResampleQuality;
+use crate::apu::{Apu, CPU_CLOCK_NTSC, DEFAULT_SAMPLE_RATE};
+use crate::audio::{AudioSink, WavAudioSink};
+use crate::bus::Bus;
+use crate::cpu::{Mem, CPU};
+use crate::mapper::nsf::NsfMapper;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const NSF_TAG: [u8; 5] = [0x4E, 0x45, 0x53, 0x4D, 0x1A]; // "NESM" followed by MS-DOS end of file
+const HEADER_SIZE: usize = 0x80;
+
+// never mapped to anything, so the CPU reaching it means the routine returned
+const RETURN_TRAP: u16 = 0x4100;
+const MAX_ROUTINE_STEPS: u64 = 1_000_000;
+
+/* NSF header outline
+    $00  "NESM" + 0x1A
+    $05  version
+    $06  number of songs
+    $07  starting song (1 based)
+    $08  load address
+    $0A  init address
+    $0C  play address
+    $0E  song name, artist, copyright (32 bytes each, NUL padded)
+    $6E  NTSC play speed in microseconds
+    $70  initial bank for each 4KB window at $8000-$FFFF, all zero when not bankswitched
+    $78  PAL play speed in microseconds
+    $7A  PAL/NTSC flags
+    $7B  expansion sound chips
+    $80  data, placed at the load address
+*/
+
+#[derive(Debug, Clone)]
+pub struct Nsf {
+    pub version: u8,
+    pub total_songs: u8,
+    pub starting_song: u8,
+    pub load_addr: u16,
+    pub init_addr: u16,
+    pub play_addr: u16,
+    pub title: String,
+    pub artist: String,
+    pub copyright: String,
+    pub ntsc_speed: u16,
+    pub bankswitch_init: [u8; 8],
+    pub expansion_chips: u8,
+    pub data: Vec<u8>,
+}
+
+impl Nsf {
+    pub fn parse(raw: &[u8]) -> Result<Nsf, String> {
+        if raw.len() < HEADER_SIZE || raw[0..5] != NSF_TAG {
+            return Err("file is not in NSF format".to_string());
+        }
+        let word = |offset: usize| u16::from_le_bytes([raw[offset], raw[offset + 1]]);
+        let text = |offset: usize| {
+            let field = &raw[offset..offset + 32];
+            let end = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+            String::from_utf8_lossy(&field[..end]).trim().to_string()
+        };
+
+        let nsf = Nsf {
+            version: raw[0x05],
+            total_songs: raw[0x06],
+            starting_song: raw[0x07].max(1),
+            load_addr: word(0x08),
+            init_addr: word(0x0A),
+            play_addr: word(0x0C),
+            title: text(0x0E),
+            artist: text(0x2E),
+            copyright: text(0x4E),
+            ntsc_speed: word(0x6E),
+            bankswitch_init: raw[0x70..0x78].try_into().unwrap(),
+            expansion_chips: raw[0x7B],
+            data: raw[HEADER_SIZE..].to_vec(),
+        };
+
+        if nsf.total_songs == 0 {
+            return Err("NSF has no songs".to_string());
+        }
+        if nsf.data.is_empty() {
+            return Err("NSF has no data".to_string());
+        }
+        if nsf.load_addr < 0x8000 {
+            return Err(format!(
+                "load address ${:04X} is below $8000, FDS rips are not supported",
+                nsf.load_addr
+            ));
+        }
+        return Ok(nsf);
+    }
+
+    pub fn is_bankswitched(&self) -> bool {
+        return self.bankswitch_init.iter().any(|bank| *bank != 0);
+    }
+
+    // CPU cycles between two calls of the play routine
+    pub fn cycles_per_play(&self) -> u64 {
+        let speed = if self.ntsc_speed == 0 {
+            16_639 // 60.1Hz, what the header should have said
+        } else {
+            self.ntsc_speed
+        };
+        return (speed as f64 * CPU_CLOCK_NTSC / 1_000_000.0) as u64;
+    }
+}
+
+pub struct NsfPlayer {
+    pub nsf: Nsf,
+    pub cpu: CPU,
+    sample_rate: u32,
+    pub quality: ResampleQuality, // applied from the next start_track
+    track: u8,
+}
+
+impl NsfPlayer {
+    pub fn new(nsf: Nsf, sample_rate: u32) -> Self {
+        let cpu = CPU::with_bus(Bus::new(Box::new(NsfMapper::new(&nsf))));
+        return Self {
+            nsf,
+            cpu,
+            sample_rate,
+            quality: ResampleQuality::default(),
+            track: 0,
+        };
+    }
+
+    pub fn track(&self) -> u8 {
+        return self.track;
+    }
+
+    // tracks are numbered from 0, as the init routine sees them in A
+    pub fn start_track(&mut self, track: u8) -> Result<(), String> {
+        if track >= self.nsf.total_songs {
+            return Err(format!(
+                "track {} out of range, the NSF has {} songs",
+                track + 1,
+                self.nsf.total_songs
+            ));
+        }
+        // a fresh console per track: cleared RAM, initial banks, silent APU
+        self.cpu = CPU::with_bus(Bus::new(Box::new(NsfMapper::new(&self.nsf))));
+        self.cpu.bus.apu = Apu::new(self.sample_rate);
+        self.cpu.bus.apu.set_resample_quality(self.quality);
+        self.cpu.reset();
+        for addr in 0x4000..=0x4013 {
+            self.cpu.mem_write(addr, 0x00);
+        }
+        self.cpu.mem_write(0x4015, 0x0F);
+        self.cpu.mem_write(0x4017, 0x40);
+
+        self.track = track;
+        self.cpu.register_a = track;
+        self.cpu.register_x = 0; // NTSC
+        let init = self.nsf.init_addr;
+        return self.call(init);
+    }
+
+    // runs the play routine once and returns one play period worth of audio
+    pub fn play_frame(&mut self) -> Result<Vec<f32>, String> {
+        let start = self.cpu.cycles;
+        let play = self.nsf.play_addr;
+        self.call(play)?;
+
+        // the rest of the period is spent idling while the APU keeps going
+        let elapsed = self.cpu.cycles - start;
+        let period = self.nsf.cycles_per_play();
+        if elapsed < period {
+            self.cpu.bus.tick(period - elapsed);
+            self.cpu.cycles += period - elapsed;
+        }
+        return Ok(self.cpu.bus.apu.take_samples());
+    }
+
+    fn call(&mut self, addr: u16) -> Result<(), String> {
+        self.cpu.call(addr, RETURN_TRAP);
+        let mut steps = 0;
+        while self.cpu.program_counter != RETURN_TRAP {
+            if steps == MAX_ROUTINE_STEPS {
+                return Err(format!("routine at ${:04X} did not return", addr));
+            }
+            if !self.cpu.step() {
+                return Err(format!(
+                    "BRK at ${:04X} in routine ${:04X}",
+                    self.cpu.program_counter.wrapping_sub(1),
+                    addr
+                ));
+            }
+            steps += 1;
+        }
+        return Ok(());
+    }
+}
+
+pub struct PlayOptions {
+    pub path: PathBuf,
+    pub track: Option<u8>,
+    pub seconds: u32,
+    pub sample_rate: u32,
+    pub quality: ResampleQuality,
+    pub out: Option<PathBuf>,
+}
+
+impl PlayOptions {
+    pub fn parse(args: &[String]) -> Result<PlayOptions, String> {
+        let mut path = None;
+        let mut options = PlayOptions {
+            path: PathBuf::new(),
+            track: None,
+            seconds: 60,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            quality: ResampleQuality::default(),
+            out: None,
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                return args.next().ok_or_else(|| format!("{} needs a value", name));
+            };
+            match arg.as_str() {
+                "--track" => options.track = Some(parse_number(value("--track")?)?),
+                "--seconds" => options.seconds = parse_number(value("--seconds")?)?,
+                "--rate" => options.sample_rate = parse_number(value("--rate")?)?,
+                "--quality" => options.quality = ResampleQuality::parse(value("--quality")?)?,
+                "--out" => options.out = Some(PathBuf::from(value("--out")?)),
+                flag if flag.starts_with("--") => return Err(format!("unknown option {}", flag)),
+                file if path.is_none() => path = Some(PathBuf::from(file)),
+                extra => return Err(format!("unexpected argument {}", extra)),
+            }
+        }
+        options.path = path.ok_or("missing NSF file")?;
+        if options.track == Some(0) {
+            return Err("tracks are numbered from 1".to_string());
+        }
+        return Ok(options);
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    return value
+        .parse()
+        .map_err(|_| format!("{} is not a valid number", value));
+}
+
+// `rustynes nsf <file> [--track N] [--seconds S] [--rate HZ] [--quality Q] [--out FILE]`
+pub fn run_cli(args: &[String]) -> Result<(), String> {
+    let options = PlayOptions::parse(args)?;
+    let raw = fs::read(&options.path).map_err(|e| format!("{}: {}", options.path.display(), e))?;
+    let nsf = Nsf::parse(&raw)?;
+    let track = options.track.unwrap_or(nsf.starting_song);
+    let out = match &options.out {
+        Some(out) => out.clone(),
+        None => default_output(&options.path, track),
+    };
+
+    let credits: Vec<&str> = [&nsf.title, &nsf.artist, &nsf.copyright]
+        .iter()
+        .map(|s| s.as_str())
+        .filter(|s| !s.is_empty())
+        .collect();
+    println!("{}", credits.join(" - "));
+    println!(
+        "track {} of {}, writing {}",
+        track,
+        nsf.total_songs,
+        out.display()
+    );
+    if nsf.expansion_chips != 0 {
+        println!("warning: expansion audio chips are not emulated, some channels will be missing");
+    }
+
+    let mut player = NsfPlayer::new(nsf, options.sample_rate);
+    player.quality = options.quality;
+    player.start_track(track - 1)?;
+    let mut sink = WavAudioSink::create(&out)?;
+    let target = options.seconds as u64 * options.sample_rate as u64;
+    let mut written = 0;
+    while written < target {
+        let samples = player.play_frame()?;
+        let take = samples.len().min((target - written) as usize);
+        sink.push_samples(&samples[..take], options.sample_rate)?;
+        written += take as u64;
+    }
+    return sink.finish();
+}
+
+fn default_output(path: &Path, track: u8) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    return path.with_file_name(format!("{}-{:02}.wav", stem, track));
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::asm::assemble;
+
+    pub fn test_nsf(load_addr: u16, banks: [u8; 8], data: &[u8]) -> Vec<u8> {
+        let mut raw = vec![0; HEADER_SIZE];
+        raw[0..5].copy_from_slice(&NSF_TAG);
+        raw[0x05] = 1;
+        raw[0x06] = 3;
+        raw[0x07] = 1;
+        raw[0x08..0x0A].copy_from_slice(&load_addr.to_le_bytes());
+        raw[0x0A..0x0C].copy_from_slice(&0x8000u16.to_le_bytes());
+        raw[0x0C..0x0E].copy_from_slice(&0x8100u16.to_le_bytes());
+        raw[0x0E..0x12].copy_from_slice(b"Tune");
+        raw[0x6E..0x70].copy_from_slice(&16_639u16.to_le_bytes());
+        raw[0x70..0x78].copy_from_slice(&banks);
+        raw.extend_from_slice(data);
+        return raw;
+    }
+
+    // init stores the track number at $00 and starts a square wave, play counts calls in $01
+    fn tune() -> Vec<u8> {
+        let mut data = assemble(
+            "STA $00
+             LDA #$01
+             STA $4015
+             LDA #$BF
+             STA $4000
+             LDA #$FD
+             STA $4002
+             LDA #$08
+             STA $4003
+             RTS",
+        )
+        .unwrap();
+        data.resize(0x100, 0xEA);
+        data.extend(assemble("INC $01\nRTS").unwrap());
+        return data;
+    }
+
+    #[test]
+    fn test_parse_header() {
+        let nsf = Nsf::parse(&test_nsf(0x8000, [0; 8], &tune())).unwrap();
+        assert_eq!(nsf.total_songs, 3);
+        assert_eq!(nsf.title, "Tune");
+        assert_eq!(nsf.init_addr, 0x8000);
+        assert_eq!(nsf.play_addr, 0x8100);
+        assert!(!nsf.is_bankswitched());
+        assert_eq!(nsf.cycles_per_play(), 29_780);
+
+        assert!(Nsf::parse(b"NESM").is_err());
+        assert!(Nsf::parse(&test_nsf(0x6000, [0; 8], &tune())).is_err());
+    }
+
+    #[test]
+    fn test_init_and_play() {
+        let nsf = Nsf::parse(&test_nsf(0x8000, [0; 8], &tune())).unwrap();
+        let mut player = NsfPlayer::new(nsf, 44_100);
+        player.start_track(2).unwrap();
+        assert_eq!(player.cpu.mem_read(0x00), 2);
+
+        let mut samples = Vec::new();
+        for _ in 0..3 {
+            samples.extend(player.play_frame().unwrap());
+        }
+        assert_eq!(player.cpu.mem_read(0x01), 3);
+        assert!((2200..=2210).contains(&samples.len()));
+        assert!(samples.iter().any(|s| *s > 0.1));
+
+        // restarting gives a clean console
+        player.start_track(0).unwrap();
+        assert_eq!(player.cpu.mem_read(0x01), 0);
+        assert!(player.start_track(3).is_err());
+    }
+
+    #[test]
+    fn test_runaway_routine() {
+        let mut data = assemble("JMP $8000").unwrap();
+        data.resize(0x200, 0);
+        let nsf = Nsf::parse(&test_nsf(0x8000, [0; 8], &data)).unwrap();
+        let mut player = NsfPlayer::new(nsf, 44_100);
+        assert_eq!(
+            player.start_track(0),
+            Err("routine at $8000 did not return".to_string())
+        );
+    }
+
+    #[test]
+    fn test_play_options() {
+        let args: Vec<String> = [
+            "song.nsf",
+            "--track",
+            "4",
+            "--seconds",
+            "5",
+            "--quality",
+            "best",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        let options = PlayOptions::parse(&args).unwrap();
+        assert_eq!(options.path, PathBuf::from("song.nsf"));
+        assert_eq!(options.track, Some(4));
+        assert_eq!(options.seconds, 5);
+        assert_eq!(options.quality, ResampleQuality::Best);
+        assert_eq!(
+            default_output(&options.path, 4),
+            PathBuf::from("song-04.wav")
+        );
+
+        assert!(PlayOptions::parse(&["--track".to_string()]).is_err());
+        assert!(PlayOptions::parse(&[]).is_err());
+    }
+}
@@ -0,0 +1,218 @@
+use crate::cpu::CPU;
+use crate::ppu::{SCANLINES_PER_FRAME, VBLANK_SCANLINE};
+
+// more than a frame of mapper writes from any game, and a queue nobody
+// drains stops here rather than growing
+const MAX_EVENTS: usize = 0x4000;
+
+/* Console events
+
+   What a program embedding the core can hear about as the console runs,
+   without a fork of it: a frame finished, vblank started, the PPU reached
+   a given scanline, the CPU took an IRQ, the game wrote to a mapper
+   register. Each is a `Topic`. A callback on one, from `Nes::on_vblank`
+   and the rest, is called with the event and the CPU, which it can read
+   but not change, so a movie plays the same with it as without. A topic
+   that's `watch`ed instead queues its events, for `drain_events` once a
+   frame.
+
+   The bus notes events as they happen, in an `EventTap` that only exists
+   while something is listening, and the console hands them out after the
+   instruction that caused them. Like the debugging recorders, the tap
+   hears the real frames and not run-ahead's.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Topic {
+    FrameComplete,
+    Vblank,
+    Scanline(u16),
+    Irq,
+    MapperWrite,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleEventKind {
+    FrameComplete,
+    Vblank,
+    Scanline,
+    Irq,
+    MapperWrite { addr: u16, value: u8 },
+}
+
+// when in the frame it happened, the PPU's frame, scanline and dot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConsoleEvent {
+    pub frame: u64,
+    pub scanline: u16,
+    pub dot: u16,
+    pub kind: ConsoleEventKind,
+}
+
+impl ConsoleEvent {
+    pub fn topic(&self) -> Topic {
+        match self.kind {
+            ConsoleEventKind::FrameComplete => return Topic::FrameComplete,
+            ConsoleEventKind::Vblank => return Topic::Vblank,
+            ConsoleEventKind::Scanline => return Topic::Scanline(self.scanline),
+            ConsoleEventKind::Irq => return Topic::Irq,
+            ConsoleEventKind::MapperWrite { .. } => return Topic::MapperWrite,
+        }
+    }
+}
+
+// the events the bus has seen since the console last took them, of the
+// topics someone is listening to
+#[derive(Debug, Clone, Default)]
+pub struct EventTap {
+    topics: Vec<Topic>,
+    pending: Vec<ConsoleEvent>,
+}
+
+impl EventTap {
+    pub fn new(topics: Vec<Topic>) -> Self {
+        return Self {
+            topics,
+            pending: Vec::new(),
+        };
+    }
+
+    pub fn wants(&self, topic: Topic) -> bool {
+        return self.topics.contains(&topic);
+    }
+
+    pub fn record(&mut self, event: ConsoleEvent) {
+        if self.wants(event.topic()) && self.pending.len() < MAX_EVENTS {
+            self.pending.push(event);
+        }
+    }
+
+    // the scanlines the PPU went into getting from `from` to `to`, each a
+    // (frame, scanline) it was on
+    pub fn crossed(&mut self, from: (u64, u16), to: (u64, u16)) {
+        let lines = SCANLINES_PER_FRAME as u64;
+        let start = from.0 * lines + from.1 as u64;
+        let end = to.0 * lines + to.1 as u64;
+        // never more than a frame's worth, whatever a state load did
+        for line in (start + 1).max(end.saturating_sub(lines - 1))..=end {
+            let (frame, scanline) = (line / lines, (line % lines) as u16);
+            let event = |kind, dot| ConsoleEvent {
+                frame,
+                scanline,
+                dot,
+                kind,
+            };
+            self.record(event(ConsoleEventKind::Scanline, 0));
+            if scanline == VBLANK_SCANLINE {
+                self.record(event(ConsoleEventKind::Vblank, 1));
+            }
+        }
+    }
+
+    pub fn has_pending(&self) -> bool {
+        return !self.pending.is_empty();
+    }
+
+    pub fn take(&mut self) -> Vec<ConsoleEvent> {
+        return std::mem::take(&mut self.pending);
+    }
+}
+
+pub type EventCallback = Box<dyn FnMut(&ConsoleEvent, &CPU) + Send>;
+
+// what `Nes::unsubscribe` takes to remove a callback
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Subscription(u64);
+
+// who's listening to the console: callbacks, and the topics queued for
+// `drain`
+#[derive(Default)]
+pub struct Observers {
+    callbacks: Vec<(Subscription, Topic, EventCallback)>,
+    next: u64,
+    watched: Vec<Topic>,
+    queue: Vec<ConsoleEvent>,
+}
+
+impl Observers {
+    pub fn subscribe(&mut self, topic: Topic, callback: EventCallback) -> Subscription {
+        let subscription = Subscription(self.next);
+        self.next += 1;
+        self.callbacks.push((subscription, topic, callback));
+        return subscription;
+    }
+
+    pub fn unsubscribe(&mut self, subscription: Subscription) {
+        self.callbacks.retain(|(s, _, _)| *s != subscription);
+    }
+
+    pub fn watch(&mut self, topic: Topic) {
+        if !self.watched.contains(&topic) {
+            self.watched.push(topic);
+        }
+    }
+
+    pub fn unwatch(&mut self, topic: Topic) {
+        self.watched.retain(|&t| t != topic);
+    }
+
+    // every topic someone is listening to, one way or the other
+    pub fn topics(&self) -> Vec<Topic> {
+        let mut topics = self.watched.clone();
+        for (_, topic, _) in &self.callbacks {
+            if !topics.contains(topic) {
+                topics.push(*topic);
+            }
+        }
+        return topics;
+    }
+
+    pub fn dispatch(&mut self, event: &ConsoleEvent, cpu: &CPU) {
+        let topic = event.topic();
+        for (_, _, callback) in self.callbacks.iter_mut().filter(|(_, t, _)| *t == topic) {
+            callback(event, cpu);
+        }
+        if self.watched.contains(&topic) && self.queue.len() < MAX_EVENTS {
+            self.queue.push(*event);
+        }
+    }
+
+    pub fn drain(&mut self) -> Vec<ConsoleEvent> {
+        return std::mem::take(&mut self.queue);
+    }
+}
+
+impl std::fmt::Debug for Observers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return f
+            .debug_struct("Observers")
+            .field("callbacks", &self.callbacks.len())
+            .field("watched", &self.watched)
+            .field("queued", &self.queue.len())
+            .finish();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tap_crossing_scanlines() {
+        let mut tap = EventTap::new(vec![Topic::Vblank, Topic::Scanline(0)]);
+        tap.crossed((0, 239), (0, 242));
+        tap.crossed((0, 260), (1, 1));
+        tap.crossed((1, 1), (1, 1));
+        let events = tap.take();
+        assert_eq!(events.len(), 2);
+        assert_eq!((events[0].frame, events[0].scanline), (0, 241));
+        assert_eq!(events[0].kind, ConsoleEventKind::Vblank);
+        assert_eq!((events[1].frame, events[1].scanline), (1, 0));
+        assert_eq!(events[1].topic(), Topic::Scanline(0));
+        assert!(!tap.has_pending());
+
+        // a jump of frames is at most one frame of lines
+        tap.crossed((0, 0), (9, 0));
+        assert_eq!(tap.take().len(), 2);
+    }
+}
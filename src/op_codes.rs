@@ -60,7 +60,7 @@ lazy_static! {
         OpCode::new(0x5D, "EOR", 3, 4, AddressingMode::Absolute_X), //cycles + 1 if page crossed
         OpCode::new(0xFE, "INC", 3, 7, AddressingMode::Absolute_X),
         OpCode::new(0xBD, "LDA", 3, 4, AddressingMode::Absolute_X), //cycles + 1 if page crossed
-        OpCode::new(0x5E, "LSR", 3, 7, AddressingMode::Absolute_X), //cycles + 1 if page crossed
+        OpCode::new(0x5E, "LSR", 3, 7, AddressingMode::Absolute_X),
         OpCode::new(0x1D, "ORA", 3, 4, AddressingMode::Absolute_X), //cycles + 1 if page crossed
         OpCode::new(0x3E, "ROL", 3, 7, AddressingMode::Absolute_X),
         OpCode::new(0x7E, "ROR", 3, 7, AddressingMode::Absolute_X),
@@ -73,7 +73,7 @@ lazy_static! {
         OpCode::new(0x59, "EOR", 3, 4, AddressingMode::Absolute_Y), //cycles + 1 if page crossed
         OpCode::new(0xB9, "LDA", 3, 4, AddressingMode::Absolute_Y), //cycles + 1 if page crossed
         OpCode::new(0xBE, "LDX", 3, 4, AddressingMode::Absolute_Y), //cycles + 1 if page crossed
-        OpCode::new(0xBC, "LDY", 3, 4, AddressingMode::Absolute_Y), //cycles + 1 if page crossed
+        OpCode::new(0xBC, "LDY", 3, 4, AddressingMode::Absolute_X), //cycles + 1 if page crossed
         OpCode::new(0x19, "ORA", 3, 4, AddressingMode::Absolute_Y), //cycles + 1 if page crossed
         OpCode::new(0xF9, "SBC", 3, 4, AddressingMode::Absolute_Y), //cycles + 1 if page crossed
         OpCode::new(0x99, "STA", 3, 5, AddressingMode::Absolute_Y),
@@ -138,7 +138,8 @@ lazy_static! {
         OpCode::new(0x55, "EOR", 2, 4, AddressingMode::ZeroPage_X),
         OpCode::new(0xF6, "INC", 2, 6, AddressingMode::ZeroPage_X),
         OpCode::new(0xB5, "LDA", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0x56, "LSR", 2, 5, AddressingMode::ZeroPage_X),
+        OpCode::new(0xB4, "LDY", 2, 4, AddressingMode::ZeroPage_X),
+        OpCode::new(0x56, "LSR", 2, 6, AddressingMode::ZeroPage_X),
         OpCode::new(0x15, "ORA", 2, 4, AddressingMode::ZeroPage_X),
         OpCode::new(0x36, "ROL", 2, 6, AddressingMode::ZeroPage_X),
         OpCode::new(0x76, "ROR", 2, 6, AddressingMode::ZeroPage_X),
@@ -177,13 +178,14 @@ lazy_static! {
         OpCode::new(0x28, "PLP", 1, 4, AddressingMode::NoneAddressing),
         OpCode::new(0x2A, "ROL", 1, 2, AddressingMode::NoneAddressing),
         OpCode::new(0x6A, "ROR", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x40, "RTI", 1, 6, AddressingMode::NoneAddressing),
         OpCode::new(0x60, "RTS", 1, 6, AddressingMode::NoneAddressing),
         OpCode::new(0x38, "SEC", 1, 2, AddressingMode::NoneAddressing),
         OpCode::new(0xF8, "SED", 1, 2, AddressingMode::NoneAddressing),
         OpCode::new(0x78, "SEI", 1, 2, AddressingMode::NoneAddressing),
         OpCode::new(0xAA, "TAX", 1, 2, AddressingMode::NoneAddressing),
         OpCode::new(0xA8, "TAY", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0xB8, "TSX", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0xBA, "TSX", 1, 2, AddressingMode::NoneAddressing),
         OpCode::new(0x8A, "TXA", 1, 2, AddressingMode::NoneAddressing),
         OpCode::new(0x9A, "TXS", 1, 2, AddressingMode::NoneAddressing),
         OpCode::new(0x98, "TYA", 1, 2, AddressingMode::NoneAddressing),
@@ -0,0 +1,185 @@
+use super::{DOTS_PER_SCANLINE, SCANLINES_PER_FRAME};
+use std::fmt;
+
+// a frame of $2007 uploads is a few thousand writes, this is well past any
+const MAX_EVENTS: usize = 0x4000;
+
+/* PPU events
+
+   What happened to the picture and when, for raster effects that land on
+   the wrong line: each write to a PPU register, OAM DMA or the mapper, the
+   NMI and IRQs as the CPU takes them and the sprite 0 hit, tagged with the
+   scanline and dot the PPU was at. Writes are timed to the cycle they're
+   made, the way the bus times them for the PPU itself. With the log on
+   the bus keeps the frame in progress and the last one it finished, and
+   `grid` folds a frame into a character a cell, the standard event viewer
+   picture of where in the frame a game does things:
+
+    N  NMI          I  IRQ          0  sprite 0 hit
+    C  $2000        M  $2001        O  $2003 $2004 $4014
+    S  $2005        A  $2006        D  $2007
+    B  a mapper register
+
+   Where a cell has more than one, the one first in that list is shown.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    Write { addr: u16, value: u8 },
+    Nmi,
+    Irq,
+    SpriteZero,
+}
+
+impl EventKind {
+    // the grid's character, and its place in the order they win a cell by
+    fn mark(self) -> (char, u8) {
+        match self {
+            EventKind::Nmi => return ('N', 0),
+            EventKind::Irq => return ('I', 1),
+            EventKind::SpriteZero => return ('0', 2),
+            EventKind::Write { addr, .. } => match addr {
+                0x2000 => return ('C', 3),
+                0x2001 => return ('M', 4),
+                0x2003 | 0x2004 | 0x4014 => return ('O', 5),
+                0x2005 => return ('S', 6),
+                0x2006 => return ('A', 7),
+                0x2007 => return ('D', 8),
+                _ => return ('B', 9),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    pub scanline: u16,
+    pub dot: u16,
+    pub kind: EventKind,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:3},{:3}  ", self.scanline, self.dot)?;
+        match self.kind {
+            EventKind::Write { addr, value } => return write!(f, "${:04X} = {:02X}", addr, value),
+            EventKind::Nmi => return write!(f, "NMI"),
+            EventKind::Irq => return write!(f, "IRQ"),
+            EventKind::SpriteZero => return write!(f, "sprite 0 hit"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct EventLog {
+    frame: Option<u64>, // the one in progress, once anything has been recorded
+    current: Vec<Event>,
+    last: Option<(u64, Vec<Event>)>,
+}
+
+impl EventLog {
+    // PPU registers as themselves rather than the mirror written
+    pub fn record(&mut self, frame: u64, scanline: u16, dot: u16, kind: EventKind) {
+        if let Some(now) = self.frame.filter(|&now| now != frame) {
+            let events = std::mem::take(&mut self.current);
+            self.last = match frame.checked_sub(1) {
+                Some(finished) if finished == now => Some((now, events)),
+                // one with nothing in it is still the last one finished
+                Some(finished) if finished > now => Some((finished, Vec::new())),
+                // a power cycle started the count again
+                _ => None,
+            };
+        }
+        self.frame = Some(frame);
+        if self.current.len() < MAX_EVENTS {
+            let kind = match kind {
+                EventKind::Write {
+                    addr: addr @ 0x2000..=0x3FFF,
+                    value,
+                } => EventKind::Write {
+                    addr: 0x2000 | (addr & 0x0007),
+                    value,
+                },
+                kind => kind,
+            };
+            self.current.push(Event {
+                scanline,
+                dot,
+                kind,
+            });
+        }
+    }
+
+    // the frame in progress, by number
+    pub fn current(&self) -> (u64, &[Event]) {
+        return (self.frame.unwrap_or(0), &self.current);
+    }
+
+    // the last frame finished, once there has been one
+    pub fn last(&self) -> Option<(u64, &[Event])> {
+        return self
+            .last
+            .as_ref()
+            .map(|(frame, events)| (*frame, events.as_slice()));
+    }
+}
+
+// `events` as `rows` rows of `columns` cells, the whole frame, `.` for a
+// cell where nothing happened
+pub fn grid(events: &[Event], rows: usize, columns: usize) -> Vec<String> {
+    let mut cells = vec![vec![None; columns]; rows];
+    for event in events {
+        let row = event.scanline as usize * rows / SCANLINES_PER_FRAME as usize;
+        let column = event.dot as usize * columns / DOTS_PER_SCANLINE as usize;
+        let Some(cell) = cells.get_mut(row).and_then(|row| row.get_mut(column)) else {
+            continue;
+        };
+        let mark = event.kind.mark();
+        if cell.is_none_or(|(_, order)| mark.1 < order) {
+            *cell = Some(mark);
+        }
+    }
+    return cells
+        .into_iter()
+        .map(|row| {
+            row.into_iter()
+                .map(|cell| cell.map_or('.', |(c, _)| c))
+                .collect()
+        })
+        .collect();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_frames_and_grid() {
+        let mut log = EventLog::default();
+        let write = |addr, value| EventKind::Write { addr, value };
+        log.record(0, 241, 1, EventKind::Nmi);
+        log.record(0, 241, 30, write(0x2005, 0x00));
+        log.record(0, 30, 300, EventKind::SpriteZero);
+        log.record(0, 30, 310, write(0x3FFD, 0x80));
+        assert!(log.last().is_none());
+        log.record(1, 0, 5, write(0x8000, 0x01));
+
+        let (frame, events) = log.last().unwrap();
+        assert_eq!(frame, 0);
+        assert_eq!(events.len(), 4);
+        assert_eq!(events[3].to_string(), " 30,310  $2005 = 80");
+        assert_eq!(events[0].to_string(), "241,  1  NMI");
+        assert_eq!(log.current().1.len(), 1);
+
+        let rows = grid(events, 2, 4);
+        assert_eq!(rows, vec!["...0", "N..."]);
+        // sprite 0 outranks the write in the same cell
+        assert_eq!(grid(&events[2..], 1, 1), vec!["0"]);
+
+        // frame 2 went by with nothing in it
+        log.record(3, 10, 10, EventKind::Irq);
+        assert_eq!(log.last(), Some((2, &[][..])));
+        log.record(0, 10, 10, EventKind::Irq);
+        assert_eq!(log.last(), None);
+    }
+}
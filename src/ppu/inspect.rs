@@ -0,0 +1,194 @@
+use crate::mapper::Mapper;
+use crate::ppu::sprite::{
+    ATTR_BEHIND_BACKGROUND, ATTR_FLIP_HORIZONTAL, ATTR_FLIP_VERTICAL, ATTR_PALETTE, SPRITE_COUNT,
+};
+use crate::ppu::{Ppu, PALETTE_SIZE};
+
+pub const TILES_PER_TABLE: usize = 256;
+pub const NAMETABLE_COLUMNS: usize = 32;
+pub const NAMETABLE_ROWS: usize = 30;
+
+/* Inspection
+
+   Decoded, read-only views of what the PPU would draw from, for debuggers,
+   viewers and exporters. None of them touch the registers, the read buffer
+   or the scroll position, so they are safe to call at any point of a frame;
+   the pattern and nametable views go through the mapper, which is needed for
+   its CHR banks and mirroring.
+*/
+
+// one 8x8 tile, each pixel a colour number 0-3 within its palette
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Tile {
+    pub pixels: [[u8; 8]; 8],
+}
+
+impl Tile {
+    // 8 bytes of the low bit plane, then 8 of the high one
+    pub fn decode(data: &[u8; 16]) -> Tile {
+        let mut tile = Tile::default();
+        for (y, row) in tile.pixels.iter_mut().enumerate() {
+            for (x, pixel) in row.iter_mut().enumerate() {
+                let bit = 7 - x;
+                *pixel = ((data[y] >> bit) & 1) | (((data[y + 8] >> bit) & 1) << 1);
+            }
+        }
+        return tile;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Nametable {
+    pub tiles: [[u8; NAMETABLE_COLUMNS]; NAMETABLE_ROWS], // tile index per cell
+    pub palettes: [[u8; NAMETABLE_COLUMNS]; NAMETABLE_ROWS], // background palette 0-3 per cell
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sprite {
+    pub index: usize,
+    pub x: u8,
+    pub y: u16, // the first scanline it is drawn on
+    pub tile: u8,
+    pub palette: u8, // sprite palette 0-3, palette RAM $3F10 + 4 * palette
+    pub behind_background: bool,
+    pub flip_horizontal: bool,
+    pub flip_vertical: bool,
+}
+
+impl Ppu {
+    // `n` is 0-3, as selected by PPUCTRL, before mirroring
+    pub fn nametable(&self, n: usize, mapper: &dyn Mapper) -> Nametable {
+        let mirroring = mapper.mirroring();
+        let base = 0x2000 + (n as u16 & 0b11) * 0x0400;
+        let mut view = Nametable {
+            tiles: [[0; NAMETABLE_COLUMNS]; NAMETABLE_ROWS],
+            palettes: [[0; NAMETABLE_COLUMNS]; NAMETABLE_ROWS],
+        };
+        for row in 0..NAMETABLE_ROWS {
+            for column in 0..NAMETABLE_COLUMNS {
+                let offset = (row * NAMETABLE_COLUMNS + column) as u16;
+                view.tiles[row][column] = self.read_nametable(base + offset, mirroring);
+                let attribute = self.read_nametable(
+                    base + 0x03C0 + ((row / 4) * 8 + column / 4) as u16,
+                    mirroring,
+                );
+                let shift = ((row % 4) / 2) * 4 + ((column % 4) / 2) * 2;
+                view.palettes[row][column] = (attribute >> shift) & 0b11;
+            }
+        }
+        return view;
+    }
+
+    // `side` 0 is the table at $0000, 1 the one at $1000
+    pub fn pattern_table(&self, side: usize, mapper: &mut dyn Mapper) -> Vec<Tile> {
+        let base = (side as u16 & 1) * 0x1000;
+        let mut tiles = Vec::with_capacity(TILES_PER_TABLE);
+        for tile in 0..TILES_PER_TABLE as u16 {
+            let mut data = [0; 16];
+            for (i, byte) in data.iter_mut().enumerate() {
+                *byte = mapper.ppu_read(base + tile * 16 + i as u16);
+            }
+            tiles.push(Tile::decode(&data));
+        }
+        return tiles;
+    }
+
+    // palette RAM as RGB, backgrounds first then sprites, mirrored entries included
+    pub fn palette_colors(&self) -> [(u8, u8, u8); PALETTE_SIZE] {
+        let mut colours = [(0, 0, 0); PALETTE_SIZE];
+        for (i, colour) in colours.iter_mut().enumerate() {
+            *colour = self.colours[self.read_palette(0x3F00 + i as u16) as usize];
+        }
+        return colours;
+    }
+
+    pub fn oam_entries(&self) -> Vec<Sprite> {
+        let mut sprites = Vec::with_capacity(SPRITE_COUNT);
+        for (index, entry) in self.oam.chunks(4).enumerate() {
+            let attributes = entry[2];
+            sprites.push(Sprite {
+                index,
+                x: entry[3],
+                y: entry[0] as u16 + 1,
+                tile: entry[1],
+                palette: attributes & ATTR_PALETTE,
+                behind_background: attributes & ATTR_BEHIND_BACKGROUND != 0,
+                flip_horizontal: attributes & ATTR_FLIP_HORIZONTAL != 0,
+                flip_vertical: attributes & ATTR_FLIP_VERTICAL != 0,
+            });
+        }
+        return sprites;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ppu::palette::SYSTEM_PALETTE;
+    use crate::ppu::test::chr_ram_mapper;
+
+    #[test]
+    fn test_tile_decode() {
+        let mut data = [0; 16];
+        data[0] = 0b1000_0001;
+        data[8] = 0b1100_0000;
+        let tile = Tile::decode(&data);
+        assert_eq!(tile.pixels[0], [3, 2, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(tile.pixels[1], [0; 8]);
+    }
+
+    #[test]
+    fn test_pattern_table() {
+        let ppu = Ppu::new();
+        let mut mapper = chr_ram_mapper();
+        mapper.ppu_write(0x1000 + 5 * 16 + 7, 0xFF); // bottom row of tile 5
+        let tiles = ppu.pattern_table(1, mapper.as_mut());
+        assert_eq!(tiles.len(), TILES_PER_TABLE);
+        assert_eq!(tiles[5].pixels[7], [1; 8]);
+        assert_eq!(ppu.pattern_table(0, mapper.as_mut())[5], Tile::default());
+    }
+
+    #[test]
+    fn test_nametable_view() {
+        let mut ppu = Ppu::new();
+        let mapper = chr_ram_mapper();
+        let mirroring = mapper.mirroring();
+        ppu.write_nametable(0x2400 + 3 * 32 + 2, mirroring, 0x42);
+        ppu.write_nametable(0x27C0, mirroring, 0b0000_1100);
+        let view = ppu.nametable(1, mapper.as_ref());
+        assert_eq!(view.tiles[3][2], 0x42);
+        assert_eq!(view.palettes[0][2], 3);
+        assert_eq!(view.palettes[2][2], 0);
+    }
+
+    #[test]
+    fn test_palette_colors() {
+        let mut ppu = Ppu::new();
+        ppu.write_palette(0x3F00, 0x0F);
+        ppu.write_palette(0x3F15, 0x30);
+        let colours = ppu.palette_colors();
+        assert_eq!(colours[0x15], SYSTEM_PALETTE[0x30]);
+        assert_eq!(colours[0x10], SYSTEM_PALETTE[0x0F]); // mirror of the backdrop
+    }
+
+    #[test]
+    fn test_oam_entries() {
+        let mut ppu = Ppu::new();
+        ppu.oam_mut()[8..12].copy_from_slice(&[39, 0x21, 0b1010_0010, 200]);
+        let sprites = ppu.oam_entries();
+        assert_eq!(sprites.len(), SPRITE_COUNT);
+        assert_eq!(
+            sprites[2],
+            Sprite {
+                index: 2,
+                x: 200,
+                y: 40,
+                tile: 0x21,
+                palette: 2,
+                behind_background: true,
+                flip_horizontal: false,
+                flip_vertical: true,
+            }
+        );
+    }
+}
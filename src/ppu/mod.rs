@@ -0,0 +1,837 @@
+use crate::cartridge::Mirroring;
+use crate::error::StateError;
+use crate::mapper::Mapper;
+use crate::savestate::{StateReader, StateWriter};
+use crate::video::Frame;
+
+pub mod events;
+pub mod inspect;
+pub mod palette;
+pub mod pipeline;
+pub mod render;
+pub mod scroll;
+pub mod sprite;
+
+use palette::{Colours, SYSTEM_PALETTE};
+use pipeline::Pipeline;
+use render::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+pub const VRAM_SIZE: usize = 0x0800;
+pub const PALETTE_SIZE: usize = 0x20;
+pub const OAM_SIZE: usize = 0x100;
+
+pub const DOTS_PER_SCANLINE: u16 = 341;
+pub const SCANLINES_PER_FRAME: u16 = 262;
+pub const VBLANK_SCANLINE: u16 = 241;
+pub const PRE_RENDER_SCANLINE: u16 = 261;
+
+const PPUCTRL: u16 = 0x2000;
+const PPUMASK: u16 = 0x2001;
+const PPUSTATUS: u16 = 0x2002;
+const OAMADDR: u16 = 0x2003;
+const OAMDATA: u16 = 0x2004;
+const PPUSCROLL: u16 = 0x2005;
+const PPUADDR: u16 = 0x2006;
+const PPUDATA: u16 = 0x2007;
+
+const CTRL_NMI: u8 = 0b1000_0000;
+const CTRL_SPRITE_SIZE: u8 = 0b0010_0000;
+const CTRL_BACKGROUND_TABLE: u8 = 0b0001_0000;
+const CTRL_SPRITE_TABLE: u8 = 0b0000_1000;
+const CTRL_INCREMENT_32: u8 = 0b0000_0100;
+const CTRL_NAMETABLE: u8 = 0b0000_0011;
+const MASK_EMPHASIS: u8 = 0b1110_0000;
+const MASK_SPRITES: u8 = 0b0001_0000;
+const MASK_BACKGROUND: u8 = 0b0000_1000;
+const MASK_SPRITES_LEFT: u8 = 0b0000_0100;
+const MASK_BACKGROUND_LEFT: u8 = 0b0000_0010;
+const MASK_GRAYSCALE: u8 = 0b0000_0001;
+const STATUS_VBLANK: u8 = 0b1000_0000;
+const STATUS_SPRITE_ZERO: u8 = 0b0100_0000;
+const STATUS_OVERFLOW: u8 = 0b0010_0000;
+
+/* PPU registers, mirrored every 8 bytes through $2000-$3FFF
+    $2000  PPUCTRL    write  VPHB SINN  NMI enable, sprite size, pattern tables, increment, nametable
+    $2001  PPUMASK    write  BGRs bMmG  emphasis, show sprites/background (and in the left 8 pixels), grayscale
+    $2002  PPUSTATUS  read   VSO- ----  vblank, sprite 0 hit, sprite overflow; reading clears V and the write toggle
+    $2003  OAMADDR    write
+    $2004  OAMDATA    read/write, writes increment OAMADDR
+    $2005  PPUSCROLL  write x2
+    $2006  PPUADDR    write x2
+    $2007  PPUDATA    read/write
+
+   The register port has no real storage for reads of write-only registers;
+   they return whatever was last driven onto it (the "open bus" latch).
+
+   PPUSCROLL and PPUADDR write into the same internal registers, see scroll.rs.
+   PPUADDR takes the high byte first, sharing its write toggle with PPUSCROLL.
+   PPUDATA reads below the palette come from a one byte buffer that is refilled
+   after the read, so the first read after setting the address returns stale
+   data. Palette reads are answered immediately but still refill the buffer,
+   with the nametable byte "under" the palette. Either access then steps the
+   address by 1 or by 32 (one nametable row) depending on PPUCTRL bit 2.
+
+   PPU address space
+    $0000-$1FFF  pattern tables, on the cartridge
+    $2000-$2FFF  four 1KB nametables backed by the 2KB of VRAM, the cartridge picks the mirroring
+    $3000-$3EFF  mirror of $2000-$2EFF
+    $3F00-$3FFF  palette RAM, 32 bytes mirrored
+
+   Palette RAM is four background palettes then four sprite palettes of four
+   entries. Entry 0 of each sprite palette is the same byte as the one of the
+   background palette below it, so $3F10 is $3F00, $3F14 is $3F04 and so on.
+
+   262 scanlines of 341 dots, three dots per CPU cycle, one dot less on odd
+   frames while rendering is on. Scanlines 0-239 are
+   visible. By default each is drawn into the frame buffer at dot 256, sprites
+   included (see render.rs and sprite.rs); `RenderMode::Dot` runs the real
+   fetch pipeline a dot at a time instead (pipeline.rs), slower but exact. Vblank starts on
+   dot 1 of scanline 241 and ends on dot 1 of the pre-render scanline.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RenderMode {
+    #[default]
+    Scanline,
+    Dot,
+}
+
+impl RenderMode {
+    pub fn parse(name: &str) -> Result<RenderMode, String> {
+        match name {
+            "scanline" => return Ok(RenderMode::Scanline),
+            "dot" => return Ok(RenderMode::Dot),
+            _ => return Err(format!("unknown render mode {}", name)),
+        }
+    }
+}
+
+pub struct Ppu {
+    vram: [u8; VRAM_SIZE],
+    four_screen_vram: [u8; VRAM_SIZE], // carried on the cartridge on real boards
+    palette: [u8; PALETTE_SIZE],
+    oam: [u8; OAM_SIZE],
+
+    ctrl: u8,
+    mask: u8,
+    status: u8,
+    oam_addr: u8,
+    write_toggle: bool,
+    vram_addr: u16,       // v
+    temp_addr: u16,       // t
+    fine_x: u8,           // x
+    line_start_addr: u16, // v when the next scanline's first tiles are fetched
+    read_buffer: u8,
+    open_bus: u8,
+
+    scanline: u16,
+    dot: u16,
+    frame: u64,
+    nmi_pending: bool,
+    sprite_zero_hit: Option<(u16, u16)>, // where, for the event log
+    output: Frame,
+    frame_complete: bool,
+    pipeline: Pipeline,
+    colours: Colours,
+
+    // reproduce the sprite overflow scan bug, off gives the flag games probably meant
+    pub accurate_sprite_overflow: bool,
+    // can be switched at any time, the dot renderer settles within a frame
+    pub render_mode: RenderMode,
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl Ppu {
+    pub fn new() -> Self {
+        return Self {
+            vram: [0; VRAM_SIZE],
+            four_screen_vram: [0; VRAM_SIZE],
+            palette: [0; PALETTE_SIZE],
+            oam: [0; OAM_SIZE],
+            ctrl: 0,
+            mask: 0,
+            status: 0,
+            oam_addr: 0,
+            write_toggle: false,
+            vram_addr: 0,
+            temp_addr: 0,
+            fine_x: 0,
+            line_start_addr: 0,
+            read_buffer: 0,
+            open_bus: 0,
+            scanline: 0,
+            dot: 0,
+            frame: 0,
+            nmi_pending: false,
+            sprite_zero_hit: None,
+            output: Frame::new(SCREEN_WIDTH, SCREEN_HEIGHT),
+            frame_complete: false,
+            pipeline: Pipeline::default(),
+            colours: SYSTEM_PALETTE,
+            accurate_sprite_overflow: true,
+            render_mode: RenderMode::Scanline,
+        };
+    }
+
+    pub fn ctrl(&self) -> u8 {
+        return self.ctrl;
+    }
+
+    pub fn mask(&self) -> u8 {
+        return self.mask;
+    }
+
+    pub fn status(&self) -> u8 {
+        return self.status;
+    }
+
+    // the scroll position last written through PPUSCROLL/PPUCTRL, as (x, y)
+    pub fn scroll(&self) -> (u8, u8) {
+        let t = self.temp_addr;
+        let x = ((t & 0x1F) << 3) as u8 | self.fine_x;
+        let y = (((t >> 5) & 0x1F) << 3) as u8 | ((t >> 12) & 0b111) as u8;
+        return (x, y);
+    }
+
+    pub fn vram_addr(&self) -> u16 {
+        return self.vram_addr;
+    }
+
+    pub fn vram(&self) -> &[u8] {
+        return &self.vram;
+    }
+
+    pub fn vram_mut(&mut self) -> &mut [u8] {
+        return &mut self.vram;
+    }
+
+    pub fn palette_ram(&self) -> &[u8] {
+        return &self.palette;
+    }
+
+    pub fn oam(&self) -> &[u8] {
+        return &self.oam;
+    }
+
+    pub fn oam_mut(&mut self) -> &mut [u8] {
+        return &mut self.oam;
+    }
+
+    pub fn scanline(&self) -> u16 {
+        return self.scanline;
+    }
+
+    pub fn dot(&self) -> u16 {
+        return self.dot;
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        return self.frame;
+    }
+
+    // the picture so far, complete once vblank has started
+    pub fn frame_buffer(&self) -> &[u8] {
+        return &self.output.pixels;
+    }
+
+    pub fn frame(&self) -> &Frame {
+        return &self.output;
+    }
+
+    // set at the start of vblank, when the last visible line has been drawn
+    pub fn frame_complete(&self) -> bool {
+        return self.frame_complete;
+    }
+
+    // a copy of the finished frame, once per frame
+    pub fn take_frame(&mut self) -> Option<Frame> {
+        if !self.frame_complete {
+            return None;
+        }
+        self.frame_complete = false;
+        return Some(self.output.clone());
+    }
+
+    // the RGB colours palette RAM indices are shown as, see palette.rs
+    pub fn system_palette(&self) -> &Colours {
+        return &self.colours;
+    }
+
+    pub fn set_palette(&mut self, colours: &Colours) {
+        self.colours = *colours;
+    }
+
+    pub fn in_vblank(&self) -> bool {
+        return self.status & STATUS_VBLANK != 0;
+    }
+
+    pub fn read_register(&mut self, addr: u16, mapper: &mut dyn Mapper) -> u8 {
+        match 0x2000 | (addr & 0b111) {
+            PPUSTATUS => {
+                // only the top three bits are driven, the rest is stale bus
+                let value = (self.status & 0b1110_0000) | (self.open_bus & 0b0001_1111);
+                self.status &= !STATUS_VBLANK;
+                self.write_toggle = false;
+                self.open_bus = value;
+            }
+            OAMDATA => self.open_bus = self.oam[self.oam_addr as usize],
+            PPUDATA => {
+                let addr = self.vram_addr & 0x3FFF;
+                if addr >= 0x3F00 {
+                    // palette entries are 6 bits, the top two come from the bus
+                    self.open_bus =
+                        (self.read_palette(addr) & 0b0011_1111) | (self.open_bus & 0b1100_0000);
+                    self.read_buffer = self.read_memory(addr - 0x1000, mapper);
+                } else {
+                    self.open_bus = self.read_buffer;
+                    self.read_buffer = self.read_memory(addr, mapper);
+                }
+                self.increment_vram_addr();
+            }
+            _ => {} // write-only
+        }
+        return self.open_bus;
+    }
+
+    pub fn write_register(&mut self, addr: u16, data: u8, mapper: &mut dyn Mapper) {
+        self.open_bus = data;
+        match 0x2000 | (addr & 0b111) {
+            PPUCTRL => {
+                let was_enabled = self.ctrl & CTRL_NMI != 0;
+                self.ctrl = data;
+                self.temp_addr =
+                    (self.temp_addr & !0x0C00) | ((data & CTRL_NAMETABLE) as u16) << 10;
+                // enabling NMI during vblank fires one straight away
+                if !was_enabled && data & CTRL_NMI != 0 && self.in_vblank() {
+                    self.nmi_pending = true;
+                }
+            }
+            PPUMASK => self.mask = data,
+            OAMADDR => self.oam_addr = data,
+            OAMDATA => {
+                self.oam[self.oam_addr as usize] = data;
+                self.oam_addr = self.oam_addr.wrapping_add(1);
+            }
+            PPUSCROLL => {
+                if !self.write_toggle {
+                    self.temp_addr = (self.temp_addr & !0x001F) | (data >> 3) as u16;
+                    self.fine_x = data & 0b111;
+                } else {
+                    self.temp_addr = (self.temp_addr & !0x73E0)
+                        | ((data & 0b111) as u16) << 12
+                        | ((data & 0xF8) as u16) << 2;
+                }
+                self.write_toggle = !self.write_toggle;
+            }
+            PPUADDR => {
+                if !self.write_toggle {
+                    // the top bit of t is cleared as well
+                    self.temp_addr = ((data as u16 & 0x3F) << 8) | (self.temp_addr & 0x00FF);
+                } else {
+                    self.temp_addr = (self.temp_addr & 0xFF00) | data as u16;
+                    self.vram_addr = self.temp_addr;
+                }
+                self.write_toggle = !self.write_toggle;
+            }
+            PPUDATA => {
+                self.write_memory(self.vram_addr & 0x3FFF, data, mapper);
+                self.increment_vram_addr();
+            }
+            _ => {}
+        }
+    }
+
+    fn increment_vram_addr(&mut self) {
+        // during rendering the access bumps the scroll counters instead
+        if self.rendering_enabled() && self.on_render_line() {
+            self.increment_coarse_x();
+            self.increment_y();
+            return;
+        }
+        let step = if self.ctrl & CTRL_INCREMENT_32 != 0 {
+            32
+        } else {
+            1
+        };
+        self.vram_addr = self.vram_addr.wrapping_add(step) & 0x7FFF;
+    }
+
+    pub fn read_memory(&self, addr: u16, mapper: &mut dyn Mapper) -> u8 {
+        match addr & 0x3FFF {
+            0x0000..=0x1FFF => return mapper.ppu_read(addr),
+            0x2000..=0x3EFF => return self.read_nametable(addr, mapper.mirroring()),
+            _ => return self.read_palette(addr),
+        }
+    }
+
+    pub fn write_memory(&mut self, addr: u16, data: u8, mapper: &mut dyn Mapper) {
+        match addr & 0x3FFF {
+            0x0000..=0x1FFF => mapper.ppu_write(addr, data),
+            0x2000..=0x3EFF => self.write_nametable(addr, mapper.mirroring(), data),
+            _ => self.write_palette(addr, data),
+        }
+    }
+
+    // $4014, the bus has already fetched the 256 bytes from CPU memory
+    pub fn oam_dma(&mut self, page: &[u8; OAM_SIZE]) {
+        for byte in page {
+            self.oam[self.oam_addr as usize] = *byte;
+            self.oam_addr = self.oam_addr.wrapping_add(1);
+        }
+    }
+
+    // the scanline and dot the flag was set at, since the last call
+    pub fn take_sprite_zero_hit(&mut self) -> Option<(u16, u16)> {
+        return self.sprite_zero_hit.take();
+    }
+
+    // `dot` being where the pixel is, the scanline renderer draws a line at once
+    fn hit_sprite_zero(&mut self, dot: u16) {
+        if self.status & STATUS_SPRITE_ZERO == 0 {
+            self.status |= STATUS_SPRITE_ZERO;
+            self.sprite_zero_hit = Some((self.scanline, dot));
+        }
+    }
+
+    pub fn take_nmi(&mut self) -> bool {
+        let pending = self.nmi_pending;
+        self.nmi_pending = false;
+        return pending;
+    }
+
+    // without a cartridge there is nothing to fetch tiles from, only the timing runs
+    pub fn tick(&mut self, dots: u64, mut mapper: Option<&mut dyn Mapper>) {
+        for _ in 0..dots {
+            self.dot += 1;
+            // with rendering on, odd frames skip the last dot of the pre-render line
+            if self.dot == DOTS_PER_SCANLINE - 1
+                && self.scanline == PRE_RENDER_SCANLINE
+                && self.frame % 2 == 1
+                && self.rendering_enabled()
+            {
+                self.dot = DOTS_PER_SCANLINE;
+            }
+            if self.dot == DOTS_PER_SCANLINE {
+                self.dot = 0;
+                self.scanline += 1;
+                if self.scanline == SCANLINES_PER_FRAME {
+                    self.scanline = 0;
+                    self.frame += 1;
+                }
+            }
+            self.clock_scroll();
+
+            if let Some(mapper) = mapper.as_deref_mut() {
+                match self.render_mode {
+                    RenderMode::Scanline => {
+                        if self.dot == 256 && (self.scanline as usize) < SCREEN_HEIGHT {
+                            self.render_scanline(mapper);
+                        }
+                    }
+                    RenderMode::Dot => self.clock_pipeline(mapper),
+                }
+            }
+
+            if self.dot == 1 {
+                match self.scanline {
+                    VBLANK_SCANLINE => {
+                        self.status |= STATUS_VBLANK;
+                        self.frame_complete = true;
+                        if self.ctrl & CTRL_NMI != 0 {
+                            self.nmi_pending = true;
+                        }
+                    }
+                    PRE_RENDER_SCANLINE => {
+                        self.status &= !(STATUS_VBLANK | STATUS_SPRITE_ZERO | STATUS_OVERFLOW);
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    // VRAM offset of a nametable address under the cartridge's mirroring
+    pub fn nametable_offset(addr: u16, mirroring: Mirroring) -> usize {
+        let addr = addr as usize & 0x0FFF;
+        let table = addr / 0x0400;
+        let physical = match mirroring {
+            Mirroring::Vertical => table & 1,
+            Mirroring::Horizontal => table >> 1,
+            Mirroring::SingleScreenLower => 0,
+            Mirroring::SingleScreenUpper => 1,
+            Mirroring::FourScreen => table,
+        };
+        return physical * 0x0400 + (addr & 0x03FF);
+    }
+
+    pub fn read_nametable(&self, addr: u16, mirroring: Mirroring) -> u8 {
+        let offset = Self::nametable_offset(addr, mirroring);
+        if offset >= VRAM_SIZE {
+            return self.four_screen_vram[offset - VRAM_SIZE];
+        }
+        return self.vram[offset];
+    }
+
+    pub fn write_nametable(&mut self, addr: u16, mirroring: Mirroring, data: u8) {
+        let offset = Self::nametable_offset(addr, mirroring);
+        if offset >= VRAM_SIZE {
+            self.four_screen_vram[offset - VRAM_SIZE] = data;
+        } else {
+            self.vram[offset] = data;
+        }
+    }
+
+    fn palette_index(addr: u16) -> usize {
+        let index = addr as usize % PALETTE_SIZE;
+        if index & 0x13 == 0x10 {
+            return index & 0x0F;
+        }
+        return index;
+    }
+
+    pub fn read_palette(&self, addr: u16) -> u8 {
+        return self.palette[Self::palette_index(addr)];
+    }
+
+    pub fn write_palette(&mut self, addr: u16, data: u8) {
+        // palette entries are 6 bits wide
+        self.palette[Self::palette_index(addr)] = data & 0b0011_1111;
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.vram);
+        writer.write_bytes(&self.four_screen_vram);
+        writer.write_bytes(&self.palette);
+        writer.write_bytes(&self.oam);
+        writer.write_u8(self.ctrl);
+        writer.write_u8(self.mask);
+        writer.write_u8(self.status);
+        writer.write_u8(self.oam_addr);
+        writer.write_bool(self.write_toggle);
+        writer.write_u16(self.vram_addr);
+        writer.write_u16(self.temp_addr);
+        writer.write_u8(self.fine_x);
+        writer.write_u16(self.line_start_addr);
+        writer.write_u8(self.read_buffer);
+        writer.write_u8(self.open_bus);
+        writer.write_u16(self.scanline);
+        writer.write_u16(self.dot);
+        writer.write_u64(self.frame);
+        writer.write_bool(self.nmi_pending);
+        self.pipeline.save_state(writer);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        reader.read_into(&mut self.vram)?;
+        reader.read_into(&mut self.four_screen_vram)?;
+        reader.read_into(&mut self.palette)?;
+        reader.read_into(&mut self.oam)?;
+        self.ctrl = reader.read_u8()?;
+        self.mask = reader.read_u8()?;
+        self.status = reader.read_u8()?;
+        self.oam_addr = reader.read_u8()?;
+        self.write_toggle = reader.read_bool()?;
+        self.vram_addr = reader.read_u16()? & 0x7FFF;
+        self.temp_addr = reader.read_u16()? & 0x7FFF;
+        self.fine_x = reader.read_u8()? & 0b111;
+        self.line_start_addr = reader.read_u16()? & 0x7FFF;
+        self.read_buffer = reader.read_u8()?;
+        self.open_bus = reader.read_u8()?;
+        self.scanline = reader.read_u16()? % SCANLINES_PER_FRAME;
+        self.dot = reader.read_u16()? % DOTS_PER_SCANLINE;
+        self.frame = reader.read_u64()?;
+        self.nmi_pending = reader.read_bool()?;
+        self.pipeline.load_state(reader)?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+    use crate::cartridge::Rom;
+    use crate::mapper::nrom::Nrom;
+
+    // NROM with 8KB of CHR RAM, so pattern table writes stick
+    pub fn chr_ram_mapper() -> Box<dyn Mapper> {
+        return Box::new(Nrom::new(Rom::new(&test_rom(0, 1, 0)).unwrap()));
+    }
+
+    // the number of dots from power on to dot 1 of `scanline`
+    fn dots_until(scanline: u16) -> u64 {
+        return scanline as u64 * DOTS_PER_SCANLINE as u64 + 1;
+    }
+
+    #[test]
+    fn test_vblank_and_nmi() {
+        let mut ppu = Ppu::new();
+        let mut mapper = chr_ram_mapper();
+        ppu.write_register(0x2000, CTRL_NMI, mapper.as_mut());
+        ppu.tick(dots_until(VBLANK_SCANLINE) - 1, None);
+        assert!(!ppu.in_vblank());
+        ppu.tick(1, None);
+        assert!(ppu.in_vblank());
+        assert!(ppu.take_nmi());
+        assert!(!ppu.take_nmi());
+
+        ppu.tick(
+            (PRE_RENDER_SCANLINE - VBLANK_SCANLINE) as u64 * DOTS_PER_SCANLINE as u64,
+            None,
+        );
+        assert!(!ppu.in_vblank());
+        ppu.tick((DOTS_PER_SCANLINE - 1) as u64, None);
+        assert_eq!(ppu.frame_count(), 1);
+        assert_eq!((ppu.scanline(), ppu.dot()), (0, 0));
+    }
+
+    #[test]
+    fn test_take_frame_once_per_frame() {
+        let mut ppu = Ppu::new();
+        assert!(ppu.take_frame().is_none());
+        ppu.tick(dots_until(VBLANK_SCANLINE), None);
+        assert!(ppu.frame_complete());
+        let frame = ppu.take_frame().unwrap();
+        assert_eq!((frame.width, frame.height), (SCREEN_WIDTH, SCREEN_HEIGHT));
+        assert_eq!(frame.pixels, ppu.frame_buffer());
+        assert!(!ppu.frame_complete());
+        assert!(ppu.take_frame().is_none());
+    }
+
+    #[test]
+    fn test_odd_frames_are_one_dot_short_while_rendering() {
+        let mut ppu = Ppu::new();
+        let mut mapper = chr_ram_mapper();
+        let frame = DOTS_PER_SCANLINE as u64 * SCANLINES_PER_FRAME as u64;
+        ppu.tick(frame * 2, None);
+        assert_eq!((ppu.frame_count(), ppu.scanline(), ppu.dot()), (2, 0, 0));
+
+        ppu.write_register(0x2001, MASK_SPRITES, mapper.as_mut());
+        ppu.tick(frame * 2 - 1, None);
+        assert_eq!((ppu.frame_count(), ppu.scanline(), ppu.dot()), (4, 0, 0));
+    }
+
+    #[test]
+    fn test_disabled_rendering_has_no_side_effects() {
+        let mut ppu = Ppu::new();
+        let mut mapper = chr_ram_mapper();
+        // nine sprites on line 11 would set the overflow flag
+        for n in 0..9 {
+            ppu.oam_mut()[n * 4] = 10;
+        }
+        ppu.write_register(0x2006, 0x21, mapper.as_mut());
+        ppu.write_register(0x2006, 0x08, mapper.as_mut());
+        ppu.tick(dots_until(VBLANK_SCANLINE), Some(mapper.as_mut()));
+        assert_eq!(ppu.vram_addr(), 0x2108);
+        assert_eq!(ppu.status() & STATUS_OVERFLOW, 0);
+
+        ppu.write_register(0x2001, MASK_BACKGROUND, mapper.as_mut());
+        ppu.tick(
+            SCANLINES_PER_FRAME as u64 * DOTS_PER_SCANLINE as u64,
+            Some(mapper.as_mut()),
+        );
+        assert_ne!(ppu.status() & STATUS_OVERFLOW, 0);
+    }
+
+    #[test]
+    fn test_status_read_clears_vblank_and_toggle() {
+        let mut ppu = Ppu::new();
+        let mut mapper = chr_ram_mapper();
+        ppu.tick(dots_until(VBLANK_SCANLINE), None);
+        ppu.write_register(0x2005, 0x12, mapper.as_mut()); // first write flips the toggle
+        ppu.write_register(0x2000, 0b0001_1111, mapper.as_mut()); // leaves junk on the bus
+        assert_eq!(ppu.read_register(0x2002, mapper.as_mut()), 0b1001_1111);
+        assert_eq!(
+            ppu.read_register(0x2002, mapper.as_mut()) & STATUS_VBLANK,
+            0
+        );
+
+        ppu.write_register(0x2005, 0x34, mapper.as_mut());
+        assert_eq!(ppu.scroll(), (0x34, 0x00));
+    }
+
+    #[test]
+    fn test_enabling_nmi_during_vblank() {
+        let mut ppu = Ppu::new();
+        let mut mapper = chr_ram_mapper();
+        ppu.tick(dots_until(VBLANK_SCANLINE), None);
+        assert!(!ppu.take_nmi());
+        ppu.write_register(0x2000, CTRL_NMI, mapper.as_mut());
+        assert!(ppu.take_nmi());
+        // rewriting with NMI still enabled doesn't fire again
+        ppu.write_register(0x2000, CTRL_NMI, mapper.as_mut());
+        assert!(!ppu.take_nmi());
+    }
+
+    #[test]
+    fn test_oam_access() {
+        let mut ppu = Ppu::new();
+        let mut mapper = chr_ram_mapper();
+        ppu.write_register(0x2003, 0xFF, mapper.as_mut());
+        ppu.write_register(0x2004, 0x11, mapper.as_mut());
+        ppu.write_register(0x2004, 0x22, mapper.as_mut()); // wraps to 0
+        assert_eq!(ppu.oam()[0xFF], 0x11);
+        assert_eq!(ppu.oam()[0x00], 0x22);
+
+        ppu.write_register(0x200B, 0x00, mapper.as_mut()); // $2003 mirror
+        assert_eq!(ppu.read_register(0x2004, mapper.as_mut()), 0x22);
+
+        let mut page = [0u8; OAM_SIZE];
+        page[0] = 0xAA;
+        ppu.write_register(0x2003, 0x10, mapper.as_mut());
+        ppu.oam_dma(&page);
+        assert_eq!(ppu.oam()[0x10], 0xAA);
+    }
+
+    #[test]
+    fn test_write_only_registers_read_open_bus() {
+        let mut ppu = Ppu::new();
+        let mut mapper = chr_ram_mapper();
+        ppu.write_register(0x2001, 0x5A, mapper.as_mut());
+        assert_eq!(ppu.read_register(0x2000, mapper.as_mut()), 0x5A);
+        assert_eq!(ppu.read_register(0x3FFD, mapper.as_mut()), 0x5A); // $2005 mirror
+    }
+
+    #[test]
+    fn test_nametable_mirroring() {
+        assert_eq!(Ppu::nametable_offset(0x2400, Mirroring::Vertical), 0x0400);
+        assert_eq!(Ppu::nametable_offset(0x2800, Mirroring::Vertical), 0x0000);
+        assert_eq!(Ppu::nametable_offset(0x2400, Mirroring::Horizontal), 0x0000);
+        assert_eq!(Ppu::nametable_offset(0x2C05, Mirroring::Horizontal), 0x0405);
+        assert_eq!(
+            Ppu::nametable_offset(0x2C00, Mirroring::SingleScreenLower),
+            0
+        );
+        assert_eq!(Ppu::nametable_offset(0x3000, Mirroring::Vertical), 0x0000);
+
+        let mut ppu = Ppu::new();
+        ppu.write_nametable(0x2C00, Mirroring::FourScreen, 7);
+        assert_eq!(ppu.read_nametable(0x2C00, Mirroring::FourScreen), 7);
+        assert_eq!(ppu.read_nametable(0x2400, Mirroring::FourScreen), 0);
+    }
+
+    fn set_addr(ppu: &mut Ppu, mapper: &mut dyn Mapper, addr: u16) {
+        ppu.write_register(0x2006, (addr >> 8) as u8, mapper);
+        ppu.write_register(0x2006, addr as u8, mapper);
+    }
+
+    #[test]
+    fn test_ppudata_read_is_buffered() {
+        let mut ppu = Ppu::new();
+        let mut mapper = chr_ram_mapper();
+        set_addr(&mut ppu, mapper.as_mut(), 0x2305);
+        ppu.write_register(0x2007, 0x66, mapper.as_mut());
+        ppu.write_register(0x2007, 0x77, mapper.as_mut());
+        assert_eq!(ppu.vram_addr(), 0x2307);
+
+        set_addr(&mut ppu, mapper.as_mut(), 0x2305);
+        // the first read returns whatever was in the buffer before
+        assert_eq!(ppu.read_register(0x2007, mapper.as_mut()), 0x00);
+        assert_eq!(ppu.read_register(0x2007, mapper.as_mut()), 0x66);
+        assert_eq!(ppu.read_register(0x2007, mapper.as_mut()), 0x77);
+    }
+
+    #[test]
+    fn test_ppudata_pattern_tables_go_to_the_cartridge() {
+        let mut ppu = Ppu::new();
+        let mut mapper = chr_ram_mapper();
+        set_addr(&mut ppu, mapper.as_mut(), 0x1FFF);
+        ppu.write_register(0x2007, 0xAB, mapper.as_mut());
+        assert_eq!(mapper.ppu_read(0x1FFF), 0xAB);
+    }
+
+    #[test]
+    fn test_palette_reads_bypass_the_buffer() {
+        let mut ppu = Ppu::new();
+        let mut mapper = chr_ram_mapper();
+        set_addr(&mut ppu, mapper.as_mut(), 0x2F01);
+        ppu.write_register(0x2007, 0x55, mapper.as_mut());
+        set_addr(&mut ppu, mapper.as_mut(), 0x3F01);
+        ppu.write_register(0x2007, 0x21, mapper.as_mut());
+
+        set_addr(&mut ppu, mapper.as_mut(), 0x3F01);
+        assert_eq!(ppu.read_register(0x2007, mapper.as_mut()), 0x21);
+        // meanwhile the buffer picked up the nametable byte at $2F01
+        set_addr(&mut ppu, mapper.as_mut(), 0x0000);
+        assert_eq!(ppu.read_register(0x2007, mapper.as_mut()), 0x55);
+    }
+
+    #[test]
+    fn test_increment_by_32() {
+        let mut ppu = Ppu::new();
+        let mut mapper = chr_ram_mapper();
+        ppu.write_register(0x2000, CTRL_INCREMENT_32, mapper.as_mut());
+        set_addr(&mut ppu, mapper.as_mut(), 0x2000);
+        ppu.write_register(0x2007, 1, mapper.as_mut());
+        ppu.write_register(0x2007, 2, mapper.as_mut());
+        assert_eq!(ppu.vram_addr(), 0x2040);
+        assert_eq!(ppu.read_nametable(0x2020, Mirroring::Horizontal), 2);
+    }
+
+    #[test]
+    fn test_ppuaddr_latch() {
+        let mut ppu = Ppu::new();
+        let mut mapper = chr_ram_mapper();
+        // the high byte only keeps 6 bits, so $7F05 lands on $3F05
+        set_addr(&mut ppu, mapper.as_mut(), 0x7F05);
+        assert_eq!(ppu.vram_addr(), 0x3F05);
+
+        // a status read in between resets the toggle, the next write is a high byte again
+        ppu.write_register(0x2006, 0x21, mapper.as_mut());
+        ppu.read_register(0x2002, mapper.as_mut());
+        set_addr(&mut ppu, mapper.as_mut(), 0x2400);
+        assert_eq!(ppu.vram_addr(), 0x2400);
+
+        // only the second write updates the address
+        ppu.write_register(0x2006, 0x23, mapper.as_mut());
+        assert_eq!(ppu.vram_addr(), 0x2400);
+
+        // addresses above $3FFF mirror down
+        ppu.write_register(0x2006, 0x00, mapper.as_mut());
+        ppu.write_memory(0x3F10 + 0x4000, 0x0C, mapper.as_mut());
+        assert_eq!(ppu.read_palette(0x3F10), 0x0C);
+    }
+
+    #[test]
+    fn test_save_load_state() {
+        let mut ppu = Ppu::new();
+        let mut mapper = chr_ram_mapper();
+        ppu.write_register(0x2000, 0x90, mapper.as_mut());
+        ppu.write_register(0x2004, 0x42, mapper.as_mut());
+        ppu.write_nametable(0x2001, Mirroring::Vertical, 3);
+        ppu.write_palette(0x3F01, 0x2A);
+        ppu.tick(1000, None);
+        let mut writer = StateWriter::new();
+        ppu.save_state(&mut writer);
+        let data = writer.into_bytes();
+
+        let mut restored = Ppu::new();
+        restored.load_state(&mut StateReader::new(&data)).unwrap();
+        assert_eq!(restored.ctrl(), 0x90);
+        assert_eq!(restored.oam()[0], 0x42);
+        assert_eq!(restored.vram()[1], 3);
+        assert_eq!(restored.read_palette(0x3F01), 0x2A);
+        assert_eq!(
+            (restored.scanline(), restored.dot()),
+            (ppu.scanline(), ppu.dot())
+        );
+    }
+
+    #[test]
+    fn test_palette_mirrors() {
+        let mut ppu = Ppu::new();
+        ppu.write_palette(0x3F10, 0x21);
+        assert_eq!(ppu.read_palette(0x3F00), 0x21);
+        ppu.write_palette(0x3F0C, 0x05);
+        assert_eq!(ppu.read_palette(0x3F1C), 0x05);
+        // the other sprite entries are their own
+        ppu.write_palette(0x3F11, 0x16);
+        assert_eq!(ppu.read_palette(0x3F01), 0x00);
+        assert_eq!(ppu.read_palette(0x3F31), 0x16);
+    }
+}
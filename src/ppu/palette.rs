@@ -0,0 +1,314 @@
+use std::f32::consts::PI;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/* System palette
+
+   The PPU doesn't output RGB, palette RAM holds 6 bit indices into the
+   composite colours the 2C02 can generate. This table is a common RGB
+   approximation of an NTSC 2C02 on a typical TV; entries $0D-$0F, $1D-$1F,
+   $2E-$2F and $3E-$3F are black.
+
+   PPUMASK can change what comes out. Grayscale drops the hue bits of every
+   index, leaving column $x0 of the table. The three emphasis bits (red,
+   green, blue from bit 5 on an NTSC PPU) darken the other two colours by
+   about a fifth each, so with all three set the whole picture dims.
+
+   The table can be replaced with a .pal file, 64 RGB triples as most
+   emulators ship them (files with the 512 emphasis variants appended are
+   accepted and the extra entries ignored), or one generated from the
+   composite signal: each hue is a square wave between two voltages, 30
+   degrees of colour burst phase apart, decoded as YIQ the way a TV would.
+*/
+
+pub type Colours = [(u8, u8, u8); 64];
+
+const EMPHASIS_RED: u8 = 0b001;
+const EMPHASIS_GREEN: u8 = 0b010;
+const EMPHASIS_BLUE: u8 = 0b100;
+
+pub const SYSTEM_PALETTE: Colours = [
+    (84, 84, 84),
+    (0, 30, 116),
+    (8, 16, 144),
+    (48, 0, 136),
+    (68, 0, 100),
+    (92, 0, 48),
+    (84, 4, 0),
+    (60, 24, 0),
+    (32, 42, 0),
+    (8, 58, 0),
+    (0, 64, 0),
+    (0, 60, 0),
+    (0, 50, 60),
+    (0, 0, 0),
+    (0, 0, 0),
+    (0, 0, 0),
+    (152, 150, 152),
+    (8, 76, 196),
+    (48, 50, 236),
+    (92, 30, 228),
+    (136, 20, 176),
+    (160, 20, 100),
+    (152, 34, 32),
+    (120, 60, 0),
+    (84, 90, 0),
+    (40, 114, 0),
+    (8, 124, 0),
+    (0, 118, 40),
+    (0, 102, 120),
+    (0, 0, 0),
+    (0, 0, 0),
+    (0, 0, 0),
+    (236, 238, 236),
+    (76, 154, 236),
+    (120, 124, 236),
+    (176, 98, 236),
+    (228, 84, 236),
+    (236, 88, 180),
+    (236, 106, 100),
+    (212, 136, 32),
+    (160, 170, 0),
+    (116, 196, 0),
+    (76, 208, 32),
+    (56, 204, 108),
+    (56, 180, 204),
+    (60, 60, 60),
+    (0, 0, 0),
+    (0, 0, 0),
+    (236, 238, 236),
+    (168, 204, 236),
+    (188, 188, 236),
+    (212, 178, 236),
+    (236, 174, 236),
+    (236, 174, 212),
+    (236, 180, 176),
+    (228, 196, 144),
+    (204, 210, 120),
+    (180, 222, 120),
+    (168, 226, 144),
+    (152, 226, 180),
+    (160, 214, 228),
+    (160, 162, 160),
+    (0, 0, 0),
+    (0, 0, 0),
+];
+
+const PAL_FILE_SIZE: usize = 64 * 3;
+
+// signal voltages per luma level, relative to white
+const SIGNAL_LOW: [f32; 4] = [-0.12, 0.00, 0.31, 0.72];
+const SIGNAL_HIGH: [f32; 4] = [0.40, 0.68, 1.00, 1.00];
+const HUE_PHASE: f32 = 165.0; // puts hue 1 at blue and hue 6 at red
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NtscParams {
+    pub hue: f32, // degrees added to every hue's phase
+    pub saturation: f32,
+    pub brightness: f32, // added to the signal, 0 is neutral
+    pub contrast: f32,
+}
+
+impl Default for NtscParams {
+    fn default() -> Self {
+        return Self {
+            hue: 0.0,
+            saturation: 1.0,
+            brightness: 0.0,
+            contrast: 1.0,
+        };
+    }
+}
+
+pub fn generate_ntsc(params: &NtscParams) -> Colours {
+    let mut colours = [(0, 0, 0); 64];
+    for (index, colour) in colours.iter_mut().enumerate() {
+        let hue = index & 0x0F;
+        let level = index >> 4;
+        let (luma, amplitude) = match hue {
+            0x00 => (SIGNAL_HIGH[level], 0.0),
+            0x0D => (SIGNAL_LOW[level], 0.0),
+            0x0E | 0x0F => (0.0, 0.0),
+            _ => (
+                (SIGNAL_LOW[level] + SIGNAL_HIGH[level]) / 2.0,
+                (SIGNAL_HIGH[level] - SIGNAL_LOW[level]) / 2.0,
+            ),
+        };
+        let y = luma * params.contrast + params.brightness;
+        let amplitude = amplitude * params.saturation * params.contrast;
+        let phase = (HUE_PHASE + params.hue - (hue as f32 - 1.0) * 30.0) * PI / 180.0;
+        let (i, q) = (amplitude * phase.cos(), amplitude * phase.sin());
+
+        let to_byte = |value: f32| -> u8 {
+            return (value * 255.0).round().clamp(0.0, 255.0) as u8;
+        };
+        *colour = (
+            to_byte(y + 0.956 * i + 0.621 * q),
+            to_byte(y - 0.272 * i - 0.647 * q),
+            to_byte(y - 1.106 * i + 1.703 * q),
+        );
+    }
+    return colours;
+}
+
+pub fn parse_pal(data: &[u8]) -> Result<Colours, String> {
+    if data.len() != PAL_FILE_SIZE && data.len() != PAL_FILE_SIZE * 8 {
+        return Err(format!(
+            "palette files are {} or {} bytes, not {}",
+            PAL_FILE_SIZE,
+            PAL_FILE_SIZE * 8,
+            data.len()
+        ));
+    }
+    let mut colours = [(0, 0, 0); 64];
+    for (colour, rgb) in colours.iter_mut().zip(data.chunks(3)) {
+        *colour = (rgb[0], rgb[1], rgb[2]);
+    }
+    return Ok(colours);
+}
+
+pub fn load_pal(path: &Path) -> Result<Colours, String> {
+    let data = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    return parse_pal(&data).map_err(|e| format!("{}: {}", path.display(), e));
+}
+
+// the palette setting: "default", "ntsc", "ntsc:hue=10,saturation=1.2" or a .pal path
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum PaletteChoice {
+    #[default]
+    Builtin,
+    Ntsc(NtscParams),
+    File(PathBuf),
+}
+
+impl PaletteChoice {
+    pub fn parse(spec: &str) -> Result<PaletteChoice, String> {
+        if spec == "default" {
+            return Ok(PaletteChoice::Builtin);
+        }
+        let settings = match spec.strip_prefix("ntsc") {
+            Some("") => return Ok(PaletteChoice::Ntsc(NtscParams::default())),
+            Some(rest) if rest.starts_with(':') => &rest[1..],
+            _ => return Ok(PaletteChoice::File(PathBuf::from(spec))),
+        };
+
+        let mut params = NtscParams::default();
+        for setting in settings.split(',') {
+            let (name, value) = setting
+                .split_once('=')
+                .ok_or_else(|| format!("expected name=value, got {}", setting))?;
+            let value: f32 = value
+                .parse()
+                .map_err(|_| format!("{} is not a valid number", value))?;
+            match name {
+                "hue" => params.hue = value,
+                "saturation" => params.saturation = value,
+                "brightness" => params.brightness = value,
+                "contrast" => params.contrast = value,
+                _ => return Err(format!("unknown palette setting {}", name)),
+            }
+        }
+        return Ok(PaletteChoice::Ntsc(params));
+    }
+
+    pub fn colours(&self) -> Result<Colours, String> {
+        match self {
+            PaletteChoice::Builtin => return Ok(SYSTEM_PALETTE),
+            PaletteChoice::Ntsc(params) => return Ok(generate_ntsc(params)),
+            PaletteChoice::File(path) => return load_pal(path),
+        }
+    }
+}
+
+// `emphasis` is PPUMASK shifted down five bits
+pub fn emphasize(colour: (u8, u8, u8), emphasis: u8) -> (u8, u8, u8) {
+    // each of the other colours' bits takes a fifth off
+    let dim = |channel: u8, keep: u8| -> u8 {
+        let mut value = channel as u16;
+        for _ in 0..(emphasis & 0b111 & !keep).count_ones() {
+            value = value * 13 / 16;
+        }
+        return value as u8;
+    };
+    let (r, g, b) = colour;
+    return (
+        dim(r, EMPHASIS_RED),
+        dim(g, EMPHASIS_GREEN),
+        dim(b, EMPHASIS_BLUE),
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_emphasis() {
+        let white = SYSTEM_PALETTE[0x30];
+        assert_eq!(emphasize(white, 0), white);
+        assert_eq!(emphasize(white, EMPHASIS_RED), (236, 193, 191));
+        assert_eq!(
+            emphasize(white, EMPHASIS_GREEN | EMPHASIS_BLUE),
+            (155, 193, 191)
+        );
+        assert_eq!(emphasize(white, 0b111), (155, 156, 155));
+    }
+
+    #[test]
+    fn test_generated_ntsc_palette() {
+        let colours = generate_ntsc(&NtscParams::default());
+        assert_eq!(colours[0x20], (255, 255, 255));
+        assert_eq!(colours[0x0D], (0, 0, 0));
+        assert_eq!(colours[0x3F], (0, 0, 0));
+        let (r, g, b) = colours[0x16];
+        assert!(r > g && r > b);
+        let (r, g, b) = colours[0x12];
+        assert!(b > r && b > g);
+        let (r, g, b) = colours[0x1A];
+        assert!(g > r && g > b);
+
+        let gray = generate_ntsc(&NtscParams {
+            saturation: 0.0,
+            ..NtscParams::default()
+        });
+        let (r, g, b) = gray[0x16];
+        assert!(r == g && g == b);
+    }
+
+    #[test]
+    fn test_parse_pal() {
+        let mut data: Vec<u8> = (0..PAL_FILE_SIZE).map(|i| i as u8).collect();
+        let colours = parse_pal(&data).unwrap();
+        assert_eq!(colours[1], (3, 4, 5));
+        assert_eq!(colours[63], (189, 190, 191));
+
+        data.extend(vec![0xFF; PAL_FILE_SIZE * 7]);
+        assert_eq!(parse_pal(&data).unwrap()[63], (189, 190, 191));
+        assert!(parse_pal(&data[..100]).is_err());
+    }
+
+    #[test]
+    fn test_palette_choice() {
+        assert_eq!(
+            PaletteChoice::parse("default").unwrap(),
+            PaletteChoice::Builtin
+        );
+        assert_eq!(
+            PaletteChoice::parse("ntsc:hue=-5,saturation=1.5").unwrap(),
+            PaletteChoice::Ntsc(NtscParams {
+                hue: -5.0,
+                saturation: 1.5,
+                ..NtscParams::default()
+            })
+        );
+        assert_eq!(
+            PaletteChoice::parse("smooth.pal").unwrap(),
+            PaletteChoice::File(PathBuf::from("smooth.pal"))
+        );
+        assert!(PaletteChoice::parse("ntsc:tint=3").is_err());
+        assert!(PaletteChoice::parse("missing.pal")
+            .unwrap()
+            .colours()
+            .is_err());
+    }
+}
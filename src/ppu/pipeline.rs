@@ -0,0 +1,476 @@
+use crate::error::StateError;
+use crate::mapper::Mapper;
+use crate::ppu::render::SCREEN_HEIGHT;
+use crate::ppu::sprite::{
+    LineSprites, ATTR_BEHIND_BACKGROUND, ATTR_FLIP_HORIZONTAL, ATTR_FLIP_VERTICAL, ATTR_PALETTE,
+    SPRITES_PER_LINE,
+};
+use crate::ppu::{
+    Ppu, CTRL_BACKGROUND_TABLE, DOTS_PER_SCANLINE, MASK_BACKGROUND, MASK_BACKGROUND_LEFT,
+    MASK_SPRITES, MASK_SPRITES_LEFT, PRE_RENDER_SCANLINE, SCANLINES_PER_FRAME,
+};
+use crate::savestate::{StateReader, StateWriter};
+
+/* Dot renderer
+
+   The accurate mode runs the PPU's own pipeline one dot at a time instead of
+   drawing whole lines, so anything a game changes mid-scanline (palette
+   entries, PPUMASK, CHR banks) lands on the right pixel.
+
+   Every 8 dots of 1-256, and of 321-336 for the next line's first two tiles,
+   the PPU fetches a nametable byte, an attribute byte and the two pattern
+   planes of one tile, then loads them into the low half of 16 bit shift
+   registers. Each dot shifts them along and the pixel comes out of bit
+   15 - fine X, so the tile being drawn is always the one fetched 16 dots
+   earlier.
+
+   Sprites for the next line are picked at dot 256 and their patterns fetched
+   over 257-320, eight dots per slot, unused slots fetching tile $FF. Every
+   fetch is put on the mapper's PPU bus, which is what MMC3 counts scanlines
+   from.
+
+   The pixel at dot x+1 is the first opaque sprite pixel unless it's behind an
+   opaque background pixel. With rendering off the backdrop is shown, or the
+   palette entry v points at if a game has left it in palette RAM.
+*/
+
+#[derive(Debug, Clone, Copy, Default)]
+struct SpriteUnit {
+    x: u8,
+    attributes: u8,
+    low: u8, // already mirrored for horizontal flips, leftmost pixel in bit 7
+    high: u8,
+    sprite_zero: bool,
+}
+
+#[derive(Debug, Default)]
+pub(super) struct Pipeline {
+    tile: u8,
+    palette: u8,
+    pattern_low: u8,
+    pattern_high: u8,
+    shift_low: u16,
+    shift_high: u16,
+    attribute_low: u16,
+    attribute_high: u16,
+    next_sprites: LineSprites,
+    sprites: [SpriteUnit; SPRITES_PER_LINE],
+    sprite_count: usize,
+}
+
+impl Pipeline {
+    fn shift(&mut self) {
+        self.shift_low <<= 1;
+        self.shift_high <<= 1;
+        self.attribute_low <<= 1;
+        self.attribute_high <<= 1;
+    }
+
+    fn reload(&mut self) {
+        self.shift_low = (self.shift_low & 0xFF00) | self.pattern_low as u16;
+        self.shift_high = (self.shift_high & 0xFF00) | self.pattern_high as u16;
+        let fill = |bit: u8| -> u16 {
+            if self.palette & bit != 0 {
+                return 0x00FF;
+            }
+            return 0x0000;
+        };
+        self.attribute_low = (self.attribute_low & 0xFF00) | fill(0b01);
+        self.attribute_high = (self.attribute_high & 0xFF00) | fill(0b10);
+    }
+
+    pub(super) fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.tile);
+        writer.write_u8(self.palette);
+        writer.write_u8(self.pattern_low);
+        writer.write_u8(self.pattern_high);
+        writer.write_u16(self.shift_low);
+        writer.write_u16(self.shift_high);
+        writer.write_u16(self.attribute_low);
+        writer.write_u16(self.attribute_high);
+        writer.write_u8(self.next_sprites.count as u8);
+        for n in self.next_sprites.entries {
+            writer.write_u8(n as u8);
+        }
+        writer.write_u8(self.sprite_count as u8);
+        for sprite in &self.sprites {
+            writer.write_u8(sprite.x);
+            writer.write_u8(sprite.attributes);
+            writer.write_u8(sprite.low);
+            writer.write_u8(sprite.high);
+            writer.write_bool(sprite.sprite_zero);
+        }
+    }
+
+    pub(super) fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.tile = reader.read_u8()?;
+        self.palette = reader.read_u8()? & 0b11;
+        self.pattern_low = reader.read_u8()?;
+        self.pattern_high = reader.read_u8()?;
+        self.shift_low = reader.read_u16()?;
+        self.shift_high = reader.read_u16()?;
+        self.attribute_low = reader.read_u16()?;
+        self.attribute_high = reader.read_u16()?;
+        self.next_sprites.count = (reader.read_u8()? as usize).min(SPRITES_PER_LINE);
+        for n in self.next_sprites.entries.iter_mut() {
+            *n = reader.read_u8()? as usize % 64;
+        }
+        self.sprite_count = (reader.read_u8()? as usize).min(SPRITES_PER_LINE);
+        for sprite in self.sprites.iter_mut() {
+            sprite.x = reader.read_u8()?;
+            sprite.attributes = reader.read_u8()?;
+            sprite.low = reader.read_u8()?;
+            sprite.high = reader.read_u8()?;
+            sprite.sprite_zero = reader.read_bool()?;
+        }
+        return Ok(());
+    }
+}
+
+impl Ppu {
+    fn ppu_cycle(&self) -> u64 {
+        let dots_per_frame = DOTS_PER_SCANLINE as u64 * SCANLINES_PER_FRAME as u64;
+        return self.frame * dots_per_frame
+            + self.scanline as u64 * DOTS_PER_SCANLINE as u64
+            + self.dot as u64;
+    }
+
+    fn fetch(&self, addr: u16, mapper: &mut dyn Mapper) -> u8 {
+        mapper.ppu_bus(addr, self.ppu_cycle());
+        if addr >= 0x2000 {
+            return self.read_nametable(addr, mapper.mirroring());
+        }
+        return mapper.ppu_read(addr);
+    }
+
+    // called once per dot in `RenderMode::Dot`, after the scroll counters have moved
+    pub(super) fn clock_pipeline(&mut self, mapper: &mut dyn Mapper) {
+        let visible = (self.scanline as usize) < SCREEN_HEIGHT;
+        if !visible && self.scanline != PRE_RENDER_SCANLINE {
+            return;
+        }
+        let dot = self.dot as usize;
+        if visible && (1..=256).contains(&dot) {
+            self.output_pixel(dot - 1);
+        }
+        if !self.rendering_enabled() {
+            return;
+        }
+
+        if (1..=256).contains(&dot) || (321..=336).contains(&dot) {
+            self.pipeline.shift();
+            self.fetch_background(dot, mapper);
+        }
+        if dot == 256 {
+            self.pipeline.next_sprites = if visible {
+                self.evaluate_sprites(self.scanline as usize + 1)
+            } else {
+                LineSprites::default()
+            };
+        }
+        if (257..=320).contains(&dot) {
+            self.fetch_sprite(dot, mapper);
+        }
+    }
+
+    fn fetch_background(&mut self, dot: usize, mapper: &mut dyn Mapper) {
+        let v = self.vram_addr;
+        let pattern_base: u16 = if self.ctrl & CTRL_BACKGROUND_TABLE != 0 {
+            0x1000
+        } else {
+            0x0000
+        };
+        let pattern_addr = pattern_base + self.pipeline.tile as u16 * 16 + ((v >> 12) & 0b111);
+        match (dot - 1) % 8 {
+            0 => self.pipeline.tile = self.fetch(0x2000 | (v & 0x0FFF), mapper),
+            2 => {
+                let addr = 0x23C0 | (v & 0x0C00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07);
+                let shift = ((v >> 4) & 0b100) | (v & 0b10);
+                self.pipeline.palette = (self.fetch(addr, mapper) >> shift) & 0b11;
+            }
+            4 => self.pipeline.pattern_low = self.fetch(pattern_addr, mapper),
+            6 => self.pipeline.pattern_high = self.fetch(pattern_addr + 8, mapper),
+            7 => self.pipeline.reload(),
+            _ => {}
+        }
+    }
+
+    fn fetch_sprite(&mut self, dot: usize, mapper: &mut dyn Mapper) {
+        let slot = (dot - 257) / 8;
+        let step = (dot - 257) % 8;
+        if step != 4 && step != 6 {
+            return;
+        }
+        let sprites = self.pipeline.next_sprites;
+        if slot >= sprites.count {
+            // nothing to draw, but the bus still sees tile $FF
+            let addr = self.sprite_row_addr(0xFF, 0);
+            self.fetch(addr + (step == 6) as u16 * 8, mapper);
+            self.pipeline.sprites[slot] = SpriteUnit::default();
+            if step == 6 {
+                self.pipeline.sprite_count = sprites.count;
+            }
+            return;
+        }
+
+        let n = sprites.entries[slot];
+        let (y, tile, attributes, x) = (
+            self.oam[n * 4],
+            self.oam[n * 4 + 1],
+            self.oam[n * 4 + 2],
+            self.oam[n * 4 + 3],
+        );
+        let height = self.sprite_height();
+        let mut row = (self.scanline as usize).wrapping_sub(y as usize) % height;
+        if attributes & ATTR_FLIP_VERTICAL != 0 {
+            row = height - 1 - row;
+        }
+        let addr = self.sprite_row_addr(tile, row);
+        let mut data = self.fetch(addr + (step == 6) as u16 * 8, mapper);
+        if attributes & ATTR_FLIP_HORIZONTAL != 0 {
+            data = data.reverse_bits();
+        }
+
+        let unit = &mut self.pipeline.sprites[slot];
+        if step == 4 {
+            unit.low = data;
+        } else {
+            unit.high = data;
+            unit.x = x;
+            unit.attributes = attributes;
+            unit.sprite_zero = n == 0;
+            if slot == SPRITES_PER_LINE - 1 {
+                self.pipeline.sprite_count = sprites.count;
+            }
+        }
+    }
+
+    fn output_pixel(&mut self, x: usize) {
+        let pipeline = &self.pipeline;
+        let mut background = 0;
+        if self.mask & MASK_BACKGROUND != 0 && (x >= 8 || self.mask & MASK_BACKGROUND_LEFT != 0) {
+            let bit = 15 - self.fine_x;
+            let pixel =
+                ((pipeline.shift_low >> bit) & 1) | (((pipeline.shift_high >> bit) & 1) << 1);
+            let palette = ((pipeline.attribute_low >> bit) & 1)
+                | (((pipeline.attribute_high >> bit) & 1) << 1);
+            if pixel != 0 {
+                background = (palette * 4 + pixel) as u8;
+            }
+        }
+
+        let mut sprite = None;
+        if self.mask & MASK_SPRITES != 0 && (x >= 8 || self.mask & MASK_SPRITES_LEFT != 0) {
+            for unit in &pipeline.sprites[..pipeline.sprite_count] {
+                let column = x.wrapping_sub(unit.x as usize);
+                if column >= 8 {
+                    continue;
+                }
+                let pixel =
+                    ((unit.low >> (7 - column)) & 1) | (((unit.high >> (7 - column)) & 1) << 1);
+                if pixel != 0 {
+                    sprite = Some((unit, pixel));
+                    break;
+                }
+            }
+        }
+
+        let mut index = background;
+        if let Some((unit, pixel)) = sprite {
+            let attributes = unit.attributes;
+            if unit.sprite_zero && background != 0 && x != 255 {
+                self.hit_sprite_zero(self.dot);
+            }
+            if attributes & ATTR_BEHIND_BACKGROUND == 0 || background == 0 {
+                index = (4 + (attributes & ATTR_PALETTE)) * 4 + pixel;
+            }
+        }
+
+        let colour = if !self.rendering_enabled() && self.vram_addr & 0x3F00 == 0x3F00 {
+            self.read_palette(self.vram_addr)
+        } else {
+            self.palette[index as usize]
+        };
+        let rgba = self.output_colour(colour);
+        let y = self.scanline as usize;
+        self.output.row_mut(y)[x * 4..x * 4 + 4].copy_from_slice(&rgba);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::Mirroring;
+    use crate::ppu::test::chr_ram_mapper;
+    use crate::ppu::{RenderMode, MASK_BACKGROUND, MASK_SPRITES, STATUS_SPRITE_ZERO};
+    use crate::savestate::{StateReader, StateWriter};
+
+    // tiles 1 and 2 are solid colours 1 and 2, tile 3 mixes all four; the
+    // nametable is diagonal stripes over all four palettes, with sprites on top
+    fn scene(mode: RenderMode) -> (Ppu, Box<dyn Mapper>) {
+        let mut ppu = Ppu::new();
+        ppu.render_mode = mode;
+        let mut mapper = chr_ram_mapper();
+        for row in 0..8 {
+            mapper.ppu_write(0x0010 + row, 0xFF);
+            mapper.ppu_write(0x0028 + row, 0xFF);
+            mapper.ppu_write(0x0030 + row, 0xF0);
+            mapper.ppu_write(0x0038 + row, 0x3C);
+        }
+        for index in 0..32 {
+            ppu.write_palette(0x3F00 + index, index as u8 + 1);
+        }
+        let mirroring = mapper.mirroring();
+        for offset in 0..0x3C0 {
+            ppu.write_nametable(
+                0x2000 + offset,
+                mirroring,
+                ((offset + offset / 32) % 4) as u8,
+            );
+        }
+        for offset in 0..0x40 {
+            ppu.write_nametable(0x23C0 + offset, mirroring, (offset * 37) as u8);
+        }
+        let sprites = [
+            [30, 3, 0, 40],
+            [30, 1, ATTR_BEHIND_BACKGROUND | 1, 44],
+            [100, 2, ATTR_FLIP_HORIZONTAL | ATTR_FLIP_VERTICAL | 2, 250],
+        ];
+        for (n, entry) in sprites.iter().enumerate() {
+            ppu.oam_mut()[n * 4..n * 4 + 4].copy_from_slice(entry);
+        }
+        for byte in ppu.oam_mut()[12..].iter_mut() {
+            *byte = 0xFF;
+        }
+        ppu.write_register(0x2005, 13, mapper.as_mut());
+        ppu.write_register(0x2005, 6, mapper.as_mut());
+        ppu.write_register(0x2001, MASK_BACKGROUND | MASK_SPRITES, mapper.as_mut());
+        return (ppu, mapper);
+    }
+
+    fn frames(ppu: &mut Ppu, mapper: &mut dyn Mapper, count: u64) {
+        let dots = DOTS_PER_SCANLINE as u64 * SCANLINES_PER_FRAME as u64;
+        ppu.tick(dots * count, Some(mapper));
+    }
+
+    #[test]
+    fn test_dot_and_scanline_renderers_agree() {
+        let (mut fast, mut fast_mapper) = scene(RenderMode::Scanline);
+        let (mut accurate, mut accurate_mapper) = scene(RenderMode::Dot);
+        frames(&mut fast, fast_mapper.as_mut(), 2);
+        frames(&mut accurate, accurate_mapper.as_mut(), 2);
+        assert!(fast.frame_buffer() == accurate.frame_buffer());
+        assert_eq!(fast.status(), accurate.status());
+    }
+
+    #[test]
+    fn test_mid_scanline_palette_write() {
+        let (mut ppu, mut mapper) = scene(RenderMode::Dot);
+        ppu.write_register(0x2001, 0, mapper.as_mut());
+        ppu.write_palette(0x3F00, 0x01);
+        frames(&mut ppu, mapper.as_mut(), 1);
+        // with rendering off the backdrop fills the line, change it half way across
+        while (ppu.scanline(), ppu.dot()) != (10, 128) {
+            ppu.tick(1, Some(mapper.as_mut()));
+        }
+        ppu.write_palette(0x3F00, 0x30);
+        ppu.tick(DOTS_PER_SCANLINE as u64, Some(mapper.as_mut()));
+        let frame = ppu.frame();
+        assert_eq!(frame.pixel(126, 10), ppu.output_colour(1));
+        assert_eq!(frame.pixel(128, 10), ppu.output_colour(0x30));
+        assert_eq!(frame.pixel(0, 11), ppu.output_colour(0x30));
+    }
+
+    #[test]
+    fn test_sprite_zero_hit_lands_on_its_dot() {
+        let (mut ppu, mut mapper) = scene(RenderMode::Dot);
+        ppu.oam_mut()[0..4].copy_from_slice(&[30, 1, 0, 100]);
+        frames(&mut ppu, mapper.as_mut(), 1);
+        while (ppu.scanline(), ppu.dot()) != (31, 100) {
+            ppu.tick(1, Some(mapper.as_mut()));
+        }
+        assert_eq!(ppu.status() & STATUS_SPRITE_ZERO, 0);
+        ppu.tick(8, Some(mapper.as_mut()));
+        assert_ne!(ppu.status() & STATUS_SPRITE_ZERO, 0);
+        assert_eq!(ppu.take_sprite_zero_hit(), Some((31, 101)));
+    }
+
+    #[test]
+    fn test_background_palette_hack() {
+        let (mut ppu, mut mapper) = scene(RenderMode::Dot);
+        ppu.write_register(0x2001, 0, mapper.as_mut());
+        ppu.write_register(0x2006, 0x3F, mapper.as_mut());
+        ppu.write_register(0x2006, 0x07, mapper.as_mut());
+        frames(&mut ppu, mapper.as_mut(), 1);
+        assert_eq!(ppu.frame().pixel(50, 50), ppu.output_colour(8));
+    }
+
+    #[test]
+    fn test_mapper_sees_the_fetches() {
+        // one rise of A12 per line with the background at $0000 and sprites at $1000
+        struct A12Counter {
+            inner: Box<dyn Mapper>,
+            high: bool,
+            rises: u32,
+        }
+        impl Mapper for A12Counter {
+            fn cpu_read(&self, addr: u16) -> u8 {
+                return self.inner.cpu_read(addr);
+            }
+            fn cpu_write(&mut self, addr: u16, data: u8) {
+                self.inner.cpu_write(addr, data);
+            }
+            fn ppu_read(&mut self, addr: u16) -> u8 {
+                return self.inner.ppu_read(addr);
+            }
+            fn ppu_write(&mut self, addr: u16, data: u8) {
+                self.inner.ppu_write(addr, data);
+            }
+            fn mirroring(&self) -> Mirroring {
+                return self.inner.mirroring();
+            }
+            fn ppu_bus(&mut self, addr: u16, _ppu_cycle: u64) {
+                let high = addr & 0x1000 != 0;
+                if high && !self.high {
+                    self.rises += 1;
+                }
+                self.high = high;
+            }
+            fn save_state(&self, writer: &mut StateWriter) {
+                self.inner.save_state(writer);
+            }
+            fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+                return self.inner.load_state(reader);
+            }
+        }
+
+        let (mut ppu, mapper) = scene(RenderMode::Dot);
+        let mut counter = A12Counter {
+            inner: mapper,
+            high: false,
+            rises: 0,
+        };
+        ppu.write_register(0x2000, 0b0000_1000, &mut counter);
+        frames(&mut ppu, &mut counter, 1);
+        assert_eq!(counter.rises, SCREEN_HEIGHT as u32 + 1);
+    }
+
+    #[test]
+    fn test_pipeline_state_round_trip() {
+        let (mut ppu, mut mapper) = scene(RenderMode::Dot);
+        frames(&mut ppu, mapper.as_mut(), 1);
+        while (ppu.scanline(), ppu.dot()) != (40, 45) {
+            ppu.tick(1, Some(mapper.as_mut()));
+        }
+        let mut writer = StateWriter::new();
+        ppu.save_state(&mut writer);
+        let data = writer.into_bytes();
+
+        let mut restored = Ppu::new();
+        restored.render_mode = RenderMode::Dot;
+        restored.load_state(&mut StateReader::new(&data)).unwrap();
+        frames(&mut ppu, mapper.as_mut(), 1);
+        frames(&mut restored, mapper.as_mut(), 1);
+        assert!(ppu.frame_buffer() == restored.frame_buffer());
+    }
+}
@@ -0,0 +1,268 @@
+use crate::mapper::Mapper;
+use crate::ppu::palette::emphasize;
+use crate::ppu::{
+    Ppu, CTRL_BACKGROUND_TABLE, MASK_BACKGROUND, MASK_BACKGROUND_LEFT, MASK_EMPHASIS,
+    MASK_GRAYSCALE, MASK_SPRITES,
+};
+
+pub const SCREEN_WIDTH: usize = 256;
+pub const SCREEN_HEIGHT: usize = 240;
+
+/* Scanline renderer
+
+   Each visible scanline is drawn in one go when the PPU reaches its last
+   visible dot, using the registers as they are at that moment, so changes
+   made between scanlines show up but changes within one don't.
+
+   PPUMASK turns the background and sprites on separately, and can hide
+   either in the leftmost 8 pixels. With both off the PPU stops fetching,
+   v stays where the CPU left it and no sprites are evaluated.
+
+   A nametable is 32x30 tile indices followed by a 64 byte attribute table.
+   Each attribute byte covers a 4x4 tile area, two bits per 2x2 quadrant:
+
+    7654 3210
+    |||| ||++- top left
+    |||| ++--- top right
+    ||++------ bottom left
+    ++-------- bottom right
+
+   A tile is 16 bytes in the pattern table, eight bytes of the low bit plane
+   then eight of the high plane, the leftmost pixel in bit 7.
+*/
+
+impl Ppu {
+    pub(super) fn render_scanline(&mut self, mapper: &mut dyn Mapper) {
+        let y = self.scanline as usize;
+        let mut line = [0u8; SCREEN_WIDTH]; // palette RAM indices
+        if self.mask & MASK_BACKGROUND != 0 {
+            self.background_line(&mut line, mapper);
+            if self.mask & MASK_BACKGROUND_LEFT == 0 {
+                line[..8].fill(0);
+            }
+        }
+        // sprite evaluation runs, and can set the overflow flag, with either layer on
+        if self.mask & (MASK_BACKGROUND | MASK_SPRITES) != 0 {
+            let sprites = self.evaluate_sprites(y);
+            if self.mask & MASK_SPRITES != 0 {
+                self.sprite_line(y, &sprites, &mut line, mapper);
+            }
+        }
+
+        let mut pixels = line.map(|index| self.output_colour(self.palette[index as usize]));
+        // with rendering off and v left in palette RAM, that entry is the backdrop
+        if !self.rendering_enabled() && self.vram_addr & 0x3F00 == 0x3F00 {
+            pixels = [self.output_colour(self.read_palette(self.vram_addr)); SCREEN_WIDTH];
+        }
+        let row = self.output.row_mut(y);
+        for (x, rgba) in pixels.iter().enumerate() {
+            row[x * 4..x * 4 + 4].copy_from_slice(rgba);
+        }
+    }
+
+    // RGBA for a palette RAM value, after PPUMASK's grayscale and emphasis
+    pub(super) fn output_colour(&self, colour: u8) -> [u8; 4] {
+        let colour_mask = if self.mask & MASK_GRAYSCALE != 0 {
+            0x30
+        } else {
+            0x3F
+        };
+        let emphasis = (self.mask & MASK_EMPHASIS) >> 5;
+        let (r, g, b) = emphasize(self.colours[(colour & colour_mask) as usize], emphasis);
+        return [r, g, b, 0xFF];
+    }
+
+    // background pixels as palette RAM indices, 0 for transparent
+    fn background_line(&self, line: &mut [u8; SCREEN_WIDTH], mapper: &mut dyn Mapper) {
+        let pattern_base: u16 = if self.ctrl & CTRL_BACKGROUND_TABLE != 0 {
+            0x1000
+        } else {
+            0x0000
+        };
+        let mirroring = mapper.mirroring();
+        let mut v = self.line_start_addr;
+        let fine_y = (v >> 12) & 0b111;
+
+        // 33 tiles, the first and last partly off screen unless fine X is 0
+        for tile_x in 0..SCREEN_WIDTH / 8 + 1 {
+            let tile = self.read_nametable(0x2000 | (v & 0x0FFF), mirroring);
+            let attribute = self.read_nametable(
+                0x23C0 | (v & 0x0C00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07),
+                mirroring,
+            );
+            let shift = ((v >> 4) & 0b100) | (v & 0b10);
+            let palette = (attribute >> shift) & 0b11;
+
+            let addr = pattern_base + tile as u16 * 16 + fine_y;
+            let low = mapper.ppu_read(addr);
+            let high = mapper.ppu_read(addr + 8);
+            for bit in 0..8 {
+                let x = (tile_x * 8 + bit) as isize - self.fine_x as isize;
+                if !(0..SCREEN_WIDTH as isize).contains(&x) {
+                    continue;
+                }
+                let pixel = ((low >> (7 - bit)) & 1) | (((high >> (7 - bit)) & 1) << 1);
+                if pixel != 0 {
+                    line[x as usize] = palette * 4 + pixel;
+                }
+            }
+
+            // coarse X, carrying into the horizontal nametable bit
+            if v & 0x001F == 0x001F {
+                v = (v & !0x001F) ^ 0x0400;
+            } else {
+                v += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ppu::palette::SYSTEM_PALETTE;
+    use crate::ppu::test::chr_ram_mapper;
+    use crate::ppu::{DOTS_PER_SCANLINE, SCANLINES_PER_FRAME};
+
+    fn pixel(ppu: &Ppu, x: usize, y: usize) -> (u8, u8, u8) {
+        let offset = (y * SCREEN_WIDTH + x) * 4;
+        let pixels = ppu.frame_buffer();
+        return (pixels[offset], pixels[offset + 1], pixels[offset + 2]);
+    }
+
+    fn render_frame(ppu: &mut Ppu, mapper: &mut dyn Mapper) {
+        ppu.tick(
+            DOTS_PER_SCANLINE as u64 * SCANLINES_PER_FRAME as u64,
+            Some(mapper),
+        );
+    }
+
+    // tile 1 is solid colour 1 on the left half and colour 3 on the right
+    fn setup(ppu: &mut Ppu, mapper: &mut dyn Mapper) {
+        for row in 0..8 {
+            mapper.ppu_write(0x0010 + row, 0xFF);
+            mapper.ppu_write(0x0018 + row, 0x0F);
+        }
+        ppu.write_palette(0x3F00, 0x0F); // black backdrop
+        ppu.write_palette(0x3F01, 0x16);
+        ppu.write_palette(0x3F03, 0x2A);
+        ppu.write_palette(0x3F0D, 0x30); // palette 3, colour 1
+    }
+
+    #[test]
+    fn test_background_tiles() {
+        let mut ppu = Ppu::new();
+        let mut mapper = chr_ram_mapper();
+        setup(&mut ppu, mapper.as_mut());
+        let mirroring = mapper.mirroring();
+        ppu.write_nametable(0x2000 + 32 + 1, mirroring, 1); // tile (1, 1)
+        ppu.mask = MASK_BACKGROUND;
+        render_frame(&mut ppu, mapper.as_mut());
+
+        assert_eq!(pixel(&ppu, 0, 0), SYSTEM_PALETTE[0x0F]);
+        assert_eq!(pixel(&ppu, 8, 8), SYSTEM_PALETTE[0x16]);
+        assert_eq!(pixel(&ppu, 15, 15), SYSTEM_PALETTE[0x2A]);
+        assert_eq!(pixel(&ppu, 16, 8), SYSTEM_PALETTE[0x0F]);
+    }
+
+    #[test]
+    fn test_attribute_quadrants() {
+        let mut ppu = Ppu::new();
+        let mut mapper = chr_ram_mapper();
+        setup(&mut ppu, mapper.as_mut());
+        let mirroring = mapper.mirroring();
+        // tile (2, 2) is the bottom right quadrant of the first attribute byte
+        ppu.write_nametable(0x2000 + 2 * 32 + 2, mirroring, 1);
+        ppu.write_nametable(0x23C0, mirroring, 0b1100_0000);
+        ppu.mask = MASK_BACKGROUND;
+        render_frame(&mut ppu, mapper.as_mut());
+        assert_eq!(pixel(&ppu, 16, 16), SYSTEM_PALETTE[0x30]);
+    }
+
+    #[test]
+    fn test_nametable_and_pattern_selection() {
+        let mut ppu = Ppu::new();
+        let mut mapper = chr_ram_mapper();
+        setup(&mut ppu, mapper.as_mut());
+        let mirroring = mapper.mirroring();
+        // tile 1 of the $1000 table is solid colour 1, placed in the second nametable
+        for row in 0..8 {
+            mapper.ppu_write(0x1010 + row, 0xFF);
+        }
+        ppu.write_nametable(0x2400, mirroring, 1);
+        ppu.write_register(0x2000, 0b0001_0001, mapper.as_mut());
+        ppu.mask = MASK_BACKGROUND | MASK_BACKGROUND_LEFT;
+        // the nametable select reaches v on the pre-render line, so the second frame shows it
+        render_frame(&mut ppu, mapper.as_mut());
+        render_frame(&mut ppu, mapper.as_mut());
+        assert_eq!(pixel(&ppu, 7, 0), SYSTEM_PALETTE[0x16]);
+    }
+
+    #[test]
+    fn test_disabled_background_shows_backdrop() {
+        let mut ppu = Ppu::new();
+        let mut mapper = chr_ram_mapper();
+        setup(&mut ppu, mapper.as_mut());
+        let mirroring = mapper.mirroring();
+        ppu.write_nametable(0x2000, mirroring, 1);
+        render_frame(&mut ppu, mapper.as_mut());
+        assert_eq!(pixel(&ppu, 0, 0), SYSTEM_PALETTE[0x0F]);
+    }
+
+    #[test]
+    fn test_horizontal_scroll_split() {
+        let mut ppu = Ppu::new();
+        let mut mapper = chr_ram_mapper();
+        setup(&mut ppu, mapper.as_mut());
+        let mirroring = mapper.mirroring();
+        for row in 0..30 {
+            ppu.write_nametable(0x2000 + row * 32 + 1, mirroring, 1); // column 1 all the way down
+        }
+        ppu.write_register(0x2005, 4, mapper.as_mut());
+        ppu.write_register(0x2005, 0, mapper.as_mut());
+        ppu.mask = MASK_BACKGROUND | MASK_BACKGROUND_LEFT;
+        render_frame(&mut ppu, mapper.as_mut());
+        assert_eq!(pixel(&ppu, 4, 50), SYSTEM_PALETTE[0x16]);
+        assert_eq!(pixel(&ppu, 11, 50), SYSTEM_PALETTE[0x2A]);
+        assert_eq!(pixel(&ppu, 12, 50), SYSTEM_PALETTE[0x0F]);
+
+        // a write once line 99 is drawn moves everything from line 100 down
+        while (ppu.scanline(), ppu.dot()) != (99, 256) {
+            ppu.tick(1, Some(mapper.as_mut()));
+        }
+        ppu.write_register(0x2005, 0, mapper.as_mut());
+        ppu.write_register(0x2005, 0, mapper.as_mut());
+        ppu.tick(DOTS_PER_SCANLINE as u64, Some(mapper.as_mut()));
+        assert_eq!(pixel(&ppu, 4, 99), SYSTEM_PALETTE[0x16]);
+        assert_eq!(pixel(&ppu, 8, 100), SYSTEM_PALETTE[0x16]);
+        assert_eq!(pixel(&ppu, 4, 100), SYSTEM_PALETTE[0x0F]);
+    }
+
+    #[test]
+    fn test_grayscale_and_emphasis() {
+        let mut ppu = Ppu::new();
+        let mut mapper = chr_ram_mapper();
+        setup(&mut ppu, mapper.as_mut());
+        let mirroring = mapper.mirroring();
+        ppu.write_nametable(0x2001, mirroring, 1);
+        ppu.mask = MASK_BACKGROUND | MASK_GRAYSCALE;
+        render_frame(&mut ppu, mapper.as_mut());
+        assert_eq!(pixel(&ppu, 8, 0), SYSTEM_PALETTE[0x10]);
+
+        ppu.mask = MASK_BACKGROUND | 0b0100_0000; // green
+        render_frame(&mut ppu, mapper.as_mut());
+        assert_eq!(pixel(&ppu, 8, 0), emphasize(SYSTEM_PALETTE[0x16], 0b010));
+    }
+
+    #[test]
+    fn test_custom_palette() {
+        let mut ppu = Ppu::new();
+        let mut mapper = chr_ram_mapper();
+        setup(&mut ppu, mapper.as_mut());
+        let mut colours = SYSTEM_PALETTE;
+        colours[0x0F] = (1, 2, 3);
+        ppu.set_palette(&colours);
+        render_frame(&mut ppu, mapper.as_mut());
+        assert_eq!(pixel(&ppu, 0, 0), (1, 2, 3));
+    }
+}
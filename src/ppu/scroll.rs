@@ -0,0 +1,168 @@
+use crate::ppu::render::SCREEN_HEIGHT;
+use crate::ppu::{Ppu, MASK_BACKGROUND, MASK_SPRITES, PRE_RENDER_SCANLINE};
+
+/* Scrolling ("loopy" registers)
+
+   The PPU has no scroll registers as such. It keeps the address it fetches
+   tiles from in v, and PPUSCROLL, PPUADDR and the nametable bits of PPUCTRL
+   all write into a second register t that gets copied into v:
+
+    yyy NN YYYYY XXXXX
+    ||| || ||||| +++++- coarse X, the tile column
+    ||| || +++++------- coarse Y, the tile row
+    ||| ++------------- nametable
+    +++---------------- fine Y, the row within the tile
+
+   plus a 3 bit fine X, the pixel within the tile, and the write toggle
+   shared by both two-write registers.
+
+    $2000 write          t: ...GH.. ........ <- d: ......GH
+    $2005 first write    t: ....... ...ABCDE <- d: ABCDE...   x <- d: .....FGH
+    $2005 second write   t: FGH..AB CDE..... <- d: ABCDEFGH
+    $2006 first write    t: .CDEFGH ........ <- d: ..CDEFGH, bit 14 cleared
+    $2006 second write   t: ....... ABCDEFGH <- d: ABCDEFGH, then v <- t
+
+   With rendering on, on the visible and pre-render scanlines, v moves with
+   the fetches: coarse X steps every 8 dots up to 256 and at 328 and 336 (the
+   first two tiles of the next line), Y steps at dot 256, the horizontal bits
+   of t are copied back at dot 257 and the vertical ones over dots 280-304 of
+   the pre-render line. Both steps carry into the nametable bits, coarse Y
+   wrapping at row 30 since rows 30 and 31 hold the attribute table.
+
+   The renderer draws a whole line at dot 256, from where v was when that
+   line's first tiles were fetched, so scroll writes between scanlines (split
+   screens, status bars) take effect on the next line.
+*/
+
+impl Ppu {
+    pub(super) fn rendering_enabled(&self) -> bool {
+        return self.mask & (MASK_BACKGROUND | MASK_SPRITES) != 0;
+    }
+
+    pub(super) fn on_render_line(&self) -> bool {
+        return (self.scanline as usize) < SCREEN_HEIGHT || self.scanline == PRE_RENDER_SCANLINE;
+    }
+
+    // called once per dot, after the position has moved on
+    pub(super) fn clock_scroll(&mut self) {
+        if !self.rendering_enabled() || !self.on_render_line() {
+            return;
+        }
+        match self.dot {
+            256 => {
+                self.increment_coarse_x();
+                self.increment_y();
+            }
+            257 => self.vram_addr = (self.vram_addr & !0x041F) | (self.temp_addr & 0x041F),
+            280..=304 if self.scanline == PRE_RENDER_SCANLINE => {
+                self.vram_addr = (self.vram_addr & !0x7BE0) | (self.temp_addr & 0x7BE0);
+            }
+            321 => {
+                self.line_start_addr = self.vram_addr;
+            }
+            dot @ (8..=248 | 328 | 336) if dot % 8 == 0 => {
+                self.increment_coarse_x();
+            }
+            _ => {}
+        }
+    }
+
+    pub(super) fn increment_coarse_x(&mut self) {
+        if self.vram_addr & 0x001F == 0x001F {
+            self.vram_addr &= !0x001F;
+            self.vram_addr ^= 0x0400;
+        } else {
+            self.vram_addr += 1;
+        }
+    }
+
+    pub(super) fn increment_y(&mut self) {
+        if self.vram_addr & 0x7000 != 0x7000 {
+            self.vram_addr += 0x1000;
+            return;
+        }
+        self.vram_addr &= !0x7000;
+        let mut coarse_y = (self.vram_addr & 0x03E0) >> 5;
+        if coarse_y == 29 {
+            coarse_y = 0;
+            self.vram_addr ^= 0x0800;
+        } else if coarse_y == 31 {
+            // set out of range by a write, wraps without switching nametables
+            coarse_y = 0;
+        } else {
+            coarse_y += 1;
+        }
+        self.vram_addr = (self.vram_addr & !0x03E0) | (coarse_y << 5);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ppu::test::chr_ram_mapper;
+    use crate::ppu::DOTS_PER_SCANLINE;
+
+    #[test]
+    fn test_register_writes_fill_t() {
+        let mut ppu = Ppu::new();
+        let mut mapper = chr_ram_mapper();
+        ppu.write_register(0x2000, 0b10, mapper.as_mut());
+        ppu.write_register(0x2005, 0x7D, mapper.as_mut()); // x = 125
+        ppu.write_register(0x2005, 0x5E, mapper.as_mut()); // y = 94
+        assert_eq!(ppu.temp_addr, 0b110_1001_0110_1111);
+        assert_eq!(ppu.fine_x, 0b101);
+        assert_eq!(ppu.scroll(), (0x7D, 0x5E));
+        assert_eq!(ppu.vram_addr(), 0);
+
+        // PPUADDR shares t, so a scroll write can be finished through it
+        ppu.write_register(0x2006, 0x04, mapper.as_mut());
+        ppu.write_register(0x2006, 0x3D, mapper.as_mut());
+        assert_eq!(ppu.vram_addr(), 0x043D);
+        assert_eq!(ppu.scroll(), (0xED, 0x08));
+    }
+
+    #[test]
+    fn test_coarse_x_and_y_wrap_into_the_next_nametable() {
+        let mut ppu = Ppu::new();
+        ppu.vram_addr = 0x001F;
+        ppu.increment_coarse_x();
+        assert_eq!(ppu.vram_addr, 0x0400);
+
+        ppu.vram_addr = 0x7000 | (29 << 5);
+        ppu.increment_y();
+        assert_eq!(ppu.vram_addr, 0x0800);
+
+        ppu.vram_addr = 0x7000 | (31 << 5) | 0x0800;
+        ppu.increment_y();
+        assert_eq!(ppu.vram_addr, 0x0800);
+    }
+
+    #[test]
+    fn test_rendering_moves_v() {
+        let mut ppu = Ppu::new();
+        let mut mapper = chr_ram_mapper();
+        ppu.write_register(0x2001, MASK_BACKGROUND, mapper.as_mut());
+        ppu.write_register(0x2005, 0x08, mapper.as_mut());
+        ppu.write_register(0x2005, 0x00, mapper.as_mut());
+
+        // through the pre-render line t is copied into v, then the next
+        // line's first two tiles are fetched
+        ppu.tick(PRE_RENDER_SCANLINE as u64 * DOTS_PER_SCANLINE as u64, None);
+        ppu.tick(DOTS_PER_SCANLINE as u64, None);
+        assert_eq!((ppu.scanline(), ppu.dot()), (0, 0));
+        assert_eq!(ppu.vram_addr(), 3);
+
+        // one line later, fine Y has stepped and X is back at the same place
+        ppu.tick(DOTS_PER_SCANLINE as u64, None);
+        assert_eq!(ppu.vram_addr(), 0x1003);
+    }
+
+    #[test]
+    fn test_ppudata_during_rendering_bumps_the_scroll() {
+        let mut ppu = Ppu::new();
+        let mut mapper = chr_ram_mapper();
+        ppu.write_register(0x2001, MASK_BACKGROUND, mapper.as_mut());
+        ppu.write_register(0x2007, 0, mapper.as_mut());
+        assert_eq!(ppu.vram_addr(), 0x1001);
+    }
+}
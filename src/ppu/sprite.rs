@@ -0,0 +1,322 @@
+use crate::mapper::Mapper;
+use crate::ppu::render::SCREEN_WIDTH;
+use crate::ppu::{Ppu, CTRL_SPRITE_SIZE, CTRL_SPRITE_TABLE, MASK_SPRITES_LEFT, STATUS_OVERFLOW};
+
+pub const SPRITE_COUNT: usize = 64;
+pub const SPRITES_PER_LINE: usize = 8;
+
+pub(super) const ATTR_FLIP_VERTICAL: u8 = 0b1000_0000;
+pub(super) const ATTR_FLIP_HORIZONTAL: u8 = 0b0100_0000;
+pub(super) const ATTR_BEHIND_BACKGROUND: u8 = 0b0010_0000;
+pub(super) const ATTR_PALETTE: u8 = 0b0000_0011;
+
+/* Sprites
+
+   OAM holds 64 four byte entries:
+    0  Y, one less than the first scanline the sprite is on
+    1  tile index
+    2  VHP- --PP  flip vertical/horizontal, behind background, palette 4-7
+    3  X of the left column
+
+   While a scanline is drawn the PPU looks through OAM for the sprites on the
+   next one and copies the first eight it finds. With eight found it keeps
+   looking only to set the overflow flag, but the hardware steps the byte
+   offset within each entry along with the entry number, so it compares tile
+   indices, attributes and X positions as if they were Y coordinates. That
+   gives both false positives and misses, and some games and test ROMs depend
+   on it, so it is reproduced unless `accurate_sprite_overflow` is turned off.
+
+   With PPUCTRL bit 5 set sprites are 8x16. Bit 0 of the tile index then picks
+   the pattern table and the rest a pair of tiles, the even one on top, and a
+   vertical flip swaps the halves as well as the rows within them.
+
+   The lowest numbered opaque sprite pixel wins, even one behind the
+   background, so a hidden sprite can still mask those after it. Sprite 0 hit
+   is set when an opaque pixel of sprite 0 lands on an opaque background pixel,
+   never at x=255 and not in the left column when either layer is clipped there.
+*/
+
+#[derive(Debug, Clone, Copy, Default)]
+pub(super) struct LineSprites {
+    pub(super) entries: [usize; SPRITES_PER_LINE], // OAM entry numbers, front to back
+    pub(super) count: usize,
+}
+
+impl Ppu {
+    pub fn sprite_height(&self) -> usize {
+        if self.ctrl & CTRL_SPRITE_SIZE != 0 {
+            return 16;
+        }
+        return 8;
+    }
+
+    // scanline `y` shows the sprites whose Y byte is in y-height..y
+    fn sprite_in_range(&self, y: usize, sprite_y: u8) -> bool {
+        let row = y as isize - 1 - sprite_y as isize;
+        return (0..self.sprite_height() as isize).contains(&row);
+    }
+
+    pub(super) fn evaluate_sprites(&mut self, y: usize) -> LineSprites {
+        let mut sprites = LineSprites {
+            entries: [0; SPRITES_PER_LINE],
+            count: 0,
+        };
+        let mut n = 0;
+        while n < SPRITE_COUNT && sprites.count < SPRITES_PER_LINE {
+            if self.sprite_in_range(y, self.oam[n * 4]) {
+                sprites.entries[sprites.count] = n;
+                sprites.count += 1;
+            }
+            n += 1;
+        }
+
+        let mut m = 0;
+        while n < SPRITE_COUNT {
+            if self.sprite_in_range(y, self.oam[n * 4 + m]) {
+                self.status |= STATUS_OVERFLOW;
+                break;
+            }
+            n += 1;
+            if self.accurate_sprite_overflow {
+                m = (m + 1) & 3;
+            }
+        }
+        return sprites;
+    }
+
+    // pattern table address of one row of a sprite, counted from its top
+    pub(super) fn sprite_row_addr(&self, tile: u8, row: usize) -> u16 {
+        if self.sprite_height() == 16 {
+            let table = (tile as u16 & 1) * 0x1000;
+            let tile = (tile & 0xFE) as u16 + (row >= 8) as u16;
+            return table + tile * 16 + (row % 8) as u16;
+        }
+        let table: u16 = if self.ctrl & CTRL_SPRITE_TABLE != 0 {
+            0x1000
+        } else {
+            0x0000
+        };
+        return table + tile as u16 * 16 + row as u16;
+    }
+
+    // draws over `line`, which holds the background as palette RAM indices
+    pub(super) fn sprite_line(
+        &mut self,
+        y: usize,
+        sprites: &LineSprites,
+        line: &mut [u8; SCREEN_WIDTH],
+        mapper: &mut dyn Mapper,
+    ) {
+        let background = *line;
+        let mut covered = [false; SCREEN_WIDTH];
+        let height = self.sprite_height();
+
+        for n in &sprites.entries[..sprites.count] {
+            let entry = &self.oam[n * 4..n * 4 + 4];
+            let (tile, attributes, left) = (entry[1], entry[2], entry[3] as usize);
+            let mut row = y - 1 - entry[0] as usize;
+            if attributes & ATTR_FLIP_VERTICAL != 0 {
+                row = height - 1 - row;
+            }
+            let addr = self.sprite_row_addr(tile, row);
+            let low = mapper.ppu_read(addr);
+            let high = mapper.ppu_read(addr + 8);
+            let palette = 4 + (attributes & ATTR_PALETTE);
+
+            for column in 0..8 {
+                let x = left + column;
+                if x >= SCREEN_WIDTH {
+                    break;
+                }
+                if x < 8 && self.mask & MASK_SPRITES_LEFT == 0 {
+                    continue;
+                }
+                let bit = if attributes & ATTR_FLIP_HORIZONTAL != 0 {
+                    column
+                } else {
+                    7 - column
+                };
+                let pixel = ((low >> bit) & 1) | (((high >> bit) & 1) << 1);
+                if pixel == 0 {
+                    continue;
+                }
+
+                if *n == 0 && background[x] != 0 && x != SCREEN_WIDTH - 1 {
+                    self.hit_sprite_zero(x as u16 + 1);
+                }
+                if covered[x] {
+                    continue;
+                }
+                covered[x] = true;
+                if attributes & ATTR_BEHIND_BACKGROUND == 0 || background[x] == 0 {
+                    line[x] = palette * 4 + pixel;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ppu::palette::SYSTEM_PALETTE;
+    use crate::ppu::test::chr_ram_mapper;
+    use crate::ppu::{MASK_BACKGROUND, MASK_BACKGROUND_LEFT, MASK_SPRITES, STATUS_SPRITE_ZERO};
+
+    fn place(ppu: &mut Ppu, n: usize, entry: [u8; 4]) {
+        ppu.oam_mut()[n * 4..n * 4 + 4].copy_from_slice(&entry);
+    }
+
+    // every sprite parked below the screen, as games do with unused entries
+    fn hidden_sprites() -> Ppu {
+        let mut ppu = Ppu::new();
+        for byte in ppu.oam_mut().iter_mut() {
+            *byte = 0xFF;
+        }
+        return ppu;
+    }
+
+    fn render_line(ppu: &mut Ppu, mapper: &mut dyn Mapper, y: usize) {
+        while ppu.scanline() as usize != y || ppu.dot() != 256 {
+            ppu.tick(1, Some(mapper));
+        }
+    }
+
+    fn pixel(ppu: &Ppu, x: usize, y: usize) -> (u8, u8, u8) {
+        let offset = (y * SCREEN_WIDTH + x) * 4;
+        let pixels = ppu.frame_buffer();
+        return (pixels[offset], pixels[offset + 1], pixels[offset + 2]);
+    }
+
+    #[test]
+    fn test_eight_sprites_per_line() {
+        let mut ppu = hidden_sprites();
+        for n in 0..9 {
+            place(&mut ppu, n, [10, 0, 0, n as u8 * 8]);
+        }
+        let sprites = ppu.evaluate_sprites(11);
+        assert_eq!(sprites.count, 8);
+        assert_eq!(sprites.entries, [0, 1, 2, 3, 4, 5, 6, 7]);
+        assert_ne!(ppu.status() & STATUS_OVERFLOW, 0);
+
+        // the sprites cover lines 11-18 only
+        let mut ppu = hidden_sprites();
+        place(&mut ppu, 3, [10, 0, 0, 0]);
+        assert_eq!(ppu.evaluate_sprites(10).count, 0);
+        assert_eq!(ppu.evaluate_sprites(18).entries[0], 3);
+        assert_eq!(ppu.evaluate_sprites(19).count, 0);
+        assert_eq!(ppu.status() & STATUS_OVERFLOW, 0);
+    }
+
+    #[test]
+    fn test_overflow_scan_bug() {
+        // eight sprites on the line, then one that is off it but has a tile
+        // index of 10 right after it, in the byte the buggy scan looks at
+        let mut ppu = hidden_sprites();
+        for n in 0..8 {
+            place(&mut ppu, n, [10, 0, 0, 0]);
+        }
+        place(&mut ppu, 9, [0xFF, 10, 0, 0]);
+        ppu.evaluate_sprites(11);
+        assert_ne!(ppu.status() & STATUS_OVERFLOW, 0);
+
+        ppu.status = 0;
+        ppu.accurate_sprite_overflow = false;
+        ppu.evaluate_sprites(11);
+        assert_eq!(ppu.status() & STATUS_OVERFLOW, 0);
+
+        // and a ninth sprite that really is on the line gets missed
+        place(&mut ppu, 9, [10, 0xFF, 0, 0]);
+        ppu.evaluate_sprites(11);
+        assert_ne!(ppu.status() & STATUS_OVERFLOW, 0);
+        ppu.status = 0;
+        ppu.accurate_sprite_overflow = true;
+        ppu.evaluate_sprites(11);
+        assert_eq!(ppu.status() & STATUS_OVERFLOW, 0);
+    }
+
+    #[test]
+    fn test_sprite_drawing_and_priority() {
+        let mut ppu = hidden_sprites();
+        let mut mapper = chr_ram_mapper();
+        // tile 1 is solid colour 1, tile 2 only has its leftmost column set
+        for row in 0..8 {
+            mapper.ppu_write(0x0010 + row, 0xFF);
+            mapper.ppu_write(0x0020 + row, 0x80);
+        }
+        ppu.write_palette(0x3F00, 0x0F);
+        ppu.write_palette(0x3F11, 0x16);
+        ppu.write_palette(0x3F15, 0x2A);
+        place(&mut ppu, 0, [0, 2, ATTR_FLIP_HORIZONTAL | 1, 20]);
+        place(&mut ppu, 1, [0, 1, 0, 24]);
+        ppu.mask = MASK_SPRITES;
+        render_line(&mut ppu, mapper.as_mut(), 1);
+
+        assert_eq!(pixel(&ppu, 20, 1), SYSTEM_PALETTE[0x0F]);
+        assert_eq!(pixel(&ppu, 27, 1), SYSTEM_PALETTE[0x2A]); // flipped to the right edge
+        assert_eq!(pixel(&ppu, 24, 1), SYSTEM_PALETTE[0x16]);
+        assert_eq!(pixel(&ppu, 28, 1), SYSTEM_PALETTE[0x16]);
+        assert_eq!(pixel(&ppu, 32, 1), SYSTEM_PALETTE[0x0F]);
+    }
+
+    #[test]
+    fn test_sprite_zero_hit() {
+        let mut ppu = hidden_sprites();
+        let mut mapper = chr_ram_mapper();
+        for row in 0..8 {
+            mapper.ppu_write(0x0010 + row, 0xFF);
+        }
+        let mirroring = mapper.mirroring();
+        ppu.write_nametable(0x2000, mirroring, 1); // background only in the top left tile
+        place(&mut ppu, 0, [0, 1, 0, 4]);
+        ppu.mask = MASK_BACKGROUND | MASK_SPRITES;
+        render_line(&mut ppu, mapper.as_mut(), 1);
+        // clipped on the left, and the overlap is all in the first 8 pixels
+        assert_eq!(ppu.status() & STATUS_SPRITE_ZERO, 0);
+
+        ppu.mask |= MASK_BACKGROUND_LEFT | MASK_SPRITES_LEFT;
+        render_line(&mut ppu, mapper.as_mut(), 2);
+        assert_ne!(ppu.status() & STATUS_SPRITE_ZERO, 0);
+        // at the first pixel they overlap on, for the event log
+        assert_eq!(ppu.take_sprite_zero_hit(), Some((2, 5)));
+
+        // cleared on the pre-render line, and a sprite over backdrop never hits
+        place(&mut ppu, 0, [0, 1, 0, 100]);
+        render_line(&mut ppu, mapper.as_mut(), 1);
+        assert_eq!(ppu.status() & STATUS_SPRITE_ZERO, 0);
+    }
+
+    #[test]
+    fn test_tall_sprites() {
+        let mut ppu = hidden_sprites();
+        let mut mapper = chr_ram_mapper();
+        ppu.ctrl = CTRL_SPRITE_SIZE;
+        assert_eq!(ppu.sprite_height(), 16);
+
+        // tile 3 means the pair 2/3 from the $1000 table, the sprite table bit is ignored
+        ppu.ctrl |= CTRL_SPRITE_TABLE;
+        assert_eq!(ppu.sprite_row_addr(3, 0), 0x1020);
+        assert_eq!(ppu.sprite_row_addr(3, 9), 0x1031);
+        assert_eq!(ppu.sprite_row_addr(2, 15), 0x0037);
+
+        // the top tile draws colour 1 and the bottom one colour 2
+        for row in 0..8 {
+            mapper.ppu_write(0x1020 + row, 0xFF);
+            mapper.ppu_write(0x1038 + row, 0xFF);
+        }
+        ppu.write_palette(0x3F00, 0x0F);
+        ppu.write_palette(0x3F11, 0x16);
+        ppu.write_palette(0x3F12, 0x2A);
+        place(&mut ppu, 0, [9, 3, 0, 0]);
+        place(&mut ppu, 1, [9, 3, ATTR_FLIP_VERTICAL, 8]);
+        ppu.mask = MASK_SPRITES | MASK_SPRITES_LEFT;
+        render_line(&mut ppu, mapper.as_mut(), 10);
+        assert_eq!(pixel(&ppu, 0, 10), SYSTEM_PALETTE[0x16]);
+        assert_eq!(pixel(&ppu, 8, 10), SYSTEM_PALETTE[0x2A]);
+        render_line(&mut ppu, mapper.as_mut(), 25);
+        assert_eq!(pixel(&ppu, 0, 25), SYSTEM_PALETTE[0x2A]);
+        assert_eq!(pixel(&ppu, 8, 25), SYSTEM_PALETTE[0x16]);
+        render_line(&mut ppu, mapper.as_mut(), 26);
+        assert_eq!(pixel(&ppu, 0, 26), SYSTEM_PALETTE[0x0F]);
+    }
+}
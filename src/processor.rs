@@ -19,6 +19,12 @@ pub struct Processor {
     flags: u8,
 }
 
+impl Default for Processor {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
 impl Processor {
     pub fn new() -> Self {
         let flags = 0b0011_0000;
@@ -26,6 +32,14 @@ impl Processor {
         return Self { flags };
     }
 
+    pub fn bits(&self) -> u8 {
+        return self.flags;
+    }
+
+    pub fn set_bits(&mut self, flags: u8) {
+        self.flags = flags;
+    }
+
     pub fn carry(&self) -> u8 {
         return self.flags >> 0 & 1;
     }
@@ -109,6 +123,18 @@ mod test {
         assert_eq!(processor.flags, 0b0011_0000);
     }
 
+    #[test]
+    fn test_bits() {
+        let mut processor = Processor::new();
+        assert_eq!(processor.bits(), 0b0011_0000);
+        processor.set_bits(0b1100_0011);
+        assert_eq!(processor.negative(), 1);
+        assert_eq!(processor.overflow(), 1);
+        assert_eq!(processor.zero(), 1);
+        assert_eq!(processor.carry(), 1);
+        assert_eq!(processor.bits(), 0b1100_0011);
+    }
+
     #[test]
     fn test_set_carry() {
         let mut processor = Processor::new();
@@ -0,0 +1,281 @@
+use crate::video::NTSC_FRAME_DURATION;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/* Frame profiler
+
+   Attributes host time to the parts of the core that spend it, frame by
+   frame. The emulation loop wraps each stage in `measure`; whatever falls
+   between the measured stages (the loop itself, input polling, sleeping on
+   vsync) shows up as "other". A disabled profiler still runs the closures
+   but never touches the clock, so it can be left in the hot loop.
+*/
+
+const BAR_WIDTH: usize = 40;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Component {
+    Cpu,
+    Ppu,
+    Apu,
+    Present,
+}
+
+impl Component {
+    pub const ALL: [Component; 4] = [
+        Component::Cpu,
+        Component::Ppu,
+        Component::Apu,
+        Component::Present,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Component::Cpu => return "cpu",
+            Component::Ppu => return "ppu",
+            Component::Apu => return "apu",
+            Component::Present => return "present",
+        }
+    }
+
+    fn describe(&self) -> &'static str {
+        match self {
+            Component::Cpu => return "CPU interpretation",
+            Component::Ppu => return "PPU rendering",
+            Component::Apu => return "APU mixing",
+            Component::Present => return "frontend present",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Profiler {
+    enabled: bool,
+    frame_start: Option<Instant>,
+    current: [Duration; 4],
+    totals: [Duration; 4],
+    wall: Duration,
+    frames: u64,
+    slowest: Option<(u64, Duration)>,
+}
+
+impl Profiler {
+    pub fn new(enabled: bool) -> Self {
+        return Self {
+            enabled,
+            ..Self::default()
+        };
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        return self.enabled;
+    }
+
+    pub fn measure<R>(&mut self, component: Component, f: impl FnOnce() -> R) -> R {
+        if !self.enabled {
+            return f();
+        }
+        let start = Instant::now();
+        let result = f();
+        self.record(component, start.elapsed());
+        return result;
+    }
+
+    pub fn record(&mut self, component: Component, elapsed: Duration) {
+        self.current[component as usize] += elapsed;
+    }
+
+    pub fn begin_frame(&mut self) {
+        if self.enabled {
+            self.frame_start = Some(Instant::now());
+        }
+    }
+
+    pub fn end_frame(&mut self) {
+        if let Some(start) = self.frame_start.take() {
+            self.finish_frame(start.elapsed());
+        }
+    }
+
+    fn finish_frame(&mut self, wall: Duration) {
+        let attributed: Duration = self.current.iter().sum();
+        let wall = wall.max(attributed);
+        for (total, current) in self.totals.iter_mut().zip(self.current.iter_mut()) {
+            *total += *current;
+            *current = Duration::ZERO;
+        }
+        if self.slowest.is_none_or(|(_, slowest)| wall > slowest) {
+            self.slowest = Some((self.frames, wall));
+        }
+        self.wall += wall;
+        self.frames += 1;
+    }
+
+    pub fn report(&self) -> Report {
+        return Report {
+            frames: self.frames,
+            wall: self.wall,
+            totals: self.totals,
+            slowest: self.slowest,
+        };
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub frames: u64,
+    pub wall: Duration,
+    pub totals: [Duration; 4],
+    pub slowest: Option<(u64, Duration)>,
+}
+
+impl Report {
+    pub fn total(&self, component: Component) -> Duration {
+        return self.totals[component as usize];
+    }
+
+    pub fn other(&self) -> Duration {
+        return self.wall.saturating_sub(self.totals.iter().sum());
+    }
+
+    pub fn per_frame(&self) -> Duration {
+        if self.frames == 0 {
+            return Duration::ZERO;
+        }
+        return self.wall / self.frames as u32;
+    }
+
+    // the component that took the most time, None if nothing was measured
+    pub fn bottleneck(&self) -> Option<Component> {
+        return Component::ALL
+            .into_iter()
+            .filter(|c| self.total(*c) > Duration::ZERO)
+            .max_by_key(|c| self.total(*c));
+    }
+
+    fn share(&self, time: Duration) -> f64 {
+        if self.wall.is_zero() {
+            return 0.0;
+        }
+        return time.as_secs_f64() / self.wall.as_secs_f64();
+    }
+
+    fn write_row(&self, f: &mut fmt::Formatter<'_>, label: &str, time: Duration) -> fmt::Result {
+        let share = self.share(time);
+        let per_frame = time / self.frames.max(1) as u32;
+        let bar = "#".repeat((share * BAR_WIDTH as f64).round() as usize);
+        return writeln!(
+            f,
+            "  {:<8} {:>9.3}ms/frame {:>5.1}%  {}",
+            label,
+            per_frame.as_secs_f64() * 1000.0,
+            share * 100.0,
+            bar
+        );
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.frames == 0 {
+            return writeln!(f, "profile: no frames recorded");
+        }
+        let per_frame = self.per_frame();
+        writeln!(
+            f,
+            "profile: {} frames in {:.3}s, {:.3}ms/frame ({:.0}% of the {:.2}ms NTSC frame budget)",
+            self.frames,
+            self.wall.as_secs_f64(),
+            per_frame.as_secs_f64() * 1000.0,
+            per_frame.as_secs_f64() / NTSC_FRAME_DURATION.as_secs_f64() * 100.0,
+            NTSC_FRAME_DURATION.as_secs_f64() * 1000.0
+        )?;
+        for component in Component::ALL {
+            self.write_row(f, component.label(), self.total(component))?;
+        }
+        self.write_row(f, "other", self.other())?;
+
+        if let Some((frame, time)) = self.slowest {
+            writeln!(
+                f,
+                "slowest frame: #{} at {:.3}ms",
+                frame,
+                time.as_secs_f64() * 1000.0
+            )?;
+        }
+        if let Some(component) = self.bottleneck() {
+            let verdict = if per_frame > NTSC_FRAME_DURATION {
+                "too slow for full speed"
+            } else {
+                "within budget"
+            };
+            writeln!(f, "{}, most time goes to {}", verdict, component.describe())?;
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ms(n: u64) -> Duration {
+        return Duration::from_millis(n);
+    }
+
+    #[test]
+    fn test_attribution() {
+        let mut profiler = Profiler::new(true);
+        profiler.record(Component::Cpu, ms(6));
+        profiler.record(Component::Ppu, ms(3));
+        profiler.finish_frame(ms(10));
+        profiler.record(Component::Cpu, ms(10));
+        profiler.record(Component::Present, ms(2));
+        profiler.finish_frame(ms(14));
+
+        let report = profiler.report();
+        assert_eq!(report.frames, 2);
+        assert_eq!(report.total(Component::Cpu), ms(16));
+        assert_eq!(report.total(Component::Apu), Duration::ZERO);
+        assert_eq!(report.other(), ms(3));
+        assert_eq!(report.per_frame(), ms(12));
+        assert_eq!(report.slowest, Some((1, ms(14))));
+        assert_eq!(report.bottleneck(), Some(Component::Cpu));
+    }
+
+    #[test]
+    fn test_report_text() {
+        let mut profiler = Profiler::new(true);
+        profiler.record(Component::Ppu, ms(20));
+        profiler.finish_frame(ms(20));
+
+        let text = profiler.report().to_string();
+        assert!(text.contains("1 frames"));
+        assert!(text.contains("too slow for full speed, most time goes to PPU rendering"));
+        assert!(text.contains("  ppu"));
+    }
+
+    #[test]
+    fn test_disabled_profiler_records_nothing() {
+        let mut profiler = Profiler::new(false);
+        profiler.begin_frame();
+        assert_eq!(profiler.measure(Component::Cpu, || 7), 7);
+        profiler.end_frame();
+        assert_eq!(profiler.report().frames, 0);
+        assert_eq!(
+            profiler.report().to_string(),
+            "profile: no frames recorded\n"
+        );
+    }
+
+    #[test]
+    fn test_measure_wall_clock() {
+        let mut profiler = Profiler::new(true);
+        profiler.begin_frame();
+        profiler.measure(Component::Apu, || std::thread::sleep(ms(2)));
+        profiler.end_frame();
+        let report = profiler.report();
+        assert!(report.total(Component::Apu) >= ms(2));
+        assert!(report.wall >= report.total(Component::Apu));
+    }
+}
@@ -0,0 +1,285 @@
+use crate::cartridge::{Mirroring, Region, Rom};
+use crate::hash;
+use std::fs;
+use std::path::Path;
+use std::sync::RwLock;
+
+/* ROM database
+
+   Dumps are keyed by the CRC32 of their PRG+CHR data, with an optional SHA-1
+   to tell apart the rare CRC collisions. Entries only list what the header
+   can't be trusted for; everything else is taken from the header as usual.
+   The built-in list lives in data/romdb.txt and user files can be layered on
+   top, later entries winning, from `--romdb` or the config file.
+
+   A dump the database knows has its header corrected as `Rom::from_file`
+   reads it and again as a console is built with it, so ROMs handed over
+   as bytes, as the web page and the Python bindings do, get the fixes
+   too. Its title names the game in the window and in `rustynes info`.
+*/
+
+const BUILTIN: &str = include_str!("../data/romdb.txt");
+
+lazy_static! {
+    static ref DATABASE: RwLock<RomDb> =
+        RwLock::new(RomDb::parse(BUILTIN).expect("built-in ROM database is valid"));
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DbEntry {
+    pub crc32: u32,
+    pub sha1: Option<[u8; 20]>,
+    pub title: String,
+    pub mapper: Option<u16>,
+    pub submapper: Option<u8>,
+    pub mirroring: Option<Mirroring>,
+    pub battery: Option<bool>,
+    pub region: Option<Region>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RomDb {
+    entries: Vec<DbEntry>,
+}
+
+impl RomDb {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    pub fn parse(text: &str) -> Result<RomDb, String> {
+        let mut db = RomDb::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let entry = parse_entry(line).map_err(|e| format!("line {}: {}", number + 1, e))?;
+            db.insert(entry);
+        }
+        return Ok(db);
+    }
+
+    pub fn len(&self) -> usize {
+        return self.entries.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.entries.is_empty();
+    }
+
+    // replaces an existing entry for the same dump
+    pub fn insert(&mut self, entry: DbEntry) {
+        self.entries
+            .retain(|e| !(e.crc32 == entry.crc32 && e.sha1 == entry.sha1));
+        self.entries.push(entry);
+    }
+
+    pub fn merge(&mut self, other: RomDb) {
+        for entry in other.entries {
+            self.insert(entry);
+        }
+    }
+
+    pub fn lookup(&self, crc32: u32, sha1: impl Fn() -> [u8; 20]) -> Option<&DbEntry> {
+        let mut candidates = self.entries.iter().filter(|e| e.crc32 == crc32).peekable();
+        candidates.peek()?;
+        let sha1 = sha1();
+        let mut fallback = None;
+        for entry in candidates {
+            match entry.sha1 {
+                Some(digest) if digest == sha1 => return Some(entry),
+                Some(_) => {}
+                None => fallback = Some(entry),
+            }
+        }
+        return fallback;
+    }
+}
+
+fn parse_entry(line: &str) -> Result<DbEntry, String> {
+    let fields: Vec<&str> = line.split('|').map(|f| f.trim()).collect();
+    if fields.len() != 7 {
+        return Err(format!("expected 7 fields, found {}", fields.len()));
+    }
+    let optional = |field: &str| {
+        if field == "-" {
+            None
+        } else {
+            Some(field.to_string())
+        }
+    };
+
+    let crc32 =
+        u32::from_str_radix(fields[0], 16).map_err(|_| format!("bad crc32 {}", fields[0]))?;
+    let sha1 = match optional(fields[1]) {
+        Some(hex) => Some(parse_sha1(&hex)?),
+        None => None,
+    };
+
+    let (mapper, submapper) = match optional(fields[2]) {
+        Some(value) => {
+            let (mapper, submapper) = match value.split_once('.') {
+                Some((mapper, submapper)) => (mapper.to_string(), Some(submapper.to_string())),
+                None => (value, None),
+            };
+            let mapper = mapper
+                .parse()
+                .map_err(|_| format!("bad mapper {}", mapper))?;
+            let submapper = match submapper {
+                Some(s) => Some(s.parse().map_err(|_| format!("bad submapper {}", s))?),
+                None => None,
+            };
+            (Some(mapper), submapper)
+        }
+        None => (None, None),
+    };
+
+    let mirroring = match fields[3] {
+        "-" => None,
+        "h" => Some(Mirroring::Horizontal),
+        "v" => Some(Mirroring::Vertical),
+        "4" => Some(Mirroring::FourScreen),
+        "1l" => Some(Mirroring::SingleScreenLower),
+        "1u" => Some(Mirroring::SingleScreenUpper),
+        other => return Err(format!("bad mirroring {}", other)),
+    };
+    let battery = match fields[4] {
+        "-" => None,
+        "y" => Some(true),
+        "n" => Some(false),
+        other => return Err(format!("bad battery flag {}", other)),
+    };
+    let region = match fields[5] {
+        "-" => None,
+        other => Some(Region::parse(other).map_err(|_| format!("bad region {}", other))?),
+    };
+    if fields[6].is_empty() {
+        return Err("missing title".to_string());
+    }
+
+    return Ok(DbEntry {
+        crc32,
+        sha1,
+        title: fields[6].to_string(),
+        mapper,
+        submapper,
+        mirroring,
+        battery,
+        region,
+    });
+}
+
+fn parse_sha1(hex: &str) -> Result<[u8; 20], String> {
+    let bad = || format!("bad sha1 {}", hex);
+    if hex.len() != 40 || !hex.is_ascii() {
+        return Err(bad());
+    }
+    let mut digest = [0u8; 20];
+    for (i, byte) in digest.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| bad())?;
+    }
+    return Ok(digest);
+}
+
+pub fn lookup(rom: &Rom) -> Option<DbEntry> {
+    let db = DATABASE.read().unwrap();
+    return db.lookup(rom.crc32(), || rom.sha1()).cloned();
+}
+
+// layers a user database over the built-in one
+pub fn load_file(path: &Path) -> Result<usize, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let db = RomDb::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let count = db.len();
+    DATABASE.write().unwrap().merge(db);
+    return Ok(count);
+}
+
+pub fn describe(entry: &DbEntry) -> String {
+    let mut text = format!("{} (crc32 {:08x}", entry.title, entry.crc32);
+    if let Some(sha1) = entry.sha1 {
+        text.push_str(&format!(", sha1 {}", hash::to_hex(&sha1)));
+    }
+    text.push(')');
+    return text;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+    use crate::nes::Nes;
+
+    #[test]
+    fn test_builtin_database_parses() {
+        let db = RomDb::parse(BUILTIN).unwrap();
+        assert!(db.len() > 2000);
+        let entry = db.lookup(0xD445_F698, || [0; 20]).unwrap();
+        assert_eq!(entry.title, "Super Mario Bros. (World)");
+        assert_eq!(entry.mapper, Some(0));
+        assert_eq!(entry.mirroring, Some(Mirroring::Vertical));
+    }
+
+    #[test]
+    fn test_parse_entries() {
+        let db = RomDb::parse(
+            "# comment\n\
+             1234abcd | - | 4.1 | v | y | pal | Some Game\n\
+             00000001 | - | - | - | - | - | Header Is Fine\n",
+        )
+        .unwrap();
+        assert_eq!(db.len(), 2);
+        let entry = db.lookup(0x1234_ABCD, || [0; 20]).unwrap();
+        assert_eq!(entry.title, "Some Game");
+        assert_eq!(entry.mapper, Some(4));
+        assert_eq!(entry.submapper, Some(1));
+        assert_eq!(entry.mirroring, Some(Mirroring::Vertical));
+        assert_eq!(entry.battery, Some(true));
+        assert_eq!(entry.region, Some(Region::Pal));
+        assert_eq!(db.lookup(0x1234_ABCE, || [0; 20]), None);
+
+        let err = RomDb::parse("1 | - | x | - | - | - | Bad").err();
+        assert_eq!(err, Some("line 1: bad mapper x".to_string()));
+        assert!(RomDb::parse("1 | - | - | - | - | -").is_err());
+    }
+
+    #[test]
+    fn test_sha1_disambiguates() {
+        let a = "a".repeat(40);
+        let b = "b".repeat(40);
+        let db = RomDb::parse(&format!(
+            "00000010 | {} | - | - | - | - | First\n\
+             00000010 | {} | - | - | - | - | Second\n",
+            a, b
+        ))
+        .unwrap();
+        assert_eq!(db.lookup(0x10, || [0xBB; 20]).unwrap().title, "Second");
+        assert_eq!(db.lookup(0x10, || [0xCC; 20]), None);
+    }
+
+    #[test]
+    fn test_identify_and_correct_header() {
+        // a dump no other test uses, as the database is shared
+        let mut raw = test_rom(0, 1, 1);
+        raw[16..20].copy_from_slice(b"rmdb");
+        let mut rom = Rom::new(&raw).unwrap();
+        assert_eq!(rom.identify(), None);
+
+        let line = format!("{:08x} | - | 66 | h | - | - | Fixed Up", rom.crc32());
+        DATABASE
+            .write()
+            .unwrap()
+            .merge(RomDb::parse(&line).unwrap());
+
+        let entry = rom.correct_header().unwrap();
+        assert_eq!(entry.title, "Fixed Up");
+        assert_eq!(rom.mapper, 66);
+        assert_eq!(rom.screen_mirroring, Mirroring::Horizontal);
+        assert!(describe(&entry).starts_with("Fixed Up (crc32 "));
+
+        // the console is built with the header the database gives
+        let nes = Nes::new(Rom::new(&raw).unwrap()).unwrap();
+        assert_eq!(nes.rom().mapper, 66);
+    }
+}
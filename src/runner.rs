@@ -0,0 +1,331 @@
+use crate::cpu::{Mem, CPU};
+use std::any::Any;
+use std::collections::VecDeque;
+use std::panic;
+use std::sync::Mutex;
+use std::thread;
+
+const RAM_START: u16 = 0x0000;
+const RAM_END: u16 = 0x07FF;
+
+/* Parallel headless runner
+
+   Jobs are dealt round-robin onto one deque per worker. A worker pops from the
+   front of its own deque and, once that is drained, steals from the back of the
+   others, so a handful of slow sessions can't leave the remaining cores idle.
+   Results are returned in the order the jobs were submitted.
+*/
+
+#[derive(Debug, Clone)]
+pub struct HeadlessJob {
+    pub name: String,
+    pub program: Vec<u8>,
+    pub seed: u64,
+    pub max_steps: u64,
+}
+
+impl HeadlessJob {
+    pub fn new(name: &str, program: Vec<u8>, seed: u64, max_steps: u64) -> Self {
+        return Self {
+            name: name.to_string(),
+            program,
+            seed,
+            max_steps,
+        };
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Registers {
+    pub register_a: u8,
+    pub register_x: u8,
+    pub register_y: u8,
+    pub program_counter: u16,
+}
+
+impl Registers {
+    fn from_cpu(cpu: &CPU) -> Self {
+        return Self {
+            register_a: cpu.register_a,
+            register_x: cpu.register_x,
+            register_y: cpu.register_y,
+            program_counter: cpu.program_counter,
+        };
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Halted { steps: u64, registers: Registers },
+    StepLimit { registers: Registers },
+    Jammed { steps: u64, registers: Registers }, // on an opcode it can't run
+    Panicked(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct SessionResult {
+    pub name: String,
+    pub seed: u64,
+    pub outcome: Outcome,
+}
+
+#[derive(Debug)]
+pub struct Summary {
+    pub results: Vec<SessionResult>,
+}
+
+impl Summary {
+    pub fn halted(&self) -> usize {
+        return self.count(|o| matches!(o, Outcome::Halted { .. }));
+    }
+
+    pub fn step_limited(&self) -> usize {
+        return self.count(|o| matches!(o, Outcome::StepLimit { .. }));
+    }
+
+    pub fn jammed(&self) -> usize {
+        return self.count(|o| matches!(o, Outcome::Jammed { .. }));
+    }
+
+    pub fn panicked(&self) -> usize {
+        return self.count(|o| matches!(o, Outcome::Panicked(_)));
+    }
+
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+        for result in &self.results {
+            let status = match &result.outcome {
+                Outcome::Halted { steps, .. } => format!("halted after {} steps", steps),
+                Outcome::StepLimit { .. } => "step limit reached".to_string(),
+                Outcome::Jammed { steps, registers } => format!(
+                    "jammed at ${:04X} after {} steps",
+                    registers.program_counter, steps
+                ),
+                Outcome::Panicked(msg) => format!("panicked: {}", msg),
+            };
+            out.push_str(&format!(
+                "{} (seed {:#x}): {}\n",
+                result.name, result.seed, status
+            ));
+        }
+        out.push_str(&format!(
+            "{} sessions: {} halted, {} hit the step limit, {} jammed, {} panicked\n",
+            self.results.len(),
+            self.halted(),
+            self.step_limited(),
+            self.jammed(),
+            self.panicked()
+        ));
+        return out;
+    }
+
+    fn count(&self, pred: impl Fn(&Outcome) -> bool) -> usize {
+        return self.results.iter().filter(|r| pred(&r.outcome)).count();
+    }
+}
+
+pub fn default_workers() -> usize {
+    return thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+}
+
+pub fn run_headless(jobs: Vec<HeadlessJob>, workers: usize) -> Summary {
+    let results = run_parallel(jobs, workers, run_session);
+    return Summary { results };
+}
+
+pub fn run_session(job: HeadlessJob) -> SessionResult {
+    let name = job.name.clone();
+    let seed = job.seed;
+    let outcome = match panic::catch_unwind(move || execute(job)) {
+        Ok(outcome) => outcome,
+        Err(payload) => Outcome::Panicked(panic_message(payload)),
+    };
+    return SessionResult {
+        name,
+        seed,
+        outcome,
+    };
+}
+
+// generic work-stealing pool; a panic inside `session` propagates to the caller
+pub fn run_parallel<T, R, F>(jobs: Vec<T>, workers: usize, session: F) -> Vec<R>
+where
+    T: Send,
+    R: Send,
+    F: Fn(T) -> R + Sync,
+{
+    let total = jobs.len();
+    let workers = workers.clamp(1, total.max(1));
+
+    let queues: Vec<Mutex<VecDeque<(usize, T)>>> =
+        (0..workers).map(|_| Mutex::new(VecDeque::new())).collect();
+    for (index, job) in jobs.into_iter().enumerate() {
+        queues[index % workers]
+            .lock()
+            .unwrap()
+            .push_back((index, job));
+    }
+
+    let results: Mutex<Vec<Option<R>>> = Mutex::new((0..total).map(|_| None).collect());
+
+    thread::scope(|scope| {
+        for id in 0..workers {
+            let queues = &queues;
+            let results = &results;
+            let session = &session;
+            scope.spawn(move || {
+                while let Some((index, job)) = next_job(queues, id) {
+                    let result = session(job);
+                    results.lock().unwrap()[index] = Some(result);
+                }
+            });
+        }
+    });
+
+    return results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|r| r.expect("every job produces a result"))
+        .collect();
+}
+
+fn next_job<T>(queues: &[Mutex<VecDeque<(usize, T)>>], id: usize) -> Option<(usize, T)> {
+    if let Some(job) = queues[id].lock().unwrap().pop_front() {
+        return Some(job);
+    }
+
+    // own queue is drained, steal from the back of the others
+    for offset in 1..queues.len() {
+        let victim = (id + offset) % queues.len();
+        if let Some(job) = queues[victim].lock().unwrap().pop_back() {
+            return Some(job);
+        }
+    }
+
+    return None;
+}
+
+fn execute(job: HeadlessJob) -> Outcome {
+    let mut cpu = CPU::new();
+    seed_ram(&mut cpu, job.seed);
+    cpu.load(job.program);
+    cpu.reset();
+
+    let mut steps = 0;
+    while steps < job.max_steps {
+        if !cpu.step() {
+            let registers = Registers::from_cpu(&cpu);
+            if cpu.jammed {
+                return Outcome::Jammed { steps, registers };
+            }
+            return Outcome::Halted { steps, registers };
+        }
+        steps += 1;
+    }
+
+    return Outcome::StepLimit {
+        registers: Registers::from_cpu(&cpu),
+    };
+}
+
+fn seed_ram(cpu: &mut CPU, seed: u64) {
+    if seed == 0 {
+        return; // seed 0 keeps the power-on zeroed RAM
+    }
+
+    // xorshift64, good enough to shake out uninitialized-memory assumptions
+    let mut state = seed;
+    for addr in RAM_START..=RAM_END {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        cpu.mem_write(addr, state as u8);
+    }
+}
+
+pub(crate) fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        return msg.to_string();
+    }
+    if let Some(msg) = payload.downcast_ref::<String>() {
+        return msg.clone();
+    }
+    return "unknown panic".to_string();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_run_parallel_preserves_order() {
+        let jobs: Vec<u32> = (0..100).collect();
+        let results = run_parallel(jobs, 4, |n| n * 2);
+        assert_eq!(results, (0..100).map(|n| n * 2).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn test_run_parallel_more_workers_than_jobs() {
+        let results = run_parallel(vec![1, 2], 16, |n| n + 1);
+        assert_eq!(results, vec![2, 3]);
+
+        let empty: Vec<u8> = run_parallel(Vec::<u8>::new(), 4, |n| n);
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn test_next_job_steals_from_other_queues() {
+        let queues: Vec<Mutex<VecDeque<(usize, u8)>>> =
+            vec![Mutex::new(VecDeque::new()), Mutex::new(VecDeque::new())];
+        queues[1].lock().unwrap().push_back((0, 10));
+        queues[1].lock().unwrap().push_back((1, 11));
+
+        // worker 0 has nothing of its own and takes from the back of worker 1
+        assert_eq!(next_job(&queues, 0), Some((1, 11)));
+        assert_eq!(next_job(&queues, 1), Some((0, 10)));
+        assert_eq!(next_job(&queues, 0), None);
+    }
+
+    #[test]
+    fn test_run_headless_aggregates_outcomes() {
+        let jobs = vec![
+            HeadlessJob::new("lda", vec![0xA9, 0x05, 0xAA, 0x00], 0, 100),
+            HeadlessJob::new("spin", vec![0xEA; 32], 0, 8),
+            HeadlessJob::new("unknown", vec![0x02], 0, 100),
+        ];
+        let summary = run_headless(jobs, 2);
+
+        assert_eq!(summary.halted(), 1);
+        assert_eq!(summary.step_limited(), 1);
+        assert_eq!(summary.jammed(), 1);
+        assert_eq!(summary.panicked(), 0);
+
+        match &summary.results[0].outcome {
+            Outcome::Halted { steps, registers } => {
+                assert_eq!(*steps, 2);
+                assert_eq!(registers.register_a, 0x05);
+                assert_eq!(registers.register_x, 0x05);
+            }
+            other => panic!("unexpected outcome {:?}", other),
+        }
+        assert!(summary.report().contains("3 sessions: 1 halted"));
+        assert!(summary
+            .report()
+            .contains("unknown (seed 0x0): jammed at $8000 after 0 steps\n"));
+    }
+
+    #[test]
+    fn test_seed_ram_is_deterministic() {
+        let mut a = CPU::new();
+        let mut b = CPU::new();
+        seed_ram(&mut a, 0xDEAD_BEEF);
+        seed_ram(&mut b, 0xDEAD_BEEF);
+        for addr in RAM_START..=RAM_END {
+            assert_eq!(a.mem_read(addr), b.mem_read(addr));
+        }
+        assert!((RAM_START..=RAM_END).any(|addr| a.mem_read(addr) != 0));
+    }
+}
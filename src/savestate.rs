@@ -0,0 +1,287 @@
+use crate::cpu::CPU;
+use crate::error::StateError;
+use crate::sync::{self, SyncHooks, SyncKind};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/* Save state layout
+
+   "RNES" magic, one version byte, then each component appends its fields in a
+   fixed order. Multi-byte values are little endian, byte blocks are written
+   with a u32 length prefix so a truncated or mismatched state is detected
+   instead of silently misaligning every field after it.
+
+   Components load straight into the running console, so `load` saves it
+   first and puts that back when the state turns out to be bad partway
+   through; a load that fails changes nothing.
+*/
+
+const MAGIC: &[u8; 4] = b"RNES";
+const VERSION: u8 = 1;
+
+#[derive(Debug, Default)]
+pub struct StateWriter {
+    buf: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        return Self { buf: Vec::new() };
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_u8(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_bytes(&mut self, data: &[u8]) {
+        self.write_u32(data.len() as u32);
+        self.buf.extend_from_slice(data);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        return self.buf;
+    }
+}
+
+#[derive(Debug)]
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        return Self { data, pos: 0 };
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, StateError> {
+        let bytes = self.take(1)?;
+        return Ok(bytes[0]);
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, StateError> {
+        return Ok(self.read_u8()? != 0);
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, StateError> {
+        let bytes = self.take(2)?;
+        return Ok(u16::from_le_bytes([bytes[0], bytes[1]]));
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, StateError> {
+        let bytes = self.take(4)?;
+        return Ok(u32::from_le_bytes(bytes.try_into().unwrap()));
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, StateError> {
+        let bytes = self.take(8)?;
+        return Ok(u64::from_le_bytes(bytes.try_into().unwrap()));
+    }
+
+    pub fn read_bytes(&mut self) -> Result<&'a [u8], StateError> {
+        let len = self.read_u32()? as usize;
+        return self.take(len);
+    }
+
+    // copies a length-prefixed block into `dest`, which must match its size exactly
+    pub fn read_into(&mut self, dest: &mut [u8]) -> Result<(), StateError> {
+        let bytes = self.read_bytes()?;
+        if bytes.len() != dest.len() {
+            return Err(StateError::BlockSize {
+                len: bytes.len(),
+                expected: dest.len(),
+            });
+        }
+        dest.copy_from_slice(bytes);
+        return Ok(());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.pos >= self.data.len();
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.pos + len > self.data.len() {
+            return Err(StateError::Truncated);
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        return Ok(bytes);
+    }
+}
+
+pub fn save(cpu: &CPU) -> Vec<u8> {
+    let mut writer = StateWriter::new();
+    writer.buf.extend_from_slice(MAGIC);
+    writer.write_u8(VERSION);
+    cpu.save_state(&mut writer);
+    return writer.into_bytes();
+}
+
+pub fn load(cpu: &mut CPU, data: &[u8]) -> Result<(), StateError> {
+    let mut reader = StateReader::new(data);
+    if reader.take(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+        return Err(StateError::NotAState);
+    }
+    let version = reader.read_u8()?;
+    if version != VERSION {
+        return Err(StateError::Version(version));
+    }
+    let before = save(cpu);
+    let loaded = cpu
+        .load_state(&mut reader)
+        .and_then(|_| match reader.is_empty() {
+            true => return Ok(()),
+            false => return Err(StateError::TrailingData),
+        });
+    if loaded.is_err() {
+        let mut reader = StateReader::new(&before[MAGIC.len() + 1..]);
+        cpu.load_state(&mut reader)
+            .expect("the console's own state, saved a moment ago");
+    }
+    return loaded;
+}
+
+pub fn save_to_file(cpu: &CPU, path: &Path, hooks: &SyncHooks) -> io::Result<()> {
+    return sync::write_and_notify(SyncKind::State, path, &save(cpu), hooks);
+}
+
+pub fn load_from_file(cpu: &mut CPU, path: &Path) -> Result<(), StateError> {
+    let data = fs::read(path).map_err(|e| StateError::File {
+        path: path.to_path_buf(),
+        message: e.to_string(),
+    })?;
+    return load(cpu, &data);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::Mem;
+
+    #[test]
+    fn test_writer_reader_round_trip() {
+        let mut writer = StateWriter::new();
+        writer.write_u8(0x12);
+        writer.write_bool(true);
+        writer.write_u16(0x3456);
+        writer.write_u32(0x789A_BCDE);
+        writer.write_u64(0x0102_0304_0506_0708);
+        writer.write_bytes(&[1, 2, 3]);
+        let data = writer.into_bytes();
+
+        let mut reader = StateReader::new(&data);
+        assert_eq!(reader.read_u8(), Ok(0x12));
+        assert_eq!(reader.read_bool(), Ok(true));
+        assert_eq!(reader.read_u16(), Ok(0x3456));
+        assert_eq!(reader.read_u32(), Ok(0x789A_BCDE));
+        assert_eq!(reader.read_u64(), Ok(0x0102_0304_0506_0708));
+        assert_eq!(reader.read_bytes(), Ok(&[1u8, 2, 3][..]));
+        assert!(reader.is_empty());
+        assert!(reader.read_u8().is_err());
+    }
+
+    #[test]
+    fn test_read_into_checks_size() {
+        let mut writer = StateWriter::new();
+        writer.write_bytes(&[1, 2, 3]);
+        let data = writer.into_bytes();
+
+        let mut dest = [0u8; 2];
+        assert!(StateReader::new(&data).read_into(&mut dest).is_err());
+    }
+
+    #[test]
+    fn test_cpu_save_load() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xA9, 0x42, 0xAA, 0xA0, 0x07, 0x85, 0x10, 0x00]);
+        let state = save(&cpu);
+
+        let mut restored = CPU::new();
+        load(&mut restored, &state).unwrap();
+        assert_eq!(restored.register_a, 0x42);
+        assert_eq!(restored.register_x, 0x42);
+        assert_eq!(restored.register_y, 0x07);
+        assert_eq!(restored.program_counter, cpu.program_counter);
+        assert_eq!(restored.status.bits(), cpu.status.bits());
+        assert_eq!(restored.mem_read(0x10), 0x42);
+    }
+
+    #[test]
+    fn test_save_to_file_fires_state_hook() {
+        use std::sync::{Arc, Mutex};
+
+        let path = std::env::temp_dir().join(format!("rustynes-state-{}.st", std::process::id()));
+        let seen: Arc<Mutex<Vec<u8>>> = Arc::new(Mutex::new(Vec::new()));
+        let mut hooks = SyncHooks::new();
+        let log = Arc::clone(&seen);
+        hooks.set_on_state(Box::new(move |event| {
+            *log.lock().unwrap() = event.payload.to_vec();
+        }));
+
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xA9, 0x33, 0x00]);
+        save_to_file(&cpu, &path, &hooks).unwrap();
+        assert_eq!(*seen.lock().unwrap(), save(&cpu));
+
+        let mut restored = CPU::new();
+        load_from_file(&mut restored, &path).unwrap();
+        assert_eq!(restored.register_a, 0x33);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_load_rejects_bad_data() {
+        let mut cpu = CPU::new();
+        assert!(load(&mut cpu, b"nope").is_err());
+
+        let mut state = save(&cpu);
+        state[4] = VERSION + 1;
+        assert!(load(&mut cpu, &state).is_err());
+
+        let mut state = save(&cpu);
+        state.truncate(state.len() - 1);
+        assert!(load(&mut cpu, &state).is_err());
+    }
+
+    #[test]
+    fn test_failed_load_changes_nothing() {
+        let mut nes = crate::nes::test::test_nes();
+        nes.run_frame();
+        let state = save(&nes.cpu);
+        for _ in 0..3 {
+            nes.run_frame();
+        }
+        let now = save(&nes.cpu);
+
+        let truncated = &state[..state.len() / 2];
+        assert_eq!(load(&mut nes.cpu, truncated), Err(StateError::Truncated));
+        assert_eq!(save(&nes.cpu), now);
+
+        let mut trailing = state.clone();
+        trailing.push(0);
+        assert_eq!(load(&mut nes.cpu, &trailing), Err(StateError::TrailingData));
+        assert_eq!(save(&nes.cpu), now);
+        assert_eq!(nes.frame_count(), 4);
+
+        load(&mut nes.cpu, &state).unwrap();
+        assert_eq!(nes.frame_count(), 1);
+    }
+}
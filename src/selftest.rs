@@ -0,0 +1,375 @@
+use crate::asm;
+use crate::cpu::{Mem, CPU};
+use crate::runner;
+use std::fmt;
+use std::panic;
+
+/* CPU self test
+
+   Each case is a small program assembled with the mini-assembler, run from
+   reset until BRK, then checked against expected registers/flags/memory and
+   the number of cycles it took (the terminating BRK is not counted).
+*/
+
+struct Case {
+    name: &'static str,
+    source: &'static str,
+    cycles: u64,
+    check: fn(&mut CPU) -> Result<(), String>,
+}
+
+#[derive(Debug)]
+pub struct CaseResult {
+    pub name: &'static str,
+    pub cycles: u64,
+    pub outcome: Result<(), String>,
+}
+
+#[derive(Debug)]
+pub struct Report {
+    pub results: Vec<CaseResult>,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        return self.results.iter().all(|r| r.outcome.is_ok());
+    }
+
+    pub fn failures(&self) -> usize {
+        return self.results.iter().filter(|r| r.outcome.is_err()).count();
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for result in &self.results {
+            match &result.outcome {
+                Ok(()) => writeln!(f, "PASS {} ({} cycles)", result.name, result.cycles)?,
+                Err(msg) => writeln!(f, "FAIL {}: {}", result.name, msg)?,
+            }
+        }
+        return writeln!(
+            f,
+            "{}/{} passed",
+            self.results.len() - self.failures(),
+            self.results.len()
+        );
+    }
+}
+
+fn expect<T: PartialEq + fmt::Debug>(what: &str, actual: T, expected: T) -> Result<(), String> {
+    if actual != expected {
+        return Err(format!("{} is {:?}, expected {:?}", what, actual, expected));
+    }
+    return Ok(());
+}
+
+const CASES: &[Case] = &[
+    Case {
+        name: "lda_immediate",
+        source: "LDA #$80",
+        cycles: 2,
+        check: |cpu| {
+            expect("A", cpu.register_a, 0x80)?;
+            expect("N", cpu.status.negative(), 1)?;
+            return expect("Z", cpu.status.zero(), 0);
+        },
+    },
+    Case {
+        name: "ldx_ldy_zero_flag",
+        source: "LDX #$00\nLDY #$01",
+        cycles: 4,
+        check: |cpu| {
+            expect("X", cpu.register_x, 0x00)?;
+            expect("Y", cpu.register_y, 0x01)?;
+            return expect("Z", cpu.status.zero(), 0);
+        },
+    },
+    Case {
+        name: "sta_zero_page",
+        source: "LDA #$42\nSTA $10\nLDX $10",
+        cycles: 8,
+        check: |cpu| {
+            expect("[$10]", cpu.mem_read(0x10), 0x42)?;
+            return expect("X", cpu.register_x, 0x42);
+        },
+    },
+    Case {
+        name: "stx_sty_absolute",
+        source: "LDX #$11\nLDY #$22\nSTX $0300\nSTY $0301",
+        cycles: 12,
+        check: |cpu| {
+            expect("[$0300]", cpu.mem_read(0x0300), 0x11)?;
+            return expect("[$0301]", cpu.mem_read(0x0301), 0x22);
+        },
+    },
+    Case {
+        name: "lda_zero_page_x_wraps",
+        source: "LDA #$37\nSTA $04\nLDX #$05\nLDA #$00\nLDA $FF,X",
+        cycles: 13,
+        check: |cpu| expect("A", cpu.register_a, 0x37),
+    },
+    Case {
+        name: "lda_absolute_x_page_cross",
+        source: "LDA #$99\nSTA $0310\nLDX #$20\nLDA $02F0,X",
+        cycles: 13,
+        check: |cpu| expect("A", cpu.register_a, 0x99),
+    },
+    Case {
+        name: "lda_indirect_y",
+        source: "LDA #$00\nSTA $20\nLDA #$03\nSTA $21\nLDA #$5A\nSTA $0305\nLDY #$05\nLDA ($20),Y",
+        cycles: 23,
+        check: |cpu| expect("A", cpu.register_a, 0x5A),
+    },
+    Case {
+        name: "lda_indirect_x",
+        source: "LDA #$10\nSTA $24\nLDA #$03\nSTA $25\nLDA #$A5\nSTA $0310\nLDX #$04\nLDA ($20,X)",
+        cycles: 24,
+        check: |cpu| expect("A", cpu.register_a, 0xA5),
+    },
+    Case {
+        name: "transfers",
+        source: "LDA #$0A\nTAX\nTAY\nLDA #$00\nTXA",
+        cycles: 10,
+        check: |cpu| {
+            expect("A", cpu.register_a, 0x0A)?;
+            expect("X", cpu.register_x, 0x0A)?;
+            return expect("Y", cpu.register_y, 0x0A);
+        },
+    },
+    Case {
+        name: "txs_tsx",
+        source: "LDX #$80\nTXS\nLDX #$00\nTSX",
+        cycles: 8,
+        check: |cpu| {
+            expect("X", cpu.register_x, 0x80)?;
+            return expect("N", cpu.status.negative(), 1);
+        },
+    },
+    Case {
+        name: "inx_iny_wrap",
+        source: "LDX #$FF\nINX\nLDY #$7F\nINY",
+        cycles: 8,
+        check: |cpu| {
+            expect("X", cpu.register_x, 0x00)?;
+            expect("Y", cpu.register_y, 0x80)?;
+            return expect("N", cpu.status.negative(), 1);
+        },
+    },
+    Case {
+        name: "dex_dey",
+        source: "LDX #$01\nDEX\nDEY",
+        cycles: 6,
+        check: |cpu| {
+            expect("X", cpu.register_x, 0x00)?;
+            return expect("Y", cpu.register_y, 0xFF);
+        },
+    },
+    Case {
+        name: "inc_dec_memory",
+        source: "INC $30\nINC $30\nDEC $31",
+        cycles: 15,
+        check: |cpu| {
+            expect("[$30]", cpu.mem_read(0x30), 0x02)?;
+            return expect("[$31]", cpu.mem_read(0x31), 0xFF);
+        },
+    },
+    Case {
+        name: "logic",
+        source: "LDA #$F0\nAND #$3C\nORA #$01\nEOR #$FF",
+        cycles: 8,
+        check: |cpu| expect("A", cpu.register_a, 0xCE),
+    },
+    Case {
+        name: "asl_lsr_accumulator",
+        source: "LDA #$81\nASL A\nLSR A",
+        cycles: 6,
+        check: |cpu| {
+            expect("A", cpu.register_a, 0x01)?;
+            return expect("C", cpu.status.carry(), 0);
+        },
+    },
+    Case {
+        name: "asl_memory_carry",
+        source: "LDA #$C0\nSTA $40\nASL $40",
+        cycles: 10,
+        check: |cpu| {
+            expect("[$40]", cpu.mem_read(0x40), 0x80)?;
+            return expect("C", cpu.status.carry(), 1);
+        },
+    },
+    Case {
+        name: "rol_ror_through_carry",
+        source: "SEC\nLDA #$80\nROL A\nROR A",
+        cycles: 8,
+        check: |cpu| {
+            expect("A", cpu.register_a, 0x80)?;
+            return expect("C", cpu.status.carry(), 1);
+        },
+    },
+    Case {
+        name: "flags",
+        source: "SEC\nSED\nSEI\nCLD",
+        cycles: 8,
+        check: |cpu| {
+            expect("C", cpu.status.carry(), 1)?;
+            expect("D", cpu.status.decimal(), 0)?;
+            return expect("I", cpu.status.interrupt(), 1);
+        },
+    },
+    Case {
+        name: "cmp_equal",
+        source: "LDA #$40\nCMP #$40",
+        cycles: 4,
+        check: |cpu| {
+            expect("C", cpu.status.carry(), 1)?;
+            return expect("Z", cpu.status.zero(), 1);
+        },
+    },
+    Case {
+        name: "cpx_greater",
+        source: "LDX #$41\nCPX #$40",
+        cycles: 4,
+        check: |cpu| {
+            expect("C", cpu.status.carry(), 1)?;
+            return expect("Z", cpu.status.zero(), 0);
+        },
+    },
+    Case {
+        name: "jmp_absolute",
+        source: "JMP $8005\nLDA #$01\nLDX #$02",
+        cycles: 5,
+        check: |cpu| {
+            expect("A", cpu.register_a, 0x00)?;
+            return expect("X", cpu.register_x, 0x02);
+        },
+    },
+    Case {
+        name: "adc_overflow",
+        source: "LDA #$50\nADC #$50",
+        cycles: 4,
+        check: |cpu| {
+            expect("A", cpu.register_a, 0xA0)?;
+            expect("V", cpu.status.overflow(), 1)?;
+            return expect("C", cpu.status.carry(), 0);
+        },
+    },
+    Case {
+        name: "sbc_borrow",
+        source: "SEC\nLDA #$03\nSBC #$05",
+        cycles: 6,
+        check: |cpu| {
+            expect("A", cpu.register_a, 0xFE)?;
+            return expect("C", cpu.status.carry(), 0);
+        },
+    },
+    Case {
+        name: "branch_taken",
+        source: "LDX #$01\nBNE $02\nLDA #$01\nLDY #$07",
+        cycles: 7,
+        check: |cpu| {
+            expect("A", cpu.register_a, 0x00)?;
+            return expect("Y", cpu.register_y, 0x07);
+        },
+    },
+    Case {
+        name: "jsr_rts",
+        source: "JSR $8006\nLDX #$02\nBRK\nLDA #$05\nRTS",
+        cycles: 16,
+        check: |cpu| {
+            expect("A", cpu.register_a, 0x05)?;
+            expect("X", cpu.register_x, 0x02)?;
+            return expect("SP", cpu.stack.ptr(), 0xFF);
+        },
+    },
+    Case {
+        name: "pha_pla",
+        source: "LDA #$80\nPHA\nLDA #$00\nPLA",
+        cycles: 2 + 3 + 2 + 4,
+        check: |cpu| {
+            expect("A", cpu.register_a, 0x80)?;
+            return expect("N", cpu.status.negative(), 1);
+        },
+    },
+    Case {
+        name: "nop",
+        source: "NOP\nNOP",
+        cycles: 4,
+        // PC ends up just past the BRK
+        check: |cpu| expect("PC", cpu.program_counter, 0x8003),
+    },
+];
+
+pub fn run() -> Report {
+    let results = CASES.iter().map(run_case).collect();
+    return Report { results };
+}
+
+fn run_case(case: &Case) -> CaseResult {
+    let program = match asm::assemble(case.source) {
+        Ok(program) => program,
+        Err(e) => {
+            return CaseResult {
+                name: case.name,
+                cycles: 0,
+                outcome: Err(format!("does not assemble: {}", e)),
+            }
+        }
+    };
+
+    let executed = panic::catch_unwind(|| {
+        let mut cpu = CPU::new();
+        cpu.load(program);
+        cpu.reset();
+        cpu.run();
+        let outcome = (case.check)(&mut cpu);
+        return (cpu.cycles, outcome);
+    });
+
+    let (cycles, outcome) = match executed {
+        Ok(result) => result,
+        Err(payload) => (
+            0,
+            Err(format!("panicked: {}", runner::panic_message(payload))),
+        ),
+    };
+    let outcome = outcome.and_then(|_| expect("cycle count", cycles, case.cycles));
+
+    return CaseResult {
+        name: case.name,
+        cycles,
+        outcome,
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_all_cases_pass() {
+        let report = run();
+        assert!(report.passed(), "{}", report);
+        assert_eq!(report.results.len(), CASES.len());
+    }
+
+    #[test]
+    fn test_failing_case_is_reported() {
+        let case = Case {
+            name: "wrong",
+            source: "LDA #$01",
+            cycles: 3,
+            check: |cpu| expect("A", cpu.register_a, 0x02),
+        };
+        let result = run_case(&case);
+        assert_eq!(result.outcome, Err("A is 1, expected 2".to_string()));
+
+        let case = Case {
+            name: "bad_source",
+            source: "LDA (",
+            cycles: 0,
+            check: |_| Ok(()),
+        };
+        assert!(run_case(&case).outcome.is_err());
+    }
+}
@@ -28,6 +28,15 @@ impl Stack {
     pub fn set_ptr(&mut self, value: u8) {
         self._ptr = value;
     }
+
+    pub fn addr(&self) -> u16 {
+        // absolute address of the next free slot, always within the stack page
+        return self.top | self.ptr();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.addr() == self.bottom;
+    }
 }
 
 #[cfg(test)]
@@ -82,6 +91,20 @@ mod test {
         assert_eq!(s.ptr(), ((bottom) as u8).into());
     }
 
+    #[test]
+    fn test_addr() {
+        let bottom: u16 = 0x01FF;
+        let top: u16 = 0x0100;
+        let mut s = Stack::new(bottom, top);
+        assert_eq!(s.addr(), bottom);
+        assert!(s.is_empty());
+        s.incr_ptr();
+        assert_eq!(s.addr(), bottom - 1);
+        assert!(!s.is_empty());
+        s.set_ptr(0x00);
+        assert_eq!(s.addr(), top);
+    }
+
     #[test]
     fn test_ptr_wrapping() {
         let bottom: u16 = 0x01FF;
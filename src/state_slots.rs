@@ -0,0 +1,272 @@
+use crate::cartridge::Rom;
+use crate::cpu::CPU;
+use crate::hash;
+use crate::nes::Nes;
+use crate::savestate::{self, StateReader, StateWriter};
+use crate::sync::{self, SyncHooks, SyncKind};
+use crate::video::{png, Frame};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const SLOTS: u8 = 10;
+const MAGIC: &[u8; 4] = b"RNSL";
+const VERSION: u8 = 1;
+// NES pixels to a side of each thumbnail pixel, 128x120
+const THUMBNAIL_SHRINK: usize = 2;
+
+/* Save state slots
+
+   Ten numbered save states for each game, 0 to 9, kept together in a
+   directory named after the SHA-1 of the ROM, so a game keeps its states
+   wherever its file is moved to and under whatever name, and two games
+   can't load each other's. A slot file is "RNSL", a version byte, the
+   time it was saved in seconds since 1970, a PNG thumbnail of the picture
+   at the time, half size, then the save state itself (see `savestate`),
+   each block with its length first. Slot files are written through a
+   temporary file like battery saves, so a crash can't cost the state that
+   was there before.
+
+   Next to the slots, `resume.state` is the same kind of file, saved as
+   the game is left so the next session can pick up where this one ended.
+*/
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlotFile {
+    pub saved: u64, // seconds since 1970
+    pub thumbnail: Vec<u8>,
+    pub state: Vec<u8>,
+}
+
+impl SlotFile {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.write_u8(VERSION);
+        writer.write_u64(self.saved);
+        writer.write_bytes(&self.thumbnail);
+        writer.write_bytes(&self.state);
+        let mut out = MAGIC.to_vec();
+        out.extend(writer.into_bytes());
+        return out;
+    }
+
+    pub fn decode(data: &[u8]) -> Result<SlotFile, String> {
+        let Some(data) = data.strip_prefix(&MAGIC[..]) else {
+            return Err("not a rustynes state slot".to_string());
+        };
+        let mut reader = StateReader::new(data);
+        let version = reader.read_u8()?;
+        if version != VERSION {
+            return Err(format!("unsupported state slot version {}", version));
+        }
+        return Ok(SlotFile {
+            saved: reader.read_u64()?,
+            thumbnail: reader.read_bytes()?.to_vec(),
+            state: reader.read_bytes()?.to_vec(),
+        });
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StateSlots {
+    dir: PathBuf, // every game's
+    game: String, // the ROM's SHA-1, its directory's name
+    pub selected: u8,
+}
+
+impl StateSlots {
+    pub fn new(dir: PathBuf, rom: &Rom) -> Self {
+        return Self {
+            dir,
+            game: hash::to_hex(&rom.sha1()),
+            selected: 0,
+        };
+    }
+
+    // another game's slots, starting from slot 0 again
+    pub fn set_rom(&mut self, rom: &Rom) {
+        self.game = hash::to_hex(&rom.sha1());
+        self.selected = 0;
+    }
+
+    pub fn path(&self, slot: u8) -> PathBuf {
+        return self
+            .dir
+            .join(&self.game)
+            .join(format!("slot{}.state", slot));
+    }
+
+    pub fn resume_path(&self) -> PathBuf {
+        return self.dir.join(&self.game).join("resume.state");
+    }
+
+    // the next slot up, back to 0 after 9
+    pub fn select_next(&mut self) -> u8 {
+        self.selected = (self.selected + 1) % SLOTS;
+        return self.selected;
+    }
+
+    pub fn save(
+        &self,
+        slot: u8,
+        nes: &Nes,
+        now: SystemTime,
+        hooks: &SyncHooks,
+    ) -> Result<(), String> {
+        return save_to(&self.path(slot), nes, now, hooks);
+    }
+
+    // None when nothing was ever saved there
+    pub fn read(&self, slot: u8) -> Result<Option<SlotFile>, String> {
+        return read_from(&self.path(slot));
+    }
+
+    // the console as the slot has it, returning when that was saved
+    pub fn load(&self, slot: u8, cpu: &mut CPU) -> Result<u64, String> {
+        let Some(file) = self.read(slot)? else {
+            return Err(format!("State {} is empty", slot));
+        };
+        savestate::load(cpu, &file.state)?;
+        return Ok(file.saved);
+    }
+
+    pub fn save_resume(&self, nes: &Nes, now: SystemTime, hooks: &SyncHooks) -> Result<(), String> {
+        return save_to(&self.resume_path(), nes, now, hooks);
+    }
+
+    // None when the game was never left running
+    pub fn read_resume(&self) -> Result<Option<SlotFile>, String> {
+        return read_from(&self.resume_path());
+    }
+}
+
+fn save_to(path: &Path, nes: &Nes, now: SystemTime, hooks: &SyncHooks) -> Result<(), String> {
+    let file = SlotFile {
+        saved: now
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs()),
+        thumbnail: png::encode(&thumbnail(nes.frame())),
+        state: savestate::save(&nes.cpu),
+    };
+    let write = |path: &Path| {
+        fs::create_dir_all(path.parent().unwrap_or(Path::new(".")))?;
+        return sync::write_and_notify(SyncKind::State, path, &file.encode(), hooks);
+    };
+    return write(path).map_err(|e| format!("{}: {}", path.display(), e));
+}
+
+fn read_from(path: &Path) -> Result<Option<SlotFile>, String> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("{}: {}", path.display(), e)),
+    };
+    let file = SlotFile::decode(&data).map_err(|e| format!("{}: {}", path.display(), e))?;
+    return Ok(Some(file));
+}
+
+// `frame` at half size, each pixel the average of the four it covers
+pub fn thumbnail(frame: &Frame) -> Frame {
+    let n = THUMBNAIL_SHRINK;
+    let mut out = Frame::new(frame.width / n, frame.height / n);
+    for y in 0..out.height {
+        for (x, pixel) in out.row_mut(y).chunks_mut(4).enumerate() {
+            for (channel, value) in pixel.iter_mut().enumerate() {
+                let sum: usize = (0..n * n)
+                    .map(|i| frame.pixel(x * n + i % n, y * n + i / n)[channel] as usize)
+                    .sum();
+                *value = (sum / (n * n)) as u8;
+            }
+        }
+    }
+    return out;
+}
+
+// seconds since 1970 as a UTC date and time, `2026-10-14 09:30 UTC`
+pub fn timestamp(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let minutes = secs % 86_400 / 60;
+    // from days to a civil date, by Howard Hinnant's algorithm
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    return format!(
+        "{:04}-{:02}-{:02} {:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        minutes / 60,
+        minutes % 60
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+    use std::env;
+    use std::time::Duration;
+
+    #[test]
+    fn test_slots_by_rom() {
+        let dir = env::temp_dir().join(format!("rustynes-slots-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let rom = Rom::new(&test_rom(0, 1, 1)).unwrap();
+        let mut nes = Nes::new(rom.clone()).unwrap();
+        let slots = StateSlots::new(dir.clone(), &rom);
+        assert_eq!(slots.read(3), Ok(None));
+        assert_eq!(slots.load(3, &mut nes.cpu).unwrap_err(), "State 3 is empty");
+
+        nes.cpu.bus.ram_mut()[0x10] = 0x42;
+        let saved = UNIX_EPOCH + Duration::from_secs(1_791_969_000);
+        slots.save(3, &nes, saved, &SyncHooks::new()).unwrap();
+        nes.cpu.bus.ram_mut()[0x10] = 0;
+        assert_eq!(slots.load(3, &mut nes.cpu), Ok(1_791_969_000));
+        assert_eq!(nes.cpu.bus.ram()[0x10], 0x42);
+        let file = slots.read(3).unwrap().unwrap();
+        assert_eq!(&file.thumbnail[1..4], b"PNG");
+        assert_eq!(SlotFile::decode(&file.encode()), Ok(file));
+        assert!(SlotFile::decode(b"RNES").is_err());
+
+        // the resume state sits beside the slots without taking one
+        assert_eq!(slots.read_resume(), Ok(None));
+        slots.save_resume(&nes, saved, &SyncHooks::new()).unwrap();
+        assert_eq!(slots.read_resume().unwrap().unwrap().saved, 1_791_969_000);
+        assert_eq!(slots.read(4), Ok(None));
+
+        // another game has slots of its own
+        let mut raw = test_rom(0, 1, 1);
+        raw[16] = 0xEA;
+        let mut other = slots.clone();
+        other.set_rom(&Rom::new(&raw).unwrap());
+        assert_ne!(other.path(3), slots.path(3));
+        assert_eq!(other.read(3), Ok(None));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_thumbnails_and_timestamps() {
+        let mut frame = Frame::new(4, 2);
+        frame.row_mut(0)[..8].copy_from_slice(&[200, 0, 0, 255, 100, 40, 0, 255]);
+        let small = thumbnail(&frame);
+        assert_eq!((small.width, small.height), (2, 1));
+        assert_eq!(small.pixel(0, 0), [75, 10, 0, 255]);
+        assert_eq!(small.pixel(1, 0), [0, 0, 0, 255]);
+
+        assert_eq!(timestamp(0), "1970-01-01 00:00 UTC");
+        assert_eq!(timestamp(951_825_600), "2000-02-29 12:00 UTC");
+        assert_eq!(timestamp(1_791_969_000), "2026-10-14 09:10 UTC");
+    }
+}
@@ -0,0 +1,194 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+/* Save sync hooks
+
+   The core only ever writes saves to the local filesystem. Frontends that want
+   to mirror them somewhere else (a user's own storage, a sync daemon, ...) can
+   register a callback per kind; it runs after the file has been written and
+   receives the exact bytes that landed on disk.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncKind {
+    SaveRam,
+    State,
+}
+
+#[derive(Debug)]
+pub struct SyncEvent<'a> {
+    pub kind: SyncKind,
+    pub path: &'a Path,
+    pub payload: &'a [u8],
+}
+
+pub type SyncCallback = Box<dyn Fn(&SyncEvent) + Send + Sync>;
+
+#[derive(Default)]
+pub struct SyncHooks {
+    on_save_ram: Option<SyncCallback>,
+    on_state: Option<SyncCallback>,
+}
+
+impl SyncHooks {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    pub fn set_on_save_ram(&mut self, callback: SyncCallback) {
+        self.on_save_ram = Some(callback);
+    }
+
+    pub fn set_on_state(&mut self, callback: SyncCallback) {
+        self.on_state = Some(callback);
+    }
+
+    pub fn clear(&mut self) {
+        self.on_save_ram = None;
+        self.on_state = None;
+    }
+
+    pub fn notify(&self, event: &SyncEvent) {
+        let callback = match event.kind {
+            SyncKind::SaveRam => &self.on_save_ram,
+            SyncKind::State => &self.on_state,
+        };
+        if let Some(callback) = callback {
+            callback(event);
+        }
+    }
+}
+
+impl std::fmt::Debug for SyncHooks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return f
+            .debug_struct("SyncHooks")
+            .field("on_save_ram", &self.on_save_ram.is_some())
+            .field("on_state", &self.on_state.is_some())
+            .finish();
+    }
+}
+
+// writes next to the destination and renames over it, so a crash mid-write
+// leaves the previous save intact instead of a truncated one
+pub fn write_atomic(path: &Path, payload: &[u8]) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = Path::new(&tmp);
+
+    let mut file = fs::File::create(tmp)?;
+    let written = file.write_all(payload).and_then(|_| file.sync_all());
+    drop(file);
+    if let Err(e) = written.and_then(|_| fs::rename(tmp, path)) {
+        let _ = fs::remove_file(tmp);
+        return Err(e);
+    }
+    return Ok(());
+}
+
+// writes the payload and, only once that succeeded, hands it to the matching hook
+pub fn write_and_notify(
+    kind: SyncKind,
+    path: &Path,
+    payload: &[u8],
+    hooks: &SyncHooks,
+) -> io::Result<()> {
+    write_atomic(path, payload)?;
+    hooks.notify(&SyncEvent {
+        kind,
+        path,
+        payload,
+    });
+    return Ok(());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::sync::{Arc, Mutex};
+
+    type EventLog = Arc<Mutex<Vec<(SyncKind, Vec<u8>)>>>;
+
+    #[test]
+    fn test_notify_routes_by_kind() {
+        let seen: EventLog = Arc::new(Mutex::new(Vec::new()));
+        let mut hooks = SyncHooks::new();
+
+        let log = Arc::clone(&seen);
+        hooks.set_on_state(Box::new(move |event| {
+            log.lock()
+                .unwrap()
+                .push((event.kind, event.payload.to_vec()));
+        }));
+
+        let path = Path::new("unused");
+        hooks.notify(&SyncEvent {
+            kind: SyncKind::State,
+            path,
+            payload: &[1, 2],
+        });
+        // no save ram hook registered, nothing should be recorded
+        hooks.notify(&SyncEvent {
+            kind: SyncKind::SaveRam,
+            path,
+            payload: &[3],
+        });
+
+        assert_eq!(*seen.lock().unwrap(), vec![(SyncKind::State, vec![1, 2])]);
+
+        hooks.clear();
+        hooks.notify(&SyncEvent {
+            kind: SyncKind::State,
+            path,
+            payload: &[4],
+        });
+        assert_eq!(seen.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_write_and_notify() {
+        let path = env::temp_dir().join(format!("rustynes-sync-{}.sav", std::process::id()));
+        let seen: Arc<Mutex<Option<Vec<u8>>>> = Arc::new(Mutex::new(None));
+        let mut hooks = SyncHooks::new();
+
+        let log = Arc::clone(&seen);
+        hooks.set_on_save_ram(Box::new(move |event| {
+            *log.lock().unwrap() = Some(event.payload.to_vec());
+        }));
+
+        write_and_notify(SyncKind::SaveRam, &path, &[9, 8, 7], &hooks).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), vec![9, 8, 7]);
+        assert_eq!(*seen.lock().unwrap(), Some(vec![9, 8, 7]));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_write_atomic_replaces_file() {
+        let path = env::temp_dir().join(format!("rustynes-atomic-{}.sav", std::process::id()));
+        fs::write(&path, [1, 1, 1, 1]).unwrap();
+        write_atomic(&path, &[2, 2]).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), vec![2, 2]);
+
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        assert!(!Path::new(&tmp).exists());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_failed_write_does_not_notify() {
+        let path = env::temp_dir().join("rustynes-missing-dir").join("x.sav");
+        let called = Arc::new(Mutex::new(false));
+        let mut hooks = SyncHooks::new();
+
+        let flag = Arc::clone(&called);
+        hooks.set_on_save_ram(Box::new(move |_| {
+            *flag.lock().unwrap() = true;
+        }));
+
+        assert!(write_and_notify(SyncKind::SaveRam, &path, &[1], &hooks).is_err());
+        assert!(!*called.lock().unwrap());
+    }
+}
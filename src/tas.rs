@@ -0,0 +1,414 @@
+use crate::hash;
+use crate::input::PLAYERS;
+use crate::movie::{Movie, MovieEvent, MovieFrame, CHECKSUM_INTERVAL};
+use crate::nes::Nes;
+use crate::savestate;
+use std::collections::BTreeMap;
+
+// the last frames run keep a state each
+const DENSE_FRAMES: usize = 180;
+// further back, a state every this many frames
+const GREENZONE_INTERVAL: usize = 60;
+// the piano roll's button columns, bit 7 first: Right, Left, Down, Up,
+// sTart, Select, B, A
+const BUTTON_LETTERS: [char; 8] = ['R', 'L', 'D', 'U', 'T', 'S', 'B', 'A'];
+
+/* TAS editing
+
+   A movie being made a frame at a time, the way tool-assisted speedruns
+   are: the console runs the movie's input, and the input can be changed
+   anywhere along it, then played from there. To get back to a frame
+   quickly the editor keeps save states of the console at the start of
+   the frames it has run, the greenzone: every frame for the last
+   `DENSE_FRAMES` and one every `GREENZONE_INTERVAL` before that. Seeking
+   to a frame loads the nearest state before it and runs forward, so it
+   lands exactly where playing the movie from the start would have, with
+   the picture of the frame before on the screen.
+
+   Changing a frame's input makes every state after it wrong, so they go,
+   and the console, if it was past the change, runs up to where it was
+   again with the new input. A change that throws away states, and so
+   history the console had already been through, counts as a rerecord,
+   which the movie keeps, and so do the checksums the movie keeps of
+   the frames after it (see `movie`), taken again as they're run. With
+   `recording` on, advancing past a frame writes the live input into it
+   instead of playing what was there, and past the end of the movie
+   every frame does.
+
+   The piano roll is the movie as rows of text, a frame to a row: the
+   frame number, `*` for a frame with a greenzone state, the buttons each
+   player holds as letters (`R.D....A` is Right, Down and A) and a reset
+   or power cycle before the frame, with `>` at the frame about to run.
+*/
+
+pub struct TasEditor {
+    movie: Movie,
+    greenzone: BTreeMap<usize, Vec<u8>>, // the console at the start of frame n
+    frame: usize,                        // the next to run
+    dense: usize,                        // `DENSE_FRAMES`, fewer in tests
+    interval: usize,
+    pub recording: bool,
+    pub paused: bool,
+}
+
+impl TasEditor {
+    // the console at the start of `movie`, paused on its first frame
+    pub fn new(nes: &mut Nes, movie: Movie) -> Result<Self, String> {
+        nes.stop();
+        savestate::load(&mut nes.cpu, &movie.start)?;
+        return Ok(Self {
+            greenzone: BTreeMap::from([(0, movie.start.clone())]),
+            movie,
+            frame: 0,
+            dense: DENSE_FRAMES,
+            interval: GREENZONE_INTERVAL,
+            recording: false,
+            paused: true,
+        });
+    }
+
+    pub fn movie(&self) -> &Movie {
+        return &self.movie;
+    }
+
+    pub fn frame(&self) -> usize {
+        return self.frame;
+    }
+
+    pub fn len(&self) -> usize {
+        return self.movie.frames.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.movie.frames.is_empty();
+    }
+
+    pub fn rerecords(&self) -> u32 {
+        return self.movie.rerecords;
+    }
+
+    pub fn is_green(&self, frame: usize) -> bool {
+        return self.greenzone.contains_key(&frame);
+    }
+
+    // the next frame, from the movie or, recording or past its end, with
+    // `live` as the players' buttons
+    pub fn advance(&mut self, nes: &mut Nes, live: [u8; PLAYERS]) {
+        if self.recording || self.frame == self.len() {
+            match self.movie.frames.get_mut(self.frame) {
+                Some(frame) if frame.buttons != live => {
+                    frame.buttons = live;
+                    self.invalidate(self.frame);
+                }
+                Some(_) => {}
+                None => self.movie.frames.push(MovieFrame {
+                    buttons: live,
+                    event: None,
+                }),
+            }
+        }
+        self.run(nes);
+    }
+
+    // frame-exact, to just before `target` runs
+    pub fn seek(&mut self, nes: &mut Nes, target: usize) -> Result<(), String> {
+        if target > self.len() {
+            return Err(format!("the movie has {} frames", self.len()));
+        }
+        let (&start, state) = self
+            .greenzone
+            .range(..target.max(1))
+            .next_back()
+            .expect("the start is always kept");
+        savestate::load(&mut nes.cpu, state)?;
+        self.frame = start;
+        while self.frame < target {
+            self.run(nes);
+        }
+        return Ok(());
+    }
+
+    pub fn set_buttons(
+        &mut self,
+        nes: &mut Nes,
+        frame: usize,
+        player: usize,
+        buttons: u8,
+    ) -> Result<(), String> {
+        check_player(player)?;
+        let len = self.len();
+        let Some(input) = self.movie.frames.get_mut(frame) else {
+            return Err(format!(
+                "frame {} is past the end, the movie has {}",
+                frame, len
+            ));
+        };
+        if input.buttons[player] == buttons {
+            return Ok(());
+        }
+        input.buttons[player] = buttons;
+        return self.changed(nes, frame);
+    }
+
+    // a button pressed on `frame` if it wasn't, released if it was
+    pub fn toggle(
+        &mut self,
+        nes: &mut Nes,
+        frame: usize,
+        player: usize,
+        bit: u8,
+    ) -> Result<(), String> {
+        check_player(player)?;
+        let buttons = self
+            .movie
+            .frames
+            .get(frame)
+            .map_or(0, |f| f.buttons[player]);
+        return self.set_buttons(nes, frame, player, buttons ^ bit);
+    }
+
+    pub fn set_event(
+        &mut self,
+        nes: &mut Nes,
+        frame: usize,
+        event: Option<MovieEvent>,
+    ) -> Result<(), String> {
+        let len = self.len();
+        let Some(input) = self.movie.frames.get_mut(frame) else {
+            return Err(format!(
+                "frame {} is past the end, the movie has {}",
+                frame, len
+            ));
+        };
+        if input.event == event {
+            return Ok(());
+        }
+        input.event = event;
+        return self.changed(nes, frame);
+    }
+
+    // `count` frames of no input before `at`, moving the rest along
+    pub fn insert(&mut self, nes: &mut Nes, at: usize, count: usize) -> Result<(), String> {
+        if at > self.len() {
+            return Err(format!(
+                "frame {} is past the end, the movie has {}",
+                at,
+                self.len()
+            ));
+        }
+        let blank = std::iter::repeat_n(MovieFrame::default(), count);
+        self.movie.frames.splice(at..at, blank);
+        return self.changed(nes, at);
+    }
+
+    pub fn delete(&mut self, nes: &mut Nes, at: usize, count: usize) -> Result<(), String> {
+        if at >= self.len() {
+            return Err(format!(
+                "frame {} is past the end, the movie has {}",
+                at,
+                self.len()
+            ));
+        }
+        let end = (at + count).min(self.len());
+        self.movie.frames.drain(at..end);
+        return self.changed(nes, at);
+    }
+
+    // `rows` rows of the piano roll from frame `from`, as far as the movie goes
+    pub fn piano_roll(&self, from: usize, rows: usize) -> Vec<String> {
+        let players = (1..=PLAYERS)
+            .rev()
+            .find(|&n| self.movie.frames.iter().any(|f| f.buttons[n - 1] != 0))
+            .unwrap_or(1);
+        let end = (from + rows).min(self.len() + 1);
+        return (from..end)
+            .map(|n| {
+                let cursor = if n == self.frame { '>' } else { ' ' };
+                let green = if self.is_green(n) { '*' } else { ' ' };
+                let mut line = format!("{}{:>6}{}", cursor, n, green);
+                let Some(frame) = self.movie.frames.get(n) else {
+                    return line + " end";
+                };
+                for &buttons in &frame.buttons[..players] {
+                    line.push(' ');
+                    line.extend(BUTTON_LETTERS.iter().enumerate().map(|(i, &letter)| {
+                        if buttons & (0x80 >> i) != 0 {
+                            letter
+                        } else {
+                            '.'
+                        }
+                    }));
+                }
+                match frame.event {
+                    Some(MovieEvent::Reset) => line += " reset",
+                    Some(MovieEvent::Power) => line += " power",
+                    None => {}
+                }
+                return line;
+            })
+            .collect();
+    }
+
+    // runs the frame at the cursor with the movie's input for it
+    fn run(&mut self, nes: &mut Nes) {
+        let input = self
+            .movie
+            .frames
+            .get(self.frame)
+            .copied()
+            .unwrap_or_default();
+        nes.run_input_frame(input);
+        self.frame += 1;
+        let state = savestate::save(&nes.cpu);
+        if self.frame.is_multiple_of(CHECKSUM_INTERVAL) {
+            self.movie.checksums.insert(self.frame, hash::crc32(&state));
+        }
+        self.greenzone.insert(self.frame, state);
+        if let Some(old) = self.frame.checked_sub(self.dense) {
+            if old % self.interval != 0 {
+                self.greenzone.remove(&old);
+            }
+        }
+    }
+
+    // after the input of `frame` changed, back to where the console was
+    // by way of the new input
+    fn changed(&mut self, nes: &mut Nes, frame: usize) -> Result<(), String> {
+        self.invalidate(frame);
+        if self.frame > frame {
+            return self.seek(nes, self.frame.min(self.len()));
+        }
+        return Ok(());
+    }
+
+    // drops the states and checksums after `frame`, which ran with its
+    // old input
+    fn invalidate(&mut self, frame: usize) {
+        self.movie.checksums.split_off(&(frame + 1));
+        let stale = self.greenzone.split_off(&(frame + 1));
+        if !stale.is_empty() {
+            self.movie.rerecords += 1;
+        }
+    }
+}
+
+// players are counted from 0 here and from 1 in the piano roll
+fn check_player(player: usize) -> Result<(), String> {
+    if player >= PLAYERS {
+        return Err(format!(
+            "there is no player {}, the console has {}",
+            player + 1,
+            PLAYERS
+        ));
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::Mem;
+    use crate::input::joypad::Button;
+    use crate::nes::test::test_nes;
+
+    // $10 is the count of reads of A across the run, a few a frame
+    fn a_count(nes: &mut Nes) -> u8 {
+        return nes.cpu.mem_read(0x10);
+    }
+
+    #[test]
+    fn test_edit_and_seek() {
+        let mut nes = test_nes();
+        let mut movie = Movie::new(savestate::save(&nes.cpu));
+        movie.frames = vec![MovieFrame::default(); 40];
+        let mut tas = TasEditor::new(&mut nes, movie).unwrap();
+        (tas.dense, tas.interval) = (10, 4);
+        let a = Button::A.bit();
+
+        // where each frame starts, played straight through
+        let mut played = Vec::new();
+        for _ in 0..40 {
+            played.push(savestate::save(&nes.cpu));
+            tas.advance(&mut nes, [0; PLAYERS]);
+        }
+        assert_eq!(a_count(&mut nes), 0);
+        assert!(tas.is_green(31) && !tas.is_green(30) && tas.is_green(28));
+        for target in [35, 13, 7, 0] {
+            tas.seek(&mut nes, target).unwrap();
+            assert_eq!(tas.frame(), target);
+            assert!(savestate::save(&nes.cpu) == played[target]);
+        }
+        assert!(tas.seek(&mut nes, 41).is_err());
+
+        // A held on frame 10, from frame 30: the states after it go and
+        // the console runs up to 30 again, pressing it
+        tas.seek(&mut nes, 30).unwrap();
+        tas.toggle(&mut nes, 10, 0, a).unwrap();
+        assert_eq!(tas.frame(), 30);
+        assert_eq!(tas.rerecords(), 1);
+        let pressed = a_count(&mut nes);
+        assert_ne!(pressed, 0);
+        tas.seek(&mut nes, 10).unwrap();
+        assert_eq!(a_count(&mut nes), 0);
+        // the states ahead of the console were run with the old input too
+        tas.set_buttons(&mut nes, 15, 1, a).unwrap();
+        assert_eq!(tas.rerecords(), 2);
+        tas.set_buttons(&mut nes, 15, 1, a).unwrap();
+        assert_eq!(tas.rerecords(), 2);
+
+        tas.insert(&mut nes, 0, 2).unwrap();
+        assert_eq!(tas.len(), 42);
+        assert_eq!(tas.movie().frames[12].buttons[0], a);
+        tas.delete(&mut nes, 0, 2).unwrap();
+        tas.seek(&mut nes, 30).unwrap();
+        assert_eq!(a_count(&mut nes), pressed);
+        assert!(tas.set_buttons(&mut nes, 40, 0, a).is_err());
+        assert!(tas.set_buttons(&mut nes, 5, PLAYERS, a).is_err());
+        assert!(tas.toggle(&mut nes, 5, PLAYERS, a).is_err());
+        assert_eq!(tas.rerecords(), 4);
+
+        assert_eq!(
+            tas.piano_roll(8, 3),
+            vec![
+                "      8* ........ ........",
+                "      9  ........ ........",
+                "     10  .......A ........",
+            ]
+        );
+        tas.set_event(&mut nes, 11, Some(MovieEvent::Reset))
+            .unwrap();
+        assert_eq!(
+            tas.piano_roll(11, 1),
+            vec!["     11  ........ ........ reset"]
+        );
+        assert_eq!(tas.piano_roll(30, 1)[0], ">    30* ........ ........");
+        assert_eq!(tas.piano_roll(39, 5).last().unwrap(), "     40  end");
+    }
+
+    #[test]
+    fn test_recording_over_the_movie() {
+        let mut nes = test_nes();
+        let movie = Movie::new(savestate::save(&nes.cpu));
+        let mut tas = TasEditor::new(&mut nes, movie).unwrap();
+        let a = Button::A.bit();
+        for _ in 0..10 {
+            tas.advance(&mut nes, [0; PLAYERS]);
+        }
+        assert_eq!(tas.len(), 10);
+
+        // playing back ignores the live input, recording takes it
+        tas.seek(&mut nes, 5).unwrap();
+        tas.advance(&mut nes, [a, 0, 0, 0]);
+        assert_eq!(tas.movie().frames[5].buttons[0], 0);
+        tas.recording = true;
+        tas.advance(&mut nes, [a, 0, 0, 0]);
+        assert_eq!(tas.movie().frames[6].buttons[0], a);
+        assert_eq!(tas.rerecords(), 1);
+        assert!(!tas.is_green(8));
+        // the branch it's on now counts once
+        tas.advance(&mut nes, [a, 0, 0, 0]);
+        assert_eq!(tas.rerecords(), 1);
+        assert_eq!(tas.frame(), 8);
+        assert_eq!(tas.len(), 10);
+    }
+}
@@ -0,0 +1,165 @@
+use crate::cpu::CPU;
+use crate::debugger::disassemble_bytes;
+use std::collections::VecDeque;
+use std::fmt;
+
+/* Instruction trace
+
+   A line for the instruction about to run and the state it runs in, laid
+   out like nestest.log, the log that came with the nestest ROM and that
+   every emulator's CPU gets checked against:
+
+    C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+
+   The PPU position is the scanline and dot, CYC the CPU cycles since power
+   on. nestest.log also shows the byte behind memory operands, `STX $00 =
+   00`, which the disassembly here leaves out, so comparisons go by the
+   address, bytes and registers rather than the text.
+
+   Tracing or not, the CPU keeps its last instructions in a `History`,
+   `--trace-history N` of them (256 by default, 0 for none). They are kept
+   as the state they ran in and only made into lines when looked at, which
+   is what a `crash` report shows leading up to the crash.
+*/
+
+// where the registers start, as in nestest.log
+const STATE_COLUMN: usize = 48;
+pub const DEFAULT_HISTORY: usize = 256;
+
+// an instruction about to run, and everything its trace line needs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry {
+    pub pc: u16,
+    pub bytes: [u8; 3],     // as many as the instruction takes count
+    pub registers: [u8; 5], // A, X, Y, P and SP
+    pub scanline: u16,
+    pub dot: u16,
+    pub cycles: u64,
+}
+
+impl Entry {
+    pub fn capture(cpu: &CPU) -> Self {
+        let pc = cpu.program_counter;
+        return Self {
+            pc,
+            bytes: [0, 1, 2].map(|i| cpu.bus.peek(pc.wrapping_add(i))),
+            registers: [
+                cpu.register_a,
+                cpu.register_x,
+                cpu.register_y,
+                cpu.status.bits(),
+                cpu.stack.ptr() as u8,
+            ],
+            scanline: cpu.bus.ppu.scanline(),
+            dot: cpu.bus.ppu.dot(),
+            cycles: cpu.cycles,
+        };
+    }
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (code, _) = disassemble_bytes(self.pc, self.bytes);
+        let [a, x, y, p, sp] = self.registers;
+        return write!(
+            f,
+            "{:<width$}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+            code,
+            a,
+            x,
+            y,
+            p,
+            sp,
+            self.scanline,
+            self.dot,
+            self.cycles,
+            width = STATE_COLUMN
+        );
+    }
+}
+
+pub fn line(cpu: &CPU) -> String {
+    return Entry::capture(cpu).to_string();
+}
+
+// the last `capacity` instructions, oldest first
+#[derive(Debug, Clone)]
+pub struct History {
+    entries: VecDeque<Entry>,
+    capacity: usize,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        return Self::new(DEFAULT_HISTORY);
+    }
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        return Self {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        };
+    }
+
+    pub fn capacity(&self) -> usize {
+        return self.capacity;
+    }
+
+    // nothing is kept at a capacity of 0, so don't capture for it
+    pub fn record(&mut self, entry: Entry) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &Entry> {
+        return self.entries.iter();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::Mem;
+
+    #[test]
+    fn test_nestest_layout() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0xC000, 0x4C);
+        cpu.mem_write(0xC001, 0xF5);
+        cpu.mem_write(0xC002, 0xC5);
+        cpu.program_counter = 0xC000;
+        cpu.status.set_bits(0x24);
+        cpu.stack.set_ptr(0xFD);
+        cpu.cycles = 7;
+        assert_eq!(
+            line(&cpu),
+            "C000  4C F5 C5  JMP $C5F5                       \
+             A:00 X:00 Y:00 P:24 SP:FD PPU:  0,  0 CYC:7"
+        );
+    }
+
+    #[test]
+    fn test_history_keeps_the_last_instructions() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0xE8, 0xE8, 0xE8, 0x00]); // INX three times
+        cpu.reset();
+        cpu.history = History::new(2);
+        cpu.run();
+        let lines: Vec<String> = cpu.history.entries().map(|e| e.to_string()).collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("8002  E8        INX"));
+        assert!(lines[0].contains(" X:02 "));
+        assert!(lines[1].starts_with("8003  00        BRK"));
+
+        cpu.history = History::new(0);
+        cpu.step();
+        assert_eq!(cpu.history.entries().count(), 0);
+    }
+}
@@ -0,0 +1,312 @@
+use crate::cartridge::Region;
+use std::path::Path;
+use std::time::Duration;
+
+pub mod png;
+pub mod record;
+
+/* Video output
+
+   Anything that consumes finished frames implements `VideoSink`: a window's
+   texture upload, image/video recorders, a browser canvas, an LED matrix...
+   The console hands every completed frame to its sink together with timing
+   information and never needs to know what is on the other side.
+
+   Pixels are RGBA8, row major, `width * height * 4` bytes. `Frame` carries
+   them with their size for code that takes frames straight from the PPU.
+
+   Screenshots are PNGs of a frame, optionally without the overscan (the
+   rows a television hides at the top and bottom, often garbage scrolled in
+   by the game) and enlarged by a whole number, named after the game and the
+   frame number so a series of them sorts in order.
+*/
+
+// rows hidden at the top and at the bottom of an NTSC picture
+pub const OVERSCAN: usize = 8;
+
+pub const NTSC_FRAME_DURATION: Duration = Duration::from_nanos(16_639_267); // 60.0988 Hz
+pub const PAL_FRAME_DURATION: Duration = Duration::from_nanos(19_997_200); // 50.0070 Hz
+
+// how often a console of `region` finishes a frame, Dendy runs at the PAL rate
+pub fn frame_duration(region: Region) -> Duration {
+    match region {
+        Region::Pal | Region::Dendy => return PAL_FRAME_DURATION,
+        Region::Ntsc | Region::Multi => return NTSC_FRAME_DURATION,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameTiming {
+    pub frame: u64,
+    pub cpu_cycle: u64,
+    pub frame_duration: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl Frame {
+    // opaque black
+    pub fn new(width: usize, height: usize) -> Self {
+        return Self {
+            width,
+            height,
+            pixels: [0, 0, 0, 0xFF].repeat(width * height),
+        };
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 4] {
+        let offset = (y * self.width + x) * 4;
+        let mut rgba = [0; 4];
+        rgba.copy_from_slice(&self.pixels[offset..offset + 4]);
+        return rgba;
+    }
+
+    pub fn row(&self, y: usize) -> &[u8] {
+        return &self.pixels[y * self.width * 4..(y + 1) * self.width * 4];
+    }
+
+    pub fn row_mut(&mut self, y: usize) -> &mut [u8] {
+        return &mut self.pixels[y * self.width * 4..(y + 1) * self.width * 4];
+    }
+
+    // the frame without `top` rows above and `bottom` rows below
+    pub fn cropped(&self, top: usize, bottom: usize) -> Frame {
+        let height = self.height.saturating_sub(top + bottom);
+        return Frame {
+            width: self.width,
+            height,
+            pixels: self.pixels[top * self.width * 4..(top + height) * self.width * 4].to_vec(),
+        };
+    }
+
+    // turned clockwise by `quarter_turns` right angles
+    pub fn rotated(&self, quarter_turns: u32) -> Frame {
+        let turned = quarter_turns % 2 == 1;
+        let (width, height) = match turned {
+            true => (self.height, self.width),
+            false => (self.width, self.height),
+        };
+        let mut out = Frame::new(width, height);
+        for y in 0..height {
+            for (x, pixel) in out.row_mut(y).chunks_mut(4).enumerate() {
+                let (sx, sy) = match quarter_turns % 4 {
+                    1 => (y, self.height - 1 - x),
+                    2 => (self.width - 1 - x, self.height - 1 - y),
+                    3 => (self.width - 1 - y, x),
+                    _ => (x, y),
+                };
+                pixel.copy_from_slice(&self.pixel(sx, sy));
+            }
+        }
+        return out;
+    }
+
+    // each pixel as a `scale` x `scale` block
+    pub fn scaled(&self, scale: usize) -> Frame {
+        let mut out = Frame::new(self.width * scale, self.height * scale);
+        for y in 0..out.height {
+            let source = self.row(y / scale);
+            for (x, pixel) in out.row_mut(y).chunks_mut(4).enumerate() {
+                pixel.copy_from_slice(&source[x / scale * 4..x / scale * 4 + 4]);
+            }
+        }
+        return out;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScreenshotOptions {
+    pub scale: usize,
+    pub crop_overscan: bool,
+}
+
+impl Default for ScreenshotOptions {
+    fn default() -> Self {
+        return Self {
+            scale: 1,
+            crop_overscan: false,
+        };
+    }
+}
+
+pub fn save_screenshot(
+    frame: &Frame,
+    path: &Path,
+    options: &ScreenshotOptions,
+) -> Result<(), String> {
+    let mut frame = frame.clone();
+    if options.crop_overscan {
+        frame = frame.cropped(OVERSCAN, OVERSCAN);
+    }
+    if options.scale > 1 {
+        frame = frame.scaled(options.scale);
+    }
+    return png::save(&frame, path);
+}
+
+// "Super Mario Bros. (World)" at frame 1234 is "Super Mario Bros. (World)-001234.png",
+// with anything that can't go in a file name left out
+pub fn screenshot_name(title: &str, frame: u64) -> String {
+    let title: String = title
+        .chars()
+        .filter(|c| c.is_alphanumeric() || " .,-_()[]'&!".contains(*c))
+        .collect();
+    let title = title.trim().trim_start_matches('.');
+    let title = if title.is_empty() {
+        "screenshot"
+    } else {
+        title
+    };
+    return format!("{}-{:06}.png", title, frame);
+}
+
+pub trait VideoSink {
+    fn present(
+        &mut self,
+        pixels: &[u8],
+        width: usize,
+        height: usize,
+        timing: &FrameTiming,
+    ) -> Result<(), String>;
+
+    // called once when the session ends so sinks can flush files or streams
+    fn finish(&mut self) -> Result<(), String> {
+        return Ok(());
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct NullVideoSink;
+
+impl VideoSink for NullVideoSink {
+    fn present(&mut self, _: &[u8], _: usize, _: usize, _: &FrameTiming) -> Result<(), String> {
+        return Ok(());
+    }
+}
+
+// keeps a copy of the most recent frame, for headless runs and tests
+#[derive(Debug, Default)]
+pub struct CaptureVideoSink {
+    pub pixels: Vec<u8>,
+    pub width: usize,
+    pub height: usize,
+    pub frames: u64,
+    pub last_timing: Option<FrameTiming>,
+}
+
+impl CaptureVideoSink {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+}
+
+impl VideoSink for CaptureVideoSink {
+    fn present(
+        &mut self,
+        pixels: &[u8],
+        width: usize,
+        height: usize,
+        timing: &FrameTiming,
+    ) -> Result<(), String> {
+        if pixels.len() != width * height * 4 {
+            return Err(format!(
+                "frame is {} bytes, expected {}x{} RGBA",
+                pixels.len(),
+                width,
+                height
+            ));
+        }
+        self.pixels.clear();
+        self.pixels.extend_from_slice(pixels);
+        self.width = width;
+        self.height = height;
+        self.frames += 1;
+        self.last_timing = Some(*timing);
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_screenshot_crop_scale_and_name() {
+        let mut frame = Frame::new(2, 20);
+        frame.row_mut(OVERSCAN)[..4].copy_from_slice(&[1, 2, 3, 0xFF]);
+        let cropped = frame.cropped(OVERSCAN, OVERSCAN);
+        assert_eq!((cropped.width, cropped.height), (2, 4));
+        assert_eq!(cropped.pixel(0, 0), [1, 2, 3, 0xFF]);
+        let scaled = cropped.scaled(2);
+        assert_eq!((scaled.width, scaled.height), (4, 8));
+        assert_eq!(scaled.pixel(1, 1), [1, 2, 3, 0xFF]);
+        assert_eq!(scaled.pixel(2, 1), [0, 0, 0, 0xFF]);
+
+        assert_eq!(
+            screenshot_name("Super Mario Bros. (World)", 1234),
+            "Super Mario Bros. (World)-001234.png"
+        );
+        assert_eq!(screenshot_name("../a/b:c", 7), "abc-000007.png");
+        assert_eq!(screenshot_name("//", 0), "screenshot-000000.png");
+    }
+
+    fn timing(frame: u64) -> FrameTiming {
+        return FrameTiming {
+            frame,
+            cpu_cycle: frame * 29781,
+            frame_duration: NTSC_FRAME_DURATION,
+        };
+    }
+
+    #[test]
+    fn test_frame_layout() {
+        let mut frame = Frame::new(3, 2);
+        assert_eq!(frame.pixels.len(), 24);
+        assert_eq!(frame.pixel(2, 1), [0, 0, 0, 0xFF]);
+        frame.row_mut(1)[8..12].copy_from_slice(&[1, 2, 3, 4]);
+        assert_eq!(frame.pixel(2, 1), [1, 2, 3, 4]);
+        assert_eq!(&frame.row(1)[8..], &[1, 2, 3, 4]);
+
+        // clockwise, the bottom left corner goes to the top left
+        frame.row_mut(1)[..4].copy_from_slice(&[5, 6, 7, 8]);
+        let turned = frame.rotated(1);
+        assert_eq!((turned.width, turned.height), (2, 3));
+        assert_eq!(turned.pixel(0, 0), [5, 6, 7, 8]);
+        assert_eq!(turned.pixel(0, 2), [1, 2, 3, 4]);
+        assert_eq!(frame.rotated(2).pixel(0, 0), [1, 2, 3, 4]);
+        assert_eq!(frame.rotated(3).pixel(1, 0), [1, 2, 3, 4]);
+        assert_eq!(frame.rotated(4), frame);
+    }
+
+    #[test]
+    fn test_capture_sink_keeps_last_frame() {
+        let mut sink = CaptureVideoSink::new();
+        sink.present(&[1; 16], 2, 2, &timing(0)).unwrap();
+        sink.present(&[2; 16], 2, 2, &timing(1)).unwrap();
+        assert_eq!(sink.pixels, vec![2; 16]);
+        assert_eq!(sink.frames, 2);
+        assert_eq!(sink.last_timing.unwrap().frame, 1);
+        assert!(sink.finish().is_ok());
+    }
+
+    #[test]
+    fn test_capture_sink_rejects_bad_size() {
+        let mut sink = CaptureVideoSink::new();
+        assert!(sink.present(&[0; 15], 2, 2, &timing(0)).is_err());
+        assert_eq!(sink.frames, 0);
+    }
+
+    #[test]
+    fn test_sinks_are_object_safe() {
+        let mut sinks: Vec<Box<dyn VideoSink>> =
+            vec![Box::new(NullVideoSink), Box::new(CaptureVideoSink::new())];
+        for sink in sinks.iter_mut() {
+            assert!(sink.present(&[0; 4], 1, 1, &timing(0)).is_ok());
+        }
+    }
+}
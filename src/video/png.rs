@@ -0,0 +1,91 @@
+use crate::archive::deflate::deflate;
+use crate::hash::{self, Crc32};
+use crate::video::Frame;
+use std::fs;
+use std::path::Path;
+
+pub(super) const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+const COLOUR_RGB: u8 = 2;
+const FILTER_NONE: u8 = 0;
+
+/* PNG writer
+
+   Just enough of PNG for frames: an IHDR for 8 bit RGB, the picture as one
+   IDAT chunk and the IEND. Alpha is dropped, frames are always opaque. The
+   image data is each row after a filter type byte (always 0, none), in a
+   zlib stream: a 2 byte header, the deflate data and its Adler-32. Every
+   chunk is a big endian length, the type, the data and a CRC32 over type
+   and data.
+*/
+
+pub(super) fn chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend((data.len() as u32).to_be_bytes());
+    out.extend(kind);
+    out.extend(data);
+    let mut crc = Crc32::new();
+    crc.update(kind);
+    crc.update(data);
+    out.extend(crc.finish().to_be_bytes());
+}
+
+pub(super) fn header(width: usize, height: usize) -> Vec<u8> {
+    let mut header = Vec::new();
+    header.extend((width as u32).to_be_bytes());
+    header.extend((height as u32).to_be_bytes());
+    // bit depth, colour type, compression, filter method, interlacing
+    header.extend([8, COLOUR_RGB, 0, 0, 0]);
+    return header;
+}
+
+// the zlib stream an IDAT holds
+pub(super) fn image_data(frame: &Frame) -> Vec<u8> {
+    let mut rows = Vec::with_capacity((frame.width * 3 + 1) * frame.height);
+    for y in 0..frame.height {
+        rows.push(FILTER_NONE);
+        for pixel in frame.row(y).chunks(4) {
+            rows.extend(&pixel[..3]);
+        }
+    }
+    let mut zlib = vec![0x78, 0x01];
+    zlib.extend(deflate(&rows));
+    zlib.extend(hash::adler32(&rows).to_be_bytes());
+    return zlib;
+}
+
+pub fn encode(frame: &Frame) -> Vec<u8> {
+    let mut out = SIGNATURE.to_vec();
+    chunk(&mut out, b"IHDR", &header(frame.width, frame.height));
+    chunk(&mut out, b"IDAT", &image_data(frame));
+    chunk(&mut out, b"IEND", &[]);
+    return out;
+}
+
+pub fn save(frame: &Frame, path: &Path) -> Result<(), String> {
+    return fs::write(path, encode(frame)).map_err(|e| format!("{}: {}", path.display(), e));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::archive::inflate::inflate;
+
+    #[test]
+    fn test_encode() {
+        let mut frame = Frame::new(2, 2);
+        frame.pixels[4..8].copy_from_slice(&[0xFF, 0x80, 0x00, 0xFF]);
+        let png = encode(&frame);
+        assert_eq!(png[..8], SIGNATURE);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(png[16..24], [0, 0, 0, 2, 0, 0, 0, 2]);
+        assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+
+        // the IDAT holds the filtered rows
+        let length = u32::from_be_bytes(png[33..37].try_into().unwrap()) as usize;
+        assert_eq!(&png[37..41], b"IDAT");
+        let zlib = &png[41..41 + length];
+        let rows = inflate(&zlib[2..zlib.len() - 4]).unwrap();
+        assert_eq!(rows, [0, 0, 0, 0, 0xFF, 0x80, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let crc = hash::crc32(&png[37..41 + length]);
+        assert_eq!(png[41 + length..45 + length], crc.to_be_bytes());
+    }
+}
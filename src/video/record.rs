@@ -0,0 +1,380 @@
+use crate::audio::{AudioSink, WavAudioSink};
+use crate::video::png::{self, SIGNATURE};
+use crate::video::{Frame, FrameTiming, VideoSink};
+use std::fs::{self, File};
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::time::Duration;
+
+// where the acTL chunk goes, after the signature and the IHDR
+const ANIMATION_CONTROL_OFFSET: u64 = 8 + 25;
+
+/* Gameplay recording
+
+   A recording takes every frame the console finishes and the audio the
+   APU produced during it, so picture and sound cover exactly the same
+   stretch of emulated time and can't drift apart however unevenly the host
+   ran. Frames are timed at the console's own rate (60.0988 Hz for NTSC),
+   not a rounded 60.
+
+    apng    built in: an animated PNG, each frame delayed by exactly one
+            frame time, with the sound next to it as a .wav of the same
+            name. Lossless and without dependencies, but large; for short
+            clips
+    ffmpeg  the frames piped to an ffmpeg process as raw RGBA, the sound
+            written to a WAV on the side, and both muxed into the output
+            (whose extension decides the container) when recording stops.
+            Needs ffmpeg on the PATH
+
+   The APNG's frame count is only known at the end, `finish` patches it in
+   the way the WAV sink patches its sizes.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VideoFormat {
+    #[default]
+    Apng,
+    Ffmpeg,
+}
+
+impl VideoFormat {
+    pub fn parse(name: &str) -> Result<VideoFormat, String> {
+        match name {
+            "apng" => return Ok(VideoFormat::Apng),
+            "ffmpeg" => return Ok(VideoFormat::Ffmpeg),
+            _ => return Err(format!("unknown video format {}", name)),
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            VideoFormat::Apng => return "apng",
+            VideoFormat::Ffmpeg => return "mp4",
+        }
+    }
+}
+
+// the frame time as an APNG delay fraction, in thousandths of a frame rate
+fn delay(frame_duration: Duration) -> (u16, u16) {
+    let nanos = frame_duration.as_nanos().max(1);
+    let rate = (1_000_000_000_000 + nanos / 2) / nanos;
+    return (1000, rate.min(u16::MAX as u128) as u16);
+}
+
+pub struct ApngVideoSink<W: Write + Seek> {
+    writer: W,
+    size: Option<(usize, usize)>,
+    frames: u32,
+    sequence: u32, // numbers the fcTL and fdAT chunks together
+}
+
+impl ApngVideoSink<BufWriter<File>> {
+    pub fn create(path: &Path) -> Result<Self, String> {
+        let file = File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        return Ok(Self::new(BufWriter::new(file)));
+    }
+}
+
+impl<W: Write + Seek> ApngVideoSink<W> {
+    pub fn new(writer: W) -> Self {
+        return Self {
+            writer,
+            size: None,
+            frames: 0,
+            sequence: 0,
+        };
+    }
+
+    pub fn into_inner(self) -> W {
+        return self.writer;
+    }
+
+    fn animation_control(&self) -> Vec<u8> {
+        let mut data = self.frames.to_be_bytes().to_vec();
+        data.extend(0u32.to_be_bytes()); // loop forever
+        let mut chunk = Vec::new();
+        png::chunk(&mut chunk, b"acTL", &data);
+        return chunk;
+    }
+}
+
+impl<W: Write + Seek> VideoSink for ApngVideoSink<W> {
+    fn present(
+        &mut self,
+        pixels: &[u8],
+        width: usize,
+        height: usize,
+        timing: &FrameTiming,
+    ) -> Result<(), String> {
+        let mut out = Vec::new();
+        match self.size {
+            None => {
+                self.size = Some((width, height));
+                out.extend(SIGNATURE);
+                png::chunk(&mut out, b"IHDR", &png::header(width, height));
+                out.extend(self.animation_control());
+            }
+            Some(size) if size != (width, height) => {
+                return Err(format!(
+                    "frame size changed from {}x{} to {}x{}",
+                    size.0, size.1, width, height
+                ));
+            }
+            Some(_) => {}
+        }
+
+        let (delay_num, delay_den) = delay(timing.frame_duration);
+        let mut control = self.sequence.to_be_bytes().to_vec();
+        control.extend((width as u32).to_be_bytes());
+        control.extend((height as u32).to_be_bytes());
+        control.extend([0; 8]); // x and y offsets
+        control.extend(delay_num.to_be_bytes());
+        control.extend(delay_den.to_be_bytes());
+        control.extend([0, 0]); // no disposal, no blending
+        png::chunk(&mut out, b"fcTL", &control);
+        self.sequence += 1;
+
+        let frame = Frame {
+            width,
+            height,
+            pixels: pixels.to_vec(),
+        };
+        if self.frames == 0 {
+            png::chunk(&mut out, b"IDAT", &png::image_data(&frame));
+        } else {
+            let mut data = self.sequence.to_be_bytes().to_vec();
+            data.extend(png::image_data(&frame));
+            png::chunk(&mut out, b"fdAT", &data);
+            self.sequence += 1;
+        }
+        self.frames += 1;
+        return self.writer.write_all(&out).map_err(|e| e.to_string());
+    }
+
+    fn finish(&mut self) -> Result<(), String> {
+        if self.size.is_none() {
+            return Err("no frames were recorded".to_string());
+        }
+        let mut end = Vec::new();
+        png::chunk(&mut end, b"IEND", &[]);
+        let control = self.animation_control();
+        let result = self
+            .writer
+            .write_all(&end)
+            .and_then(|_| self.writer.seek(SeekFrom::Start(ANIMATION_CONTROL_OFFSET)))
+            .and_then(|_| self.writer.write_all(&control))
+            .and_then(|_| self.writer.seek(SeekFrom::End(0)))
+            .and_then(|_| self.writer.flush());
+        return result.map_err(|e| e.to_string());
+    }
+}
+
+// ffmpeg encoding the frames, started at the first one once their size is known
+pub struct FfmpegVideoSink {
+    output: PathBuf,
+    child: Option<(Child, ChildStdin)>,
+}
+
+impl FfmpegVideoSink {
+    pub fn new(output: &Path) -> Self {
+        return Self {
+            output: output.to_path_buf(),
+            child: None,
+        };
+    }
+
+    fn spawn(&self, width: usize, height: usize, timing: &FrameTiming) -> Result<Child, String> {
+        let rate = format!("1000000000/{}", timing.frame_duration.as_nanos());
+        let size = format!("{}x{}", width, height);
+        let mut command = Command::new("ffmpeg");
+        command.args([
+            "-y",
+            "-loglevel",
+            "error",
+            "-f",
+            "rawvideo",
+            "-pix_fmt",
+            "rgba",
+        ]);
+        command.args(["-s", &size, "-framerate", &rate, "-i", "-", "-c:v", "ffv1"]);
+        command.arg(&self.output).stdin(Stdio::piped());
+        return command
+            .spawn()
+            .map_err(|e| format!("can't run ffmpeg: {}", e));
+    }
+}
+
+impl VideoSink for FfmpegVideoSink {
+    fn present(
+        &mut self,
+        pixels: &[u8],
+        width: usize,
+        height: usize,
+        timing: &FrameTiming,
+    ) -> Result<(), String> {
+        if self.child.is_none() {
+            let mut child = self.spawn(width, height, timing)?;
+            let stdin = child.stdin.take().ok_or("ffmpeg has no input pipe")?;
+            self.child = Some((child, stdin));
+        }
+        let (_, stdin) = self.child.as_mut().unwrap();
+        return stdin
+            .write_all(pixels)
+            .map_err(|e| format!("ffmpeg: {}", e));
+    }
+
+    fn finish(&mut self) -> Result<(), String> {
+        let Some((mut child, stdin)) = self.child.take() else {
+            return Err("no frames were recorded".to_string());
+        };
+        drop(stdin);
+        let status = child.wait().map_err(|e| format!("ffmpeg: {}", e))?;
+        if !status.success() {
+            return Err(format!("ffmpeg failed ({})", status));
+        }
+        return Ok(());
+    }
+}
+
+pub struct Recording {
+    format: VideoFormat,
+    path: PathBuf,
+    video: Box<dyn VideoSink>,
+    audio: WavAudioSink<BufWriter<File>>,
+}
+
+impl Recording {
+    pub fn start(path: &Path, format: VideoFormat) -> Result<Recording, String> {
+        let video: Box<dyn VideoSink> = match format {
+            VideoFormat::Apng => Box::new(ApngVideoSink::create(path)?),
+            VideoFormat::Ffmpeg => Box::new(FfmpegVideoSink::new(&ffmpeg_video_path(path))),
+        };
+        return Ok(Recording {
+            format,
+            path: path.to_path_buf(),
+            video,
+            audio: WavAudioSink::create(&audio_path(path, format))?,
+        });
+    }
+
+    pub fn path(&self) -> &Path {
+        return &self.path;
+    }
+
+    pub fn push_frame(&mut self, frame: &Frame, timing: &FrameTiming) -> Result<(), String> {
+        return self
+            .video
+            .present(&frame.pixels, frame.width, frame.height, timing);
+    }
+
+    // the samples produced during the frame pushed last
+    pub fn push_samples(&mut self, samples: &[f32], sample_rate: u32) -> Result<(), String> {
+        return self.audio.push_samples(samples, sample_rate);
+    }
+
+    pub fn finish(mut self) -> Result<(), String> {
+        self.video.finish()?;
+        self.audio.finish()?;
+        if self.format == VideoFormat::Apng {
+            return Ok(());
+        }
+        let video = ffmpeg_video_path(&self.path);
+        let audio = audio_path(&self.path, self.format);
+        let status = Command::new("ffmpeg")
+            .args(["-y", "-loglevel", "error", "-i"])
+            .arg(&video)
+            .arg("-i")
+            .arg(&audio)
+            .args([
+                "-c:v", "libx264", "-pix_fmt", "yuv420p", "-crf", "16", "-c:a", "aac",
+            ])
+            .arg(&self.path)
+            .status()
+            .map_err(|e| format!("can't run ffmpeg: {}", e))?;
+        if !status.success() {
+            return Err(format!(
+                "ffmpeg failed ({}), the parts are in {}",
+                status,
+                video.display()
+            ));
+        }
+        // the parts are only scratch files once muxed
+        let _ = fs::remove_file(video);
+        let _ = fs::remove_file(audio);
+        return Ok(());
+    }
+}
+
+fn ffmpeg_video_path(path: &Path) -> PathBuf {
+    return path.with_extension("video.mkv");
+}
+
+fn audio_path(path: &Path, format: VideoFormat) -> PathBuf {
+    match format {
+        VideoFormat::Apng => return path.with_extension("wav"),
+        VideoFormat::Ffmpeg => return path.with_extension("audio.wav"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::video::NTSC_FRAME_DURATION;
+    use std::io::Cursor;
+
+    fn timing(frame: u64) -> FrameTiming {
+        return FrameTiming {
+            frame,
+            cpu_cycle: frame * 29781,
+            frame_duration: NTSC_FRAME_DURATION,
+        };
+    }
+
+    // (type, data) of every chunk after the signature
+    fn chunks(data: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let mut chunks = Vec::new();
+        let mut pos = 8;
+        while pos < data.len() {
+            let length = u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap()) as usize;
+            let kind = String::from_utf8(data[pos + 4..pos + 8].to_vec()).unwrap();
+            chunks.push((kind, data[pos + 8..pos + 8 + length].to_vec()));
+            pos += 12 + length;
+        }
+        return chunks;
+    }
+
+    #[test]
+    fn test_apng_frames() {
+        let mut sink = ApngVideoSink::new(Cursor::new(Vec::new()));
+        let frame = Frame::new(4, 2);
+        for number in 0..3 {
+            sink.present(&frame.pixels, 4, 2, &timing(number)).unwrap();
+        }
+        assert!(sink.present(&[0; 4], 1, 1, &timing(3)).is_err());
+        sink.finish().unwrap();
+
+        let data = sink.into_inner().into_inner();
+        let chunks = chunks(&data);
+        let kinds: Vec<&str> = chunks.iter().map(|(kind, _)| kind.as_str()).collect();
+        assert_eq!(
+            kinds,
+            ["IHDR", "acTL", "fcTL", "IDAT", "fcTL", "fdAT", "fcTL", "fdAT", "IEND"]
+        );
+        assert_eq!(chunks[1].1[..4], 3u32.to_be_bytes());
+        // sequence numbers run through the fcTL and fdAT chunks, 60.099 frames a second
+        assert_eq!(chunks[4].1[..4], 1u32.to_be_bytes());
+        assert_eq!(chunks[5].1[..4], 2u32.to_be_bytes());
+        assert_eq!(chunks[6].1[20..24], [0x03, 0xE8, 0xEA, 0xC3]);
+    }
+
+    #[test]
+    fn test_format_option() {
+        assert_eq!(VideoFormat::parse("ffmpeg"), Ok(VideoFormat::Ffmpeg));
+        assert!(VideoFormat::parse("gif").is_err());
+        assert_eq!(
+            audio_path(Path::new("clip.apng"), VideoFormat::Apng),
+            PathBuf::from("clip.wav")
+        );
+    }
+}
@@ -0,0 +1,228 @@
+use crate::cartridge::Rom;
+use crate::nes::Nes;
+use std::cell::RefCell;
+
+/* Web frontend exports
+
+   What web/main.js calls once the crate is built as a cdylib for
+   wasm32-unknown-unknown:
+
+    cargo build --release --lib --target wasm32-unknown-unknown
+    cp target/wasm32-unknown-unknown/release/rustynes.wasm web/
+
+   The console, not `frontend::Session`, is what runs in the browser, since
+   the session's pacing, files and host processes have nothing to stand on
+   there: the page's requestAnimationFrame loop is the pacer, a canvas the
+   window and Web Audio the sound device. So the exports are plain C ABI
+   functions over one console held here, no bindings generator needed:
+
+    rustynes_alloc(len) / rustynes_free(ptr, len)
+                          a buffer in wasm memory for the page to copy a ROM in
+    rustynes_load(ptr, len, sample_rate)
+                          boots the .nes or .zip in the buffer, 0 on success,
+                          otherwise `rustynes_error` has the message
+    rustynes_run_frame()  a frame, the page calls it at the console's rate
+    rustynes_set_buttons(player, bits)
+                          bits as in `Button::bit`, A being the lowest
+    rustynes_frame()      the finished picture, 256x240 RGBA
+    rustynes_samples() / rustynes_sample_count()
+                          the frame's sound as f32 at `sample_rate`
+    rustynes_save_ram() / rustynes_save_ram_len()
+                          battery RAM, which the page keeps in localStorage
+    rustynes_reset(), rustynes_frame_rate(), rustynes_crc32()
+
+   Pointers stay valid until the next call that runs the console or loads
+   a game. Strings come back as a pointer with the length in
+   `rustynes_error_len`.
+*/
+
+struct WebConsole {
+    nes: Option<Nes>,
+    samples: Vec<f32>,
+    error: String,
+}
+
+thread_local! {
+    static CONSOLE: RefCell<WebConsole> = const {
+        RefCell::new(WebConsole {
+            nes: None,
+            samples: Vec::new(),
+            error: String::new(),
+        })
+    };
+}
+
+fn with_console<T>(f: impl FnOnce(&mut WebConsole) -> T) -> T {
+    return CONSOLE.with(|console| f(&mut console.borrow_mut()));
+}
+
+fn load(data: &[u8], sample_rate: u32) -> Result<(), String> {
+    let rom = Rom::new(&crate::archive::unpack_rom(data.to_vec(), None)?)?;
+    let nes = Nes::builder().sample_rate(sample_rate).build(rom)?;
+    with_console(|console| {
+        console.nes = Some(nes);
+        console.samples.clear();
+    });
+    return Ok(());
+}
+
+#[no_mangle]
+pub extern "C" fn rustynes_alloc(len: usize) -> *mut u8 {
+    let mut buffer = vec![0u8; len];
+    let ptr = buffer.as_mut_ptr();
+    std::mem::forget(buffer);
+    return ptr;
+}
+
+/// # Safety
+/// `ptr` and `len` must come from one `rustynes_alloc` call.
+#[no_mangle]
+pub unsafe extern "C" fn rustynes_free(ptr: *mut u8, len: usize) {
+    drop(Vec::from_raw_parts(ptr, len, len));
+}
+
+/// # Safety
+/// `ptr` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn rustynes_load(ptr: *const u8, len: usize, sample_rate: u32) -> i32 {
+    let data = std::slice::from_raw_parts(ptr, len);
+    match load(data, sample_rate) {
+        Ok(()) => return 0,
+        Err(e) => {
+            with_console(|console| console.error = e);
+            return 1;
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn rustynes_error() -> *const u8 {
+    return with_console(|console| console.error.as_ptr());
+}
+
+#[no_mangle]
+pub extern "C" fn rustynes_error_len() -> usize {
+    return with_console(|console| console.error.len());
+}
+
+#[no_mangle]
+pub extern "C" fn rustynes_run_frame() {
+    with_console(|console| {
+        if let Some(nes) = &mut console.nes {
+            nes.run_frame();
+            console.samples = nes.audio_samples();
+        }
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn rustynes_set_buttons(player: usize, bits: u8) {
+    with_console(|console| {
+        if let Some(nes) = &mut console.nes {
+            nes.ports_mut().set_buttons(player, bits);
+        }
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn rustynes_reset() {
+    with_console(|console| {
+        if let Some(nes) = &mut console.nes {
+            nes.reset();
+        }
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn rustynes_frame() -> *const u8 {
+    return with_console(|console| match &console.nes {
+        Some(nes) => nes.frame().pixels.as_ptr(),
+        None => std::ptr::null(),
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn rustynes_samples() -> *const f32 {
+    return with_console(|console| console.samples.as_ptr());
+}
+
+#[no_mangle]
+pub extern "C" fn rustynes_sample_count() -> usize {
+    return with_console(|console| console.samples.len());
+}
+
+#[no_mangle]
+pub extern "C" fn rustynes_save_ram() -> *mut u8 {
+    return with_console(|console| {
+        let ram = console
+            .nes
+            .as_mut()
+            .filter(|nes| nes.rom().battery)
+            .and_then(|nes| nes.cpu.bus.mapper_mut())
+            .and_then(|mapper| mapper.prg_ram_mut());
+        return ram.map_or(std::ptr::null_mut(), |ram| ram.as_mut_ptr());
+    });
+}
+
+// 0 without a battery, the page then has nothing to keep
+#[no_mangle]
+pub extern "C" fn rustynes_save_ram_len() -> usize {
+    return with_console(|console| {
+        let ram = console
+            .nes
+            .as_ref()
+            .filter(|nes| nes.rom().battery)
+            .and_then(|nes| nes.cpu.bus.mapper())
+            .and_then(|mapper| mapper.prg_ram());
+        return ram.map_or(0, |ram| ram.len());
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn rustynes_frame_rate() -> f64 {
+    return with_console(|console| match &console.nes {
+        Some(_) => 1.0 / crate::video::NTSC_FRAME_DURATION.as_secs_f64(),
+        None => 0.0,
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn rustynes_crc32() -> u32 {
+    return with_console(|console| console.nes.as_ref().map_or(0, |nes| nes.rom().crc32()));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+
+    #[test]
+    fn test_exports_run_a_game() {
+        let junk = b"not a rom";
+        assert_eq!(
+            unsafe { rustynes_load(junk.as_ptr(), junk.len(), 48_000) },
+            1
+        );
+        assert!(rustynes_error_len() > 0);
+
+        // spins on a JMP to itself at $8000, with a battery
+        let mut raw = test_rom(0, 1, 1);
+        raw[6] |= 0b0000_0010;
+        raw[16..19].copy_from_slice(&[0x4C, 0x00, 0x80]);
+        raw[16 + 0x3FFC..16 + 0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        let ptr = rustynes_alloc(raw.len());
+        unsafe {
+            std::slice::from_raw_parts_mut(ptr, raw.len()).copy_from_slice(&raw);
+            assert_eq!(rustynes_load(ptr, raw.len(), 48_000), 0);
+            rustynes_free(ptr, raw.len());
+        }
+        rustynes_set_buttons(0, 0b1001);
+        rustynes_run_frame();
+        assert!(!rustynes_frame().is_null());
+        // 48000 / 60.0988, give or take the resampler's phase
+        assert!((798..=800).contains(&rustynes_sample_count()));
+        assert_eq!(rustynes_save_ram_len(), 0x2000);
+        assert!((rustynes_frame_rate() - 60.0988).abs() < 0.001);
+        assert_ne!(rustynes_crc32(), 0);
+    }
+}
@@ -0,0 +1,212 @@
+// the browser side of the web build: loads rustynes.wasm and drives it
+// from requestAnimationFrame, see src/web.rs for the exports
+"use strict";
+
+const WIDTH = 256;
+const HEIGHT = 240;
+
+// bits as in Button::bit: A, B, Select, Start, Up, Down, Left, Right
+const KEYS = {
+  KeyX: 0, KeyZ: 1, ShiftRight: 2, Enter: 3,
+  ArrowUp: 4, ArrowDown: 5, ArrowLeft: 6, ArrowRight: 7,
+};
+// the standard gamepad mapping's buttons for the same bits
+const PAD_BUTTONS = [0, 2, 8, 9, 12, 13, 14, 15];
+
+const status = document.getElementById("status");
+let wasm = null;
+let audio = null;
+let audioTime = 0;
+let running = false;
+let saveKey = null;
+let keyBits = 0;
+let due = 0;
+
+function check(result) {
+  if (result !== 0) {
+    const bytes = new Uint8Array(wasm.memory.buffer, wasm.rustynes_error(), wasm.rustynes_error_len());
+    throw new Error(new TextDecoder().decode(bytes));
+  }
+}
+
+// WebGL when there is one, a 2D context otherwise
+function makeScreen(canvas) {
+  const gl = canvas.getContext("webgl");
+  if (!gl) {
+    const context = canvas.getContext("2d");
+    const image = context.createImageData(WIDTH, HEIGHT);
+    return (pixels) => {
+      image.data.set(pixels);
+      context.putImageData(image, 0, 0);
+    };
+  }
+  const shader = (type, source) => {
+    const s = gl.createShader(type);
+    gl.shaderSource(s, source);
+    gl.compileShader(s);
+    return s;
+  };
+  const program = gl.createProgram();
+  gl.attachShader(program, shader(gl.VERTEX_SHADER,
+    "attribute vec2 p; varying vec2 uv;" +
+    "void main() { uv = vec2(p.x + 1.0, 1.0 - p.y) / 2.0; gl_Position = vec4(p, 0.0, 1.0); }"));
+  gl.attachShader(program, shader(gl.FRAGMENT_SHADER,
+    "precision mediump float; uniform sampler2D t; varying vec2 uv;" +
+    "void main() { gl_FragColor = texture2D(t, uv); }"));
+  gl.linkProgram(program);
+  gl.useProgram(program);
+  gl.bindBuffer(gl.ARRAY_BUFFER, gl.createBuffer());
+  gl.bufferData(gl.ARRAY_BUFFER, new Float32Array([-1, -1, 1, -1, -1, 1, 1, 1]), gl.STATIC_DRAW);
+  const position = gl.getAttribLocation(program, "p");
+  gl.enableVertexAttribArray(position);
+  gl.vertexAttribPointer(position, 2, gl.FLOAT, false, 0, 0);
+  gl.bindTexture(gl.TEXTURE_2D, gl.createTexture());
+  gl.texParameteri(gl.TEXTURE_2D, gl.TEXTURE_MIN_FILTER, gl.NEAREST);
+  gl.texParameteri(gl.TEXTURE_2D, gl.TEXTURE_MAG_FILTER, gl.NEAREST);
+  gl.texParameteri(gl.TEXTURE_2D, gl.TEXTURE_WRAP_S, gl.CLAMP_TO_EDGE);
+  gl.texParameteri(gl.TEXTURE_2D, gl.TEXTURE_WRAP_T, gl.CLAMP_TO_EDGE);
+  return (pixels) => {
+    gl.texImage2D(gl.TEXTURE_2D, 0, gl.RGBA, WIDTH, HEIGHT, 0, gl.RGBA, gl.UNSIGNED_BYTE, pixels);
+    gl.drawArrays(gl.TRIANGLE_STRIP, 0, 4);
+  };
+}
+
+const draw = makeScreen(document.getElementById("screen"));
+
+// each frame's samples are queued right behind the last ones
+function playSamples() {
+  const count = wasm.rustynes_sample_count();
+  if (count === 0) {
+    return;
+  }
+  const samples = new Float32Array(wasm.memory.buffer, wasm.rustynes_samples(), count);
+  const buffer = audio.createBuffer(1, count, audio.sampleRate);
+  buffer.copyToChannel(samples, 0);
+  const source = audio.createBufferSource();
+  source.buffer = buffer;
+  source.connect(audio.destination);
+  // too far behind, e.g. after the tab was hidden, starts over a little ahead
+  if (audioTime < audio.currentTime) {
+    audioTime = audio.currentTime + 0.05;
+  }
+  source.start(audioTime);
+  audioTime += buffer.duration;
+}
+
+function gamepadBits(pad) {
+  let bits = 0;
+  PAD_BUTTONS.forEach((button, bit) => {
+    if (pad.buttons[button] && pad.buttons[button].pressed) {
+      bits |= 1 << bit;
+    }
+  });
+  const [x, y] = [pad.axes[0] || 0, pad.axes[1] || 0];
+  if (y < -0.5) bits |= 1 << 4;
+  if (y > 0.5) bits |= 1 << 5;
+  if (x < -0.5) bits |= 1 << 6;
+  if (x > 0.5) bits |= 1 << 7;
+  return bits;
+}
+
+function pollInput() {
+  const pads = navigator.getGamepads ? Array.from(navigator.getGamepads()).filter((p) => p) : [];
+  for (let player = 0; player < 2; player++) {
+    let bits = pads[player] ? gamepadBits(pads[player]) : 0;
+    if (player === 0) {
+      bits |= keyBits;
+    }
+    wasm.rustynes_set_buttons(player, bits);
+  }
+}
+
+function frame(now) {
+  requestAnimationFrame(frame);
+  if (!running) {
+    return;
+  }
+  // at the console's rate whatever the display's, a few frames at most to catch up
+  const period = 1000 / wasm.rustynes_frame_rate();
+  if (now - due > period * 4) {
+    due = now;
+  }
+  while (due <= now) {
+    pollInput();
+    wasm.rustynes_run_frame();
+    playSamples();
+    due += period;
+  }
+  draw(new Uint8Array(wasm.memory.buffer, wasm.rustynes_frame(), WIDTH * HEIGHT * 4));
+}
+
+function saveRam() {
+  const len = wasm && wasm.rustynes_save_ram_len();
+  if (!running || !saveKey || !len) {
+    return;
+  }
+  const ram = new Uint8Array(wasm.memory.buffer, wasm.rustynes_save_ram(), len);
+  localStorage.setItem(saveKey, btoa(String.fromCharCode(...ram)));
+}
+
+async function loadRom(file) {
+  saveRam();
+  // the audio context can only start from a user's gesture, which this follows
+  audio = audio || new AudioContext();
+  await audio.resume();
+  const data = new Uint8Array(await file.arrayBuffer());
+  const ptr = wasm.rustynes_alloc(data.length);
+  new Uint8Array(wasm.memory.buffer, ptr, data.length).set(data);
+  try {
+    check(wasm.rustynes_load(ptr, data.length, audio.sampleRate));
+  } catch (e) {
+    status.textContent = e.message;
+    return;
+  } finally {
+    wasm.rustynes_free(ptr, data.length);
+  }
+  saveKey = "rustynes-sav-" + (wasm.rustynes_crc32() >>> 0).toString(16).padStart(8, "0");
+  const saved = localStorage.getItem(saveKey);
+  const len = wasm.rustynes_save_ram_len();
+  if (saved && len) {
+    const bytes = Uint8Array.from(atob(saved), (c) => c.charCodeAt(0));
+    new Uint8Array(wasm.memory.buffer, wasm.rustynes_save_ram(), len).set(bytes.subarray(0, len));
+  }
+  status.textContent = file.name;
+  running = true;
+  due = performance.now();
+}
+
+document.getElementById("rom").addEventListener("change", (e) => {
+  if (e.target.files[0]) {
+    loadRom(e.target.files[0]);
+  }
+});
+document.getElementById("reset").addEventListener("click", () => wasm && wasm.rustynes_reset());
+document.addEventListener("dragover", (e) => e.preventDefault());
+document.addEventListener("drop", (e) => {
+  e.preventDefault();
+  if (e.dataTransfer.files[0]) {
+    loadRom(e.dataTransfer.files[0]);
+  }
+});
+document.addEventListener("keydown", (e) => {
+  if (e.code in KEYS) {
+    keyBits |= 1 << KEYS[e.code];
+    e.preventDefault();
+  }
+});
+document.addEventListener("keyup", (e) => {
+  if (e.code in KEYS) {
+    keyBits &= ~(1 << KEYS[e.code]);
+  }
+});
+window.addEventListener("blur", () => (keyBits = 0));
+// battery RAM is kept every few seconds and when the page goes away
+setInterval(saveRam, 10000);
+window.addEventListener("pagehide", saveRam);
+
+WebAssembly.instantiateStreaming(fetch("rustynes.wasm"), {})
+  .then(({ instance }) => {
+    wasm = instance.exports;
+    requestAnimationFrame(frame);
+  })
+  .catch((e) => (status.textContent = "couldn't load rustynes.wasm: " + e.message));